@@ -77,11 +77,8 @@ export default class P2PVaultSyncPlugin extends Plugin {
 
             // Ensure device ID exists
             if (!this.settings.deviceId) {
-                // Generate a random UUID-like string
-                this.settings.deviceId = 'xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx'.replace(/[xy]/g, function(c) {
-                    var r = Math.random() * 16 | 0, v = c == 'x' ? r : (r & 0x3 | 0x8);
-                    return v.toString(16);
-                });
+                // Sortable ID; devices set up before this keep their UUIDs
+                this.settings.deviceId = this.wasmBridge.getModule()!.generate_id();
                 settingsChanged = true;
             }
 
@@ -419,6 +416,12 @@ export default class P2PVaultSyncPlugin extends Plugin {
               node.load_journal_state(json);
               console.log('Loaded change journal from disk');
           }
+
+          // History is saved apart from the journal; load it after the journal replaces it
+          const historyPath = path.join(this.manifest.dir || '.', 'history.json');
+          if (await adapter.exists(historyPath)) {
+              node.load_history_state(await adapter.read(historyPath));
+          }
       } catch (e) {
           console.error('Failed to load journal:', e);
       }
@@ -434,6 +437,7 @@ export default class P2PVaultSyncPlugin extends Plugin {
           const journalPath = path.join(this.manifest.dir || '.', 'journal.json');
 
           await adapter.write(journalPath, json);
+          await adapter.write(path.join(this.manifest.dir || '.', 'history.json'), node.get_history_state());
           console.log('Saved change journal to disk');
       } catch (e) {
           console.error('Failed to save journal:', e);
@@ -0,0 +1,53 @@
+//! Transfer preparation on a 100 MB input: the current in-place path against
+//! the per-chunk `encrypt_data` + `Vec<FileChunk>` approach it replaced.
+//!
+//! Run with `cargo bench --target x86_64-unknown-linux-gnu`.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use criterion::{criterion_group, Criterion};
+    use obsidian_p2p_sync::crypto::encrypt_data;
+    use obsidian_p2p_sync::transfer::{FileChunk, TransferManager};
+
+    const INPUT_BYTES: usize = 100 * 1024 * 1024;
+    const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    fn previous_prepare_transfer(file_path: &str, content: &[u8]) -> String {
+        let total_chunks = content.len().div_ceil(64 * 1024);
+        let chunks: Vec<FileChunk> = content
+            .chunks(64 * 1024)
+            .enumerate()
+            .map(|(i, slice)| {
+                let encrypted = encrypt_data(KEY.to_string(), slice).unwrap();
+                FileChunk {
+                    file_path: file_path.to_string(),
+                    chunk_index: i as u32,
+                    total_chunks: total_chunks as u32,
+                    data: encrypted.get_data(),
+                    nonce: encrypted.get_nonce(),
+                }
+            })
+            .collect();
+        serde_json::to_string(&chunks).unwrap()
+    }
+
+    fn prepare_transfer(c: &mut Criterion) {
+        let content: Vec<u8> = (0..INPUT_BYTES).map(|i| (i * 31 % 251) as u8).collect();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let mut group = c.benchmark_group("prepare_transfer_100mb");
+        group.sample_size(10);
+        group.bench_function("in_place", |b| {
+            b.iter(|| manager.prepare_transfer("big.bin".to_string(), &content, KEY.to_string()).unwrap())
+        });
+        group.bench_function("per_chunk_alloc", |b| b.iter(|| previous_prepare_transfer("big.bin", &content)));
+        group.finish();
+    }
+
+    criterion_group!(benches, prepare_transfer);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+criterion::criterion_main!(native::benches);
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
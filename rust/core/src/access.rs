@@ -0,0 +1,493 @@
+//! Capability tokens
+//!
+//! A peer's permissions (read-only, limited to some folders, no attachments)
+//! are not something the peer should enforce on itself. The granting device
+//! signs a token naming the grantee's identity key and its permissions; the
+//! peer presents it with every manifest or chunk request and the serving side
+//! checks it, against its own list of trusted issuers, before answering.
+//!
+//! The requester is the device whose handshake this node verified in this
+//! session, identified by the identity key the handshake was signed with;
+//! a key the host passes in is never taken on trust. `get_manifest_for_token`
+//! and `begin_serving_file`, which produces the chunks of a file, check the
+//! token themselves, and `authorize_request` covers everything else.
+//!
+//! Guest sessions are tokens for someone else's device: read-only, one
+//! folder, expiring after a set time. The issuing device remembers them so
+//! they can be listed and revoked early; revoked and expired sessions are
+//! cleaned up on the scheduler tick.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::clock;
+use crate::crypto::{generate_fingerprint, verify_signature, DeviceIdentity};
+use crate::ids;
+use crate::ingest::FileIngest;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::security::SecurityEventKind;
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Permissions {
+    #[serde(default)]
+    pub read_only: bool,
+    /// Folders the grantee may see; empty grants the whole vault
+    #[serde(default)]
+    pub folders: Vec<String>,
+    #[serde(default)]
+    pub deny_attachments: bool,
+}
+
+/// A vault-relative path with no empty, `.` or `..` segments and no leading `/`,
+/// which could otherwise step out of the folder it names first
+pub fn is_plain_path(path: &str) -> bool {
+    !path.is_empty() && path.split('/').all(|segment| !matches!(segment, "" | "." | ".."))
+}
+
+impl Permissions {
+    pub fn covers(&self, path: &str) -> bool {
+        if !is_plain_path(path) {
+            return false;
+        }
+        self.folders.is_empty()
+            || self.folders.iter().any(|folder| {
+                let folder = folder.trim_matches('/');
+                folder.is_empty() || path.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+}
+
+/// What the grantee asks the serving side for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Listing the journal (filtered to what the token covers)
+    Manifest,
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn parse(name: &str) -> Result<Access, String> {
+        match name {
+            "manifest" => Ok(Access::Manifest),
+            "read" => Ok(Access::Read),
+            "write" => Ok(Access::Write),
+            other => Err(format!("Unknown access kind: {}", other)),
+        }
+    }
+}
+
+/// The signed part of a token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Grant {
+    pub id: String,
+    /// Identity key (base64) of the issuing device
+    pub issuer: String,
+    /// Identity key (base64) of the device the grant is for
+    pub grantee: String,
+    pub permissions: Permissions,
+    pub issued_at: u64,
+    /// Crate-clock time after which the grant is void; `None` never expires
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedGrant {
+    grant: String,
+    signature: String,
+}
+
+/// Sign `grant` with the issuer's identity; returns the base64 token
+pub fn issue(identity: &DeviceIdentity, grant: &Grant) -> String {
+    let body = serde_json::to_string(grant).unwrap_or_default();
+    let signature = identity.sign(body.as_bytes());
+    BASE64.encode(serde_json::to_vec(&SignedGrant { grant: body, signature }).unwrap_or_default())
+}
+
+/// Decode a token and check its signature (not its issuer, grantee or expiry)
+pub fn open(token: &str) -> Result<Grant, String> {
+    let bytes = BASE64.decode(token.trim()).map_err(|_| "Malformed capability token".to_string())?;
+    let signed: SignedGrant = serde_json::from_slice(&bytes).map_err(|_| "Malformed capability token".to_string())?;
+    let grant: Grant = serde_json::from_str(&signed.grant).map_err(|_| "Malformed capability token".to_string())?;
+    if !verify_signature(grant.issuer.clone(), signed.grant.as_bytes(), signed.signature) {
+        return Err(format!("Invalid signature on capability token {}", grant.id));
+    }
+    Ok(grant)
+}
+
+/// A guest grant this device issued
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GuestSession {
+    pub id: String,
+    /// Fingerprint of the guest's identity key, for showing to the user
+    pub fingerprint: String,
+    pub folder: String,
+    pub expires_at: u64,
+    pub token: String,
+}
+
+/// Issuers whose tokens this device honours, and the guest grants it issued
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AccessControl {
+    #[serde(default)]
+    issuers: BTreeSet<String>,
+    #[serde(default)]
+    guests: Vec<GuestSession>,
+    /// Revoked grant ID → when it would have expired (after which it can be forgotten)
+    #[serde(default)]
+    revoked: BTreeMap<String, u64>,
+    /// Device ID → identity key (base64) its handshake was verified with, this session
+    #[serde(skip)]
+    verified: BTreeMap<String, String>,
+    /// Device ID → identity key of a verified handshake whose session is not confirmed yet
+    #[serde(skip)]
+    pending: BTreeMap<String, String>,
+}
+
+impl AccessControl {
+    /// Drop guest sessions and revocations past their expiry; returns the sessions removed
+    pub fn expire(&mut self, now: u64) -> usize {
+        let before = self.guests.len();
+        self.guests.retain(|g| g.expires_at > now);
+        self.revoked.retain(|_, expires_at| *expires_at > now);
+        before - self.guests.len()
+    }
+
+    /// Hold the identity key a device's handshake was verified with until its session is confirmed
+    pub fn stage(&mut self, device_id: &str, identity_key: String) {
+        self.pending.insert(device_id.to_string(), identity_key);
+    }
+
+    /// Serve requests from the key staged for `device_id`; false if none was staged
+    pub fn bind(&mut self, device_id: &str) -> bool {
+        match self.pending.remove(device_id) {
+            Some(identity_key) => {
+                self.verified.insert(device_id.to_string(), identity_key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget the keys staged and bound for `device_id`
+    pub fn unbind(&mut self, device_id: &str) {
+        self.pending.remove(device_id);
+        self.verified.remove(device_id);
+    }
+
+    /// Forget every staged and bound key
+    pub fn unbind_all(&mut self) {
+        self.pending.clear();
+        self.verified.clear();
+    }
+
+    pub(crate) fn requester_key(&self, device_id: &str) -> Result<&str, String> {
+        self.verified
+            .get(device_id)
+            .map(String::as_str)
+            .ok_or_else(|| format!("No verified handshake from {}", device_id))
+    }
+}
+
+impl P2PNode {
+    /// Accept tokens signed by this identity key (base64), normally our own and the vault owner's
+    pub fn trust_token_issuer(&mut self, public_key: String) {
+        self.access.issuers.insert(public_key);
+    }
+
+    pub fn untrust_token_issuer(&mut self, public_key: &str) {
+        self.access.issuers.remove(public_key);
+    }
+
+    /// Issue a token for `grantee_public_key` with `permissions_json`
+    /// (`{read_only, folders, deny_attachments}`), valid until `expires_at` (0: no expiry)
+    pub fn issue_capability_token(
+        &mut self,
+        identity: &DeviceIdentity,
+        grantee_public_key: String,
+        permissions_json: &str,
+        expires_at: u64,
+    ) -> Result<String, String> {
+        let permissions: Permissions = serde_json::from_str(permissions_json)
+            .map_err(|e| self.record_error(format!("Invalid permissions: {}", e)))?;
+        let grant = Grant {
+            id: ids::new_id(),
+            issuer: identity.get_public_key(),
+            grantee: grantee_public_key,
+            permissions,
+            issued_at: clock::now_ms(),
+            expires_at: (expires_at != 0).then_some(expires_at),
+        };
+        Ok(issue(identity, &grant))
+    }
+
+    /// Give `guest_public_key` read-only access to `folder` for `duration_ms`; returns
+    /// the session (`{id, fingerprint, folder, expires_at, token}`) as JSON
+    pub fn create_guest_session(
+        &mut self,
+        identity: &DeviceIdentity,
+        guest_public_key: String,
+        folder: String,
+        duration_ms: u64,
+    ) -> Result<String, String> {
+        self.start_guest_session(identity, guest_public_key, folder, duration_ms)
+            .and_then(|session| serde_json::to_string(&session).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// End a guest session before it expires; false if there is no such session
+    pub fn revoke_guest_session(&mut self, id: &str) -> bool {
+        let Some(index) = self.access.guests.iter().position(|g| g.id == id) else {
+            return false;
+        };
+        let session = self.access.guests.remove(index);
+        self.access.revoked.insert(session.id, session.expires_at);
+        true
+    }
+
+    pub fn get_guest_sessions_json(&self) -> String {
+        serde_json::to_string(&self.access.guests).unwrap_or_default()
+    }
+
+    /// Trusted issuers, guest sessions and revocations as JSON, for persisting with `load_access_state`
+    pub fn get_access_state(&self) -> String {
+        serde_json::to_string(&self.access).unwrap_or_default()
+    }
+
+    pub fn load_access_state(&mut self, json: &str) -> Result<(), String> {
+        let access = check_size("Access state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load access state: {}", e)))?;
+        self.access = access;
+        Ok(())
+    }
+
+    /// Check a request from `device_id`, whose handshake was verified, before serving it;
+    /// `access` is `manifest`, `read` or `write`. Errors with the reason when the token doesn't allow it
+    pub fn authorize_request(&mut self, token: &str, device_id: &str, access: &str, path: &str) -> Result<(), String> {
+        Access::parse(access)
+            .and_then(|access| self.authorize_peer(token, device_id, access, path))
+            .map(|_| ())
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// The journal entries a token lets `device_id` see, as a JSON array
+    pub fn get_manifest_for_token(&mut self, token: &str, device_id: &str) -> Result<String, String> {
+        self.manifest_for_token(token, device_id)
+            .and_then(|files| serde_json::to_string(&files).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Start producing the transfer chunks of `path` for `device_id`, if its token allows
+    /// reading it; see `begin_file_ingest`
+    pub fn begin_serving_file(
+        &mut self,
+        token: &str,
+        device_id: &str,
+        path: String,
+        size: u64,
+        session_key: String,
+    ) -> Result<FileIngest, String> {
+        self.serve_file(token, device_id, path, size, session_key).map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn start_guest_session(
+        &mut self,
+        identity: &DeviceIdentity,
+        guest_public_key: String,
+        folder: String,
+        duration_ms: u64,
+    ) -> Result<GuestSession, String> {
+        let folder = folder.trim_matches('/').to_string();
+        if folder.is_empty() {
+            return Err("A guest session needs a folder".to_string());
+        }
+        if duration_ms == 0 {
+            return Err("A guest session needs a duration".to_string());
+        }
+        let now = clock::now_ms();
+        let grant = Grant {
+            id: ids::new_id(),
+            issuer: identity.get_public_key(),
+            grantee: guest_public_key,
+            permissions: Permissions { read_only: true, folders: vec![folder.clone()], deny_attachments: false },
+            issued_at: now,
+            expires_at: Some(now.saturating_add(duration_ms)),
+        };
+        let session = GuestSession {
+            id: grant.id.clone(),
+            fingerprint: generate_fingerprint(&grant.grantee),
+            folder,
+            expires_at: now.saturating_add(duration_ms),
+            token: issue(identity, &grant),
+        };
+        self.access.issuers.insert(grant.issuer);
+        self.access.guests.push(session.clone());
+        Ok(session)
+    }
+
+    /// Verify `token` for `requester` and check it allows `access` to `path`; returns the grant
+    pub(crate) fn authorize(&mut self, token: &str, requester: &str, access: Access, path: &str) -> Result<Grant, String> {
+        let grant = open(token).inspect_err(|e| {
+            if e.starts_with("Invalid signature") {
+                self.security_log.record(SecurityEventKind::SignatureInvalid, requester, e.clone(), &[("frame", "capability_token")]);
+            }
+        })?;
+        if self.access.revoked.contains_key(&grant.id) {
+            return Err(format!("Capability token {} was revoked", grant.id));
+        }
+        if !self.access.issuers.contains(&grant.issuer) {
+            return Err(format!("Capability token {} was issued by an untrusted device", grant.id));
+        }
+        if grant.grantee != requester {
+            return Err(format!("Capability token {} belongs to another device", grant.id));
+        }
+        if grant.expires_at.is_some_and(|at| clock::now_ms() >= at) {
+            return Err(format!("Capability token {} has expired", grant.id));
+        }
+        let permissions = &grant.permissions;
+        if access == Access::Write && permissions.read_only {
+            return Err("Capability token is read-only".to_string());
+        }
+        if access != Access::Manifest {
+            if !permissions.covers(path) {
+                return Err(format!("Capability token does not cover {}", path));
+            }
+            if permissions.deny_attachments && self.attachment_policy.is_attachment(path) {
+                return Err(format!("Capability token does not allow attachments: {}", path));
+            }
+        }
+        Ok(grant)
+    }
+
+    /// `authorize` for the identity key `device_id`'s handshake was verified with
+    pub(crate) fn authorize_peer(&mut self, token: &str, device_id: &str, access: Access, path: &str) -> Result<Grant, String> {
+        self.check_not_quarantined(device_id)?;
+        let requester = self.access.requester_key(device_id)?.to_string();
+        self.authorize(token, &requester, access, path)
+    }
+
+    pub(crate) fn manifest_for_token(&mut self, token: &str, device_id: &str) -> Result<Vec<FileMetadata>, String> {
+        let permissions = self.authorize_peer(token, device_id, Access::Manifest, "")?.permissions;
+        let mut files: Vec<FileMetadata> = self
+            .change_journal
+            .files()
+            .filter(|m| permissions.covers(&m.path))
+            .filter(|m| !(permissions.deny_attachments && self.attachment_policy.is_attachment(&m.path)))
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    pub(crate) fn serve_file(
+        &mut self,
+        token: &str,
+        device_id: &str,
+        path: String,
+        size: u64,
+        session_key: String,
+    ) -> Result<FileIngest, String> {
+        self.authorize_peer(token, device_id, Access::Read, &path)?;
+        self.create_file_ingest(path, size, Some(session_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyExchange;
+
+    #[test]
+    fn test_token_limits_what_is_served() {
+        let owner = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let guest = DeviceIdentity::new("tablet".to_string()).unwrap();
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.set_attachment_folder("./");
+        node.update_file("Shared/a.md".to_string(), b"a", 1);
+        node.update_file("Shared/photo.png".to_string(), b"p", 1);
+        node.update_file("Private/b.md".to_string(), b"b", 1);
+
+        let permissions = r#"{"read_only":true,"folders":["Shared"],"deny_attachments":true}"#;
+        let token = node.issue_capability_token(&owner, guest.get_public_key(), permissions, 0).unwrap();
+        let requester = guest.get_public_key();
+        // Not trusted yet
+        assert!(node.authorize(&token, &requester, Access::Read, "Shared/a.md").is_err());
+        node.trust_token_issuer(owner.get_public_key());
+
+        assert!(node.authorize(&token, &requester, Access::Read, "Shared/a.md").is_ok());
+        assert!(node.authorize(&token, &requester, Access::Write, "Shared/a.md").is_err());
+        assert!(node.authorize(&token, &requester, Access::Read, "Private/b.md").is_err());
+        assert!(node.authorize(&token, &requester, Access::Read, "Shared/photo.png").is_err());
+        assert!(node.authorize(&token, &owner.get_public_key(), Access::Read, "Shared/a.md").is_err());
+        for traversal in ["Shared/../Private/b.md", "Shared/./a.md", "Shared//a.md", "/Shared/a.md"] {
+            assert!(node.authorize(&token, &requester, Access::Read, traversal).is_err(), "{}", traversal);
+        }
+
+        // Served requests are bound to the key the requester's handshake was verified with
+        let tablet = P2PNode::new("Tablet".to_string(), "tablet".to_string(), 8082);
+        let session = BASE64.encode([9u8; 32]);
+        assert!(node.manifest_for_token(&token, "tablet").is_err());
+        node.apply_handshake_frame(&tablet.get_handshake_frame(&guest, &KeyExchange::new())).unwrap();
+        assert!(node.manifest_for_token(&token, "tablet").is_err());
+        let confirmation = BASE64.encode([7u8; 32]);
+        assert!(node.check_session_confirmation("tablet", &confirmation, &confirmation));
+        let paths: Vec<String> = node.manifest_for_token(&token, "tablet").unwrap().into_iter().map(|m| m.path).collect();
+        assert_eq!(paths, vec!["Shared/a.md"]);
+        assert!(node.serve_file(&token, "tablet", "Shared/a.md".to_string(), 1, session.clone()).is_ok());
+        assert!(node.serve_file(&token, "tablet", "Shared/../Private/b.md".to_string(), 1, session.clone()).is_err());
+        assert!(node.serve_file(&token, "laptop", "Shared/a.md".to_string(), 1, session).is_err());
+
+        // A token the grantee widened itself no longer verifies
+        let mut signed: serde_json::Value = serde_json::from_slice(&BASE64.decode(&token).unwrap()).unwrap();
+        signed["grant"] = signed["grant"].as_str().unwrap().replace(r#""read_only":true"#, r#""read_only":false"#).into();
+        let forged = BASE64.encode(serde_json::to_vec(&signed).unwrap());
+        assert!(node.authorize(&forged, &requester, Access::Write, "Shared/a.md").is_err());
+        assert_eq!(node.security_log.len(), 1);
+
+        clock::set_clock_time(1_000);
+        let expiring = node.issue_capability_token(&owner, requester.clone(), "{}", 2_000).unwrap();
+        assert!(node.authorize(&expiring, &requester, Access::Write, "Private/b.md").is_ok());
+        clock::advance_clock(1_000);
+        assert!(node.authorize(&expiring, &requester, Access::Read, "Private/b.md").is_err());
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_guest_sessions_expire_and_revoke() {
+        clock::set_clock_time(10_000);
+        let owner = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let guest = DeviceIdentity::new("colleague".to_string()).unwrap();
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        const WEEK: u64 = 7 * 24 * 60 * 60_000;
+
+        let session = node.start_guest_session(&owner, guest.get_public_key(), "/Project/".to_string(), WEEK).unwrap();
+        assert_eq!(session.folder, "Project");
+        let requester = guest.get_public_key();
+        assert!(node.authorize(&session.token, &requester, Access::Read, "Project/plan.md").is_ok());
+        assert!(node.authorize(&session.token, &requester, Access::Read, "Diary/today.md").is_err());
+        assert!(node.authorize(&session.token, &requester, Access::Write, "Project/plan.md").is_err());
+
+        // Revocation survives a restart
+        let second = node.start_guest_session(&owner, guest.get_public_key(), "Project".to_string(), WEEK).unwrap();
+        assert!(node.revoke_guest_session(&second.id));
+        assert!(!node.revoke_guest_session(&second.id));
+        let mut restarted = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        restarted.load_access_state(&node.get_access_state()).unwrap();
+        assert!(restarted.authorize(&second.token, &requester, Access::Read, "Project/plan.md").is_err());
+        assert!(restarted.authorize(&session.token, &requester, Access::Read, "Project/plan.md").is_ok());
+
+        clock::advance_clock(WEEK);
+        restarted.tick(clock::now_ms());
+        assert_eq!(restarted.get_guest_sessions_json(), "[]");
+        assert!(restarted.access.revoked.is_empty());
+        assert!(restarted.authorize(&session.token, &requester, Access::Read, "Project/plan.md").is_err());
+        assert!(node.start_guest_session(&owner, requester, String::new(), WEEK).is_err());
+        clock::use_system_clock();
+    }
+}
@@ -0,0 +1,424 @@
+//! Vault snapshot archives
+//!
+//! Builds a tar or zip archive of the vault incrementally: the host feeds
+//! files one at a time and appends the returned bytes to its output, so the
+//! whole vault never has to sit in memory. `finish()` writes a
+//! `MANIFEST.json` entry listing every file's SHA-256 and then the format
+//! trailer. With a key, every emitted segment is sealed with AES-256-GCM;
+//! `decrypt_archive` turns such a stream back into the plain archive.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::clock;
+use crate::crypto::{decrypt_data, encrypt_data};
+use crate::sync::hash_content;
+
+pub const MANIFEST_NAME: &str = "MANIFEST.json";
+
+/// Leading bytes of an encrypted archive stream
+const ENCRYPTED_MAGIC: &[u8] = b"P2PSARC1";
+const NONCE_LEN: usize = 12;
+const TAR_BLOCK: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub created_at: u64,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Central directory record kept until `finish()` (zip only)
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+pub struct ArchiveBuilder {
+    format: ArchiveFormat,
+    key_b64: Option<String>,
+    manifest: Vec<ManifestEntry>,
+    paths: HashSet<String>,
+    zip_entries: Vec<ZipEntry>,
+    /// Plain archive bytes emitted so far
+    offset: u64,
+    started: bool,
+    finished: bool,
+}
+
+impl ArchiveBuilder {
+    /// `format` is `tar` or `zip`; with `key_b64` the output is encrypted
+    pub fn new(format: &str, key_b64: Option<String>) -> Result<ArchiveBuilder, String> {
+        let format = match format {
+            "tar" => ArchiveFormat::Tar,
+            "zip" => ArchiveFormat::Zip,
+            other => return Err(format!("Unknown archive format: {}", other)),
+        };
+        Ok(ArchiveBuilder {
+            format,
+            key_b64,
+            manifest: Vec::new(),
+            paths: HashSet::new(),
+            zip_entries: Vec::new(),
+            offset: 0,
+            started: false,
+            finished: false,
+        })
+    }
+
+    /// Add one file; returns the bytes to append to the output
+    pub fn add_file(&mut self, path: String, content: &[u8], mtime: u64) -> Result<Vec<u8>, String> {
+        if self.finished {
+            return Err("Archive already finished".to_string());
+        }
+        if path.is_empty() || path == MANIFEST_NAME || path.starts_with('/') || path.split('/').any(|s| s == "..") {
+            return Err(format!("Invalid archive path: {}", path));
+        }
+        if !self.paths.insert(path.clone()) {
+            return Err(format!("Duplicate archive path: {}", path));
+        }
+        self.manifest.push(ManifestEntry {
+            path: path.clone(),
+            sha256: hash_content(content),
+            size: content.len() as u64,
+            mtime,
+        });
+        let plain = self.entry_bytes(&path, content, mtime)?;
+        self.emit(plain)
+    }
+
+    /// Write the manifest and format trailer; returns the final output bytes
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        if self.finished {
+            return Err("Archive already finished".to_string());
+        }
+        let now = clock::now_ms();
+        let manifest = serde_json::to_vec_pretty(&self.manifest())
+            .map_err(|e| e.to_string())?;
+        let mut plain = self.entry_bytes(MANIFEST_NAME, &manifest, now)?;
+        match self.format {
+            ArchiveFormat::Tar => plain.extend_from_slice(&[0u8; 2 * TAR_BLOCK]),
+            ArchiveFormat::Zip => plain.extend(self.zip_central_directory(plain.len())?),
+        }
+        self.finished = true;
+        self.emit(plain)
+    }
+
+    /// Manifest of the files added so far as JSON
+    pub fn get_manifest_json(&self) -> String {
+        serde_json::to_string(&self.manifest()).unwrap_or_default()
+    }
+
+    pub fn get_file_count(&self) -> usize {
+        self.manifest.len()
+    }
+}
+
+impl ArchiveBuilder {
+    fn manifest(&self) -> Manifest {
+        Manifest { format_version: 1, created_at: clock::now_ms(), files: self.manifest.clone() }
+    }
+
+    fn entry_bytes(&mut self, path: &str, content: &[u8], mtime: u64) -> Result<Vec<u8>, String> {
+        match self.format {
+            ArchiveFormat::Tar => Ok(tar_entry(path, content, mtime)),
+            ArchiveFormat::Zip => self.zip_entry(path, content, mtime),
+        }
+    }
+
+    /// Track the plain offset and seal the segment when encrypting
+    fn emit(&mut self, plain: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.offset += plain.len() as u64;
+        let Some(key) = &self.key_b64 else {
+            return Ok(plain);
+        };
+        let sealed = encrypt_data(key.clone(), &plain)?;
+        let data = sealed.get_data();
+        let mut out = Vec::with_capacity(ENCRYPTED_MAGIC.len() + 4 + NONCE_LEN + data.len());
+        if !self.started {
+            out.extend_from_slice(ENCRYPTED_MAGIC);
+        }
+        self.started = true;
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&sealed.get_nonce());
+        out.extend_from_slice(&data);
+        Ok(out)
+    }
+
+    fn zip_entry(&mut self, path: &str, content: &[u8], mtime: u64) -> Result<Vec<u8>, String> {
+        let size = u32::try_from(content.len()).map_err(|_| format!("File too large for zip: {}", path))?;
+        let offset = u32::try_from(self.offset).map_err(|_| "Archive too large for zip".to_string())?;
+        if self.zip_entries.len() == u16::MAX as usize {
+            return Err("Too many files for zip".to_string());
+        }
+        let (dos_time, dos_date) = dos_datetime(mtime);
+        let entry = ZipEntry { name: path.to_string(), crc: crc32(content), size, offset, dos_time, dos_date };
+
+        let mut out = Vec::with_capacity(30 + path.len() + content.len());
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        out.extend_from_slice(&0x0800u16.to_le_bytes()); // UTF-8 names
+        out.extend_from_slice(&0u16.to_le_bytes()); // stored
+        out.extend_from_slice(&entry.dos_time.to_le_bytes());
+        out.extend_from_slice(&entry.dos_date.to_le_bytes());
+        out.extend_from_slice(&entry.crc.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // extra length
+        out.extend_from_slice(path.as_bytes());
+        out.extend_from_slice(content);
+        self.zip_entries.push(entry);
+        Ok(out)
+    }
+
+    /// Central directory and end record; `pending` bytes precede it in this segment
+    fn zip_central_directory(&self, pending: usize) -> Result<Vec<u8>, String> {
+        let start = u32::try_from(self.offset + pending as u64).map_err(|_| "Archive too large for zip".to_string())?;
+        let mut out = Vec::new();
+        for entry in &self.zip_entries {
+            out.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            out.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            out.extend_from_slice(&0x0800u16.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&entry.dos_time.to_le_bytes());
+            out.extend_from_slice(&entry.dos_date.to_le_bytes());
+            out.extend_from_slice(&entry.crc.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&[0u8; 12]); // extra, comment, disk, attributes
+            out.extend_from_slice(&entry.offset.to_le_bytes());
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+        let count = self.zip_entries.len() as u16;
+        let size = out.len() as u32;
+        out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        out.extend_from_slice(&[0u8; 4]); // disk numbers
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        Ok(out)
+    }
+}
+
+/// Recover the plain archive from an encrypted stream
+pub fn decrypt_archive(key_b64: String, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut rest = data.strip_prefix(ENCRYPTED_MAGIC).ok_or("Not an encrypted archive")?;
+    let mut out = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 4 + NONCE_LEN {
+            return Err("Truncated archive segment".to_string());
+        }
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let (nonce, body) = rest[4..].split_at(NONCE_LEN);
+        if body.len() < len {
+            return Err("Truncated archive segment".to_string());
+        }
+        out.extend(decrypt_data(key_b64.clone(), &body[..len], nonce)?);
+        rest = &body[len..];
+    }
+    Ok(out)
+}
+
+/// One ustar entry, preceded by a PAX header when the path does not fit
+fn tar_entry(path: &str, content: &[u8], mtime_ms: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 * TAR_BLOCK + content.len());
+    let mtime = mtime_ms / 1000;
+    let split = split_ustar_path(path);
+    if split.is_none() {
+        let record = pax_record("path", path);
+        out.extend(tar_header("PaxHeader", record.len() as u64, mtime, b'x'));
+        out.extend_from_slice(&record);
+        pad_block(&mut out);
+    }
+    let (prefix, name) = split.unwrap_or(("", truncate_bytes(path, 100)));
+    let mut header = tar_header(name, content.len() as u64, mtime, b'0');
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    set_tar_checksum(&mut header);
+    out.extend(header);
+    out.extend_from_slice(content);
+    pad_block(&mut out);
+    out
+}
+
+fn tar_header(name: &str, size: u64, mtime: u64, typeflag: u8) -> Vec<u8> {
+    let mut header = vec![0u8; TAR_BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes());
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    set_tar_checksum(&mut header);
+    header
+}
+
+fn set_tar_checksum(header: &mut [u8]) {
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+}
+
+/// Split into ustar `(prefix, name)` when the path fits the fixed fields
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+fn truncate_bytes(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// `"<len> key=value\n"` where `<len>` counts the whole record
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len() + 1;
+    while format!("{}{}", len, body).len() != len {
+        len += 1;
+    }
+    format!("{}{}", len, body).into_bytes()
+}
+
+fn pad_block(out: &mut Vec<u8>) {
+    let rem = out.len() % TAR_BLOCK;
+    if rem != 0 {
+        out.resize(out.len() + TAR_BLOCK - rem, 0);
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// MS-DOS `(time, date)` for a Unix time in ms (UTC, clamped to 1980..2107)
+fn dos_datetime(mtime_ms: u64) -> (u16, u16) {
+    let secs = mtime_ms / 1000;
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = year.min(2107);
+    let time = ((rem / 3600) << 11) | (((rem % 3600) / 60) << 5) | ((rem % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_layout_and_long_paths() {
+        let mut builder = ArchiveBuilder::new("tar", None).unwrap();
+        let mut out = builder.add_file("Notes/a.md".to_string(), b"hello", 1_700_000_000_000).unwrap();
+        assert_eq!(out.len(), 2 * TAR_BLOCK);
+        assert_eq!(&out[..10], b"Notes/a.md");
+        assert_eq!(&out[257..262], b"ustar");
+        assert_eq!(&out[TAR_BLOCK..TAR_BLOCK + 5], b"hello");
+
+        let long = format!("{}/{}.md", "d".repeat(120), "n".repeat(150));
+        let pax = builder.add_file(long.clone(), b"x", 0).unwrap();
+        assert_eq!(pax[156], b'x');
+        assert!(String::from_utf8_lossy(&pax).contains(&format!("path={}\n", long)));
+
+        out.extend(builder.finish().unwrap());
+        assert!(out.ends_with(&[0u8; 2 * TAR_BLOCK]));
+        assert!(builder.add_file("late.md".to_string(), b"", 0).is_err());
+    }
+
+    #[test]
+    fn test_zip_directory_and_crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(dos_datetime(0), (0, 33));
+        // 2024-02-29 12:34:56 UTC
+        assert_eq!(dos_datetime(1_709_210_096_000), ((12 << 11) | (34 << 5) | 28, (44 << 9) | (2 << 5) | 29));
+
+        let mut builder = ArchiveBuilder::new("zip", None).unwrap();
+        let mut out = builder.add_file("a.md".to_string(), b"alpha", 0).unwrap();
+        out.extend(builder.finish().unwrap());
+        let eocd = &out[out.len() - 22..];
+        assert_eq!(&eocd[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([eocd[10], eocd[11]]), 2);
+        let cd_offset = u32::from_le_bytes([eocd[16], eocd[17], eocd[18], eocd[19]]) as usize;
+        assert_eq!(&out[cd_offset..cd_offset + 4], &0x02014b50u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_encrypted_archive_round_trip() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+        let key = BASE64.encode([7u8; 32]);
+
+        let mut plain = ArchiveBuilder::new("tar", None).unwrap();
+        let mut sealed = ArchiveBuilder::new("tar", Some(key.clone())).unwrap();
+        let mut expected = plain.add_file("a.md".to_string(), b"secret", 0).unwrap();
+        let mut stream = sealed.add_file("a.md".to_string(), b"secret", 0).unwrap();
+        stream.extend(sealed.finish().unwrap());
+        expected.extend(plain.finish().unwrap());
+
+        let decrypted = decrypt_archive(key, &stream).unwrap();
+        // Manifest timestamps may differ; the entry itself must match exactly
+        assert_eq!(decrypted[..2 * TAR_BLOCK], expected[..2 * TAR_BLOCK]);
+        assert_eq!(decrypted.len(), expected.len());
+        assert!(!stream.windows(6).any(|w| w == b"secret"));
+    }
+}
@@ -0,0 +1,192 @@
+//! Attachment layout awareness
+//!
+//! Obsidian stores attachments according to the `attachmentFolderPath`
+//! setting: the vault root (`/`), next to the note (`./`), a subfolder next
+//! to the note (`./assets`), or one fixed folder (`Attachments`). Knowing the
+//! layout lets a device express rules such as "attachments only on Wi-Fi",
+//! "attachments up to 25 MB" or "attachments after notes".
+
+use serde::{Deserialize, Serialize};
+
+/// Extensions Obsidian treats as notes rather than attachments
+const NOTE_EXTENSIONS: &[&str] = &["md", "canvas"];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AttachmentLayout {
+    VaultRoot,
+    SameFolderAsNote,
+    SubfolderOfNote { name: String },
+    Folder { path: String },
+}
+
+impl AttachmentLayout {
+    /// Interpret Obsidian's `attachmentFolderPath` value
+    pub fn from_obsidian_setting(value: &str) -> AttachmentLayout {
+        let value = value.trim();
+        match value {
+            "" | "/" => AttachmentLayout::VaultRoot,
+            "./" | "." => AttachmentLayout::SameFolderAsNote,
+            _ => match value.strip_prefix("./") {
+                Some(name) => AttachmentLayout::SubfolderOfNote { name: name.trim_end_matches('/').to_string() },
+                None => AttachmentLayout::Folder { path: value.trim_matches('/').to_string() },
+            },
+        }
+    }
+}
+
+/// Per-device rules for attachment transfers
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttachmentRules {
+    /// Defer attachments while on a metered network
+    #[serde(default)]
+    pub unmetered_only: bool,
+    /// Never transfer attachments larger than this
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+    /// Schedule attachments after all notes
+    #[serde(default)]
+    pub transfer_last: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDecision {
+    Allow,
+    /// Allowed, but only after everything else
+    AllowLast,
+    /// Not now (e.g. metered network); retry later
+    Defer,
+    /// Never transferred under the current rules
+    Skip,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AttachmentPolicy {
+    pub layout: AttachmentLayout,
+    #[serde(default)]
+    pub rules: AttachmentRules,
+}
+
+impl Default for AttachmentPolicy {
+    fn default() -> Self {
+        AttachmentPolicy { layout: AttachmentLayout::VaultRoot, rules: AttachmentRules::default() }
+    }
+}
+
+impl AttachmentPolicy {
+    /// Whether the path is an attachment under the configured layout
+    pub fn is_attachment(&self, path: &str) -> bool {
+        let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+        if extension.is_some_and(|ext| NOTE_EXTENSIONS.contains(&ext.as_str())) {
+            return false;
+        }
+        if path.starts_with(".obsidian/") {
+            return false;
+        }
+        let parent = path.rsplit_once('/').map(|(dir, _)| dir);
+        match &self.layout {
+            AttachmentLayout::VaultRoot => parent.is_none(),
+            AttachmentLayout::SameFolderAsNote => true,
+            AttachmentLayout::SubfolderOfNote { name } => {
+                parent.is_some_and(|dir| dir.rsplit('/').next() == Some(name.as_str()))
+            }
+            AttachmentLayout::Folder { path: folder } => {
+                path.strip_prefix(folder.as_str()).is_some_and(|rest| rest.starts_with('/'))
+            }
+        }
+    }
+
+    /// Decide whether a file of `size` bytes may be transferred now
+    pub fn decide(&self, path: &str, size: u64, metered: bool) -> TransferDecision {
+        if !self.is_attachment(path) {
+            return TransferDecision::Allow;
+        }
+        if self.rules.max_size_bytes.is_some_and(|max| size > max) {
+            return TransferDecision::Skip;
+        }
+        if metered && self.rules.unmetered_only {
+            return TransferDecision::Defer;
+        }
+        if self.rules.transfer_last {
+            TransferDecision::AllowLast
+        } else {
+            TransferDecision::Allow
+        }
+    }
+}
+
+/// Input entry for transfer ordering
+#[derive(Deserialize, Clone, Debug)]
+pub struct PendingFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Transfer plan split by decision, in the order transfers should happen
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferOrder {
+    pub transfer: Vec<String>,
+    pub deferred: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Order pending files according to attachment rules (stable within each group)
+pub fn order_transfers(policy: &AttachmentPolicy, files: &[PendingFile], metered: bool) -> TransferOrder {
+    let mut order = TransferOrder::default();
+    let mut last = Vec::new();
+    for file in files {
+        match policy.decide(&file.path, file.size, metered) {
+            TransferDecision::Allow => order.transfer.push(file.path.clone()),
+            TransferDecision::AllowLast => last.push(file.path.clone()),
+            TransferDecision::Defer => order.deferred.push(file.path.clone()),
+            TransferDecision::Skip => order.skipped.push(file.path.clone()),
+        }
+    }
+    order.transfer.extend(last);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_from_obsidian_setting() {
+        assert_eq!(AttachmentLayout::from_obsidian_setting("/"), AttachmentLayout::VaultRoot);
+        assert_eq!(AttachmentLayout::from_obsidian_setting("./"), AttachmentLayout::SameFolderAsNote);
+        assert_eq!(
+            AttachmentLayout::from_obsidian_setting("./assets"),
+            AttachmentLayout::SubfolderOfNote { name: "assets".to_string() }
+        );
+        assert_eq!(
+            AttachmentLayout::from_obsidian_setting("Files/Attachments"),
+            AttachmentLayout::Folder { path: "Files/Attachments".to_string() }
+        );
+    }
+
+    #[test]
+    fn test_mobile_rules_order_and_filter() {
+        let policy = AttachmentPolicy {
+            layout: AttachmentLayout::Folder { path: "Attachments".to_string() },
+            rules: AttachmentRules { unmetered_only: false, max_size_bytes: Some(25 * 1024 * 1024), transfer_last: true },
+        };
+        let files = vec![
+            PendingFile { path: "Attachments/big.mp4".to_string(), size: 100 * 1024 * 1024 },
+            PendingFile { path: "Attachments/pic.png".to_string(), size: 1024 },
+            PendingFile { path: "Notes/a.md".to_string(), size: 10 },
+            PendingFile { path: "Other/doc.pdf".to_string(), size: 1024 },
+        ];
+        let order = order_transfers(&policy, &files, false);
+        assert_eq!(order.transfer, vec!["Notes/a.md", "Other/doc.pdf", "Attachments/pic.png"]);
+        assert_eq!(order.skipped, vec!["Attachments/big.mp4"]);
+
+        let wifi_only = AttachmentPolicy {
+            rules: AttachmentRules { unmetered_only: true, ..Default::default() },
+            ..policy
+        };
+        let order = order_transfers(&wifi_only, &files, true);
+        assert_eq!(order.transfer, vec!["Notes/a.md", "Other/doc.pdf"]);
+        assert_eq!(order.deferred.len(), 2);
+    }
+}
@@ -0,0 +1,154 @@
+//! Sync audit log
+//!
+//! Every operation applied to the journal is recorded with its time, the
+//! device that authored the change and where it came from, so users can
+//! answer questions like "which device deleted this note, and when?". The
+//! log is bounded (oldest entries are dropped first) and can be exported
+//! and persisted by the host.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::sync::FileMetadata;
+use crate::memory::{deque_bytes, string_bytes, MemoryFootprint};
+
+/// Default number of audit entries retained
+pub const DEFAULT_AUDIT_CAPACITY: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Update,
+    Delete,
+    ConflictResolved,
+}
+
+/// Where an applied operation came from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditOrigin {
+    /// Made on this device
+    Local,
+    /// Pulled from a peer
+    Peer { peer_id: String },
+    /// Brought in by an importer or bulk index
+    Import { source: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    /// Journal sequence the operation produced
+    pub sequence: u64,
+    pub action: AuditAction,
+    pub path: String,
+    /// Device that authored the change
+    pub device_id: String,
+    pub origin: AuditOrigin,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> AuditLog {
+        AuditLog { entries: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    pub fn push(&mut self, entry: AuditEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Record a journal version as an update or deletion
+    pub fn record_version(&mut self, meta: &FileMetadata, origin: AuditOrigin, timestamp: u64) {
+        let action = if meta.is_deleted { AuditAction::Delete } else { AuditAction::Update };
+        self.push(AuditEntry {
+            timestamp,
+            sequence: meta.version,
+            action,
+            path: meta.path.clone(),
+            device_id: meta.last_modified_by.clone(),
+            origin,
+            hash: (!meta.is_deleted).then(|| meta.hash.clone()),
+            detail: None,
+        });
+    }
+
+    /// Entries for one path (or all when `path` is empty), oldest first
+    pub fn entries_for<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a AuditEntry> {
+        self.entries.iter().filter(move |e| path.is_empty() || e.path == path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog::new(DEFAULT_AUDIT_CAPACITY)
+    }
+}
+
+impl MemoryFootprint for AuditLog {
+    fn heap_bytes(&self) -> usize {
+        deque_bytes(&self.entries, |e| {
+            string_bytes(&e.path)
+                + string_bytes(&e.device_id)
+                + e.hash.as_deref().map_or(0, string_bytes)
+                + e.detail.as_deref().map_or(0, string_bytes)
+        })
+    }
+
+    fn trim(&mut self) {
+        self.entries.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_and_filtered() {
+        let mut log = AuditLog::new(2);
+        for (i, path) in ["a.md", "b.md", "a.md"].iter().enumerate() {
+            let meta = FileMetadata {
+                path: path.to_string(),
+                hash: String::new(),
+                mtime: 0,
+                size: 0,
+                version: i as u64 + 1,
+                is_deleted: i == 2,
+                last_modified_by: "phone".to_string(),
+                moved_from: None,
+                extended: Default::default(),
+            };
+            log.record_version(&meta, AuditOrigin::Peer { peer_id: "p1".to_string() }, i as u64);
+        }
+        assert_eq!(log.len(), 2);
+        let a: Vec<&AuditEntry> = log.entries_for("a.md").collect();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].action, AuditAction::Delete);
+        assert_eq!(a[0].hash, None);
+        assert_eq!(log.entries_for("").count(), 2);
+    }
+}
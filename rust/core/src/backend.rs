@@ -0,0 +1,156 @@
+//! Mirror backends
+//!
+//! A backend is a dumb storage target (WebDAV share, object store…) that
+//! receives a copy of the vault but does not run this plugin. The journal
+//! decides what has to change on the target; each backend turns those
+//! actions into the requests its protocol needs, which the host performs
+//! with its own HTTP client and feeds back as responses.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sync::FileMetadata;
+
+/// One change the target needs to match the journal
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MirrorAction {
+    Put { path: String, hash: String, size: u64 },
+    Delete { path: String },
+}
+
+/// Actions that bring a target holding `remote` (path → content hash) in line
+/// with the journal, uploads sorted by path, then deletions
+pub fn plan_mirror<'a>(
+    files: impl Iterator<Item = &'a FileMetadata>,
+    remote: &HashMap<String, String>,
+) -> Vec<MirrorAction> {
+    let mut puts = Vec::new();
+    let mut deletes = Vec::new();
+    for meta in files {
+        match (meta.is_deleted, remote.get(&meta.path)) {
+            (false, Some(hash)) if *hash == meta.hash => {}
+            (false, _) => puts.push(MirrorAction::Put {
+                path: meta.path.clone(),
+                hash: meta.hash.clone(),
+                size: meta.size,
+            }),
+            (true, Some(_)) => deletes.push(MirrorAction::Delete { path: meta.path.clone() }),
+            (true, None) => {}
+        }
+    }
+    let path = |action: &MirrorAction| match action {
+        MirrorAction::Put { path, .. } | MirrorAction::Delete { path } => path.clone(),
+    };
+    puts.sort_by_key(path);
+    deletes.sort_by_key(path);
+    puts.extend(deletes);
+    puts
+}
+
+/// An HTTP request for the host to perform
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub id: u64,
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    /// Literal request body (e.g. a PROPFIND query)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Send the vault file at this path as the body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_from_path: Option<String>,
+}
+
+/// The host's answer to an `HttpRequest`
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct HttpResponse {
+    pub id: u64,
+    pub status: u16,
+    /// Header names are matched case-insensitively
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Percent-encode each segment of a vault path for use in a URL
+pub fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Reverse `encode_path`; invalid escapes are kept literally
+pub fn decode_path(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(value)) => {
+                out.push(value);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(path: &str, hash: &str, deleted: bool) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            mtime: 0,
+            size: 1,
+            version: 1,
+            is_deleted: deleted,
+            last_modified_by: "dev".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_plan_mirror() {
+        let files = [meta("b.md", "2", false), meta("a.md", "1", false), meta("gone.md", "", true), meta("same.md", "s", false)];
+        let remote: HashMap<String, String> =
+            [("a.md", "old"), ("gone.md", "x"), ("same.md", "s")].iter().map(|(p, h)| (p.to_string(), h.to_string())).collect();
+        let actions = plan_mirror(files.iter(), &remote);
+        assert_eq!(
+            actions,
+            vec![
+                MirrorAction::Put { path: "a.md".to_string(), hash: "1".to_string(), size: 1 },
+                MirrorAction::Put { path: "b.md".to_string(), hash: "2".to_string(), size: 1 },
+                MirrorAction::Delete { path: "gone.md".to_string() },
+            ]
+        );
+        assert_eq!(encode_path("Daily notes/été.md"), "Daily%20notes/%C3%A9t%C3%A9.md");
+        assert_eq!(decode_path(&encode_path("Daily notes/été.md")), "Daily notes/été.md");
+    }
+}
@@ -0,0 +1,194 @@
+//! Binary deltas for changed attachments
+//!
+//! An edited PDF, drawing or recording usually keeps most of its bytes, but
+//! text merging doesn't apply and re-sending the whole file is slow on a
+//! phone. The sender can instead encode the new version as a patch against
+//! the version the peer already has (its content hash is in the peer's
+//! journal). Like xdelta, the base is indexed in fixed blocks, the new file
+//! is scanned with a rolling hash, and every block found is extended as far
+//! as it matches and sent as a copy; everything else is sent as literal
+//! bytes. When the patch would not save at least half the transfer the
+//! encoder gives up and the file goes in full.
+//!
+//! Patches name the SHA-256 of both versions, so applying one to the wrong
+//! base, or a corrupted one, fails instead of producing a damaged file.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Files smaller than one transfer chunk always go in full
+pub const MIN_DELTA_BYTES: usize = 64 * 1024;
+const MAGIC: &[u8; 4] = b"BDF1";
+const BLOCK: usize = 64;
+const HASH_BASE: u32 = 257;
+const OP_ADD: u8 = 0;
+const OP_COPY: u8 = 1;
+
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |h, b| h.wrapping_mul(HASH_BASE).wrapping_add(*b as u32))
+}
+
+fn push_add(out: &mut Vec<u8>, literals: &[u8]) {
+    if !literals.is_empty() {
+        out.push(OP_ADD);
+        prost::encoding::encode_varint(literals.len() as u64, out);
+        out.extend_from_slice(literals);
+    }
+}
+
+fn push_copy(out: &mut Vec<u8>, offset: usize, len: usize) {
+    out.push(OP_COPY);
+    prost::encoding::encode_varint(offset as u64, out);
+    prost::encoding::encode_varint(len as u64, out);
+}
+
+/// A patch turning `base` into `target`, or `None` when a full transfer is as good
+pub fn encode(base: &[u8], target: &[u8]) -> Option<Vec<u8>> {
+    if target.len() < MIN_DELTA_BYTES || base.len() < BLOCK {
+        return None;
+    }
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&Sha256::digest(base));
+    out.extend_from_slice(&Sha256::digest(target));
+    prost::encoding::encode_varint(target.len() as u64, &mut out);
+
+    let mut index = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK).enumerate() {
+        index.entry(block_hash(block)).or_insert(i * BLOCK);
+    }
+    // Weight of the byte leaving the rolling window
+    let outgoing = (1..BLOCK).fold(1u32, |p, _| p.wrapping_mul(HASH_BASE));
+
+    let (mut pos, mut literal_start) = (0, 0);
+    let mut hash = block_hash(&target[..BLOCK]);
+    while pos + BLOCK <= target.len() {
+        let found = index.get(&hash).copied().filter(|&offset| base[offset..offset + BLOCK] == target[pos..pos + BLOCK]);
+        if let Some(offset) = found {
+            // Grow the match backwards into pending literals, then forwards
+            let (mut start, mut source) = (pos, offset);
+            while start > literal_start && source > 0 && target[start - 1] == base[source - 1] {
+                start -= 1;
+                source -= 1;
+            }
+            let mut end = pos + BLOCK;
+            while end < target.len() && source + (end - start) < base.len() && target[end] == base[source + (end - start)] {
+                end += 1;
+            }
+            push_add(&mut out, &target[literal_start..start]);
+            push_copy(&mut out, source, end - start);
+            pos = end;
+            literal_start = end;
+            if pos + BLOCK <= target.len() {
+                hash = block_hash(&target[pos..pos + BLOCK]);
+            }
+            continue;
+        }
+        if pos + BLOCK < target.len() {
+            hash = hash
+                .wrapping_sub((target[pos] as u32).wrapping_mul(outgoing))
+                .wrapping_mul(HASH_BASE)
+                .wrapping_add(target[pos + BLOCK] as u32);
+        }
+        pos += 1;
+    }
+    push_add(&mut out, &target[literal_start..]);
+    (out.len() <= target.len() / 2).then_some(out)
+}
+
+fn read_varint(patch: &mut &[u8]) -> Result<usize, String> {
+    let value = prost::encoding::decode_varint(patch).map_err(|_| "Truncated binary delta")?;
+    usize::try_from(value).map_err(|_| "Binary delta length out of range".to_string())
+}
+
+/// Rebuild the new version from `base` and a patch made by `encode`
+pub fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let header = MAGIC.len() + 64;
+    if patch.len() < header || &patch[..MAGIC.len()] != MAGIC {
+        return Err("Not a binary delta".to_string());
+    }
+    if Sha256::digest(base)[..] != patch[MAGIC.len()..MAGIC.len() + 32] {
+        return Err("Binary delta was made against a different version".to_string());
+    }
+    let target_hash = &patch[MAGIC.len() + 32..header];
+    let mut ops = &patch[header..];
+    let target_len = read_varint(&mut ops)?;
+
+    let mut out = Vec::with_capacity(target_len.min(base.len() + patch.len()));
+    while let Some((&op, rest)) = ops.split_first() {
+        ops = rest;
+        match op {
+            OP_ADD => {
+                let len = read_varint(&mut ops)?;
+                if len > ops.len() {
+                    return Err("Truncated binary delta".to_string());
+                }
+                out.extend_from_slice(&ops[..len]);
+                ops = &ops[len..];
+            }
+            OP_COPY => {
+                let (offset, len) = (read_varint(&mut ops)?, read_varint(&mut ops)?);
+                let source = offset.checked_add(len).and_then(|end| base.get(offset..end)).ok_or("Binary delta copies past the base")?;
+                out.extend_from_slice(source);
+            }
+            other => return Err(format!("Unknown binary delta op {}", other)),
+        }
+        if out.len() > target_len {
+            return Err("Binary delta output exceeds its declared length".to_string());
+        }
+    }
+    if Sha256::digest(&out)[..] != *target_hash {
+        return Err("Binary delta output does not match its hash".to_string());
+    }
+    Ok(out)
+}
+
+/// Patch from the peer's version `base` to `target`, or `undefined` to send `target` in full
+pub fn create_binary_delta(base: &[u8], target: &[u8]) -> Option<Vec<u8>> {
+    encode(base, target)
+}
+
+pub fn apply_binary_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    apply(base, delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_partial_edits_become_small_patches() {
+        let base = noise(300_000, 1);
+        // Bytes inserted, overwritten and removed in a few places
+        let mut target = base[..1000].to_vec();
+        target.extend(noise(500, 7));
+        target.extend_from_slice(&base[1000..150_000]);
+        target.extend(noise(2000, 9));
+        target.extend_from_slice(&base[152_000..290_000]);
+        let patch = encode(&base, &target).unwrap();
+        assert!(patch.len() < 4000, "patch is {} bytes", patch.len());
+        assert_eq!(apply(&base, &patch).unwrap(), target);
+
+        // Unrelated content and small files go in full
+        assert!(encode(&base, &noise(300_000, 5)).is_none());
+        assert!(encode(&base, &base[..1000]).is_none());
+
+        // Wrong base or corrupted patch
+        assert!(apply(&target, &patch).unwrap_err().contains("different version"));
+        let mut corrupted = patch.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(apply(&base, &corrupted).is_err());
+        assert!(apply(&base, &patch[..patch.len() - 3]).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! Bulk initial vault indexing
+//!
+//! The first scan of a large vault feeds entries in batches instead of one
+//! `update_file` call per file. Hosts that already hash files (in a worker,
+//! or from a previous index) pass the hash and size; otherwise the content
+//! is hashed here, a batch at a time. Progress accumulates across batches so each call returns
+//! where the whole indexing run stands.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::sync::ChangeJournal;
+
+use crate::cancel;
+use crate::hashing::{hash_contents, hash_digest, HashAlgorithm};
+use crate::timing::{self, Stage};
+use crate::P2PNode;
+
+#[derive(Deserialize, Debug)]
+pub struct IndexEntry {
+    pub path: String,
+    pub mtime: u64,
+    /// Content hash computed by the host (requires `size`)
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// File content to hash here when no hash is given
+    #[serde(default)]
+    pub content_b64: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BootstrapProgress {
+    pub processed: u64,
+    pub recorded: u64,
+    pub unchanged: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub cancelled: bool,
+    /// First few failures, for display
+    pub errors: Vec<String>,
+}
+
+const MAX_REPORTED_ERRORS: usize = 20;
+
+impl BootstrapProgress {
+    pub(crate) fn record_failure(&mut self, error: String) {
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error);
+        }
+    }
+}
+
+/// What an index entry provides for hashing
+enum Digest {
+    Known(String, u64),
+    Content(Vec<u8>),
+}
+
+impl IndexEntry {
+    fn digest(&self) -> Result<Digest, String> {
+        match (&self.hash, &self.content_b64) {
+            (Some(hash), _) => {
+                if hash_digest(hash).is_none() {
+                    return Err(format!("{}: invalid hash", self.path));
+                }
+                let size = self.size.ok_or_else(|| format!("{}: hash given without size", self.path))?;
+                Ok(Digest::Known(hash.to_ascii_lowercase(), size))
+            }
+            (None, Some(b64)) => {
+                let content = BASE64.decode(b64).map_err(|e| format!("{}: invalid base64: {}", self.path, e))?;
+                Ok(Digest::Content(content))
+            }
+            (None, None) => Err(format!("{}: needs either hash or content_b64", self.path)),
+        }
+    }
+}
+
+/// `(hash, size)` for each entry `wanted` accepts (`None` for the rest),
+/// hashing supplied contents with the journal's algorithm, as one batch for SHA-256
+fn digest_batch(
+    entries: &[IndexEntry],
+    journal: &ChangeJournal,
+    wanted: impl Fn(&str) -> bool,
+) -> Vec<Option<Result<(String, u64), String>>> {
+    let digests: Vec<Option<Result<Digest, String>>> =
+        entries.iter().map(|e| wanted(&e.path).then(|| e.digest())).collect();
+    let contents: Vec<&[u8]> = digests
+        .iter()
+        .filter_map(|d| match d {
+            Some(Ok(Digest::Content(content))) => Some(content.as_slice()),
+            _ => None,
+        })
+        .collect();
+    let sha256 = journal.hash_algorithm() == HashAlgorithm::Sha256;
+    let mut hashes = if sha256 { hash_contents(&contents) } else { Vec::new() }.into_iter();
+    entries
+        .iter()
+        .zip(digests)
+        .map(|(entry, d)| {
+            d.map(|d| match d? {
+                Digest::Known(hash, size) => Ok((hash, size)),
+                Digest::Content(content) => {
+                    let hash = match sha256 {
+                        true => hashes.next().unwrap_or_default(),
+                        false => journal.content_hash(&entry.path, &content),
+                    };
+                    Ok((hash, content.len() as u64))
+                }
+            })
+        })
+        .collect()
+}
+
+impl P2PNode {
+    /// Ingest one batch of index entries, updating cumulative progress
+    pub(crate) fn ingest_index_batch(&mut self, entries: Vec<IndexEntry>) -> &BootstrapProgress {
+        let _span = timing::span(Stage::Scan);
+        let digests = digest_batch(&entries, &self.change_journal, |path| self.policy.should_sync(path));
+        for (entry, digest) in entries.into_iter().zip(digests) {
+            if cancel::check(&self.cancel_token).is_err() {
+                self.bootstrap_progress.cancelled = true;
+                break;
+            }
+            let progress = &mut self.bootstrap_progress;
+            progress.processed += 1;
+
+            let Some(digest) = digest else {
+                progress.skipped += 1;
+                continue;
+            };
+            match digest {
+                Ok((hash, size)) => {
+                    let device_id = self.device_id.clone();
+                    if self.change_journal.record_update(entry.path, hash, size, entry.mtime, device_id) {
+                        self.bootstrap_progress.recorded += 1;
+                    } else {
+                        self.bootstrap_progress.unchanged += 1;
+                    }
+                }
+                Err(e) => progress.record_failure(e),
+            }
+        }
+        &self.bootstrap_progress
+    }
+}
@@ -0,0 +1,305 @@
+//! Cache quota
+//!
+//! Long sessions on a phone must not grow without bound. Two things the
+//! node keeps around are only there to save work: chunks the host caches by
+//! content hash (`cache_chunk`), so a chunk already sent or received for one
+//! file isn't read or fetched again for another, and frames held for peers
+//! that have no open link (see `transports`), so they go out as soon as one
+//! opens. Together they are held to a quota, 64 MiB unless the host sets
+//! another with `set_cache_quota`.
+//!
+//! Over the quota, eviction starts with what is cheapest to redo: cached
+//! chunks, least recently used first, since the host still has the files
+//! they came from. Only then are held frames dropped, bulk before control
+//! and from the peer holding the most bytes first; the next sync round with
+//! that peer sends them again. `get_cache_usage_json` reports what is held
+//! and what was evicted, and `clear_caches` empties both, as `trim_memory`
+//! does. Both count towards `get_memory_stats`, and the quota is part of the
+//! exported configuration.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::debugring::DebugCategory;
+use crate::memory::{map_overhead, string_bytes, MemoryFootprint};
+use crate::sync::hash_content;
+use crate::P2PNode;
+
+pub const DEFAULT_CACHE_QUOTA_BYTES: usize = 64 * 1024 * 1024;
+/// Smallest quota a host may set; below it held frames would be dropped as they are queued
+pub const MIN_CACHE_QUOTA_BYTES: usize = 1024 * 1024;
+
+pub fn check_cache_quota(bytes: usize) -> Result<(), String> {
+    if bytes < MIN_CACHE_QUOTA_BYTES {
+        return Err(format!("Cache quota must be at least {} bytes", MIN_CACHE_QUOTA_BYTES));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct Cached {
+    data: Vec<u8>,
+    /// Key in `ChunkCache::order`
+    used: u64,
+}
+
+/// Chunks by content hash, least recently used evicted first
+#[derive(Default)]
+pub struct ChunkCache {
+    entries: HashMap<String, Cached>,
+    /// Use counter → hash, least recently used first
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    stats: ChunkCacheStats,
+}
+
+impl ChunkCache {
+    /// Cache `data` under its content hash and return the hash
+    pub fn insert(&mut self, data: &[u8]) -> String {
+        let hash = hash_content(data);
+        if !self.touch(&hash) {
+            self.clock += 1;
+            self.order.insert(self.clock, hash.clone());
+            self.entries.insert(hash.clone(), Cached { data: data.to_vec(), used: self.clock });
+            self.stats.entries += 1;
+            self.stats.bytes += data.len();
+        }
+        hash
+    }
+
+    pub fn get(&mut self, hash: &str) -> Option<&[u8]> {
+        if self.touch(hash) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        self.entries.get(hash).map(|cached| cached.data.as_slice())
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Mark `hash` as just used; false if it isn't cached
+    fn touch(&mut self, hash: &str) -> bool {
+        let Some(cached) = self.entries.get_mut(hash) else {
+            return false;
+        };
+        self.clock += 1;
+        if let Some(hash) = self.order.remove(&cached.used) {
+            self.order.insert(self.clock, hash);
+        }
+        cached.used = self.clock;
+        true
+    }
+
+    /// Drop the least recently used chunk; returns the bytes freed
+    pub fn evict(&mut self) -> Option<usize> {
+        let (_, hash) = self.order.pop_first()?;
+        let cached = self.entries.remove(&hash)?;
+        self.stats.entries -= 1;
+        self.stats.bytes -= cached.data.len();
+        self.stats.evictions += 1;
+        Some(cached.data.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.entries = 0;
+        self.stats.bytes = 0;
+    }
+
+    pub fn stats(&self) -> ChunkCacheStats {
+        self.stats
+    }
+}
+
+impl MemoryFootprint for ChunkCache {
+    fn heap_bytes(&self) -> usize {
+        let entries = self.entries.keys().map(|hash| 2 * string_bytes(hash)).sum::<usize>();
+        map_overhead(&self.entries) + self.order.len() * size_of::<(u64, String)>() + entries + self.stats.bytes
+    }
+
+    /// Everything cached can be read from the vault again
+    fn trim(&mut self) {
+        self.clear();
+        self.entries.shrink_to_fit();
+    }
+}
+
+pub struct Caches {
+    quota_bytes: usize,
+    chunks: ChunkCache,
+    /// Held frames dropped to stay under the quota
+    held_dropped: u64,
+}
+
+impl Caches {
+    pub fn quota_bytes(&self) -> usize {
+        self.quota_bytes
+    }
+
+    pub fn chunks(&self) -> &ChunkCache {
+        &self.chunks
+    }
+}
+
+impl Default for Caches {
+    fn default() -> Self {
+        Caches { quota_bytes: DEFAULT_CACHE_QUOTA_BYTES, chunks: ChunkCache::default(), held_dropped: 0 }
+    }
+}
+
+#[derive(Serialize)]
+struct HeldFrameUsage {
+    frames: usize,
+    bytes: usize,
+    dropped: u64,
+}
+
+#[derive(Serialize)]
+struct CacheUsage {
+    quota_bytes: usize,
+    used_bytes: usize,
+    chunks: ChunkCacheStats,
+    held_frames: HeldFrameUsage,
+}
+
+impl P2PNode {
+    /// Bytes the chunk cache and frames held for unreachable peers may take together
+    pub fn set_cache_quota(&mut self, bytes: usize) -> Result<(), String> {
+        self.apply_cache_quota(bytes).map_err(|e| self.record_error(e))
+    }
+
+    /// Cache a chunk by content; returns its hash. A chunk larger than the quota isn't kept
+    pub fn cache_chunk(&mut self, data: &[u8]) -> String {
+        if data.len() > self.caches.quota_bytes {
+            return hash_content(data);
+        }
+        let hash = self.caches.chunks.insert(data);
+        self.enforce_cache_quota();
+        hash
+    }
+
+    pub fn get_cached_chunk(&mut self, hash: &str) -> Option<Vec<u8>> {
+        self.caches.chunks.get(hash).map(<[u8]>::to_vec)
+    }
+
+    pub fn has_cached_chunk(&self, hash: &str) -> bool {
+        self.caches.chunks.contains(hash)
+    }
+
+    /// `{quota_bytes, used_bytes, chunks: {entries, bytes, hits, misses, evictions},
+    /// held_frames: {frames, bytes, dropped}}`
+    pub fn get_cache_usage_json(&self) -> String {
+        let chunks = self.caches.chunks.stats();
+        let (frames, bytes) = self.transports.held();
+        let usage = CacheUsage {
+            quota_bytes: self.caches.quota_bytes,
+            used_bytes: chunks.bytes + bytes,
+            chunks,
+            held_frames: HeldFrameUsage { frames, bytes, dropped: self.caches.held_dropped },
+        };
+        serde_json::to_string(&usage).unwrap_or_default()
+    }
+
+    /// Empty the chunk cache and drop the frames held for unreachable peers
+    pub fn clear_caches(&mut self) {
+        self.caches.chunks.trim();
+        self.caches.held_dropped += self.transports.clear_held() as u64;
+        self.debug_event(DebugCategory::State, "caches_cleared", "", None);
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn apply_cache_quota(&mut self, bytes: usize) -> Result<(), String> {
+        check_cache_quota(bytes)?;
+        self.caches.quota_bytes = bytes;
+        self.enforce_cache_quota();
+        Ok(())
+    }
+
+    /// Evict cached chunks, then held frames, until both fit the quota
+    pub(crate) fn enforce_cache_quota(&mut self) {
+        let mut used = self.caches.chunks.stats().bytes + self.transports.held().1;
+        while used > self.caches.quota_bytes {
+            let freed = match self.caches.chunks.evict() {
+                Some(freed) => freed,
+                None => match self.transports.drop_held() {
+                    Some(freed) => {
+                        self.caches.held_dropped += 1;
+                        self.debug_event(DebugCategory::Decision, "held_frame_dropped", "", Some(freed as u64));
+                        freed
+                    }
+                    None => break,
+                },
+            };
+            used -= freed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(node: &P2PNode) -> serde_json::Value {
+        serde_json::from_str(&node.get_cache_usage_json()).unwrap()
+    }
+
+    #[test]
+    fn test_quota_evicts_chunks_before_held_frames() {
+        const MIB: usize = 1024 * 1024;
+        let mut node = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        assert!(node.set_cache_quota(1024).is_err());
+        node.set_cache_quota(2 * MIB).unwrap();
+
+        let a = node.cache_chunk(&vec![1; MIB / 2]);
+        let b = node.cache_chunk(&vec![2; MIB / 2]);
+        assert_eq!(node.cache_chunk(&vec![1; MIB / 2]), a);
+        assert_eq!(node.get_cached_chunk(&a).unwrap().len(), MIB / 2);
+        assert!(node.get_cached_chunk("missing").is_none());
+        // Over the quota: `b` is the least recently used
+        let c = node.cache_chunk(&vec![3; MIB + 1]);
+        assert!(!node.has_cached_chunk(&b) && node.has_cached_chunk(&a) && node.has_cached_chunk(&c));
+        let too_large = node.cache_chunk(&vec![4; 2 * MIB + 1]);
+        assert!(!node.has_cached_chunk(&too_large));
+        let stats = usage(&node);
+        assert_eq!(stats["chunks"]["entries"], 2);
+        assert_eq!((stats["chunks"]["hits"].as_u64(), stats["chunks"]["misses"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(stats["chunks"]["evictions"], 1);
+
+        // Frames for an unreachable peer push the chunks out, then each other
+        node.register_transport(r#"{"name":"wifi","kind":"direct"}"#).unwrap();
+        node.send_frame("laptop", &[0; 64]).unwrap();
+        node.send_frame("laptop", &vec![5; MIB]).unwrap();
+        let stats = usage(&node);
+        assert_eq!((stats["chunks"]["entries"].as_u64(), stats["held_frames"]["dropped"].as_u64()), (Some(0), Some(0)));
+        node.send_frame("laptop", &vec![6; MIB]).unwrap();
+        let stats = usage(&node);
+        let held = &stats["held_frames"];
+        assert_eq!((held["frames"].as_u64(), held["dropped"].as_u64()), (Some(2), Some(1)));
+        assert!(stats["used_bytes"].as_u64().unwrap() <= 2 * MIB as u64);
+
+        // The small frame survived; the first large one was dropped
+        node.transport_connected("wifi", "laptop").unwrap();
+        assert_eq!(node.next_outgoing_message("wifi").unwrap().data().len(), 64);
+        assert_eq!(node.next_outgoing_message("wifi").unwrap().data()[0], 6);
+
+        node.cache_chunk(b"note");
+        node.transport_disconnected("wifi", "laptop", "closed");
+        node.send_frame("laptop", b"ping").unwrap();
+        node.clear_caches();
+        let stats = usage(&node);
+        assert_eq!((stats["used_bytes"].as_u64(), stats["held_frames"]["dropped"].as_u64()), (Some(0), Some(2)));
+    }
+}
@@ -0,0 +1,69 @@
+//! Cooperative cancellation for long operations
+//!
+//! The host creates a `CancellationToken`, attaches it to a node or transfer
+//! manager, and calls `cancel()` when the user disables sync. Long
+//! operations check the token at safe points (between commands, between
+//! chunks, between the paths of a plan) and stop with `CANCELLED`, leaving
+//! already-applied work intact.
+
+#[cfg(test)]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::Error;
+
+/// Error message returned by operations stopped through a token
+pub const CANCELLED: &str = "cancelled";
+
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>,
+    /// Checks that pass before the token cancels itself (0: never), to stop an operation midway
+    #[cfg(test)]
+    trip_after: Arc<AtomicUsize>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Request cancellation of every operation observing this token
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// Re-arm the token so it can be reused for the next operation
+    pub fn reset(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+
+    /// Cancel once `checks` more checks have passed
+    #[cfg(test)]
+    pub(crate) fn cancel_after_checks(&self, checks: usize) {
+        self.trip_after.store(checks + 1, Ordering::Relaxed);
+    }
+
+    /// Return `Err(CANCELLED)` if cancellation was requested
+    pub fn check(&self) -> Result<(), Error> {
+        #[cfg(test)]
+        if self.trip_after.load(Ordering::Relaxed) > 0 && self.trip_after.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.cancel();
+        }
+        if self.is_cancelled() {
+            Err(CANCELLED.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Check an optional token, treating a missing token as "never cancelled"
+pub fn check(token: &Option<CancellationToken>) -> Result<(), Error> {
+    token.as_ref().map_or(Ok(()), CancellationToken::check)
+}
@@ -0,0 +1,245 @@
+//! Signed journal checkpoints
+//!
+//! Every so often a device signs the Merkle root of its journal together with
+//! the journal sequence and sends it to its peers as a `Checkpoint` frame.
+//! Peers keep the recent checkpoints of each device. A device that signs two
+//! different roots for the same sequence, or whose sequence goes backwards,
+//! has rewritten its history (through compromise or a bug); that is recorded
+//! in the security log and the checkpoint is rejected.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sync::ChangeJournal;
+
+use crate::crypto::{self, DeviceIdentity};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::security::SecurityEventKind;
+use crate::wire;
+use crate::P2PNode;
+
+/// Journal changes between checkpoints
+pub const CHECKPOINT_INTERVAL: u64 = 100;
+/// Checkpoints kept per device
+pub const MAX_CHECKPOINTS_PER_DEVICE: usize = 64;
+const SIGNATURE_DOMAIN: &[u8] = b"obsidian-p2p-sync checkpoint v1";
+
+fn length_prefixed(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// Merkle root over every entry (tombstones included), ordered by path
+pub fn merkle_root(journal: &ChangeJournal) -> [u8; 32] {
+    let mut entries: Vec<_> = journal.files().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut level: Vec<[u8; 32]> = entries
+        .iter()
+        .map(|meta| {
+            let mut leaf = vec![0u8];
+            length_prefixed(&mut leaf, meta.path.as_bytes());
+            length_prefixed(&mut leaf, meta.hash.as_bytes());
+            leaf.extend_from_slice(&meta.size.to_be_bytes());
+            leaf.extend_from_slice(&meta.version.to_be_bytes());
+            leaf.push(meta.is_deleted as u8);
+            length_prefixed(&mut leaf, meta.last_modified_by.as_bytes());
+            Sha256::digest(&leaf).into()
+        })
+        .collect();
+    if level.is_empty() {
+        return Sha256::digest(b"").into();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new().chain_update([1u8]).chain_update(left).chain_update(right).finalize().into(),
+                // An odd node moves up unchanged
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+fn signed_bytes(device_id: &str, sequence: u64, root: &[u8]) -> Vec<u8> {
+    let mut out = SIGNATURE_DOMAIN.to_vec();
+    length_prefixed(&mut out, device_id.as_bytes());
+    out.extend_from_slice(&sequence.to_be_bytes());
+    length_prefixed(&mut out, root);
+    out
+}
+
+/// Checkpoints received from one device
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DeviceCheckpoints {
+    /// Base64 identity key the checkpoints were signed with
+    identity_key: String,
+    /// Sequence → base64 root, the most recent `MAX_CHECKPOINTS_PER_DEVICE`
+    roots: BTreeMap<u64, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CheckpointStore {
+    devices: HashMap<String, DeviceCheckpoints>,
+    /// Sequence of our own last checkpoint
+    #[serde(default)]
+    last_own_sequence: Option<u64>,
+}
+
+impl CheckpointStore {
+    /// Drop what `device_id` signed, e.g. after it moved to a new key
+    pub(crate) fn forget(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+    }
+
+    /// Check `checkpoint` against what the device signed before and remember it;
+    /// on a conflict returns the event kind and description
+    fn admit(&mut self, checkpoint: &wire::Checkpoint) -> Result<(), (SecurityEventKind, String)> {
+        let key = BASE64.encode(&checkpoint.identity_key);
+        let root = BASE64.encode(&checkpoint.root);
+        let device = self.devices.entry(checkpoint.device_id.clone()).or_insert_with(|| DeviceCheckpoints {
+            identity_key: key.clone(),
+            roots: BTreeMap::new(),
+        });
+        if device.identity_key != key {
+            return Err((SecurityEventKind::KeyChanged, format!("Checkpoint from {} is signed by a different key", checkpoint.device_id)));
+        }
+        match device.roots.get(&checkpoint.sequence) {
+            Some(known) if *known == root => return Ok(()),
+            Some(_) => {
+                return Err((
+                    SecurityEventKind::HistoryRewritten,
+                    format!("{} signed two different journals at sequence {}", checkpoint.device_id, checkpoint.sequence),
+                ))
+            }
+            None => {}
+        }
+        if let Some((&latest, _)) = device.roots.last_key_value().filter(|(&s, _)| s > checkpoint.sequence) {
+            return Err((
+                SecurityEventKind::HistoryRewritten,
+                format!("{} went back from sequence {} to {}", checkpoint.device_id, latest, checkpoint.sequence),
+            ));
+        }
+        device.roots.insert(checkpoint.sequence, root);
+        while device.roots.len() > MAX_CHECKPOINTS_PER_DEVICE {
+            device.roots.pop_first();
+        }
+        Ok(())
+    }
+}
+
+impl P2PNode {
+    /// Whether enough has changed since our last checkpoint to send a new one
+    pub fn is_checkpoint_due(&self) -> bool {
+        let sequence = self.change_journal.sequence();
+        match self.checkpoints.last_own_sequence {
+            None => sequence > 0,
+            Some(last) => sequence >= last + CHECKPOINT_INTERVAL,
+        }
+    }
+
+    /// Sign the current journal root; returns a `Checkpoint` frame for peers with `CAP_CHECKPOINTS`
+    pub fn create_checkpoint_frame(&mut self, identity: &DeviceIdentity) -> Vec<u8> {
+        let sequence = self.change_journal.sequence();
+        let root = merkle_root(&self.change_journal).to_vec();
+        let signature = identity.sign(&signed_bytes(&identity.get_device_id(), sequence, &root));
+        self.checkpoints.last_own_sequence = Some(sequence);
+        wire::encode_frame(&wire::Envelope::new(wire::Body::Checkpoint(wire::Checkpoint {
+            device_id: identity.get_device_id(),
+            sequence,
+            root,
+            identity_key: BASE64.decode(identity.get_public_key()).unwrap_or_default(),
+            signature: BASE64.decode(signature).unwrap_or_default(),
+        })))
+    }
+
+    /// Verify a peer's checkpoint frame and check it against its earlier ones
+    pub fn process_checkpoint_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.apply_checkpoint_frame(frame).map_err(|e| self.record_error(e))
+    }
+
+    /// Received checkpoints as JSON, for persisting with `load_checkpoint_state`
+    pub fn get_checkpoint_state(&self) -> String {
+        serde_json::to_string(&self.checkpoints).unwrap_or_default()
+    }
+
+    pub fn load_checkpoint_state(&mut self, json: &str) -> Result<(), String> {
+        let store = check_size("Checkpoint state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load checkpoints: {}", e)))?;
+        self.checkpoints = store;
+        Ok(())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn apply_checkpoint_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        let Some((envelope, _)) = wire::decode_frame(frame)? else {
+            return Err("Incomplete checkpoint frame".to_string());
+        };
+        let Some(wire::Body::Checkpoint(checkpoint)) = envelope.body else {
+            return Err("Expected a checkpoint frame".to_string());
+        };
+        let signed = signed_bytes(&checkpoint.device_id, checkpoint.sequence, &checkpoint.root);
+        if !crypto::verify_signature(BASE64.encode(&checkpoint.identity_key), &signed, BASE64.encode(&checkpoint.signature)) {
+            let message = format!("Invalid checkpoint signature from {}", checkpoint.device_id);
+            self.security_log.record(SecurityEventKind::SignatureInvalid, &checkpoint.device_id, message.clone(), &[("frame", "checkpoint")]);
+            return Err(message);
+        }
+        self.checkpoints.admit(&checkpoint).map_err(|(kind, message)| {
+            let sequence = checkpoint.sequence.to_string();
+            self.security_log.record(kind, &checkpoint.device_id, message.clone(), &[("sequence", &sequence)]);
+            message
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewritten_history_is_detected() {
+        let identity = DeviceIdentity::new("phone".to_string()).unwrap();
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        assert!(!phone.is_checkpoint_due());
+        phone.update_file("a.md".to_string(), b"one", 1);
+        assert!(phone.is_checkpoint_due());
+
+        let first = phone.create_checkpoint_frame(&identity);
+        assert!(!phone.is_checkpoint_due());
+        laptop.apply_checkpoint_frame(&first).unwrap();
+        // Re-sending the same checkpoint is fine
+        laptop.apply_checkpoint_frame(&first).unwrap();
+        phone.update_file("b.md".to_string(), b"two", 2);
+        phone.update_file("c.md".to_string(), b"three", 3);
+        laptop.apply_checkpoint_frame(&phone.create_checkpoint_frame(&identity)).unwrap();
+
+        // A phone that signs a different journal at an earlier sequence
+        let mut rewritten = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        rewritten.update_file("x.md".to_string(), b"other", 1);
+        let same_sequence = rewritten.create_checkpoint_frame(&identity);
+        assert!(laptop.apply_checkpoint_frame(&same_sequence).is_err());
+        rewritten.update_file("b.md".to_string(), b"two", 2);
+        let rolled_back = rewritten.create_checkpoint_frame(&identity);
+
+        // Still detected after the store is persisted and reloaded
+        let mut restored = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        restored.load_checkpoint_state(&laptop.get_checkpoint_state()).unwrap();
+        assert!(restored.apply_checkpoint_frame(&rolled_back).is_err());
+        let events: Vec<_> = laptop.security_log.iter().chain(restored.security_log.iter()).map(|e| e.kind).collect();
+        assert_eq!(events, vec![SecurityEventKind::HistoryRewritten; 2]);
+
+        // Tampered root
+        let mut frame = phone.create_checkpoint_frame(&identity);
+        let len = frame.len();
+        frame[len - 110] ^= 1;
+        assert!(laptop.apply_checkpoint_frame(&frame).is_err());
+        assert_eq!(laptop.security_log.iter().last().unwrap().kind, SecurityEventKind::SignatureInvalid);
+    }
+}
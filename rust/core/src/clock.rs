@@ -0,0 +1,95 @@
+//! Crate-wide time source
+//!
+//! Most APIs take `current_time` from the host, but anything that needs a
+//! timestamp on its own must read it from here rather than the system clock.
+//! The host (or a test) can pin the clock to a fixed value and advance it
+//! manually, which keeps expiry and ordering behavior deterministic.
+//!
+//! Native builds read the OS clock. A WASM module has none, so the bindings
+//! install the host's clock with `set_time_source` when the module starts.
+
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+thread_local! {
+    static OVERRIDE_MS: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Clock readings supplied by the host
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSource {
+    /// Milliseconds since the Unix epoch
+    pub now_ms: fn() -> u64,
+    /// High-resolution milliseconds from an arbitrary origin
+    pub monotonic_ms: fn() -> f64,
+}
+
+static SOURCE: OnceLock<TimeSource> = OnceLock::new();
+
+/// Read time from `source` instead of the OS; only the first call takes effect
+pub fn set_time_source(source: TimeSource) {
+    let _ = SOURCE.set(source);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn os_now_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn os_monotonic_ms() -> f64 {
+    use std::time::Instant;
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    ORIGIN.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+// No OS clock to fall back on; the bindings install the host's at startup
+#[cfg(target_arch = "wasm32")]
+fn os_now_ms() -> u64 {
+    0
+}
+
+#[cfg(target_arch = "wasm32")]
+fn os_monotonic_ms() -> f64 {
+    0.0
+}
+
+fn system_now_ms() -> u64 {
+    SOURCE.get().map_or_else(os_now_ms, |source| (source.now_ms)())
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    OVERRIDE_MS.with(|o| o.get()).unwrap_or_else(system_now_ms)
+}
+
+/// High-resolution milliseconds from an arbitrary origin, for measuring durations;
+/// unaffected by a pinned clock
+pub fn monotonic_ms() -> f64 {
+    SOURCE.get().map_or_else(os_monotonic_ms, |source| (source.monotonic_ms)())
+}
+
+/// Pin the clock to a fixed time (milliseconds since the Unix epoch)
+pub fn set_clock_time(time_ms: u64) {
+    OVERRIDE_MS.with(|o| o.set(Some(time_ms)));
+}
+
+/// Advance a pinned clock; pins it at the current system time first if needed
+pub fn advance_clock(delta_ms: u64) {
+    let next = now_ms().saturating_add(delta_ms);
+    set_clock_time(next);
+}
+
+/// Return to the system clock
+pub fn use_system_clock() {
+    OVERRIDE_MS.with(|o| o.set(None));
+}
+
+/// Whether the clock is currently pinned
+pub fn is_clock_overridden() -> bool {
+    OVERRIDE_MS.with(|o| o.get().is_some())
+}
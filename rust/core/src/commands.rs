@@ -0,0 +1,140 @@
+//! Batched command execution
+//!
+//! Vault-wide operations (find-and-replace, folder moves) fire thousands of
+//! file events. Crossing the JS/WASM boundary once per event dominates the
+//! cost, so the host can queue typed commands and apply them in one call.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel;
+use crate::limits::{check_size, MAX_COMMAND_BATCH_BYTES};
+use crate::P2PNode;
+
+/// A single operation in a batch, tagged by `op`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Command {
+    UpdateFile {
+        path: String,
+        content_b64: String,
+        mtime: u64,
+    },
+    MarkDeleted {
+        path: String,
+        mtime: u64,
+    },
+    ProcessAnnouncement {
+        json: String,
+        sender_ip: String,
+        current_time: u64,
+    },
+    RemovePeer {
+        peer_id: String,
+    },
+    PrunePeers {
+        current_time: u64,
+        ttl_ms: u64,
+    },
+    /// A received chunk that verified, as `record_verified_chunk`; not undone
+    /// when an atomic batch rolls back
+    AckChunk {
+        transfer_id: String,
+        chunk_index: u32,
+    },
+}
+
+/// Outcome of one command, in the same position as its command
+#[derive(Serialize, Debug)]
+pub struct CommandResult {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+}
+
+impl CommandResult {
+    fn success(value: serde_json::Value) -> CommandResult {
+        CommandResult { ok: true, value: Some(value), error: None, cancelled: false }
+    }
+
+    fn failure(error: String) -> CommandResult {
+        CommandResult { ok: false, value: None, error: Some(error), cancelled: false }
+    }
+
+    fn cancelled() -> CommandResult {
+        CommandResult { cancelled: true, ..CommandResult::failure(cancel::CANCELLED.to_string()) }
+    }
+}
+
+impl From<Result<serde_json::Value, String>> for CommandResult {
+    fn from(result: Result<serde_json::Value, String>) -> Self {
+        match result {
+            Ok(value) => CommandResult::success(value),
+            Err(e) => CommandResult::failure(e),
+        }
+    }
+}
+
+/// Outcome of an all-or-nothing batch
+#[derive(Serialize, Debug)]
+pub struct AtomicBatchResult {
+    /// False if a command failed and the batch was rolled back
+    pub committed: bool,
+    pub results: Vec<CommandResult>,
+}
+
+/// Parse a JSON array of commands
+pub fn parse_batch(batch_json: &str) -> Result<Vec<Command>, String> {
+    check_size("Command batch", batch_json, MAX_COMMAND_BATCH_BYTES)?;
+    serde_json::from_str(batch_json).map_err(|e| format!("Invalid command batch: {}", e))
+}
+
+impl P2PNode {
+    /// Apply commands in order, checking the cancellation token between them
+    pub(crate) fn execute_batch(&mut self, commands: Vec<Command>) -> Vec<CommandResult> {
+        let mut results = Vec::with_capacity(commands.len());
+        for command in commands {
+            if cancel::check(&self.cancel_token).is_err() {
+                results.push(CommandResult::cancelled());
+            } else {
+                results.push(self.execute_command(command));
+            }
+        }
+        results
+    }
+
+    /// Apply commands inside a transaction, rolling all of them back if any fails
+    pub(crate) fn execute_atomic(&mut self, commands: Vec<Command>) -> Result<AtomicBatchResult, String> {
+        self.open_transaction()?;
+        let results = self.execute_batch(commands);
+        let committed = results.iter().all(|r| r.ok);
+        self.close_transaction(committed)?;
+        Ok(AtomicBatchResult { committed, results })
+    }
+
+    /// Apply a single command; failures are reported, never propagated
+    pub(crate) fn execute_command(&mut self, command: Command) -> CommandResult {
+        let result = match command {
+            Command::UpdateFile { path, content_b64, mtime } => BASE64
+                .decode(content_b64)
+                .map_err(|e| format!("Invalid base64 content: {}", e))
+                .map(|content| self.update_file(path, &content, mtime).into()),
+            Command::MarkDeleted { path, mtime } => Ok(self.mark_file_deleted(path, mtime).into()),
+            Command::ProcessAnnouncement { json, sender_ip, current_time } => self
+                .apply_announcement(&json, &sender_ip, current_time)
+                .map(Into::into),
+            Command::RemovePeer { peer_id } => Ok(self.peers.remove(&peer_id).is_some().into()),
+            Command::PrunePeers { current_time, ttl_ms } => {
+                Ok(self.prune_stale_peers(current_time, ttl_ms).into())
+            }
+            Command::AckChunk { transfer_id, chunk_index } => {
+                self.mark_chunk_verified(&transfer_id, chunk_index).map(|_| serde_json::Value::Null)
+            }
+        };
+        result.into()
+    }
+}
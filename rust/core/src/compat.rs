@@ -0,0 +1,200 @@
+//! Reading state and frames written by older releases
+//!
+//! Devices are upgraded at different times, so a release must read journals
+//! and wire frames from at least the two versions before it. Formats change
+//! in one place each, listed here:
+//!
+//! Journal formats (`JOURNAL_FORMAT_VERSION`):
+//! 1. `{files, global_sequence}`
+//! 2. adds the version `history` and `backups`
+//! 3. adds `hash_algorithm`; from this format on the journal records its
+//!    `format_version`
+//!
+//! A journal without `format_version` is sniffed by the fields it has and
+//! read with the deserializer for that format. A journal from a newer format
+//! is refused rather than loaded with fields silently dropped.
+//!
+//! Wire protocol (`wire::PROTOCOL_VERSION`): the protobuf schema only gains
+//! fields, so envelopes of protocols 1 to 3 all decode. Protocol 1 handshakes
+//! signed only the session key, which does not bind the rest of the
+//! handshake; they are refused with an explicit "update the peer" error
+//! rather than failing as a bad signature.
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+
+use crate::error::Error;
+use crate::retention::BackupSchedule;
+use crate::sync::{ChangeJournal, FileMetadata};
+use crate::wire;
+
+/// Journal format written by this release
+pub const JOURNAL_FORMAT_VERSION: u32 = 3;
+/// Oldest journal format still read
+pub const OLDEST_JOURNAL_FORMAT: u32 = JOURNAL_FORMAT_VERSION - 2;
+/// Oldest wire protocol whose frames are still decoded
+pub const OLDEST_PROTOCOL_VERSION: u32 = wire::PROTOCOL_VERSION - 2;
+/// Handshakes before this protocol don't sign their whole transcript
+pub const MIN_HANDSHAKE_PROTOCOL_VERSION: u32 = 2;
+
+/// Persisted journal bytes in one of the supported encodings
+pub enum JournalSource<'a> {
+    Json(&'a str),
+    Cbor(&'a [u8]),
+}
+
+impl JournalSource<'_> {
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        match self {
+            JournalSource::Json(json) => serde_json::from_str(json).map_err(|e| e.to_string().into()),
+            JournalSource::Cbor(data) => ciborium::from_reader(*data).map_err(|e| e.to_string().into()),
+        }
+    }
+}
+
+/// The fields that tell journal formats apart, without building the entries
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    format_version: Option<u32>,
+    #[serde(default)]
+    history: Option<IgnoredAny>,
+    #[serde(default)]
+    backups: Option<IgnoredAny>,
+    #[serde(default)]
+    hash_algorithm: Option<IgnoredAny>,
+}
+
+impl Probe {
+    fn version(&self) -> u32 {
+        match self.format_version {
+            Some(version) => version,
+            None if self.hash_algorithm.is_some() => 3,
+            None if self.history.is_some() || self.backups.is_some() => 2,
+            None => 1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JournalV1 {
+    #[serde(default)]
+    files: HashMap<String, FileMetadata>,
+    global_sequence: u64,
+}
+
+#[derive(Deserialize)]
+struct JournalV2 {
+    #[serde(default)]
+    files: HashMap<String, FileMetadata>,
+    global_sequence: u64,
+    #[serde(default)]
+    history: VecDeque<FileMetadata>,
+    #[serde(default)]
+    backups: BackupSchedule,
+}
+
+/// Format version of a persisted journal
+pub fn sniff_journal_version(source: &JournalSource) -> Result<u32, Error> {
+    source.decode::<Probe>().map(|probe| probe.version())
+}
+
+/// Read a persisted journal of any supported format
+pub fn decode_journal(source: JournalSource) -> Result<ChangeJournal, Error> {
+    let version = sniff_journal_version(&source)?;
+    match version {
+        1 => {
+            let v1: JournalV1 = source.decode()?;
+            Ok(ChangeJournal::upgraded(v1.files.into_values(), v1.global_sequence, VecDeque::new(), BackupSchedule::default()))
+        }
+        2 => {
+            let v2: JournalV2 = source.decode()?;
+            Ok(ChangeJournal::upgraded(v2.files.into_values(), v2.global_sequence, v2.history, v2.backups))
+        }
+        JOURNAL_FORMAT_VERSION => source.decode(),
+        newer if newer > JOURNAL_FORMAT_VERSION => Err(format!(
+            "Journal format {} was written by a newer release (this one reads up to {}); update the plugin",
+            newer, JOURNAL_FORMAT_VERSION
+        )
+        .into()),
+        older => Err(format!("Journal format {} is no longer supported (oldest: {})", older, OLDEST_JOURNAL_FORMAT).into()),
+    }
+}
+
+/// Whether frames of `version` can be decoded at all
+pub fn check_protocol_version(version: u32) -> Result<(), Error> {
+    if version < OLDEST_PROTOCOL_VERSION {
+        return Err(format!("Protocol version {} is not supported (oldest: {})", version, OLDEST_PROTOCOL_VERSION).into());
+    }
+    Ok(())
+}
+
+/// Whether a handshake of `version` can be verified
+pub fn check_handshake_version(version: u32, device_id: &str) -> Result<(), Error> {
+    if version < MIN_HANDSHAKE_PROTOCOL_VERSION {
+        return Err(format!(
+            "{} runs protocol {}, whose handshakes can't be verified safely; update it to connect",
+            device_id, version
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::negotiation;
+    use hex::FromHex;
+
+    const JOURNAL_V1: &str = include_str!("../tests/fixtures/journal_v1.json");
+    const JOURNAL_V2: &str = include_str!("../tests/fixtures/journal_v2.json");
+    const JOURNAL_V3: &str = include_str!("../tests/fixtures/journal_v3.json");
+    const FRAMES: &str = include_str!("../tests/fixtures/frames.txt");
+
+    fn frame(name: &str) -> Vec<u8> {
+        let line = FRAMES.lines().find_map(|l| l.strip_prefix(&format!("{} ", name))).unwrap();
+        Vec::from_hex(line.trim()).unwrap()
+    }
+
+    #[test]
+    fn test_old_journal_fixtures_load() {
+        for (fixture, version) in [(JOURNAL_V1, 1), (JOURNAL_V2, 2), (JOURNAL_V3, 3)] {
+            assert_eq!(sniff_journal_version(&JournalSource::Json(fixture)).unwrap(), version);
+            let journal = ChangeJournal::from_json(fixture).unwrap();
+            assert_eq!(journal.sequence(), 3, "format {}", version);
+            let note = journal.get("notes/a.md").unwrap();
+            assert_eq!((note.version, note.size, note.last_modified_by.as_str()), (3, 5, "laptop"));
+            assert!(journal.get("old.md").unwrap().is_deleted);
+
+            // Saved again in the current format, with its version recorded
+            let saved = journal.to_json();
+            assert_eq!(sniff_journal_version(&JournalSource::Json(&saved)).unwrap(), JOURNAL_FORMAT_VERSION);
+            let cbor = journal.to_cbor();
+            assert_eq!(ChangeJournal::from_cbor(&cbor).unwrap().sequence(), 3);
+        }
+        assert_eq!(ChangeJournal::from_json(JOURNAL_V2).unwrap().history().count(), 3);
+
+        let future = JOURNAL_V3.replacen('{', r#"{"format_version":9,"#, 1);
+        assert!(ChangeJournal::from_json(&future).err().unwrap().message().contains("newer release"));
+    }
+
+    #[test]
+    fn test_old_wire_fixtures_decode() {
+        for name in ["v1_announcement", "v2_manifest", "v3_manifest"] {
+            let bytes = frame(name);
+            let (envelope, used) = wire::decode_frame(&bytes).unwrap().unwrap();
+            assert_eq!(used, bytes.len());
+            assert_eq!(envelope.protocol_version.to_string(), name[1..2]);
+        }
+        let (envelope, _) = wire::decode_frame(&frame("v2_manifest")).unwrap().unwrap();
+        let Some(wire::Body::Manifest(manifest)) = envelope.body else { panic!("not a manifest") };
+        assert_eq!(manifest.entries[0].path, "notes/a.md");
+
+        assert!(negotiation::verify(&frame("v1_handshake")).unwrap_err().message().contains("update it"));
+        let mut unsupported = wire::Envelope::new(wire::Body::Announcement(Default::default()));
+        unsupported.protocol_version = 0;
+        assert!(wire::decode_frame(&wire::encode_frame(&unsupported)).is_err());
+    }
+}
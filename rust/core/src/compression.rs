@@ -0,0 +1,145 @@
+//! Control frame compression
+//!
+//! Manifests, acknowledgements and checkpoints are repetitive (paths share
+//! prefixes, hashes sit next to the same field names), so sessions that
+//! negotiate `CAP_COMPRESSION` compress those frames once they pass a size
+//! threshold. File chunks are encrypted and therefore incompressible, and
+//! are never touched. The codec is a small LZ77 variant in the LZ4 block
+//! layout: each sequence is a token (literal length, match length), the
+//! literals, and a two-byte back-reference; the last sequence has literals
+//! only. Decompression is bounded by the caller so a tiny frame cannot
+//! expand without limit.
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn write_length(out: &mut Vec<u8>, mut extra: usize) {
+    while extra >= 255 {
+        out.push(255);
+        extra -= 255;
+    }
+    out.push(extra as u8);
+}
+
+/// One sequence: `literals`, then a match of `(offset, length)` unless it is the last
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) << 4) | match_len.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // Position + 1 of the last occurrence of each 4-byte hash; 0 is empty
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut anchor, mut pos) = (0, 0);
+    while pos + MIN_MATCH <= input.len() {
+        let word = u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]);
+        let slot = (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[slot], pos + 1);
+        if let Some(start) = candidate.checked_sub(1).filter(|s| pos - s <= MAX_OFFSET) {
+            if input[start..start + MIN_MATCH] == input[pos..pos + MIN_MATCH] {
+                let mut len = MIN_MATCH;
+                while pos + len < input.len() && input[start + len] == input[pos + len] {
+                    len += 1;
+                }
+                write_sequence(&mut out, &input[anchor..pos], Some((pos - start, len)));
+                pos += len;
+                anchor = pos;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+fn read_byte(input: &[u8], pos: &mut usize) -> Result<u8, String> {
+    let byte = *input.get(*pos).ok_or("Truncated compressed data")?;
+    *pos += 1;
+    Ok(byte)
+}
+
+fn read_length(input: &[u8], pos: &mut usize, nibble: u8) -> Result<usize, String> {
+    let mut len = nibble as usize;
+    if nibble == 15 {
+        loop {
+            let byte = read_byte(input, pos)?;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Decompress `input`, failing once the output would exceed `max_len` bytes
+pub fn decompress(input: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    let too_large = || format!("Decompressed data exceeds {} bytes", max_len);
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let token = read_byte(input, &mut pos)?;
+        let literals = read_length(input, &mut pos, token >> 4)?;
+        let end = pos.checked_add(literals).filter(|end| *end <= input.len()).ok_or("Truncated compressed data")?;
+        if out.len() + literals > max_len {
+            return Err(too_large());
+        }
+        out.extend_from_slice(&input[pos..end]);
+        pos = end;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([read_byte(input, &mut pos)?, read_byte(input, &mut pos)?]) as usize;
+        let len = read_length(input, &mut pos, token & 15)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() {
+            return Err(format!("Invalid back-reference offset {}", offset));
+        }
+        if out.len() + len > max_len {
+            return Err(too_large());
+        }
+        // Byte by byte: a match may overlap the bytes it produces
+        for _ in 0..len {
+            out.push(out[out.len() - offset]);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_bounds() {
+        let manifest: Vec<u8> = (0..200)
+            .flat_map(|i| format!("{{\"path\":\"notes/daily/2024-01-{:02}.md\",\"version\":{}}}", i % 31, i).into_bytes())
+            .collect();
+        let long_run = vec![b'a'; 5000];
+        let noise: Vec<u8> = (0..3000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        for input in [&b""[..], b"abc", &manifest, &long_run, &noise] {
+            let packed = compress(input);
+            assert_eq!(decompress(&packed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&manifest).len() < manifest.len() / 3);
+        assert!(compress(&long_run).len() < 64);
+
+        assert!(decompress(&compress(&long_run), 4999).unwrap_err().contains("exceeds"));
+        // A back-reference before the start of the output
+        assert!(decompress(&[0x00, 0x05, 0x00], 100).is_err());
+        assert!(decompress(&[0xF0], 100).is_err());
+    }
+}
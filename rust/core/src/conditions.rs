@@ -0,0 +1,128 @@
+//! Device power and network conditions
+//!
+//! Mobile hosts know when they are on cellular or running low on battery;
+//! the node doesn't. The host reports both, and the scheduler and transfer
+//! planning consult them: nothing is due while offline, attachments wait for
+//! an unmetered network when the profile asks for it, and background sync
+//! pauses below the profile's battery threshold unless the device is charging.
+
+use serde::{Deserialize, Serialize};
+
+use crate::profiles::SyncProfile;
+use crate::P2PNode;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkClass {
+    #[default]
+    Unmetered,
+    Metered,
+    Offline,
+}
+
+impl NetworkClass {
+    pub fn parse(name: &str) -> Result<NetworkClass, String> {
+        match name {
+            "unmetered" => Ok(NetworkClass::Unmetered),
+            "metered" => Ok(NetworkClass::Metered),
+            "offline" => Ok(NetworkClass::Offline),
+            other => Err(format!("Unknown network class: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PowerState {
+    pub battery_percent: u8,
+    pub charging: bool,
+}
+
+/// Conditions as last reported by the host; a device that never reports
+/// power (a desktop) is treated as plugged in
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceConditions {
+    pub network: NetworkClass,
+    pub power: Option<PowerState>,
+}
+
+impl DeviceConditions {
+    pub fn is_metered(&self) -> bool {
+        self.network == NetworkClass::Metered
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.network == NetworkClass::Offline
+    }
+
+    /// Whether bulk (background and attachment) sync should wait under `profile`
+    pub fn bulk_paused(&self, profile: &SyncProfile) -> bool {
+        match (self.power, profile.pause_bulk_below_battery_percent) {
+            (Some(power), Some(threshold)) => !power.charging && power.battery_percent < threshold,
+            _ => false,
+        }
+    }
+}
+
+impl P2PNode {
+    /// Report the battery level (0-100) and whether the device is charging
+    pub fn set_power_state(&mut self, battery_percent: u8, charging: bool) -> Result<(), String> {
+        if battery_percent > 100 {
+            return Err(self.record_error(format!("Battery level out of range: {}", battery_percent)));
+        }
+        self.conditions.power = Some(PowerState { battery_percent, charging });
+        Ok(())
+    }
+
+    /// Report the network: `unmetered`, `metered` or `offline`
+    pub fn set_network_class(&mut self, class: &str) -> Result<(), String> {
+        self.conditions.network = NetworkClass::parse(class).map_err(|e| self.record_error(e))?;
+        Ok(())
+    }
+
+    /// Reported conditions as JSON, with whether bulk sync is currently paused
+    pub fn get_conditions_json(&self) -> String {
+        serde_json::json!({
+            "conditions": self.conditions,
+            "bulk_paused": self.conditions.bulk_paused(&self.profile),
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_battery_pauses_unless_charging() {
+        let mut conditions = DeviceConditions::default();
+        let profile = SyncProfile::mobile_lite();
+        assert!(!conditions.bulk_paused(&profile));
+        conditions.power = Some(PowerState { battery_percent: 15, charging: false });
+        assert!(conditions.bulk_paused(&profile));
+        assert!(!conditions.bulk_paused(&SyncProfile { pause_bulk_below_battery_percent: None, ..profile.clone() }));
+        conditions.power = Some(PowerState { battery_percent: 15, charging: true });
+        assert!(!conditions.bulk_paused(&profile));
+        assert_eq!(NetworkClass::parse("metered"), Ok(NetworkClass::Metered));
+        assert!(NetworkClass::parse("5g").is_err());
+    }
+
+    #[test]
+    fn test_node_defers_attachments() {
+        let mut node = P2PNode::new("Phone".to_string(), "phone".to_string(), 8080);
+        node.select_profile("mobile-lite").unwrap();
+        let files = r#"[{"path":"a.md","size":10},{"path":"pic.png","size":10}]"#;
+        let order = |node: &mut P2PNode| -> serde_json::Value {
+            serde_json::from_str(&node.plan_transfer_order(files, false).unwrap()).unwrap()
+        };
+        assert_eq!(order(&mut node)["transfer"], serde_json::json!(["a.md", "pic.png"]));
+
+        node.set_network_class("metered").unwrap();
+        assert_eq!(order(&mut node)["deferred"], serde_json::json!(["pic.png"]));
+        node.set_network_class("unmetered").unwrap();
+        node.set_power_state(12, false).unwrap();
+        assert_eq!(order(&mut node)["deferred"], serde_json::json!(["pic.png"]));
+        node.set_network_class("offline").unwrap();
+        assert_eq!(order(&mut node)["transfer"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,107 @@
+//! Node configuration export and import
+//!
+//! Everything a user configures by hand (path policies, merge and debounce
+//! rules, the sync profile and its pins, attachment handling, backup
+//! retention, the content hash, the sync schedule, traffic padding, the
+//! cache quota) in one
+//! versioned document, so settings can move to a new machine or be checked
+//! into the vault. Key material and device identity are never included.
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachments::AttachmentPolicy;
+use crate::caches::{check_cache_quota, DEFAULT_CACHE_QUOTA_BYTES};
+use crate::hashing::HashAlgorithm;
+use crate::limits::{check_size, MAX_ANNOUNCEMENT_BYTES};
+use crate::padding::PaddingConfig;
+use crate::pins::PinSets;
+use crate::policy::SyncPolicy;
+use crate::profiles::SyncProfile;
+use crate::retention::RetentionPolicy;
+use crate::schedule::ScheduleRules;
+
+/// Current configuration schema; older documents are upgraded on import
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+
+/// Configuration documents are small; anything bigger is not one
+const MAX_CONFIG_BYTES: usize = 256 * MAX_ANNOUNCEMENT_BYTES;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub policy: SyncPolicy,
+    #[serde(default)]
+    pub profile: SyncProfile,
+    #[serde(default)]
+    pub attachments: AttachmentPolicy,
+    #[serde(default)]
+    pub backup_retention: RetentionPolicy,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub schedule: ScheduleRules,
+    #[serde(default)]
+    pub padding: PaddingConfig,
+    #[serde(default)]
+    pub pins: PinSets,
+    #[serde(default = "default_cache_quota")]
+    pub cache_quota_bytes: usize,
+}
+
+fn default_cache_quota() -> usize {
+    DEFAULT_CACHE_QUOTA_BYTES
+}
+
+impl NodeConfig {
+    /// Parse and validate a configuration document
+    pub fn parse(blob: &str) -> Result<NodeConfig, String> {
+        check_size("Configuration", blob, MAX_CONFIG_BYTES)?;
+        let value: serde_json::Value = serde_json::from_str(blob).map_err(|e| format!("Invalid configuration: {}", e))?;
+        let version = value
+            .get("schema_version")
+            .and_then(|v| v.as_u64())
+            .ok_or("Configuration has no schema_version")?;
+        if version == 0 || version > CONFIG_SCHEMA_VERSION as u64 {
+            return Err(format!(
+                "Unsupported configuration schema {} (this build reads up to {})",
+                version, CONFIG_SCHEMA_VERSION
+            ));
+        }
+        let config: NodeConfig = serde_json::from_value(value).map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.profile.name.trim().is_empty() {
+            return Err("Profile name must not be empty".to_string());
+        }
+        if self.backup_retention.tiers.iter().any(|t| t.every_ms == 0) {
+            return Err("Retention tiers need a non-zero every_ms".to_string());
+        }
+        self.schedule.validate()?;
+        check_cache_quota(self.cache_quota_bytes)?;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_validation() {
+        assert!(NodeConfig::parse(r#"{"schema_version": 1}"#).is_ok());
+        assert!(NodeConfig::parse(r#"{"policy": {}}"#).unwrap_err().contains("schema_version"));
+        assert!(NodeConfig::parse(r#"{"schema_version": 2}"#).unwrap_err().contains("Unsupported"));
+        assert!(NodeConfig::parse(r#"{"schema_version": 1, "peers": []}"#).is_err());
+        assert!(NodeConfig::parse(r#"{"schema_version": 1, "profile": {"name": " "}}"#).is_err());
+        assert!(NodeConfig::parse(r#"{"schema_version": 1, "cache_quota_bytes": 1024}"#).is_err());
+    }
+}
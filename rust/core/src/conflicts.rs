@@ -0,0 +1,296 @@
+//! Conflict review queue
+//!
+//! Concurrent edits that cannot be settled automatically wait here until the
+//! user decides. Each entry carries both versions' metadata, a short summary
+//! of how the texts differ and a suggested resolution, so the plugin can show
+//! a proper review modal. The queue is serializable and persisted by the host
+//! alongside the journal.
+
+use serde::{Deserialize, Serialize};
+
+use crate::diff::{self, Hunk, LineKind, WordSegment};
+use crate::ids;
+use crate::sync::FileMetadata;
+use crate::memory::{string_bytes, MemoryFootprint};
+
+/// Where and how much two versions of a text differ
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub local_lines: usize,
+    pub remote_lines: usize,
+    /// First differing line (1-based), if any
+    pub first_difference_line: Option<usize>,
+    /// Lines between the common prefix and suffix on each side
+    pub local_changed_lines: usize,
+    pub remote_changed_lines: usize,
+}
+
+impl DiffSummary {
+    pub fn between(local: &str, remote: &str) -> DiffSummary {
+        let local: Vec<&str> = local.lines().collect();
+        let remote: Vec<&str> = remote.lines().collect();
+        let prefix = local.iter().zip(&remote).take_while(|(a, b)| a == b).count();
+        let suffix = local[prefix..]
+            .iter()
+            .rev()
+            .zip(remote[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let local_changed_lines = local.len() - prefix - suffix;
+        let remote_changed_lines = remote.len() - prefix - suffix;
+        let differs = local_changed_lines > 0 || remote_changed_lines > 0;
+        DiffSummary {
+            local_lines: local.len(),
+            remote_lines: remote.len(),
+            first_difference_line: differs.then_some(prefix + 1),
+            local_changed_lines,
+            remote_changed_lines,
+        }
+    }
+}
+
+/// A hunk of the review diff, with its removed and added lines compared word by word
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReviewHunk {
+    #[serde(flatten)]
+    pub hunk: Hunk,
+    pub words: Vec<WordSegment>,
+}
+
+/// Full diff of a conflict's two texts for the review modal
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConflictDiff {
+    pub unified: String,
+    pub hunks: Vec<ReviewHunk>,
+}
+
+impl ConflictDiff {
+    pub fn between(conflict: &Conflict, local: &str, remote: &str, context: usize) -> ConflictDiff {
+        let local_label = format!("{} (local)", conflict.path);
+        let remote_label = format!("{} ({})", conflict.path, conflict.remote.last_modified_by);
+        let side = |hunk: &Hunk, kind: LineKind| {
+            hunk.lines.iter().filter(|l| l.kind == kind).map(|l| l.text.as_str()).collect::<Vec<_>>().join("\n")
+        };
+        let hunks = diff::hunks(local, remote, context)
+            .into_iter()
+            .map(|hunk| ReviewHunk { words: diff::word_diff(&side(&hunk, LineKind::Removed), &side(&hunk, LineKind::Added)), hunk })
+            .collect();
+        ConflictDiff { unified: diff::unified(local, remote, &local_label, &remote_label, context), hunks }
+    }
+}
+
+/// How the user settles a conflict
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Resolution {
+    KeepLocal,
+    KeepRemote,
+    /// Keep local at the original path and the remote version beside it
+    KeepBoth,
+    UseMerged { content: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Conflict {
+    pub id: String,
+    pub path: String,
+    pub local: FileMetadata,
+    pub remote: FileMetadata,
+    #[serde(default)]
+    pub diff: Option<DiffSummary>,
+    pub suggestion: Resolution,
+    pub created_at: u64,
+    /// Hidden from the active list until this time
+    #[serde(default)]
+    pub deferred_until: Option<u64>,
+}
+
+impl Conflict {
+    pub fn is_deferred(&self, now: u64) -> bool {
+        self.deferred_until.is_some_and(|until| until > now)
+    }
+}
+
+/// What the host must do on disk to apply a resolution
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolutionOutcome {
+    pub path: String,
+    /// Fetch the remote version (by hash) and write it to this path
+    pub fetch_remote_to: Option<String>,
+    pub remote_hash: Option<String>,
+    /// Write this content to `path`
+    pub write_content: Option<String>,
+}
+
+/// Suggest a resolution from the diff and timestamps
+/// Purely additive changes keep the side that added; otherwise the newer side wins
+pub fn suggest(local: &FileMetadata, remote: &FileMetadata, diff: Option<&DiffSummary>) -> Resolution {
+    if let Some(diff) = diff {
+        let local_grew = diff.local_lines >= diff.remote_lines;
+        if diff.local_changed_lines == 0 || diff.remote_changed_lines == 0 {
+            return if local_grew { Resolution::KeepLocal } else { Resolution::KeepRemote };
+        }
+    }
+    if remote.mtime > local.mtime {
+        Resolution::KeepRemote
+    } else {
+        Resolution::KeepLocal
+    }
+}
+
+/// Path for the remote copy kept by `keep_both`, e.g. `a (conflict laptop).md`
+pub fn conflict_copy_path(path: &str, device: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    let device: String = device.chars().filter(|c| !matches!(c, '/' | '\\' | ':')).collect();
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{} (conflict {}).{}", dir, stem, device, ext),
+        _ => format!("{}{} (conflict {})", dir, name, device),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ConflictQueue {
+    conflicts: Vec<Conflict>,
+}
+
+impl ConflictQueue {
+    pub fn new() -> ConflictQueue {
+        ConflictQueue::default()
+    }
+
+    /// Queue a conflict, replacing any unresolved one for the same path
+    pub fn push(
+        &mut self,
+        local: FileMetadata,
+        remote: FileMetadata,
+        texts: Option<(&str, &str)>,
+        now: u64,
+    ) -> String {
+        let diff = texts.map(|(local, remote)| DiffSummary::between(local, remote));
+        let suggestion = suggest(&local, &remote, diff.as_ref());
+        let id = ids::new_id();
+        self.conflicts.retain(|c| c.path != local.path);
+        self.conflicts.push(Conflict {
+            id: id.clone(),
+            path: local.path.clone(),
+            local,
+            remote,
+            diff,
+            suggestion,
+            created_at: now,
+            deferred_until: None,
+        });
+        id
+    }
+
+    /// Conflicts in arrival order, optionally including deferred ones
+    pub fn list(&self, now: u64, include_deferred: bool) -> Vec<&Conflict> {
+        self.conflicts.iter().filter(|c| include_deferred || !c.is_deferred(now)).collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Conflict> {
+        self.conflicts.iter().find(|c| c.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.conflicts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Hide a conflict from the active list until `until`
+    pub fn defer(&mut self, id: &str, until: u64) -> Result<(), String> {
+        let conflict = self
+            .conflicts
+            .iter_mut()
+            .find(|c| c.id == id)
+            .ok_or_else(|| format!("Unknown conflict: {}", id))?;
+        conflict.deferred_until = Some(until);
+        Ok(())
+    }
+
+    /// Remove a conflict from the queue, returning it
+    pub fn take(&mut self, id: &str) -> Result<Conflict, String> {
+        let index = self
+            .conflicts
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| format!("Unknown conflict: {}", id))?;
+        Ok(self.conflicts.remove(index))
+    }
+}
+
+impl MemoryFootprint for ConflictQueue {
+    fn heap_bytes(&self) -> usize {
+        self.conflicts.capacity() * size_of::<Conflict>()
+            + self
+                .conflicts
+                .iter()
+                .map(|c| string_bytes(&c.id) + string_bytes(&c.path) + c.local.heap_bytes() + c.remote.heap_bytes())
+                .sum::<usize>()
+    }
+
+    fn trim(&mut self) {
+        self.conflicts.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(path: &str, hash: &str, mtime: u64) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            mtime,
+            size: 0,
+            version: 1,
+            is_deleted: false,
+            last_modified_by: "dev".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_diff_summary_and_suggestion() {
+        let diff = DiffSummary::between("a\nb\nc\n", "a\nB\nX\nc\n");
+        assert_eq!(diff.first_difference_line, Some(2));
+        assert_eq!((diff.local_changed_lines, diff.remote_changed_lines), (1, 2));
+
+        let appended = DiffSummary::between("a\n", "a\nb\n");
+        let suggestion = suggest(&meta("n.md", "1", 5), &meta("n.md", "2", 1), Some(&appended));
+        assert_eq!(suggestion, Resolution::KeepRemote);
+        assert_eq!(suggest(&meta("n.md", "1", 5), &meta("n.md", "2", 1), Some(&diff)), Resolution::KeepLocal);
+
+        let mut queue = ConflictQueue::new();
+        let id = queue.push(meta("n.md", "1", 5), meta("n.md", "2", 1), None, 0);
+        let review = ConflictDiff::between(queue.get(&id).unwrap(), "a\nold words\n", "a\nnew words\n", 1);
+        assert!(review.unified.starts_with("--- n.md (local)\n+++ n.md (dev)\n@@ -1,2 +1,2 @@\n a\n-old words\n+new words\n"));
+        assert_eq!(review.hunks[0].words[0], WordSegment { kind: LineKind::Removed, text: "old".to_string() });
+    }
+
+    #[test]
+    fn test_queue_defer_and_take() {
+        let mut queue = ConflictQueue::new();
+        let first = queue.push(meta("a.md", "1", 1), meta("a.md", "2", 2), None, 100);
+        let second = queue.push(meta("a.md", "1", 1), meta("a.md", "3", 3), None, 100);
+        assert_eq!(queue.len(), 1);
+        assert!(queue.get(&first).is_none());
+
+        queue.defer(&second, 500).unwrap();
+        assert!(queue.list(200, false).is_empty());
+        assert_eq!(queue.list(600, false).len(), 1);
+        assert_eq!(queue.take(&second).unwrap().remote.hash, "3");
+        assert!(queue.take(&second).is_err());
+
+        assert_eq!(conflict_copy_path("Notes/a.md", "laptop"), "Notes/a (conflict laptop).md");
+        assert_eq!(conflict_copy_path("README", "phone"), "README (conflict phone)");
+    }
+}
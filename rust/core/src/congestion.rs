@@ -0,0 +1,196 @@
+//! Bandwidth sharing across peers
+//!
+//! Syncing to several peers at once over one uplink made every transfer slow,
+//! and a large backfill could hold up the note the user just edited. The node
+//! now keeps one flow per peer with the bytes it still has to send and a
+//! priority. The host calls `grant_bandwidth(now)` from its send loop and
+//! sends at most the granted bytes to each peer: the configured budget for
+//! the elapsed time is split by weight (interactive syncs get four shares,
+//! bulk backfill one), and whatever a nearly finished flow doesn't need goes
+//! to the others, so the split rebalances as transfers finish. A budget of 0
+//! leaves the uplink unlimited.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::P2PNode;
+
+/// Most elapsed time granted at once, so a stalled host doesn't get one huge burst
+pub const MAX_GRANT_INTERVAL_MS: u64 = 1000;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// A sync the user is waiting on (a note just edited or opened)
+    Interactive,
+    /// Initial backfill and other background transfers
+    Bulk,
+}
+
+impl Priority {
+    pub fn parse(name: &str) -> Result<Priority, String> {
+        match name {
+            "interactive" => Ok(Priority::Interactive),
+            "bulk" => Ok(Priority::Bulk),
+            other => Err(format!("Unknown transfer priority: {}", other)),
+        }
+    }
+
+    fn weight(self) -> u64 {
+        match self {
+            Priority::Interactive => 4,
+            Priority::Bulk => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Flow {
+    pub priority: Priority,
+    /// Bytes still to send
+    pub pending_bytes: u64,
+    /// Bytes granted in the last round
+    pub last_grant: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct BandwidthScheduler {
+    /// Bytes per second for all peers together; 0 is unlimited
+    budget_bytes_per_sec: u64,
+    flows: BTreeMap<String, Flow>,
+    last_grant_at: Option<u64>,
+}
+
+impl BandwidthScheduler {
+    /// Add `bytes` to the peer's flow; a flow takes the highest priority it was given
+    pub fn enqueue(&mut self, peer_id: &str, priority: Priority, bytes: u64) {
+        let flow = self.flows.entry(peer_id.to_string()).or_insert(Flow { priority, pending_bytes: 0, last_grant: 0 });
+        flow.pending_bytes = flow.pending_bytes.saturating_add(bytes);
+        if priority.weight() > flow.priority.weight() {
+            flow.priority = priority;
+        }
+    }
+
+    pub fn finish(&mut self, peer_id: &str) {
+        self.flows.remove(peer_id);
+    }
+
+    /// Bytes each peer may send now; finished flows are dropped
+    pub fn grant(&mut self, now: u64) -> BTreeMap<String, u64> {
+        let elapsed = self.last_grant_at.map_or(MAX_GRANT_INTERVAL_MS, |last| now.saturating_sub(last));
+        self.last_grant_at = Some(now);
+        let budget = if self.budget_bytes_per_sec == 0 {
+            u64::MAX
+        } else {
+            (self.budget_bytes_per_sec as u128 * elapsed.min(MAX_GRANT_INTERVAL_MS) as u128 / 1000) as u64
+        };
+        self.distribute(budget)
+    }
+
+    /// Split `budget` bytes across the flows by weight; finished flows are dropped
+    pub fn distribute(&mut self, budget: u64) -> BTreeMap<String, u64> {
+        let mut remaining = budget;
+        for flow in self.flows.values_mut() {
+            flow.last_grant = 0;
+        }
+        // Water-filling: split by weight, and hand what satisfied flows leave over to the rest
+        while remaining > 0 {
+            let open: Vec<&mut Flow> = self.flows.values_mut().filter(|f| f.last_grant < f.pending_bytes).collect();
+            if open.is_empty() {
+                break;
+            }
+            let total_weight: u64 = open.iter().map(|f| f.priority.weight()).sum();
+            let mut spent = 0;
+            for flow in open {
+                let share = (remaining as u128 * flow.priority.weight() as u128 / total_weight as u128) as u64;
+                let give = share.max(1).min(flow.pending_bytes - flow.last_grant).min(remaining - spent);
+                flow.last_grant += give;
+                spent += give;
+            }
+            remaining -= spent;
+        }
+
+        let grants = self.flows.iter().map(|(peer, flow)| (peer.clone(), flow.last_grant)).collect();
+        for flow in self.flows.values_mut() {
+            flow.pending_bytes -= flow.last_grant;
+        }
+        self.flows.retain(|_, flow| flow.pending_bytes > 0);
+        grants
+    }
+}
+
+#[derive(Serialize)]
+struct BandwidthJson<'a> {
+    budget_bytes_per_sec: u64,
+    flows: &'a BTreeMap<String, Flow>,
+}
+
+impl P2PNode {
+    /// Upload budget shared by all peers, in bytes per second; 0 is unlimited
+    pub fn set_bandwidth_budget(&mut self, bytes_per_sec: u64) {
+        self.bandwidth.budget_bytes_per_sec = bytes_per_sec;
+    }
+
+    /// Queue `bytes` to send to `peer_id`; `priority` is `interactive` or `bulk`
+    pub fn enqueue_peer_transfer(&mut self, peer_id: &str, priority: &str, bytes: u64) -> Result<(), String> {
+        let priority = Priority::parse(priority).map_err(|e| self.record_error(e))?;
+        self.bandwidth.enqueue(peer_id, priority, bytes);
+        Ok(())
+    }
+
+    /// Drop a peer's flow when its transfers finished early or were cancelled
+    pub fn finish_peer_transfer(&mut self, peer_id: &str) {
+        self.bandwidth.finish(peer_id);
+    }
+
+    /// Bytes each peer may be sent now, as a `{peer_id: bytes}` JSON object
+    pub fn grant_bandwidth(&mut self, now: u64) -> String {
+        serde_json::to_string(&self.bandwidth.grant(now)).unwrap_or_default()
+    }
+
+    /// `{budget_bytes_per_sec, flows: {peer_id: {priority, pending_bytes, last_grant}}}` as JSON
+    pub fn get_bandwidth_json(&self) -> String {
+        serde_json::to_string(&BandwidthJson {
+            budget_bytes_per_sec: self.bandwidth.budget_bytes_per_sec,
+            flows: &self.bandwidth.flows,
+        })
+        .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_shared_by_priority_and_rebalanced() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.set_bandwidth_budget(6000);
+        node.enqueue_peer_transfer("phone", "interactive", 5000).unwrap();
+        node.enqueue_peer_transfer("desktop", "bulk", 100_000).unwrap();
+        node.enqueue_peer_transfer("tablet", "bulk", 100_000).unwrap();
+        assert!(Priority::parse("urgent").is_err());
+
+        let grants = node.bandwidth.grant(0);
+        assert_eq!(grants["phone"], 4000);
+        assert_eq!(grants["desktop"], 1000);
+        assert_eq!(grants["tablet"], 1000);
+
+        // The phone needs only 1000 more; the bulk flows split what it leaves
+        let grants = node.bandwidth.grant(1000);
+        assert_eq!(grants["phone"], 1000);
+        assert_eq!(grants["desktop"], 2500);
+        assert_eq!(grants["tablet"], 2500);
+        assert!(!node.bandwidth.flows.contains_key("phone"));
+
+        // Half a second grants half the budget
+        let grants = node.bandwidth.grant(1500);
+        assert_eq!(grants.values().sum::<u64>(), 3000);
+
+        node.finish_peer_transfer("tablet");
+        node.set_bandwidth_budget(0);
+        let grants = node.bandwidth.grant(1600);
+        assert_eq!(grants["desktop"], 100_000 - 1000 - 2500 - 1500);
+        assert!(node.bandwidth.flows.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+//! Rolling debug ring
+//!
+//! "It just stopped syncing" is usually reported minutes after the fact,
+//! when nobody was recording a trace. The node therefore always keeps the
+//! last few thousand internal events in a fixed-size ring: state changes
+//! (discovery, links, sync rounds), the messages it sent and received with
+//! their sizes, the planner's and scheduler's decisions, and errors
+//! returned to the host. An entry is a timestamp, two static names and at
+//! most a short subject (a peer or transport) and a number, so recording
+//! costs a push into preallocated memory and the ring never grows.
+//!
+//! The host dumps it with `get_debug_ring_json`, e.g. into a bug report.
+//! Unlike `trace`, the ring holds no message bodies and can't be replayed;
+//! it is for reading.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::clock;
+use crate::P2PNode;
+use crate::memory::{deque_bytes, string_bytes, MemoryFootprint};
+
+/// Entries kept by default
+pub const DEFAULT_DEBUG_RING_CAPACITY: usize = 4096;
+/// Entries a host may ask for
+const MAX_DEBUG_RING_CAPACITY: usize = 65_536;
+/// Longer subjects (error messages) are cut off
+const MAX_SUBJECT_BYTES: usize = 160;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugCategory {
+    State,
+    Sent,
+    Received,
+    Decision,
+    Error,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DebugEntry {
+    pub at: u64,
+    pub category: DebugCategory,
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct DebugDump<'a> {
+    capacity: usize,
+    /// Entries pushed out of the ring since it was last cleared
+    dropped: u64,
+    entries: Vec<&'a DebugEntry>,
+}
+
+#[derive(Debug)]
+pub struct DebugRing {
+    capacity: usize,
+    entries: VecDeque<DebugEntry>,
+    dropped: u64,
+}
+
+impl Default for DebugRing {
+    fn default() -> Self {
+        DebugRing::with_capacity(DEFAULT_DEBUG_RING_CAPACITY)
+    }
+}
+
+/// `subject` cut to at most `MAX_SUBJECT_BYTES` on a character boundary
+fn clip(subject: &str) -> &str {
+    if subject.len() <= MAX_SUBJECT_BYTES {
+        return subject;
+    }
+    let mut end = MAX_SUBJECT_BYTES;
+    while !subject.is_char_boundary(end) {
+        end -= 1;
+    }
+    &subject[..end]
+}
+
+impl DebugRing {
+    pub fn with_capacity(capacity: usize) -> DebugRing {
+        DebugRing { capacity, entries: VecDeque::with_capacity(capacity), dropped: 0 }
+    }
+
+    pub fn record(&mut self, category: DebugCategory, event: &'static str, subject: &str, value: Option<u64>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        let entry = DebugEntry { at: clock::now_ms(), category, event, subject: clip(subject).to_string(), value };
+        self.entries.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl P2PNode {
+    /// The ring as `{capacity, dropped, entries: [{at, category, event, subject?, value?}]}`,
+    /// oldest first, keeping entries from `since_ms` on (0 for all)
+    pub fn get_debug_ring_json(&self, since_ms: u64) -> String {
+        let ring = self.debug_ring.borrow();
+        let dump = DebugDump {
+            capacity: ring.capacity,
+            dropped: ring.dropped,
+            entries: ring.entries.iter().filter(|entry| entry.at >= since_ms).collect(),
+        };
+        serde_json::to_string(&dump).unwrap_or_default()
+    }
+
+    /// Resize the ring, keeping the newest entries; 0 turns it off
+    pub fn set_debug_ring_capacity(&mut self, capacity: usize) {
+        let capacity = capacity.min(MAX_DEBUG_RING_CAPACITY);
+        let mut ring = self.debug_ring.borrow_mut();
+        let mut resized = DebugRing::with_capacity(capacity);
+        let skip = ring.entries.len().saturating_sub(capacity);
+        resized.dropped = ring.dropped + skip as u64;
+        resized.entries.extend(ring.entries.drain(skip..));
+        *ring = resized;
+    }
+
+    pub fn clear_debug_ring(&mut self) {
+        let capacity = self.debug_ring.borrow().capacity;
+        *self.debug_ring.borrow_mut() = DebugRing::with_capacity(capacity);
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn debug_event(&self, category: DebugCategory, event: &'static str, subject: &str, value: Option<u64>) {
+        self.debug_ring.borrow_mut().record(category, event, subject, value);
+    }
+}
+
+impl MemoryFootprint for DebugRing {
+    fn heap_bytes(&self) -> usize {
+        deque_bytes(&self.entries, |e| string_bytes(&e.subject))
+    }
+
+    fn trim(&mut self) {
+        self.entries.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::FileMetadata;
+
+    #[test]
+    fn test_ring_keeps_recent_events() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        laptop.update_file("a.md".to_string(), b"note", 1);
+        phone.process_announcement_frame(&laptop.get_announcement_frame(), "10.0.0.2", 5).unwrap();
+        let changes: Vec<FileMetadata> = laptop.change_journal.files().collect();
+        phone.prepare_round(&serde_json::to_string(&changes).unwrap(), "laptop").unwrap();
+        assert!(phone.commit_round().is_err());
+        phone.abort_sync_round();
+
+        let dump: serde_json::Value = serde_json::from_str(&phone.get_debug_ring_json(0)).unwrap();
+        let events: Vec<&str> = dump["entries"].as_array().unwrap().iter().map(|e| e["event"].as_str().unwrap()).collect();
+        assert_eq!(events, vec!["announcement", "round_staged", "round_incomplete", "round_aborted"]);
+        assert_eq!(dump["entries"][1]["subject"], "laptop");
+        assert_eq!(dump["entries"][1]["value"], 1);
+        assert_eq!(laptop.debug_ring.borrow().entries[0].category, DebugCategory::Sent);
+
+        // Bounded, newest kept
+        phone.set_debug_ring_capacity(2);
+        let dump: serde_json::Value = serde_json::from_str(&phone.get_debug_ring_json(0)).unwrap();
+        assert_eq!((dump["dropped"].as_u64(), dump["entries"][0]["event"].as_str()), (Some(2), Some("round_incomplete")));
+        for _ in 0..10 {
+            phone.stop_discovery().unwrap();
+        }
+        assert_eq!(phone.debug_ring.borrow().len(), 2);
+        assert!(phone.get_debug_ring_json(u64::MAX).ends_with(r#""entries":[]}"#));
+
+        phone.set_debug_ring_capacity(0);
+        phone.stop_discovery().unwrap();
+        assert!(phone.debug_ring.borrow().is_empty());
+        assert_eq!(clip(&"é".repeat(100)).len(), MAX_SUBJECT_BYTES);
+    }
+}
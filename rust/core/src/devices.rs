@@ -0,0 +1,282 @@
+//! Device profiles
+//!
+//! A device's name used to travel only in discovery announcements, so after
+//! "John's Laptop" became "Work Laptop" peers that hadn't seen a fresh
+//! announcement (or only heard of the device through others) kept showing
+//! the old name. Renaming now produces a `DeviceProfile` frame with the new
+//! name, icon and platform, a revision and the device's identity signature.
+//! Peers keep the latest revision of every device's profile, update their
+//! peer tables and show it wherever a device ID is attributed, and can
+//! forward the signed record unchanged to peers that weren't connected. The
+//! first identity key seen for a device is pinned; a profile signed by
+//! another key is refused and recorded as a key change.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::crypto::{self, DeviceIdentity};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::security::SecurityEventKind;
+use crate::wire;
+use crate::P2PNode;
+
+/// Longest device name accepted, in characters
+pub const MAX_DEVICE_NAME_CHARS: usize = 64;
+const SIGNATURE_DOMAIN: &[u8] = b"obsidian-p2p-sync device profile v1";
+
+fn length_prefixed(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+fn signed_bytes(profile: &wire::DeviceProfile) -> Vec<u8> {
+    let mut out = SIGNATURE_DOMAIN.to_vec();
+    length_prefixed(&mut out, profile.device_id.as_bytes());
+    out.extend_from_slice(&profile.revision.to_be_bytes());
+    length_prefixed(&mut out, profile.name.as_bytes());
+    length_prefixed(&mut out, profile.icon.as_bytes());
+    length_prefixed(&mut out, profile.platform.as_bytes());
+    out
+}
+
+/// What the user can change about a device
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceDetails {
+    pub name: String,
+    #[serde(default)]
+    pub icon: String,
+    #[serde(default)]
+    pub platform: String,
+}
+
+impl DeviceDetails {
+    fn validate(&self) -> Result<(), String> {
+        let chars = self.name.trim().chars().count();
+        if chars == 0 || chars > MAX_DEVICE_NAME_CHARS {
+            return Err(format!("Device names must have 1 to {} characters", MAX_DEVICE_NAME_CHARS));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KnownDevice {
+    #[serde(flatten)]
+    pub details: DeviceDetails,
+    pub revision: u64,
+    /// Base64 identity key pinned for the device
+    identity_key: String,
+    /// Base64 signature, kept so the record can be forwarded
+    signature: String,
+    pub updated_at: u64,
+}
+
+impl KnownDevice {
+    pub fn identity_key(&self) -> &str {
+        &self.identity_key
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DeviceDirectory {
+    devices: BTreeMap<String, KnownDevice>,
+}
+
+impl DeviceDirectory {
+    pub fn get(&self, device_id: &str) -> Option<&KnownDevice> {
+        self.devices.get(device_id)
+    }
+
+    /// Drop the profile of `device_id`, so the next one pins its key afresh
+    pub(crate) fn forget(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+    }
+
+    fn frame(&self, device_id: &str) -> Option<Vec<u8>> {
+        let device = self.devices.get(device_id)?;
+        let profile = wire::DeviceProfile {
+            device_id: device_id.to_string(),
+            revision: device.revision,
+            name: device.details.name.clone(),
+            icon: device.details.icon.clone(),
+            platform: device.details.platform.clone(),
+            identity_key: BASE64.decode(&device.identity_key).ok()?,
+            signature: BASE64.decode(&device.signature).ok()?,
+        };
+        Some(wire::encode_frame(&wire::Envelope::new(wire::Body::DeviceProfile(profile))))
+    }
+
+    /// Remember a verified profile; returns whether it was newer than the one known,
+    /// or the event kind and description when it is signed by another key
+    fn admit(&mut self, profile: &wire::DeviceProfile, now: u64) -> Result<bool, (SecurityEventKind, String)> {
+        let key = BASE64.encode(&profile.identity_key);
+        if let Some(known) = self.devices.get(&profile.device_id) {
+            if known.identity_key != key {
+                return Err((SecurityEventKind::KeyChanged, format!("Profile of {} is signed by a different key", profile.device_id)));
+            }
+            if known.revision >= profile.revision {
+                return Ok(false);
+            }
+        }
+        let device = KnownDevice {
+            details: DeviceDetails { name: profile.name.clone(), icon: profile.icon.clone(), platform: profile.platform.clone() },
+            revision: profile.revision,
+            identity_key: key,
+            signature: BASE64.encode(&profile.signature),
+            updated_at: now,
+        };
+        self.devices.insert(profile.device_id.clone(), device);
+        Ok(true)
+    }
+}
+
+impl P2PNode {
+    /// Rename this device (and set its icon and platform) from JSON `{name, icon, platform}`;
+    /// returns a signed `DeviceProfile` frame for peers with `CAP_DEVICE_PROFILES`
+    pub fn update_device_profile(&mut self, identity: &DeviceIdentity, details_json: &str) -> Result<Vec<u8>, String> {
+        serde_json::from_str(details_json)
+            .map_err(|e| format!("Invalid device profile: {}", e))
+            .and_then(|details| self.profile_frame(identity, details))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Verify a device's profile frame (sent by it or forwarded by a peer); returns
+    /// true when it was newer than what we knew and names were updated
+    pub fn process_device_profile_frame(&mut self, frame: &[u8]) -> Result<bool, String> {
+        self.apply_device_profile_frame(frame).map_err(|e| self.record_error(e))
+    }
+
+    /// The latest signed profile of `device_id`, to forward to a peer that may not have it
+    pub fn get_device_profile_frame(&self, device_id: &str) -> Option<Vec<u8>> {
+        self.devices.frame(device_id)
+    }
+
+    /// Name to show for `device_id`, e.g. in journal attribution; the ID itself when unknown
+    pub fn get_device_display_name(&self, device_id: &str) -> String {
+        if device_id == self.device_id {
+            return self.device_name.clone();
+        }
+        self.devices
+            .get(device_id)
+            .map(|d| d.details.name.clone())
+            .or_else(|| self.peers.values().find(|p| p.device_id == device_id).map(|p| p.name.clone()))
+            .unwrap_or_else(|| device_id.to_string())
+    }
+
+    /// Known device profiles as a JSON object keyed by device ID
+    pub fn get_known_devices_json(&self) -> String {
+        serde_json::to_string(&self.devices.devices).unwrap_or_default()
+    }
+
+    /// Device profiles as JSON, for persisting with `load_device_directory_state`
+    pub fn get_device_directory_state(&self) -> String {
+        serde_json::to_string(&self.devices).unwrap_or_default()
+    }
+
+    pub fn load_device_directory_state(&mut self, json: &str) -> Result<(), String> {
+        let directory = check_size("Device directory", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load device directory: {}", e)))?;
+        self.devices = directory;
+        Ok(())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn profile_frame(&mut self, identity: &DeviceIdentity, details: DeviceDetails) -> Result<Vec<u8>, String> {
+        details.validate()?;
+        let device_id = identity.get_device_id();
+        // Clock-based so a reinstalled device (which lost its revision) still counts as newer
+        let revision = self.devices.get(&device_id).map_or(0, |d| d.revision + 1).max(clock::now_ms());
+        let mut profile = wire::DeviceProfile {
+            device_id: device_id.clone(),
+            revision,
+            name: details.name.trim().to_string(),
+            icon: details.icon,
+            platform: details.platform,
+            identity_key: BASE64.decode(identity.get_public_key()).map_err(|e| e.to_string())?,
+            signature: Vec::new(),
+        };
+        profile.signature = BASE64.decode(identity.sign(&signed_bytes(&profile))).map_err(|e| e.to_string())?;
+        self.devices.admit(&profile, clock::now_ms()).map_err(|(_, message)| message)?;
+        if device_id == self.device_id {
+            self.device_name = profile.name.clone();
+        }
+        Ok(self.devices.frame(&device_id).unwrap_or_default())
+    }
+
+    pub(crate) fn apply_device_profile_frame(&mut self, frame: &[u8]) -> Result<bool, String> {
+        let Some((envelope, _)) = wire::decode_frame(frame)? else {
+            return Err("Incomplete device profile frame".to_string());
+        };
+        let Some(wire::Body::DeviceProfile(profile)) = envelope.body else {
+            return Err("Expected a device profile frame".to_string());
+        };
+        let signed = signed_bytes(&profile);
+        if !crypto::verify_signature(BASE64.encode(&profile.identity_key), &signed, BASE64.encode(&profile.signature)) {
+            let message = format!("Invalid device profile signature from {}", profile.device_id);
+            self.security_log.record(SecurityEventKind::SignatureInvalid, &profile.device_id, message.clone(), &[("frame", "device_profile")]);
+            return Err(message);
+        }
+        let details = DeviceDetails { name: profile.name.clone(), icon: profile.icon.clone(), platform: profile.platform.clone() };
+        details.validate()?;
+        self.check_identity_key(&profile.device_id, &BASE64.encode(&profile.identity_key), "device_profile")?;
+        let updated = self.devices.admit(&profile, clock::now_ms()).map_err(|(kind, message)| {
+            self.security_log.record(kind, &profile.device_id, message.clone(), &[("frame", "device_profile")]);
+            message
+        })?;
+        if updated {
+            for peer in self.peers.values_mut().filter(|p| p.device_id == profile.device_id) {
+                peer.name = profile.name.clone();
+            }
+        }
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_propagates_and_forwards() {
+        let identity = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let mut laptop = P2PNode::new("John's Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let mut tablet = P2PNode::new("Tablet".to_string(), "tablet".to_string(), 8082);
+        phone.process_announcement_frame(&laptop.get_announcement_frame(), "10.0.0.2", 1).unwrap();
+        assert_eq!(phone.get_device_display_name("laptop"), "John's Laptop");
+
+        let details = |name: &str| DeviceDetails { name: name.to_string(), icon: "laptop".to_string(), platform: "linux".to_string() };
+        let first = laptop.profile_frame(&identity, details("Laptop")).unwrap();
+        let renamed = laptop.profile_frame(&identity, details("  Work Laptop ")).unwrap();
+        assert_eq!(laptop.get_device_name(), "Work Laptop");
+        assert!(phone.apply_device_profile_frame(&renamed).unwrap());
+        assert_eq!(phone.get_device_display_name("laptop"), "Work Laptop");
+        assert!(phone.get_discovered_peers_json().contains("Work Laptop"));
+        // An older revision arriving late changes nothing
+        assert!(!phone.apply_device_profile_frame(&first).unwrap());
+        assert_eq!(phone.get_device_display_name("laptop"), "Work Laptop");
+
+        // The phone forwards the signed record to a device the laptop never met
+        let mut restored = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        restored.load_device_directory_state(&phone.get_device_directory_state()).unwrap();
+        assert!(tablet.apply_device_profile_frame(&restored.get_device_profile_frame("laptop").unwrap()).unwrap());
+        assert_eq!(tablet.get_device_display_name("laptop"), "Work Laptop");
+        assert_eq!(tablet.get_device_display_name("unknown"), "unknown");
+
+        // Another key claiming to be the laptop, and a tampered record
+        let impostor = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let forged = P2PNode::new("X".to_string(), "laptop".to_string(), 8080).profile_frame(&impostor, details("Evil")).unwrap();
+        assert!(phone.apply_device_profile_frame(&forged).is_err());
+        assert_eq!(phone.security_log.iter().last().unwrap().kind, SecurityEventKind::KeyChanged);
+        let mut tampered = laptop.profile_frame(&identity, details("Laptop 3")).unwrap();
+        let at = tampered.windows(8).position(|w| w == b"Laptop 3").unwrap();
+        tampered[at + 7] = b'4';
+        assert!(phone.apply_device_profile_frame(&tampered).is_err());
+        assert_eq!(phone.get_device_display_name("laptop"), "Work Laptop");
+        assert!(details("").validate().is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Line and word diffs
+//!
+//! Conflict review shows the user how two versions differ, and the line
+//! merge strategy needs the same edit script to find the regions each side
+//! changed relative to the ancestor. Both come from here: `changes` lists the
+//! differing regions, `hunks` groups them with surrounding context, `unified`
+//! renders hunks as a standard unified diff and `word_diff` splits a changed
+//! region into word-level segments for inline highlighting.
+//!
+//! The edit script is a longest-common-subsequence diff after trimming the
+//! common prefix and suffix. When the remaining middle is too large for that
+//! (`MAX_DIFF_CELLS`), it is reported as one replaced block instead.
+
+use serde::Serialize;
+use std::ops::Range;
+
+/// Largest middle section (old × new tokens) diffed exactly
+pub const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A region that differs: `old` in the old tokens was replaced by `new` in the new ones
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    Context,
+    Removed,
+    Added,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// Without the line ending
+    pub text: String,
+    /// The last line of a text that doesn't end with a newline
+    pub no_newline: bool,
+}
+
+/// A run of changes with context, numbered like a unified diff (1-based)
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct WordSegment {
+    pub kind: LineKind,
+    pub text: String,
+}
+
+/// Lines of `text`, each keeping its line ending so they join back losslessly
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Regions where `new` differs from `old`, in order
+pub fn changes<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Change> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+    if a.is_empty() || b.is_empty() || a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        return vec![Change { old: prefix..prefix + a.len(), new: prefix..prefix + b.len() }];
+    }
+
+    // lcs[i][j]: length of the LCS of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut out: Vec<Change> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut pending: Option<Change> = None;
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.extend(pending.take());
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let change = pending.get_or_insert(Change { old: prefix + i..prefix + i, new: prefix + j..prefix + j });
+        if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            i += 1;
+            change.old.end = prefix + i;
+        } else {
+            j += 1;
+            change.new.end = prefix + j;
+        }
+    }
+    out.extend(pending);
+    out
+}
+
+fn diff_line(kind: LineKind, line: &str) -> DiffLine {
+    let text = line.strip_suffix('\n').map(|l| l.strip_suffix('\r').unwrap_or(l));
+    DiffLine { kind, text: text.unwrap_or(line).to_string(), no_newline: text.is_none() }
+}
+
+/// Changes between two texts grouped into hunks with `context` unchanged lines around them
+pub fn hunks(old: &str, new: &str, context: usize) -> Vec<Hunk> {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    let all = changes(&old_lines, &new_lines);
+    let mut out = Vec::new();
+    let mut index = 0;
+    while index < all.len() {
+        // Changes closer together than twice the context share a hunk
+        let mut last = index;
+        while last + 1 < all.len() && all[last + 1].old.start - all[last].old.end <= 2 * context {
+            last += 1;
+        }
+        let first = &all[index];
+        let old_start = first.old.start.saturating_sub(context);
+        let new_start = first.new.start - (first.old.start - old_start);
+        let old_end = (all[last].old.end + context).min(old_lines.len());
+        let new_end = all[last].new.end + (old_end - all[last].old.end);
+
+        let mut lines = Vec::new();
+        let mut position = old_start;
+        for change in &all[index..=last] {
+            lines.extend(old_lines[position..change.old.start].iter().map(|l| diff_line(LineKind::Context, l)));
+            lines.extend(old_lines[change.old.clone()].iter().map(|l| diff_line(LineKind::Removed, l)));
+            lines.extend(new_lines[change.new.clone()].iter().map(|l| diff_line(LineKind::Added, l)));
+            position = change.old.end;
+        }
+        lines.extend(old_lines[position..old_end].iter().map(|l| diff_line(LineKind::Context, l)));
+
+        // Unified diffs number an empty range by the line before it
+        let number = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        out.push(Hunk {
+            old_start: number(old_start, old_end - old_start),
+            old_lines: old_end - old_start,
+            new_start: number(new_start, new_end - new_start),
+            new_lines: new_end - new_start,
+            lines,
+        });
+        index = last + 1;
+    }
+    out
+}
+
+/// A unified diff of two texts, empty when they are equal
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str, context: usize) -> String {
+    let hunks = hunks(old, new, context);
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for hunk in &hunks {
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines));
+        for line in &hunk.lines {
+            let marker = match line.kind {
+                LineKind::Context => ' ',
+                LineKind::Removed => '-',
+                LineKind::Added => '+',
+            };
+            out.push(marker);
+            out.push_str(&line.text);
+            out.push('\n');
+            if line.no_newline {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// Runs of whitespace and of everything else
+fn words(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let space = c.is_whitespace();
+        if let Some(&(next, n)) = chars.peek() {
+            if n.is_whitespace() != space {
+                out.push(&text[start..next]);
+                start = next;
+            }
+        }
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+/// Word-level segments turning `old` into `new`; adjacent segments of one kind are joined
+pub fn word_diff(old: &str, new: &str) -> Vec<WordSegment> {
+    let (old_words, new_words) = (words(old), words(new));
+    let mut segments: Vec<WordSegment> = Vec::new();
+    let mut push = |kind: LineKind, text: &[&str]| {
+        if text.is_empty() {
+            return;
+        }
+        match segments.last_mut() {
+            Some(last) if last.kind == kind => last.text.push_str(&text.concat()),
+            _ => segments.push(WordSegment { kind, text: text.concat() }),
+        }
+    };
+    let mut position = 0;
+    for change in changes(&old_words, &new_words) {
+        push(LineKind::Context, &old_words[position..change.old.start]);
+        push(LineKind::Removed, &old_words[change.old.clone()]);
+        push(LineKind::Added, &new_words[change.new.clone()]);
+        position = change.old.end;
+    }
+    push(LineKind::Context, &old_words[position..]);
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_and_hunks() {
+        let old = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\n";
+        let new = "one\n2\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+        assert_eq!(
+            unified(old, new, "a/note.md", "b/note.md", 1),
+            "--- a/note.md\n+++ b/note.md\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n@@ -9,1 +9,2 @@\n nine\n+ten\n"
+        );
+        // Wider context joins the two changes into one hunk
+        assert_eq!(hunks(old, new, 4).len(), 1);
+        assert!(unified(old, old, "a", "b", 3).is_empty());
+        assert!(unified("a", "b", "a", "b", 3).ends_with("-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"));
+        assert_eq!(changes(&split_lines("x\n"), &split_lines("")), vec![Change { old: 0..1, new: 0..0 }]);
+    }
+
+    #[test]
+    fn test_word_diff() {
+        let segments = word_diff("the quick brown fox", "the slow brown fox jumps");
+        let rendered: Vec<(LineKind, &str)> = segments.iter().map(|s| (s.kind, s.text.as_str())).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                (LineKind::Context, "the "),
+                (LineKind::Removed, "quick"),
+                (LineKind::Added, "slow"),
+                (LineKind::Context, " brown fox"),
+                (LineKind::Added, " jumps"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Segmented export of large outputs
+//!
+//! `get_journal_state` and `get_all_files` build one string for the whole
+//! vault, which for large vaults means several megabytes allocated at once
+//! and a long pause while they are written. An export instead yields the same
+//! JSON in segments of roughly `SEGMENT_BYTES`, so the host can append them
+//! to a file or a socket as it goes. Concatenated, the segments are exactly
+//! what the one-shot call would have returned (up to map order), and a
+//! journal export can be fed straight back into `push_journal_segment`.
+
+use serde::Serialize;
+
+use crate::sync::ChangeJournal;
+
+use crate::compat::JOURNAL_FORMAT_VERSION;
+
+/// Target size of one segment; a single entry larger than this is emitted whole
+pub const SEGMENT_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportKind {
+    /// The persisted journal, as `get_journal_state`
+    Journal,
+    /// Current file metadata, as `get_all_files`
+    Files,
+}
+
+impl ExportKind {
+    pub fn parse(name: &str) -> Result<ExportKind, String> {
+        match name {
+            "journal" => Ok(ExportKind::Journal),
+            "files" => Ok(ExportKind::Files),
+            other => Err(format!("Unknown export: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Open,
+    Files,
+    Close,
+    Done,
+}
+
+pub struct Exporter {
+    kind: ExportKind,
+    /// Paths to emit, captured when the export began
+    paths: Vec<String>,
+    next: usize,
+    phase: Phase,
+    /// Journal sequence when the export began; a change invalidates the export
+    sequence: u64,
+    segment_bytes: usize,
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+impl Exporter {
+    pub fn new(kind: ExportKind, journal: &ChangeJournal) -> Exporter {
+        Exporter::with_segment_bytes(kind, journal, SEGMENT_BYTES)
+    }
+
+    pub fn with_segment_bytes(kind: ExportKind, journal: &ChangeJournal, segment_bytes: usize) -> Exporter {
+        Exporter {
+            kind,
+            paths: journal.files().map(|meta| meta.path).collect(),
+            next: 0,
+            phase: Phase::Open,
+            sequence: journal.sequence(),
+            segment_bytes,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    /// Next segment of output, or `None` once everything has been returned
+    pub fn next_segment(&mut self, journal: &ChangeJournal) -> Result<Option<String>, String> {
+        if self.phase == Phase::Done {
+            return Ok(None);
+        }
+        if journal.sequence() != self.sequence {
+            self.phase = Phase::Done;
+            return Err("Journal changed during export; start a new one".to_string());
+        }
+        let mut out = String::new();
+        while out.len() < self.segment_bytes && self.phase != Phase::Done {
+            self.step(journal, &mut out);
+        }
+        Ok(Some(out))
+    }
+
+    /// Append the next piece of output
+    fn step(&mut self, journal: &ChangeJournal, out: &mut String) {
+        match self.phase {
+            Phase::Open => {
+                match self.kind {
+                    ExportKind::Journal => out.push_str(&format!("{{\"format_version\":{},\"files\":{{", JOURNAL_FORMAT_VERSION)),
+                    ExportKind::Files => out.push('['),
+                }
+                self.phase = Phase::Files;
+            }
+            Phase::Files => {
+                let Some(path) = self.paths.get(self.next) else {
+                    if self.kind == ExportKind::Journal {
+                        out.push_str(&format!("}},\"global_sequence\":{}", journal.sequence()));
+                    }
+                    self.phase = Phase::Close;
+                    self.next = 0;
+                    return;
+                };
+                // The sequence check guarantees the entry is still there
+                if let Some(meta) = journal.get(path) {
+                    if self.next > 0 {
+                        out.push(',');
+                    }
+                    if self.kind == ExportKind::Journal {
+                        out.push_str(&to_json(path));
+                        out.push(':');
+                    }
+                    out.push_str(&to_json(&meta));
+                }
+                self.next += 1;
+            }
+            Phase::Close => {
+                if self.kind == ExportKind::Journal {
+                    out.push_str(&format!(
+                        ",\"backups\":{},\"hash_algorithm\":{}}}",
+                        to_json(journal.backups()),
+                        to_json(&journal.hash_algorithm())
+                    ));
+                } else {
+                    out.push(']');
+                }
+                self.paths = Vec::new();
+                self.phase = Phase::Done;
+            }
+            Phase::Done => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::JournalLoader;
+    use crate::sync::FileMetadata;
+
+    fn sample() -> ChangeJournal {
+        let mut journal = ChangeJournal::new();
+        for i in 0..200 {
+            journal.update_file(format!("Folder {}/note \"{}\".md", i % 7, i), format!("body {}", i).as_bytes(), i, "laptop".to_string());
+        }
+        journal.mark_deleted("Folder 3/note \"3\".md".to_string(), 500, "phone".to_string());
+        journal
+    }
+
+    fn drain(exporter: &mut Exporter, journal: &ChangeJournal) -> Vec<String> {
+        let mut segments = Vec::new();
+        while let Some(segment) = exporter.next_segment(journal).unwrap() {
+            segments.push(segment);
+        }
+        segments
+    }
+
+    #[test]
+    fn test_journal_segments_round_trip_through_loader() {
+        let source = sample();
+        let mut exporter = Exporter::with_segment_bytes(ExportKind::Journal, &source, 1024);
+        let segments = drain(&mut exporter, &source);
+        assert!(segments.len() > 10);
+        assert!(segments.iter().all(|s| s.len() < 1024 + 512));
+
+        let whole: serde_json::Value = serde_json::from_str(&segments.concat()).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(&source.to_json()).unwrap();
+        assert_eq!(whole, expected);
+
+        let mut loaded = ChangeJournal::new();
+        let mut loader = JournalLoader::new();
+        for segment in &segments {
+            loader.push(segment, &mut loaded).unwrap();
+        }
+        assert!(loader.progress().complete);
+        assert_eq!(loaded.sequence(), source.sequence());
+        assert_eq!(loaded.get("Folder 3/note \"3\".md"), source.get("Folder 3/note \"3\".md"));
+    }
+
+    #[test]
+    fn test_file_list_and_concurrent_change() {
+        let mut journal = sample();
+        let mut exporter = Exporter::with_segment_bytes(ExportKind::Files, &journal, 2048);
+        let files: Vec<FileMetadata> = serde_json::from_str(&drain(&mut exporter, &journal).concat()).unwrap();
+        assert_eq!(files.len(), 200);
+
+        let mut exporter = Exporter::with_segment_bytes(ExportKind::Files, &journal, 2048);
+        exporter.next_segment(&journal).unwrap();
+        journal.update_file("new.md".to_string(), b"x", 1, "laptop".to_string());
+        assert!(exporter.next_segment(&journal).is_err());
+        assert_eq!(exporter.next_segment(&journal), Ok(None));
+    }
+}
@@ -0,0 +1,190 @@
+//! Extended file metadata
+//!
+//! Besides content, size and mtime, an entry can carry a creation time, an
+//! executable flag and a few string attributes (`sync::ExtendedMetadata`).
+//! Most entries have none and cost nothing; hosts that care, e.g. to keep the
+//! creation dates some plugins rely on, set them through
+//! `P2PNode::set_file_extended_metadata` and they travel with the entry.
+//!
+//! Propagation rules for an incoming version:
+//! - `ctime`: the earliest known time wins, since a copy made on another
+//!   device reports its own, later creation time
+//! - `executable`: taken from the incoming version, like the content
+//! - attributes: taken from the incoming version, except keys under a
+//!   local-only prefix (`local.` by default), which receivers ignore and
+//!   keep from their own entry
+//!
+//! Content changes carry the previous version's metadata forward, so a host
+//! only sets metadata when it changes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditOrigin;
+use crate::error::Error;
+use crate::sync::ExtendedMetadata;
+use crate::P2PNode;
+
+const DEFAULT_LOCAL_PREFIX: &str = "local.";
+const DEFAULT_MAX_ATTRIBUTES: usize = 32;
+const DEFAULT_MAX_VALUE_BYTES: usize = 1024;
+/// Attribute keys are identifiers, not data
+const MAX_KEY_BYTES: usize = 128;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MetadataRules {
+    /// Attribute key prefixes kept on this device and ignored when received
+    #[serde(default = "default_local_prefixes")]
+    pub local_prefixes: Vec<String>,
+    #[serde(default = "default_max_attributes")]
+    pub max_attributes: usize,
+    #[serde(default = "default_max_value_bytes")]
+    pub max_value_bytes: usize,
+}
+
+fn default_local_prefixes() -> Vec<String> {
+    vec![DEFAULT_LOCAL_PREFIX.to_string()]
+}
+
+fn default_max_attributes() -> usize {
+    DEFAULT_MAX_ATTRIBUTES
+}
+
+fn default_max_value_bytes() -> usize {
+    DEFAULT_MAX_VALUE_BYTES
+}
+
+impl Default for MetadataRules {
+    fn default() -> Self {
+        MetadataRules {
+            local_prefixes: default_local_prefixes(),
+            max_attributes: DEFAULT_MAX_ATTRIBUTES,
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+        }
+    }
+}
+
+impl MetadataRules {
+    pub fn is_local(&self, key: &str) -> bool {
+        self.local_prefixes.iter().any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    /// Metadata a host may set
+    pub fn validate(&self, extended: &ExtendedMetadata) -> Result<(), Error> {
+        if extended.attributes.len() > self.max_attributes {
+            return Err(format!("{} attributes (limit {})", extended.attributes.len(), self.max_attributes).into());
+        }
+        for (key, value) in &extended.attributes {
+            if key.is_empty() || key.len() > MAX_KEY_BYTES {
+                return Err(format!("Attribute key {:?} must be 1 to {} bytes", key, MAX_KEY_BYTES).into());
+            }
+            if value.len() > self.max_value_bytes {
+                return Err(format!("Attribute {} is {} bytes (limit {})", key, value.len(), self.max_value_bytes).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Metadata to record for an incoming version, given the local entry's
+    pub fn merge_incoming(&self, local: &ExtendedMetadata, remote: ExtendedMetadata) -> ExtendedMetadata {
+        let ctime = match (local.ctime, remote.ctime) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut attributes: Vec<(String, String)> = remote
+            .attributes
+            .into_iter()
+            .filter(|(key, value)| !self.is_local(key) && key.len() <= MAX_KEY_BYTES && value.len() <= self.max_value_bytes)
+            .take(self.max_attributes)
+            .collect();
+        attributes.extend(local.attributes.iter().filter(|(key, _)| self.is_local(key)).map(|(k, v)| (k.clone(), v.clone())));
+        ExtendedMetadata { ctime, executable: remote.executable, attributes: attributes.into_iter().collect() }
+    }
+}
+
+impl P2PNode {
+    /// Replace a file's extended metadata (`{ctime?, executable?, attributes?}`),
+    /// recording a new version if it changed
+    pub fn set_file_extended_metadata(&mut self, path: &str, json: &str) -> Result<bool, String> {
+        let extended: ExtendedMetadata = match serde_json::from_str(json) {
+            Ok(extended) => extended,
+            Err(e) => return Err(self.record_error(format!("Invalid extended metadata: {}", e))),
+        };
+        if let Err(e) = self.metadata_rules.validate(&extended) {
+            return Err(self.record_error(format!("Invalid extended metadata for {}: {}", path, e)));
+        }
+        let sequence = self.change_journal.sequence();
+        let changed = self
+            .change_journal
+            .set_extended(path, extended, self.device_id.clone())
+            .map_err(|e| self.record_error(e))?;
+        self.audit_since(sequence, AuditOrigin::Local);
+        Ok(changed)
+    }
+
+    /// A file's extended metadata as JSON, `null` for an unknown path
+    pub fn get_file_extended_metadata(&self, path: &str) -> String {
+        let extended = self.change_journal.get(path).map(|meta| meta.extended);
+        serde_json::to_string(&extended).unwrap_or_default()
+    }
+
+    /// Set `{local_prefixes?, max_attributes?, max_value_bytes?}`; omitted fields take their defaults
+    pub fn set_metadata_rules(&mut self, json: &str) -> Result<(), String> {
+        let rules = serde_json::from_str(json).map_err(|e| self.record_error(format!("Invalid metadata rules: {}", e)))?;
+        self.metadata_rules = rules;
+        Ok(())
+    }
+
+    pub fn get_metadata_rules_json(&self) -> String {
+        serde_json::to_string(&self.metadata_rules).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::round::RoundAction;
+    use crate::sync::FileMetadata;
+
+    fn changes_since(node: &P2PNode, sequence: u64) -> String {
+        let changes: Vec<FileMetadata> = node.change_journal.files().filter(|m| m.version > sequence).collect();
+        serde_json::to_string(&changes).unwrap()
+    }
+
+    #[test]
+    fn test_extended_metadata_round_trips() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        laptop.update_file("a.md".to_string(), b"note", 5);
+        phone.update_file("a.md".to_string(), b"note", 7);
+        phone.set_file_extended_metadata("a.md", r#"{"ctime":7,"attributes":{"local.folded":"3"}}"#).unwrap();
+        let json = r#"{"ctime":5,"attributes":{"color":"red","local.cursor":"12"}}"#;
+        assert!(laptop.set_file_extended_metadata("a.md", json).unwrap());
+        assert!(!laptop.set_file_extended_metadata("a.md", json).unwrap());
+        assert!(laptop.set_file_extended_metadata("missing.md", json).is_err());
+
+        // Edits keep the metadata
+        laptop.update_file("a.md".to_string(), b"note, edited", 6);
+        assert_eq!(laptop.change_journal.get("a.md").unwrap().extended.ctime, Some(5));
+
+        phone.prepare_round(&changes_since(&laptop, 0), "laptop").unwrap();
+        phone.verify_round_content("a.md", b"note, edited").unwrap();
+        let ops = phone.commit_round().unwrap();
+        let extended = &ops[0].extended;
+        assert_eq!((ops[0].action, extended.ctime), (RoundAction::Write, Some(5)));
+        let keys: Vec<&str> = extended.attributes.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["color", "local.folded"]);
+
+        // A metadata-only change needs no download
+        let sequence = laptop.change_journal.sequence();
+        laptop.set_file_extended_metadata("a.md", r#"{"ctime":9,"executable":true}"#).unwrap();
+        assert!(phone.prepare_round(&changes_since(&laptop, sequence), "laptop").unwrap().awaiting.is_empty());
+        let ops = phone.commit_round().unwrap();
+        assert_eq!(ops[0].action, RoundAction::Metadata);
+        assert!(ops[0].extended.executable);
+        assert_eq!(ops[0].extended.ctime, Some(5));
+        assert_eq!(phone.prepare_round(&changes_since(&laptop, sequence), "laptop").unwrap().staged, 0);
+
+        let oversized = format!(r#"{{"attributes":{{"k":"{}"}}}}"#, "x".repeat(2000));
+        assert!(phone.set_file_extended_metadata("a.md", &oversized).is_err());
+    }
+}
@@ -0,0 +1,551 @@
+//! Compact storage for the journal's current file entries
+//!
+//! A vault with 100k notes keeps one entry per file in memory for the whole
+//! session, so the layout matters: folder prefixes and device ids are
+//! interned once, SHA-256 hashes are held as 32 raw bytes instead of 64 hex
+//! characters, and each entry only owns its file name. `FileMetadata` values
+//! are rebuilt on access. The serialized form is unchanged (a map of path to
+//! metadata), so persisted journals load either way.
+//!
+//! The table also keeps running totals per shard (top-level folder, see
+//! `shards`), updated on every insert and removal.
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::size_of;
+
+use crate::hashing::{hash_digest, HashAlgorithm, BLAKE3_PREFIX};
+use crate::sync::{ExtendedMetadata, FileMetadata};
+
+/// Strings stored once and referred to by index
+#[derive(Default)]
+pub(crate) struct Interner {
+    names: Vec<Box<str>>,
+    ids: HashMap<Box<str>, u32>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(name.into());
+        self.ids.insert(name.into(), id);
+        id
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    pub(crate) fn name(&self, id: u32) -> &str {
+        &self.names[id as usize]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.names.capacity() * size_of::<Box<str>>()
+            + self.ids.capacity() * (size_of::<Box<str>>() + size_of::<u32>() + 1)
+            + 2 * self.names.iter().map(|n| n.len()).sum::<usize>()
+    }
+}
+
+/// Algorithm and raw bytes of a hash in the canonical lowercase form `HashAlgorithm::hash` produces
+fn parse_digest(hash: &str) -> Option<(HashKind, [u8; 32])> {
+    let (algorithm, hex) = hash_digest(hash)?;
+    let mut bytes = [0u8; 32];
+    let lowercase = hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    let kind = match algorithm {
+        HashAlgorithm::Sha256 => HashKind::Sha256,
+        HashAlgorithm::Blake3 => HashKind::Blake3,
+    };
+    (lowercase && hex::decode_to_slice(hex, &mut bytes).is_ok()).then_some((kind, bytes))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HashKind {
+    Empty,
+    Sha256,
+    Blake3,
+    /// Anything else, interned verbatim; `hash` holds the id
+    Other,
+}
+
+struct Entry {
+    hash: [u8; 32],
+    mtime: u64,
+    size: u64,
+    version: u64,
+    device: u32,
+    hash_kind: HashKind,
+    is_deleted: bool,
+}
+
+impl Entry {
+    fn other_hash_id(&self) -> u32 {
+        other_hash_id(&self.hash)
+    }
+}
+
+fn other_hash_id(hash: &[u8; 32]) -> u32 {
+    u32::from_le_bytes(hash[..4].try_into().unwrap_or_default())
+}
+
+/// Raw form of `hash`, interning it in `other` if it isn't a digest `parse_digest` knows
+pub(crate) fn pack_hash(hash: &str, other: &mut Interner) -> (HashKind, [u8; 32]) {
+    if hash.is_empty() {
+        (HashKind::Empty, [0; 32])
+    } else if let Some(parsed) = parse_digest(hash) {
+        parsed
+    } else {
+        let mut id = [0; 32];
+        id[..4].copy_from_slice(&other.intern(hash).to_le_bytes());
+        (HashKind::Other, id)
+    }
+}
+
+/// The hash string `pack_hash` was given
+pub(crate) fn unpack_hash(kind: HashKind, hash: &[u8; 32], other: &Interner) -> String {
+    match kind {
+        HashKind::Empty => String::new(),
+        HashKind::Sha256 => hex::encode(hash),
+        HashKind::Blake3 => format!("{}{}", BLAKE3_PREFIX, hex::encode(hash)),
+        HashKind::Other => other.name(other_hash_id(hash)).to_string(),
+    }
+}
+
+/// Top-level folder `path` belongs to, or `""` for files in the vault root
+pub fn shard_of(path: &str) -> &str {
+    path.split_once('/').map_or("", |(top, _)| top)
+}
+
+/// An entry's contribution to its shard digest
+fn entry_digest(meta: &FileMetadata) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for field in [meta.path.as_bytes(), meta.hash.as_bytes()] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.update(meta.version.to_be_bytes());
+    hasher.update([u8::from(meta.is_deleted)]);
+    hasher.finalize().into()
+}
+
+/// A live entry's contribution to its shard's content digest: path and hash only, so
+/// devices holding the same files agree whatever versions they numbered them with
+fn content_digest(meta: &FileMetadata) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for field in [meta.path.as_bytes(), meta.hash.as_bytes()] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().into()
+}
+
+fn xor_into(digest: &mut [u8; 32], other: &[u8; 32]) {
+    digest.iter_mut().zip(other).for_each(|(a, b)| *a ^= b);
+}
+
+/// Running totals for the entries of one shard
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShardStats {
+    /// Highest version among the shard's entries
+    pub sequence: u64,
+    /// Entries, deleted ones included
+    pub entries: usize,
+    pub deleted: usize,
+    /// XOR of `entry_digest` over the entries, so it updates in place and ignores order
+    pub digest: [u8; 32],
+    /// XOR of `content_digest` over the live entries
+    pub content: [u8; 32],
+}
+
+impl ShardStats {
+    pub fn add(&mut self, meta: &FileMetadata) {
+        self.sequence = self.sequence.max(meta.version);
+        self.entries += 1;
+        self.deleted += usize::from(meta.is_deleted);
+        xor_into(&mut self.digest, &entry_digest(meta));
+        if !meta.is_deleted {
+            xor_into(&mut self.content, &content_digest(meta));
+        }
+    }
+
+    fn subtract(&mut self, meta: &FileMetadata) {
+        self.entries -= 1;
+        self.deleted -= usize::from(meta.is_deleted);
+        xor_into(&mut self.digest, &entry_digest(meta));
+        if !meta.is_deleted {
+            xor_into(&mut self.content, &content_digest(meta));
+        }
+    }
+}
+
+/// Current metadata for every path, keyed by folder then file name
+#[derive(Default)]
+pub struct FileTable {
+    /// Folder prefixes including their trailing `/` (empty for the vault root)
+    dirs: Interner,
+    devices: Interner,
+    other_hashes: Interner,
+    /// Entries per folder id
+    entries: Vec<HashMap<Box<str>, Entry>>,
+    len: usize,
+    shards: HashMap<Box<str>, ShardStats>,
+    /// `moved_from` of the few entries recorded by a rename, by path
+    moves: HashMap<Box<str>, Box<str>>,
+    /// Non-empty extended metadata, by path
+    extended: HashMap<Box<str>, ExtendedMetadata>,
+}
+
+/// Split after the last `/` so that `dir + name` is always the original path
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path),
+    }
+}
+
+impl FileTable {
+    pub fn new() -> FileTable {
+        FileTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn entry(&self, path: &str) -> Option<&Entry> {
+        let (dir, name) = split_path(path);
+        let dir = self.dirs.get(dir)?;
+        self.entries[dir as usize].get(name)
+    }
+
+    fn metadata(&self, dir: u32, name: &str, entry: &Entry) -> FileMetadata {
+        let path = format!("{}{}", self.dirs.name(dir), name);
+        let moved_from = if self.moves.is_empty() { None } else { self.moves.get(path.as_str()).map(|from| from.to_string()) };
+        FileMetadata {
+            hash: self.hash_hex(entry),
+            mtime: entry.mtime,
+            size: entry.size,
+            version: entry.version,
+            is_deleted: entry.is_deleted,
+            last_modified_by: self.devices.name(entry.device).to_string(),
+            moved_from,
+            extended: self.extended(&path),
+            path,
+        }
+    }
+
+    fn hash_hex(&self, entry: &Entry) -> String {
+        unpack_hash(entry.hash_kind, &entry.hash, &self.other_hashes)
+    }
+
+    pub fn get(&self, path: &str) -> Option<FileMetadata> {
+        let (dir, name) = split_path(path);
+        let dir = self.dirs.get(dir)?;
+        self.entries[dir as usize].get(name).map(|entry| self.metadata(dir, name, entry))
+    }
+
+    /// Extended metadata of `path`, empty if it has none
+    pub fn extended(&self, path: &str) -> ExtendedMetadata {
+        if self.extended.is_empty() {
+            return ExtendedMetadata::default();
+        }
+        self.extended.get(path).cloned().unwrap_or_default()
+    }
+
+    /// Whether `path` exists, is not deleted, and has content `hash`
+    pub fn is_live_with_hash(&self, path: &str, hash: &str) -> bool {
+        self.entry(path).is_some_and(|e| {
+            !e.is_deleted
+                && match e.hash_kind {
+                    HashKind::Empty => hash.is_empty(),
+                    HashKind::Sha256 | HashKind::Blake3 => parse_digest(hash) == Some((e.hash_kind, e.hash)),
+                    HashKind::Other => self.other_hashes.get(hash) == Some(e.other_hash_id()),
+                }
+        })
+    }
+
+    /// `Some(true)` for a tombstone, `None` for an unknown path
+    pub fn is_deleted(&self, path: &str) -> Option<bool> {
+        self.entry(path).map(|e| e.is_deleted)
+    }
+
+    pub fn insert(&mut self, meta: FileMetadata) {
+        let prior = self.get(&meta.path);
+        self.account(prior.as_ref(), Some(&meta));
+        match &meta.moved_from {
+            Some(from) => self.moves.insert(meta.path.as_str().into(), from.as_str().into()),
+            None if self.moves.is_empty() => None,
+            None => self.moves.remove(meta.path.as_str()),
+        };
+        if !meta.extended.is_empty() {
+            self.extended.insert(meta.path.as_str().into(), meta.extended.clone());
+        } else if !self.extended.is_empty() {
+            self.extended.remove(meta.path.as_str());
+        }
+        let (dir, name) = split_path(&meta.path);
+        let dir = self.dirs.intern(dir) as usize;
+        if dir == self.entries.len() {
+            self.entries.push(HashMap::new());
+        }
+        let (hash_kind, hash) = pack_hash(&meta.hash, &mut self.other_hashes);
+        let entry = Entry {
+            hash,
+            hash_kind,
+            mtime: meta.mtime,
+            size: meta.size,
+            version: meta.version,
+            device: self.devices.intern(&meta.last_modified_by),
+            is_deleted: meta.is_deleted,
+        };
+        if self.entries[dir].insert(name.into(), entry).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// Forget `path` entirely (unlike a tombstone); interned names are kept
+    pub fn remove(&mut self, path: &str) {
+        let prior = self.get(path);
+        let (dir, name) = split_path(path);
+        if let Some(dir) = self.dirs.get(dir) {
+            if self.entries[dir as usize].remove(name).is_some() {
+                self.len -= 1;
+            }
+        }
+        if prior.is_some() {
+            self.account(prior.as_ref(), None);
+            self.moves.remove(path);
+            self.extended.remove(path);
+        }
+    }
+
+    /// Move `prior` out of its shard's totals and `next` (the same path) in
+    fn account(&mut self, prior: Option<&FileMetadata>, next: Option<&FileMetadata>) {
+        let Some(path) = next.or(prior).map(|meta| meta.path.clone()) else {
+            return;
+        };
+        let shard = shard_of(&path);
+        let stats = self.shards.entry(shard.into()).or_default();
+        if let Some(prior) = prior {
+            stats.subtract(prior);
+        }
+        if let Some(next) = next {
+            stats.add(next);
+        }
+        if stats.entries == 0 {
+            self.shards.remove(shard);
+            return;
+        }
+        // Only a rollback or a removal lowers the highest version; recount the shard then
+        let lowered = prior.is_some_and(|p| p.version == stats.sequence && next.is_none_or(|n| n.version < p.version));
+        if lowered {
+            let others = self.shard_iter(shard).filter(|m| m.path != path).map(|m| m.version);
+            let sequence = others.chain(next.map(|n| n.version)).max().unwrap_or(0);
+            if let Some(stats) = self.shards.get_mut(shard) {
+                stats.sequence = sequence;
+            }
+        }
+    }
+
+    /// Totals of every shard with at least one entry
+    pub fn shards(&self) -> impl Iterator<Item = (&str, &ShardStats)> {
+        self.shards.iter().map(|(name, stats)| (&**name, stats))
+    }
+
+    pub fn shard(&self, shard: &str) -> Option<&ShardStats> {
+        self.shards.get(shard)
+    }
+
+    /// Entries of one shard; folders of other shards are skipped without visiting their entries
+    pub fn shard_iter<'a>(&'a self, shard: &'a str) -> impl Iterator<Item = FileMetadata> + 'a {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(move |(dir, _)| shard_of(self.dirs.name(*dir as u32)) == shard)
+            .flat_map(move |(dir, files)| files.iter().map(move |(name, entry)| self.metadata(dir as u32, name, entry)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = FileMetadata> + '_ {
+        self.entries.iter().enumerate().flat_map(move |(dir, files)| {
+            files.iter().map(move |(name, entry)| self.metadata(dir as u32, name, entry))
+        })
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.entries.iter().flat_map(|files| files.values()).filter(|e| e.is_deleted).count()
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.dirs.heap_bytes()
+            + self.devices.heap_bytes()
+            + self.other_hashes.heap_bytes()
+            + self.entries.capacity() * size_of::<HashMap<Box<str>, Entry>>()
+            + self.shards.capacity() * (size_of::<Box<str>>() + size_of::<ShardStats>() + 1)
+            + self.shards.keys().map(|name| name.len()).sum::<usize>()
+            + self.moves.capacity() * (2 * size_of::<Box<str>>() + 1)
+            + self.moves.iter().map(|(path, from)| path.len() + from.len()).sum::<usize>()
+            + self.extended.capacity() * (size_of::<Box<str>>() + size_of::<ExtendedMetadata>() + 1)
+            + self.extended.iter().map(|(path, meta)| path.len() + meta.heap_bytes()).sum::<usize>()
+            + self
+                .entries
+                .iter()
+                .map(|files| {
+                    files.capacity() * (size_of::<Box<str>>() + size_of::<Entry>() + 1)
+                        + files.keys().map(|name| name.len()).sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.entries.shrink_to_fit();
+        self.shards.shrink_to_fit();
+        self.moves.shrink_to_fit();
+        self.extended.shrink_to_fit();
+        for files in &mut self.entries {
+            files.shrink_to_fit();
+        }
+    }
+}
+
+impl Serialize for FileTable {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(self.iter().map(|meta| (meta.path.clone(), meta)))
+    }
+}
+
+struct FileTableVisitor;
+
+impl<'de> Visitor<'de> for FileTableVisitor {
+    type Value = FileTable;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of path to file metadata")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FileTable, A::Error> {
+        let mut table = FileTable::new();
+        while let Some((path, mut meta)) = map.next_entry::<String, FileMetadata>()? {
+            meta.path = path;
+            table.insert(meta);
+        }
+        Ok(table)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTable {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<FileTable, D::Error> {
+        d.deserialize_map(FileTableVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::map_overhead;
+    use crate::sync::hash_content;
+
+    fn meta(path: &str, hash: &str) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            mtime: 1,
+            size: 2,
+            version: 3,
+            is_deleted: hash.is_empty(),
+            last_modified_by: "laptop".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_round_trips_unusual_paths_and_hashes() {
+        let mut table = FileTable::new();
+        let sha = hash_content(b"note");
+        let entries = [
+            meta("a.md", &sha),
+            meta("/rooted.md", "not-a-sha"),
+            meta("Folder/", &sha.to_uppercase()),
+            meta("Folder/Sub/b.md", ""),
+            meta("Folder/c.md", &HashAlgorithm::Blake3.hash(b"note")),
+        ];
+        for entry in &entries {
+            table.insert(entry.clone());
+        }
+        table.insert(meta("a.md", &sha));
+        assert_eq!(table.len(), 5);
+        for entry in &entries {
+            assert_eq!(table.get(&entry.path).as_ref(), Some(entry));
+        }
+        assert!(table.is_live_with_hash("a.md", &sha));
+        assert!(!table.is_live_with_hash("Folder/", &sha));
+        assert!(!table.is_live_with_hash("Folder/c.md", &HashAlgorithm::Sha256.hash(b"note")));
+        assert_eq!(table.is_deleted("Folder/Sub/b.md"), Some(true));
+        assert_eq!(table.get("Folder/Sub"), None);
+
+        let json = serde_json::to_string(&table).unwrap();
+        let restored: FileTable = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get("/rooted.md").as_ref(), Some(&entries[1]));
+        assert_eq!(restored.deleted_count(), 1);
+    }
+
+    #[test]
+    fn test_shard_totals_follow_changes() {
+        let mut table = FileTable::new();
+        let sha = hash_content(b"note");
+        for (path, version) in [("Daily/a.md", 3), ("Daily/2024/b.md", 7), ("Attachments/c.png", 5), ("root.md", 1)] {
+            table.insert(FileMetadata { version, ..meta(path, &sha) });
+        }
+        let recount = |table: &FileTable, shard: &str| {
+            let mut stats = ShardStats::default();
+            table.shard_iter(shard).for_each(|m| stats.add(&m));
+            stats
+        };
+        assert_eq!(table.shards().count(), 3);
+        assert_eq!(table.shard("Daily").unwrap().sequence, 7);
+        assert_eq!(shard_of("/rooted.md"), "");
+
+        // A rollback to an older version and a removal both lower the sequence
+        let attachments = *table.shard("Attachments").unwrap();
+        table.insert(FileMetadata { version: 2, ..meta("Daily/2024/b.md", "") });
+        assert_eq!(*table.shard("Daily").unwrap(), recount(&table, "Daily"));
+        assert_eq!(table.shard("Daily").unwrap().sequence, 3);
+        table.remove("Daily/a.md");
+        assert_eq!(*table.shard("Daily").unwrap(), recount(&table, "Daily"));
+        assert_eq!(*table.shard("Attachments").unwrap(), attachments);
+        table.remove("root.md");
+        assert!(table.shard("").is_none());
+    }
+
+    #[test]
+    fn test_large_vault_footprint() {
+        let mut table = FileTable::new();
+        let mut plain: HashMap<String, FileMetadata> = HashMap::new();
+        for i in 0..10_000 {
+            let path = format!("Projects/Area {}/Notes/note-{}.md", i % 20, i);
+            let entry = meta(&path, &hash_content(path.as_bytes()));
+            table.insert(entry.clone());
+            plain.insert(path, entry);
+        }
+        // The layout this replaced: full path keys and `FileMetadata` values with hex hashes
+        let plain_bytes = map_overhead(&plain)
+            + plain
+                .iter()
+                .map(|(path, m)| path.len() + m.path.len() + m.hash.len() + m.last_modified_by.len())
+                .sum::<usize>();
+        assert!(table.heap_bytes() * 2 < plain_bytes, "{} vs {}", table.heap_bytes(), plain_bytes);
+    }
+}
@@ -0,0 +1,294 @@
+//! First-sync bootstrap between two devices
+//!
+//! Two devices that were set up from copies of the same vault have the same
+//! files but journals built independently (different versions, different
+//! `last_modified_by`), so a normal first round compares every entry. The
+//! bootstrap exchange instead compares a two-level Merkle tree over the live
+//! entries' paths and content hashes:
+//!
+//! 1. `Offer`: the initiator's root, entry count and journal sequence.
+//! 2. Equal roots: the responder answers `Identical` and both sides are done.
+//!    Otherwise it answers `Buckets` with its 256 bucket hashes.
+//! 3. The initiator answers `Manifest` with its entries in the buckets that
+//!    differ; the responder answers with its own entries in those buckets
+//!    (marked final), and each side knows exactly which paths differ.
+//!
+//! Either way each side ends with a watermark for the peer: the peer's
+//! journal sequence at the exchange, so later rounds only look at what
+//! changed after it. When the vaults turn out identical the peer is also
+//! known to have everything up to our own sequence, which seeds trickle mode.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::sync::ChangeJournal;
+
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+const BUCKETS: usize = 256;
+
+fn length_prefixed(hasher: &mut Sha256, field: &[u8]) {
+    hasher.update((field.len() as u32).to_be_bytes());
+    hasher.update(field);
+}
+
+/// Bucket of a path: the first byte of its SHA-256
+fn bucket_of(path: &str) -> usize {
+    Sha256::digest(path.as_bytes())[0] as usize
+}
+
+/// Bucket hashes over live entries' `(path, hash)`, each bucket ordered by path
+pub fn bucket_hashes(journal: &ChangeJournal) -> Vec<[u8; 32]> {
+    let mut buckets: Vec<Vec<FileMetadata>> = vec![Vec::new(); BUCKETS];
+    for meta in journal.files().filter(|m| !m.is_deleted) {
+        buckets[bucket_of(&meta.path)].push(meta);
+    }
+    buckets
+        .into_iter()
+        .map(|mut entries| {
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut hasher = Sha256::new();
+            for meta in &entries {
+                length_prefixed(&mut hasher, meta.path.as_bytes());
+                length_prefixed(&mut hasher, meta.hash.to_ascii_lowercase().as_bytes());
+            }
+            hasher.finalize().into()
+        })
+        .collect()
+}
+
+pub fn content_root(buckets: &[[u8; 32]]) -> [u8; 32] {
+    buckets.iter().fold(Sha256::new(), |hasher, bucket| hasher.chain_update(bucket)).finalize().into()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BootstrapMessage {
+    Offer { device_id: String, sequence: u64, root: String, entries: usize },
+    Identical { device_id: String, sequence: u64 },
+    Buckets { device_id: String, sequence: u64, buckets: Vec<String> },
+    Manifest {
+        device_id: String,
+        sequence: u64,
+        buckets: Vec<usize>,
+        entries: Vec<FileMetadata>,
+        /// The answer to a manifest; nothing is sent back
+        #[serde(default)]
+        last: bool,
+    },
+}
+
+/// Outcome of a bootstrap with one peer
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BootstrapResult {
+    pub identical: bool,
+    /// The peer's entries that are missing here or have other content
+    pub differing: Vec<FileMetadata>,
+    /// Live paths here that the peer doesn't have
+    pub missing_on_peer: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BootstrapState {
+    /// Peer journal sequence already reconciled, by device ID
+    watermarks: BTreeMap<String, u64>,
+    results: BTreeMap<String, BootstrapResult>,
+}
+
+impl BootstrapState {
+    pub fn watermark(&self, device_id: &str) -> Option<u64> {
+        self.watermarks.get(device_id).copied()
+    }
+
+    /// Raise the watermark of `device_id` to `sequence`
+    pub fn reconciled(&mut self, device_id: &str, sequence: u64) {
+        let watermark = self.watermarks.entry(device_id.to_string()).or_default();
+        *watermark = (*watermark).max(sequence);
+    }
+
+    fn finish(&mut self, device_id: &str, sequence: u64, result: BootstrapResult) {
+        self.reconciled(device_id, sequence);
+        self.results.insert(device_id.to_string(), result);
+    }
+}
+
+/// Compare the peer's entries in `buckets` with ours in the same buckets
+fn compare(journal: &ChangeJournal, buckets: &[usize], remote: Vec<FileMetadata>) -> BootstrapResult {
+    let wanted: BTreeSet<usize> = buckets.iter().copied().collect();
+    let mut local: BTreeMap<String, FileMetadata> = journal
+        .files()
+        .filter(|m| !m.is_deleted && wanted.contains(&bucket_of(&m.path)))
+        .map(|m| (m.path.clone(), m))
+        .collect();
+    let mut result = BootstrapResult::default();
+    for entry in remote.into_iter().filter(|m| !m.is_deleted) {
+        match local.remove(&entry.path) {
+            Some(mine) if mine.hash.eq_ignore_ascii_case(&entry.hash) => {}
+            _ => result.differing.push(entry),
+        }
+    }
+    result.missing_on_peer = local.into_keys().collect();
+    result.identical = result.differing.is_empty() && result.missing_on_peer.is_empty();
+    result
+}
+
+impl P2PNode {
+    /// Open a first-sync bootstrap: the `Offer` message to send the peer, as JSON
+    pub fn start_bootstrap_exchange(&self) -> String {
+        let buckets = bucket_hashes(&self.change_journal);
+        let offer = BootstrapMessage::Offer {
+            device_id: self.device_id.clone(),
+            sequence: self.change_journal.sequence(),
+            root: hex::encode(content_root(&buckets)),
+            entries: self.change_journal.files().filter(|m| !m.is_deleted).count(),
+        };
+        serde_json::to_string(&offer).unwrap_or_default()
+    }
+
+    /// Handle a bootstrap message from a peer; returns the reply to send as JSON, or `null`
+    /// once the exchange is over (see `get_bootstrap_result_json`)
+    pub fn process_bootstrap_message(&mut self, message_json: &str) -> Result<String, String> {
+        let reply = self.bootstrap_reply(message_json).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&reply).map_err(|e| e.to_string())
+    }
+
+    /// `{identical, differing, missing_on_peer}` from the last bootstrap with a peer, or `null`
+    pub fn get_bootstrap_result_json(&self, device_id: &str) -> String {
+        serde_json::to_string(&self.first_sync.results.get(device_id)).unwrap_or_default()
+    }
+
+    /// Peer journal sequence reconciled by bootstrap, if any
+    pub fn get_peer_watermark(&self, device_id: &str) -> Option<u64> {
+        self.first_sync.watermark(device_id)
+    }
+
+    /// Watermarks and results as JSON, for persisting with `load_bootstrap_state`
+    pub fn get_bootstrap_state(&self) -> String {
+        serde_json::to_string(&self.first_sync).unwrap_or_default()
+    }
+
+    pub fn load_bootstrap_state(&mut self, json: &str) -> Result<(), String> {
+        let state = check_size("Bootstrap state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load bootstrap state: {}", e)))?;
+        self.first_sync = state;
+        Ok(())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn bootstrap_reply(&mut self, message_json: &str) -> Result<Option<BootstrapMessage>, String> {
+        check_size("Bootstrap message", message_json, MAX_JOURNAL_BYTES)?;
+        let message: BootstrapMessage =
+            serde_json::from_str(message_json).map_err(|e| format!("Invalid bootstrap message: {}", e))?;
+        let device_id = self.device_id.clone();
+        let sequence = self.change_journal.sequence();
+        let buckets = bucket_hashes(&self.change_journal);
+
+        let reply = match message {
+            BootstrapMessage::Offer { device_id: peer, sequence: peer_sequence, root, .. } => {
+                if root.eq_ignore_ascii_case(&hex::encode(content_root(&buckets))) {
+                    self.finish_identical(&peer, peer_sequence, sequence);
+                    Some(BootstrapMessage::Identical { device_id, sequence })
+                } else {
+                    Some(BootstrapMessage::Buckets { device_id, sequence, buckets: buckets.iter().map(hex::encode).collect() })
+                }
+            }
+            BootstrapMessage::Identical { device_id: peer, sequence: peer_sequence } => {
+                self.finish_identical(&peer, peer_sequence, sequence);
+                None
+            }
+            BootstrapMessage::Buckets { buckets: theirs, .. } => {
+                if theirs.len() != BUCKETS {
+                    return Err(format!("Expected {} bucket hashes, got {}", BUCKETS, theirs.len()));
+                }
+                let differing: Vec<usize> =
+                    (0..BUCKETS).filter(|&i| !theirs[i].eq_ignore_ascii_case(&hex::encode(buckets[i]))).collect();
+                Some(self.manifest_for(differing, false))
+            }
+            BootstrapMessage::Manifest { device_id: peer, sequence: peer_sequence, buckets: wanted, entries, last } => {
+                if wanted.iter().any(|&b| b >= BUCKETS) {
+                    return Err("Bucket index out of range".to_string());
+                }
+                let result = compare(&self.change_journal, &wanted, entries);
+                self.first_sync.finish(&peer, peer_sequence, result);
+                (!last).then(|| self.manifest_for(wanted, true))
+            }
+        };
+        Ok(reply)
+    }
+
+    fn manifest_for(&self, buckets: Vec<usize>, last: bool) -> BootstrapMessage {
+        let wanted: BTreeSet<usize> = buckets.iter().copied().collect();
+        let mut entries: Vec<FileMetadata> = self
+            .change_journal
+            .files()
+            .filter(|m| !m.is_deleted && wanted.contains(&bucket_of(&m.path)))
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        BootstrapMessage::Manifest {
+            device_id: self.device_id.clone(),
+            sequence: self.change_journal.sequence(),
+            buckets,
+            entries,
+            last,
+        }
+    }
+
+    fn finish_identical(&mut self, peer: &str, peer_sequence: u64, sequence: u64) {
+        self.first_sync.finish(peer, peer_sequence, BootstrapResult { identical: true, ..Default::default() });
+        self.trickle.ack(peer, sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run an exchange to completion, returning the number of messages sent
+    fn exchange(initiator: &mut P2PNode, responder: &mut P2PNode) -> usize {
+        let mut message = initiator.start_bootstrap_exchange();
+        let mut sent = 1;
+        let (mut from, mut to) = (initiator, responder);
+        while let Some(reply) = to.bootstrap_reply(&message).unwrap() {
+            message = serde_json::to_string(&reply).unwrap();
+            sent += 1;
+            std::mem::swap(&mut from, &mut to);
+        }
+        sent
+    }
+
+    #[test]
+    fn test_identical_vaults_finish_in_two_messages() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        for i in 0..50 {
+            laptop.update_file(format!("n{}.md", i), format!("note {}", i).as_bytes(), 1);
+        }
+        // Same files indexed in another order, plus an unrelated tombstone
+        for i in (0..50).rev() {
+            phone.update_file(format!("n{}.md", i), format!("note {}", i).as_bytes(), 2);
+        }
+        phone.update_file("old.md".to_string(), b"x", 1);
+        phone.mark_file_deleted("old.md".to_string(), 3);
+
+        assert_eq!(exchange(&mut laptop, &mut phone), 2);
+        assert_eq!(laptop.get_peer_watermark("phone"), Some(phone.change_journal.sequence()));
+        assert_eq!(phone.get_peer_watermark("laptop"), Some(50));
+        assert!(laptop.first_sync.results["phone"].identical);
+
+        phone.update_file("n7.md".to_string(), b"edited", 4);
+        phone.update_file("new.md".to_string(), b"new", 4);
+        laptop.update_file("mine.md".to_string(), b"mine", 4);
+        assert_eq!(exchange(&mut laptop, &mut phone), 4);
+        let at_laptop = &laptop.first_sync.results["phone"];
+        let mut differing: Vec<&str> = at_laptop.differing.iter().map(|m| m.path.as_str()).collect();
+        differing.sort();
+        assert_eq!(differing, vec!["n7.md", "new.md"]);
+        assert_eq!(at_laptop.missing_on_peer, vec!["mine.md"]);
+        assert_eq!(phone.first_sync.results["laptop"].missing_on_peer, vec!["new.md"]);
+    }
+}
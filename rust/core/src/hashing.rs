@@ -0,0 +1,324 @@
+//! Batch content hashing
+//!
+//! SHA-256 of a single file is inherently sequential, but indexing hashes
+//! thousands of independent files, so the batch path runs four messages
+//! through the compression function side by side, one per 32-bit lane. When
+//! the module is built with `-C target-feature=+simd128` the lanes map onto
+//! wasm SIMD registers; otherwise the same code runs on plain arrays. Since a
+//! module using SIMD instructions fails to compile on engines without them,
+//! hosts ship both builds, pick one after feature-detecting SIMD, and can
+//! confirm the choice with the bindings' `get_build_features`.
+//!
+//! BLAKE3 is available as a faster alternative content hash. Hashes record
+//! their algorithm: SHA-256 as bare hex (every hash written before BLAKE3
+//! existed), BLAKE3 as `blake3:` followed by hex, so a journal can mix both
+//! while it migrates.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::timing::{self, Stage};
+
+/// Marks a BLAKE3 hash; SHA-256 hashes are bare hex
+pub const BLAKE3_PREFIX: &str = "blake3:";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Result<HashAlgorithm, Error> {
+        match name {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            other => Err(format!("Unknown hash algorithm: {}", other).into()),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Algorithm a recorded hash was made with
+    pub fn of(hash: &str) -> HashAlgorithm {
+        if hash.starts_with(BLAKE3_PREFIX) {
+            HashAlgorithm::Blake3
+        } else {
+            HashAlgorithm::Sha256
+        }
+    }
+
+    pub fn hash(&self, content: &[u8]) -> String {
+        let _span = timing::span(Stage::Hash);
+        match self {
+            HashAlgorithm::Sha256 => crate::sync::hash_content(content),
+            HashAlgorithm::Blake3 => format!("{}{}", BLAKE3_PREFIX, blake3::hash(content).to_hex()),
+        }
+    }
+
+    /// Hasher for content that arrives in pieces
+    pub fn stream(&self) -> StreamHasher {
+        match self {
+            HashAlgorithm::Sha256 => StreamHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => StreamHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Incremental form of `HashAlgorithm::hash`; `finish` gives the same string
+pub enum StreamHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl StreamHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        let _span = timing::span(Stage::Hash);
+        match self {
+            StreamHasher::Sha256(hasher) => hasher.update(data),
+            StreamHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        match self {
+            StreamHasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+            StreamHasher::Blake3(hasher) => format!("{}{}", BLAKE3_PREFIX, hasher.finalize().to_hex()),
+        }
+    }
+}
+
+/// Digest bytes of a hash string: 64 hex chars, optionally with the BLAKE3 prefix
+pub fn hash_digest(hash: &str) -> Option<(HashAlgorithm, &str)> {
+    let algorithm = HashAlgorithm::of(hash);
+    let hex = hash.strip_prefix(BLAKE3_PREFIX).unwrap_or(hash);
+    (hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit())).then_some((algorithm, hex))
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Messages hashed side by side
+const LANES: usize = 4;
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod lanes {
+    use core::arch::wasm32::*;
+
+    pub type V = v128;
+
+    pub fn splat(x: u32) -> V {
+        u32x4_splat(x)
+    }
+    pub fn from_lanes(x: [u32; 4]) -> V {
+        u32x4(x[0], x[1], x[2], x[3])
+    }
+    pub fn to_lanes(v: V) -> [u32; 4] {
+        [u32x4_extract_lane::<0>(v), u32x4_extract_lane::<1>(v), u32x4_extract_lane::<2>(v), u32x4_extract_lane::<3>(v)]
+    }
+    pub fn add(a: V, b: V) -> V {
+        u32x4_add(a, b)
+    }
+    pub fn xor(a: V, b: V) -> V {
+        v128_xor(a, b)
+    }
+    pub fn and(a: V, b: V) -> V {
+        v128_and(a, b)
+    }
+    pub fn andnot(a: V, b: V) -> V {
+        v128_andnot(b, a)
+    }
+    pub fn shr(a: V, n: u32) -> V {
+        u32x4_shr(a, n)
+    }
+    pub fn rotr(a: V, n: u32) -> V {
+        v128_or(u32x4_shr(a, n), u32x4_shl(a, 32 - n))
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+mod lanes {
+    pub type V = [u32; 4];
+
+    fn map(a: V, f: impl Fn(u32) -> u32) -> V {
+        [f(a[0]), f(a[1]), f(a[2]), f(a[3])]
+    }
+    fn zip(a: V, b: V, f: impl Fn(u32, u32) -> u32) -> V {
+        [f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]), f(a[3], b[3])]
+    }
+
+    pub fn splat(x: u32) -> V {
+        [x; 4]
+    }
+    pub fn from_lanes(x: [u32; 4]) -> V {
+        x
+    }
+    pub fn to_lanes(v: V) -> [u32; 4] {
+        v
+    }
+    pub fn add(a: V, b: V) -> V {
+        zip(a, b, u32::wrapping_add)
+    }
+    pub fn xor(a: V, b: V) -> V {
+        zip(a, b, |x, y| x ^ y)
+    }
+    pub fn and(a: V, b: V) -> V {
+        zip(a, b, |x, y| x & y)
+    }
+    /// `!a & b`
+    pub fn andnot(a: V, b: V) -> V {
+        zip(a, b, |x, y| !x & y)
+    }
+    pub fn shr(a: V, n: u32) -> V {
+        map(a, |x| x >> n)
+    }
+    pub fn rotr(a: V, n: u32) -> V {
+        map(a, |x| x.rotate_right(n))
+    }
+}
+
+use lanes::*;
+
+fn compress(state: &mut [V; 8], block: &[V; 16]) {
+    let mut w = [splat(0); 64];
+    w[..16].copy_from_slice(block);
+    for i in 16..64 {
+        let s0 = xor(xor(rotr(w[i - 15], 7), rotr(w[i - 15], 18)), shr(w[i - 15], 3));
+        let s1 = xor(xor(rotr(w[i - 2], 17), rotr(w[i - 2], 19)), shr(w[i - 2], 10));
+        w[i] = add(add(w[i - 16], s0), add(w[i - 7], s1));
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = xor(xor(rotr(e, 6), rotr(e, 11)), rotr(e, 25));
+        let ch = xor(and(e, f), andnot(e, g));
+        let t1 = add(add(add(h, s1), add(ch, splat(K[i]))), w[i]);
+        let s0 = xor(xor(rotr(a, 2), rotr(a, 13)), rotr(a, 22));
+        let maj = xor(xor(and(a, b), and(a, c)), and(b, c));
+        let t2 = add(s0, maj);
+        h = g;
+        g = f;
+        f = e;
+        e = add(d, t1);
+        d = c;
+        c = b;
+        b = a;
+        a = add(t1, t2);
+    }
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *s = add(*s, v);
+    }
+}
+
+/// Blocks in the padded message
+fn block_count(len: usize) -> usize {
+    (len + 9).div_ceil(64)
+}
+
+/// Block `index` of the padded message as big-endian words
+fn message_block(msg: &[u8], index: usize) -> [u32; 16] {
+    let start = index * 64;
+    let mut bytes = [0u8; 64];
+    if start + 64 <= msg.len() {
+        bytes.copy_from_slice(&msg[start..start + 64]);
+    } else {
+        let tail = msg.get(start..).unwrap_or(&[]);
+        bytes[..tail.len()].copy_from_slice(tail);
+        if start <= msg.len() {
+            bytes[tail.len()] = 0x80;
+        }
+        if index + 1 == block_count(msg.len()) {
+            bytes[56..].copy_from_slice(&((msg.len() as u64) * 8).to_be_bytes());
+        }
+    }
+    std::array::from_fn(|i| u32::from_be_bytes([bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]]))
+}
+
+/// Hash up to four messages together
+fn hash_group(msgs: &[&[u8]]) -> Vec<[u8; 32]> {
+    let counts: Vec<usize> = msgs.iter().map(|m| block_count(m.len())).collect();
+    let mut state: [V; 8] = std::array::from_fn(|i| splat(H0[i]));
+    let mut digests = vec![[0u8; 32]; msgs.len()];
+    for index in 0..counts.iter().copied().max().unwrap_or(0) {
+        let words: Vec<[u32; 16]> = (0..LANES)
+            .map(|lane| match msgs.get(lane) {
+                // Lanes past their last block hash filler that is never read
+                Some(msg) if index < counts[lane] => message_block(msg, index),
+                _ => [0; 16],
+            })
+            .collect();
+        let block: [V; 16] = std::array::from_fn(|i| from_lanes(std::array::from_fn(|lane| words[lane][i])));
+        compress(&mut state, &block);
+        for (lane, digest) in digests.iter_mut().enumerate() {
+            if index + 1 == counts[lane] {
+                for (i, word) in state.iter().enumerate() {
+                    digest[4 * i..4 * i + 4].copy_from_slice(&to_lanes(*word)[lane].to_be_bytes());
+                }
+            }
+        }
+    }
+    digests
+}
+
+/// Hex SHA-256 of each message, in input order (same result as `hash_content`)
+pub fn hash_contents(msgs: &[&[u8]]) -> Vec<String> {
+    let _span = timing::span(Stage::Hash);
+    // Similar lengths share a group so lanes rarely idle
+    let mut order: Vec<usize> = (0..msgs.len()).collect();
+    order.sort_by_key(|&i| msgs[i].len());
+    let mut out = vec![String::new(); msgs.len()];
+    for group in order.chunks(LANES) {
+        let batch: Vec<&[u8]> = group.iter().map(|&i| msgs[i]).collect();
+        for (&i, digest) in group.iter().zip(hash_group(&batch)) {
+            out[i] = hex::encode(digest);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::hash_content;
+
+    #[test]
+    fn test_matches_sha256_at_padding_edges() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 31 % 251) as u8).collect();
+        let lens = [0usize, 1, 55, 56, 63, 64, 65, 119, 120, 128, 300, 3, 200];
+        let msgs: Vec<&[u8]> = lens.iter().map(|&len| &data[..len]).collect();
+        let expected: Vec<String> = msgs.iter().map(|m| hash_content(m)).collect();
+        assert_eq!(hash_contents(&msgs), expected);
+        assert!(hash_contents(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_algorithm_is_recorded_in_the_hash() {
+        let sha = HashAlgorithm::Sha256.hash(b"note");
+        let b3 = HashAlgorithm::Blake3.hash(b"note");
+        assert_eq!(HashAlgorithm::of(&sha), HashAlgorithm::Sha256);
+        assert_eq!(HashAlgorithm::of(&b3), HashAlgorithm::Blake3);
+        assert_eq!(hash_digest(&b3).map(|(a, hex)| (a, hex.len())), Some((HashAlgorithm::Blake3, 64)));
+        assert_eq!(hash_digest("blake3:xyz"), None);
+    }
+}
@@ -0,0 +1,189 @@
+//! Hash work offloading
+//!
+//! WASM in Obsidian is single-threaded, but the host can start several Web
+//! Workers running their own instance of this module. For the initial index
+//! the node plans hashing jobs, balanced by bytes across workers; each worker
+//! hashes its slices with `compute_hash_job`, and the results come back to
+//! the node, which checks them against the outstanding jobs and records them
+//! like any other index batch.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::sync::ChangeJournal;
+
+use crate::bootstrap::{BootstrapProgress, IndexEntry};
+use crate::hashing::HashAlgorithm;
+use crate::timing::{self, Stage};
+use crate::P2PNode;
+
+/// A file the host found while scanning the vault
+#[derive(Deserialize, Debug)]
+pub struct FileStat {
+    pub path: String,
+    pub mtime: u64,
+    pub size: u64,
+}
+
+/// Hash `len` bytes at `offset` of the buffer the host hands the worker, with `algorithm`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HashJob {
+    pub id: u64,
+    pub path: String,
+    pub mtime: u64,
+    pub offset: u64,
+    pub len: u64,
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HashResult {
+    pub id: u64,
+    pub hash: String,
+}
+
+#[derive(Default)]
+pub struct HashJobQueue {
+    next_id: u64,
+    outstanding: HashMap<u64, HashJob>,
+}
+
+impl HashJobQueue {
+    /// Plan jobs for files whose size or mtime changed since the journal saw them,
+    /// split into `workers` groups of roughly equal bytes; also returns how many were unchanged
+    pub fn plan(&mut self, files: Vec<FileStat>, workers: usize, journal: &ChangeJournal) -> (Vec<Vec<HashJob>>, usize) {
+        let mut unchanged = 0;
+        let mut jobs = Vec::new();
+        for file in files {
+            let known = journal.get(&file.path);
+            if known.is_some_and(|m| !m.is_deleted && m.size == file.size && m.mtime == file.mtime) {
+                unchanged += 1;
+                continue;
+            }
+            self.next_id += 1;
+            let job = HashJob {
+                id: self.next_id,
+                path: file.path,
+                mtime: file.mtime,
+                offset: 0,
+                len: file.size,
+                algorithm: journal.hash_algorithm(),
+            };
+            self.outstanding.insert(job.id, job.clone());
+            jobs.push(job);
+        }
+
+        // Largest first onto the least loaded worker
+        jobs.sort_by(|a, b| b.len.cmp(&a.len).then(a.id.cmp(&b.id)));
+        let mut groups: Vec<(u64, Vec<HashJob>)> = vec![(0, Vec::new()); workers.max(1)];
+        for job in jobs {
+            let lightest = groups.iter_mut().min_by_key(|(bytes, _)| *bytes).expect("at least one worker");
+            lightest.0 += job.len;
+            lightest.1.push(job);
+        }
+        (groups.into_iter().map(|(_, jobs)| jobs).collect(), unchanged)
+    }
+
+    /// Match results to outstanding jobs; returns index entries and errors for unknown ids
+    pub fn complete(&mut self, results: Vec<HashResult>) -> (Vec<IndexEntry>, Vec<String>) {
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for result in results {
+            match self.outstanding.remove(&result.id) {
+                Some(job) => entries.push(IndexEntry {
+                    path: job.path,
+                    mtime: job.mtime,
+                    hash: Some(result.hash),
+                    size: Some(job.len),
+                    content_b64: None,
+                }),
+                None => errors.push(format!("Unknown hash job {}", result.id)),
+            }
+        }
+        (entries, errors)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.outstanding.clear();
+    }
+}
+
+impl P2PNode {
+    /// Plan hashing for a scan, counting unchanged files toward bootstrap progress
+    pub(crate) fn plan_hash_work(&mut self, files: Vec<FileStat>, workers: usize) -> Vec<Vec<HashJob>> {
+        let _span = timing::span(Stage::Plan);
+        let total = files.len() as u64;
+        let files: Vec<FileStat> = files.into_iter().filter(|f| self.policy.should_sync(&f.path)).collect();
+        let skipped = total - files.len() as u64;
+        let (groups, unchanged) = self.hash_jobs.plan(files, workers, &self.change_journal);
+        let progress = &mut self.bootstrap_progress;
+        progress.processed += skipped + unchanged as u64;
+        progress.skipped += skipped;
+        progress.unchanged += unchanged as u64;
+        groups
+    }
+
+    /// Record worker results as an index batch
+    pub(crate) fn integrate_hash_results(&mut self, results: Vec<HashResult>) -> &BootstrapProgress {
+        let (entries, errors) = self.hash_jobs.complete(results);
+        for error in errors {
+            self.bootstrap_progress.record_failure(error);
+        }
+        self.ingest_index_batch(entries)
+    }
+}
+
+/// Run one job in a worker: hash the job's slice of `data`; returns `{id, hash}` JSON
+pub fn compute_hash_job(job_json: &str, data: &[u8]) -> Result<String, String> {
+    let job: HashJob = serde_json::from_str(job_json).map_err(|e| format!("Invalid hash job: {}", e))?;
+    let slice = usize::try_from(job.offset)
+        .ok()
+        .zip(usize::try_from(job.len).ok())
+        .and_then(|(offset, len)| data.get(offset..offset.checked_add(len)?))
+        .ok_or_else(|| format!("Hash job {} is outside the {}-byte buffer", job.id, data.len()))?;
+    serde_json::to_string(&HashResult { id: job.id, hash: job.algorithm.hash(slice) }).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(path: &str, size: u64) -> FileStat {
+        FileStat { path: path.to_string(), mtime: 1, size }
+    }
+
+    #[test]
+    fn test_plan_balances_bytes_and_skips_unchanged() {
+        let mut journal = ChangeJournal::new();
+        journal.record_update("same.md".to_string(), "h".to_string(), 5, 1, "laptop".to_string());
+
+        let mut queue = HashJobQueue::default();
+        let files = vec![stat("same.md", 5), stat("a", 100), stat("b", 60), stat("c", 50), stat("d", 10)];
+        let (groups, unchanged) = queue.plan(files, 2, &journal);
+        assert_eq!(unchanged, 1);
+        let bytes: Vec<u64> = groups.iter().map(|g| g.iter().map(|j| j.len).sum()).collect();
+        assert_eq!(bytes, vec![110, 110]);
+        assert_eq!(queue.pending(), 4);
+    }
+
+    #[test]
+    fn test_worker_result_round_trip() {
+        let mut queue = HashJobQueue::default();
+        let (groups, _) = queue.plan(vec![stat("a.md", 3)], 4, &ChangeJournal::new());
+        let job = serde_json::to_string(&groups[0][0]).unwrap();
+        assert!(compute_hash_job(&job, b"ab").is_err());
+
+        let result: HashResult = serde_json::from_str(&compute_hash_job(&job, b"abc").unwrap()).unwrap();
+        assert_eq!(result.hash, crate::sync::hash_content(b"abc"));
+        let (entries, errors) = queue.complete(vec![result.clone(), HashResult { id: 99, hash: String::new() }]);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, Some(3));
+        assert_eq!(errors, vec!["Unknown hash job 99"]);
+        assert_eq!(queue.complete(vec![result]).1.len(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Change history export
+//!
+//! Turns the journal's version history into either a commit-log JSON or a
+//! `git fast-import` stream, one commit per recorded version, attributed to
+//! the device that made the change. The journal only stores hashes, so file
+//! bodies come from the host; versions whose content is not supplied are
+//! written as small pointer files naming the hash and size.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::sync::FileMetadata;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryCommit {
+    pub sequence: u64,
+    pub path: String,
+    pub device_id: String,
+    pub timestamp_ms: u64,
+    pub hash: String,
+    pub size: u64,
+    pub deleted: bool,
+    pub message: String,
+}
+
+impl HistoryCommit {
+    fn from_metadata(meta: &FileMetadata) -> HistoryCommit {
+        let verb = if meta.is_deleted { "Delete" } else { "Update" };
+        HistoryCommit {
+            sequence: meta.version,
+            path: meta.path.clone(),
+            device_id: meta.last_modified_by.clone(),
+            timestamp_ms: meta.mtime,
+            hash: meta.hash.clone(),
+            size: meta.size,
+            deleted: meta.is_deleted,
+            message: format!("{} {} (from {})", verb, meta.path, meta.last_modified_by),
+        }
+    }
+}
+
+/// One commit per recorded version, oldest first
+pub fn commit_log(history: impl Iterator<Item = FileMetadata>) -> Vec<HistoryCommit> {
+    history.map(|meta| HistoryCommit::from_metadata(&meta)).collect()
+}
+
+/// Content stand-in for versions the host could not supply
+fn pointer_file(meta: &FileMetadata) -> Vec<u8> {
+    format!("version obsidian-p2p-sync/pointer\noid sha256:{}\nsize {}\n", meta.hash, meta.size).into_bytes()
+}
+
+/// Render history as a `git fast-import` stream on `branch`
+/// `contents` maps content hash to file bytes
+pub fn fast_import_stream(
+    history: impl Iterator<Item = FileMetadata>,
+    contents: &HashMap<String, Vec<u8>>,
+    branch: &str,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (mark, meta) in history.enumerate() {
+        let commit = HistoryCommit::from_metadata(&meta);
+        let device = ident_part(&commit.device_id);
+        let ident = format!("{} <{}@obsidian-p2p-sync> {} +0000", device, device, commit.timestamp_ms / 1000);
+
+        let mut header = String::new();
+        let _ = writeln!(header, "commit refs/heads/{}", branch);
+        let _ = writeln!(header, "mark :{}", mark + 1);
+        let _ = writeln!(header, "author {}", ident);
+        let _ = writeln!(header, "committer {}", ident);
+        let _ = writeln!(header, "data {}", commit.message.len());
+        header.push_str(&commit.message);
+        header.push('\n');
+        if mark > 0 {
+            let _ = writeln!(header, "from :{}", mark);
+        }
+        out.extend_from_slice(header.as_bytes());
+
+        let path = quote_path(&commit.path);
+        if commit.deleted {
+            out.extend_from_slice(format!("D {}\n\n", path).as_bytes());
+        } else {
+            let pointer;
+            let body = match contents.get(&commit.hash) {
+                Some(body) => body,
+                None => {
+                    pointer = pointer_file(&meta);
+                    &pointer
+                }
+            };
+            out.extend_from_slice(format!("M 100644 inline {}\ndata {}\n", path, body.len()).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\n\n");
+        }
+    }
+    out
+}
+
+/// A device ID made safe for an ident line: a peer-chosen ID could otherwise
+/// close the email early or start a new fast-import command
+fn ident_part(device_id: &str) -> String {
+    device_id.replace(['\n', '\r'], "").replace(['<', '>', ' '], "-")
+}
+
+/// Quote a path for fast-import when it contains characters that need it
+fn quote_path(path: &str) -> String {
+    if path.contains(['"', '\\', '\n']) || path.starts_with('"') || path.contains(' ') {
+        let escaped = path.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("\"{}\"", escaped)
+    } else {
+        path.to_string()
+    }
+}
+
+/// Decode a JSON object of `{hash: base64 content}`
+pub fn parse_contents(contents_json: &str) -> Result<HashMap<String, Vec<u8>>, String> {
+    let encoded: HashMap<String, String> = serde_json::from_str(contents_json)
+        .map_err(|e| format!("Invalid contents JSON: {}", e))?;
+    encoded
+        .into_iter()
+        .map(|(hash, b64)| {
+            BASE64
+                .decode(b64)
+                .map(|body| (hash.clone(), body))
+                .map_err(|e| format!("Invalid base64 for {}: {}", hash, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ident_cannot_inject_commands() {
+        let meta = FileMetadata {
+            path: "a.md".to_string(),
+            hash: "h".to_string(),
+            size: 1,
+            mtime: 1_000,
+            version: 1,
+            is_deleted: false,
+            last_modified_by: "evil <x>\nreset refs/heads/main".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        };
+        let stream = String::from_utf8(fast_import_stream(std::iter::once(meta), &HashMap::new(), "main")).unwrap();
+        let ident = "evil--x-reset-refs/heads/main <evil--x-reset-refs/heads/main@obsidian-p2p-sync> 1 +0000";
+        assert!(stream.contains(&format!("\nauthor {}\ncommitter {}\ndata ", ident, ident)));
+    }
+}
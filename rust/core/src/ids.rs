@@ -0,0 +1,130 @@
+//! Sortable identifiers
+//!
+//! Peer, transfer, conflict and session IDs used to be random UUIDv4s, so
+//! sorting logs or an index by ID scattered entries at random. New IDs are
+//! ULIDs: a 48-bit millisecond timestamp from the crate clock followed by 80
+//! random bits, written as 26 Crockford base32 characters. They sort by
+//! creation time as plain strings, and IDs made in the same millisecond by
+//! one node still sort in creation order. IDs persisted before the switch
+//! are UUIDs; they stay valid everywhere and `parse_id` accepts both.
+
+use rand_core::{OsRng, RngCore};
+use std::cell::Cell;
+
+use crate::clock;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_LEN: usize = 26;
+const RANDOM_BITS: u32 = 80;
+const RANDOM_MASK: u128 = (1 << RANDOM_BITS) - 1;
+
+thread_local! {
+    /// Last ULID handed out, so IDs within one millisecond stay ordered
+    static LAST: Cell<u128> = const { Cell::new(0) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdKind {
+    Ulid,
+    /// Any RFC 4122 UUID, as written by older versions
+    Uuid,
+}
+
+fn encode(value: u128) -> String {
+    (0..ULID_LEN).map(|i| CROCKFORD[((value >> (125 - 5 * i)) & 31) as usize] as char).collect()
+}
+
+fn decode(id: &str) -> Option<u128> {
+    if id.len() != ULID_LEN {
+        return None;
+    }
+    let mut value: u128 = 0;
+    for (i, c) in id.bytes().enumerate() {
+        let digit = match c.to_ascii_uppercase() {
+            b'O' => 0,
+            b'I' | b'L' => 1,
+            c => CROCKFORD.iter().position(|&d| d == c)? as u128,
+        };
+        // 26 characters carry 130 bits; the first may only use the low 3
+        if i == 0 && digit > 7 {
+            return None;
+        }
+        value = (value << 5) | digit;
+    }
+    Some(value)
+}
+
+/// A new ULID stamped with the crate clock
+pub fn new_id() -> String {
+    let now = (clock::now_ms() as u128) << RANDOM_BITS;
+    let last = LAST.with(|l| l.get());
+    let value = if now <= last & !RANDOM_MASK {
+        // Same millisecond (or the clock went back): count up from the last ID
+        last + 1
+    } else {
+        let mut random = [0u8; 16];
+        OsRng.fill_bytes(&mut random[6..]);
+        now | u128::from_be_bytes(random)
+    };
+    LAST.with(|l| l.set(value));
+    encode(value)
+}
+
+/// What kind of ID `id` is; anything else is rejected
+pub fn parse_id(id: &str) -> Result<IdKind, String> {
+    if decode(id).is_some() {
+        Ok(IdKind::Ulid)
+    } else if uuid::Uuid::parse_str(id).is_ok() {
+        Ok(IdKind::Uuid)
+    } else {
+        Err(format!("Invalid ID: {}", id))
+    }
+}
+
+/// Creation time of an ID in milliseconds; UUIDs other than v7 don't carry one
+pub fn id_timestamp_ms(id: &str) -> Option<u64> {
+    if let Some(value) = decode(id) {
+        return Some((value >> RANDOM_BITS) as u64);
+    }
+    let uuid = uuid::Uuid::parse_str(id).ok()?;
+    (uuid.get_version_num() == 7).then(|| (uuid.as_u128() >> RANDOM_BITS) as u64)
+}
+
+/// A new sortable ID, for IDs the host creates itself
+pub fn generate_id() -> String {
+    new_id()
+}
+
+/// Creation time of a ULID (or UUIDv7) in milliseconds, or `undefined`
+pub fn get_id_timestamp(id: &str) -> Option<u64> {
+    id_timestamp_ms(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_sort_by_time_and_old_uuids_parse() {
+        clock::set_clock_time(1_700_000_000_000);
+        let first = new_id();
+        let same_ms: Vec<String> = (0..5).map(|_| new_id()).collect();
+        clock::advance_clock(1);
+        let later = new_id();
+        clock::use_system_clock();
+
+        assert_eq!(first.len(), 26);
+        assert!(same_ms.windows(2).all(|w| w[0] < w[1]));
+        assert!(first < same_ms[0] && same_ms[4] < later);
+        assert_eq!(id_timestamp_ms(&first), Some(1_700_000_000_000));
+        assert_eq!(id_timestamp_ms(&later.to_lowercase()), Some(1_700_000_000_001));
+        assert_eq!(parse_id(&later), Ok(IdKind::Ulid));
+
+        let old = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(parse_id(old), Ok(IdKind::Uuid));
+        assert_eq!(id_timestamp_ms(old), None);
+        assert_eq!(id_timestamp_ms("018b8f6e-7c00-7000-8000-000000000000"), Some(0x018b_8f6e_7c00));
+        assert!(parse_id("not an id").is_err());
+        assert!(parse_id("8ZZZZZZZZZZZZZZZZZZZZZZZZZ").is_err());
+    }
+}
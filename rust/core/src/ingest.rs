@@ -0,0 +1,345 @@
+//! Slice-fed file ingestion
+//!
+//! A `FileIngest` session is the one pathway for large files: the host reads
+//! the file in slices of whatever size suits it and pushes them in order,
+//! and the session hashes them incrementally and, when it has a session key,
+//! cuts them into encrypted transfer chunks as soon as each chunk is full. At
+//! most one chunk of plaintext is held, so memory stays bounded however large
+//! the file is. `P2PNode::record_ingest` records the resulting hash;
+//! `TransferManager::prepare_transfer` is the same session fed all at once.
+
+use aes_gcm::Aes256Gcm;
+use zeroize::Zeroizing;
+
+use crate::cancel::{self, CancellationToken};
+use crate::error::Error;
+use crate::crypto::{encrypt_in_place, session_key_from, transfer_cipher, TAG_LEN};
+use crate::hashing::{HashAlgorithm, StreamHasher};
+use crate::padding;
+use crate::timing::{self, Stage};
+use crate::transfer::{check_binding, chunk_aad, write_chunk_json, CHUNK_SIZE};
+use crate::P2PNode;
+
+pub struct FileIngest {
+    file_path: String,
+    path_json: String,
+    size: u64,
+    received: u64,
+    /// One hasher per algorithm the caller needs, consumed by `finish`
+    hashers: Vec<(HashAlgorithm, StreamHasher)>,
+    hashes: Vec<(HashAlgorithm, String)>,
+    session_key: Option<Zeroizing<[u8; 32]>>,
+    /// The transfer's own cipher (see `crypto::transfer_cipher`), derived at the first chunk
+    cipher: Option<Aes256Gcm>,
+    /// Plaintext of the chunk being filled, encrypted in place once full
+    chunk: Vec<u8>,
+    next_chunk: u32,
+    total_chunks: u32,
+    /// Pad every chunk to a bucket and round the chunk count up (see `padding`)
+    padded: bool,
+    /// Bound into every chunk's associated data (see `transfer::chunk_aad`)
+    transfer_id: String,
+    /// Expected hash of the file, bound into the transfer key
+    file_hash: String,
+    finished: bool,
+    cancel_token: Option<CancellationToken>,
+}
+
+impl FileIngest {
+    /// Start ingesting `size` bytes of `file_path`, hashed with `algorithm`
+    /// and, if `session_key` is given, encrypted into transfer chunks
+    pub fn new(file_path: String, size: u64, algorithm: &str, session_key: Option<String>) -> Result<FileIngest, Error> {
+        let algorithm = HashAlgorithm::parse(algorithm)?;
+        let session_key = session_key.map(|key| session_key_from(&key)).transpose()?;
+        FileIngest::create(file_path, size, &[algorithm], session_key)
+    }
+
+    /// Push the next slice; returns a JSON array of the chunks it completed
+    /// (always empty without a session key)
+    pub fn push(&mut self, slice: &[u8]) -> Result<String, Error> {
+        let mut out = vec![b'['];
+        self.push_into(slice, &mut out)?;
+        out.push(b']');
+        String::from_utf8(out).map_err(|e| e.to_string().into())
+    }
+
+    /// End the session once every byte has been pushed; returns the last chunks as a JSON array
+    pub fn finish(&mut self) -> Result<String, Error> {
+        let mut out = vec![b'['];
+        self.finish_into(&mut out)?;
+        out.push(b']');
+        String::from_utf8(out).map_err(|e| e.to_string().into())
+    }
+
+    /// Hash of the whole file with the session's algorithm, once finished
+    pub fn get_hash(&self) -> Option<String> {
+        self.hashes.first().map(|(_, hash)| hash.clone())
+    }
+
+    pub fn get_received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Attach a token checked between chunks
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.cancel_token = Some(token.clone());
+    }
+
+    /// Pad the chunks this session produces; only before the first slice
+    pub fn set_padding(&mut self, enabled: bool) -> Result<(), Error> {
+        if self.received > 0 || self.finished {
+            return Err(format!("Ingest of {} has already started", self.file_path).into());
+        }
+        let chunks = self.size.div_ceil(CHUNK_SIZE as u64) as u32;
+        self.total_chunks = if enabled { padding::padded_chunk_count(chunks) } else { chunks };
+        self.padded = enabled;
+        Ok(())
+    }
+
+    /// Transfer the chunks belong to, for the receiver's `IncomingTransfer`; only before the first slice
+    pub fn set_transfer_id(&mut self, transfer_id: String) -> Result<(), Error> {
+        if self.received > 0 || self.finished {
+            return Err(format!("Ingest of {} has already started", self.file_path).into());
+        }
+        self.transfer_id = transfer_id;
+        Ok(())
+    }
+
+    /// Hash the receiver expects, when the sender knows it up front (e.g. from
+    /// the journal); both sides derive the transfer key from it. Only before the first slice
+    pub fn set_file_hash(&mut self, file_hash: String) -> Result<(), Error> {
+        if self.received > 0 || self.finished {
+            return Err(format!("Ingest of {} has already started", self.file_path).into());
+        }
+        self.file_hash = file_hash;
+        Ok(())
+    }
+
+    /// Number of chunks the session will produce, filler chunks included
+    pub fn get_total_chunks(&self) -> u32 {
+        self.total_chunks
+    }
+
+    /// Session hashing with each of `algorithms` (the first is reported by `get_hash`)
+    pub fn create(
+        file_path: String,
+        size: u64,
+        algorithms: &[HashAlgorithm],
+        session_key: Option<Zeroizing<[u8; 32]>>,
+    ) -> Result<FileIngest, Error> {
+        let total_chunks = u32::try_from(size.div_ceil(CHUNK_SIZE as u64))
+            .map_err(|_| Error::new(format!("File too large to transfer: {} bytes", size)))?;
+        Ok(FileIngest {
+            path_json: serde_json::to_string(&file_path).map_err(|e| e.to_string())?,
+            file_path,
+            size,
+            received: 0,
+            hashers: algorithms.iter().map(|a| (*a, a.stream())).collect(),
+            hashes: Vec::new(),
+            chunk: Vec::with_capacity(if session_key.is_some() { CHUNK_SIZE + TAG_LEN } else { 0 }),
+            session_key,
+            cipher: None,
+            next_chunk: 0,
+            total_chunks,
+            padded: false,
+            transfer_id: String::new(),
+            file_hash: String::new(),
+            finished: false,
+            cancel_token: None,
+        })
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    /// Hash with `algorithm`, if the session computed it and is finished
+    pub fn hash_for(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        self.hashes.iter().find(|(a, _)| *a == algorithm).map(|(_, hash)| hash.as_str())
+    }
+
+    pub fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancel_token = token;
+    }
+
+    /// Push a slice, appending completed chunks to the JSON array being written to `out`
+    pub fn push_into(&mut self, mut slice: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if self.finished {
+            return Err(format!("Ingest of {} is already finished", self.file_path).into());
+        }
+        if self.received + slice.len() as u64 > self.size {
+            return Err(format!("Ingest of {} exceeds its declared {} bytes", self.file_path, self.size).into());
+        }
+        self.received += slice.len() as u64;
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(slice);
+        }
+        if self.session_key.is_none() {
+            return Ok(());
+        }
+        while !slice.is_empty() {
+            let take = (CHUNK_SIZE - self.chunk.len()).min(slice.len());
+            self.chunk.extend_from_slice(&slice[..take]);
+            slice = &slice[take..];
+            if self.chunk.len() == CHUNK_SIZE {
+                self.emit_chunk(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish_into(&mut self, out: &mut Vec<u8>) -> Result<(), Error> {
+        if self.finished {
+            return Err(format!("Ingest of {} is already finished", self.file_path).into());
+        }
+        if self.received != self.size {
+            return Err(format!("Ingest of {} ended at {} of {} bytes", self.file_path, self.received, self.size).into());
+        }
+        if self.padded {
+            while self.next_chunk < self.total_chunks && self.session_key.is_some() {
+                self.emit_chunk(out)?;
+            }
+        } else if !self.chunk.is_empty() {
+            self.emit_chunk(out)?;
+        }
+        self.hashes = self.hashers.drain(..).map(|(a, hasher)| (a, hasher.finish())).collect();
+        self.finished = true;
+        Ok(())
+    }
+
+    fn emit_chunk(&mut self, out: &mut Vec<u8>) -> Result<(), Error> {
+        cancel::check(&self.cancel_token)?;
+        let Some(session_key) = &self.session_key else {
+            return Ok(());
+        };
+        check_binding(&self.transfer_id, &self.file_hash)?;
+        let cipher = self.cipher.get_or_insert_with(|| transfer_cipher(session_key, &self.transfer_id, &self.file_hash));
+        if self.padded {
+            padding::pad(&mut self.chunk);
+        }
+        let nonce = {
+            let _span = timing::span(Stage::Encrypt);
+            let aad = chunk_aad(&self.transfer_id, &self.file_path, self.next_chunk, self.total_chunks);
+            encrypt_in_place(cipher, &aad, &mut self.chunk)?
+        };
+        write_chunk_json(out, &self.path_json, self.next_chunk, self.total_chunks, &self.chunk, &nonce);
+        self.next_chunk += 1;
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl P2PNode {
+    /// Start an ingest of `path` that hashes the way the journal needs, and
+    /// produces transfer chunks too when `session_key` is given
+    pub fn begin_file_ingest(&mut self, path: String, size: u64, session_key: Option<String>) -> Result<FileIngest, String> {
+        self.create_file_ingest(path, size, session_key).map_err(|e| self.record_error(e))
+    }
+
+    /// Record a finished ingest as a local change, like `update_file`
+    pub fn record_ingest(&mut self, ingest: &FileIngest, mtime: u64) -> Result<bool, String> {
+        self.apply_ingest(ingest, mtime).map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn create_file_ingest(&self, path: String, size: u64, session_key: Option<String>) -> Result<FileIngest, String> {
+        let session_key = session_key.map(|key| session_key_from(&key)).transpose()?;
+        let algorithms = self.change_journal.hash_algorithms_for(&path);
+        let mut ingest = FileIngest::create(path, size, &algorithms, session_key)?;
+        ingest.set_cancellation(self.cancel_token.clone());
+        ingest.set_padding(self.padding.enabled)?;
+        Ok(ingest)
+    }
+
+    pub(crate) fn apply_ingest(&mut self, ingest: &FileIngest, mtime: u64) -> Result<bool, String> {
+        if !ingest.is_finished() {
+            return Err(format!("Ingest of {} is not finished", ingest.file_path()));
+        }
+        let hash = self
+            .change_journal
+            .hash_with(ingest.file_path(), |algorithm| ingest.hash_for(algorithm).map(str::to_string))
+            .ok_or_else(|| format!("Ingest of {} was not hashed for this journal", ingest.file_path()))?;
+        Ok(self.record_local_change(ingest.file_path().to_string(), hash, ingest.get_size(), mtime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgorithm;
+
+    const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    fn content() -> Vec<u8> {
+        (0..CHUNK_SIZE * 2 + 77).map(|i| (i % 241) as u8).collect()
+    }
+
+    #[test]
+    fn test_slices_match_one_shot_results() {
+        let content = content();
+        let mut ingest = FileIngest::new("a.md".to_string(), content.len() as u64, "blake3", Some(KEY.to_string())).unwrap();
+        // Chunks are only produced for a named transfer of a known file
+        assert!(ingest.push(&content).is_err());
+        let mut ingest = FileIngest::new("a.md".to_string(), content.len() as u64, "blake3", Some(KEY.to_string())).unwrap();
+        ingest.set_transfer_id("t1".to_string()).unwrap();
+        ingest.set_file_hash("hash-a".to_string()).unwrap();
+        let mut chunks = Vec::new();
+        for slice in content.chunks(10_000) {
+            let completed: Vec<crate::transfer::FileChunk> = serde_json::from_str(&ingest.push(slice).unwrap()).unwrap();
+            chunks.extend(completed);
+        }
+        assert!(ingest.get_hash().is_none());
+        chunks.extend(serde_json::from_str::<Vec<crate::transfer::FileChunk>>(&ingest.finish().unwrap()).unwrap());
+        assert_eq!(ingest.get_hash().unwrap(), HashAlgorithm::Blake3.hash(&content));
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.iter().map(|c| c.chunk_index).collect::<Vec<_>>(), vec![0, 1, 2]);
+        let manager = crate::transfer::TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let mut restored = Vec::new();
+        for chunk in chunks {
+            restored.extend(manager.decrypt_chunk(serde_json::to_string(&chunk).unwrap(), KEY.to_string()).unwrap());
+        }
+        assert_eq!(restored, content);
+    }
+
+    #[test]
+    fn test_size_is_enforced() {
+        let mut ingest = FileIngest::new("a.md".to_string(), 4, "sha256", None).unwrap();
+        assert_eq!(ingest.push(b"abc").unwrap(), "[]");
+        assert!(ingest.push(b"de").is_err());
+        assert!(ingest.finish().is_err());
+        ingest.push(b"d").unwrap();
+        ingest.finish().unwrap();
+        assert_eq!(ingest.get_hash().unwrap(), crate::sync::hash_content(b"abcd"));
+        assert!(ingest.push(b"").is_err());
+    }
+
+    #[test]
+    fn test_node_records_ingest_like_update_file() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("old.md".to_string(), b"same", 1);
+        node.set_hash_algorithm("blake3").unwrap();
+
+        // Unchanged content keeps its SHA-256 hash after the switch
+        let mut ingest = node.create_file_ingest("old.md".to_string(), 4, None).unwrap();
+        assert!(node.apply_ingest(&ingest, 2).is_err());
+        ingest.push(b"same").unwrap();
+        ingest.finish().unwrap();
+        assert!(!node.apply_ingest(&ingest, 2).unwrap());
+
+        let mut ingest = node.create_file_ingest("new.md".to_string(), 3, None).unwrap();
+        ingest.push(b"new").unwrap();
+        ingest.finish().unwrap();
+        assert!(node.apply_ingest(&ingest, 2).unwrap());
+        assert_eq!(node.change_journal.get("new.md").unwrap().hash, HashAlgorithm::Blake3.hash(b"new"));
+    }
+}
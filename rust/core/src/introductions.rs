@@ -0,0 +1,357 @@
+//! Vault membership and introductions
+//!
+//! Pairing a third device used to mean entering a code on it once for every
+//! device already in the vault. Now one pairing is enough: the device that
+//! approved the newcomer calls `introduce_device`, which adds it to its
+//! member list and returns
+//! - a vault certificate for the newcomer, a statement signed by the
+//!   approving device that the newcomer's identity key belongs to the vault,
+//!   which the newcomer presents to members it meets before they have heard
+//!   of it, and
+//! - one signed introduction per other member naming the newcomer, plus one
+//!   for the newcomer naming every member, for the host to deliver over any
+//!   channel (a live session, a mailbox bundle).
+//!
+//! `process_introduction` accepts certificates and introductions alike when
+//! they are signed by a current member and addressed to this device (or to
+//! nobody, for certificates). A message that would give a known member a
+//! different key is refused and recorded as a key change, and a device
+//! removed from the vault can only come back through a message issued
+//! after its removal.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::crypto::{verify_signature, DeviceIdentity};
+use crate::ids;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::security::SecurityEventKind;
+use crate::P2PNode;
+
+const SIGNATURE_DOMAIN: &[u8] = b"obsidian-p2p-sync introduction v1";
+/// Devices one introduction can name
+const MAX_INTRODUCED: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IntroducedDevice {
+    pub device_id: String,
+    /// Base64 identity key
+    pub identity_key: String,
+}
+
+/// The signed part of a certificate or introduction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Introduction {
+    pub id: String,
+    pub introducer: String,
+    /// Base64 identity key of the introducer
+    pub introducer_key: String,
+    /// Device the message is for; empty for a certificate, which any member accepts
+    pub recipient: String,
+    pub devices: Vec<IntroducedDevice>,
+    pub issued_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedIntroduction {
+    introduction: String,
+    signature: String,
+}
+
+fn signed_bytes(body: &str) -> Vec<u8> {
+    [SIGNATURE_DOMAIN, body.as_bytes()].concat()
+}
+
+/// Sign `introduction` with the introducer's identity; returns the base64 message
+pub fn seal(identity: &DeviceIdentity, introduction: &Introduction) -> String {
+    let body = serde_json::to_string(introduction).unwrap_or_default();
+    let signature = identity.sign(&signed_bytes(&body));
+    BASE64.encode(serde_json::to_vec(&SignedIntroduction { introduction: body, signature }).unwrap_or_default())
+}
+
+/// Decode a message and check its signature (not who signed it)
+pub fn open(message: &str) -> Result<Introduction, String> {
+    let malformed = || "Malformed introduction".to_string();
+    let bytes = BASE64.decode(message.trim()).map_err(|_| malformed())?;
+    let signed: SignedIntroduction = serde_json::from_slice(&bytes).map_err(|_| malformed())?;
+    let introduction: Introduction = serde_json::from_str(&signed.introduction).map_err(|_| malformed())?;
+    let key = introduction.introducer_key.clone();
+    if !verify_signature(key, &signed_bytes(&signed.introduction), signed.signature) {
+        return Err(format!("Invalid signature on introduction {}", introduction.id));
+    }
+    Ok(introduction)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub identity_key: String,
+    pub added_at: u64,
+    /// Device whose introduction added it; `None` when paired directly
+    #[serde(default)]
+    pub introduced_by: Option<String>,
+}
+
+/// Paired devices, and when removed ones left
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct VaultMembers {
+    #[serde(default)]
+    members: BTreeMap<String, Member>,
+    /// Device ID → removal time
+    #[serde(default)]
+    removed: BTreeMap<String, u64>,
+}
+
+impl VaultMembers {
+    pub fn get(&self, device_id: &str) -> Option<&Member> {
+        self.members.get(device_id)
+    }
+
+    pub fn is_member(&self, device_id: &str, identity_key: &str) -> bool {
+        self.members.get(device_id).is_some_and(|m| m.identity_key == identity_key)
+    }
+
+    /// Pin a member's new key after the user verified it; non-members stay out
+    pub(crate) fn repin(&mut self, device_id: &str, identity_key: &str) {
+        if let Some(member) = self.members.get_mut(device_id) {
+            member.identity_key = identity_key.to_string();
+        }
+    }
+
+    /// The member `device_id` pinned to another key, if any
+    fn conflict(&self, device: &IntroducedDevice) -> Option<&Member> {
+        self.members.get(&device.device_id).filter(|m| m.identity_key != device.identity_key)
+    }
+
+    /// Add a member; false if it was already one with this key
+    fn add(&mut self, device: &IntroducedDevice, introduced_by: Option<String>, now: u64) -> bool {
+        if self.is_member(&device.device_id, &device.identity_key) {
+            return false;
+        }
+        self.removed.remove(&device.device_id);
+        let member = Member { identity_key: device.identity_key.clone(), added_at: now, introduced_by };
+        self.members.insert(device.device_id.clone(), member);
+        true
+    }
+}
+
+/// What `introduce_device` returns
+#[derive(Serialize, Debug)]
+pub struct Introductions {
+    /// For the newcomer to keep and present to members
+    pub certificate: String,
+    pub messages: Vec<AddressedIntroduction>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AddressedIntroduction {
+    pub to: String,
+    pub message: String,
+}
+
+impl P2PNode {
+    /// Record a device paired directly with this one (e.g. after `check_pairing_attempt`
+    /// accepted its code); false if it was already a member with this key
+    pub fn add_vault_member(&mut self, device_id: &str, public_key: &str) -> Result<bool, String> {
+        let device = IntroducedDevice { device_id: device_id.to_string(), identity_key: public_key.to_string() };
+        if self.vault_members.conflict(&device).is_some() {
+            return Err(self.record_error(format!("{} is already a member with another key", device_id)));
+        }
+        Ok(self.vault_members.add(&device, None, clock::now_ms()))
+    }
+
+    /// Remove a device from the vault; false if it wasn't a member
+    pub fn remove_vault_member(&mut self, device_id: &str) -> bool {
+        let removed = self.vault_members.members.remove(device_id).is_some();
+        if removed {
+            self.vault_members.removed.insert(device_id.to_string(), clock::now_ms());
+        }
+        removed
+    }
+
+    pub fn is_vault_member(&self, device_id: &str, public_key: &str) -> bool {
+        self.vault_members.is_member(device_id, public_key)
+    }
+
+    /// Members as `{device_id: {identity_key, added_at, introduced_by}}`
+    pub fn get_vault_members_json(&self) -> String {
+        serde_json::to_string(&self.vault_members.members).unwrap_or_default()
+    }
+
+    /// Admit a newly paired device and introduce it to the vault; returns
+    /// `{certificate, messages: [{to, message}]}` as JSON
+    pub fn introduce_device(
+        &mut self,
+        identity: &DeviceIdentity,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<String, String> {
+        self.add_vault_member(device_id, public_key)?;
+        let introductions = self.introductions_for(identity, device_id);
+        serde_json::to_string(&introductions).map_err(|e| e.to_string())
+    }
+
+    /// Apply a certificate or introduction from another member; returns the device IDs
+    /// it added as a JSON array
+    pub fn process_introduction(&mut self, message: &str) -> Result<String, String> {
+        let added = self.apply_introduction(message).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&added).map_err(|e| e.to_string())
+    }
+
+    /// Members and removals as JSON, for persisting with `load_vault_members_state`
+    pub fn get_vault_members_state(&self) -> String {
+        serde_json::to_string(&self.vault_members).unwrap_or_default()
+    }
+
+    pub fn load_vault_members_state(&mut self, json: &str) -> Result<(), String> {
+        let result = check_size("Vault members", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str::<VaultMembers>(json).map_err(|e| e.to_string().into()));
+        match result {
+            Ok(members) => {
+                self.vault_members = members;
+                Ok(())
+            }
+            Err(e) => Err(self.record_error(format!("Invalid vault members: {}", e))),
+        }
+    }
+}
+
+impl P2PNode {
+    fn introductions_for(&self, identity: &DeviceIdentity, newcomer: &str) -> Introductions {
+        let introduction = |recipient: &str, devices: Vec<IntroducedDevice>| Introduction {
+            id: ids::new_id(),
+            introducer: identity.get_device_id(),
+            introducer_key: identity.get_public_key(),
+            recipient: recipient.to_string(),
+            devices,
+            issued_at: clock::now_ms(),
+        };
+        let device = |id: &str, member: &Member| IntroducedDevice {
+            device_id: id.to_string(),
+            identity_key: member.identity_key.clone(),
+        };
+        let members = &self.vault_members.members;
+        let newcomer_device = device(newcomer, &members[newcomer]);
+        let others: Vec<(&String, &Member)> = members.iter().filter(|(id, _)| *id != newcomer).collect();
+
+        let mut messages: Vec<AddressedIntroduction> = others
+            .iter()
+            .map(|(id, _)| AddressedIntroduction {
+                to: id.to_string(),
+                message: seal(identity, &introduction(id, vec![newcomer_device.clone()])),
+            })
+            .collect();
+        let mut known: Vec<IntroducedDevice> = others.iter().map(|(id, member)| device(id, member)).collect();
+        known.push(IntroducedDevice { device_id: identity.get_device_id(), identity_key: identity.get_public_key() });
+        let message = seal(identity, &introduction(newcomer, known));
+        messages.push(AddressedIntroduction { to: newcomer.to_string(), message });
+        Introductions { certificate: seal(identity, &introduction("", vec![newcomer_device])), messages }
+    }
+
+    fn apply_introduction(&mut self, message: &str) -> Result<Vec<String>, String> {
+        let introduction = open(message).inspect_err(|e| {
+            if e.starts_with("Invalid signature") {
+                let context = [("frame", "introduction")];
+                self.security_log.record(SecurityEventKind::SignatureInvalid, "", e.clone(), &context);
+            }
+        })?;
+        if !self.vault_members.is_member(&introduction.introducer, &introduction.introducer_key) {
+            let (id, introducer) = (&introduction.id, &introduction.introducer);
+            return Err(format!("Introduction {} is from {}, which is not a vault member", id, introducer));
+        }
+        if !introduction.recipient.is_empty() && introduction.recipient != self.device_id {
+            return Err(format!("Introduction {} is for {}", introduction.id, introduction.recipient));
+        }
+        if introduction.devices.len() > MAX_INTRODUCED {
+            return Err(format!("Introduction {} names too many devices", introduction.id));
+        }
+        for device in &introduction.devices {
+            if self.vault_members.conflict(device).is_some() {
+                let message = format!("{} introduced {} with a different key", introduction.introducer, device.device_id);
+                let context = [("frame", "introduction")];
+                self.security_log.record(SecurityEventKind::KeyChanged, &device.device_id, message.clone(), &context);
+                return Err(message);
+            }
+        }
+        let now = clock::now_ms();
+        let mut added = Vec::new();
+        for device in introduction.devices.iter().filter(|d| d.device_id != self.device_id) {
+            // A replayed introduction must not undo a removal
+            if self.vault_members.removed.get(&device.device_id).is_some_and(|at| introduction.issued_at <= *at) {
+                continue;
+            }
+            if self.vault_members.add(device, Some(introduction.introducer.clone()), now) {
+                added.push(device.device_id.clone());
+            }
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Device {
+        node: P2PNode,
+        identity: DeviceIdentity,
+    }
+
+    fn device(id: &str) -> Device {
+        let node = P2PNode::new(id.to_string(), id.to_string(), 8080);
+        Device { node, identity: DeviceIdentity::new(id.to_string()).unwrap() }
+    }
+
+    fn pair(a: &mut Device, b: &mut Device) {
+        a.node.add_vault_member(&b.identity.get_device_id(), &b.identity.get_public_key()).unwrap();
+        b.node.add_vault_member(&a.identity.get_device_id(), &a.identity.get_public_key()).unwrap();
+    }
+
+    #[test]
+    fn test_one_pairing_introduces_the_whole_vault() {
+        let (mut laptop, mut tablet) = (device("laptop"), device("tablet"));
+        let (mut desktop, mut phone) = (device("desktop"), device("phone"));
+        pair(&mut laptop, &mut tablet);
+        pair(&mut laptop, &mut desktop);
+        phone.node.add_vault_member("laptop", &laptop.identity.get_public_key()).unwrap();
+
+        let json = laptop.node.introduce_device(&laptop.identity, "phone", &phone.identity.get_public_key()).unwrap();
+        let result: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let message_for = |to: &str| {
+            let messages = result["messages"].as_array().unwrap();
+            messages.iter().find(|m| m["to"] == to).unwrap()["message"].as_str().unwrap().to_string()
+        };
+        assert_eq!(tablet.node.process_introduction(&message_for("tablet")).unwrap(), r#"["phone"]"#);
+        assert_eq!(phone.node.process_introduction(&message_for("phone")).unwrap(), r#"["desktop","tablet"]"#);
+        assert!(phone.node.is_vault_member("tablet", &tablet.identity.get_public_key()));
+        assert!(desktop.node.process_introduction(&message_for("tablet")).is_err());
+
+        // The newcomer's certificate works where the introduction hasn't arrived
+        let certificate = result["certificate"].as_str().unwrap();
+        assert_eq!(desktop.node.process_introduction(certificate).unwrap(), r#"["phone"]"#);
+        assert_eq!(desktop.node.process_introduction(certificate).unwrap(), "[]");
+
+        // Only members introduce, and only with the keys members already have
+        let stranger = device("stranger");
+        let mut forged = open(certificate).unwrap();
+        forged.introducer_key = stranger.identity.get_public_key();
+        assert!(tablet.node.process_introduction(&seal(&stranger.identity, &forged)).is_err());
+        let mut tampered: SignedIntroduction = serde_json::from_slice(&BASE64.decode(certificate).unwrap()).unwrap();
+        tampered.introduction = tampered.introduction.replace("phone", "phon3");
+        let tampered = BASE64.encode(serde_json::to_vec(&tampered).unwrap());
+        assert!(tablet.node.process_introduction(&tampered).is_err());
+        let mut impostor = open(certificate).unwrap();
+        impostor.devices[0].identity_key = stranger.identity.get_public_key();
+        assert!(desktop.node.process_introduction(&seal(&laptop.identity, &impostor)).is_err());
+        let kinds: Vec<SecurityEventKind> = desktop.node.security_log.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![SecurityEventKind::KeyChanged]);
+
+        // A removed device isn't brought back by an old certificate
+        assert!(desktop.node.remove_vault_member("phone"));
+        assert_eq!(desktop.node.process_introduction(certificate).unwrap(), "[]");
+        let saved = desktop.node.get_vault_members_state();
+        desktop.node.load_vault_members_state(&saved).unwrap();
+        assert!(desktop.node.get_vault_members_json().contains("laptop"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Bounded log of non-fatal problems
+//!
+//! Things the node recovers from on its own (ignored announcements, skewed
+//! clocks) are still worth telling the user about. They are kept here until
+//! the host retrieves and clears them; the oldest entries are dropped first.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::clock;
+use crate::memory::{deque_bytes, string_bytes, string_map_bytes, MemoryFootprint};
+
+/// Default number of issues retained
+pub const DEFAULT_ISSUE_CAPACITY: usize = 200;
+
+// Issue codes
+pub const MALFORMED_ANNOUNCEMENT: &str = "malformed_announcement";
+pub const OVERSIZE_INPUT: &str = "oversize_input";
+pub const CLOCK_SKEW: &str = "clock_skew";
+pub const CORRUPT_FILE: &str = "corrupt_file";
+pub const FILE_REPAIRED: &str = "file_repaired";
+pub const REPAIR_FAILED: &str = "repair_failed";
+pub const KEY_CHANGED: &str = "key_changed";
+pub const KEY_ACCEPTED: &str = "key_accepted";
+pub const MERGE_DRIVER_FAILED: &str = "merge_driver_failed";
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub context: BTreeMap<String, String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug)]
+pub struct IssueLog {
+    entries: VecDeque<Issue>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl IssueLog {
+    pub fn new(capacity: usize) -> IssueLog {
+        IssueLog { entries: VecDeque::new(), capacity: capacity.max(1), dropped: 0 }
+    }
+
+    /// Record an issue with `(key, value)` context pairs
+    pub fn push(&mut self, severity: Severity, code: &'static str, message: String, context: &[(&str, &str)]) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(Issue {
+            severity,
+            code,
+            message,
+            context: context.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            timestamp: clock::now_ms(),
+        });
+    }
+
+    pub fn warn(&mut self, code: &'static str, message: String, context: &[(&str, &str)]) {
+        self.push(Severity::Warning, code, message, context);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of issues evicted because the log was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Issue> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.entries).unwrap_or_default()
+    }
+}
+
+impl Default for IssueLog {
+    fn default() -> Self {
+        IssueLog::new(DEFAULT_ISSUE_CAPACITY)
+    }
+}
+
+impl MemoryFootprint for IssueLog {
+    fn heap_bytes(&self) -> usize {
+        deque_bytes(&self.entries, |e| string_bytes(&e.message) + string_map_bytes(&e.context))
+    }
+
+    fn trim(&mut self) {
+        self.entries.shrink_to_fit();
+    }
+}
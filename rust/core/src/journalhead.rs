@@ -0,0 +1,233 @@
+//! Journal heads in announcements and heartbeats
+//!
+//! Whether a sync round with a peer is worth starting can be told without
+//! connecting: every announcement, and every `Ping`/`Pong` heartbeat on an
+//! open link, carries the sender's journal head, i.e. its sequence and the
+//! first bytes of a digest over its live files. The digest is the XOR of the
+//! content digests the file table keeps per shard (see `shards`), so it
+//! costs nothing to compute however often the host announces, and it covers
+//! paths and hashes only: versions are numbered by each device, so two
+//! devices holding the same files advertise the same digest.
+//!
+//! Equal digests mean nothing to sync. Sequences are per device too, so a
+//! peer's is only compared with the last of its sequences we reconciled, by
+//! a first sync or a committed sync round: past it, the peer has changes we
+//! haven't seen and is `ahead` ("Laptop is ahead of you"); otherwise the
+//! difference is ours and it is `behind`. Both sides may still hold changes
+//! the other lacks; the head is a hint for the UI and the scheduler, and the
+//! sync round finds out exactly. Peers running older versions advertise no
+//! head and compare as `unknown`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sync::ChangeJournal;
+
+use crate::wire;
+use crate::{DiscoveredPeer, P2PNode};
+
+/// Digest bytes advertised
+pub const HEAD_DIGEST_BYTES: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JournalHead {
+    pub sequence: u64,
+    /// Hex prefix of the journal digest
+    pub digest: String,
+}
+
+impl JournalHead {
+    pub fn of(journal: &ChangeJournal) -> JournalHead {
+        let mut digest = [0u8; 32];
+        for (_, stats) in journal.shards() {
+            digest.iter_mut().zip(&stats.content).for_each(|(a, b)| *a ^= b);
+        }
+        JournalHead { sequence: journal.sequence(), digest: hex::encode(&digest[..HEAD_DIGEST_BYTES]) }
+    }
+
+    /// The head carried in a frame's `journal_sequence` and `journal_digest`; `None` when
+    /// the sender advertised none
+    pub fn from_wire(sequence: u64, digest: &[u8]) -> Option<JournalHead> {
+        (digest.len() == HEAD_DIGEST_BYTES).then(|| JournalHead { sequence, digest: hex::encode(digest) })
+    }
+
+    pub fn digest_bytes(&self) -> Vec<u8> {
+        hex::decode(&self.digest).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadComparison {
+    InSync,
+    /// The peer has changes we haven't reconciled
+    Ahead,
+    /// Only we have changes the peer lacks
+    Behind,
+    /// The peer advertised no head
+    Unknown,
+}
+
+impl HeadComparison {
+    /// Compare with `remote`, given the peer's last sequence we reconciled
+    pub fn between(local: &JournalHead, remote: Option<&JournalHead>, reconciled: Option<u64>) -> HeadComparison {
+        match remote {
+            None => HeadComparison::Unknown,
+            Some(remote) if remote.digest == local.digest => HeadComparison::InSync,
+            Some(remote) if remote.sequence > reconciled.unwrap_or(0) => HeadComparison::Ahead,
+            Some(_) => HeadComparison::Behind,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PeerHead<'a> {
+    peer_id: &'a str,
+    device_id: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    head: Option<&'a JournalHead>,
+    status: HeadComparison,
+}
+
+impl P2PNode {
+    /// This device's journal head as `{sequence, digest}`
+    pub fn get_journal_head_json(&self) -> String {
+        serde_json::to_string(&JournalHead::of(&self.change_journal)).unwrap_or_default()
+    }
+
+    /// How a discovered peer's journal compares to ours: `in_sync`, `ahead`, `behind`,
+    /// or `unknown` for an unknown peer or one that advertised no head
+    pub fn compare_peer_journal_head(&self, peer_id: &str) -> String {
+        let local = JournalHead::of(&self.change_journal);
+        let status = match self.peers.get(peer_id) {
+            Some(peer) => self.compare_head(&local, peer),
+            None => HeadComparison::Unknown,
+        };
+        serde_json::to_value(status).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+    }
+
+    /// Every discovered peer as `[{peer_id, device_id, name, head?, status}]`
+    pub fn get_peer_journal_heads_json(&self) -> String {
+        let local = JournalHead::of(&self.change_journal);
+        let mut heads: Vec<PeerHead> = self
+            .peers
+            .values()
+            .map(|peer| PeerHead {
+                peer_id: &peer.id,
+                device_id: &peer.device_id,
+                name: &peer.name,
+                head: peer.journal_head.as_ref(),
+                status: self.compare_head(&local, peer),
+            })
+            .collect();
+        heads.sort_by(|a, b| a.peer_id.cmp(b.peer_id));
+        serde_json::to_string(&heads).unwrap_or_default()
+    }
+
+    /// A `Ping` control frame carrying our journal head, for a link's keepalive
+    pub fn get_heartbeat_frame(&self) -> Vec<u8> {
+        self.heartbeat_frame(wire::ControlKind::Ping)
+    }
+
+    /// Record the journal head in a peer's `Ping` or `Pong`; returns the `Pong` to send
+    /// back for a `Ping`, or nothing
+    pub fn process_heartbeat_frame(&mut self, peer_id: &str, frame: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.apply_heartbeat_frame(peer_id, frame, crate::clock::now_ms()).map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    /// Highest journal sequence a discovered peer with `device_id` advertised
+    pub(crate) fn advertised_sequence(&self, device_id: &str) -> Option<u64> {
+        self.peers
+            .values()
+            .filter(|peer| peer.device_id == device_id)
+            .filter_map(|peer| peer.journal_head.as_ref().map(|head| head.sequence))
+            .max()
+    }
+
+    fn compare_head(&self, local: &JournalHead, peer: &DiscoveredPeer) -> HeadComparison {
+        HeadComparison::between(local, peer.journal_head.as_ref(), self.first_sync.watermark(&peer.device_id))
+    }
+
+    fn heartbeat_frame(&self, kind: wire::ControlKind) -> Vec<u8> {
+        let head = JournalHead::of(&self.change_journal);
+        wire::encode_frame(&wire::Envelope::new(wire::Body::Control(wire::Control {
+            kind: kind as i32,
+            reason: String::new(),
+            journal_sequence: head.sequence,
+            journal_digest: head.digest_bytes(),
+        })))
+    }
+
+    fn apply_heartbeat_frame(&mut self, peer_id: &str, frame: &[u8], now: u64) -> Result<Option<Vec<u8>>, String> {
+        let Some((envelope, _)) = wire::decode_frame(frame)? else {
+            return Err("Incomplete heartbeat frame".to_string());
+        };
+        let Some(wire::Body::Control(control)) = envelope.body else {
+            return Err("Expected a control frame".to_string());
+        };
+        let kind = wire::ControlKind::try_from(control.kind).unwrap_or(wire::ControlKind::Unspecified);
+        if !matches!(kind, wire::ControlKind::Ping | wire::ControlKind::Pong) {
+            return Err(format!("Expected a heartbeat, got {:?}", kind));
+        }
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.last_seen_timestamp = now;
+            if let Some(head) = JournalHead::from_wire(control.journal_sequence, &control.journal_digest) {
+                peer.journal_head = Some(head);
+            }
+        }
+        Ok((kind == wire::ControlKind::Ping).then(|| self.heartbeat_frame(wire::ControlKind::Pong)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heads_compare_before_connecting() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        laptop.update_file("a.md".to_string(), b"note", 1);
+        laptop.update_file("b.md".to_string(), b"other", 2);
+        phone.process_announcement_frame(&laptop.get_announcement_frame(), "10.0.0.2", 5).unwrap();
+        let laptop_id = laptop.get_peer_id();
+        assert_eq!(phone.compare_peer_journal_head(&laptop_id), "ahead");
+        assert_eq!(phone.compare_peer_journal_head("nobody"), "unknown");
+
+        // The same entries in another order give the same head
+        for meta in laptop.change_journal.files().collect::<Vec<_>>().into_iter().rev() {
+            phone.apply_remote_change(&serde_json::to_string(&meta).unwrap(), "laptop").unwrap();
+        }
+        assert_eq!(phone.get_journal_head_json(), laptop.get_journal_head_json());
+        assert_eq!(phone.compare_peer_journal_head(&laptop_id), "in_sync");
+
+        // Never reconciled, a peer with changes is ahead
+        phone.update_file("c.md".to_string(), b"phone", 3);
+        laptop.apply_announcement(&phone.get_announcement_json(), "10.0.0.3", 6).unwrap();
+        let phone_id = phone.get_peer_id();
+        assert_eq!(laptop.compare_peer_journal_head(&phone_id), "ahead");
+
+        // After a round, only the phone's own change is left: the laptop is behind
+        let files: Vec<_> = laptop.change_journal.files().collect();
+        phone.prepare_round(&serde_json::to_string(&files).unwrap(), "laptop").unwrap();
+        phone.commit_round().unwrap();
+        assert_eq!(phone.compare_peer_journal_head(&laptop_id), "behind");
+
+        // Heartbeats keep the head current; a Ping is answered with our own head
+        laptop.update_file("d.md".to_string(), b"laptop", 4);
+        let pong = phone.process_heartbeat_frame(&laptop_id, &laptop.get_heartbeat_frame()).unwrap().unwrap();
+        assert!(laptop.process_heartbeat_frame(&phone_id, &pong).unwrap().is_none());
+        let heads: serde_json::Value = serde_json::from_str(&phone.get_peer_journal_heads_json()).unwrap();
+        assert_eq!((heads[0]["name"].as_str(), heads[0]["status"].as_str()), (Some("Laptop"), Some("ahead")));
+        assert_eq!(heads[0]["head"]["sequence"], 3);
+
+        let close = wire::encode_frame(&wire::Envelope::new(wire::Body::Control(wire::Control {
+            kind: wire::ControlKind::Close as i32,
+            ..Default::default()
+        })));
+        assert!(phone.process_heartbeat_frame(&laptop_id, &close).is_err());
+        assert_eq!(JournalHead::from_wire(3, &[1, 2]), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Past versions of every file, for history export and audits
+//!
+//! Versions are stored the way `filetable` stores current entries: path and
+//! device are interned, the hash is held as raw bytes, so a version is a fixed
+//! size record plus its path the first time that path appears. The history is
+//! capped both by count (`MAX_HISTORY_ENTRIES`) and by bytes
+//! (`MAX_HISTORY_BYTES`), dropping the oldest versions first. Renames and
+//! extended metadata are not kept.
+//!
+//! The history is not part of the journal blob saved after every change; hosts
+//! that want it across restarts save it on its own (`P2PNode::get_history_state`).
+//! Journals saved with their history inline still load it.
+
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem::size_of;
+
+use crate::filetable::{pack_hash, unpack_hash, HashKind, Interner};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::memory::MemoryFootprint;
+use crate::sync::{ExtendedMetadata, FileMetadata};
+use crate::P2PNode;
+
+/// Number of past versions kept for history export
+pub const MAX_HISTORY_ENTRIES: usize = 10_000;
+/// Bytes the history may take, counting each version as its record plus its path
+pub const MAX_HISTORY_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+struct Version {
+    hash: [u8; 32],
+    mtime: u64,
+    size: u64,
+    version: u64,
+    path: u32,
+    device: u32,
+    hash_kind: HashKind,
+    is_deleted: bool,
+}
+
+/// Recorded versions, oldest first
+#[derive(Default)]
+pub struct JournalHistory {
+    paths: Interner,
+    devices: Interner,
+    other_hashes: Interner,
+    versions: VecDeque<Version>,
+    /// Sum of `cost` over `versions`
+    bytes: usize,
+}
+
+fn cost(path: &str) -> usize {
+    size_of::<Version>() + path.len()
+}
+
+impl JournalHistory {
+    pub fn new() -> JournalHistory {
+        JournalHistory::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Bytes counted against `MAX_HISTORY_BYTES`
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn metadata(&self, version: &Version) -> FileMetadata {
+        FileMetadata {
+            path: self.paths.name(version.path).to_string(),
+            hash: unpack_hash(version.hash_kind, &version.hash, &self.other_hashes),
+            mtime: version.mtime,
+            size: version.size,
+            version: version.version,
+            is_deleted: version.is_deleted,
+            last_modified_by: self.devices.name(version.device).to_string(),
+            moved_from: None,
+            extended: ExtendedMetadata::default(),
+        }
+    }
+
+    fn pack(&mut self, meta: &FileMetadata) -> Version {
+        let (hash_kind, hash) = pack_hash(&meta.hash, &mut self.other_hashes);
+        Version {
+            hash,
+            hash_kind,
+            mtime: meta.mtime,
+            size: meta.size,
+            version: meta.version,
+            path: self.paths.intern(&meta.path),
+            device: self.devices.intern(&meta.last_modified_by),
+            is_deleted: meta.is_deleted,
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = FileMetadata> + '_ {
+        self.versions.iter().map(|v| self.metadata(v))
+    }
+
+    /// Append a version, returning those dropped to stay under the caps, oldest first
+    pub fn push(&mut self, meta: &FileMetadata) -> Vec<FileMetadata> {
+        let mut dropped = Vec::new();
+        let added = cost(&meta.path);
+        while !self.versions.is_empty()
+            && (self.versions.len() >= MAX_HISTORY_ENTRIES || self.bytes + added > MAX_HISTORY_BYTES)
+        {
+            dropped.extend(self.pop_front());
+        }
+        let version = self.pack(meta);
+        self.versions.push_back(version);
+        self.bytes += added;
+        // Paths no version refers to any more pile up as old files leave the history
+        if self.paths.len() > 2 * self.versions.len() + 64 {
+            self.compact();
+        }
+        dropped
+    }
+
+    pub fn pop_front(&mut self) -> Option<FileMetadata> {
+        let version = self.versions.pop_front()?;
+        self.bytes -= cost(self.paths.name(version.path));
+        Some(self.metadata(&version))
+    }
+
+    pub fn pop_back(&mut self) -> Option<FileMetadata> {
+        let version = self.versions.pop_back()?;
+        self.bytes -= cost(self.paths.name(version.path));
+        Some(self.metadata(&version))
+    }
+
+    /// Put back a version taken with `pop_front`; not capped
+    pub fn push_front(&mut self, meta: &FileMetadata) {
+        let version = self.pack(meta);
+        self.versions.push_front(version);
+        self.bytes += cost(&meta.path);
+    }
+
+    /// Rebuild the interners from the versions still held
+    pub fn compact(&mut self) {
+        let versions: Vec<FileMetadata> = self.iter().collect();
+        let mut compacted = JournalHistory::new();
+        for meta in &versions {
+            let version = compacted.pack(meta);
+            compacted.versions.push_back(version);
+        }
+        compacted.bytes = self.bytes;
+        *self = compacted;
+    }
+}
+
+impl MemoryFootprint for JournalHistory {
+    fn heap_bytes(&self) -> usize {
+        self.versions.capacity() * size_of::<Version>()
+            + self.paths.heap_bytes()
+            + self.devices.heap_bytes()
+            + self.other_hashes.heap_bytes()
+    }
+
+    fn trim(&mut self) {
+        self.compact();
+        self.versions.shrink_to_fit();
+    }
+}
+
+// Persisted as a list of `FileMetadata`, as the history was before it was compacted
+impl Serialize for JournalHistory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.versions.len()))?;
+        for meta in self.iter() {
+            seq.serialize_element(&meta)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for JournalHistory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HistoryVisitor;
+
+        impl<'de> Visitor<'de> for HistoryVisitor {
+            type Value = JournalHistory;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of file versions")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JournalHistory, A::Error> {
+                let mut history = JournalHistory::new();
+                while let Some(meta) = seq.next_element::<FileMetadata>()? {
+                    history.push(&meta);
+                }
+                Ok(history)
+            }
+        }
+
+        deserializer.deserialize_seq(HistoryVisitor)
+    }
+}
+
+impl P2PNode {
+    /// The journal's version history as JSON, for persisting with `load_history_state`
+    /// apart from the journal itself
+    pub fn get_history_state(&self) -> String {
+        serde_json::to_string(self.change_journal.history_log()).unwrap_or_default()
+    }
+
+    /// Restore a history saved with `get_history_state`; load the journal first,
+    /// as loading a journal replaces its history
+    pub fn load_history_state(&mut self, json: &str) -> Result<(), String> {
+        let result = check_size("History state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .and_then(|history| self.change_journal.set_history(history));
+        result.map_err(|e| self.record_error(format!("Failed to load history: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::FileMetadata;
+
+    fn version(path: &str, version: u64) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: crate::sync::hash_content(path.as_bytes()),
+            mtime: version,
+            size: 1,
+            version,
+            is_deleted: false,
+            last_modified_by: "laptop".to_string(),
+            moved_from: None,
+            extended: ExtendedMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_history_is_capped_by_count_and_bytes() {
+        let mut history = JournalHistory::new();
+        for i in 0..MAX_HISTORY_ENTRIES as u64 + 5 {
+            history.push(&version("a.md", i + 1));
+        }
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history.iter().next().unwrap().version, 6);
+
+        let mut history = JournalHistory::new();
+        let long = "x/".repeat(2000);
+        let mut dropped = 0;
+        for i in 0..1000u64 {
+            dropped += history.push(&version(&format!("{}{}.md", long, i), i + 1)).len();
+        }
+        assert!(history.bytes() <= MAX_HISTORY_BYTES);
+        assert!(dropped > 0 && history.len() + dropped == 1000);
+        assert_eq!(history.iter().next_back().unwrap().version, 1000);
+        // Paths of dropped versions are compacted away rather than kept forever
+        assert!(history.paths.len() <= 2 * history.len() + 64);
+    }
+
+    #[test]
+    fn test_aborted_transaction_restores_dropped_versions() {
+        let mut journal = crate::sync::ChangeJournal::new();
+        for i in 0..MAX_HISTORY_ENTRIES {
+            journal.update_file(format!("{}.md", i % 7), &i.to_be_bytes(), i as u64, "laptop".to_string());
+        }
+        let before: Vec<FileMetadata> = journal.history().collect();
+        journal.begin_transaction().unwrap();
+        for i in 0..3u8 {
+            journal.update_file("new.md".to_string(), &[i], 1, "phone".to_string());
+        }
+        assert_eq!(journal.history().next().unwrap().version, 4);
+        journal.abort_transaction().unwrap();
+        assert_eq!(journal.history().collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    fn test_history_round_trips_and_stays_out_of_the_journal() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"one", 1);
+        node.update_file("a.md".to_string(), b"two", 2);
+        node.mark_file_deleted("a.md".to_string(), 3);
+        let expected: Vec<FileMetadata> = node.change_journal.history().collect();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(expected[1].hash, crate::sync::hash_content(b"two"));
+
+        let journal = node.get_journal_state();
+        assert!(!journal.contains("\"history\""));
+        let history = node.get_history_state();
+
+        let mut restored = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        restored.load_journal_state(&journal).unwrap();
+        assert_eq!(restored.change_journal.history().count(), 0);
+        restored.load_history_state(&history).unwrap();
+        assert_eq!(restored.change_journal.history().collect::<Vec<_>>(), expected);
+
+        // A journal saved with its history inline keeps it
+        let mut legacy: serde_json::Value = serde_json::from_str(&journal).unwrap();
+        legacy["history"] = serde_json::from_str(&history).unwrap();
+        let legacy = crate::sync::ChangeJournal::from_json(&legacy.to_string()).unwrap();
+        assert_eq!(legacy.history().collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,2159 @@
+//! Sync logic with plain Rust types and no JS bindings
+//!
+//! The change journal, device keys and encryption, chunked transfers, the
+//! wire format, the sync round state machines and `P2PNode`, which ties them
+//! together, live here. Lower-level modules return `error::Error`; the node
+//! returns its errors as messages. The WASM crate re-exports these modules
+//! and wraps them for JavaScript; native tools and tests can depend on this
+//! crate directly.
+
+pub mod access;
+pub mod archive;
+pub mod attachments;
+pub mod audit;
+pub mod backend;
+pub mod bindiff;
+pub mod bootstrap;
+pub mod caches;
+pub mod cancel;
+pub mod checkpoints;
+pub mod clock;
+pub mod commands;
+pub mod compat;
+pub mod compression;
+pub mod conditions;
+pub mod config;
+pub mod conflicts;
+pub mod congestion;
+pub mod crypto;
+pub mod debugring;
+pub mod devices;
+pub mod diff;
+pub mod error;
+pub mod export;
+pub mod extmeta;
+pub mod filetable;
+pub mod firstsync;
+pub mod hashing;
+pub mod hashjobs;
+pub mod history;
+pub mod ids;
+pub mod ingest;
+pub mod introductions;
+pub mod issues;
+pub mod journalhead;
+pub mod journalhistory;
+pub mod limits;
+pub mod links;
+pub mod livesync;
+pub mod loader;
+pub mod loopback;
+pub mod mailbox;
+pub mod memory;
+pub mod merge;
+pub mod mergedrivers;
+pub mod mnemonic;
+pub mod negotiation;
+pub mod objectstore;
+pub mod orphans;
+pub mod padding;
+pub mod pairing;
+pub mod peerstats;
+pub mod pins;
+pub mod placeholders;
+pub mod policy;
+pub mod prefetch;
+pub mod preview;
+pub mod profiles;
+pub mod quarantine;
+pub mod query;
+pub mod relaypair;
+pub mod repair;
+pub mod reports;
+pub mod resume;
+pub mod retention;
+pub mod round;
+pub mod schedule;
+pub mod security;
+pub mod shards;
+pub mod shutdown;
+pub mod sim;
+pub mod sketch;
+pub mod status;
+pub mod streams;
+pub mod sync;
+pub mod timing;
+pub mod trace;
+pub mod transfer;
+pub mod transports;
+pub mod trickle;
+pub mod usage;
+pub mod vaultevents;
+pub mod wal;
+pub mod webdav;
+pub mod wire;
+
+pub use error::Error;
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+use attachments::{AttachmentLayout, AttachmentPolicy};
+use audit::{AuditLog, AuditOrigin};
+use bootstrap::BootstrapProgress;
+use cancel::CancellationToken;
+use conflicts::{ConflictQueue, Resolution, ResolutionOutcome};
+use debugring::DebugCategory;
+use issues::IssueLog;
+use memory::{map_overhead, string_bytes, MemoryFootprint, MemoryStats};
+use policy::{AppendRule, DebounceRule, PolicyRule, SyncAction, SyncPolicy};
+use profiles::SyncProfile;
+use status::NodeStatus;
+use sync::ChangeJournal;
+use trace::{Direction, TraceMessage};
+
+// ============================================================================
+// P2P Discovery Structures
+// ============================================================================
+
+#[derive(Serialize, Deserialize)]
+struct PeerAnnouncement {
+    #[serde(default = "default_announcement_type")]
+    #[serde(rename = "type")]
+    msg_type: String,
+    peer_id: String,
+    device_name: String,
+    device_id: String,
+    #[serde(default)]
+    service_port: u16,
+    #[serde(default)]
+    profile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    journal_head: Option<journalhead::JournalHead>,
+}
+
+/// What a peer's handshake told us; `journal.identical` means the manifest exchange can be skipped
+#[derive(Serialize)]
+struct HandshakeSummary {
+    device_id: String,
+    journal: sketch::SketchComparison,
+}
+
+/// Everything `get_metrics_json` reports
+#[derive(Serialize)]
+struct Metrics {
+    memory: MemoryStats,
+    timings: std::collections::BTreeMap<timing::Stage, timing::StageTiming>,
+}
+
+fn default_announcement_type() -> String {
+    "announcement".to_string()
+}
+
+/// Decode a discovery datagram
+/// Returns `None` for other message types, or the issue code and message on failure
+fn parse_announcement(json: &str) -> Result<Option<PeerAnnouncement>, (&'static str, String)> {
+    limits::check_size("Announcement", json, limits::MAX_ANNOUNCEMENT_BYTES)
+        .map_err(|e| (issues::OVERSIZE_INPUT, e.into()))?;
+
+    // Parse as generic JSON first to check type
+    let v: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| (issues::MALFORMED_ANNOUNCEMENT, format!("Failed to parse JSON: {}", e)))?;
+
+    // Enforce "type": "announcement"
+    match v.get("type") {
+        // Not an announcement (e.g. pairing request), ignore silently
+        Some(msg_type) if msg_type.as_str() != Some("announcement") => return Ok(None),
+        Some(_) => {}
+        // Missing type field - invalid protocol message
+        None => {
+            return Err((issues::MALFORMED_ANNOUNCEMENT, "Announcement is missing its type".to_string()))
+        }
+    }
+
+    serde_json::from_value(v)
+        .map(Some)
+        .map_err(|e| (issues::MALFORMED_ANNOUNCEMENT, format!("Failed to parse announcement: {}", e)))
+}
+
+/// Discovered peer information
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiscoveredPeer {
+    id: String,
+    name: String,
+    device_id: String,
+    last_seen_timestamp: u64,
+    address: String, // IP address
+    service_port: u16,
+    #[serde(default)]
+    profile: String, // Advertised sync profile name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    journal_head: Option<journalhead::JournalHead>,
+}
+
+impl DiscoveredPeer {
+    pub fn new(
+        id: String,
+        name: String,
+        device_id: String,
+        last_seen_timestamp: u64,
+        address: String,
+        service_port: u16,
+    ) -> DiscoveredPeer {
+        DiscoveredPeer {
+            id,
+            name,
+            device_id,
+            last_seen_timestamp,
+            address,
+            service_port,
+            profile: String::new(),
+            journal_head: None,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_device_id(&self) -> String {
+        self.device_id.clone()
+    }
+
+    pub fn get_last_seen_timestamp(&self) -> u64 {
+        self.last_seen_timestamp
+    }
+
+    pub fn get_address(&self) -> String {
+        self.address.clone()
+    }
+
+    pub fn get_service_port(&self) -> u16 {
+        self.service_port
+    }
+
+    pub fn get_profile(&self) -> String {
+        self.profile.clone()
+    }
+}
+
+impl DiscoveredPeer {
+    fn heap_bytes(&self) -> usize {
+        string_bytes(&self.id)
+            + string_bytes(&self.name)
+            + string_bytes(&self.device_id)
+            + string_bytes(&self.address)
+            + string_bytes(&self.profile)
+    }
+}
+
+/// Tolerated difference between a file's mtime and the local clock
+const MAX_CLOCK_SKEW_MS: u64 = 5 * 60 * 1000;
+
+// ============================================================================
+// P2P Node with Discovery
+// ============================================================================
+
+/// Main P2P Node structure
+pub struct P2PNode {
+    peer_id: String,
+    device_name: String,
+    device_id: String,
+    service_port: u16,
+    peers: HashMap<String, DiscoveredPeer>,
+    is_discovering: bool,
+    change_journal: ChangeJournal,
+    cancel_token: Option<crate::cancel::CancellationToken>,
+    last_error: Option<String>,
+    issues: IssueLog,
+    policy: SyncPolicy,
+    attachment_policy: AttachmentPolicy,
+    profile: SyncProfile,
+    bootstrap_progress: BootstrapProgress,
+    conflicts: ConflictQueue,
+    audit: AuditLog,
+    journal_loader: Option<loader::JournalLoader>,
+    exporter: Option<export::Exporter>,
+    sync_round: Option<round::SyncRound>,
+    /// Audit log as it was when the open transaction began
+    audit_checkpoint: Option<AuditLog>,
+    /// Journal sequence covered by WAL records already handed to the host
+    wal_sequence: u64,
+    hash_jobs: hashjobs::HashJobQueue,
+    scheduler: schedule::SyncScheduler,
+    conditions: conditions::DeviceConditions,
+    orphan_scan: Option<orphans::OrphanScan>,
+    pairing: pairing::PairingGuard,
+    padding: padding::PaddingConfig,
+    cover_traffic: padding::CoverTraffic,
+    security_log: security::SecurityLog,
+    /// Capabilities each device advertised in its last verified handshake
+    peer_capabilities: HashMap<String, u32>,
+    checkpoints: checkpoints::CheckpointStore,
+    access: access::AccessControl,
+    resumable: resume::ResumableTransfers,
+    bandwidth: congestion::BandwidthScheduler,
+    prefetch: prefetch::PrefetchState,
+    trickle: trickle::TrickleState,
+    devices: devices::DeviceDirectory,
+    peer_history: peerstats::PeerHistory,
+    round_reports: reports::RoundReports,
+    first_sync: firstsync::BootstrapState,
+    vault_events: vaultevents::VaultEventAdapter,
+    metadata_rules: extmeta::MetadataRules,
+    placeholders: placeholders::PlaceholderState,
+    pins: pins::PinSets,
+    repairs: repair::RepairQueue,
+    transports: transports::TransportRegistry,
+    vault_members: introductions::VaultMembers,
+    quarantine: quarantine::Quarantine,
+    merge_drivers: mergedrivers::MergeDrivers,
+    caches: caches::Caches,
+    /// Interior mutability so `&self` getters can record outbound messages
+    trace: RefCell<trace::TraceRecorder>,
+    /// Always on, unlike `trace`; recorded from `&self` getters too
+    debug_ring: RefCell<debugring::DebugRing>,
+}
+
+impl P2PNode {
+    /// Create a new P2P node with device configuration
+    pub fn new(device_name: String, device_id: String, service_port: u16) -> P2PNode {
+        let peer_id = ids::new_id();
+        // Use provided device_id
+
+        P2PNode {
+            peer_id: peer_id.clone(),
+            device_name,
+            device_id,
+            service_port,
+            peers: HashMap::new(),
+            is_discovering: false,
+            change_journal: ChangeJournal::new(),
+            cancel_token: None,
+            last_error: None,
+            issues: IssueLog::default(),
+            policy: SyncPolicy::new(),
+            attachment_policy: AttachmentPolicy::default(),
+            profile: SyncProfile::full(),
+            bootstrap_progress: BootstrapProgress::default(),
+            conflicts: ConflictQueue::new(),
+            audit: AuditLog::default(),
+            journal_loader: None,
+            exporter: None,
+            sync_round: None,
+            audit_checkpoint: None,
+            wal_sequence: 0,
+            hash_jobs: hashjobs::HashJobQueue::default(),
+            scheduler: schedule::SyncScheduler::default(),
+            conditions: conditions::DeviceConditions::default(),
+            orphan_scan: None,
+            pairing: pairing::PairingGuard::default(),
+            padding: padding::PaddingConfig::default(),
+            cover_traffic: padding::CoverTraffic::default(),
+            security_log: security::SecurityLog::default(),
+            peer_capabilities: HashMap::new(),
+            checkpoints: checkpoints::CheckpointStore::default(),
+            access: access::AccessControl::default(),
+            resumable: resume::ResumableTransfers::default(),
+            bandwidth: congestion::BandwidthScheduler::default(),
+            prefetch: prefetch::PrefetchState::default(),
+            trickle: trickle::TrickleState::default(),
+            devices: devices::DeviceDirectory::default(),
+            peer_history: peerstats::PeerHistory::default(),
+            round_reports: reports::RoundReports::default(),
+            first_sync: firstsync::BootstrapState::default(),
+            vault_events: vaultevents::VaultEventAdapter::default(),
+            metadata_rules: extmeta::MetadataRules::default(),
+            placeholders: placeholders::PlaceholderState::default(),
+            pins: pins::PinSets::default(),
+            repairs: repair::RepairQueue::default(),
+            transports: transports::TransportRegistry::default(),
+            vault_members: introductions::VaultMembers::default(),
+            quarantine: quarantine::Quarantine::default(),
+            merge_drivers: mergedrivers::MergeDrivers::default(),
+            caches: caches::Caches::default(),
+            trace: RefCell::new(trace::TraceRecorder::default()),
+            debug_ring: RefCell::new(debugring::DebugRing::default()),
+        }
+    }
+
+    /// Get the peer ID of this node
+    pub fn get_peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+
+    /// Get the device name
+    pub fn get_device_name(&self) -> String {
+        self.device_name.clone()
+    }
+
+    /// Get the device ID
+    pub fn get_device_id(&self) -> String {
+        self.device_id.clone()
+    }
+
+    /// Start peer discovery
+    pub fn start_discovery(&mut self) -> Result<(), String> {
+        if self.is_discovering {
+            return Err("Discovery already in progress".to_string());
+        }
+
+        self.is_discovering = true;
+        self.debug_event(DebugCategory::State, "discovery_started", "", None);
+        Ok(())
+    }
+
+    /// Stop peer discovery
+    pub fn stop_discovery(&mut self) -> Result<(), String> {
+        self.is_discovering = false;
+        self.debug_event(DebugCategory::State, "discovery_stopped", "", None);
+        Ok(())
+    }
+
+    /// Add a discovered peer (manual/legacy)
+    pub fn add_discovered_peer(&mut self, peer: &DiscoveredPeer) -> Result<(), String> {
+        self.peers.insert(peer.id.clone(), peer.clone());
+        Ok(())
+    }
+
+    /// Process an incoming discovery announcement
+    /// Returns true if this is a new peer or an update to an existing one
+    pub fn process_announcement(&mut self, json: &str, sender_ip: &str, current_time: u64) -> Result<bool, String> {
+        self.apply_announcement(json, sender_ip, current_time)
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Generate an announcement message for this node
+    pub fn get_announcement_json(&self) -> String {
+        let announcement = PeerAnnouncement {
+            msg_type: "announcement".to_string(),
+            peer_id: self.peer_id.clone(),
+            device_name: self.device_name.clone(),
+            device_id: self.device_id.clone(),
+            service_port: self.service_port,
+            profile: self.profile.name.clone(),
+            journal_head: Some(journalhead::JournalHead::of(&self.change_journal)),
+        };
+        let json = serde_json::to_string(&announcement).unwrap_or_default();
+        self.debug_event(DebugCategory::Sent, "announcement", "", Some(json.len() as u64));
+        let message = TraceMessage::AnnouncementJson { json: json.clone(), sender_ip: String::new() };
+        self.trace_event(clock::now_ms(), Direction::Outbound, message);
+        json
+    }
+
+    /// Generate a binary announcement frame for this node
+    pub fn get_announcement_frame(&self) -> Vec<u8> {
+        let head = journalhead::JournalHead::of(&self.change_journal);
+        let frame = wire::encode_frame(&wire::Envelope::new(wire::Body::Announcement(wire::Announcement {
+            peer_id: self.peer_id.clone(),
+            device_name: self.device_name.clone(),
+            device_id: self.device_id.clone(),
+            service_port: self.service_port as u32,
+            profile: self.profile.name.clone(),
+            journal_sequence: head.sequence,
+            journal_digest: head.digest_bytes(),
+        })));
+        let message = TraceMessage::AnnouncementFrame { frame: trace::redact_frame(&frame), sender_ip: String::new() };
+        self.trace_event(clock::now_ms(), Direction::Outbound, message);
+        self.debug_event(DebugCategory::Sent, "announcement", "", Some(frame.len() as u64));
+        frame
+    }
+
+    /// Process a binary discovery frame; other message types are ignored
+    pub fn process_announcement_frame(&mut self, frame: &[u8], sender_ip: &str, current_time: u64) -> Result<bool, String> {
+        self.apply_announcement_frame(frame, sender_ip, current_time)
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Generate a handshake frame carrying our journal sketch, signed over the whole transcript
+    pub fn get_handshake_frame(&self, identity: &crypto::DeviceIdentity, session: &crypto::KeyExchange) -> Vec<u8> {
+        let mut handshake = wire::Handshake {
+            device_id: identity.get_device_id(),
+            identity_key: BASE64.decode(identity.get_public_key()).unwrap_or_default(),
+            signature: Vec::new(),
+            session_key: session.public_key_bytes().to_vec(),
+            capabilities: wire::LOCAL_CAPABILITIES,
+            journal_sketch: self.change_journal.sketch().to_bytes(),
+        };
+        negotiation::sign(identity, &mut handshake);
+        let frame = wire::encode_frame(&wire::Envelope::new(wire::Body::Handshake(handshake)));
+        self.trace_event(clock::now_ms(), Direction::Outbound, TraceMessage::HandshakeFrame { frame: trace::redact_frame(&frame) });
+        self.debug_event(DebugCategory::Sent, "handshake", "", Some(frame.len() as u64));
+        frame
+    }
+
+    /// Verify a peer's handshake frame and compare journal sketches; returns a summary as JSON
+    pub fn process_handshake_frame(&mut self, frame: &[u8]) -> Result<String, String> {
+        self.apply_handshake_frame(frame)
+            .and_then(|summary| serde_json::to_string(&summary).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Prune peers that haven't been seen for `ttl_ms`
+    pub fn prune_peers(&mut self, current_time: u64, ttl_ms: u64) -> Result<usize, String> {
+        Ok(self.prune_stale_peers(current_time, ttl_ms))
+    }
+
+    /// Get list of discovered peers as JSON
+    pub fn get_discovered_peers_json(&self) -> String {
+        let peers_vec: Vec<&DiscoveredPeer> = self.peers.values().collect();
+        serde_json::to_string(&peers_vec).unwrap_or_default()
+    }
+
+    /// Remove a discovered peer by ID
+    pub fn remove_peer(&mut self, peer_id: &str) -> Result<(), String> {
+        self.peers.remove(peer_id);
+        Ok(())
+    }
+
+    /// Clear all discovered peers
+    pub fn clear_peers(&mut self) -> Result<(), String> {
+        self.peers.clear();
+        Ok(())
+    }
+
+    /// Get the number of discovered peers
+    pub fn get_peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Replace user policy rules from a JSON array of `{pattern, action}`
+    /// (`action` is `lww`, `merge` or `skip`); user rules take precedence over defaults
+    pub fn set_policy_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        let rules: Vec<PolicyRule> = serde_json::from_str(rules_json)
+            .map_err(|e| self.record_error(format!("Invalid policy rules: {}", e)))?;
+        self.policy.set_user_rules(rules);
+        Ok(())
+    }
+
+    /// Configure append-merged paths from a JSON array of `{pattern, separator}`
+    pub fn set_append_merge_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        let rules: Vec<AppendRule> = serde_json::from_str(rules_json)
+            .map_err(|e| self.record_error(format!("Invalid append rules: {}", e)))?;
+        self.policy.set_append_rules(rules);
+        Ok(())
+    }
+
+    /// Switch to a built-in profile (`full`, `mobile-lite`)
+    pub fn select_profile(&mut self, name: &str) -> Result<(), String> {
+        match SyncProfile::builtin(name) {
+            Some(profile) => {
+                self.apply_profile(profile);
+                Ok(())
+            }
+            None => Err(self.record_error(format!("Unknown profile: {}", name))),
+        }
+    }
+
+    /// Use a custom profile given as JSON
+    pub fn set_custom_profile(&mut self, profile_json: &str) -> Result<(), String> {
+        let profile: SyncProfile = serde_json::from_str(profile_json)
+            .map_err(|e| self.record_error(format!("Invalid profile: {}", e)))?;
+        self.apply_profile(profile);
+        Ok(())
+    }
+
+    /// All non-secret configuration as a versioned JSON document
+    pub fn export_config(&self) -> String {
+        self.config().to_json()
+    }
+
+    /// Replace configuration from an `export_config()` document
+    /// Nothing is changed if the document is invalid
+    pub fn import_config(&mut self, blob: &str) -> Result<(), String> {
+        let config = config::NodeConfig::parse(blob).map_err(|e| self.record_error(e))?;
+        self.apply_config(config);
+        Ok(())
+    }
+
+    /// Active profile as JSON
+    pub fn get_profile_json(&self) -> String {
+        serde_json::to_string(&self.profile).unwrap_or_default()
+    }
+
+    /// Built-in profiles as JSON
+    pub fn list_builtin_profiles_json(&self) -> String {
+        serde_json::to_string(&SyncProfile::builtins()).unwrap_or_default()
+    }
+
+    /// Set the attachment layout from Obsidian's `attachmentFolderPath` setting
+    pub fn set_attachment_folder(&mut self, attachment_folder_path: &str) {
+        self.attachment_policy.layout = AttachmentLayout::from_obsidian_setting(attachment_folder_path);
+    }
+
+    /// Set this device's attachment rules from JSON
+    /// (`{unmetered_only, max_size_bytes, transfer_last}`)
+    pub fn set_attachment_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        let rules = serde_json::from_str(rules_json)
+            .map_err(|e| self.record_error(format!("Invalid attachment rules: {}", e)))?;
+        self.attachment_policy.rules = rules;
+        Ok(())
+    }
+
+    /// Whether a path is an attachment under the configured layout
+    pub fn is_attachment(&self, path: &str) -> bool {
+        self.attachment_policy.is_attachment(path)
+    }
+
+    /// Order a JSON array of `{path, size}` for transfer under the attachment rules
+    /// and the reported conditions (`metered` adds to what `set_network_class` says);
+    /// attachments past the profile's storage budget are deferred; pinned files go first
+    /// Returns `{transfer, deferred, skipped}` path lists as JSON
+    pub fn plan_transfer_order(&mut self, files_json: &str, metered: bool) -> Result<String, String> {
+        let files: Vec<attachments::PendingFile> = serde_json::from_str(files_json)
+            .map_err(|e| self.record_error(format!("Invalid file list: {}", e)))?;
+        let _span = timing::span(timing::Stage::Plan);
+        let (wanted, unwanted): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|f| self.wants_locally(&f.path, f.size));
+        let metered = metered || self.conditions.is_metered();
+        let mut order = attachments::order_transfers(&self.attachment_policy, &wanted, metered);
+        order.skipped.extend(unwanted.into_iter().map(|f| f.path));
+        if let Some(budget) = self.profile.storage_budget_bytes {
+            let sizes = wanted.into_iter().map(|f| (f.path, f.size)).collect();
+            let used = usage::local_bytes(&self.change_journal, &self.profile);
+            usage::apply_budget(&mut order, &sizes, &self.attachment_policy, used, budget);
+        }
+        if self.conditions.is_offline() {
+            order.deferred.append(&mut order.transfer);
+        } else if self.conditions.bulk_paused(&self.profile) {
+            let (attachments, notes) = order.transfer.into_iter().partition(|p| self.attachment_policy.is_attachment(p));
+            order.transfer = notes;
+            order.deferred.extend::<Vec<String>>(attachments);
+        }
+        if !self.conditions.is_offline() {
+            pins::prioritize(&mut order, |path| self.is_pinned(path));
+        }
+        self.debug_event(DebugCategory::Decision, "transfer_planned", "", Some(order.transfer.len() as u64));
+        if !order.deferred.is_empty() {
+            self.debug_event(DebugCategory::Decision, "transfer_deferred", "", Some(order.deferred.len() as u64));
+        }
+        serde_json::to_string(&order).map_err(|e| e.to_string())
+    }
+
+    /// Dry run of a sync against a peer's manifest (a JSON array of file metadata):
+    /// what would be pulled, overwritten, deleted, conflicted or pushed, with
+    /// byte totals, as JSON. Nothing is changed
+    pub fn plan_sync_preview(&self, remote_json: &str) -> Result<String, String> {
+        let remote = limits::check_size("Manifest", remote_json, limits::MAX_JOURNAL_BYTES)
+            .map_err(String::from)
+            .and_then(|_| serde_json::from_str(remote_json).map_err(|e| format!("Invalid remote manifest: {}", e)))?;
+        let preview =
+            preview::plan_preview(&self.change_journal, &self.policy, remote, &self.cancel_token).map_err(String::from)?;
+        serde_json::to_string(&preview).map_err(|e| e.to_string())
+    }
+
+    /// Enable or disable the built-in `.obsidian/` rules
+    pub fn set_config_defaults_enabled(&mut self, enabled: bool) {
+        self.policy.set_use_config_defaults(enabled);
+    }
+
+    /// Effective policy rules in evaluation order as JSON
+    pub fn get_policy_rules_json(&self) -> String {
+        serde_json::to_string(&self.policy.effective_rules()).unwrap_or_default()
+    }
+
+    /// Policy action (`lww`, `merge` or `skip`) that applies to a path
+    pub fn get_path_policy(&self, path: &str) -> String {
+        serde_json::to_value(self.policy.action_for(path))
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    /// Merge two versions of a file whose policy is `merge`, or that a merge driver
+    /// (`register_merge_driver`) takes
+    /// `options_json` is strategy-specific (for JSON: `{arrays, prefer}`, for other
+    /// text merged line by line against `base`: `{prefer}`) and may be empty.
+    /// Returns `{strategy, merged, conflicts}` as JSON
+    pub fn merge_file(
+        &mut self,
+        path: &str,
+        base: Option<String>,
+        local: &str,
+        remote: &str,
+        options_json: &str,
+    ) -> Result<String, String> {
+        let result = self.merge_file_contents(path, base.as_deref(), local, remote, options_json);
+        let result = result.map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&result).map_err(|e| e.to_string())
+    }
+
+    /// Queue a conflict between the local journal entry and a remote version
+    /// `remote_json` is the peer's file metadata; the texts, when given, feed the
+    /// diff summary. Returns the conflict id
+    pub fn add_conflict(
+        &mut self,
+        remote_json: &str,
+        local_text: Option<String>,
+        remote_text: Option<String>,
+    ) -> Result<String, String> {
+        let remote = serde_json::from_str(remote_json)
+            .map_err(|e| self.record_error(format!("Invalid remote metadata: {}", e)))?;
+        let texts = local_text.as_deref().zip(remote_text.as_deref());
+        self.queue_conflict(remote, texts).map_err(|e| self.record_error(e))
+    }
+
+    /// Unresolved conflicts as a JSON array of
+    /// `{id, path, local, remote, diff, suggestion, created_at, deferred_until}`
+    pub fn list_conflicts_json(&self, include_deferred: bool) -> String {
+        serde_json::to_string(&self.conflicts.list(clock::now_ms(), include_deferred)).unwrap_or_default()
+    }
+
+    /// Number of unresolved conflicts, deferred ones included
+    pub fn get_conflict_count(&self) -> usize {
+        self.conflicts.len()
+    }
+
+    /// Settle a conflict with `{action: keep_local|keep_remote|keep_both|use_merged, content?}`
+    /// Returns `{path, fetch_remote_to, remote_hash, write_content}` describing the disk changes
+    pub fn resolve_conflict(&mut self, id: &str, resolution_json: &str) -> Result<String, String> {
+        let resolution = serde_json::from_str(resolution_json)
+            .map_err(|e| self.record_error(format!("Invalid resolution: {}", e)))?;
+        let outcome = self.apply_resolution(id, resolution).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&outcome).map_err(|e| e.to_string())
+    }
+
+    /// Diff of a queued conflict's two texts with `context` lines around each change,
+    /// as `{unified, hunks: [{old_start, old_lines, new_start, new_lines, lines, words}]}`
+    pub fn get_conflict_diff(&mut self, id: &str, local_text: &str, remote_text: &str, context: usize) -> Result<String, String> {
+        let diff = match self.conflicts.get(id) {
+            Some(conflict) => conflicts::ConflictDiff::between(conflict, local_text, remote_text, context),
+            None => return Err(self.record_error(format!("Unknown conflict: {}", id))),
+        };
+        serde_json::to_string(&diff).map_err(|e| e.to_string())
+    }
+
+    /// Hide a conflict from the active list until `until_ms`
+    pub fn defer_conflict(&mut self, id: &str, until_ms: u64) -> Result<(), String> {
+        self.conflicts.defer(id, until_ms).map_err(|e| self.record_error(e))
+    }
+
+    /// Export the conflict queue for persistence
+    pub fn get_conflict_state(&self) -> String {
+        serde_json::to_string(&self.conflicts).unwrap_or_default()
+    }
+
+    /// Restore a persisted conflict queue
+    pub fn load_conflict_state(&mut self, json: &str) -> Result<(), String> {
+        let queue = limits::check_size("Conflict queue", json, limits::MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load conflicts: {}", e)))?;
+        self.conflicts = queue;
+        Ok(())
+    }
+
+    /// Recorded warnings as a JSON array of `{severity, code, message, context, timestamp}`
+    pub fn get_issues_json(&self) -> String {
+        self.issues.to_json()
+    }
+
+    /// Number of recorded warnings
+    pub fn get_issue_count(&self) -> usize {
+        self.issues.len()
+    }
+
+    /// Forget all recorded warnings
+    pub fn clear_issues(&mut self) {
+        self.issues.clear();
+    }
+
+    /// Drop the secrets and trust state the node holds (the active pairing code,
+    /// attempt counters, verified handshake keys and negotiated sessions, peers'
+    /// advertised capabilities, transfer resumption secrets) when the user logs out
+    /// of sync. Keys live in the host's `DeviceIdentity` / `KeyExchange` objects,
+    /// which wipe their memory when freed
+    pub fn wipe_secrets(&mut self) {
+        self.pairing = pairing::PairingGuard::default();
+        self.access.unbind_all();
+        self.sync_round = None;
+        self.peer_capabilities.clear();
+        self.resumable = resume::ResumableTransfers::default();
+    }
+
+    /// Structured status (peer counts, journal head, last error) for UI binding
+    pub fn get_status(&self) -> NodeStatus {
+        self.build_status()
+    }
+
+    /// Node status
+    pub fn status(&self) -> String {
+        format!(
+            "P2P Node [{}] on {}: {} peers discovered, discovering={}",
+            self.device_name, self.device_id, self.peers.len(), self.is_discovering
+        )
+    }
+
+    /// Update file in change journal
+    /// Paths whose policy is `skip` are never recorded
+    pub fn update_file(&mut self, path: String, content: &[u8], mtime: u64) -> bool {
+        if !self.policy.should_sync(&path) {
+            return false;
+        }
+        let hash = self.change_journal.content_hash(&path, content);
+        self.record_local_change(path, hash, content.len() as u64, mtime)
+    }
+
+    /// Record debounced changes whose quiet interval has elapsed
+    /// Returns the number of journal entries written
+    pub fn flush_debounced(&mut self, current_time: u64) -> usize {
+        let sequence = self.change_journal.sequence();
+        let committed = self.change_journal.flush_pending(current_time);
+        self.audit_since(sequence, AuditOrigin::Local);
+        committed
+    }
+
+    /// Record all debounced changes immediately (e.g. before saving state)
+    pub fn flush_all_debounced(&mut self) -> usize {
+        let sequence = self.change_journal.sequence();
+        let committed = self.change_journal.flush_all_pending();
+        self.audit_since(sequence, AuditOrigin::Local);
+        committed
+    }
+
+    /// Number of changes waiting on a debounce interval
+    pub fn get_debounced_count(&self) -> usize {
+        self.change_journal.pending_count()
+    }
+
+    /// Replace user debounce rules from a JSON array of `{pattern, interval_ms}`
+    /// (`interval_ms: 0` disables debouncing for matching paths)
+    pub fn set_debounce_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        let rules: Vec<DebounceRule> = serde_json::from_str(rules_json)
+            .map_err(|e| self.record_error(format!("Invalid debounce rules: {}", e)))?;
+        self.policy.set_debounce_rules(rules);
+        Ok(())
+    }
+
+    /// Effective debounce rules in evaluation order as JSON
+    pub fn get_debounce_rules_json(&self) -> String {
+        serde_json::to_string(&self.policy.effective_debounce_rules()).unwrap_or_default()
+    }
+
+    /// Mark file as deleted in change journal
+    pub fn mark_file_deleted(&mut self, path: String, mtime: u64) -> bool {
+        self.trace_event(clock::now_ms(), Direction::Inbound, TraceMessage::LocalDelete { path: path.clone(), mtime });
+        if !self.policy.should_sync(&path) {
+            return false;
+        }
+        let sequence = self.change_journal.sequence();
+        let changed = self.change_journal.mark_deleted(path, mtime, self.device_id.clone());
+        self.audit_since(sequence, AuditOrigin::Local);
+        changed
+    }
+
+    /// Record a rename of a synced file as one move, so peers move their copy instead of
+    /// downloading it again; a move out of the synced paths is recorded as a delete
+    pub fn rename_file(&mut self, old_path: String, new_path: String, mtime: u64) -> bool {
+        let message = TraceMessage::LocalRename { old_path: old_path.clone(), path: new_path.clone(), mtime };
+        self.trace_event(clock::now_ms(), Direction::Inbound, message);
+        if !self.policy.should_sync(&old_path) {
+            return false;
+        }
+        if !self.policy.should_sync(&new_path) {
+            return self.mark_file_deleted(old_path, mtime);
+        }
+        let sequence = self.change_journal.sequence();
+        let moved = self.change_journal.record_move(&old_path, new_path, mtime, self.device_id.clone());
+        self.audit_since(sequence, AuditOrigin::Local);
+        moved
+    }
+
+    /// Apply a change pulled from a peer (`remote_json` is the peer's file metadata)
+    /// Returns false if the journal already had this version or the path is skipped
+    pub fn apply_remote_change(&mut self, remote_json: &str, from_peer_id: &str) -> Result<bool, String> {
+        self.check_not_quarantined(from_peer_id).map_err(|e| self.record_error(e))?;
+        let remote = serde_json::from_str(remote_json)
+            .map_err(|e| self.record_error(format!("Invalid remote metadata: {}", e)))?;
+        Ok(self.apply_remote(remote, from_peer_id))
+    }
+
+    /// Audit log entries for `path` (all paths when empty) as a JSON array, oldest first
+    pub fn get_audit_log_json(&self, path: &str) -> String {
+        let entries: Vec<&audit::AuditEntry> = self.audit.entries_for(path).collect();
+        serde_json::to_string(&entries).unwrap_or_default()
+    }
+
+    pub fn get_audit_count(&self) -> usize {
+        self.audit.len()
+    }
+
+    pub fn clear_audit_log(&mut self) {
+        self.audit.clear();
+    }
+
+    /// Export the audit log for persistence
+    pub fn get_audit_state(&self) -> String {
+        serde_json::to_string(&self.audit).unwrap_or_default()
+    }
+
+    /// Restore a persisted audit log
+    pub fn load_audit_state(&mut self, json: &str) -> Result<(), String> {
+        let log = limits::check_size("Audit log", json, limits::MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load audit log: {}", e)))?;
+        self.audit = log;
+        Ok(())
+    }
+
+    /// Get all files metadata
+    pub fn get_all_files(&self) -> String {
+        let _span = timing::span(timing::Stage::Serialize);
+        let all: Vec<sync::FileMetadata> = self.change_journal.files().collect();
+        serde_json::to_string(&all).unwrap_or_default()
+    }
+
+    /// Export change journal state as JSON
+    pub fn get_journal_state(&self) -> String {
+        let _span = timing::span(timing::Stage::Serialize);
+        self.change_journal.to_json()
+    }
+
+    /// Import change journal state from JSON
+    pub fn load_journal_state(&mut self, json: &str) -> Result<(), String> {
+        match ChangeJournal::from_json(json) {
+            Ok(journal) => {
+                self.change_journal = journal;
+                self.wal_sequence = self.change_journal.sequence();
+                Ok(())
+            }
+            Err(e) => Err(self.record_error(format!("Failed to load journal: {}", e))),
+        }
+    }
+
+    /// Start loading persisted journal JSON in segments; clears the current journal
+    /// Entries are queryable as soon as their segment arrives; hold back local
+    /// changes until the load completes
+    pub fn begin_journal_load(&mut self) {
+        self.change_journal = ChangeJournal::new();
+        self.journal_loader = Some(loader::JournalLoader::new());
+    }
+
+    /// Feed the next segment of the journal blob; returns true once it is fully loaded
+    pub fn push_journal_segment(&mut self, segment: &str) -> Result<bool, String> {
+        self.apply_journal_segment(segment).map_err(|e| self.record_error(e))
+    }
+
+    /// Progress of the current load as `{bytes_received, files_loaded, complete}`
+    pub fn get_journal_load_progress(&self) -> String {
+        let progress = self.journal_loader.as_ref().map(|l| l.progress().clone()).unwrap_or_default();
+        serde_json::to_string(&progress).unwrap_or_default()
+    }
+
+    /// Start a segmented export of `"journal"` (as `get_journal_state`) or `"files"` (as `get_all_files`)
+    pub fn begin_export(&mut self, kind: &str) -> Result<(), String> {
+        let kind = export::ExportKind::parse(kind).map_err(|e| self.record_error(e))?;
+        self.exporter = Some(export::Exporter::new(kind, &self.change_journal));
+        Ok(())
+    }
+
+    /// Next segment of the current export, or `undefined` once it is complete
+    pub fn next_segment(&mut self) -> Result<Option<String>, String> {
+        self.next_export_segment().map_err(|e| self.record_error(e))
+    }
+
+    /// Hash new content with `"sha256"` or `"blake3"`; recorded hashes stay valid
+    pub fn set_hash_algorithm(&mut self, algorithm: &str) -> Result<(), String> {
+        let algorithm = hashing::HashAlgorithm::parse(algorithm).map_err(|e| self.record_error(e))?;
+        self.change_journal.set_hash_algorithm(algorithm);
+        Ok(())
+    }
+
+    pub fn get_hash_algorithm(&self) -> String {
+        self.change_journal.hash_algorithm().as_str().to_string()
+    }
+
+    /// Export change journal state as CBOR
+    pub fn get_journal_state_cbor(&self) -> Vec<u8> {
+        let _span = timing::span(timing::Stage::Serialize);
+        self.change_journal.to_cbor()
+    }
+
+    /// Import change journal state from CBOR
+    pub fn load_journal_state_cbor(&mut self, data: &[u8]) -> Result<(), String> {
+        match ChangeJournal::from_cbor(data) {
+            Ok(journal) => {
+                self.change_journal = journal;
+                self.wal_sequence = self.change_journal.sequence();
+                Ok(())
+            }
+            Err(e) => Err(self.record_error(format!("Failed to load journal: {}", e))),
+        }
+    }
+
+    /// Apply a JSON array of commands in one call
+    /// Returns a JSON array of `{ok, value?, error?}` results in command order;
+    /// commands skipped after cancellation are marked `cancelled`
+    pub fn execute_commands(&mut self, batch_json: &str) -> Result<String, String> {
+        let commands = commands::parse_batch(batch_json).map_err(|e| self.record_error(e))?;
+        let results = self.execute_batch(commands);
+        serde_json::to_string(&results).map_err(|e| e.to_string())
+    }
+
+    /// Like `execute_commands`, but all or nothing: if any command fails, every journal
+    /// change the batch made is rolled back. Returns `{committed, results}` as JSON
+    pub fn execute_commands_atomic(&mut self, batch_json: &str) -> Result<String, String> {
+        let outcome = commands::parse_batch(batch_json)
+            .and_then(|commands| self.execute_atomic(commands))
+            .map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&outcome).map_err(|e| e.to_string())
+    }
+
+    /// Group the following journal changes so they can be undone together
+    pub fn begin_transaction(&mut self) -> Result<(), String> {
+        self.open_transaction().map_err(|e| self.record_error(e))
+    }
+
+    /// Keep the changes made since `begin_transaction`
+    pub fn commit_transaction(&mut self) -> Result<(), String> {
+        self.close_transaction(true).map_err(|e| self.record_error(e))
+    }
+
+    /// Undo every journal change, and its audit entries, since `begin_transaction`
+    pub fn abort_transaction(&mut self) -> Result<(), String> {
+        self.close_transaction(false).map_err(|e| self.record_error(e))
+    }
+
+    /// Attach a token checked at safe points inside long operations
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.cancel_token = Some(token.clone());
+    }
+
+    /// Detach the current cancellation token
+    pub fn clear_cancellation_token(&mut self) {
+        self.cancel_token = None;
+    }
+
+    /// Index a batch of existing files in one pass
+    /// `entries_json` is an array of `{path, mtime, hash?, size?, content_b64?}`;
+    /// returns cumulative progress JSON across batches since the last reset
+    pub fn bootstrap_index(&mut self, entries_json: &str) -> Result<String, String> {
+        let entries: Vec<bootstrap::IndexEntry> = serde_json::from_str(entries_json)
+            .map_err(|e| self.record_error(format!("Invalid index entries: {}", e)))?;
+        let progress = self.ingest_index_batch(entries);
+        serde_json::to_string(progress).map_err(|e| e.to_string())
+    }
+
+    /// Plan hashing of scanned files across `workers` Web Workers
+    /// `files_json` is an array of `{path, mtime, size}`; returns one array of
+    /// `{id, path, mtime, offset, len}` jobs per worker, skipping unchanged files
+    pub fn plan_hash_jobs(&mut self, files_json: &str, workers: usize) -> Result<String, String> {
+        let files: Vec<hashjobs::FileStat> = serde_json::from_str(files_json)
+            .map_err(|e| self.record_error(format!("Invalid file list: {}", e)))?;
+        let groups = self.plan_hash_work(files, workers);
+        serde_json::to_string(&groups).map_err(|e| e.to_string())
+    }
+
+    /// Record `{id, hash}` results from `compute_hash_job`; returns cumulative progress JSON
+    pub fn submit_hash_results(&mut self, results_json: &str) -> Result<String, String> {
+        let results: Vec<hashjobs::HashResult> = serde_json::from_str(results_json)
+            .map_err(|e| self.record_error(format!("Invalid hash results: {}", e)))?;
+        let progress = self.integrate_hash_results(results);
+        serde_json::to_string(progress).map_err(|e| e.to_string())
+    }
+
+    /// Jobs handed out and not yet reported back
+    pub fn get_pending_hash_job_count(&self) -> usize {
+        self.hash_jobs.pending()
+    }
+
+    /// Cumulative indexing progress as JSON
+    pub fn get_bootstrap_progress(&self) -> String {
+        serde_json::to_string(&self.bootstrap_progress).unwrap_or_default()
+    }
+
+    /// Start a new indexing run
+    pub fn reset_bootstrap_progress(&mut self) {
+        self.bootstrap_progress = BootstrapProgress::default();
+        self.hash_jobs.clear();
+    }
+
+    /// Recorded versions as a commit-log JSON array (oldest first)
+    pub fn export_history_commit_log(&self) -> String {
+        serde_json::to_string(&history::commit_log(self.change_journal.history())).unwrap_or_default()
+    }
+
+    /// Recorded versions as a `git fast-import` stream on `branch`
+    /// `contents_json` maps content hashes to base64 file bodies; missing
+    /// bodies are exported as pointer files
+    pub fn export_history_fast_import(&mut self, contents_json: &str, branch: &str) -> Result<Vec<u8>, String> {
+        let contents = history::parse_contents(contents_json).map_err(|e| self.record_error(e))?;
+        Ok(history::fast_import_stream(self.change_journal.history(), &contents, branch))
+    }
+
+    /// Export the journal as obsidian-livesync CouchDB documents (JSON array)
+    /// `contents_json` maps content hashes to base64 bodies; files without a
+    /// body are exported as metadata-only entries
+    pub fn export_livesync_docs(&mut self, contents_json: &str) -> Result<String, String> {
+        let contents = history::parse_contents(contents_json).map_err(|e| self.record_error(e))?;
+        let docs = livesync::export_docs(self.change_journal.files(), &contents);
+        serde_json::to_string(&docs).map_err(|e| e.to_string())
+    }
+
+    /// Seed the journal from obsidian-livesync CouchDB documents
+    /// Returns `{imported, deleted, incomplete, contents}` where `contents` maps
+    /// hashes to base64 bodies the host should write to disk
+    pub fn import_livesync_docs(&mut self, docs_json: &str) -> Result<String, String> {
+        let report = self.import_livesync(docs_json).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&report).map_err(|e| e.to_string())
+    }
+
+    /// Seal journal changes after `since_sequence` into a mailbox bundle for an offline peer
+    /// `contents_json` maps content hashes to base64 bodies; only those the delta needs are included
+    pub fn create_mailbox_bundle(
+        &mut self,
+        identity: &crypto::DeviceIdentity,
+        recipient_public_key: &str,
+        since_sequence: u64,
+        contents_json: &str,
+    ) -> Result<Vec<u8>, String> {
+        let body = self.mailbox_body(identity, since_sequence, contents_json).map_err(|e| self.record_error(e))?;
+        mailbox::seal_bundle(identity, recipient_public_key, &body).map_err(|e| self.record_error(e))
+    }
+
+    /// Replace the backup retention policy from `{interval_ms, tiers: [{every_ms, keep_for_ms}]}`
+    pub fn set_backup_retention(&mut self, policy_json: &str) -> Result<(), String> {
+        let policy = serde_json::from_str(policy_json)
+            .map_err(|e| self.record_error(format!("Invalid retention policy: {}", e)))?;
+        self.change_journal.backups_mut().policy = policy;
+        Ok(())
+    }
+
+    /// Record a snapshot taken at `created_at` of the current journal state
+    pub fn record_backup_snapshot(&mut self, id: String, created_at: u64, size_bytes: u64) {
+        let sequence = self.change_journal.head().sequence;
+        self.change_journal
+            .backups_mut()
+            .record(retention::SnapshotRecord { id, created_at, sequence, size_bytes });
+    }
+
+    /// Whether a new snapshot should be taken now
+    pub fn is_backup_due(&self, current_time: u64) -> bool {
+        self.change_journal.backups().is_due(current_time, self.change_journal.head().sequence)
+    }
+
+    /// Forget snapshots the retention policy no longer keeps
+    /// Returns the ids (JSON array) the host should delete
+    pub fn prune_backup_snapshots(&mut self, current_time: u64) -> String {
+        serde_json::to_string(&self.change_journal.backups_mut().prune(current_time)).unwrap_or_default()
+    }
+
+    /// Known snapshots as JSON, oldest first
+    pub fn list_backup_snapshots_json(&self) -> String {
+        serde_json::to_string(self.change_journal.backups().snapshots()).unwrap_or_default()
+    }
+
+    /// Estimated memory usage as JSON (journal, history, peer table, conflicts, logs,
+    /// transport buffers, chunk cache, held frames, linear memory)
+    pub fn get_memory_stats(&self) -> String {
+        serde_json::to_string(&self.memory_stats()).unwrap_or_default()
+    }
+
+    /// Memory estimates and per-stage timing totals as JSON
+    pub fn get_metrics_json(&self) -> String {
+        let metrics = Metrics { memory: self.memory_stats(), timings: timing::snapshot() };
+        serde_json::to_string(&metrics).unwrap_or_default()
+    }
+
+    /// Release spare capacity held by internal maps and buffers, and empty the chunk
+    /// cache and the frames held for unreachable peers (see `clear_caches`).
+    /// Returns the number of tracked bytes freed
+    pub fn trim_memory(&mut self) -> usize {
+        let before = self.memory_stats().total_tracked_bytes;
+        self.change_journal.trim();
+        self.peers.shrink_to_fit();
+        self.conflicts.trim();
+        self.audit.trim();
+        self.issues.trim();
+        self.security_log.trim();
+        self.trace.get_mut().trim();
+        self.debug_ring.get_mut().trim();
+        self.transports.trim();
+        self.clear_caches();
+        let after = self.memory_stats().total_tracked_bytes;
+        before.saturating_sub(after)
+    }
+}
+
+impl P2PNode {
+    /// Process an incoming discovery announcement (shared by the JS entry point and batches)
+    fn apply_announcement(&mut self, json: &str, sender_ip: &str, current_time: u64) -> Result<bool, String> {
+        let message = TraceMessage::AnnouncementJson { json: json.to_string(), sender_ip: sender_ip.to_string() };
+        self.trace_event(current_time, Direction::Inbound, message);
+        self.debug_event(DebugCategory::Received, "announcement", sender_ip, Some(json.len() as u64));
+        let announcement = match parse_announcement(json) {
+            Ok(Some(announcement)) => announcement,
+            Ok(None) => return Ok(false),
+            Err((code, e)) => {
+                self.issues.warn(code, e.clone(), &[("sender", sender_ip)]);
+                return Err(e);
+            }
+        };
+
+        Ok(self.insert_announced_peer(announcement, sender_ip, current_time))
+    }
+
+    fn apply_announcement_frame(&mut self, frame: &[u8], sender_ip: &str, current_time: u64) -> Result<bool, String> {
+        let message = TraceMessage::AnnouncementFrame { frame: trace::redact_frame(frame), sender_ip: sender_ip.to_string() };
+        self.trace_event(current_time, Direction::Inbound, message);
+        self.debug_event(DebugCategory::Received, "announcement", sender_ip, Some(frame.len() as u64));
+        let envelope = match wire::decode_frame(frame) {
+            Ok(Some((envelope, _))) => envelope,
+            other => {
+                let e = other.err().map(String::from).unwrap_or_else(|| "Incomplete announcement frame".to_string());
+                self.issues.warn(issues::MALFORMED_ANNOUNCEMENT, e.clone(), &[("sender", sender_ip)]);
+                return Err(e);
+            }
+        };
+        let Some(wire::Body::Announcement(announcement)) = envelope.body else {
+            return Ok(false);
+        };
+        let announcement = PeerAnnouncement {
+            msg_type: default_announcement_type(),
+            peer_id: announcement.peer_id,
+            device_name: announcement.device_name,
+            device_id: announcement.device_id,
+            service_port: u16::try_from(announcement.service_port).map_err(|_| "Invalid service port".to_string())?,
+            profile: announcement.profile,
+            journal_head: journalhead::JournalHead::from_wire(announcement.journal_sequence, &announcement.journal_digest),
+        };
+        Ok(self.insert_announced_peer(announcement, sender_ip, current_time))
+    }
+
+    fn apply_handshake_frame(&mut self, frame: &[u8]) -> Result<HandshakeSummary, String> {
+        self.trace_event(clock::now_ms(), Direction::Inbound, TraceMessage::HandshakeFrame { frame: trace::redact_frame(frame) });
+        self.debug_event(DebugCategory::Received, "handshake", "", Some(frame.len() as u64));
+        let handshake = self.verify_peer_handshake(frame)?;
+        Ok(HandshakeSummary {
+            journal: self.change_journal.compare_sketch(&handshake.journal_sketch)?,
+            device_id: handshake.device_id,
+        })
+    }
+
+    /// Record an announced peer; returns false for our own announcements
+    fn insert_announced_peer(&mut self, announcement: PeerAnnouncement, sender_ip: &str, current_time: u64) -> bool {
+        if announcement.peer_id == self.peer_id {
+            return false;
+        }
+
+        let peer = DiscoveredPeer {
+            id: announcement.peer_id.clone(),
+            name: announcement.device_name,
+            device_id: announcement.device_id,
+            last_seen_timestamp: current_time,
+            address: sender_ip.to_string(),
+            service_port: announcement.service_port,
+            profile: announcement.profile,
+            journal_head: announcement.journal_head,
+        };
+
+        if !self.peers.contains_key(&announcement.peer_id) && self.peers.len() >= limits::MAX_PEERS {
+            let stalest = self.peers.values().min_by_key(|p| p.last_seen_timestamp).map(|p| p.id.clone());
+            if let Some(stalest) = stalest {
+                self.peers.remove(&stalest);
+            }
+        }
+        self.peers.insert(announcement.peer_id, peer);
+        true
+    }
+
+    fn apply_journal_segment(&mut self, segment: &str) -> Result<bool, String> {
+        let loader = self.journal_loader.as_mut().ok_or("No journal load in progress")?;
+        match loader.push(segment, &mut self.change_journal) {
+            Ok(done) => {
+                if done {
+                    self.wal_sequence = self.change_journal.sequence();
+                }
+                Ok(done)
+            }
+            Err(e) => {
+                // Never leave a half-loaded journal behind
+                self.journal_loader = None;
+                self.change_journal = ChangeJournal::new();
+                Err(format!("Failed to load journal: {}", e))
+            }
+        }
+    }
+
+    fn next_export_segment(&mut self) -> Result<Option<String>, String> {
+        let exporter = self.exporter.as_mut().ok_or("No export in progress")?;
+        let _span = timing::span(timing::Stage::Serialize);
+        let segment = exporter.next_segment(&self.change_journal);
+        if !matches!(segment, Ok(Some(_))) {
+            self.exporter = None;
+        }
+        segment
+    }
+
+    /// Record a local edit of `path` whose content hashes to `hash`, honoring
+    /// policy and debounce rules; returns true if a journal entry was written
+    pub(crate) fn record_local_change(&mut self, path: String, hash: String, size: u64, mtime: u64) -> bool {
+        let message = TraceMessage::LocalChange { path: path.clone(), hash: hash.clone(), size, mtime };
+        self.trace_event(clock::now_ms(), Direction::Inbound, message);
+        if !self.policy.should_sync(&path) {
+            return false;
+        }
+        self.check_mtime_skew(&path, mtime);
+        let sequence = self.change_journal.sequence();
+        let interval = self.policy.debounce_for(&path);
+        if interval > 0 {
+            let now = clock::now_ms();
+            self.change_journal.flush_pending(now);
+            self.change_journal.stage_hashed(path, hash, size, mtime, self.device_id.clone(), now.saturating_add(interval));
+            self.audit_since(sequence, AuditOrigin::Local);
+            return false;
+        }
+        let changed = self.change_journal.update_hashed(path, hash, size, mtime, self.device_id.clone());
+        self.audit_since(sequence, AuditOrigin::Local);
+        changed
+    }
+
+    pub(crate) fn open_transaction(&mut self) -> Result<(), String> {
+        self.change_journal.begin_transaction()?;
+        self.audit_checkpoint = Some(self.audit.clone());
+        Ok(())
+    }
+
+    pub(crate) fn close_transaction(&mut self, commit: bool) -> Result<(), String> {
+        if commit {
+            self.change_journal.commit_transaction()?;
+        } else {
+            self.change_journal.abort_transaction()?;
+            if let Some(audit) = self.audit_checkpoint.take() {
+                self.audit = audit;
+            }
+        }
+        self.audit_checkpoint = None;
+        Ok(())
+    }
+
+    /// Warn when a file claims to be modified in the future
+    fn check_mtime_skew(&mut self, path: &str, mtime: u64) {
+        let now = clock::now_ms();
+        if mtime > now.saturating_add(MAX_CLOCK_SKEW_MS) {
+            self.issues.warn(
+                issues::CLOCK_SKEW,
+                format!("File modified {} ms in the future", mtime - now),
+                &[("path", path)],
+            );
+        }
+    }
+
+    fn merge_file_contents(
+        &mut self,
+        path: &str,
+        base: Option<&str>,
+        local: &str,
+        remote: &str,
+        options_json: &str,
+    ) -> Result<merge::MergeResult, String> {
+        if let Some(result) = self.run_merge_driver(path, base, local, remote)? {
+            return Ok(result);
+        }
+        if self.policy.action_for(path) != SyncAction::Merge {
+            return Err(format!("Path is not configured for merging: {}", path));
+        }
+        let options_json = if options_json.trim().is_empty() { "{}" } else { options_json };
+        if let Some(rule) = self.policy.append_rule_for(path) {
+            let options = merge::AppendMergeOptions { separator: rule.separator.clone() };
+            merge::merge_append_text(base, local, remote, &options)
+        } else if path.ends_with(".json") {
+            let options = serde_json::from_str(options_json)
+                .map_err(|e| format!("Invalid merge options: {}", e))?;
+            merge::merge_json_text(base, local, remote, options)
+        } else if let Some(base) = base {
+            let options = serde_json::from_str(options_json)
+                .map_err(|e| format!("Invalid merge options: {}", e))?;
+            merge::merge_lines_text(base, local, remote, options, &self.cancel_token)
+        } else {
+            Err(format!("Merging {} line by line needs the common ancestor", path))
+        }
+    }
+
+    fn queue_conflict(&mut self, remote: sync::FileMetadata, texts: Option<(&str, &str)>) -> Result<String, String> {
+        let local = self
+            .change_journal
+            .get(&remote.path)
+            .ok_or_else(|| format!("No local version of {}", remote.path))?;
+        self.round_reports.peer(&remote.last_modified_by).conflicts += 1;
+        Ok(self.conflicts.push(local, remote, texts, clock::now_ms()))
+    }
+
+    /// Remove a conflict from the queue and record the chosen version in the journal
+    fn apply_resolution(&mut self, id: &str, resolution: Resolution) -> Result<ResolutionOutcome, String> {
+        let conflict = self.conflicts.take(id)?;
+        let sequence = self.change_journal.sequence();
+        let detail = serde_json::to_value(&resolution)
+            .ok()
+            .and_then(|v| v.get("action").and_then(|a| a.as_str()).map(str::to_string));
+        self.audit.push(audit::AuditEntry {
+            timestamp: clock::now_ms(),
+            sequence,
+            action: audit::AuditAction::ConflictResolved,
+            path: conflict.path.clone(),
+            device_id: self.device_id.clone(),
+            origin: AuditOrigin::Local,
+            hash: None,
+            detail,
+        });
+        let remote = conflict.remote;
+        let mut outcome = ResolutionOutcome { path: conflict.path.clone(), ..Default::default() };
+        match resolution {
+            Resolution::KeepLocal => {}
+            Resolution::KeepRemote | Resolution::KeepBoth => {
+                let target = if resolution == Resolution::KeepBoth {
+                    conflicts::conflict_copy_path(&conflict.path, &remote.last_modified_by)
+                } else {
+                    conflict.path
+                };
+                self.change_journal.record_update(
+                    target.clone(),
+                    remote.hash.clone(),
+                    remote.size,
+                    remote.mtime,
+                    remote.last_modified_by,
+                );
+                outcome.fetch_remote_to = Some(target);
+                outcome.remote_hash = Some(remote.hash);
+            }
+            Resolution::UseMerged { content } => {
+                let hash = self.change_journal.content_hash(&conflict.path, content.as_bytes());
+                self.change_journal.record_update(
+                    conflict.path,
+                    hash,
+                    content.len() as u64,
+                    clock::now_ms(),
+                    self.device_id.clone(),
+                );
+                outcome.write_content = Some(content);
+            }
+        }
+        self.audit_since(sequence, AuditOrigin::Local);
+        Ok(outcome)
+    }
+
+    fn import_livesync(&mut self, docs_json: &str) -> Result<livesync::ImportReport, String> {
+        limits::check_size("LiveSync documents", docs_json, limits::MAX_JOURNAL_BYTES)?;
+        let docs = serde_json::from_str(docs_json).map_err(|e| format!("Invalid LiveSync documents: {}", e))?;
+        let (files, incomplete) = livesync::reassemble(docs);
+        let mut report = livesync::ImportReport { incomplete, ..Default::default() };
+        let sequence = self.change_journal.sequence();
+        for file in files {
+            if !self.policy.should_sync(&file.path) {
+                continue;
+            }
+            match file.content {
+                None => {
+                    self.change_journal.mark_deleted(file.path, file.mtime, livesync::LIVESYNC_DEVICE.to_string());
+                    report.deleted += 1;
+                }
+                Some(content) => {
+                    let hash = self.change_journal.content_hash(&file.path, &content);
+                    let size = content.len() as u64;
+                    self.change_journal.record_update(
+                        file.path,
+                        hash.clone(),
+                        size,
+                        file.mtime,
+                        livesync::LIVESYNC_DEVICE.to_string(),
+                    );
+                    report.contents.insert(hash, BASE64.encode(&content));
+                    report.imported += 1;
+                }
+            }
+        }
+        self.audit_since(sequence, AuditOrigin::Import { source: livesync::LIVESYNC_DEVICE.to_string() });
+        Ok(report)
+    }
+
+    fn apply_remote(&mut self, remote: sync::FileMetadata, from_peer_id: &str) -> bool {
+        let message = TraceMessage::RemoteChange {
+            metadata: serde_json::to_string(&remote).unwrap_or_default(),
+            from_peer_id: from_peer_id.to_string(),
+        };
+        self.trace_event(clock::now_ms(), Direction::Inbound, message);
+        if !self.policy.should_sync(&remote.path) {
+            return false;
+        }
+        let sequence = self.change_journal.sequence();
+        let changed = if remote.is_deleted {
+            self.change_journal.mark_deleted(remote.path, remote.mtime, remote.last_modified_by)
+        } else {
+            let local = self.change_journal.get(&remote.path).map(|m| m.extended).unwrap_or_default();
+            let extended = self.metadata_rules.merge_incoming(&local, remote.extended.clone());
+            self.change_journal.record_version(sync::FileMetadata { extended, ..remote })
+        };
+        self.audit_since(sequence, AuditOrigin::Peer { peer_id: from_peer_id.to_string() });
+        changed
+    }
+
+    /// Audit every journal version recorded after `sequence`
+    fn audit_since(&mut self, sequence: u64, origin: AuditOrigin) {
+        let now = clock::now_ms();
+        let mut recorded: Vec<sync::FileMetadata> =
+            self.change_journal.history().rev().take_while(|m| m.version > sequence).collect();
+        recorded.reverse();
+        for meta in &recorded {
+            self.audit.record_version(meta, origin.clone(), now);
+        }
+    }
+
+    fn mailbox_body(
+        &self,
+        identity: &crypto::DeviceIdentity,
+        since_sequence: u64,
+        contents_json: &str,
+    ) -> Result<mailbox::MailboxBody, String> {
+        let mut contents: HashMap<String, String> = serde_json::from_str(contents_json)
+            .map_err(|e| format!("Invalid contents JSON: {}", e))?;
+        let mut entries: Vec<sync::FileMetadata> =
+            self.change_journal.files().filter(|m| m.version > since_sequence).collect();
+        entries.sort_by_key(|m| m.version);
+        let contents = entries
+            .iter()
+            .filter(|m| !m.is_deleted)
+            .filter_map(|m| contents.remove_entry(&m.hash))
+            .collect();
+        Ok(mailbox::MailboxBody {
+            sender_device_id: self.device_id.clone(),
+            sender_public_key: identity.get_public_key(),
+            created_at: clock::now_ms(),
+            since_sequence,
+            entries,
+            contents,
+        })
+    }
+
+    fn config(&self) -> config::NodeConfig {
+        config::NodeConfig {
+            schema_version: config::CONFIG_SCHEMA_VERSION,
+            policy: self.policy.clone(),
+            profile: self.profile.clone(),
+            attachments: self.attachment_policy.clone(),
+            backup_retention: self.change_journal.backups().policy.clone(),
+            hash_algorithm: self.change_journal.hash_algorithm(),
+            schedule: self.scheduler.rules.clone(),
+            padding: self.padding.clone(),
+            pins: self.pins.clone(),
+            cache_quota_bytes: self.caches.quota_bytes(),
+        }
+    }
+
+    fn apply_config(&mut self, config: config::NodeConfig) {
+        self.policy = config.policy;
+        self.apply_profile(config.profile);
+        // Attachment rules may differ from the profile's when set separately
+        self.attachment_policy = config.attachments;
+        self.change_journal.backups_mut().policy = config.backup_retention;
+        self.change_journal.set_hash_algorithm(config.hash_algorithm);
+        self.scheduler.rules = config.schedule;
+        self.padding = config.padding;
+        self.pins = config.pins;
+        // Checked when the document was parsed
+        let _ = self.apply_cache_quota(config.cache_quota_bytes);
+    }
+
+    fn apply_profile(&mut self, profile: SyncProfile) {
+        self.attachment_policy.rules = profile.attachments.clone();
+        self.profile = profile;
+    }
+
+    /// Remember an error for `get_status()` and hand it back to the caller
+    fn record_error(&mut self, error: impl Into<String>) -> String {
+        let error = error.into();
+        self.debug_event(DebugCategory::Error, "api_error", &error, None);
+        self.last_error = Some(error.clone());
+        error
+    }
+
+    fn prune_stale_peers(&mut self, current_time: u64, ttl_ms: u64) -> usize {
+        let initial_count = self.peers.len();
+        self.peers.retain(|_, peer| {
+            current_time.saturating_sub(peer.last_seen_timestamp) < ttl_ms
+        });
+        initial_count - self.peers.len()
+    }
+
+    fn memory_stats(&self) -> MemoryStats {
+        let peer_table_bytes = map_overhead(&self.peers)
+            + self
+                .peers
+                .iter()
+                .map(|(id, peer)| string_bytes(id) + peer.heap_bytes())
+                .sum::<usize>();
+
+        let history_bytes = self.change_journal.history_bytes();
+        let log_bytes = self.audit.heap_bytes()
+            + self.issues.heap_bytes()
+            + self.security_log.heap_bytes()
+            + self.trace.borrow().heap_bytes()
+            + self.debug_ring.borrow().heap_bytes();
+
+        MemoryStats {
+            journal_bytes: self.change_journal.heap_bytes() - history_bytes,
+            history_bytes,
+            peer_table_bytes,
+            conflict_bytes: self.conflicts.heap_bytes(),
+            log_bytes,
+            transport_bytes: self.transports.heap_bytes(),
+            cache_bytes: self.caches.chunks().heap_bytes(),
+            held_frame_bytes: self.transports.held().1,
+            ..Default::default()
+        }
+        .finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p2p_node_creation() {
+        let node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        assert!(!node.get_peer_id().is_empty());
+        assert_eq!(node.get_device_name(), "Device A");
+        assert!(!node.get_device_id().is_empty());
+    }
+
+    #[test]
+    fn test_peer_discovery() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+
+        // Start discovery
+        assert!(node.start_discovery().is_ok());
+        assert_eq!(node.get_peer_count(), 0);
+
+        // Add a peer
+        let peer = DiscoveredPeer::new(
+            "peer-1".to_string(),
+            "Device B".to_string(),
+            "device-b-id".to_string(),
+            1000u64,
+            "192.168.1.2".to_string(),
+            8081,
+        );
+        assert!(node.add_discovered_peer(&peer).is_ok());
+        assert_eq!(node.get_peer_count(), 1);
+
+        // Remove the peer
+        assert!(node.remove_peer("peer-1").is_ok());
+        assert_eq!(node.get_peer_count(), 0);
+    }
+
+    #[test]
+    fn test_node_status() {
+        let node = P2PNode::new("Test Device".to_string(), "device-id".to_string(), 8080);
+        let status = node.status();
+        assert!(status.contains("Test Device"));
+        assert!(status.contains("0 peers"));
+    }
+
+    #[test]
+    fn test_execute_command_batch() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        let batch = r#"[
+            {"op": "update_file", "path": "a.md", "content_b64": "aGVsbG8=", "mtime": 1},
+            {"op": "update_file", "path": "b.md", "content_b64": "not base64!", "mtime": 1},
+            {"op": "mark_deleted", "path": "a.md", "mtime": 2},
+            {"op": "process_announcement", "json": "{\"type\":\"announcement\",\"peer_id\":\"p2\",\"device_name\":\"B\",\"device_id\":\"b\"}", "sender_ip": "10.0.0.2", "current_time": 5},
+            {"op": "ack_chunk", "transfer_id": "t1", "chunk_index": 0},
+            {"op": "ack_chunk", "transfer_id": "t1", "chunk_index": 9}
+        ]"#;
+        let key = BASE64.encode([7u8; 32]);
+        node.start_tracking("p2", Some("t1".to_string()), "big.pdf".to_string(), "h".to_string(), 2, &key).unwrap();
+        let results = node.execute_batch(commands::parse_batch(batch).unwrap());
+
+        assert_eq!(results.len(), 6);
+        assert!(results[0].ok);
+        assert!(!results[1].ok);
+        assert_eq!(results[2].value, Some(serde_json::Value::Bool(true)));
+        assert_eq!(node.get_peer_count(), 1);
+        assert!(results[4].ok && !results[5].ok);
+        assert!(node.get_resumable_transfers_json().contains(r#""verified_chunks":1"#));
+    }
+
+    #[test]
+    fn test_cancelled_batch_stops_at_safe_point() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        let token = CancellationToken::new();
+        node.set_cancellation_token(&token);
+        token.cancel();
+
+        let batch = r#"[{"op": "mark_deleted", "path": "a.md", "mtime": 1}]"#;
+        let results = node.execute_batch(commands::parse_batch(batch).unwrap());
+        assert!(results[0].cancelled);
+        assert_eq!(node.get_all_files(), "[]");
+
+        token.reset();
+        let results = node.execute_batch(commands::parse_batch(batch).unwrap());
+        assert!(results[0].ok);
+    }
+
+    #[test]
+    fn test_atomic_batch_rolls_back_journal_and_audit() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"one", 1);
+        let before = node.get_journal_state();
+        let audit_before = node.get_audit_count();
+
+        let batch = r#"[
+            {"op": "update_file", "path": "a.md", "content_b64": "dHdv", "mtime": 2},
+            {"op": "update_file", "path": "new.md", "content_b64": "aGk=", "mtime": 2},
+            {"op": "mark_deleted", "path": "a.md", "mtime": 3},
+            {"op": "update_file", "path": "b.md", "content_b64": "not base64!", "mtime": 3}
+        ]"#;
+        let outcome = node.execute_atomic(commands::parse_batch(batch).unwrap()).unwrap();
+        assert!(!outcome.committed);
+        assert!(outcome.results[0].ok);
+        assert_eq!(node.get_journal_state(), before);
+        assert_eq!(node.get_audit_count(), audit_before);
+        assert!(!node.change_journal.in_transaction());
+
+        let outcome = node.execute_atomic(commands::parse_batch(&batch.replace("not base64!", "aGk=")).unwrap()).unwrap();
+        assert!(outcome.committed);
+        assert_eq!(node.change_journal.sequence(), 5);
+        assert!(node.close_transaction(false).is_err());
+    }
+
+    #[test]
+    fn test_pairing_code_expiry_follows_injected_clock() {
+        clock::set_clock_time(1_000_000);
+        let code = crypto::PairingCode::generate();
+        assert_eq!(code.get_created_at(), 1_000_000);
+        assert!(!code.is_expired(0));
+
+        clock::advance_clock(crypto::DEFAULT_PAIRING_CODE_TTL_MS);
+        assert!(code.is_expired(0));
+        assert!(!code.is_expired(crypto::DEFAULT_PAIRING_CODE_TTL_MS * 2));
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_structured_status() {
+        clock::set_clock_time(100_000);
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        node.start_discovery().unwrap();
+        node.update_file("a.md".to_string(), b"a", 1);
+        node.update_file("b.md".to_string(), b"b", 1);
+        node.mark_file_deleted("b.md".to_string(), 2);
+        let fresh = r#"{"type":"announcement","peer_id":"p1","device_name":"B","device_id":"b"}"#;
+        let stale = r#"{"type":"announcement","peer_id":"p2","device_name":"C","device_id":"c"}"#;
+        node.apply_announcement(fresh, "10.0.0.2", 99_000).unwrap();
+        node.apply_announcement(stale, "10.0.0.3", 1_000).unwrap();
+
+        let status = node.get_status();
+        assert!(status.get_is_discovering());
+        assert_eq!(status.get_active_peer_count(), 1);
+        assert_eq!(status.get_stale_peer_count(), 1);
+        assert_eq!(status.get_journal_sequence(), 3);
+        assert_eq!(status.get_file_count(), 1);
+        assert_eq!(status.get_deleted_count(), 1);
+        assert_eq!(status.get_last_error(), None);
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_issue_log_records_recoverable_problems() {
+        clock::set_clock_time(1_000_000);
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        assert!(node.apply_announcement("{not json", "10.0.0.9", 0).is_err());
+        assert!(!node.apply_announcement(r#"{"type":"pairing_request"}"#, "10.0.0.9", 0).unwrap());
+        node.update_file("future.md".to_string(), b"x", 1_000_000 + MAX_CLOCK_SKEW_MS + 1);
+
+        let issues: Vec<serde_json::Value> = serde_json::from_str(&node.get_issues_json()).unwrap();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0]["code"], issues::MALFORMED_ANNOUNCEMENT);
+        assert_eq!(issues[0]["context"]["sender"], "10.0.0.9");
+        assert_eq!(issues[1]["code"], issues::CLOCK_SKEW);
+
+        node.clear_issues();
+        assert_eq!(node.get_issue_count(), 0);
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_policy_skips_device_specific_config() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        assert!(!node.update_file(".obsidian/workspace.json".to_string(), b"{}", 1));
+        assert!(node.update_file(".obsidian/hotkeys.json".to_string(), b"{}", 1));
+        assert_eq!(node.get_path_policy(".obsidian/plugins/x/data.json"), "merge");
+        assert_eq!(node.get_status().get_file_count(), 1);
+    }
+
+    #[test]
+    fn test_daily_notes_use_append_merge() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        node.set_append_merge_rules(r#"[{"pattern": "Daily/*.md"}]"#).unwrap();
+        assert_eq!(node.get_path_policy("Daily/2024-01-01.md"), "merge");
+
+        let result = node
+            .merge_file_contents("Daily/2024-01-01.md", Some("a\n"), "a\nb\n", "a\nc\n", "")
+            .unwrap();
+        assert_eq!(result.strategy, "append");
+        assert_eq!(result.merged, "a\nb\nc\n");
+        assert!(node.merge_file_contents("Notes/x.md", None, "a", "b", "").is_err());
+    }
+
+    #[test]
+    fn test_volatile_files_are_debounced() {
+        clock::set_clock_time(10_000);
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        for i in 0..5u8 {
+            node.update_file(".obsidian/graph.json".to_string(), &[i], 10_000);
+            clock::advance_clock(1_000);
+        }
+        assert_eq!(node.get_debounced_count(), 1);
+        assert_eq!(node.get_status().get_journal_sequence(), 0);
+
+        assert_eq!(node.flush_debounced(clock::now_ms()), 0);
+        assert_eq!(node.flush_debounced(clock::now_ms() + 60_000), 1);
+        assert_eq!(node.get_status().get_journal_sequence(), 1);
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_profile_is_applied_and_advertised() {
+        let mut phone = P2PNode::new("Phone".to_string(), "phone-id".to_string(), 8080);
+        phone.select_profile(profiles::PROFILE_MOBILE_LITE).unwrap();
+        phone.set_attachment_folder("Attachments");
+        let order = phone
+            .plan_transfer_order(
+                r#"[{"path": "Attachments/a.png", "size": 10}, {"path": "movie.mkv", "size": 1000000000}, {"path": "a.md", "size": 1}]"#,
+                true,
+            )
+            .unwrap();
+        let order: serde_json::Value = serde_json::from_str(&order).unwrap();
+        assert_eq!(order["transfer"], serde_json::json!(["a.md"]));
+        assert_eq!(order["deferred"], serde_json::json!(["Attachments/a.png"]));
+        assert_eq!(order["skipped"], serde_json::json!(["movie.mkv"]));
+
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop-id".to_string(), 8081);
+        laptop.apply_announcement(&phone.get_announcement_json(), "10.0.0.2", 1).unwrap();
+        let peers: serde_json::Value = serde_json::from_str(&laptop.get_discovered_peers_json()).unwrap();
+        assert_eq!(peers[0]["profile"], profiles::PROFILE_MOBILE_LITE);
+    }
+
+    #[test]
+    fn test_binary_announcement_frames() {
+        let phone = P2PNode::new("Phone".to_string(), "phone-id".to_string(), 8080);
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop-id".to_string(), 8081);
+        assert!(laptop.apply_announcement_frame(&phone.get_announcement_frame(), "10.0.0.2", 1).unwrap());
+        assert!(!laptop.apply_announcement_frame(&laptop.get_announcement_frame(), "10.0.0.3", 1).unwrap());
+        let peers: serde_json::Value = serde_json::from_str(&laptop.get_discovered_peers_json()).unwrap();
+        assert_eq!(peers[0]["device_id"], "phone-id");
+        assert_eq!(peers[0]["service_port"], 8080);
+
+        let ping = wire::encode_frame(&wire::Envelope::new(wire::Body::Control(wire::Control {
+            kind: wire::ControlKind::Ping as i32,
+            ..Default::default()
+        })));
+        assert!(!laptop.apply_announcement_frame(&ping, "10.0.0.2", 2).unwrap());
+        assert!(laptop.apply_announcement_frame(&[0x05, 0xff], "10.0.0.9", 2).is_err());
+    }
+
+    #[test]
+    fn test_wrapped_keys_and_wipe() {
+        let wrapping = BASE64.encode([5u8; 32]);
+        let identity = crypto::DeviceIdentity::new("laptop".to_string()).unwrap();
+        let wrapped = identity.export_wrapped_secret_key(wrapping.clone()).unwrap();
+        assert!(!wrapped.contains(&BASE64.encode(*identity.seed())));
+        let restored = crypto::DeviceIdentity::from_wrapped_secret_key("laptop".to_string(), wrapped.clone(), wrapping.clone()).unwrap();
+        assert_eq!(restored.get_public_key(), identity.get_public_key());
+        assert!(crypto::DeviceIdentity::from_wrapped_secret_key("laptop".to_string(), wrapped.clone(), BASE64.encode([6u8; 32])).is_err());
+        // An identity blob is not accepted as an exchange key
+        assert!(crypto::KeyExchange::from_wrapped_secret_key(wrapped, wrapping.clone()).is_err());
+
+        let exchange = crypto::KeyExchange::new();
+        let wrapped = exchange.export_wrapped_secret_key(wrapping.clone()).unwrap();
+        let restored = crypto::KeyExchange::from_wrapped_secret_key(wrapped, wrapping).unwrap();
+        assert_eq!(restored.get_public_key(), exchange.get_public_key());
+
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let code = node.start_pairing(0);
+        node.apply_handshake_frame(&phone.get_handshake_frame(&crypto::DeviceIdentity::new("phone".to_string()).unwrap(), &exchange))
+            .unwrap();
+        let confirmation = BASE64.encode([7u8; 32]);
+        assert!(node.check_session_confirmation("phone", &confirmation, &confirmation));
+        node.wipe_secrets();
+        assert_eq!(node.pairing_attempt("10.0.0.9", &code), pairing::AttemptOutcome::NoCode);
+        assert!(node.access.requester_key("phone").is_err());
+        assert!(node.peer_capabilities.is_empty());
+    }
+
+    #[test]
+    fn test_handshake_sketch_check() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let identity = crypto::DeviceIdentity::new("phone".to_string()).unwrap();
+        let session = crypto::KeyExchange::new();
+        laptop.update_file("a.md".to_string(), b"one", 1_000);
+        phone.update_file("a.md".to_string(), b"one", 2_000);
+
+        let summary: serde_json::Value =
+            serde_json::from_str(&laptop.process_handshake_frame(&phone.get_handshake_frame(&identity, &session)).unwrap()).unwrap();
+        assert_eq!(summary["device_id"], "phone");
+        assert_eq!(summary["journal"]["identical"], true);
+
+        phone.update_file("b.md".to_string(), b"two", 3_000);
+        let mut frame = phone.get_handshake_frame(&identity, &session);
+        let summary = laptop.apply_handshake_frame(&frame).unwrap();
+        assert_eq!((summary.journal.identical, summary.journal.estimated_differences), (false, 1));
+
+        // Inside the signature, which precedes the capabilities and the 256-byte sketch
+        let len = frame.len();
+        frame[len - 300] ^= 1;
+        assert!(laptop.apply_handshake_frame(&frame).is_err());
+        assert_eq!(laptop.security_log.iter().next().unwrap().kind, security::SecurityEventKind::SignatureInvalid);
+    }
+
+    #[test]
+    fn test_history_export() {
+        let mut node = P2PNode::new("Device A".to_string(), "laptop".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"one", 1_000);
+        node.update_file("a.md".to_string(), b"two", 2_000);
+        node.mark_file_deleted("a.md".to_string(), 3_000);
+
+        let log: Vec<serde_json::Value> = serde_json::from_str(&node.export_history_commit_log()).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[2]["deleted"], true);
+        assert_eq!(log[0]["device_id"], "laptop");
+
+        let one = sync::hash_content(b"one");
+        let stream = node
+            .export_history_fast_import(&format!(r#"{{"{}": "b25l"}}"#, one), "main")
+            .unwrap();
+        let stream = String::from_utf8(stream).unwrap();
+        assert!(stream.contains("M 100644 inline a.md\ndata 3\none\n"));
+        assert!(stream.contains("oid sha256:"));
+        assert!(stream.contains("from :2\nD a.md\n"));
+        assert!(stream.contains("author laptop <laptop@obsidian-p2p-sync> 2 +0000"));
+    }
+
+    #[test]
+    fn test_bootstrap_index_batches() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        let hash = sync::hash_content(b"hello");
+        let batch = format!(
+            r#"[{{"path": "a.md", "mtime": 1, "hash": "{}", "size": 5}},
+                {{"path": "b.md", "mtime": 1, "content_b64": "aGVsbG8="}},
+                {{"path": ".obsidian/workspace.json", "mtime": 1, "content_b64": ""}},
+                {{"path": "c.md", "mtime": 1, "hash": "nothex"}}]"#,
+            hash
+        );
+        node.ingest_index_batch(serde_json::from_str(&batch).unwrap());
+        let progress = node
+            .ingest_index_batch(serde_json::from_str(r#"[{"path": "b.md", "mtime": 2, "content_b64": "aGVsbG8="}]"#).unwrap())
+            .clone();
+
+        assert_eq!(progress.processed, 5);
+        assert_eq!(progress.recorded, 2);
+        assert_eq!(progress.unchanged, 1);
+        assert_eq!(progress.skipped, 1);
+        assert_eq!(progress.failed, 1);
+        assert_eq!(node.get_status().get_file_count(), 2);
+    }
+
+    #[test]
+    fn test_conflict_resolution_updates_journal() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"local\n", 1);
+        let remote = sync::FileMetadata {
+            path: "a.md".to_string(),
+            hash: sync::hash_content(b"remote\n"),
+            mtime: 2,
+            size: 7,
+            version: 4,
+            is_deleted: false,
+            last_modified_by: "phone".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        };
+
+        let id = node.queue_conflict(remote.clone(), Some(("local\n", "remote\n"))).unwrap();
+        let outcome = node.apply_resolution(&id, Resolution::KeepBoth).unwrap();
+        assert_eq!(outcome.fetch_remote_to.as_deref(), Some("a (conflict phone).md"));
+        assert_eq!(node.get_status().get_file_count(), 2);
+
+        let id = node.queue_conflict(remote, None).unwrap();
+        let merged = Resolution::UseMerged { content: "local\nremote\n".to_string() };
+        let outcome = node.apply_resolution(&id, merged).unwrap();
+        assert_eq!(outcome.write_content.as_deref(), Some("local\nremote\n"));
+        assert_eq!(node.change_journal.get("a.md").unwrap().last_modified_by, "device-a");
+        assert_eq!(node.get_conflict_count(), 0);
+    }
+
+    #[test]
+    fn test_mailbox_bundle_carries_delta() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("old.md".to_string(), b"old", 1);
+        node.update_file("new.md".to_string(), b"new", 2);
+        let identity = crypto::DeviceIdentity::new("device-a".to_string()).unwrap();
+        let contents = format!(
+            r#"{{"{}": "bmV3", "{}": "b2xk"}}"#,
+            sync::hash_content(b"new"),
+            sync::hash_content(b"old")
+        );
+
+        let body = node.mailbox_body(&identity, 1, &contents).unwrap();
+        assert_eq!(body.entries.len(), 1);
+        assert_eq!(body.entries[0].path, "new.md");
+        assert_eq!(body.contents.len(), 1);
+
+        let mailbox_key = crypto::KeyExchange::new();
+        let bundle = mailbox::seal_bundle(&identity, &mailbox_key.get_public_key(), &body).unwrap();
+        assert_eq!(mailbox::open_bundle(&mailbox_key, &bundle).unwrap(), body);
+    }
+
+    #[test]
+    fn test_audit_log_answers_who_deleted() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("note.md".to_string(), b"hello", 1);
+        let deletion = r#"{"path": "note.md", "hash": "", "mtime": 5, "size": 0, "version": 9,
+                           "is_deleted": true, "last_modified_by": "phone"}"#;
+        assert!(node.apply_remote(serde_json::from_str(deletion).unwrap(), "peer-phone"));
+
+        let entries: Vec<&audit::AuditEntry> = node.audit.entries_for("note.md").collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].origin, AuditOrigin::Local);
+        assert_eq!(entries[1].action, audit::AuditAction::Delete);
+        assert_eq!(entries[1].device_id, "phone");
+        assert_eq!(entries[1].origin, AuditOrigin::Peer { peer_id: "peer-phone".to_string() });
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut source = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        source.policy.set_user_rules(vec![policy::PolicyRule::new("Private/**", SyncAction::Skip)]);
+        source.apply_profile(SyncProfile::mobile_lite());
+        source.attachment_policy.layout = AttachmentLayout::from_obsidian_setting("Attachments");
+        source.set_cache_quota(8 * 1024 * 1024).unwrap();
+
+        let mut target = P2PNode::new("Device B".to_string(), "device-b".to_string(), 8080);
+        target.apply_config(config::NodeConfig::parse(&source.export_config()).unwrap());
+        assert_eq!(target.get_path_policy("Private/diary.md"), "skip");
+        assert_eq!(target.profile.name, profiles::PROFILE_MOBILE_LITE);
+        assert!(target.is_attachment("Attachments/a.png"));
+        assert_eq!(target.caches.quota_bytes(), 8 * 1024 * 1024);
+        assert_eq!(target.export_config(), source.export_config());
+    }
+
+    #[test]
+    fn test_journal_state_cbor() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        for i in 0..32 {
+            node.update_file(format!("Notes/note-{}.md", i), format!("body {}", i).as_bytes(), 1000 + i);
+        }
+        node.mark_file_deleted("Notes/note-3.md".to_string(), 2000);
+        let cbor = node.get_journal_state_cbor();
+        assert!(cbor.len() < node.get_journal_state().len());
+
+        let mut restored = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        restored.load_journal_state_cbor(&cbor).unwrap();
+        assert_eq!(restored.change_journal.sequence(), node.change_journal.sequence());
+        assert_eq!(restored.change_journal.get("Notes/note-3.md"), node.change_journal.get("Notes/note-3.md"));
+        assert_eq!(restored.change_journal.history().count(), 0);
+        restored.load_history_state(&node.get_history_state()).unwrap();
+        assert_eq!(restored.change_journal.history().count(), 33);
+        assert!(ChangeJournal::from_cbor(&cbor[..cbor.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_metrics_report_stage_timings() {
+        timing::reset_timings();
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"one", 1);
+        node.get_journal_state();
+        let metrics: serde_json::Value = serde_json::from_str(&node.get_metrics_json()).unwrap();
+        assert_eq!(metrics["timings"]["hash"]["count"], 1);
+        assert_eq!(metrics["timings"]["serialize"]["count"], 1);
+        assert!(metrics["memory"]["total_tracked_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_segmented_export() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"one", 1);
+        assert!(node.next_export_segment().is_err());
+        node.begin_export("files").unwrap();
+        let mut output = String::new();
+        while let Some(segment) = node.next_segment().unwrap() {
+            output.push_str(&segment);
+        }
+        assert_eq!(output, node.get_all_files());
+        assert!(node.next_export_segment().is_err());
+    }
+
+    #[test]
+    fn test_blake3_migration_keeps_sha256_entries() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-a".to_string(), 8080);
+        node.update_file("old.md".to_string(), b"old", 1);
+        node.update_file("edited.md".to_string(), b"v1", 1);
+        node.set_hash_algorithm("blake3").unwrap();
+
+        // Unchanged content keeps its SHA-256 hash and is not a new version
+        assert!(!node.update_file("old.md".to_string(), b"old", 2));
+        assert_eq!(node.change_journal.get("old.md").unwrap().hash, sync::hash_content(b"old"));
+        assert!(node.update_file("edited.md".to_string(), b"v2", 2));
+        assert!(node.update_file("new.md".to_string(), b"new", 2));
+        let edited = node.change_journal.get("edited.md").unwrap().hash;
+        assert_eq!(hashing::HashAlgorithm::of(&edited), hashing::HashAlgorithm::Blake3);
+
+        let config = config::NodeConfig::parse(&node.export_config()).unwrap();
+        assert_eq!(config.hash_algorithm, hashing::HashAlgorithm::Blake3);
+        let restored = ChangeJournal::from_json(&node.get_journal_state()).unwrap();
+        assert_eq!(restored.hash_algorithm(), hashing::HashAlgorithm::Blake3);
+        assert_eq!(restored.get("edited.md").unwrap().hash, edited);
+    }
+
+    #[test]
+    fn test_trim_memory_releases_capacity() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        for i in 0..64 {
+            node.update_file(format!("note-{}.md", i), b"content", 1000);
+        }
+        let before: serde_json::Value = serde_json::from_str(&node.get_memory_stats()).unwrap();
+        assert!(before["journal_bytes"].as_u64().unwrap() > 0);
+
+        for i in 0..64 {
+            let peer = DiscoveredPeer::new(
+                format!("peer-{}", i),
+                "Device B".to_string(),
+                "device-b-id".to_string(),
+                1000u64,
+                "192.168.1.2".to_string(),
+                8081,
+            );
+            node.add_discovered_peer(&peer).unwrap();
+        }
+        node.clear_peers().unwrap();
+        assert!(node.trim_memory() > 0);
+    }
+
+    #[test]
+    fn test_memory_stats_cover_long_lived_subsystems() {
+        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"content", 1000);
+        node.register_transport(r#"{"name":"wifi","kind":"direct"}"#).unwrap();
+        node.send_frame("laptop", &[0; 4096]).unwrap();
+        node.cache_chunk(&[1; 8192]);
+        let stats: serde_json::Value = serde_json::from_str(&node.get_memory_stats()).unwrap();
+        let bytes = |field: &str| stats[field].as_u64().unwrap();
+        assert!(bytes("history_bytes") > 0 && bytes("log_bytes") > 0);
+        assert!(bytes("held_frame_bytes") >= 4096 && bytes("cache_bytes") >= 8192);
+        let parts = ["journal_bytes", "history_bytes", "peer_table_bytes", "conflict_bytes", "log_bytes", "transport_bytes"];
+        let parts = parts.iter().chain(&["cache_bytes", "held_frame_bytes"]);
+        assert_eq!(parts.map(|field| bytes(field)).sum::<u64>(), bytes("total_tracked_bytes"));
+
+        // The cache and held frames are the first thing trimming drops
+        assert!(node.trim_memory() >= 4096 + 8192);
+        let stats: serde_json::Value = serde_json::from_str(&node.get_memory_stats()).unwrap();
+        assert_eq!((stats["cache_bytes"].as_u64(), stats["held_frame_bytes"].as_u64()), (Some(0), Some(0)));
+    }
+}
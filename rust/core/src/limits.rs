@@ -0,0 +1,232 @@
+//! Input size limits for untrusted data
+//!
+//! A panic inside the WASM module takes the whole plugin down, so every
+//! decoder fed by the network or by persisted state rejects oversized input
+//! before allocating for it and reports failures as errors.
+
+use crate::error::Error;
+
+/// Discovery announcements are a handful of short fields
+pub const MAX_ANNOUNCEMENT_BYTES: usize = 4 * 1024;
+
+/// Discovered peers kept at once; a new announcement beyond this replaces the
+/// peer seen least recently, so spoofed announcements can't grow the table
+pub const MAX_PEERS: usize = 256;
+
+/// One serialized chunk: 64 KiB of ciphertext rendered as a JSON number array
+pub const MAX_CHUNK_JSON_BYTES: usize = 512 * 1024;
+
+/// One binary wire frame: a chunk, or a manifest of a few thousand entries
+pub const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// Persisted journal blob (roughly 500k files of metadata)
+pub const MAX_JOURNAL_BYTES: usize = 256 * 1024 * 1024;
+
+/// One batch of host commands
+pub const MAX_COMMAND_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+/// Reject input larger than `max` bytes
+pub fn check_size(kind: &str, input: &str, max: usize) -> Result<(), Error> {
+    if input.len() > max {
+        Err(format!("{} too large: {} bytes (limit {})", kind, input.len(), max).into())
+    } else {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    //! Seeded mutation fuzzing of every parse entry point, text and binary: each
+    //! input is truncated, bit-flipped or spliced, and the decoder must return
+    //! (either way) without panicking.
+
+    use crate::commands::parse_batch;
+    use crate::crypto::{decrypt_data, DeviceIdentity, KeyExchange};
+    use crate::sync::ChangeJournal;
+    use crate::transfer::TransferManager;
+    use crate::wire::{self, Encoding, Fragmenter, FrameBuffer, MAX_PENDING_FRAGMENTED};
+    use crate::P2PNode;
+
+    struct Mutator(u64);
+
+    impl Mutator {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Mutate `seed`, inserting bytes drawn from `alphabet`
+        fn mutate_bytes(&mut self, seed: &[u8], alphabet: &[u8]) -> Vec<u8> {
+            let mut bytes = seed.to_vec();
+            for _ in 0..(1 + self.next() % 4) {
+                if bytes.is_empty() {
+                    break;
+                }
+                let at = (self.next() as usize) % bytes.len();
+                match self.next() % 4 {
+                    0 => bytes.truncate(at),
+                    1 => bytes[at] ^= 1 << (self.next() % 8),
+                    2 => bytes.insert(at, alphabet[(self.next() as usize) % alphabet.len()]),
+                    _ => {
+                        bytes.remove(at);
+                    }
+                }
+            }
+            bytes
+        }
+
+        fn mutate(&mut self, seed: &str) -> String {
+            String::from_utf8_lossy(&self.mutate_bytes(seed.as_bytes(), b"{}[]\",:-0e9")).into_owned()
+        }
+
+        /// Binary mutation; the alphabet leans on varint continuation bits and CBOR major types
+        fn mutate_binary(&mut self, seed: &[u8]) -> Vec<u8> {
+            self.mutate_bytes(seed, &[0x00, 0x01, 0x08, 0x0a, 0x12, 0x7f, 0x80, 0x9f, 0xa1, 0xbf, 0xff])
+        }
+    }
+
+    const ROUNDS: usize = 2000;
+
+    #[test]
+    fn test_fuzz_announcements() {
+        let mut node = P2PNode::new("A".to_string(), "a".to_string(), 1);
+        let seed = r#"{"type":"announcement","peer_id":"p","device_name":"B","device_id":"b","service_port":9}"#;
+        let mut m = Mutator(0x1234_5678);
+        for _ in 0..ROUNDS {
+            let _ = node.apply_announcement(&m.mutate(seed), "10.0.0.1", 5);
+        }
+        assert!(node.apply_announcement(&"x".repeat(super::MAX_ANNOUNCEMENT_BYTES + 1), "", 0).is_err());
+    }
+
+    #[test]
+    fn test_announced_peers_are_capped() {
+        let mut node = P2PNode::new("A".to_string(), "a".to_string(), 1);
+        for i in 0..super::MAX_PEERS + 10 {
+            let json = format!(r#"{{"type":"announcement","peer_id":"p{}","device_name":"B","device_id":"b","service_port":9}}"#, i);
+            node.apply_announcement(&json, "10.0.0.1", i as u64).unwrap();
+        }
+        assert_eq!(node.peers.len(), super::MAX_PEERS);
+        // The least recently seen made room
+        assert!(!node.peers.contains_key("p0"));
+        assert!(node.peers.contains_key(&format!("p{}", super::MAX_PEERS + 9)));
+    }
+
+    #[test]
+    fn test_fuzz_wire_frames() {
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8080);
+        let mut node = P2PNode::new("A".to_string(), "a".to_string(), 1);
+        let protobuf = phone.get_announcement_frame();
+        let (envelope, _) = wire::decode_frame(&protobuf).unwrap().unwrap();
+        let cbor = wire::encode_frame_as(Encoding::Cbor, &envelope);
+        let compressed = wire::encode_session_frame(Encoding::Protobuf, true, &envelope);
+        let mut m = Mutator(0x5EED_F4A3);
+        for _ in 0..ROUNDS {
+            let frame = m.mutate_binary(&protobuf);
+            let _ = wire::decode_frame(&frame);
+            let _ = node.apply_announcement_frame(&frame, "10.0.0.1", 5);
+            let _ = wire::decode_frame_as(Encoding::Cbor, &m.mutate_binary(&cbor));
+            let _ = wire::decode_session_frame(Encoding::Protobuf, true, &m.mutate_binary(&compressed));
+            let _ = wire::wire_frame_debug_json(&m.mutate_binary(&cbor), "cbor");
+
+            let mut buffer = FrameBuffer::new(Encoding::Protobuf);
+            buffer.push(&m.mutate_binary(&protobuf));
+            buffer.push(&protobuf);
+            while let Ok(Some(_)) = buffer.next_frame() {}
+        }
+        // A length prefix past the frame limit is refused before anything is buffered for it
+        let mut huge = Vec::new();
+        prost::encode_length_delimiter(super::MAX_FRAME_BYTES + 1, &mut huge).unwrap();
+        assert!(wire::decode_frame(&huge).is_err());
+    }
+
+    #[test]
+    fn test_fuzz_fragments() {
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8080);
+        let frame = phone.get_announcement_frame();
+        let mut sender = Fragmenter::new(wire::MIN_FRAGMENT_MESSAGE_BYTES).unwrap();
+        let mut receiver = Fragmenter::new(wire::MIN_FRAGMENT_MESSAGE_BYTES).unwrap();
+        let mut m = Mutator(0x0F4A_6E00);
+        for _ in 0..ROUNDS / 10 {
+            sender.queue_frame(&frame).unwrap();
+            while let Some(message) = sender.next_message() {
+                let _ = receiver.receive_message(&m.mutate_binary(&message));
+            }
+            assert!(receiver.pending_frames() <= MAX_PENDING_FRAGMENTED);
+        }
+        // Headers that name no valid slot
+        assert!(receiver.receive_message(&[]).is_err());
+        assert!(receiver.receive_message(&[0xff; 9]).is_err());
+        // Fragment marker, message 1, fragment 0 of 0
+        assert!(receiver.receive_message(&[0xF7, 0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+        assert!(receiver.receive_message(&[0xF7, 0, 0, 0, 1, 0, 2, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn test_fuzz_handshake_frames() {
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8080);
+        let identity = DeviceIdentity::new("phone".to_string()).unwrap();
+        let seed = phone.get_handshake_frame(&identity, &KeyExchange::new());
+        let mut node = P2PNode::new("A".to_string(), "a".to_string(), 1);
+        let mut m = Mutator(0x4A4D_5348);
+        for _ in 0..ROUNDS {
+            let frame = m.mutate_binary(&seed);
+            if let Ok(handshake) = node.verify_peer_handshake(&frame) {
+                // Whatever got through still carries the signed transcript
+                assert_eq!(handshake.device_id, "phone");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz_cbor_journals() {
+        let mut journal = ChangeJournal::new();
+        journal.update_file("a.md".to_string(), b"hello", 1, "dev".to_string());
+        journal.mark_deleted("b.md".to_string(), 2, "dev".to_string());
+        let seed = journal.to_cbor();
+        let mut m = Mutator(0xCB0B_CB0B);
+        for _ in 0..ROUNDS {
+            let _ = ChangeJournal::from_cbor(&m.mutate_binary(&seed));
+        }
+    }
+
+    #[test]
+    fn test_fuzz_journal_blobs() {
+        let mut journal = ChangeJournal::new();
+        journal.update_file("a.md".to_string(), b"hello", 1, "dev".to_string());
+        journal.mark_deleted("b.md".to_string(), 2, "dev".to_string());
+        let seed = journal.to_json();
+        let mut m = Mutator(0xDEAD_BEEF);
+        for _ in 0..ROUNDS {
+            let _ = ChangeJournal::from_json(&m.mutate(&seed));
+        }
+    }
+
+    #[test]
+    fn test_fuzz_chunks() {
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let seed = manager.prepare_transfer("a.md".to_string(), b"payload", key.clone()).unwrap();
+        let seed = seed.trim_start_matches('[').trim_end_matches(']').to_string();
+        let mut m = Mutator(0xC0FF_EE00);
+        for _ in 0..ROUNDS {
+            let _ = manager.decrypt_chunk(m.mutate(&seed), key.clone());
+            let _ = manager.decrypt_chunk(seed.clone(), m.mutate(&key));
+        }
+        assert!(decrypt_data(key, b"short", b"bad nonce").is_err());
+    }
+
+    #[test]
+    fn test_fuzz_command_batches() {
+        let seed = r#"[{"op":"update_file","path":"a","content_b64":"aGk=","mtime":1},{"op":"prune_peers","current_time":0,"ttl_ms":5}]"#;
+        let mut node = P2PNode::new("A".to_string(), "a".to_string(), 1);
+        let mut m = Mutator(0xABCD_EF01);
+        for _ in 0..ROUNDS {
+            if let Ok(commands) = parse_batch(&m.mutate(seed)) {
+                node.execute_batch(commands);
+            }
+        }
+    }
+}
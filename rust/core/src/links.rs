@@ -0,0 +1,346 @@
+//! Link rewriting for renames received from peers
+//!
+//! When a note is renamed locally Obsidian updates every link to it, but a
+//! rename applied by the sync engine bypasses that. Given the old and new
+//! path and the contents of the notes that may link to it, this rewrites
+//! `[[wikilinks]]` (keeping headings, block refs and aliases) and markdown
+//! links (relative or vault-rooted), leaving code spans and fences alone.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NoteContent {
+    pub path: String,
+    pub content: String,
+}
+
+pub struct LinkRewriter {
+    old_path: String,
+    new_path: String,
+}
+
+impl LinkRewriter {
+    pub fn new(old_path: &str, new_path: &str) -> LinkRewriter {
+        LinkRewriter {
+            old_path: old_path.trim_start_matches('/').to_string(),
+            new_path: new_path.trim_start_matches('/').to_string(),
+        }
+    }
+
+    /// Rewrite links in one note; `None` if nothing changed
+    pub fn rewrite_note(&self, note_path: &str, content: &str) -> Option<String> {
+        let note_dir = dirname(note_path);
+        let mut out = String::with_capacity(content.len());
+        let mut in_fence = false;
+        let mut changed = false;
+
+        for line in content.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+                out.push_str(line);
+                continue;
+            }
+            if in_fence {
+                out.push_str(line);
+                continue;
+            }
+            let rewritten = self.rewrite_line(note_dir, line);
+            changed |= rewritten != line;
+            out.push_str(&rewritten);
+        }
+
+        if changed {
+            Some(out)
+        } else {
+            None
+        }
+    }
+
+    fn rewrite_line(&self, note_dir: &str, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('`') {
+                // Inline code: copy verbatim up to the closing backtick
+                let end = after.find('`').map(|i| i + 2).unwrap_or(rest.len());
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+            } else if let Some(after) = rest.strip_prefix("[[") {
+                match after.find("]]") {
+                    Some(end) => {
+                        out.push_str("[[");
+                        out.push_str(&self.rewrite_wikilink(&after[..end]));
+                        out.push_str("]]");
+                        rest = &after[end + 2..];
+                    }
+                    None => {
+                        out.push_str(rest);
+                        rest = "";
+                    }
+                }
+            } else if let Some(after) = rest.strip_prefix("](") {
+                match find_destination_end(after) {
+                    Some(end) => {
+                        out.push_str("](");
+                        out.push_str(&self.rewrite_markdown_destination(note_dir, &after[..end]));
+                        out.push(')');
+                        rest = &after[end + 1..];
+                    }
+                    None => {
+                        out.push_str(rest);
+                        rest = "";
+                    }
+                }
+            } else {
+                let ch = rest.chars().next().map(char::len_utf8).unwrap_or(1);
+                out.push_str(&rest[..ch]);
+                rest = &rest[ch..];
+            }
+        }
+        out
+    }
+
+    /// `target#heading|alias` or `target#^block\|alias` inside a wikilink
+    fn rewrite_wikilink(&self, inner: &str) -> String {
+        let (target_part, alias_part) = match inner.find('|') {
+            Some(i) => {
+                let (t, a) = inner.split_at(i);
+                // Table cells escape the pipe as `\|`; keep the backslash with the alias
+                match t.strip_suffix('\\') {
+                    Some(t) => (t, &inner[i - 1..]),
+                    None => (t, a),
+                }
+            }
+            None => (inner, ""),
+        };
+        let (target, subpath) = match target_part.find('#') {
+            Some(i) => target_part.split_at(i),
+            None => (target_part, ""),
+        };
+
+        match self.replacement_for_wikilink(target) {
+            Some(new_target) => format!("{}{}{}", new_target, subpath, alias_part),
+            None => inner.to_string(),
+        }
+    }
+
+    /// New target if `target` (full path, partial path, or bare name) resolves to the old path
+    fn replacement_for_wikilink(&self, target: &str) -> Option<String> {
+        let target = target.trim();
+        if target.is_empty() {
+            return None;
+        }
+        let implicit_md = !target.ends_with(".md") && self.old_path.ends_with(".md");
+        let candidate = if implicit_md { format!("{}.md", target) } else { target.to_string() };
+
+        let matches = self.old_path == candidate
+            || self.old_path.ends_with(&format!("/{}", candidate));
+        if !matches {
+            return None;
+        }
+
+        // Keep the same number of path segments the author used
+        let segments = candidate.split('/').count();
+        let new_segments: Vec<&str> = self.new_path.split('/').collect();
+        let keep = segments.min(new_segments.len());
+        let mut replacement = new_segments[new_segments.len() - keep..].join("/");
+        if implicit_md {
+            if let Some(stripped) = replacement.strip_suffix(".md") {
+                replacement = stripped.to_string();
+            }
+        }
+        if replacement == target {
+            None
+        } else {
+            Some(replacement)
+        }
+    }
+
+    /// `dest`, `<dest>` or `dest "title"` inside a markdown link
+    fn rewrite_markdown_destination(&self, note_dir: &str, raw: &str) -> String {
+        let (dest, trailer, angled) = if let Some(inner) = raw.strip_prefix('<') {
+            match inner.find('>') {
+                Some(end) => (&inner[..end], &inner[end + 1..], true),
+                None => return raw.to_string(),
+            }
+        } else {
+            match raw.find(' ') {
+                Some(i) => (&raw[..i], &raw[i..], false),
+                None => (raw, "", false),
+            }
+        };
+
+        if dest.is_empty() || dest.starts_with('#') || dest.contains("://") || dest.starts_with("mailto:") {
+            return raw.to_string();
+        }
+        let (path_part, fragment) = match dest.find('#') {
+            Some(i) => dest.split_at(i),
+            None => (dest, ""),
+        };
+        let decoded = percent_decode(path_part);
+
+        let new_dest = if decoded.starts_with('/') {
+            (normalize(&decoded) == self.old_path).then(|| format!("/{}", self.new_path))
+        } else if normalize(&join(note_dir, &decoded)) == self.old_path {
+            Some(relative_path(note_dir, &self.new_path))
+        } else if normalize(&decoded) == self.old_path {
+            Some(self.new_path.clone())
+        } else {
+            None
+        };
+
+        match new_dest {
+            Some(new_dest) if angled => format!("<{}{}>{}", new_dest, fragment, trailer),
+            Some(new_dest) => format!("{}{}{}", new_dest.replace(' ', "%20"), fragment, trailer),
+            None => raw.to_string(),
+        }
+    }
+}
+
+/// Index of the `)` closing a markdown link destination
+fn find_destination_end(s: &str) -> Option<usize> {
+    let mut in_angle = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' if i == 0 => in_angle = true,
+            '>' if in_angle => in_angle = false,
+            ')' if !in_angle => return Some(i),
+            '\n' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn dirname(path: &str) -> &str {
+    path.rfind('/').map(|i| &path[..i]).unwrap_or("")
+}
+
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", dir, path)
+    }
+}
+
+/// Resolve `.` and `..` segments; the result has no leading slash
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            s => parts.push(s),
+        }
+    }
+    parts.join("/")
+}
+
+/// Path to `target` as seen from a note in `from_dir`
+fn relative_path(from_dir: &str, target: &str) -> String {
+    let from: Vec<&str> = from_dir.split('/').filter(|s| !s.is_empty()).collect();
+    let to: Vec<&str> = target.split('/').collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<&str> = vec![".."; from.len() - common];
+    parts.extend(&to[common..]);
+    parts.join("/")
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push((hi * 16 + lo) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| s.to_string())
+}
+
+/// Rewrite links to a renamed file across a set of notes
+/// Takes a JSON array of `{path, content}` and returns only the notes that changed
+pub fn rewrite_links_for_rename(old_path: &str, new_path: &str, notes_json: &str) -> Result<String, String> {
+    let notes: Vec<NoteContent> = serde_json::from_str(notes_json)
+        .map_err(|e| format!("Invalid notes JSON: {}", e))?;
+    let rewriter = LinkRewriter::new(old_path, new_path);
+
+    let changed: Vec<NoteContent> = notes
+        .into_iter()
+        .filter_map(|note| {
+            rewriter
+                .rewrite_note(&note.path, &note.content)
+                .map(|content| NoteContent { path: note.path, content })
+        })
+        .collect();
+
+    serde_json::to_string(&changed).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(old: &str, new: &str, note: &str, content: &str) -> Option<String> {
+        LinkRewriter::new(old, new).rewrite_note(note, content)
+    }
+
+    #[test]
+    fn test_wikilinks_keep_heading_block_and_alias() {
+        let out = rewrite(
+            "Projects/Plan.md",
+            "Projects/Roadmap.md",
+            "Daily/2024-01-01.md",
+            "See [[Plan#Goals|the plan]], [[Plan#^abc123]] and ![[Projects/Plan]].\n",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "See [[Roadmap#Goals|the plan]], [[Roadmap#^abc123]] and ![[Projects/Roadmap]].\n"
+        );
+    }
+
+    #[test]
+    fn test_folder_move_keeps_bare_names() {
+        assert_eq!(rewrite("a/Note.md", "b/Note.md", "x.md", "[[Note]]"), None);
+        assert_eq!(
+            rewrite("a/Note.md", "b/Note.md", "x.md", "[[a/Note|n]]").unwrap(),
+            "[[b/Note|n]]"
+        );
+    }
+
+    #[test]
+    fn test_markdown_links_relative_and_rooted() {
+        let content = "[rel](../assets/My%20Pic.png) [root](assets/My%20Pic.png \"t\") [web](https://x.y/assets/My%20Pic.png)";
+        let out = rewrite("assets/My Pic.png", "media/Pic 2.png", "notes/a.md", content).unwrap();
+        assert_eq!(
+            out,
+            "[rel](../media/Pic%202.png) [root](media/Pic%202.png \"t\") [web](https://x.y/assets/My%20Pic.png)"
+        );
+    }
+
+    #[test]
+    fn test_code_is_left_alone() {
+        let content = "`[[Plan]]`\n```\n[[Plan]]\n```\n[[Plan]]\n";
+        let out = rewrite("Plan.md", "Roadmap.md", "x.md", content).unwrap();
+        assert_eq!(out, "`[[Plan]]`\n```\n[[Plan]]\n```\n[[Roadmap]]\n");
+    }
+
+    #[test]
+    fn test_table_escaped_alias() {
+        let out = rewrite("Plan.md", "Roadmap.md", "x.md", "| [[Plan\\|p]] |").unwrap();
+        assert_eq!(out, "| [[Roadmap\\|p]] |");
+    }
+}
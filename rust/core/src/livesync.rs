@@ -0,0 +1,273 @@
+//! obsidian-livesync document mapping
+//!
+//! LiveSync stores a vault in CouchDB as one metadata document per file
+//! (`type: "plain"` for text, `"newnote"` for binary) whose `children` list
+//! the ids of `leaf` documents holding the content chunks. Exporting to that
+//! shape lets a vault be bridged with a LiveSync database; importing it
+//! seeds the journal from an existing database without re-reading every
+//! file from disk.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::sync::{hash_content, FileMetadata};
+
+/// Device id recorded for versions imported from LiveSync
+pub const LIVESYNC_DEVICE: &str = "livesync";
+
+/// Chunk size used for exported leaves
+const LEAF_CHUNK_BYTES: usize = 100 * 1024;
+
+/// Extensions LiveSync stores as plain text
+const TEXT_EXTENSIONS: &[&str] = &["md", "txt", "canvas", "json", "css", "js", "csv"];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum LiveSyncDoc {
+    Leaf(LeafDoc),
+    Entry(EntryDoc),
+    /// Design documents, version info and anything else we don't map
+    Other(serde_json::Value),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LeafDoc {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub data: String,
+    #[serde(rename = "type")]
+    pub doc_type: LeafType,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeafType {
+    Leaf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Plain,
+    Newnote,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EntryDoc {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub path: String,
+    #[serde(default)]
+    pub ctime: u64,
+    pub mtime: u64,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(rename = "type")]
+    pub doc_type: EntryType,
+    #[serde(default)]
+    pub children: Vec<String>,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// LiveSync document id for a vault path (ids starting with `_` are reserved by CouchDB)
+pub fn doc_id(path: &str) -> String {
+    if path.starts_with('_') {
+        format!("/{}", path)
+    } else {
+        path.to_string()
+    }
+}
+
+fn entry_type(path: &str) -> EntryType {
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    if extension.is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.as_str())) {
+        EntryType::Plain
+    } else {
+        EntryType::Newnote
+    }
+}
+
+fn leaf_id(data: &[u8]) -> String {
+    format!("h:{}", &hash_content(data)[..32])
+}
+
+/// Split text into chunks of at most `LEAF_CHUNK_BYTES` on character boundaries
+fn text_chunks(mut text: &str) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+        let mut end = text.len().min(LEAF_CHUNK_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, rest) = text.split_at(end);
+        text = rest;
+        Some(chunk)
+    })
+}
+
+/// Export journal entries as LiveSync documents
+/// Files whose content is in `contents` (keyed by hash) get leaf documents;
+/// others are exported as metadata only, with no children
+pub fn export_docs(
+    files: impl Iterator<Item = FileMetadata>,
+    contents: &HashMap<String, Vec<u8>>,
+) -> Vec<LiveSyncDoc> {
+    let mut docs = Vec::new();
+    let mut leaves: HashMap<String, LeafDoc> = HashMap::new();
+    let mut files: Vec<FileMetadata> = files.collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    for meta in &files {
+        let mut doc_type = entry_type(&meta.path);
+        let mut children = Vec::new();
+        if let Some(body) = contents.get(&meta.hash).filter(|_| !meta.is_deleted) {
+            let chunks: Vec<(String, &[u8])> = match std::str::from_utf8(body) {
+                Ok(text) if doc_type == EntryType::Plain => {
+                    text_chunks(text).map(|chunk| (chunk.to_string(), chunk.as_bytes())).collect()
+                }
+                _ => {
+                    // Text that isn't valid UTF-8 has to travel as binary
+                    doc_type = EntryType::Newnote;
+                    body.chunks(LEAF_CHUNK_BYTES).map(|chunk| (BASE64.encode(chunk), chunk)).collect()
+                }
+            };
+            for (data, raw) in chunks {
+                let id = leaf_id(raw);
+                leaves.entry(id.clone()).or_insert(LeafDoc { id: id.clone(), data, doc_type: LeafType::Leaf });
+                children.push(id);
+            }
+        }
+        docs.push(LiveSyncDoc::Entry(EntryDoc {
+            id: doc_id(&meta.path),
+            path: meta.path.clone(),
+            ctime: meta.mtime,
+            mtime: meta.mtime,
+            size: meta.size,
+            doc_type,
+            children,
+            deleted: meta.is_deleted,
+        }));
+    }
+
+    let mut leaves: Vec<LeafDoc> = leaves.into_values().collect();
+    leaves.sort_by(|a, b| a.id.cmp(&b.id));
+    docs.extend(leaves.into_iter().map(LiveSyncDoc::Leaf));
+    docs
+}
+
+/// One file reassembled from LiveSync documents
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportedFile {
+    pub path: String,
+    pub mtime: u64,
+    pub deleted: bool,
+    /// `None` when deleted
+    pub content: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub deleted: usize,
+    /// Entries skipped because a leaf was missing or undecodable
+    pub incomplete: Vec<String>,
+    /// Imported content by hash (base64), for the host to write to disk
+    pub contents: HashMap<String, String>,
+}
+
+/// Reassemble file entries from a set of LiveSync documents
+/// Returns the files plus the paths that could not be reassembled
+pub fn reassemble(docs: Vec<LiveSyncDoc>) -> (Vec<ImportedFile>, Vec<String>) {
+    let mut leaves = HashMap::new();
+    let mut entries = Vec::new();
+    for doc in docs {
+        match doc {
+            LiveSyncDoc::Leaf(leaf) => {
+                leaves.insert(leaf.id, leaf.data);
+            }
+            LiveSyncDoc::Entry(entry) => entries.push(entry),
+            LiveSyncDoc::Other(_) => {}
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut incomplete = Vec::new();
+    for entry in entries {
+        if entry.deleted {
+            files.push(ImportedFile { path: entry.path, mtime: entry.mtime, deleted: true, content: None });
+            continue;
+        }
+        let mut content = Vec::with_capacity(entry.size as usize);
+        let complete = entry.children.iter().all(|id| match (leaves.get(id), entry.doc_type) {
+            (Some(data), EntryType::Plain) => {
+                content.extend_from_slice(data.as_bytes());
+                true
+            }
+            (Some(data), EntryType::Newnote) => match BASE64.decode(data) {
+                Ok(bytes) => {
+                    content.extend_from_slice(&bytes);
+                    true
+                }
+                Err(_) => false,
+            },
+            (None, _) => false,
+        });
+        if complete {
+            files.push(ImportedFile { path: entry.path, mtime: entry.mtime, deleted: false, content: Some(content) });
+        } else {
+            incomplete.push(entry.path);
+        }
+    }
+    (files, incomplete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(path: &str, content: &[u8]) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: hash_content(content),
+            mtime: 1000,
+            size: content.len() as u64,
+            version: 1,
+            is_deleted: false,
+            last_modified_by: "dev".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let note = b"# Note\n".to_vec();
+        let image = vec![0u8, 159, 146, 150];
+        let files = [meta("_inbox/note.md", &note), meta("img.png", &image)];
+        let contents: HashMap<String, Vec<u8>> =
+            files.iter().zip([note.clone(), image.clone()]).map(|(m, c)| (m.hash.clone(), c)).collect();
+
+        let docs = export_docs(files.iter().cloned(), &contents);
+        assert_eq!(docs.len(), 4);
+        let LiveSyncDoc::Entry(first) = &docs[0] else { panic!("expected entry") };
+        assert_eq!(first.id, "/_inbox/note.md");
+        assert_eq!(first.doc_type, EntryType::Plain);
+
+        let json = serde_json::to_string(&docs).unwrap();
+        let (imported, incomplete) = reassemble(serde_json::from_str(&json).unwrap());
+        assert!(incomplete.is_empty());
+        assert_eq!(imported[0].content.as_deref(), Some(&note[..]));
+        assert_eq!(imported[1].content.as_deref(), Some(&image[..]));
+
+        let long = "é".repeat(LEAF_CHUNK_BYTES);
+        assert!(text_chunks(&long).all(|chunk| chunk.len() <= LEAF_CHUNK_BYTES));
+        assert_eq!(text_chunks(&long).collect::<String>(), long);
+
+        let without_leaves: Vec<LiveSyncDoc> = docs.into_iter().take(1).collect();
+        assert_eq!(reassemble(without_leaves).1, vec!["_inbox/note.md".to_string()]);
+    }
+}
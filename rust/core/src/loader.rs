@@ -0,0 +1,297 @@
+//! Streaming journal loader
+//!
+//! Parsing a persisted journal of a large vault in one `from_json` call holds
+//! up plugin startup. The loader accepts the same JSON blob in segments of
+//! any size and inserts each file entry into the journal as soon as its
+//! closing brace arrives, so lookups for already-loaded paths work while the
+//! rest is still being read. The sequence counter and backup schedule (and
+//! the history, in journals saved with it inline) are applied once the blob is
+//! complete.
+
+use serde::Serialize;
+
+use crate::sync::ChangeJournal;
+
+use crate::sync::FileMetadata;
+use crate::limits::MAX_JOURNAL_BYTES;
+
+/// Bytes of consumed input kept before compacting the buffer
+const COMPACT_AFTER_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+    Start,
+    /// Expecting a top-level key, or the end of the object
+    TopKey,
+    TopColon(String),
+    TopValue(String),
+    TopComma,
+    FilesOpen,
+    /// Expecting a path, or the end of the files map
+    FilesKey,
+    FilesColon(String),
+    FilesValue(String),
+    FilesComma,
+    Done,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub bytes_received: usize,
+    pub files_loaded: usize,
+    pub complete: bool,
+}
+
+pub struct JournalLoader {
+    buf: String,
+    pos: usize,
+    state: State,
+    /// Top-level fields other than `files`, as raw JSON, applied at the end
+    tail: Vec<(String, String)>,
+    progress: LoadProgress,
+}
+
+/// End of the JSON string starting at `start` (just past the closing quote)
+fn scan_string(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// End of the JSON value starting at `start`, or `None` if it isn't complete yet
+fn scan_value(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes[start] {
+        b'"' => scan_string(bytes, start),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut i = start;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'"' => {
+                        i = scan_string(bytes, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            None
+        }
+        // Scalars end at the next delimiter, which is always present inside an object
+        _ => bytes[start..]
+            .iter()
+            .position(|b| matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace())
+            .map(|len| start + len),
+    }
+}
+
+impl JournalLoader {
+    pub fn new() -> JournalLoader {
+        JournalLoader {
+            buf: String::new(),
+            pos: 0,
+            state: State::Start,
+            tail: Vec::new(),
+            progress: LoadProgress::default(),
+        }
+    }
+
+    pub fn progress(&self) -> &LoadProgress {
+        &self.progress
+    }
+
+    /// Feed the next segment; entries completed by it are inserted into `journal`
+    /// Returns true once the whole blob has been applied
+    pub fn push(&mut self, segment: &str, journal: &mut ChangeJournal) -> Result<bool, String> {
+        if self.progress.complete {
+            return Err("Journal already loaded".to_string());
+        }
+        self.progress.bytes_received += segment.len();
+        if self.progress.bytes_received > MAX_JOURNAL_BYTES {
+            return Err(format!("Journal too large: over {} bytes", MAX_JOURNAL_BYTES));
+        }
+        self.buf.push_str(segment);
+        self.advance(journal)?;
+        if self.pos > COMPACT_AFTER_BYTES {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        if self.state == State::Done && !self.progress.complete {
+            self.finish(journal)?;
+        }
+        Ok(self.progress.complete)
+    }
+
+    fn expect(&mut self, byte: u8, next: State) -> Result<(), String> {
+        if self.buf.as_bytes()[self.pos] != byte {
+            return Err(format!("Invalid journal: expected '{}' at byte {}", byte as char, self.position()));
+        }
+        self.pos += 1;
+        self.state = next;
+        Ok(())
+    }
+
+    fn position(&self) -> usize {
+        self.progress.bytes_received - (self.buf.len() - self.pos)
+    }
+
+    /// Decode the key string at the cursor, or `None` if it isn't complete yet
+    fn take_key(&mut self) -> Result<Option<String>, String> {
+        if self.buf.as_bytes()[self.pos] != b'"' {
+            return Err(format!("Invalid journal: expected a key at byte {}", self.position()));
+        }
+        let Some(end) = scan_string(self.buf.as_bytes(), self.pos) else {
+            return Ok(None);
+        };
+        let key: String = serde_json::from_str(&self.buf[self.pos..end]).map_err(|e| format!("Invalid journal: {}", e))?;
+        self.pos = end;
+        Ok(Some(key))
+    }
+
+    fn advance(&mut self, journal: &mut ChangeJournal) -> Result<(), String> {
+        loop {
+            let bytes = self.buf.as_bytes();
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos == bytes.len() {
+                return Ok(());
+            }
+            let byte = bytes[self.pos];
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::Start => self.expect(b'{', State::TopKey)?,
+                State::TopKey if byte == b'}' => self.expect(b'}', State::Done)?,
+                State::TopKey => match self.take_key()? {
+                    Some(key) => self.state = State::TopColon(key),
+                    None => {
+                        self.state = State::TopKey;
+                        return Ok(());
+                    }
+                },
+                State::TopColon(key) if key == "files" => self.expect(b':', State::FilesOpen)?,
+                State::TopColon(key) => self.expect(b':', State::TopValue(key))?,
+                State::TopValue(key) => {
+                    let Some(end) = scan_value(bytes, self.pos) else {
+                        self.state = State::TopValue(key);
+                        return Ok(());
+                    };
+                    self.tail.push((key, self.buf[self.pos..end].to_string()));
+                    self.pos = end;
+                    self.state = State::TopComma;
+                }
+                State::TopComma if byte == b'}' => self.expect(b'}', State::Done)?,
+                State::TopComma => self.expect(b',', State::TopKey)?,
+                State::FilesOpen => self.expect(b'{', State::FilesKey)?,
+                State::FilesKey if byte == b'}' => self.expect(b'}', State::TopComma)?,
+                State::FilesKey => match self.take_key()? {
+                    Some(path) => self.state = State::FilesColon(path),
+                    None => {
+                        self.state = State::FilesKey;
+                        return Ok(());
+                    }
+                },
+                State::FilesColon(path) => self.expect(b':', State::FilesValue(path))?,
+                State::FilesValue(path) => {
+                    let Some(end) = scan_value(bytes, self.pos) else {
+                        self.state = State::FilesValue(path);
+                        return Ok(());
+                    };
+                    let mut meta: FileMetadata = serde_json::from_str(&self.buf[self.pos..end])
+                        .map_err(|e| format!("Invalid journal entry {}: {}", path, e))?;
+                    meta.path = path;
+                    journal.restore_file(meta);
+                    self.progress.files_loaded += 1;
+                    self.pos = end;
+                    self.state = State::FilesComma;
+                }
+                State::FilesComma if byte == b'}' => self.expect(b'}', State::TopComma)?,
+                State::FilesComma => self.expect(b',', State::FilesKey)?,
+                State::Done => {
+                    return Err(format!("Invalid journal: trailing data at byte {}", self.position()));
+                }
+            }
+        }
+    }
+
+    /// Apply the sequence counter, backups and any inline history
+    fn finish(&mut self, journal: &mut ChangeJournal) -> Result<(), String> {
+        let fields: Vec<String> = self
+            .tail
+            .iter()
+            .map(|(key, raw)| format!("{}:{}", serde_json::to_string(key).unwrap_or_default(), raw))
+            .collect();
+        let tail = ChangeJournal::from_json(&format!("{{{}}}", fields.join(",")))?;
+        journal.restore_tail(tail);
+        self.tail.clear();
+        self.buf = String::new();
+        self.pos = 0;
+        self.progress.complete = true;
+        Ok(())
+    }
+}
+
+impl Default for JournalLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> ChangeJournal {
+        let mut journal = ChangeJournal::new();
+        for i in 0..50 {
+            journal.update_file(format!("Notes/\"quoted\" {{{}}}.md", i), format!("body {}", i).as_bytes(), i, "laptop".to_string());
+        }
+        journal.mark_deleted("Notes/\"quoted\" {7}.md".to_string(), 99, "phone".to_string());
+        journal
+    }
+
+    #[test]
+    fn test_segments_become_queryable_before_completion() {
+        let source = sample();
+        let blob = source.to_json();
+        let mut journal = ChangeJournal::new();
+        let mut loader = JournalLoader::new();
+        let mut seen_partial = false;
+        for segment in blob.as_bytes().chunks(97) {
+            let done = loader.push(std::str::from_utf8(segment).unwrap(), &mut journal).unwrap();
+            if !done && journal.files().count() > 0 {
+                seen_partial = true;
+            }
+        }
+        assert!(seen_partial);
+        assert!(loader.progress().complete);
+        assert_eq!(loader.progress().files_loaded, 50);
+        assert_eq!(journal.sequence(), source.sequence());
+        // History is saved on its own, not in the blob
+        assert!(!blob.contains("\"history\""));
+        assert_eq!(journal.history().count(), 0);
+        assert_eq!(journal.get("Notes/\"quoted\" {7}.md"), source.get("Notes/\"quoted\" {7}.md"));
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        let mut journal = ChangeJournal::new();
+        assert!(JournalLoader::new().push("[]", &mut journal).is_err());
+        assert!(JournalLoader::new().push(r#"{"files": {"a.md" 1}}"#, &mut journal).is_err());
+        assert!(JournalLoader::new().push(r#"{"files": {"a.md": {"hash": 1}}}"#, &mut journal).is_err());
+        let mut loader = JournalLoader::new();
+        assert!(loader.push(r#"{"files": {}, "global_sequence": 3} x"#, &mut journal).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! In-memory loopback transport
+//!
+//! Connects two `P2PNode`s directly, each with an in-memory vault, so the
+//! whole path from pairing to a verified commit runs inside `cargo test`
+//! without sockets. Frames are the same bytes a host would put on the wire;
+//! the link can lose them and delay them by a latency drawn from a seeded
+//! generator, and the flows resend a lost frame the way a reliable
+//! transport would, so a lossy run still completes and reproduces from its
+//! seed. Like `sim`, a loopback pins the crate clock while it lives.
+//!
+//! The flows do what the plugin host does:
+//! - `pair`: one side shows a code, the other sends it back
+//! - `handshake`: signed handshake frames both ways, session keys derived
+//!   and confirmations checked
+//! - `sync`: the sender's new entries go over as a manifest frame, the
+//!   receiver stages them as a round, each body it awaits is sent as
+//!   encrypted chunk frames under the session key, and the round commits
+//!   once every body has verified; the receiver's vault then carries out the
+//!   operations
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::clock;
+use crate::crypto::{DeviceIdentity, KeyExchange};
+use crate::round::{CommitOp, RoundAction};
+use crate::sim::SimRng;
+use crate::streams::encode_chunk_frame;
+use crate::sync::FileMetadata;
+use crate::transfer::{IncomingTransfer, TransferManager, DEFAULT_REORDER_CHUNKS};
+use crate::wire::{self, Body, Envelope};
+use crate::P2PNode;
+
+/// Sends of one frame before a flow gives up on the link
+const MAX_ATTEMPTS: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::A => 0,
+            Side::B => 1,
+        }
+    }
+
+    pub fn other(self) -> Side {
+        match self {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoopbackConfig {
+    pub seed: u64,
+    /// Probability in [0, 1] that a frame is lost
+    pub loss_rate: f64,
+    pub min_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub start_time_ms: u64,
+}
+
+impl Default for LoopbackConfig {
+    fn default() -> Self {
+        LoopbackConfig { seed: 1, loss_rate: 0.0, min_latency_ms: 0, max_latency_ms: 0, start_time_ms: 1_000_000 }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoopbackStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    /// Frames sent again after a loss
+    pub resent: u64,
+    pub bytes: u64,
+}
+
+struct Peer {
+    node: P2PNode,
+    identity: DeviceIdentity,
+    /// Path → content, as the host's vault would hold it
+    vault: BTreeMap<String, Vec<u8>>,
+    inbox: VecDeque<Vec<u8>>,
+    /// Journal sequence of this side already sent to the other
+    sent_sequence: u64,
+    session_key: Option<String>,
+}
+
+pub struct Loopback {
+    config: LoopbackConfig,
+    rng: SimRng,
+    now: u64,
+    next_seq: u64,
+    peers: [Peer; 2],
+    /// (deliver_at, seq) → (receiver, frame)
+    in_flight: BTreeMap<(u64, u64), (Side, Vec<u8>)>,
+    stats: LoopbackStats,
+}
+
+impl Loopback {
+    pub fn new(config: LoopbackConfig) -> Loopback {
+        clock::set_clock_time(config.start_time_ms);
+        let peer = |name: &str, device_id: &str, port| Peer {
+            node: P2PNode::new(name.to_string(), device_id.to_string(), port),
+            identity: DeviceIdentity::new(device_id.to_string()).expect("fresh identity"),
+            vault: BTreeMap::new(),
+            inbox: VecDeque::new(),
+            sent_sequence: 0,
+            session_key: None,
+        };
+        Loopback {
+            now: config.start_time_ms,
+            rng: SimRng::new(config.seed),
+            next_seq: 0,
+            peers: [peer("Loopback A", "loopback-a", 8000), peer("Loopback B", "loopback-b", 8001)],
+            in_flight: BTreeMap::new(),
+            stats: LoopbackStats::default(),
+            config,
+        }
+    }
+
+    pub fn node(&self, side: Side) -> &P2PNode {
+        &self.peers[side.index()].node
+    }
+
+    pub fn node_mut(&mut self, side: Side) -> &mut P2PNode {
+        &mut self.peers[side.index()].node
+    }
+
+    pub fn vault(&self, side: Side) -> &BTreeMap<String, Vec<u8>> {
+        &self.peers[side.index()].vault
+    }
+
+    pub fn stats(&self) -> &LoopbackStats {
+        &self.stats
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Address a side's frames appear to come from
+    pub fn address_of(side: Side) -> String {
+        format!("127.0.0.{}", side.index() + 1)
+    }
+
+    /// Write a file into a side's vault and journal, as a local edit
+    pub fn write(&mut self, side: Side, path: &str, content: &[u8]) {
+        let peer = &mut self.peers[side.index()];
+        peer.vault.insert(path.to_string(), content.to_vec());
+        peer.node.update_file(path.to_string(), content, self.now);
+    }
+
+    pub fn delete(&mut self, side: Side, path: &str) {
+        let peer = &mut self.peers[side.index()];
+        peer.vault.remove(path);
+        peer.node.mark_file_deleted(path.to_string(), self.now);
+    }
+
+    pub fn rename(&mut self, side: Side, from: &str, to: &str) {
+        let peer = &mut self.peers[side.index()];
+        if let Some(content) = peer.vault.remove(from) {
+            peer.vault.insert(to.to_string(), content);
+        }
+        peer.node.rename_file(from.to_string(), to.to_string(), self.now);
+    }
+
+    /// Put a frame on the link towards the other side; it may be lost
+    pub fn send(&mut self, from: Side, frame: Vec<u8>) {
+        self.stats.sent += 1;
+        self.stats.bytes += frame.len() as u64;
+        if self.rng.next_f64() < self.config.loss_rate {
+            self.stats.dropped += 1;
+            return;
+        }
+        let latency = self.rng.range(self.config.min_latency_ms, self.config.max_latency_ms);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.in_flight.insert((self.now + latency, seq), (from.other(), frame));
+    }
+
+    /// Next frame delivered to `side`
+    pub fn receive(&mut self, side: Side) -> Option<Vec<u8>> {
+        self.peers[side.index()].inbox.pop_front()
+    }
+
+    /// Advance the clock, delivering frames that fall due
+    pub fn advance(&mut self, delta_ms: u64) {
+        let target = self.now + delta_ms;
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 > target {
+                break;
+            }
+            let ((deliver_at, _), (to, frame)) = entry.remove_entry();
+            self.set_now(deliver_at);
+            self.stats.delivered += 1;
+            self.peers[to.index()].inbox.push_back(frame);
+        }
+        self.set_now(target);
+    }
+
+    fn set_now(&mut self, now: u64) {
+        self.now = now;
+        clock::set_clock_time(now);
+    }
+
+    /// Send `frame` until it arrives, returning it as the other side received it
+    pub fn deliver(&mut self, from: Side, frame: Vec<u8>) -> Result<Vec<u8>, String> {
+        let to = from.other();
+        for attempt in 0..MAX_ATTEMPTS {
+            if attempt > 0 {
+                self.stats.resent += 1;
+            }
+            self.send(from, frame.clone());
+            self.advance(self.config.max_latency_ms);
+            if let Some(received) = self.receive(to) {
+                return Ok(received);
+            }
+        }
+        Err(format!("Frame lost {} times in a row", MAX_ATTEMPTS))
+    }
+
+    /// `responder` shows a pairing code and `initiator` sends it back
+    pub fn pair(&mut self, initiator: Side) -> Result<(), String> {
+        let code = self.node_mut(initiator.other()).start_pairing(0);
+        let received = self.deliver(initiator, code.into_bytes())?;
+        let code = String::from_utf8(received).map_err(|e| e.to_string())?;
+        let source = Self::address_of(initiator);
+        let outcome = self.node_mut(initiator.other()).check_pairing_attempt(&source, &code);
+        if !outcome.contains("accepted") {
+            return Err(format!("Pairing refused: {}", outcome));
+        }
+        Ok(())
+    }
+
+    /// Exchange handshakes and derive the session key both sides use for transfers
+    pub fn handshake(&mut self) -> Result<(), String> {
+        let sessions = [KeyExchange::new(), KeyExchange::new()];
+        let mut frames = Vec::new();
+        for side in [Side::A, Side::B] {
+            let peer = &self.peers[side.index()];
+            frames.push(peer.node.get_handshake_frame(&peer.identity, &sessions[side.index()]));
+        }
+        let mut keys = Vec::new();
+        for side in [Side::A, Side::B] {
+            let received = self.deliver(side.other(), frames[side.other().index()].clone())?;
+            let node = self.node_mut(side);
+            node.apply_handshake_frame(&received)?;
+            keys.push(node.session_keys(&sessions[side.index()], &frames[side.index()], &received)?);
+        }
+        for side in [Side::A, Side::B] {
+            let confirmation = BASE64.encode(keys[side.other().index()].confirmation);
+            let received = self.deliver(side.other(), confirmation.into_bytes())?;
+            let received = String::from_utf8(received).map_err(|e| e.to_string())?;
+            let expected = BASE64.encode(keys[side.index()].expected_confirmation);
+            let peer_id = self.peers[side.other().index()].identity.get_device_id();
+            if !self.node_mut(side).check_session_confirmation(&peer_id, &expected, &received) {
+                return Err("Session confirmation mismatch".to_string());
+            }
+            self.peers[side.index()].session_key = Some(BASE64.encode(keys[side.index()].session_key));
+        }
+        Ok(())
+    }
+
+    /// Send `from`'s changes since the last sync to the other side and commit them there;
+    /// returns the operations the receiver's vault carried out
+    pub fn sync(&mut self, from: Side) -> Result<Vec<CommitOp>, String> {
+        let to = from.other();
+        let key = self.peers[from.index()].session_key.clone().ok_or("No session; run handshake first")?;
+        let sender = &self.peers[from.index()];
+        let since = sender.sent_sequence;
+        let sequence = sender.node.change_journal.sequence();
+        let mut entries: Vec<FileMetadata> = sender.node.change_journal.files().filter(|m| m.version > since).collect();
+        entries.sort_by_key(|m| m.version);
+        let manifest = wire::Manifest { since_sequence: since, sequence, entries: entries.iter().map(Into::into).collect() };
+        let frame = wire::encode_frame(&Envelope::new(Body::Manifest(manifest)));
+
+        let received = self.deliver(from, frame)?;
+        let Some((Envelope { body: Some(Body::Manifest(manifest)), .. }, _)) = wire::decode_frame(&received)? else {
+            return Err("Expected a manifest frame".to_string());
+        };
+        let changes: Vec<FileMetadata> = manifest.entries.into_iter().map(Into::into).collect();
+        let changes_json = serde_json::to_string(&changes).map_err(|e| e.to_string())?;
+        let peer_id = self.peers[from.index()].identity.get_device_id();
+        let status = self.node_mut(to).prepare_round(&changes_json, &peer_id)?;
+
+        for path in status.awaiting {
+            let content = self.transfer(from, &path, &key)?;
+            let receiver = self.node_mut(to);
+            let verified = receiver.sync_round.as_mut().ok_or("Round ended")?.verify_content(&path, &content);
+            if let Err(e) = verified {
+                receiver.abort_sync_round();
+                return Err(e.into());
+            }
+            self.peers[to.index()].vault.insert(format!("{}.staged", path), content);
+        }
+        let ops = self.node_mut(to).commit_round()?;
+        self.apply_ops(to, &ops);
+        self.peers[from.index()].sent_sequence = sequence;
+        Ok(ops)
+    }
+
+    /// Send one body from `from`'s vault as encrypted chunk frames
+    fn transfer(&mut self, from: Side, path: &str, key: &str) -> Result<Vec<u8>, String> {
+        let content = self.peers[from.index()].vault.get(path).cloned();
+        let content = content.ok_or_else(|| format!("{} is not in the vault", path))?;
+        let hash = self.node(from).change_journal.get(path).map(|m| m.hash).unwrap_or_default();
+        let transfer_id = format!("{}-{}", self.now, path);
+        let manager = TransferManager::new(transfer_id.clone(), hash.clone())?;
+        let chunks = manager.prepare_transfer(path.to_string(), &content, key.to_string())?;
+        let chunks: Vec<serde_json::Value> = serde_json::from_str(&chunks).map_err(|e| e.to_string())?;
+        let mut incoming = IncomingTransfer::new(
+            transfer_id,
+            path.to_string(),
+            hash,
+            chunks.len() as u32,
+            key.to_string(),
+            DEFAULT_REORDER_CHUNKS,
+        )?;
+        let mut body = Vec::with_capacity(content.len());
+        for chunk in chunks {
+            let frame = encode_chunk_frame(&chunk.to_string(), 0)?;
+            let received = self.deliver(from, frame)?;
+            incoming.accept_chunk_frame(&received)?;
+            body.extend(incoming.take_ready());
+        }
+        if !incoming.is_complete() {
+            return Err(format!("Transfer of {} ended early", path));
+        }
+        Ok(body)
+    }
+
+    fn apply_ops(&mut self, side: Side, ops: &[CommitOp]) {
+        let vault = &mut self.peers[side.index()].vault;
+        for op in ops {
+            match op.action {
+                RoundAction::Write => {
+                    if let Some(content) = vault.remove(&format!("{}.staged", op.path)) {
+                        vault.insert(op.path.clone(), content);
+                    }
+                }
+                RoundAction::Delete => {
+                    vault.remove(&op.path);
+                }
+                RoundAction::Move => {
+                    if let Some(content) = op.from.as_ref().and_then(|from| vault.remove(from)) {
+                        vault.insert(op.path.clone(), content);
+                    }
+                }
+                RoundAction::Metadata | RoundAction::Placeholder => {}
+            }
+        }
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        clock::use_system_clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_handshake_and_sync_over_lossy_link() {
+        let config = LoopbackConfig { seed: 7, loss_rate: 0.3, min_latency_ms: 5, max_latency_ms: 40, ..Default::default() };
+        let mut link = Loopback::new(config);
+        link.pair(Side::A).unwrap();
+        link.handshake().unwrap();
+
+        let large: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        link.write(Side::A, "notes/a.md", b"hello");
+        link.write(Side::A, "attachments/large.bin", &large);
+        let ops = link.sync(Side::A).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(link.vault(Side::B), link.vault(Side::A));
+        assert_eq!(link.node(Side::B).change_journal.get("notes/a.md").unwrap().hash, crate::sync::hash_content(b"hello"));
+
+        link.rename(Side::A, "notes/a.md", "notes/b.md");
+        link.delete(Side::A, "attachments/large.bin");
+        let ops = link.sync(Side::A).unwrap();
+        let actions: Vec<RoundAction> = ops.iter().map(|op| op.action).collect();
+        assert_eq!(actions, vec![RoundAction::Delete, RoundAction::Move]);
+        assert_eq!(link.vault(Side::B).keys().collect::<Vec<_>>(), vec!["notes/b.md"]);
+        assert!(link.stats().dropped > 0);
+        assert_eq!(link.stats().dropped, link.stats().resent);
+    }
+
+    #[test]
+    fn test_sync_needs_a_session() {
+        let mut link = Loopback::new(LoopbackConfig::default());
+        link.write(Side::B, "a.md", b"a");
+        assert!(link.sync(Side::B).is_err());
+        link.pair(Side::A).unwrap();
+        link.handshake().unwrap();
+        assert_eq!(link.sync(Side::B).unwrap().len(), 1);
+        // Nothing new to send
+        assert!(link.sync(Side::B).unwrap().is_empty());
+        assert_eq!(link.stats().resent, 0);
+    }
+}
@@ -0,0 +1,233 @@
+//! Relay mailbox bundles
+//!
+//! Two devices that are never online at the same time exchange changes
+//! through a store-and-forward mailbox: a shared folder, a dumb relay, a USB
+//! stick. A bundle carries a journal delta and the file bodies it needs,
+//! signed by the sender's device identity and encrypted to the recipient's
+//! long-lived X25519 mailbox key with an ephemeral key agreement. Only the
+//! recipient fingerprint is visible in the clear, so the carrier can route
+//! bundles but learns nothing else; the plaintext is padded to a size bucket
+//! so the bundle length reveals little about how much changed.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::crypto::{verify_signature, DeviceIdentity, KeyExchange};
+use crate::limits::MAX_JOURNAL_BYTES;
+use crate::sync::FileMetadata;
+
+const MAGIC: &[u8] = b"P2PMBX1\0";
+const KDF_INFO: &[u8] = b"obsidian-p2p-sync mailbox v1";
+const NONCE_LEN: usize = 12;
+const MIN_PADDED_BYTES: usize = 1024;
+const PAD_STEP_BYTES: usize = 1024 * 1024;
+
+/// Decrypted bundle contents
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MailboxBody {
+    pub sender_device_id: String,
+    /// Ed25519 identity key (base64) the bundle is signed with
+    pub sender_public_key: String,
+    pub created_at: u64,
+    /// Entries are changes after this journal sequence
+    pub since_sequence: u64,
+    pub entries: Vec<FileMetadata>,
+    /// Content hash → base64 body
+    pub contents: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedBody {
+    body: String,
+    signature: String,
+}
+
+/// Public fingerprint a bundle is addressed to (hex, 32 chars)
+pub fn mailbox_fingerprint(public_key: &[u8; 32]) -> String {
+    hex::encode(&Sha256::digest(public_key)[..16])
+}
+
+/// Plaintext size after padding: powers of two up to 1 MiB, then whole MiBs
+fn padded_len(len: usize) -> usize {
+    if len <= PAD_STEP_BYTES {
+        len.next_power_of_two().max(MIN_PADDED_BYTES)
+    } else {
+        len.div_ceil(PAD_STEP_BYTES) * PAD_STEP_BYTES
+    }
+}
+
+fn bundle_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Result<Aes256Gcm, String> {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(recipient);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(KDF_INFO, &mut key)
+        .map_err(|e| e.to_string())?;
+    Aes256Gcm::new_from_slice(&key).map_err(|e| e.to_string())
+}
+
+fn decode_key(key_b64: &str) -> Result<[u8; 32], String> {
+    let bytes = BASE64.decode(key_b64).map_err(|e| e.to_string())?;
+    bytes.try_into().map_err(|_| "Invalid key length".to_string())
+}
+
+/// A bundle split into its parts
+struct RawBundle<'a> {
+    /// Everything before the nonce; authenticated as associated data
+    header: &'a [u8],
+    fingerprint: &'a str,
+    ephemeral: [u8; 32],
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+fn parse_header(data: &[u8]) -> Result<RawBundle<'_>, String> {
+    let rest = data.strip_prefix(MAGIC).ok_or("Not a mailbox bundle")?;
+    let (&fp_len, rest) = rest.split_first().ok_or("Truncated bundle")?;
+    let fp_len = fp_len as usize;
+    if rest.len() < fp_len + 32 + NONCE_LEN {
+        return Err("Truncated bundle".to_string());
+    }
+    let fingerprint = std::str::from_utf8(&rest[..fp_len]).map_err(|_| "Invalid recipient fingerprint")?;
+    let ephemeral: [u8; 32] = rest[fp_len..fp_len + 32].try_into().map_err(|_| "Truncated bundle")?;
+    let header_len = MAGIC.len() + 1 + fp_len + 32;
+    let (nonce, ciphertext) = data[header_len..].split_at(NONCE_LEN);
+    Ok(RawBundle { header: &data[..header_len], fingerprint, ephemeral, nonce, ciphertext })
+}
+
+/// Sign, pad and encrypt a bundle for the holder of `recipient_public_key_b64`
+pub fn seal_bundle(identity: &DeviceIdentity, recipient_public_key_b64: &str, body: &MailboxBody) -> Result<Vec<u8>, String> {
+    let recipient = decode_key(recipient_public_key_b64)?;
+    let body_json = serde_json::to_string(body).map_err(|e| e.to_string())?;
+    let signature = identity.sign(body_json.as_bytes());
+    let signed = serde_json::to_vec(&SignedBody { body: body_json, signature }).map_err(|e| e.to_string())?;
+
+    let mut plaintext = Vec::with_capacity(padded_len(signed.len() + 4));
+    plaintext.extend_from_slice(&(signed.len() as u32).to_be_bytes());
+    plaintext.extend_from_slice(&signed);
+    plaintext.resize(padded_len(signed.len() + 4), 0);
+
+    let ephemeral = KeyExchange::new();
+    let ephemeral_public = ephemeral.public_key_bytes();
+    let cipher = bundle_key(&ephemeral.shared_secret_bytes(recipient), &ephemeral_public, &recipient)?;
+
+    let fingerprint = mailbox_fingerprint(&recipient);
+    let mut out = Vec::with_capacity(MAGIC.len() + 1 + fingerprint.len() + 32 + NONCE_LEN + plaintext.len() + 16);
+    out.extend_from_slice(MAGIC);
+    out.push(fingerprint.len() as u8);
+    out.extend_from_slice(fingerprint.as_bytes());
+    out.extend_from_slice(&ephemeral_public);
+    let header_len = out.len();
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &out[..header_len] })
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt a bundle with the recipient's mailbox key and verify the sender's signature
+pub fn open_bundle(recipient: &KeyExchange, data: &[u8]) -> Result<MailboxBody, String> {
+    if data.len() > MAX_JOURNAL_BYTES {
+        return Err(format!("Mailbox bundle too large: {} bytes (limit {})", data.len(), MAX_JOURNAL_BYTES));
+    }
+    let raw = parse_header(data)?;
+    let own = recipient.public_key_bytes();
+    if raw.fingerprint != mailbox_fingerprint(&own) {
+        return Err("Bundle is addressed to another device".to_string());
+    }
+    let cipher = bundle_key(&recipient.shared_secret_bytes(raw.ephemeral), &raw.ephemeral, &own)?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(raw.nonce), Payload { msg: raw.ciphertext, aad: raw.header })
+        .map_err(|_| "Bundle could not be decrypted".to_string())?;
+
+    let len_bytes: [u8; 4] = plaintext.get(..4).and_then(|b| b.try_into().ok()).ok_or("Truncated bundle")?;
+    let signed = plaintext.get(4..4 + u32::from_be_bytes(len_bytes) as usize).ok_or("Truncated bundle")?;
+    let signed: SignedBody = serde_json::from_slice(signed).map_err(|e| format!("Invalid bundle: {}", e))?;
+    let body: MailboxBody = serde_json::from_str(&signed.body).map_err(|e| format!("Invalid bundle: {}", e))?;
+    if !verify_signature(body.sender_public_key.clone(), signed.body.as_bytes(), signed.signature) {
+        return Err("Bundle signature is invalid".to_string());
+    }
+    Ok(body)
+}
+
+/// Recipient fingerprint of a bundle, readable without decrypting (for routing)
+pub fn mailbox_bundle_recipient(data: &[u8]) -> Option<String> {
+    parse_header(data).ok().map(|raw| raw.fingerprint.to_string())
+}
+
+/// Fingerprint bundles for this mailbox key are addressed to
+pub fn mailbox_key_fingerprint(key: &KeyExchange) -> String {
+    mailbox_fingerprint(&key.public_key_bytes())
+}
+
+/// Decrypt and verify a bundle; returns `{sender_device_id, sender_public_key, created_at, since_sequence, entries, contents}`
+pub fn open_mailbox_bundle(recipient: &KeyExchange, data: &[u8]) -> Result<String, String> {
+    let body = open_bundle(recipient, data)?;
+    serde_json::to_string(&body).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(identity: &DeviceIdentity) -> MailboxBody {
+        MailboxBody {
+            sender_device_id: identity.get_device_id(),
+            sender_public_key: identity.get_public_key(),
+            created_at: 1,
+            since_sequence: 0,
+            entries: Vec::new(),
+            contents: [("h".to_string(), BASE64.encode(b"note"))].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let sender = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let mailbox = KeyExchange::new();
+        let bundle = seal_bundle(&sender, &mailbox.get_public_key(), &body(&sender)).unwrap();
+
+        assert_eq!(mailbox_bundle_recipient(&bundle), Some(mailbox_key_fingerprint(&mailbox)));
+        assert!(bundle.len() >= MIN_PADDED_BYTES);
+        assert!(!bundle.windows(6).any(|w| w == b"laptop"));
+
+        let wrapping = BASE64.encode([3u8; 32]);
+        let saved = mailbox.export_wrapped_secret_key(wrapping.clone()).unwrap();
+        let restored = KeyExchange::from_wrapped_secret_key(saved, wrapping).unwrap();
+        assert_eq!(open_bundle(&restored, &bundle).unwrap(), body(&sender));
+    }
+
+    #[test]
+    fn test_bundle_rejects_wrong_recipient_and_tampering() {
+        let sender = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let mailbox = KeyExchange::new();
+        let bundle = seal_bundle(&sender, &mailbox.get_public_key(), &body(&sender)).unwrap();
+
+        assert!(open_bundle(&KeyExchange::new(), &bundle).unwrap_err().contains("another device"));
+        let mut tampered = bundle.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open_bundle(&mailbox, &tampered).is_err());
+
+        // A body signed by someone other than the claimed sender
+        let impostor = DeviceIdentity::new("impostor".to_string()).unwrap();
+        let forged = seal_bundle(&impostor, &mailbox.get_public_key(), &body(&sender)).unwrap();
+        assert_eq!(open_bundle(&mailbox, &forged).unwrap_err(), "Bundle signature is invalid");
+
+        assert_eq!(padded_len(10), 1024);
+        assert_eq!(padded_len(1500), 2048);
+        assert_eq!(padded_len(PAD_STEP_BYTES + 1), 2 * PAD_STEP_BYTES);
+    }
+}
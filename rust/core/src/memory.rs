@@ -0,0 +1,91 @@
+//! Memory accounting for long-running sessions
+//!
+//! WASM linear memory never shrinks once grown, but dropping caches and
+//! compacting maps lets the allocator reuse freed pages instead of growing
+//! further. The figures reported here are estimates based on container
+//! capacities, which is what actually occupies the heap.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::mem::size_of;
+
+/// WASM page size in bytes
+#[cfg(target_arch = "wasm32")]
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// Snapshot of the node's estimated heap usage
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MemoryStats {
+    pub journal_bytes: usize,
+    /// Journal history kept for the activity view and rollbacks
+    pub history_bytes: usize,
+    pub peer_table_bytes: usize,
+    pub conflict_bytes: usize,
+    /// Audit, issue, security, trace and debug logs
+    pub log_bytes: usize,
+    /// Frames queued for or received from transports, and partly reassembled fragments
+    pub transport_bytes: usize,
+    /// Chunks cached by content hash
+    pub cache_bytes: usize,
+    /// Frames held for peers without an open link
+    pub held_frame_bytes: usize,
+    pub total_tracked_bytes: usize,
+    pub linear_memory_bytes: usize,
+}
+
+impl MemoryStats {
+    /// Fill in the derived totals
+    pub fn finalize(mut self) -> MemoryStats {
+        self.total_tracked_bytes = self.journal_bytes
+            + self.history_bytes
+            + self.peer_table_bytes
+            + self.conflict_bytes
+            + self.log_bytes
+            + self.transport_bytes
+            + self.cache_bytes
+            + self.held_frame_bytes;
+        self.linear_memory_bytes = linear_memory_bytes();
+        self
+    }
+}
+
+/// Types that can report and reduce their heap footprint
+pub trait MemoryFootprint {
+    /// Estimated heap bytes held by this value
+    fn heap_bytes(&self) -> usize;
+
+    /// Release spare capacity and drop anything that can be recomputed
+    fn trim(&mut self);
+}
+
+/// Heap bytes held by a string
+pub fn string_bytes(s: &str) -> usize {
+    s.len()
+}
+
+/// Bookkeeping overhead of a hash map's buckets, excluding heap data owned by entries
+pub fn map_overhead<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<K>() + size_of::<V>() + 1)
+}
+
+/// Slots of a deque plus the heap data its entries own
+pub fn deque_bytes<T>(deque: &VecDeque<T>, owned: impl Fn(&T) -> usize) -> usize {
+    deque.capacity() * size_of::<T>() + deque.iter().map(owned).sum::<usize>()
+}
+
+/// Entries of a string map, counting each node's key and value slots
+pub fn string_map_bytes(map: &BTreeMap<String, String>) -> usize {
+    map.iter().map(|(k, v)| size_of::<(String, String)>() + k.len() + v.len()).sum()
+}
+
+/// Current size of the WASM linear memory (0 on native targets)
+pub fn linear_memory_bytes() -> usize {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) * WASM_PAGE_SIZE
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
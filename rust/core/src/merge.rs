@@ -0,0 +1,413 @@
+//! Content merge strategies
+//!
+//! Used instead of whole-file last-writer-wins for paths whose policy is
+//! `merge`. Each strategy takes the common ancestor (when known) and both
+//! sides, and returns merged content plus the locations it had to decide
+//! arbitrarily so the host can tell the user.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::cancel::{self, CancellationToken};
+use crate::diff::{self, Change};
+
+/// How to combine arrays that both sides changed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrayPolicy {
+    /// Keep items from both sides, honoring removals relative to the base
+    #[default]
+    Union,
+    PreferLocal,
+    PreferRemote,
+}
+
+/// Which side wins when both changed the same scalar
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Preference {
+    Local,
+    #[default]
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct JsonMergeOptions {
+    #[serde(default)]
+    pub arrays: ArrayPolicy,
+    #[serde(default)]
+    pub prefer: Preference,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MergeResult {
+    pub strategy: &'static str,
+    pub merged: String,
+    /// Locations (JSON pointers, line numbers…) resolved by preference rather than merged
+    pub conflicts: Vec<String>,
+}
+
+/// Key-wise three-way merge of two JSON documents
+pub fn merge_json_text(
+    base: Option<&str>,
+    local: &str,
+    remote: &str,
+    options: JsonMergeOptions,
+) -> Result<MergeResult, String> {
+    let parse = |side: &str, text: &str| {
+        serde_json::from_str::<Value>(text).map_err(|e| format!("Invalid {} JSON: {}", side, e))
+    };
+    let base = base.map(|b| parse("base", b)).transpose()?;
+    let local = parse("local", local)?;
+    let remote = parse("remote", remote)?;
+
+    let mut conflicts = Vec::new();
+    let merged = merge_values(base.as_ref(), &local, &remote, options, "", &mut conflicts);
+    let mut text = serde_json::to_string_pretty(&merged).map_err(|e| e.to_string())?;
+    text.push('\n');
+
+    Ok(MergeResult { strategy: "json", merged: text, conflicts })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppendMergeOptions {
+    /// Inserted between the two divergent tails (e.g. `"\n---\n"`)
+    #[serde(default)]
+    pub separator: String,
+}
+
+/// Merge two versions that only appended to a shared prefix
+/// Fails when either side edited text before the divergence point, so the
+/// caller can fall back to conflict handling.
+pub fn merge_append_text(
+    base: Option<&str>,
+    local: &str,
+    remote: &str,
+    options: &AppendMergeOptions,
+) -> Result<MergeResult, String> {
+    let prefix_len = match base {
+        Some(base) if local.starts_with(base) && remote.starts_with(base) => base.len(),
+        Some(_) => return Err("Both versions must extend the common ancestor".to_string()),
+        None => common_line_prefix(local, remote),
+    };
+    let local_tail = &local[prefix_len..];
+    let remote_tail = &remote[prefix_len..];
+
+    let merged = if remote_tail.starts_with(local_tail) {
+        remote.to_string()
+    } else if local_tail.starts_with(remote_tail) {
+        local.to_string()
+    } else {
+        let mut merged = String::with_capacity(local.len() + remote_tail.len() + options.separator.len() + 1);
+        merged.push_str(local);
+        if !merged.ends_with('\n') {
+            merged.push('\n');
+        }
+        merged.push_str(&options.separator);
+        merged.push_str(remote_tail);
+        merged
+    };
+
+    Ok(MergeResult { strategy: "append", merged, conflicts: Vec::new() })
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct LineMergeOptions {
+    /// Side kept where both changed the same lines
+    #[serde(default)]
+    pub prefer: Preference,
+}
+
+/// Three-way merge of plain text, line by line
+/// Regions changed on only one side relative to `base` are taken from that
+/// side; regions both sides changed differently (or adjacent edits) keep the
+/// preferred side and are reported by their first base line number.
+/// `cancel` is checked after each diff and between regions.
+pub fn merge_lines_text(
+    base: &str,
+    local: &str,
+    remote: &str,
+    options: LineMergeOptions,
+    cancel: &Option<CancellationToken>,
+) -> Result<MergeResult, String> {
+    let base_lines = diff::split_lines(base);
+    let local_lines = diff::split_lines(local);
+    let remote_lines = diff::split_lines(remote);
+    let local_changes = diff::changes(&base_lines, &local_lines);
+    cancel::check(cancel)?;
+    let remote_changes = diff::changes(&base_lines, &remote_lines);
+    cancel::check(cancel)?;
+
+    let mut merged = String::with_capacity(local.len().max(remote.len()));
+    let mut conflicts = Vec::new();
+    let mut position = 0;
+    let (mut l, mut r) = (0, 0);
+    while l < local_changes.len() || r < remote_changes.len() {
+        cancel::check(cancel)?;
+        // Start a group at the earlier change and pull in everything that overlaps or touches it
+        let local_first = r == remote_changes.len()
+            || (l < local_changes.len() && local_changes[l].old.start <= remote_changes[r].old.start);
+        let first = if local_first { &local_changes[l] } else { &remote_changes[r] };
+        let (start, mut end) = (first.old.start, first.old.end);
+        let (l_from, r_from) = (l, r);
+        loop {
+            if l < local_changes.len() && local_changes[l].old.start <= end {
+                end = end.max(local_changes[l].old.end);
+                l += 1;
+            } else if r < remote_changes.len() && remote_changes[r].old.start <= end {
+                end = end.max(remote_changes[r].old.end);
+                r += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged.push_str(&base_lines[position..start].concat());
+        let ours = side_region(&base_lines, &local_lines, &local_changes[l_from..l], start, end);
+        let theirs = side_region(&base_lines, &remote_lines, &remote_changes[r_from..r], start, end);
+        if l == l_from {
+            merged.push_str(&theirs);
+        } else if r == r_from || ours == theirs {
+            merged.push_str(&ours);
+        } else {
+            conflicts.push(format!("line {}", start + 1));
+            merged.push_str(match options.prefer {
+                Preference::Local => &ours,
+                Preference::Remote => &theirs,
+            });
+        }
+        position = end;
+    }
+    merged.push_str(&base_lines[position..].concat());
+
+    Ok(MergeResult { strategy: "lines", merged, conflicts })
+}
+
+/// One side's text for base lines `start..end`, given its changes within them
+fn side_region(base: &[&str], side: &[&str], changes: &[Change], start: usize, end: usize) -> String {
+    let mut out = String::new();
+    let mut position = start;
+    for change in changes {
+        out.push_str(&base[position..change.old.start].concat());
+        out.push_str(&side[change.new.clone()].concat());
+        position = change.old.end;
+    }
+    out.push_str(&base[position..end].concat());
+    out
+}
+
+/// Length of the longest common prefix ending at a line boundary
+fn common_line_prefix(a: &str, b: &str) -> usize {
+    let common = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    if common == a.len() && common == b.len() {
+        return common;
+    }
+    a.as_bytes()[..common].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1)
+}
+
+fn merge_values(
+    base: Option<&Value>,
+    local: &Value,
+    remote: &Value,
+    options: JsonMergeOptions,
+    pointer: &str,
+    conflicts: &mut Vec<String>,
+) -> Value {
+    if local == remote {
+        return local.clone();
+    }
+    if base == Some(local) {
+        return remote.clone();
+    }
+    if base == Some(remote) {
+        return local.clone();
+    }
+
+    match (local, remote) {
+        (Value::Object(l), Value::Object(r)) => {
+            let b = base.and_then(Value::as_object);
+            Value::Object(merge_objects(b, l, r, options, pointer, conflicts))
+        }
+        (Value::Array(l), Value::Array(r)) => {
+            let b = base.and_then(Value::as_array);
+            Value::Array(merge_arrays(b, l, r, options, pointer, conflicts))
+        }
+        _ => {
+            conflicts.push(display_pointer(pointer));
+            prefer(local, remote, options.prefer).clone()
+        }
+    }
+}
+
+fn merge_objects(
+    base: Option<&Map<String, Value>>,
+    local: &Map<String, Value>,
+    remote: &Map<String, Value>,
+    options: JsonMergeOptions,
+    pointer: &str,
+    conflicts: &mut Vec<String>,
+) -> Map<String, Value> {
+    let mut out = Map::new();
+    let keys = local.keys().chain(remote.keys().filter(|k| !local.contains_key(*k)));
+
+    for key in keys {
+        let child = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+        let b = base.and_then(|b| b.get(key));
+        match (local.get(key), remote.get(key)) {
+            (Some(l), Some(r)) => {
+                out.insert(key.clone(), merge_values(b, l, r, options, &child, conflicts));
+            }
+            // Present on one side only: an addition, or a deletion by the other side
+            (Some(v), None) | (None, Some(v)) => match b {
+                None => {
+                    out.insert(key.clone(), v.clone());
+                }
+                Some(b) if b == v => {}
+                Some(_) => {
+                    // Modified on one side, deleted on the other: keep the modification
+                    conflicts.push(display_pointer(&child));
+                    out.insert(key.clone(), v.clone());
+                }
+            },
+            (None, None) => {}
+        }
+    }
+    out
+}
+
+fn merge_arrays(
+    base: Option<&Vec<Value>>,
+    local: &[Value],
+    remote: &[Value],
+    options: JsonMergeOptions,
+    pointer: &str,
+    conflicts: &mut Vec<String>,
+) -> Vec<Value> {
+    match options.arrays {
+        ArrayPolicy::PreferLocal => {
+            conflicts.push(display_pointer(pointer));
+            local.to_vec()
+        }
+        ArrayPolicy::PreferRemote => {
+            conflicts.push(display_pointer(pointer));
+            remote.to_vec()
+        }
+        ArrayPolicy::Union => {
+            let in_base = |v: &Value| base.is_some_and(|b| b.contains(v));
+            let mut out: Vec<Value> = local
+                .iter()
+                // Drop items the remote side removed
+                .filter(|v| !in_base(v) || remote.contains(v))
+                .cloned()
+                .collect();
+            for v in remote {
+                let removed_locally = in_base(v) && !local.contains(v);
+                if !removed_locally && !out.contains(v) {
+                    out.push(v.clone());
+                }
+            }
+            out
+        }
+    }
+}
+
+fn prefer<'a>(local: &'a Value, remote: &'a Value, preference: Preference) -> &'a Value {
+    match preference {
+        Preference::Local => local,
+        Preference::Remote => remote,
+    }
+}
+
+fn display_pointer(pointer: &str) -> String {
+    if pointer.is_empty() {
+        "/".to_string()
+    } else {
+        pointer.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: Option<&str>, local: &str, remote: &str) -> (Value, Vec<String>) {
+        let result = merge_json_text(base, local, remote, JsonMergeOptions::default()).unwrap();
+        (serde_json::from_str(&result.merged).unwrap(), result.conflicts)
+    }
+
+    #[test]
+    fn test_independent_key_changes_both_survive() {
+        let (merged, conflicts) = merge(
+            Some(r#"{"a": 1, "b": 1, "nested": {"x": 1, "y": 1}}"#),
+            r#"{"a": 2, "b": 1, "nested": {"x": 2, "y": 1}}"#,
+            r#"{"a": 1, "b": 3, "nested": {"x": 1, "y": 3}, "c": true}"#,
+        );
+        assert_eq!(merged, serde_json::json!({"a": 2, "b": 3, "nested": {"x": 2, "y": 3}, "c": true}));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_scalar_conflict_and_deletions() {
+        let (merged, conflicts) = merge(
+            Some(r#"{"theme": "dark", "gone": 1, "edited": 1}"#),
+            r#"{"theme": "light", "edited": 2}"#,
+            r#"{"theme": "solarized", "gone": 1}"#,
+        );
+        assert_eq!(merged, serde_json::json!({"theme": "solarized", "edited": 2}));
+        assert_eq!(conflicts, vec!["/theme".to_string(), "/edited".to_string()]);
+    }
+
+    #[test]
+    fn test_array_union_honors_removals() {
+        let (merged, _) = merge(
+            Some(r#"{"list": ["a", "b", "c"]}"#),
+            r#"{"list": ["a", "c", "d"]}"#,
+            r#"{"list": ["a", "b", "e"]}"#,
+        );
+        assert_eq!(merged, serde_json::json!({"list": ["a", "d", "e"]}));
+    }
+
+    #[test]
+    fn test_append_merge_concatenates_tails() {
+        let base = "# 2024-01-01\n\n- woke up\n";
+        let local = "# 2024-01-01\n\n- woke up\n- desk: wrote report\n";
+        let remote = "# 2024-01-01\n\n- woke up\n- phone: bought milk\n";
+        let options = AppendMergeOptions { separator: String::new() };
+
+        let merged = merge_append_text(Some(base), local, remote, &options).unwrap().merged;
+        assert_eq!(merged, format!("{}- phone: bought milk\n", local));
+        // Same answer when the ancestor is unknown
+        assert_eq!(merge_append_text(None, local, remote, &options).unwrap().merged, merged);
+        // One side already contains the other's append
+        assert_eq!(merge_append_text(Some(base), base, local, &options).unwrap().merged, local);
+        // Earlier text was edited: not an append
+        assert!(merge_append_text(Some(base), "# edited\n", remote, &options).is_err());
+    }
+
+    #[test]
+    fn test_line_merge_combines_separate_edits() {
+        let base = "title\n\nintro\nbody\nend\n";
+        let local = "Title\n\nintro\nbody\nend\n";
+        let remote = "title\n\nintro\nbody, revised\nend\nfooter\n";
+        let result = merge_lines_text(base, local, remote, LineMergeOptions::default(), &None).unwrap();
+        assert_eq!(result.merged, "Title\n\nintro\nbody, revised\nend\nfooter\n");
+        assert!(result.conflicts.is_empty());
+
+        // Both rewrote the same line: the preferred side wins and the line is reported
+        let local = "title\n\nintro\nmy body\nend\n";
+        let options = LineMergeOptions { prefer: Preference::Local };
+        let result = merge_lines_text(base, local, remote, options, &None).unwrap();
+        assert_eq!(result.merged, "title\n\nintro\nmy body\nend\nfooter\n");
+        assert_eq!(result.conflicts, vec!["line 4".to_string()]);
+
+        let token = CancellationToken::new();
+        token.cancel_after_checks(2);
+        assert_eq!(merge_lines_text(base, local, remote, options, &Some(token)).unwrap_err(), cancel::CANCELLED);
+    }
+
+    #[test]
+    fn test_key_order_is_preserved() {
+        let result = merge_json_text(None, r#"{"z": 1, "a": 1}"#, r#"{"z": 1, "a": 1, "m": 2}"#, Default::default()).unwrap();
+        assert!(result.merged.find("\"z\"").unwrap() < result.merged.find("\"a\"").unwrap());
+    }
+}
@@ -0,0 +1,241 @@
+//! Custom merge drivers
+//!
+//! Some plugins keep files in formats only they understand (task lists,
+//! kanban boards, review schedules), where a line merge produces a valid
+//! text but a broken board. Such a plugin registers a merge driver for its
+//! path patterns with `register_merge_driver`: a `MergeDriver` (in the
+//! bindings, a JS object whose `merge(path, base, local, remote)` returns the
+//! merged text, `null` to leave the file to the built-in strategies, or
+//! `{conflict: reason}` when the versions can't be combined). `merge_file`
+//! asks the first driver whose pattern matches before anything else, also for
+//! paths whose policy isn't `merge`; a conflict is returned as an error so the
+//! host queues it with `add_conflict` as usual. A driver that fails is
+//! recorded as an issue and the built-in strategies take over.
+//!
+//! Native hosts and tests can also pass the patterns directly to `set_merge_driver`.
+
+use crate::merge::MergeResult;
+use crate::policy::glob_match;
+use serde::{Deserialize, Serialize};
+
+use crate::issues;
+use crate::P2PNode;
+
+/// Strategy reported in the `MergeResult` of a driver
+pub const DRIVER_STRATEGY: &str = "driver";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DriverOutcome {
+    Merged(String),
+    /// The versions can't be combined, with the driver's reason
+    Conflict(String),
+    /// Not the driver's business after all; the built-in strategies decide
+    Declined,
+}
+
+pub trait MergeDriver {
+    /// Merge `local` and `remote`, with their common ancestor when it is known;
+    /// an error means the driver failed
+    fn merge(&self, path: &str, base: Option<&str>, local: &str, remote: &str) -> Result<DriverOutcome, String>;
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Reply {
+    Merged(String),
+    Object {
+        merged: Option<String>,
+        conflict: Option<String>,
+    },
+}
+
+/// Outcome of a JS driver from its return value as JSON (`None` for `undefined`)
+pub fn parse_reply(json: Option<&str>) -> Result<DriverOutcome, String> {
+    let reply: Option<Reply> = match json {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("Invalid merge driver result: {}", e))?,
+        None => None,
+    };
+    match reply {
+        None => Ok(DriverOutcome::Declined),
+        Some(Reply::Merged(merged)) | Some(Reply::Object { merged: Some(merged), conflict: None }) => {
+            Ok(DriverOutcome::Merged(merged))
+        }
+        Some(Reply::Object { merged: None, conflict: Some(reason) }) => Ok(DriverOutcome::Conflict(reason)),
+        Some(Reply::Object { .. }) => Err("Merge driver result needs exactly one of merged and conflict".to_string()),
+    }
+}
+
+struct Registered {
+    name: String,
+    patterns: Vec<String>,
+    driver: Box<dyn MergeDriver>,
+}
+
+#[derive(Serialize)]
+struct DriverInfo<'a> {
+    name: &'a str,
+    patterns: &'a [String],
+}
+
+#[derive(Default)]
+pub struct MergeDrivers {
+    /// In registration order; the first match wins
+    drivers: Vec<Registered>,
+}
+
+impl MergeDrivers {
+    /// Add a driver, replacing one with the same name in its place
+    pub fn set(&mut self, name: &str, patterns: Vec<String>, driver: Box<dyn MergeDriver>) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err("Merge drivers need a name".to_string());
+        }
+        if patterns.is_empty() || patterns.iter().any(|p| p.trim().is_empty()) {
+            return Err(format!("Merge driver {} needs non-empty path patterns", name));
+        }
+        let registered = Registered { name: name.to_string(), patterns, driver };
+        match self.drivers.iter_mut().find(|d| d.name == name) {
+            Some(existing) => *existing = registered,
+            None => self.drivers.push(registered),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.drivers.len();
+        self.drivers.retain(|d| d.name != name);
+        self.drivers.len() < before
+    }
+
+    fn find(&self, path: &str) -> Option<&Registered> {
+        self.drivers.iter().find(|d| d.patterns.iter().any(|p| glob_match(p, path)))
+    }
+}
+
+impl P2PNode {
+    /// Merge files matching any of `patterns_json` (a JSON array of globs) with `driver`;
+    /// replaces a driver registered under the same name
+    pub fn register_merge_driver(
+        &mut self,
+        name: &str,
+        patterns_json: &str,
+        driver: Box<dyn MergeDriver>,
+    ) -> Result<(), String> {
+        serde_json::from_str(patterns_json)
+            .map_err(|e| format!("Invalid merge driver patterns: {}", e))
+            .and_then(|patterns| self.set_merge_driver(name, patterns, driver))
+            .map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    /// Remove a merge driver; false if none had this name
+    pub fn unregister_merge_driver(&mut self, name: &str) -> bool {
+        self.merge_drivers.remove(name)
+    }
+
+    /// Registered drivers as `[{name, patterns}]`, in the order they are tried
+    pub fn get_merge_drivers_json(&self) -> String {
+        let drivers: Vec<DriverInfo> =
+            self.merge_drivers.drivers.iter().map(|d| DriverInfo { name: &d.name, patterns: &d.patterns }).collect();
+        serde_json::to_string(&drivers).unwrap_or_default()
+    }
+
+    /// Name of the driver `merge_file` would ask for `path`
+    pub fn get_merge_driver_for(&self, path: &str) -> Option<String> {
+        self.merge_drivers.find(path).map(|d| d.name.clone())
+    }
+}
+
+impl P2PNode {
+    pub fn set_merge_driver(
+        &mut self,
+        name: &str,
+        patterns: Vec<String>,
+        driver: Box<dyn MergeDriver>,
+    ) -> Result<(), String> {
+        self.merge_drivers.set(name, patterns, driver)
+    }
+
+    /// Ask the driver for `path`, if any; `None` leaves the merge to the built-in strategies
+    pub(crate) fn run_merge_driver(
+        &mut self,
+        path: &str,
+        base: Option<&str>,
+        local: &str,
+        remote: &str,
+    ) -> Result<Option<MergeResult>, String> {
+        let Some(registered) = self.merge_drivers.find(path) else {
+            return Ok(None);
+        };
+        let name = registered.name.clone();
+        match registered.driver.merge(path, base, local, remote) {
+            Ok(DriverOutcome::Merged(merged)) => {
+                Ok(Some(MergeResult { strategy: DRIVER_STRATEGY, merged, conflicts: Vec::new() }))
+            }
+            Ok(DriverOutcome::Conflict(reason)) => {
+                Err(format!("Merge driver {} found a conflict in {}: {}", name, path, reason))
+            }
+            Ok(DriverOutcome::Declined) => Ok(None),
+            Err(e) => {
+                let message = format!("Merge driver {} failed on {}: {}", name, path, e);
+                self.issues.warn(issues::MERGE_DRIVER_FAILED, message, &[("path", path), ("driver", &name)]);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Merges boards by taking every card either side has, in order
+    struct Board;
+
+    impl MergeDriver for Board {
+        fn merge(&self, _path: &str, _base: Option<&str>, local: &str, remote: &str) -> Result<DriverOutcome, String> {
+            if local.starts_with("archived") || remote.starts_with("archived") {
+                return Ok(DriverOutcome::Conflict("board archived on one side".to_string()));
+            }
+            if !local.starts_with("## ") {
+                return if local.is_empty() { Err("empty board".to_string()) } else { Ok(DriverOutcome::Declined) };
+            }
+            let mut cards: Vec<&str> = local.lines().collect();
+            cards.extend(remote.lines().filter(|card| !local.lines().any(|own| own == *card)));
+            Ok(DriverOutcome::Merged(cards.join("\n")))
+        }
+    }
+
+    #[test]
+    fn test_drivers_run_before_builtin_strategies() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.set_merge_driver("kanban", vec!["Boards/*.md".to_string()], Box::new(Board)).unwrap();
+        assert!(node.set_merge_driver("", vec!["*.md".to_string()], Box::new(Board)).is_err());
+        assert!(node.set_merge_driver("tasks", vec![], Box::new(Board)).is_err());
+        assert_eq!(node.get_merge_driver_for("Boards/work.md").as_deref(), Some("kanban"));
+        assert_eq!(node.get_merge_drivers_json(), r#"[{"name":"kanban","patterns":["Boards/*.md"]}]"#);
+
+        // Boards merge even though Markdown isn't merged by policy
+        let merged = node.merge_file("Boards/work.md", None, "## Todo\n- a", "## Todo\n- b", "").unwrap();
+        assert_eq!(merged, r###"{"strategy":"driver","merged":"## Todo\n- a\n- b","conflicts":[]}"###);
+        let e = node.merge_file("Boards/work.md", None, "archived", "## Todo", "").err().unwrap();
+        assert!(e.contains("board archived"));
+
+        // Declined or failed, the built-in strategies decide, which don't merge Markdown
+        let e = node.merge_file("Boards/work.md", None, "plain", "## Todo", "").err().unwrap();
+        assert!(e.contains("not configured"));
+        assert!(node.merge_file("Boards/work.md", None, "", "## Todo", "").is_err());
+        assert_eq!(node.issues.iter().filter(|issue| issue.code == issues::MERGE_DRIVER_FAILED).count(), 1);
+
+        assert!(node.unregister_merge_driver("kanban"));
+        assert!(node.get_merge_driver_for("Boards/work.md").is_none());
+
+        assert_eq!(parse_reply(Some(r#""text""#)), Ok(DriverOutcome::Merged("text".to_string())));
+        assert_eq!(parse_reply(Some(r#"{"merged":"text"}"#)), Ok(DriverOutcome::Merged("text".to_string())));
+        assert_eq!(parse_reply(Some(r#"{"conflict":"no"}"#)), Ok(DriverOutcome::Conflict("no".to_string())));
+        assert_eq!(parse_reply(Some("null")), Ok(DriverOutcome::Declined));
+        assert_eq!(parse_reply(None), Ok(DriverOutcome::Declined));
+        assert!(parse_reply(Some("{}")).is_err());
+        assert!(parse_reply(Some("3")).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Mnemonic backup of the device identity
+//!
+//! If the plugin's data folder is lost, so is the device key, and every peer
+//! has to pair with what is now a stranger. The identity's Ed25519 seed can
+//! be written down as a 24-word BIP39 phrase instead; restoring from it gives
+//! back the same signing key, so peers keep trusting the device, and the
+//! same long-lived exchange key (derived from the seed) for mailboxes.
+
+use bip39::Mnemonic;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::crypto::{DeviceIdentity, KeyExchange};
+
+const EXCHANGE_KEY_INFO: &[u8] = b"obsidian-p2p-sync device exchange key v1";
+
+impl DeviceIdentity {
+    /// The identity seed as a 24-word BIP39 phrase
+    pub fn to_mnemonic(&self) -> String {
+        // 32 bytes is a valid BIP39 entropy length
+        Mnemonic::from_entropy(self.seed().as_slice()).map(|m| m.to_string()).unwrap_or_default()
+    }
+
+    /// Restore an identity from its phrase (case and extra whitespace are ignored)
+    pub fn from_mnemonic(device_id: String, phrase: &str) -> Result<DeviceIdentity, String> {
+        let normalized = phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let mnemonic = Mnemonic::parse_normalized(&normalized).map_err(|e| format!("Invalid backup phrase: {}", e))?;
+        let entropy = Zeroizing::new(mnemonic.to_entropy());
+        let seed: Zeroizing<[u8; 32]> =
+            Zeroizing::new(entropy.as_slice().try_into().map_err(|_| "Backup phrase must be 24 words".to_string())?);
+        Ok(DeviceIdentity::from_seed(device_id, &seed)?)
+    }
+
+    /// Long-lived X25519 key derived from the identity seed, recoverable with it
+    pub fn derive_exchange_key(&self) -> KeyExchange {
+        let mut secret = Zeroizing::new([0u8; 32]);
+        // 32 bytes is well within HKDF-SHA256's output limit
+        let _ = Hkdf::<Sha256>::new(None, self.seed().as_slice()).expand(EXCHANGE_KEY_INFO, secret.as_mut_slice());
+        KeyExchange::from_secret_bytes(*secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phrase_restores_the_same_keys() {
+        let identity = DeviceIdentity::new("laptop".to_string()).unwrap();
+        let phrase = identity.to_mnemonic();
+        assert_eq!(phrase.split(' ').count(), 24);
+
+        let messy = format!("  {}\n", phrase.to_uppercase().replace(' ', "   "));
+        let restored = DeviceIdentity::from_mnemonic("laptop".to_string(), &messy).unwrap();
+        assert_eq!(restored.get_public_key(), identity.get_public_key());
+        assert_eq!(restored.derive_exchange_key().get_public_key(), identity.derive_exchange_key().get_public_key());
+        assert_ne!(identity.derive_exchange_key().get_public_key(), identity.get_public_key());
+
+        // Bad checksum, then a valid phrase of the wrong length
+        assert!(DeviceIdentity::from_mnemonic("laptop".to_string(), &["abandon"; 24].join(" ")).is_err());
+        let short = format!("{} about", ["abandon"; 11].join(" "));
+        assert!(DeviceIdentity::from_mnemonic("laptop".to_string(), &short).is_err());
+    }
+}
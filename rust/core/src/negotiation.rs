@@ -0,0 +1,272 @@
+//! Downgrade-resistant session negotiation
+//!
+//! Handshake capabilities pick the encoding, the content hash and whether
+//! cover frames are understood, so an attacker who strips flags in transit
+//! could force the weakest options. The identity signature therefore covers
+//! the whole handshake transcript (protocol version, keys, capabilities and
+//! sketch) instead of just the session key, and the session key is derived
+//! from both transcripts: if the two sides saw different handshakes, their
+//! confirmation tags differ and the session fails before any data is sent.
+//! A correctly signed handshake advertising fewer capabilities than the same
+//! device did before is still accepted; `P2PNode` records it in the security log.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hkdf::Hkdf;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::compat;
+use crate::crypto::{self, DeviceIdentity, KeyExchange};
+use crate::error::Error;
+use crate::security::SecurityEventKind;
+use crate::wire::{self, Handshake};
+use crate::P2PNode;
+
+const TRANSCRIPT_DOMAIN: &[u8] = b"obsidian-p2p-sync handshake v2";
+
+fn length_prefixed(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// Constant-time comparison of two tags
+pub fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Bytes the identity key signs: every handshake field except the signature
+pub fn transcript(protocol_version: u32, handshake: &Handshake) -> Vec<u8> {
+    let mut out = TRANSCRIPT_DOMAIN.to_vec();
+    out.extend_from_slice(&protocol_version.to_be_bytes());
+    length_prefixed(&mut out, handshake.device_id.as_bytes());
+    length_prefixed(&mut out, &handshake.identity_key);
+    length_prefixed(&mut out, &handshake.session_key);
+    out.extend_from_slice(&handshake.capabilities.to_be_bytes());
+    length_prefixed(&mut out, &handshake.journal_sketch);
+    out
+}
+
+/// Sign `handshake` in place with `identity`
+pub fn sign(identity: &DeviceIdentity, handshake: &mut Handshake) {
+    let signed = transcript(wire::PROTOCOL_VERSION, handshake);
+    handshake.signature = BASE64.decode(identity.sign(&signed)).unwrap_or_default();
+}
+
+/// Decode a handshake frame; returns the envelope's protocol version and the handshake
+pub fn decode(frame: &[u8]) -> Result<(u32, Handshake), Error> {
+    let Some((envelope, _)) = wire::decode_frame(frame)? else {
+        return Err("Incomplete handshake frame".into());
+    };
+    let Some(wire::Body::Handshake(handshake)) = envelope.body else {
+        return Err("Expected a handshake frame".into());
+    };
+    Ok((envelope.protocol_version, handshake))
+}
+
+/// Whether the handshake's signature covers its transcript under `protocol_version`
+pub fn signature_valid(protocol_version: u32, handshake: &Handshake) -> bool {
+    let signed = transcript(protocol_version, handshake);
+    crypto::verify_signature(BASE64.encode(&handshake.identity_key), &signed, BASE64.encode(&handshake.signature))
+}
+
+/// Decode a handshake frame and check its transcript signature
+pub fn verify(frame: &[u8]) -> Result<Handshake, Error> {
+    let (version, handshake) = decode(frame)?;
+    compat::check_handshake_version(version, &handshake.device_id)?;
+    if !signature_valid(version, &handshake) {
+        return Err(format!("Invalid handshake signature from {}", handshake.device_id).into());
+    }
+    Ok(handshake)
+}
+
+/// Session key and confirmation tags bound to both handshake transcripts
+#[derive(Debug)]
+pub struct SessionKeys {
+    pub session_key: [u8; 32],
+    /// Tag to send to the peer
+    pub confirmation: [u8; 32],
+    /// Tag the peer must send back
+    pub expected_confirmation: [u8; 32],
+}
+
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.session_key.zeroize();
+        self.confirmation.zeroize();
+        self.expected_confirmation.zeroize();
+    }
+}
+
+/// Derive the session from our ephemeral key, the handshake we sent and the one we received
+pub fn derive(session: &KeyExchange, local: &Handshake, remote: &Handshake) -> Result<SessionKeys, Error> {
+    if local.session_key != session.public_key_bytes() {
+        return Err("Handshake was not sent with this session key".into());
+    }
+    let remote_key: [u8; 32] = remote.session_key.as_slice().try_into().map_err(|_| "Invalid session key length")?;
+    let shared = session.shared_secret_bytes(remote_key);
+    if shared == [0; 32] {
+        return Err("Invalid session key".into());
+    }
+
+    // Both sides hash the transcripts in the same order, lower session key first
+    let (first, second) = if local.session_key <= remote.session_key { (local, remote) } else { (remote, local) };
+    let mut hasher = Sha256::new();
+    hasher.update(transcript(wire::PROTOCOL_VERSION, first));
+    hasher.update(transcript(wire::PROTOCOL_VERSION, second));
+    let hk = Hkdf::<Sha256>::new(Some(&hasher.finalize()), &shared);
+
+    let mut keys = SessionKeys { session_key: [0; 32], confirmation: [0; 32], expected_confirmation: [0; 32] };
+    let confirm_info = |key: &[u8]| [&b"confirm "[..], key].concat();
+    hk.expand(b"session key", &mut keys.session_key).map_err(|e| e.to_string())?;
+    hk.expand(&confirm_info(&local.session_key), &mut keys.confirmation).map_err(|e| e.to_string())?;
+    hk.expand(&confirm_info(&remote.session_key), &mut keys.expected_confirmation).map_err(|e| e.to_string())?;
+    Ok(keys)
+}
+
+#[derive(Serialize)]
+struct SessionKeysJson {
+    session_key: String,
+    confirmation: String,
+    expected_confirmation: String,
+}
+
+impl P2PNode {
+    /// Derive the session from the handshake frame we sent and the peer's; returns
+    /// `{session_key, confirmation, expected_confirmation}` (base64) as JSON.
+    /// Send `confirmation`, and check the peer's with `check_session_confirmation`
+    pub fn derive_session_keys(&mut self, session: &KeyExchange, own_frame: &[u8], peer_frame: &[u8]) -> Result<String, String> {
+        self.session_keys(session, own_frame, peer_frame)
+            .map(|keys| {
+                serde_json::to_string(&SessionKeysJson {
+                    session_key: BASE64.encode(keys.session_key),
+                    confirmation: BASE64.encode(keys.confirmation),
+                    expected_confirmation: BASE64.encode(keys.expected_confirmation),
+                })
+                .unwrap_or_default()
+            })
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Compare the peer's confirmation with the expected one; a mismatch means the
+    /// handshake was tampered with and is recorded in the security log. Requests from
+    /// the peer are only served under its handshake's identity key once this succeeds
+    pub fn check_session_confirmation(&mut self, peer_device_id: &str, expected_b64: &str, received_b64: &str) -> bool {
+        let expected = BASE64.decode(expected_b64).unwrap_or_default();
+        let received = BASE64.decode(received_b64).unwrap_or_default();
+        if !expected.is_empty() && equal(&expected, &received) {
+            self.access.bind(peer_device_id);
+            return true;
+        }
+        self.access.unbind(peer_device_id);
+        self.security_log.record(
+            SecurityEventKind::DowngradeAttempt,
+            peer_device_id,
+            format!("Handshake confirmation from {} does not match; the negotiation was altered", peer_device_id),
+            &[],
+        );
+        false
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn session_keys(&mut self, session: &KeyExchange, own_frame: &[u8], peer_frame: &[u8]) -> Result<SessionKeys, String> {
+        let local = verify(own_frame)?;
+        let remote = self.verify_peer_handshake(peer_frame)?;
+        Ok(derive(session, &local, &remote)?)
+    }
+
+    /// Verify a peer's handshake, recording bad signatures and capability downgrades
+    pub(crate) fn verify_peer_handshake(&mut self, frame: &[u8]) -> Result<Handshake, String> {
+        let (version, handshake) = decode(frame)?;
+        if !signature_valid(version, &handshake) {
+            let message = format!("Invalid handshake signature from {}", handshake.device_id);
+            self.security_log.record(SecurityEventKind::SignatureInvalid, &handshake.device_id, message.clone(), &[("frame", "handshake")]);
+            return Err(message);
+        }
+        let identity_key = BASE64.encode(&handshake.identity_key);
+        self.check_identity_key(&handshake.device_id, &identity_key, "handshake")?;
+        self.access.stage(&handshake.device_id, identity_key);
+        let previous = self.peer_capabilities.insert(handshake.device_id.clone(), handshake.capabilities);
+        if let Some(previous) = previous.filter(|p| p & !handshake.capabilities != 0) {
+            let (before, after) = (format!("{:#x}", previous), format!("{:#x}", handshake.capabilities));
+            self.security_log.record(
+                SecurityEventKind::DowngradeAttempt,
+                &handshake.device_id,
+                format!("{} now advertises fewer capabilities", handshake.device_id),
+                &[("before", &before), ("after", &after)],
+            );
+        }
+        Ok(handshake)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::DeviceIdentity;
+    use crate::wire;
+    use prost::Message;
+
+    #[test]
+    fn test_stripped_capabilities_fail_verification() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let identity = DeviceIdentity::new("phone".to_string()).unwrap();
+        let session = KeyExchange::new();
+        let original = phone.get_handshake_frame(&identity, &session);
+        assert!(laptop.verify_peer_handshake(&original).is_ok());
+
+        let (mut envelope, _) = wire::decode_frame(&original).unwrap().unwrap();
+        if let Some(wire::Body::Handshake(h)) = envelope.body.as_mut() {
+            h.capabilities &= !wire::CAP_BLAKE3;
+        }
+        let stripped = envelope.encode_length_delimited_to_vec();
+        assert!(laptop.verify_peer_handshake(&stripped).is_err());
+        assert_eq!(laptop.security_log.iter().last().unwrap().kind, SecurityEventKind::SignatureInvalid);
+
+        // A genuinely signed handshake with fewer capabilities is accepted but recorded
+        let mut weaker = verify(&original).unwrap();
+        weaker.capabilities = wire::CAP_CBOR;
+        sign(&identity, &mut weaker);
+        let weaker = wire::encode_frame(&wire::Envelope::new(wire::Body::Handshake(weaker)));
+        assert!(laptop.verify_peer_handshake(&weaker).is_ok());
+        assert_eq!(laptop.security_log.iter().last().unwrap().kind, SecurityEventKind::DowngradeAttempt);
+    }
+
+    #[test]
+    fn test_session_keys_bind_both_transcripts() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let (laptop_id, phone_id) = (DeviceIdentity::new("laptop".to_string()).unwrap(), DeviceIdentity::new("phone".to_string()).unwrap());
+        let (laptop_session, phone_session) = (KeyExchange::new(), KeyExchange::new());
+        let laptop_frame = laptop.get_handshake_frame(&laptop_id, &laptop_session);
+        let phone_frame = phone.get_handshake_frame(&phone_id, &phone_session);
+
+        let a = laptop.session_keys(&laptop_session, &laptop_frame, &phone_frame).unwrap();
+        let b = phone.session_keys(&phone_session, &phone_frame, &laptop_frame).unwrap();
+        assert_eq!(a.session_key, b.session_key);
+        assert_eq!(a.confirmation, b.expected_confirmation);
+        assert_eq!(b.confirmation, a.expected_confirmation);
+
+        // The phone was shown a different (older) laptop handshake: confirmation fails
+        laptop.update_file("a.md".to_string(), b"one", 1);
+        let replaced = laptop.get_handshake_frame(&laptop_id, &laptop_session);
+        let c = phone.session_keys(&phone_session, &phone_frame, &replaced).unwrap();
+        assert_ne!(c.session_key, a.session_key);
+        let (expected, received) = (BASE64.encode(c.expected_confirmation), BASE64.encode(a.confirmation));
+        assert!(!phone.check_session_confirmation("laptop", &expected, &received));
+        assert_eq!(phone.security_log.len(), 1);
+        assert!(!phone.access.bind("laptop"));
+
+        // Only a confirmed session binds the peer's key; a later mismatch drops it again
+        phone.session_keys(&phone_session, &phone_frame, &laptop_frame).unwrap();
+        let (expected, received) = (BASE64.encode(b.expected_confirmation), BASE64.encode(a.confirmation));
+        assert!(phone.check_session_confirmation("laptop", &expected, &received));
+        assert!(phone.access.requester_key("laptop").is_ok());
+        assert!(!phone.check_session_confirmation("laptop", &expected, "AAAA"));
+        assert!(phone.access.requester_key("laptop").is_err());
+
+        assert!(laptop.session_keys(&KeyExchange::new(), &laptop_frame, &phone_frame).is_err());
+    }
+}
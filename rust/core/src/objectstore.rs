@@ -0,0 +1,299 @@
+//! Encrypted object-store snapshots
+//!
+//! Backs the vault up to any S3-compatible (or otherwise dumb) object store
+//! without the store learning names or contents. Files are split into
+//! chunks stored under keyed-hash names (`chunks/<hex>`), each sealed with
+//! AES-256-GCM; a snapshot is an encrypted manifest (`manifests/<time>`)
+//! mapping paths to chunk lists. Chunk names are derived from a secret, so
+//! identical content deduplicates across snapshots but cannot be confirmed
+//! by the store. The planner tracks what the store already holds to upload
+//! only new chunks, and computes which objects can be deleted when old
+//! snapshots are dropped.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::clock;
+use crate::crypto::{decrypt_data, encrypt_data};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::sync::hash_content;
+
+/// Plaintext bytes per chunk object
+pub const OBJECT_CHUNK_BYTES: usize = 1024 * 1024;
+const NONCE_LEN: usize = 12;
+
+/// One object for the host to upload
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ObjectUpload {
+    pub key: String,
+    pub data_b64: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotFile {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    pub mtime: u64,
+    pub chunks: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotManifest {
+    pub format_version: u32,
+    pub created_at: u64,
+    pub files: Vec<SnapshotFile>,
+}
+
+/// Objects to delete after dropping old snapshots
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcPlan {
+    pub manifests: Vec<String>,
+    pub chunks: Vec<String>,
+}
+
+/// Persisted planner state (contains no key material)
+#[derive(Serialize, Deserialize, Default)]
+struct StoreIndex {
+    /// Content hash → chunk keys already on the store
+    content_chunks: HashMap<String, Vec<String>>,
+    /// Every chunk key on the store
+    chunks: HashSet<String>,
+    /// Manifest key → chunk keys it references, oldest first
+    manifests: BTreeMap<String, Vec<String>>,
+}
+
+pub struct ObjectStoreSnapshot {
+    id_key: [u8; 32],
+    enc_key_b64: String,
+    index: StoreIndex,
+    /// Files of the snapshot being built
+    staged: Vec<SnapshotFile>,
+    /// Chunks returned for upload during this snapshot
+    staged_chunks: HashSet<String>,
+}
+
+impl ObjectStoreSnapshot {
+    /// `master_key_b64` is a 32-byte secret; names and contents are keyed from it
+    pub fn new(master_key_b64: &str) -> Result<ObjectStoreSnapshot, String> {
+        let master = Zeroizing::new(BASE64.decode(master_key_b64).map_err(|e| e.to_string())?);
+        if master.len() != 32 {
+            return Err(format!("Invalid key length: {}", master.len()));
+        }
+        let hk = Hkdf::<Sha256>::new(Some(b"obsidian-p2p-sync objectstore"), master.as_slice());
+        let mut id_key = [0u8; 32];
+        let mut enc_key = Zeroizing::new([0u8; 32]);
+        hk.expand(b"chunk-names", &mut id_key).map_err(|e| e.to_string())?;
+        hk.expand(b"encryption", enc_key.as_mut_slice()).map_err(|e| e.to_string())?;
+        Ok(ObjectStoreSnapshot {
+            id_key,
+            enc_key_b64: BASE64.encode(enc_key.as_slice()),
+            index: StoreIndex::default(),
+            staged: Vec::new(),
+            staged_chunks: HashSet::new(),
+        })
+    }
+
+    /// Restore planner state saved with `get_state()`
+    pub fn load_state(&mut self, json: &str) -> Result<(), String> {
+        check_size("Object store state", json, MAX_JOURNAL_BYTES)?;
+        self.index = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn get_state(&self) -> String {
+        serde_json::to_string(&self.index).unwrap_or_default()
+    }
+
+    /// Whether the store already holds this content (so `add_known_file` suffices)
+    pub fn has_content(&self, hash: &str) -> bool {
+        self.index.content_chunks.contains_key(hash)
+    }
+
+    /// Add a file whose content the store already holds; false if unknown
+    pub fn add_known_file(&mut self, path: String, hash: String, size: u64, mtime: u64) -> bool {
+        let Some(chunks) = self.index.content_chunks.get(&hash) else {
+            return false;
+        };
+        self.staged.push(SnapshotFile { path, hash, size, mtime, chunks: chunks.clone() });
+        true
+    }
+
+    /// Add a file to the snapshot; returns `[{key, data_b64}]` for chunks not yet on the store
+    pub fn add_file(&mut self, path: String, content: &[u8], mtime: u64) -> Result<String, String> {
+        let uploads = self.stage_file(path, content, mtime)?;
+        serde_json::to_string(&uploads).map_err(|e| e.to_string())
+    }
+
+    /// Seal the snapshot; returns the manifest object `{key, data_b64}` to upload
+    pub fn finish_snapshot(&mut self) -> Result<String, String> {
+        let upload = self.seal_manifest()?;
+        serde_json::to_string(&upload).map_err(|e| e.to_string())
+    }
+
+    /// Keep the newest `keep` snapshots; returns `{manifests, chunks}` to delete
+    pub fn plan_gc(&mut self, keep: usize) -> String {
+        serde_json::to_string(&self.collect_garbage(keep)).unwrap_or_default()
+    }
+
+    /// Decrypt a manifest object for restore
+    pub fn open_manifest(&self, data: &[u8]) -> Result<String, String> {
+        let plain = self.open(data)?;
+        let manifest: SnapshotManifest = serde_json::from_slice(&plain).map_err(|e| e.to_string())?;
+        serde_json::to_string(&manifest).map_err(|e| e.to_string())
+    }
+
+    /// Decrypt a chunk object for restore
+    pub fn open_chunk(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.open(data)
+    }
+
+    pub fn get_snapshot_count(&self) -> usize {
+        self.index.manifests.len()
+    }
+}
+
+impl Drop for ObjectStoreSnapshot {
+    fn drop(&mut self) {
+        self.id_key.zeroize();
+        self.enc_key_b64.zeroize();
+    }
+}
+
+impl ObjectStoreSnapshot {
+    /// Object key for a chunk: keyed hash of its plaintext
+    fn chunk_key(&self, chunk: &[u8]) -> String {
+        let digest = Sha256::digest(chunk);
+        let mut name = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&self.id_key), &digest)
+            .expand(b"chunk", &mut name)
+            .expect("32 bytes is a valid HKDF output length");
+        format!("chunks/{}", hex::encode(name))
+    }
+
+    fn seal(&self, plain: &[u8]) -> Result<String, String> {
+        let sealed = encrypt_data(self.enc_key_b64.clone(), plain)?;
+        let mut object = sealed.get_nonce();
+        object.extend(sealed.get_data());
+        Ok(BASE64.encode(object))
+    }
+
+    fn open(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if data.len() < NONCE_LEN {
+            return Err("Object too short".to_string());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        Ok(decrypt_data(self.enc_key_b64.clone(), ciphertext, nonce)?)
+    }
+
+    pub fn stage_file(&mut self, path: String, content: &[u8], mtime: u64) -> Result<Vec<ObjectUpload>, String> {
+        let hash = hash_content(content);
+        if self.add_known_file(path.clone(), hash.clone(), content.len() as u64, mtime) {
+            return Ok(Vec::new());
+        }
+        let mut uploads = Vec::new();
+        let mut chunks = Vec::new();
+        for chunk in content.chunks(OBJECT_CHUNK_BYTES) {
+            let key = self.chunk_key(chunk);
+            if !self.index.chunks.contains(&key) && self.staged_chunks.insert(key.clone()) {
+                uploads.push(ObjectUpload { key: key.clone(), data_b64: self.seal(chunk)? });
+            }
+            chunks.push(key);
+        }
+        self.index.chunks.extend(chunks.iter().cloned());
+        self.index.content_chunks.insert(hash.clone(), chunks.clone());
+        self.staged.push(SnapshotFile { path, hash, size: content.len() as u64, mtime, chunks });
+        Ok(uploads)
+    }
+
+    pub fn seal_manifest(&mut self) -> Result<ObjectUpload, String> {
+        let created_at = clock::now_ms();
+        let mut files = std::mem::take(&mut self.staged);
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut key = format!("manifests/{:016}", created_at);
+        while self.index.manifests.contains_key(&key) {
+            key.push('a');
+        }
+        let referenced: Vec<String> = {
+            let set: HashSet<&String> = files.iter().flat_map(|f| &f.chunks).collect();
+            let mut keys: Vec<String> = set.into_iter().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let manifest = SnapshotManifest { format_version: 1, created_at, files };
+        let plain = serde_json::to_vec(&manifest).map_err(|e| e.to_string())?;
+        self.index.manifests.insert(key.clone(), referenced);
+        self.staged_chunks.clear();
+        Ok(ObjectUpload { key, data_b64: self.seal(&plain)? })
+    }
+
+    pub fn collect_garbage(&mut self, keep: usize) -> GcPlan {
+        let drop_count = self.index.manifests.len().saturating_sub(keep);
+        let dropped: Vec<String> = self.index.manifests.keys().take(drop_count).cloned().collect();
+        for key in &dropped {
+            self.index.manifests.remove(key);
+        }
+        let live: HashSet<String> = self.index.manifests.values().flatten().cloned().collect();
+        let mut chunks: Vec<String> = self.index.chunks.difference(&live).cloned().collect();
+        chunks.sort();
+        self.index.chunks = live;
+        let chunks_left = &self.index.chunks;
+        self.index.content_chunks.retain(|_, keys| keys.iter().all(|k| chunks_left.contains(k)));
+        GcPlan { manifests: dropped, chunks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> ObjectStoreSnapshot {
+        ObjectStoreSnapshot::new(&BASE64.encode([9u8; 32])).unwrap()
+    }
+
+    #[test]
+    fn test_incremental_uploads_and_restore() {
+        let mut snapshots = store();
+        let first = snapshots.stage_file("a.md".to_string(), b"alpha", 1).unwrap();
+        assert_eq!(first.len(), 1);
+        // Same content under another name is deduplicated
+        assert!(snapshots.stage_file("copy.md".to_string(), b"alpha", 1).unwrap().is_empty());
+        let manifest = snapshots.seal_manifest().unwrap();
+        assert!(!first[0].key.contains(&hash_content(b"alpha")));
+
+        let data = BASE64.decode(&manifest.data_b64).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("a.md"));
+        let opened: SnapshotManifest = serde_json::from_str(&snapshots.open_manifest(&data).unwrap()).unwrap();
+        assert_eq!(opened.files.len(), 2);
+        let chunk = BASE64.decode(&first[0].data_b64).unwrap();
+        assert_eq!(snapshots.open_chunk(&chunk).unwrap(), b"alpha");
+
+        // A reloaded planner knows what the store holds
+        let mut reloaded = store();
+        reloaded.load_state(&snapshots.get_state()).unwrap();
+        assert!(reloaded.has_content(&hash_content(b"alpha")));
+        assert!(reloaded.stage_file("a.md".to_string(), b"alpha", 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gc_drops_unreferenced_chunks() {
+        let mut snapshots = store();
+        clock::set_clock_time(1_000);
+        let old = snapshots.stage_file("a.md".to_string(), b"v1", 1).unwrap();
+        let first = snapshots.seal_manifest().unwrap();
+        clock::set_clock_time(2_000);
+        snapshots.stage_file("a.md".to_string(), b"v2", 2).unwrap();
+        snapshots.seal_manifest().unwrap();
+        clock::use_system_clock();
+
+        let plan = snapshots.collect_garbage(1);
+        assert_eq!(plan.manifests, vec![first.key]);
+        assert_eq!(plan.chunks, vec![old[0].key.clone()]);
+        assert!(!snapshots.has_content(&hash_content(b"v1")));
+        assert!(snapshots.has_content(&hash_content(b"v2")));
+    }
+}
@@ -0,0 +1,167 @@
+//! Orphaned journal state
+//!
+//! Over time the journal drifts from the vault: a file deleted while the
+//! plugin wasn't running still has a live entry, a tombstone stays behind
+//! after the file was recreated, and entries linger for paths the policy has
+//! since excluded. `detect_orphans` compares the journal with a full scan of
+//! the vault and remembers what it found; `cleanup_orphans` fixes exactly
+//! that (or only reports it, as a dry run), and refuses if the journal
+//! changed in between.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::sync::ChangeJournal;
+
+use crate::clock;
+use crate::policy::SyncPolicy;
+use crate::profiles::SyncProfile;
+use crate::P2PNode;
+
+/// A path found by the host's scan; other fields of the scan entry are ignored
+#[derive(Deserialize, Debug)]
+pub struct ScannedPath {
+    pub path: String,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanKind {
+    /// Live in the journal but not in the vault; cleanup records the missed delete
+    Missing,
+    /// Tombstoned but present in the vault; cleanup drops the tombstone so the
+    /// next index records the file afresh
+    Recreated,
+    /// Entry for a path the policy no longer syncs; cleanup drops it
+    Excluded,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Orphan {
+    pub path: String,
+    pub kind: OrphanKind,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrphanReport {
+    pub orphans: Vec<Orphan>,
+    /// Whether the entries were changed (false for a dry run)
+    pub applied: bool,
+}
+
+/// What the last detection found, valid while the journal stays at `sequence`
+pub struct OrphanScan {
+    sequence: u64,
+    orphans: Vec<Orphan>,
+}
+
+/// Compare the journal with the paths present in the vault. Live entries the
+/// profile doesn't hold locally are expected to be absent and are not reported
+pub fn detect(journal: &ChangeJournal, policy: &SyncPolicy, profile: &SyncProfile, scan: &[ScannedPath]) -> Vec<Orphan> {
+    let present: HashSet<&str> = scan.iter().map(|s| s.path.as_str()).collect();
+    let mut orphans: Vec<Orphan> = journal
+        .files()
+        .filter_map(|meta| {
+            let kind = if !policy.should_sync(&meta.path) {
+                OrphanKind::Excluded
+            } else if meta.is_deleted && present.contains(meta.path.as_str()) {
+                OrphanKind::Recreated
+            } else if !meta.is_deleted && !present.contains(meta.path.as_str()) && profile.wants(&meta.path, meta.size) {
+                OrphanKind::Missing
+            } else {
+                return None;
+            };
+            Some(Orphan { path: meta.path, kind })
+        })
+        .collect();
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    orphans
+}
+
+impl P2PNode {
+    /// Compare the journal with a full vault scan (a JSON array of `{path, ...}`);
+    /// returns the `{orphans: [{path, kind}]}` report as JSON and remembers it for `cleanup_orphans`
+    pub fn detect_orphans(&mut self, scan_json: &str) -> Result<String, String> {
+        self.find_orphans(scan_json)
+            .and_then(|report| serde_json::to_string(&report).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Fix what the last `detect_orphans` found; with `dry_run` only report it
+    pub fn cleanup_orphans(&mut self, dry_run: bool) -> Result<String, String> {
+        self.clean_orphans(dry_run)
+            .and_then(|report| serde_json::to_string(&report).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn find_orphans(&mut self, scan_json: &str) -> Result<OrphanReport, String> {
+        let scan: Vec<ScannedPath> = serde_json::from_str(scan_json).map_err(|e| format!("Invalid scan entries: {}", e))?;
+        let orphans = detect(&self.change_journal, &self.policy, &self.profile, &scan);
+        self.orphan_scan = Some(OrphanScan { sequence: self.change_journal.sequence(), orphans: orphans.clone() });
+        Ok(OrphanReport { orphans, applied: false })
+    }
+
+    pub(crate) fn clean_orphans(&mut self, dry_run: bool) -> Result<OrphanReport, String> {
+        let scan = self.orphan_scan.take().ok_or("Run detect_orphans first")?;
+        if scan.sequence != self.change_journal.sequence() {
+            return Err("Journal changed since detect_orphans; run it again".to_string());
+        }
+        if dry_run {
+            let orphans = scan.orphans.clone();
+            self.orphan_scan = Some(scan);
+            return Ok(OrphanReport { orphans, applied: false });
+        }
+        let now = clock::now_ms();
+        for orphan in &scan.orphans {
+            match orphan.kind {
+                OrphanKind::Missing => {
+                    self.mark_file_deleted(orphan.path.clone(), now);
+                }
+                OrphanKind::Recreated | OrphanKind::Excluded => {
+                    self.change_journal.forget(&orphan.path);
+                }
+            }
+        }
+        Ok(OrphanReport { orphans: scan.orphans, applied: true })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_then_cleanup() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.update_file("kept.md".to_string(), b"k", 1);
+        node.update_file("vanished.md".to_string(), b"v", 1);
+        node.update_file("back.md".to_string(), b"b", 1);
+        node.mark_file_deleted("back.md".to_string(), 2);
+        node.update_file("Private/x.md".to_string(), b"x", 1);
+        node.set_policy_rules(r#"[{"pattern":"Private/**","action":"skip"}]"#).unwrap();
+        let scan = r#"[{"path":"kept.md","mtime":1,"size":1},{"path":"back.md"}]"#;
+
+        let report = node.find_orphans(scan).unwrap();
+        let kinds: Vec<(&str, OrphanKind)> = report.orphans.iter().map(|o| (o.path.as_str(), o.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![("Private/x.md", OrphanKind::Excluded), ("back.md", OrphanKind::Recreated), ("vanished.md", OrphanKind::Missing)]
+        );
+
+        let sequence = node.change_journal.sequence();
+        assert_eq!(node.clean_orphans(true).unwrap().orphans.len(), 3);
+        assert_eq!(node.change_journal.sequence(), sequence);
+
+        assert!(node.clean_orphans(false).unwrap().applied);
+        assert!(node.change_journal.get("vanished.md").unwrap().is_deleted);
+        assert!(node.change_journal.get("back.md").is_none());
+        assert!(node.change_journal.get("Private/x.md").is_none());
+        assert!(node.clean_orphans(false).is_err());
+
+        node.find_orphans(scan).unwrap();
+        node.update_file("new.md".to_string(), b"n", 3);
+        assert!(node.clean_orphans(false).is_err());
+    }
+}
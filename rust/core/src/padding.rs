@@ -0,0 +1,156 @@
+//! Traffic padding and cover frames
+//!
+//! Encryption hides what a note says but not how big it is or when it
+//! changes. For users on hostile networks there is an opt-in padding mode:
+//! every chunk is padded inside the encryption to one of a few bucket sizes,
+//! the chunk count of a file is rounded up with filler chunks (Padmé, at most
+//! ~12% overhead), and the host can send `Cover` frames of random size at a
+//! low, jittered rate so idle periods look like activity. Both sides must
+//! enable padding for a transfer; cover frames need `CAP_COVER_TRAFFIC`.
+
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::transfer::CHUNK_SIZE;
+use crate::wire;
+use crate::P2PNode;
+
+/// A padded full chunk: the data plus the padding marker
+pub const PADDED_CHUNK_BYTES: usize = CHUNK_SIZE + 1;
+/// Plaintext sizes a padded chunk can have
+pub const BUCKETS: [usize; 5] = [512, 2048, 8192, 32 * 1024, PADDED_CHUNK_BYTES];
+const MARKER: u8 = 0x80;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PaddingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Mean interval between cover frames; 0 sends none
+    #[serde(default = "default_cover_interval_ms")]
+    pub cover_interval_ms: u64,
+}
+
+fn default_cover_interval_ms() -> u64 {
+    30_000
+}
+
+impl Default for PaddingConfig {
+    fn default() -> Self {
+        PaddingConfig { enabled: false, cover_interval_ms: default_cover_interval_ms() }
+    }
+}
+
+/// Pad `chunk` in place (ISO/IEC 7816-4: a marker byte, then zeros) to the next bucket
+pub fn pad(chunk: &mut Vec<u8>) {
+    let bucket = BUCKETS.iter().copied().find(|b| *b > chunk.len()).unwrap_or(chunk.len() + 1);
+    chunk.push(MARKER);
+    chunk.resize(bucket, 0);
+}
+
+/// Strip the padding added by `pad`
+pub fn unpad(chunk: &mut Vec<u8>) -> Result<(), Error> {
+    let end = chunk.iter().rposition(|b| *b != 0).ok_or("Padded chunk has no marker")?;
+    if chunk[end] != MARKER {
+        return Err("Padded chunk has no marker".into());
+    }
+    chunk.truncate(end);
+    Ok(())
+}
+
+/// Round `chunks` up with Padmé so only its leading bits reveal the size (at least one chunk)
+pub fn padded_chunk_count(chunks: u32) -> u32 {
+    if chunks <= 2 {
+        return chunks.max(1);
+    }
+    let exponent = 31 - chunks.leading_zeros();
+    let significant = 32 - exponent.leading_zeros();
+    let mask = (1u32 << (exponent - significant)) - 1;
+    chunks.saturating_add(mask) & !mask
+}
+
+/// When the next cover frame is due
+#[derive(Debug, Default)]
+pub struct CoverTraffic {
+    next_at: Option<u64>,
+}
+
+impl CoverTraffic {
+    /// A cover frame if one is due at `now`, scheduling the next at a jittered interval
+    pub fn poll(&mut self, now: u64, interval_ms: u64) -> Option<Vec<u8>> {
+        if interval_ms == 0 {
+            return None;
+        }
+        let jitter = interval_ms / 2 + OsRng.next_u64() % interval_ms.max(1);
+        let Some(next_at) = self.next_at else {
+            self.next_at = Some(now + jitter);
+            return None;
+        };
+        if now < next_at {
+            return None;
+        }
+        self.next_at = Some(now + jitter);
+        let mut filler = vec![0u8; BUCKETS[OsRng.next_u32() as usize % BUCKETS.len()]];
+        OsRng.fill_bytes(&mut filler);
+        Some(wire::encode_frame(&wire::Envelope::new(wire::Body::Cover(wire::Cover { filler }))))
+    }
+}
+
+impl P2PNode {
+    /// Configure padding from JSON (`{enabled, cover_interval_ms}`)
+    pub fn set_traffic_padding(&mut self, config_json: &str) -> Result<(), String> {
+        self.padding = serde_json::from_str(config_json)
+            .map_err(|e| self.record_error(format!("Invalid padding config: {}", e)))?;
+        Ok(())
+    }
+
+    pub fn get_traffic_padding_json(&self) -> String {
+        serde_json::to_string(&self.padding).unwrap_or_default()
+    }
+
+    /// A protobuf `Cover` frame to send if one is due at `now`; call from the host's timer
+    pub fn take_cover_frame(&mut self, now: u64) -> Option<Vec<u8>> {
+        if !self.padding.enabled {
+            return None;
+        }
+        self.cover_traffic.poll(now, self.padding.cover_interval_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_and_chunk_counts() {
+        for len in [0, 1, 511, 512, 5000, CHUNK_SIZE] {
+            let original: Vec<u8> = (0..len).map(|i| (i % 7) as u8).collect();
+            let mut chunk = original.clone();
+            pad(&mut chunk);
+            assert!(BUCKETS.contains(&chunk.len()), "{} padded to {}", len, chunk.len());
+            unpad(&mut chunk).unwrap();
+            assert_eq!(chunk, original);
+        }
+        assert!(unpad(&mut vec![1, 2, 0, 0]).is_err());
+
+        assert_eq!(padded_chunk_count(0), 1);
+        assert_eq!(padded_chunk_count(9), 10);
+        assert_eq!(padded_chunk_count(33), 36);
+        for n in 1..5000 {
+            let padded = padded_chunk_count(n);
+            assert!(padded >= n && (padded - n) as f64 <= n as f64 * 0.12 + 1.0);
+        }
+    }
+
+    #[test]
+    fn test_cover_frames_are_jittered_and_decodable() {
+        let mut cover = CoverTraffic::default();
+        assert!(cover.poll(0, 1000).is_none());
+        assert!(cover.poll(400, 1000).is_none());
+        let frame = cover.poll(1600, 1000).unwrap();
+        let (envelope, _) = wire::decode_frame(&frame).unwrap().unwrap();
+        assert!(matches!(envelope.body, Some(wire::Body::Cover(_))));
+        assert!(cover.poll(1600, 1000).is_none());
+        assert!(cover.poll(0, 0).is_none());
+    }
+}
@@ -0,0 +1,238 @@
+//! Pairing attempt limiting
+//!
+//! A 6-digit code has a million values, which a LAN attacker spamming
+//! pairing requests could get through in minutes. The node therefore checks
+//! every attempt itself: each source gets a few tries before it is locked
+//! out, for twice as long on every further lockout; the code is invalidated
+//! after too many failures across all sources (an attacker can rotate
+//! addresses) and as soon as it is used once. Lockouts are recorded in the
+//! security log. Only sources that sent a wrong code are tracked, at most
+//! `MAX_TRACKED_SOURCES` of them, and each is forgotten once it has been quiet
+//! for the longest lockout.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::clock;
+use crate::crypto::{PairingCode, DEFAULT_PAIRING_CODE_TTL_MS};
+use crate::security::SecurityEventKind;
+use crate::P2PNode;
+
+/// Wrong codes a source may send before it is locked out
+pub const MAX_FAILURES_PER_SOURCE: u32 = 3;
+/// Wrong codes across all sources before the code is invalidated
+pub const MAX_FAILURES_PER_CODE: u32 = 10;
+/// Sources with failed attempts remembered at once; the least recent is dropped beyond this
+pub const MAX_TRACKED_SOURCES: usize = 1024;
+const BASE_LOCKOUT_MS: u64 = 30_000;
+const MAX_LOCKOUT_MS: u64 = 60 * 60_000;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "result")]
+pub enum AttemptOutcome {
+    Accepted,
+    Rejected { remaining: u32 },
+    LockedOut { retry_after_ms: u64 },
+    /// No code is active (never started, expired, used or invalidated)
+    NoCode,
+}
+
+#[derive(Debug, Default)]
+struct SourceState {
+    failures: u32,
+    lockouts: u32,
+    locked_until: u64,
+    last_failure: u64,
+}
+
+impl SourceState {
+    /// Still locked out, or recent enough that its next lockout should be longer
+    fn is_live(&self, now: u64) -> bool {
+        self.locked_until > now || now.saturating_sub(self.last_failure) < MAX_LOCKOUT_MS
+    }
+}
+
+struct ActiveCode {
+    code: PairingCode,
+    ttl_ms: u64,
+    failures: u32,
+}
+
+#[derive(Default)]
+pub struct PairingGuard {
+    active: Option<ActiveCode>,
+    sources: HashMap<String, SourceState>,
+}
+
+fn codes_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl PairingGuard {
+    /// Replace any active code with a new one valid for `ttl_ms` (0 selects the default)
+    pub fn start(&mut self, ttl_ms: u64) -> String {
+        let code = PairingCode::generate();
+        let value = code.get_code();
+        let ttl_ms = if ttl_ms == 0 { DEFAULT_PAIRING_CODE_TTL_MS } else { ttl_ms };
+        self.active = Some(ActiveCode { code, ttl_ms, failures: 0 });
+        // Forget sources that are not locked out, so spoofed addresses don't pile up
+        let now = clock::now_ms();
+        self.sources.retain(|_, s| s.locked_until > now);
+        value
+    }
+
+    pub fn cancel(&mut self) {
+        self.active = None;
+    }
+
+    /// Check `code` sent by `source`; returns the outcome and whether this attempt caused a lockout
+    pub fn attempt(&mut self, source: &str, code: &str) -> (AttemptOutcome, bool) {
+        let now = clock::now_ms();
+        if let Some(state) = self.sources.get(source).filter(|s| s.locked_until > now) {
+            return (AttemptOutcome::LockedOut { retry_after_ms: state.locked_until - now }, false);
+        }
+        let Some(active) = self.active.as_mut().filter(|a| !a.code.is_expired(a.ttl_ms)) else {
+            self.active = None;
+            return (AttemptOutcome::NoCode, false);
+        };
+        if codes_equal(&active.code.get_code(), code) {
+            self.active = None;
+            self.sources.remove(source);
+            return (AttemptOutcome::Accepted, false);
+        }
+
+        active.failures += 1;
+        if active.failures >= MAX_FAILURES_PER_CODE {
+            self.active = None;
+        }
+        let state = self.failed_source(source, now);
+        state.failures += 1;
+        state.last_failure = now;
+        if state.failures < MAX_FAILURES_PER_SOURCE {
+            return (AttemptOutcome::Rejected { remaining: MAX_FAILURES_PER_SOURCE - state.failures }, false);
+        }
+        let lockout = BASE_LOCKOUT_MS.saturating_mul(1 << state.lockouts.min(16)).min(MAX_LOCKOUT_MS);
+        state.failures = 0;
+        state.lockouts += 1;
+        state.locked_until = now + lockout;
+        (AttemptOutcome::LockedOut { retry_after_ms: lockout }, true)
+    }
+
+    /// State of a source that just failed, making room for it if it is new
+    fn failed_source(&mut self, source: &str, now: u64) -> &mut SourceState {
+        if !self.sources.contains_key(source) && self.sources.len() >= MAX_TRACKED_SOURCES {
+            self.sources.retain(|_, s| s.is_live(now));
+            if self.sources.len() >= MAX_TRACKED_SOURCES {
+                let oldest = self.sources.iter().min_by_key(|(_, s)| s.last_failure).map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    self.sources.remove(&oldest);
+                }
+            }
+        }
+        self.sources.entry(source.to_string()).or_default()
+    }
+}
+
+impl P2PNode {
+    /// Start accepting pairing requests with a fresh single-use code, valid for
+    /// `ttl_ms` (0 selects the default); returns the code to show the user
+    pub fn start_pairing(&mut self, ttl_ms: u64) -> String {
+        self.pairing.start(ttl_ms)
+    }
+
+    pub fn cancel_pairing(&mut self) {
+        self.pairing.cancel();
+    }
+
+    /// Check the code in a pairing request from `source` (e.g. its IP); returns
+    /// `{result: accepted | rejected | locked_out | no_code, ...}` as JSON
+    pub fn check_pairing_attempt(&mut self, source: &str, code: &str) -> String {
+        serde_json::to_string(&self.pairing_attempt(source, code)).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn pairing_attempt(&mut self, source: &str, code: &str) -> AttemptOutcome {
+        let (outcome, locked) = self.pairing.attempt(source, code);
+        if let (true, AttemptOutcome::LockedOut { retry_after_ms }) = (locked, outcome) {
+            let retry = retry_after_ms.to_string();
+            self.security_log.record(
+                SecurityEventKind::Lockout,
+                source,
+                format!("Too many wrong pairing codes from {}", source),
+                &[("retry_after_ms", &retry)],
+            );
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrong(code: &str) -> String {
+        format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000)
+    }
+
+    #[test]
+    fn test_lockout_doubles_and_code_is_single_use() {
+        clock::set_clock_time(1_000);
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let code = node.start_pairing(0);
+        let bad = wrong(&code);
+
+        assert_eq!(node.pairing_attempt("10.0.0.9", &bad), AttemptOutcome::Rejected { remaining: 2 });
+        node.pairing_attempt("10.0.0.9", &bad);
+        assert_eq!(node.pairing_attempt("10.0.0.9", &bad), AttemptOutcome::LockedOut { retry_after_ms: 30_000 });
+        // Even the right code is refused during a lockout
+        assert!(matches!(node.pairing_attempt("10.0.0.9", &code), AttemptOutcome::LockedOut { .. }));
+        assert_eq!(node.security_log.len(), 1);
+
+        clock::advance_clock(30_000);
+        for _ in 0..3 {
+            node.pairing_attempt("10.0.0.9", &bad);
+        }
+        assert_eq!(node.pairing_attempt("10.0.0.9", &bad), AttemptOutcome::LockedOut { retry_after_ms: 60_000 });
+
+        assert_eq!(node.pairing_attempt("10.0.0.7", &code), AttemptOutcome::Accepted);
+        assert_eq!(node.pairing_attempt("10.0.0.8", &code), AttemptOutcome::NoCode);
+
+        // Failures spread across sources still burn the code
+        let code = node.start_pairing(0);
+        for i in 0..MAX_FAILURES_PER_CODE {
+            node.pairing_attempt(&format!("10.0.1.{}", i), &wrong(&code));
+        }
+        assert_eq!(node.pairing_attempt("10.0.2.1", &code), AttemptOutcome::NoCode);
+        clock::use_system_clock();
+    }
+
+    #[test]
+    fn test_sources_are_tracked_only_on_failure_and_capped() {
+        clock::set_clock_time(1_000);
+        let mut guard = PairingGuard::default();
+        // Without a code nothing is remembered about the sender
+        for i in 0..100 {
+            assert_eq!(guard.attempt(&format!("10.1.0.{}", i), "000000").0, AttemptOutcome::NoCode);
+        }
+        assert!(guard.sources.is_empty());
+
+        let code = guard.start(0);
+        guard.attempt("10.0.0.9", &wrong(&code));
+        assert_eq!(guard.sources.len(), 1);
+        for i in 0..MAX_TRACKED_SOURCES + 50 {
+            guard.active = Some(ActiveCode { code: PairingCode::generate(), ttl_ms: DEFAULT_PAIRING_CODE_TTL_MS, failures: 0 });
+            clock::advance_clock(1);
+            guard.attempt(&format!("source-{}", i), "not-a-code");
+        }
+        assert_eq!(guard.sources.len(), MAX_TRACKED_SOURCES);
+        assert!(!guard.sources.contains_key("10.0.0.9"));
+
+        // Quiet sources are forgotten once the longest lockout has passed
+        clock::advance_clock(MAX_LOCKOUT_MS);
+        guard.active = Some(ActiveCode { code: PairingCode::generate(), ttl_ms: 0, failures: 0 });
+        guard.attempt("10.0.0.10", "not-a-code");
+        assert_eq!(guard.sources.len(), 1);
+        clock::use_system_clock();
+    }
+}
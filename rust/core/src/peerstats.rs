@@ -0,0 +1,243 @@
+//! Per-peer sync history and reliability
+//!
+//! The host reports every finished round with `record_sync_round`: who it
+//! was with, how long it took, whether it succeeded and how many bytes went
+//! each way. The node keeps the last success, the run of consecutive
+//! failures, a running average of round durations and hourly byte counts for
+//! the last day, so the UI can show "Phone: last synced 2h ago, 3 failed
+//! attempts". The scheduler stretches a peer's interval while it keeps
+//! failing (doubling per failure, up to `MAX_BACKOFF_SHIFT`) so a device
+//! that is switched off doesn't take a round slot every tick.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::P2PNode;
+
+const HOUR_MS: u64 = 60 * 60_000;
+/// Hourly byte windows kept per peer
+const WINDOW_HOURS: usize = 24;
+/// A failing peer's interval grows to at most 2^5 = 32 times its usual one
+pub const MAX_BACKOFF_SHIFT: u32 = 5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ByteWindow {
+    /// Start of the hour, in milliseconds
+    pub start: u64,
+    pub sent: u64,
+    pub received: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PeerStats {
+    pub last_success_at: Option<u64>,
+    pub last_failure_at: Option<u64>,
+    pub consecutive_failures: u32,
+    pub rounds: u64,
+    pub failed_rounds: u64,
+    pub average_duration_ms: f64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Oldest first, at most `WINDOW_HOURS`
+    pub windows: VecDeque<ByteWindow>,
+}
+
+impl PeerStats {
+    fn record(&mut self, round: &RoundOutcome) {
+        self.rounds += 1;
+        let duration = round.finished_at.saturating_sub(round.started_at) as f64;
+        self.average_duration_ms += (duration - self.average_duration_ms) / self.rounds as f64;
+        if round.succeeded {
+            self.last_success_at = Some(round.finished_at);
+            self.consecutive_failures = 0;
+        } else {
+            self.last_failure_at = Some(round.finished_at);
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            self.failed_rounds += 1;
+        }
+        self.bytes_sent += round.bytes_sent;
+        self.bytes_received += round.bytes_received;
+
+        let start = round.finished_at - round.finished_at % HOUR_MS;
+        match self.windows.back_mut() {
+            Some(window) if window.start == start => {
+                window.sent += round.bytes_sent;
+                window.received += round.bytes_received;
+            }
+            _ => {
+                self.windows.push_back(ByteWindow { start, sent: round.bytes_sent, received: round.bytes_received });
+                while self.windows.len() > WINDOW_HOURS {
+                    self.windows.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Bytes (sent, received) in windows that overlap the last `span_ms` before `now`
+    pub fn bytes_within(&self, now: u64, span_ms: u64) -> (u64, u64) {
+        self.windows
+            .iter()
+            .filter(|w| w.start + HOUR_MS > now.saturating_sub(span_ms))
+            .fold((0, 0), |(sent, received), w| (sent + w.sent, received + w.received))
+    }
+}
+
+#[derive(Debug)]
+struct RoundOutcome {
+    started_at: u64,
+    finished_at: u64,
+    succeeded: bool,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PeerHistory {
+    peers: BTreeMap<String, PeerStats>,
+}
+
+impl PeerHistory {
+    pub fn get(&self, device_id: &str) -> Option<&PeerStats> {
+        self.peers.get(device_id)
+    }
+
+    /// Every peer with a recorded round
+    pub fn device_ids(&self) -> impl Iterator<Item = &str> {
+        self.peers.keys().map(String::as_str)
+    }
+
+    /// How many times a peer's usual interval to wait before its next round
+    pub fn backoff_factor(&self, device_id: &str) -> u64 {
+        let failures = self.get(device_id).map_or(0, |s| s.consecutive_failures);
+        1 << failures.min(MAX_BACKOFF_SHIFT)
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct PeerReport<'a> {
+    device_id: &'a str,
+    name: String,
+    last_success_at: Option<u64>,
+    /// Milliseconds since the last success, for "last synced 2h ago"
+    since_last_success_ms: Option<u64>,
+    consecutive_failures: u32,
+    rounds: u64,
+    failed_rounds: u64,
+    average_duration_ms: u64,
+    bytes_sent_last_hour: u64,
+    bytes_received_last_hour: u64,
+    bytes_sent_last_day: u64,
+    bytes_received_last_day: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    backoff_factor: u64,
+}
+
+impl P2PNode {
+    /// Record a finished round with a peer; also counts towards the current round report
+    pub fn record_sync_round(
+        &mut self,
+        device_id: &str,
+        started_at: u64,
+        finished_at: u64,
+        succeeded: bool,
+        bytes_sent: u64,
+        bytes_received: u64,
+    ) {
+        let round = RoundOutcome { started_at, finished_at, succeeded, bytes_sent, bytes_received };
+        self.peer_history.peers.entry(device_id.to_string()).or_default().record(&round);
+        let report = self.round_reports.peer(device_id);
+        report.bytes_sent += bytes_sent;
+        report.bytes_received += bytes_received;
+        report.succeeded = Some(succeeded);
+    }
+
+    /// Sync history of every peer with at least one recorded round, as a JSON array
+    pub fn get_peer_stats_json(&self, now: u64) -> String {
+        let reports: Vec<PeerReport> = self
+            .peer_history
+            .peers
+            .iter()
+            .map(|(device_id, stats)| {
+                let (sent_hour, received_hour) = stats.bytes_within(now, HOUR_MS);
+                let (sent_day, received_day) = stats.bytes_within(now, WINDOW_HOURS as u64 * HOUR_MS);
+                PeerReport {
+                    device_id,
+                    name: self.get_device_display_name(device_id),
+                    last_success_at: stats.last_success_at,
+                    since_last_success_ms: stats.last_success_at.map(|at| now.saturating_sub(at)),
+                    consecutive_failures: stats.consecutive_failures,
+                    rounds: stats.rounds,
+                    failed_rounds: stats.failed_rounds,
+                    average_duration_ms: stats.average_duration_ms.round() as u64,
+                    bytes_sent_last_hour: sent_hour,
+                    bytes_received_last_hour: received_hour,
+                    bytes_sent_last_day: sent_day,
+                    bytes_received_last_day: received_day,
+                    bytes_sent: stats.bytes_sent,
+                    bytes_received: stats.bytes_received,
+                    backoff_factor: self.peer_history.backoff_factor(device_id),
+                }
+            })
+            .collect();
+        serde_json::to_string(&reports).unwrap_or_default()
+    }
+
+    /// Forget a peer's history, e.g. after it was fixed or unpaired
+    pub fn reset_peer_stats(&mut self, device_id: &str) {
+        self.peer_history.peers.remove(device_id);
+    }
+
+    /// Peer history as JSON, for persisting with `load_peer_history_state`
+    pub fn get_peer_history_state(&self) -> String {
+        serde_json::to_string(&self.peer_history).unwrap_or_default()
+    }
+
+    pub fn load_peer_history_state(&mut self, json: &str) -> Result<(), String> {
+        let history = check_size("Peer history", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Failed to load peer history: {}", e)))?;
+        self.peer_history = history;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_tracks_failures_and_backs_off() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let start = 100 * HOUR_MS;
+        node.record_sync_round("phone", start, start + 1000, true, 500, 2000);
+        node.record_sync_round("phone", start + HOUR_MS, start + HOUR_MS + 3000, true, 100, 0);
+        for i in 0..3 {
+            node.record_sync_round("phone", start + 2 * HOUR_MS + i, start + 2 * HOUR_MS + i, false, 0, 0);
+        }
+
+        let now = start + 2 * HOUR_MS + 60_000;
+        let stats: serde_json::Value = serde_json::from_str(&node.get_peer_stats_json(now)).unwrap();
+        let phone = &stats[0];
+        assert_eq!(phone["device_id"], "phone");
+        assert_eq!(phone["since_last_success_ms"], HOUR_MS - 3000 + 60_000);
+        assert_eq!(phone["consecutive_failures"], 3);
+        assert_eq!(phone["average_duration_ms"], 800);
+        assert_eq!(phone["bytes_sent_last_hour"], 100);
+        assert_eq!(phone["bytes_sent_last_day"], 600);
+        assert_eq!(phone["backoff_factor"], 8);
+
+        // A failing peer waits its interval times the backoff factor
+        let mut restored = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        restored.load_peer_history_state(&node.get_peer_history_state()).unwrap();
+        let backoff = |peer: &str| restored.peer_history.backoff_factor(peer);
+        let mut scheduler = crate::schedule::SyncScheduler::default();
+        assert_eq!(scheduler.tick(now, ["phone", "tablet"], 60_000, backoff), vec!["phone", "tablet"]);
+        assert_eq!(scheduler.tick(now + 60_000, ["phone", "tablet"], 60_000, backoff), vec!["tablet"]);
+        assert_eq!(scheduler.tick(now + 480_000, ["phone", "tablet"], 60_000, backoff), vec!["phone", "tablet"]);
+
+        node.record_sync_round("phone", now, now + 10, true, 0, 0);
+        assert_eq!(node.peer_history.backoff_factor("phone"), 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! Pinned files and folders
+//!
+//! A pin keeps a note, or everything under a folder, fully downloaded on
+//! this device whatever the profile would otherwise leave out: pinned files
+//! never become placeholders, are wanted past the profile's folder filters,
+//! size cap and storage budget, and go to the front of the transfer plan
+//! and the prefetch plan so they are the first to be fresh after a peer
+//! connects. Pinning a folder that already holds placeholders queues their
+//! content for download.
+//!
+//! Each profile has its own pin set: switching a device to `mobile-lite`
+//! applies the pins made under `mobile-lite`, and switching back restores
+//! the others. Pin sets are part of the exported configuration.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::attachments::TransferOrder;
+use crate::clock;
+use crate::P2PNode;
+
+/// Pins per profile; a profile can hold this many
+pub const MAX_PINS: usize = 1000;
+
+/// Pinned paths and folders by profile name
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct PinSets {
+    sets: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// `Notes/` and `Notes` pin the same folder
+pub(crate) fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+/// Whether `pin` is `path` or one of its folders
+pub(crate) fn covers(pin: &str, path: &str) -> bool {
+    pin.is_empty() || path == pin || path.strip_prefix(pin).is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl PinSets {
+    pub fn pins(&self, profile: &str) -> impl Iterator<Item = &str> {
+        self.sets.get(profile).into_iter().flatten().map(String::as_str)
+    }
+
+    pub fn is_pinned(&self, profile: &str, path: &str) -> bool {
+        self.pins(profile).any(|pin| covers(pin, path))
+    }
+
+    /// False if `path` was already pinned
+    pub fn pin(&mut self, profile: &str, path: &str) -> Result<bool, String> {
+        let set = self.sets.entry(profile.to_string()).or_default();
+        if set.len() >= MAX_PINS && !set.contains(normalize(path)) {
+            return Err(format!("Profile {} already has {} pins", profile, MAX_PINS));
+        }
+        Ok(set.insert(normalize(path).to_string()))
+    }
+
+    pub fn unpin(&mut self, profile: &str, path: &str) -> bool {
+        let Some(set) = self.sets.get_mut(profile) else {
+            return false;
+        };
+        let removed = set.remove(normalize(path));
+        if set.is_empty() {
+            self.sets.remove(profile);
+        }
+        removed
+    }
+}
+
+/// Move pinned paths to the front of `order.transfer`, taking them out of `deferred`
+pub fn prioritize(order: &mut TransferOrder, pinned: impl Fn(&str) -> bool) {
+    let (mut first, rest): (Vec<String>, Vec<String>) = order.transfer.drain(..).partition(|path| pinned(path));
+    let (held_back, deferred): (Vec<String>, Vec<String>) = order.deferred.drain(..).partition(|path| pinned(path));
+    first.extend(held_back);
+    first.extend(rest);
+    order.transfer = first;
+    order.deferred = deferred;
+}
+
+impl P2PNode {
+    /// Pin a file or folder under the active profile so its content is always held here.
+    /// Returns how many placeholders under it were queued for download
+    pub fn pin(&mut self, path_or_folder: &str) -> Result<usize, String> {
+        let profile = self.profile.name.clone();
+        if let Err(e) = self.pins.pin(&profile, path_or_folder) {
+            return Err(self.record_error(e));
+        }
+        let pin = normalize(path_or_folder).to_string();
+        let placeholders: Vec<String> = self
+            .change_journal
+            .files()
+            .filter(|meta| covers(&pin, &meta.path) && self.is_placeholder(&meta.path))
+            .map(|meta| meta.path)
+            .collect();
+        let now = clock::now_ms();
+        let mut queued = 0;
+        for path in placeholders {
+            if self.placeholders.request(&self.change_journal, &path, now) == Ok(true) {
+                queued += 1;
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Remove a pin of the active profile; false if there was none. Content already
+    /// downloaded stays
+    pub fn unpin(&mut self, path_or_folder: &str) -> bool {
+        let profile = self.profile.name.clone();
+        self.pins.unpin(&profile, path_or_folder)
+    }
+
+    /// Whether the active profile pins `path` or one of its folders
+    pub fn is_pinned(&self, path: &str) -> bool {
+        self.pins.is_pinned(&self.profile.name, path)
+    }
+
+    /// Pins of the active profile as a JSON array
+    pub fn get_pins_json(&self) -> String {
+        serde_json::to_string(&self.pins.pins(&self.profile.name).collect::<Vec<_>>()).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    /// Whether this device should hold `path`: pinned, or wanted by the profile
+    pub(crate) fn wants_locally(&self, path: &str, size: u64) -> bool {
+        self.is_pinned(path) || self.profile.wants(path, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::FileMetadata;
+
+    #[test]
+    fn test_pins_follow_profile_and_lead_the_plan() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let profile = r#"{"name":"tree-only","on_demand":true,"exclude_folders":["Projects/Archive"]}"#;
+        phone.set_custom_profile(profile).unwrap();
+        for path in ["Projects/plan.md", "Projects/Archive/old.md", "inbox.md"] {
+            laptop.update_file(path.to_string(), path.as_bytes(), 1);
+        }
+        let changes: Vec<FileMetadata> = laptop.change_journal.files().collect();
+        phone.prepare_round(&serde_json::to_string(&changes).unwrap(), "laptop").unwrap();
+        phone.commit_round().unwrap();
+        assert!(phone.is_placeholder("Projects/plan.md"));
+
+        assert_eq!(phone.pin("Projects/").unwrap(), 2);
+        assert!(phone.is_pinned("Projects/Archive/old.md"));
+        assert!(!phone.is_pinned("ProjectsOld/a.md"));
+        assert_eq!(phone.get_pins_json(), r#"["Projects"]"#);
+
+        // Pinned files are downloaded rather than left as placeholders
+        laptop.update_file("Projects/plan.md".to_string(), b"v2", 2);
+        laptop.update_file("inbox.md".to_string(), b"v2", 2);
+        let changes: Vec<FileMetadata> = laptop.change_journal.files().filter(|m| m.version > 3).collect();
+        let status = phone.prepare_round(&serde_json::to_string(&changes).unwrap(), "laptop").unwrap();
+        assert_eq!(status.awaiting, vec!["Projects/plan.md"]);
+        phone.abort_sync_round();
+
+        let files = r#"[{"path":"inbox.md","size":5},{"path":"Projects/Archive/old.md","size":5}]"#;
+        let order: serde_json::Value = serde_json::from_str(&phone.plan_transfer_order(files, false).unwrap()).unwrap();
+        assert_eq!(order["transfer"], serde_json::json!(["Projects/Archive/old.md", "inbox.md"]));
+
+        // Another profile has its own pins
+        phone.select_profile("full").unwrap();
+        assert!(!phone.is_pinned("Projects/plan.md"));
+        phone.set_custom_profile(profile).unwrap();
+        assert!(phone.unpin("Projects"));
+        assert_eq!(phone.get_pins_json(), "[]");
+    }
+}
@@ -0,0 +1,277 @@
+//! Placeholders and on-demand fetch
+//!
+//! A device on an `on_demand` profile shows the whole vault tree but holds
+//! content only for the files it has opened. Sync rounds record the writes
+//! of files it doesn't hold as placeholders: the journal has their metadata
+//! as usual, the host shows them however it likes (a stub file, an entry in
+//! its own tree view) and downloads nothing.
+//!
+//! Opening a placeholder calls `request_file`, which queues an on-demand
+//! pull. The host's transfer loop takes `get_file_requests_json` before any
+//! other pull, fetches each body from a peer that has it and hands it to
+//! `complete_file_request` (or `complete_file_request_ingest` for streamed
+//! bodies), which checks it against the journal's hash. Arrivals and failures
+//! are reported as events from `take_placeholder_events`.
+//!
+//! A placeholder remembers the hash it was recorded for, so a local write
+//! or delete of the path, or a normal download, ends it without every
+//! journal entry point having to know about placeholders. Only a newer
+//! placeholder operation for the path moves it to the new hash.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::sync::ChangeJournal;
+
+use crate::clock;
+use crate::hashing::HashAlgorithm;
+use crate::ingest::FileIngest;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::round::{CommitOp, RoundAction};
+use crate::P2PNode;
+
+/// Events kept for the host before the oldest are dropped
+const MAX_EVENTS: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileRequest {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    pub requested_at: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PlaceholderEvent {
+    /// The content of a requested file arrived and verified
+    Arrived { path: String, hash: String },
+    /// A request was given up; the file is still a placeholder
+    Failed { path: String, reason: String },
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PlaceholderState {
+    /// Path → hash the placeholder was recorded for
+    files: BTreeMap<String, String>,
+    /// Oldest request first
+    requests: VecDeque<FileRequest>,
+    #[serde(skip)]
+    events: VecDeque<PlaceholderEvent>,
+}
+
+impl PlaceholderState {
+    /// Whether `path` is a placeholder for the journal's current version
+    pub fn is_placeholder(&self, journal: &ChangeJournal, path: &str) -> bool {
+        self.files.get(path).is_some_and(|hash| journal.get(path).is_some_and(|m| !m.is_deleted && m.hash == *hash))
+    }
+
+    /// Track the placeholders a committed round created or ended
+    pub fn record_round(&mut self, ops: &[CommitOp]) {
+        for op in ops {
+            match op.action {
+                RoundAction::Placeholder => {
+                    self.files.insert(op.path.clone(), op.hash.clone());
+                }
+                RoundAction::Write | RoundAction::Delete => {
+                    self.files.remove(&op.path);
+                }
+                RoundAction::Move | RoundAction::Metadata => {}
+            }
+        }
+    }
+
+    /// Drop placeholders and requests the journal has moved past
+    fn prune(&mut self, journal: &ChangeJournal) {
+        let stale: Vec<String> = self.files.keys().filter(|path| !self.is_placeholder(journal, path)).cloned().collect();
+        for path in stale {
+            self.files.remove(&path);
+        }
+        let files = &self.files;
+        self.requests.retain(|request| files.contains_key(&request.path));
+    }
+
+    fn push_event(&mut self, event: PlaceholderEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Queue a pull of `path`; false if it was already queued
+    pub fn request(&mut self, journal: &ChangeJournal, path: &str, now: u64) -> Result<bool, String> {
+        if !self.is_placeholder(journal, path) {
+            return Err(format!("{} is not a placeholder", path));
+        }
+        if self.requests.iter().any(|request| request.path == path) {
+            return Ok(false);
+        }
+        let meta = journal.get(path).ok_or_else(|| format!("{} is not a placeholder", path))?;
+        self.requests.push_back(FileRequest { path: meta.path, hash: meta.hash, size: meta.size, requested_at: now });
+        Ok(true)
+    }
+
+    /// Content for `path` hashed to `hash`: the placeholder ends if it matches
+    pub fn complete(&mut self, journal: &ChangeJournal, path: &str, hash: &str) -> Result<(), String> {
+        if !self.is_placeholder(journal, path) {
+            return Err(format!("{} is not a placeholder", path));
+        }
+        let expected = self.files[path].clone();
+        if hash != expected {
+            return Err(format!("Content for {} does not match hash {}", path, expected));
+        }
+        self.files.remove(path);
+        self.requests.retain(|request| request.path != path);
+        self.push_event(PlaceholderEvent::Arrived { path: path.to_string(), hash: expected });
+        Ok(())
+    }
+
+    pub fn fail(&mut self, path: &str, reason: &str) -> bool {
+        let before = self.requests.len();
+        self.requests.retain(|request| request.path != path);
+        if self.requests.len() == before {
+            return false;
+        }
+        self.push_event(PlaceholderEvent::Failed { path: path.to_string(), reason: reason.to_string() });
+        true
+    }
+}
+
+impl P2PNode {
+    /// Whether `path` is shown without its content
+    pub fn is_placeholder(&self, path: &str) -> bool {
+        self.placeholders.is_placeholder(&self.change_journal, path)
+    }
+
+    /// Paths of every current placeholder as a JSON array
+    pub fn get_placeholders_json(&mut self) -> String {
+        self.placeholders.prune(&self.change_journal);
+        serde_json::to_string(&self.placeholders.files.keys().collect::<Vec<_>>()).unwrap_or_default()
+    }
+
+    /// Queue an on-demand pull of a placeholder's content; false if already queued
+    pub fn request_file(&mut self, path: &str) -> Result<bool, String> {
+        self.placeholders.request(&self.change_journal, path, clock::now_ms()).map_err(|e| self.record_error(e))
+    }
+
+    /// Queued pulls as `[{path, hash, size, requested_at}]`, oldest first, to fetch
+    /// before any other transfer
+    pub fn get_file_requests_json(&mut self) -> String {
+        self.placeholders.prune(&self.change_journal);
+        serde_json::to_string(&self.placeholders.requests).unwrap_or_default()
+    }
+
+    /// Hand over the fetched content of a placeholder; the host writes it into
+    /// the vault once this succeeds
+    pub fn complete_file_request(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        let expected = self.change_journal.get(path).map(|m| m.hash).unwrap_or_default();
+        let hash = HashAlgorithm::of(&expected).hash(content);
+        self.placeholders.complete(&self.change_journal, path, &hash).map_err(|e| self.record_error(e))
+    }
+
+    /// `complete_file_request` for content streamed through a `FileIngest`
+    pub fn complete_file_request_ingest(&mut self, ingest: &FileIngest) -> Result<(), String> {
+        let path = ingest.file_path().to_string();
+        let expected = self.change_journal.get(&path).map(|m| m.hash).unwrap_or_default();
+        let result = match ingest.hash_for(HashAlgorithm::of(&expected)) {
+            Some(hash) => self.placeholders.complete(&self.change_journal, &path, hash),
+            None => Err(format!("Ingest of {} was not hashed with {}", path, HashAlgorithm::of(&expected).as_str())),
+        };
+        result.map_err(|e| self.record_error(e))
+    }
+
+    /// Give up on a queued pull (e.g. no peer has the content); false if it wasn't queued
+    pub fn fail_file_request(&mut self, path: &str, reason: &str) -> bool {
+        self.placeholders.fail(path, reason)
+    }
+
+    /// Events since the last call as `[{type: "arrived", path, hash} | {type: "failed", path, reason}]`
+    pub fn take_placeholder_events(&mut self) -> String {
+        let events: Vec<PlaceholderEvent> = self.placeholders.events.drain(..).collect();
+        serde_json::to_string(&events).unwrap_or_default()
+    }
+
+    /// Placeholders and queued pulls, to persist next to the journal
+    pub fn export_placeholder_state(&mut self) -> String {
+        self.placeholders.prune(&self.change_journal);
+        serde_json::to_string(&self.placeholders).unwrap_or_default()
+    }
+
+    pub fn load_placeholder_state(&mut self, json: &str) -> Result<(), String> {
+        let result = check_size("Placeholder state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str::<PlaceholderState>(json).map_err(|e| e.to_string().into()));
+        match result {
+            Ok(state) => {
+                self.placeholders = state;
+                Ok(())
+            }
+            Err(e) => Err(self.record_error(format!("Invalid placeholder state: {}", e))),
+        }
+    }
+}
+
+impl P2PNode {
+    /// Whether this device has the content of `path`'s current version
+    pub(crate) fn holds_content(&self, path: &str) -> bool {
+        self.change_journal.get(path).is_some_and(|m| !m.is_deleted) && !self.is_placeholder(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{hash_content, FileMetadata};
+
+    fn changes_since(node: &P2PNode, since: u64) -> String {
+        let changes: Vec<FileMetadata> = node.change_journal.files().filter(|m| m.version > since).collect();
+        serde_json::to_string(&changes).unwrap()
+    }
+
+    #[test]
+    fn test_placeholders_fetch_on_request() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        phone.set_custom_profile(r#"{"name":"tree-only","on_demand":true}"#).unwrap();
+        phone.update_file("held.md".to_string(), b"old", 1);
+        laptop.update_file("held.md".to_string(), b"new", 2);
+        laptop.update_file("big.pdf".to_string(), b"pdf", 2);
+
+        // Files already held are downloaded as usual
+        phone.prepare_round(&changes_since(&laptop, 0), "laptop").unwrap();
+        phone.verify_round_content("held.md", b"new").unwrap();
+        let ops = phone.commit_round().unwrap();
+        let actions: Vec<_> = ops.iter().map(|op| (op.path.as_str(), op.action)).collect();
+        assert_eq!(actions, vec![("big.pdf", RoundAction::Placeholder), ("held.md", RoundAction::Write)]);
+        assert!(phone.is_placeholder("big.pdf"));
+        assert!(phone.request_file("held.md").is_err());
+
+        assert!(phone.request_file("big.pdf").unwrap());
+        assert!(!phone.request_file("big.pdf").unwrap());
+        assert!(phone.get_file_requests_json().contains("big.pdf"));
+        assert!(phone.complete_file_request("big.pdf", b"tampered").is_err());
+        phone.complete_file_request("big.pdf", b"pdf").unwrap();
+        assert!(!phone.is_placeholder("big.pdf"));
+        assert_eq!(phone.get_file_requests_json(), "[]");
+        let arrived = format!(r#"[{{"type":"arrived","path":"big.pdf","hash":"{}"}}]"#, hash_content(b"pdf"));
+        assert_eq!(phone.take_placeholder_events(), arrived);
+
+        // Fetched files stay fetched; new ones arrive as placeholders
+        let sequence = laptop.change_journal.sequence();
+        laptop.update_file("big.pdf".to_string(), b"pdf v2", 3);
+        laptop.update_file("later.md".to_string(), b"later", 3);
+        let status = phone.prepare_round(&changes_since(&laptop, sequence), "laptop").unwrap();
+        assert_eq!(status.awaiting, vec!["big.pdf"]);
+        phone.verify_round_content("big.pdf", b"pdf v2").unwrap();
+        phone.commit_round().unwrap();
+        assert!(phone.is_placeholder("later.md"));
+
+        phone.request_file("later.md").unwrap();
+        let saved = phone.export_placeholder_state();
+        // A local write ends the placeholder and its request
+        phone.update_file("later.md".to_string(), b"written here", 4);
+        assert_eq!(phone.get_placeholders_json(), "[]");
+        assert!(!phone.fail_file_request("later.md", "offline"));
+        phone.load_placeholder_state(&saved).unwrap();
+        assert_eq!(phone.get_file_requests_json(), "[]");
+    }
+}
@@ -0,0 +1,283 @@
+//! Per-path sync policies
+//!
+//! Each path resolves to one action: sync it with last-writer-wins, merge
+//! it structurally, or skip it entirely. User rules are consulted first,
+//! then append-merge rules, then the built-in defaults for the `.obsidian/`
+//! config tree and common junk files; the first matching rule wins and
+//! unmatched paths sync normally. Volatile files can additionally be
+//! debounced so a burst of writes produces one journal entry.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    /// Whole-file last-writer-wins
+    Lww,
+    /// Structural merge where a strategy exists for the format
+    Merge,
+    /// Never recorded or transferred
+    Skip,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PolicyRule {
+    /// Glob over vault-relative paths (`*`, `?`, `**`)
+    pub pattern: String,
+    pub action: SyncAction,
+}
+
+impl PolicyRule {
+    pub fn new(pattern: &str, action: SyncAction) -> PolicyRule {
+        PolicyRule { pattern: pattern.to_string(), action }
+    }
+}
+
+/// Paths merged by concatenating appended tails (daily notes and similar)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AppendRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub separator: String,
+}
+
+/// Paths whose changes are recorded only after a quiet interval
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DebounceRule {
+    pub pattern: String,
+    pub interval_ms: u64,
+}
+
+impl DebounceRule {
+    pub fn new(pattern: &str, interval_ms: u64) -> DebounceRule {
+        DebounceRule { pattern: pattern.to_string(), interval_ms }
+    }
+}
+
+/// Built-in debounce intervals for files Obsidian rewrites constantly
+pub fn default_debounce_rules() -> Vec<DebounceRule> {
+    vec![
+        DebounceRule::new(".obsidian/graph.json", 60_000),
+        DebounceRule::new(".obsidian/workspaces.json", 30_000),
+        DebounceRule::new(".obsidian/plugins/*/data.json", 10_000),
+        DebounceRule::new("**/*.canvas", 5_000),
+    ]
+}
+
+/// Built-in exclusions for OS, VCS and trash artifacts
+pub fn default_exclusion_rules() -> Vec<PolicyRule> {
+    vec![
+        PolicyRule::new(".trash/**", SyncAction::Skip),
+        PolicyRule::new(".git/**", SyncAction::Skip),
+        PolicyRule::new("**/.DS_Store", SyncAction::Skip),
+        PolicyRule::new("**/Thumbs.db", SyncAction::Skip),
+        PolicyRule::new("**/desktop.ini", SyncAction::Skip),
+        PolicyRule::new("**/*.tmp", SyncAction::Skip),
+        PolicyRule::new("**/*.swp", SyncAction::Skip),
+        PolicyRule::new("**/*~", SyncAction::Skip),
+    ]
+}
+
+/// Built-in rules for the `.obsidian/` config tree, followed by the exclusions
+pub fn default_config_rules() -> Vec<PolicyRule> {
+    let mut rules = vec![
+        // Per-device window layout and caches
+        PolicyRule::new(".obsidian/workspace.json", SyncAction::Skip),
+        PolicyRule::new(".obsidian/workspace-mobile.json", SyncAction::Skip),
+        PolicyRule::new(".obsidian/cache/**", SyncAction::Skip),
+        // Plugin code is versioned per device; settings merge key-wise
+        PolicyRule::new(".obsidian/plugins/*/data.json", SyncAction::Merge),
+        PolicyRule::new(".obsidian/plugins/*/main.js", SyncAction::Lww),
+        PolicyRule::new(".obsidian/plugins/*/manifest.json", SyncAction::Lww),
+        PolicyRule::new(".obsidian/plugins/*/styles.css", SyncAction::Lww),
+        PolicyRule::new(".obsidian/themes/**", SyncAction::Lww),
+        PolicyRule::new(".obsidian/snippets/**", SyncAction::Lww),
+        PolicyRule::new(".obsidian/hotkeys.json", SyncAction::Lww),
+        PolicyRule::new(".obsidian/appearance.json", SyncAction::Lww),
+        PolicyRule::new(".obsidian/app.json", SyncAction::Lww),
+        PolicyRule::new(".obsidian/core-plugins.json", SyncAction::Lww),
+        PolicyRule::new(".obsidian/community-plugins.json", SyncAction::Lww),
+    ];
+    rules.extend(default_exclusion_rules());
+    rules
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncPolicy {
+    user_rules: Vec<PolicyRule>,
+    #[serde(default)]
+    append_rules: Vec<AppendRule>,
+    #[serde(default)]
+    debounce_rules: Vec<DebounceRule>,
+    use_config_defaults: bool,
+    #[serde(skip, default = "default_config_rules")]
+    config_defaults: Vec<PolicyRule>,
+    #[serde(skip, default = "default_debounce_rules")]
+    debounce_defaults: Vec<DebounceRule>,
+}
+
+impl SyncPolicy {
+    pub fn new() -> SyncPolicy {
+        SyncPolicy {
+            user_rules: Vec::new(),
+            append_rules: Vec::new(),
+            debounce_rules: Vec::new(),
+            use_config_defaults: true,
+            config_defaults: default_config_rules(),
+            debounce_defaults: default_debounce_rules(),
+        }
+    }
+
+    /// Action for a path: user rules, then append rules (`Merge`), then defaults, then `Lww`
+    pub fn action_for(&self, path: &str) -> SyncAction {
+        if let Some(rule) = self.user_rules.iter().find(|r| glob_match(&r.pattern, path)) {
+            return rule.action;
+        }
+        if self.append_rule_for(path).is_some() {
+            return SyncAction::Merge;
+        }
+        self.matching_rule(path).map(|r| r.action).unwrap_or(SyncAction::Lww)
+    }
+
+    pub fn append_rule_for(&self, path: &str) -> Option<&AppendRule> {
+        self.append_rules.iter().find(|r| glob_match(&r.pattern, path))
+    }
+
+    pub fn set_append_rules(&mut self, rules: Vec<AppendRule>) {
+        self.append_rules = rules;
+    }
+
+    pub fn append_rules(&self) -> &[AppendRule] {
+        &self.append_rules
+    }
+
+    /// Debounce interval for a path (user rules first, then defaults); 0 = record immediately
+    pub fn debounce_for(&self, path: &str) -> u64 {
+        let defaults: &[DebounceRule] = if self.use_config_defaults { &self.debounce_defaults } else { &[] };
+        self.debounce_rules
+            .iter()
+            .chain(defaults)
+            .find(|r| glob_match(&r.pattern, path))
+            .map_or(0, |r| r.interval_ms)
+    }
+
+    pub fn set_debounce_rules(&mut self, rules: Vec<DebounceRule>) {
+        self.debounce_rules = rules;
+    }
+
+    /// Effective debounce rules in evaluation order
+    pub fn effective_debounce_rules(&self) -> Vec<DebounceRule> {
+        let mut rules = self.debounce_rules.clone();
+        if self.use_config_defaults {
+            rules.extend(self.debounce_defaults.iter().cloned());
+        }
+        rules
+    }
+
+    pub fn should_sync(&self, path: &str) -> bool {
+        self.action_for(path) != SyncAction::Skip
+    }
+
+    pub fn matching_rule(&self, path: &str) -> Option<&PolicyRule> {
+        let defaults: &[PolicyRule] = if self.use_config_defaults { &self.config_defaults } else { &[] };
+        self.user_rules.iter().chain(defaults).find(|r| glob_match(&r.pattern, path))
+    }
+
+    pub fn set_user_rules(&mut self, rules: Vec<PolicyRule>) {
+        self.user_rules = rules;
+    }
+
+    pub fn user_rules(&self) -> &[PolicyRule] {
+        &self.user_rules
+    }
+
+    pub fn set_use_config_defaults(&mut self, enabled: bool) {
+        self.use_config_defaults = enabled;
+    }
+
+    /// Effective rules in evaluation order
+    pub fn effective_rules(&self) -> Vec<PolicyRule> {
+        let mut rules = self.user_rules.clone();
+        if self.use_config_defaults {
+            rules.extend(self.config_defaults.iter().cloned());
+        }
+        rules
+    }
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Match a vault-relative path against a glob
+/// `*` and `?` stay within one path segment, `**` spans any number of segments
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                match_segment(first.as_bytes(), segment.as_bytes()) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| match_segment(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && match_segment(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && match_segment(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(".obsidian/plugins/*/data.json", ".obsidian/plugins/dataview/data.json"));
+        assert!(!glob_match(".obsidian/plugins/*/data.json", ".obsidian/plugins/a/b/data.json"));
+        assert!(glob_match(".obsidian/themes/**", ".obsidian/themes/Minimal/theme.css"));
+        assert!(glob_match("**/*.pdf", "a/b/c.pdf"));
+        assert!(glob_match("**/*.pdf", "c.pdf"));
+        assert!(glob_match("Daily/202?-*.md", "Daily/2024-01-01.md"));
+        assert!(!glob_match("*.md", "Daily/note.md"));
+    }
+
+    #[test]
+    fn test_defaults_and_user_overrides() {
+        let mut policy = SyncPolicy::new();
+        assert_eq!(policy.action_for(".obsidian/workspace.json"), SyncAction::Skip);
+        assert_eq!(policy.action_for(".obsidian/plugins/x/data.json"), SyncAction::Merge);
+        assert_eq!(policy.action_for(".obsidian/hotkeys.json"), SyncAction::Lww);
+        assert_eq!(policy.action_for("Notes/a.md"), SyncAction::Lww);
+
+        policy.set_user_rules(vec![
+            PolicyRule::new(".obsidian/workspace.json", SyncAction::Lww),
+            PolicyRule::new(".obsidian/hotkeys.json", SyncAction::Skip),
+        ]);
+        assert_eq!(policy.action_for(".obsidian/workspace.json"), SyncAction::Lww);
+        assert!(!policy.should_sync(".obsidian/hotkeys.json"));
+
+        assert!(!policy.should_sync(".trash/old.md"));
+        assert!(!policy.should_sync("Notes/.DS_Store"));
+        assert_eq!(policy.debounce_for(".obsidian/graph.json"), 60_000);
+        policy.set_debounce_rules(vec![DebounceRule::new(".obsidian/graph.json", 0)]);
+        assert_eq!(policy.debounce_for(".obsidian/graph.json"), 0);
+
+        policy.set_use_config_defaults(false);
+        assert_eq!(policy.action_for(".obsidian/workspace-mobile.json"), SyncAction::Lww);
+    }
+}
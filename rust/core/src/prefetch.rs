@@ -0,0 +1,136 @@
+//! Predictive prefetching
+//!
+//! A sync round pulls files in whatever order the transfer plan puts them,
+//! so the note the user is about to open may arrive last. The node keeps a
+//! short list of recently opened files and the note currently in focus, and
+//! the host supplies the links/embeds graph it already resolves for
+//! Obsidian. When a peer connects, `plan_prefetch` picks the files worth
+//! pulling first from the peer's manifest: the active note, what it links to
+//! or embeds, the recently opened files, and what those link to, in that
+//! order, limited to files the peer has a newer version of. Pinned files
+//! the peer has newer versions of come before all of them.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::preview;
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+/// Recently opened files remembered
+pub const MAX_RECENT_FILES: usize = 50;
+/// Most files one prefetch plan returns
+pub const MAX_PREFETCH_FILES: usize = 25;
+
+#[derive(Debug, Default)]
+pub struct PrefetchState {
+    /// Most recently opened first
+    recent: VecDeque<String>,
+    active_note: Option<String>,
+    /// Note → paths it links to or embeds, as resolved by the host
+    links: BTreeMap<String, Vec<String>>,
+}
+
+impl PrefetchState {
+    pub fn record_opened(&mut self, path: &str) {
+        self.recent.retain(|p| p != path);
+        self.recent.push_front(path.to_string());
+        self.recent.truncate(MAX_RECENT_FILES);
+    }
+
+    /// Paths in the order they should be fetched, without duplicates
+    pub fn candidates(&self) -> Vec<String> {
+        let linked = |note: &String| self.links.get(note).into_iter().flatten();
+        let active = self.active_note.iter();
+        let ordered = active
+            .clone()
+            .chain(active.flat_map(linked))
+            .chain(self.recent.iter())
+            .chain(self.recent.iter().flat_map(linked));
+        let mut seen = HashSet::new();
+        ordered.filter(|path| seen.insert(path.as_str())).cloned().collect()
+    }
+}
+
+impl P2PNode {
+    /// Note that the user opened `path`
+    pub fn record_file_opened(&mut self, path: &str) {
+        self.prefetch.record_opened(path);
+    }
+
+    /// The note in focus (`undefined` when none); it is also recorded as opened
+    pub fn set_active_note(&mut self, path: Option<String>) {
+        if let Some(path) = &path {
+            self.prefetch.record_opened(path);
+        }
+        self.prefetch.active_note = path;
+    }
+
+    /// Replace the links/embeds graph, a JSON object mapping each note to the paths it references
+    pub fn set_link_graph(&mut self, graph_json: &str) -> Result<(), String> {
+        let links = check_size("Link graph", graph_json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(graph_json).map_err(|e| e.to_string().into()))
+            .map_err(|e| self.record_error(format!("Invalid link graph: {}", e)))?;
+        self.prefetch.links = links;
+        Ok(())
+    }
+
+    /// Files to pull first from a newly connected peer, given its manifest (a JSON
+    /// array of file metadata); returns a JSON array of paths, most needed first
+    pub fn plan_prefetch(&mut self, remote_json: &str) -> Result<String, String> {
+        let remote: Vec<FileMetadata> = check_size("Manifest", remote_json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str(remote_json).map_err(|e| format!("Invalid remote manifest: {}", e).into()))
+            .map_err(|e| self.record_error(e))?;
+        let plan = self.prefetch_plan(remote).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&plan).map_err(|e| e.to_string())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn prefetch_plan(&self, remote: Vec<FileMetadata>) -> Result<Vec<String>, String> {
+        let preview = preview::plan_preview(&self.change_journal, &self.policy, remote, &self.cancel_token)?;
+        let wanted: HashSet<&str> = preview
+            .pull
+            .iter()
+            .chain(&preview.overwrite)
+            .filter(|item| self.wants_locally(&item.path, item.remote_size.unwrap_or(0)))
+            .map(|item| item.path.as_str())
+            .collect();
+        let mut pinned: Vec<String> =
+            wanted.iter().filter(|path| self.is_pinned(path)).map(|path| path.to_string()).collect();
+        pinned.sort();
+        let mut seen = HashSet::new();
+        Ok(pinned
+            .into_iter()
+            .chain(self.prefetch.candidates().into_iter().filter(|path| wanted.contains(path.as_str())))
+            .filter(|path| seen.insert(path.clone()))
+            .take(MAX_PREFETCH_FILES)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_note_and_links_come_first() {
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        for path in ["daily.md", "project.md", "diagram.png", "old.md", "unrelated.md"] {
+            laptop.update_file(path.to_string(), path.as_bytes(), 1);
+        }
+        phone.update_file("old.md".to_string(), b"old.md", 1);
+        let remote: Vec<_> = laptop.change_journal.files().collect();
+
+        phone.record_file_opened("old.md");
+        phone.record_file_opened("daily.md");
+        phone.set_active_note(Some("project.md".to_string()));
+        phone.set_link_graph(r#"{"project.md": ["diagram.png", "missing.md"], "daily.md": ["project.md"]}"#).unwrap();
+        // old.md is already up to date and unrelated.md is not a candidate
+        assert_eq!(phone.prefetch_plan(remote.clone()).unwrap(), vec!["project.md", "diagram.png", "daily.md"]);
+
+        phone.set_active_note(None);
+        assert_eq!(phone.prefetch_plan(remote).unwrap(), vec!["project.md", "daily.md", "diagram.png"]);
+    }
+}
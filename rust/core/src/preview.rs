@@ -0,0 +1,183 @@
+//! Dry-run sync preview
+//!
+//! Before the first sync with a peer the plugin shows a review screen. The
+//! preview classifies every path of the peer's manifest against the journal
+//! the way a sync round would, with byte totals, and changes nothing.
+//!
+//! Without a common ancestor the classification leans on what the journal
+//! knows: a remote hash found in our own history for that path is an older
+//! version of ours, and a local entry last written by the same device as the
+//! remote one is simply behind it. Two live versions that fit neither case
+//! are reported as a conflict.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::cancel::{self, CancellationToken};
+use crate::error::Error;
+use crate::policy::SyncPolicy;
+use crate::sync::{ChangeJournal, FileMetadata};
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PreviewItem {
+    pub path: String,
+    /// Size of our version, if we have a live one
+    pub local_size: Option<u64>,
+    /// Size of the peer's version, if it has a live one
+    pub remote_size: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncPreview {
+    /// New to this vault
+    pub pull: Vec<PreviewItem>,
+    /// Local files the peer's version would replace
+    pub overwrite: Vec<PreviewItem>,
+    /// Local files the peer has deleted
+    pub delete: Vec<PreviewItem>,
+    /// Both sides changed; needs review
+    pub conflict: Vec<PreviewItem>,
+    /// Ours is newer or unknown to the peer
+    pub push: Vec<PreviewItem>,
+    /// Excluded by the sync policy
+    pub skipped: Vec<String>,
+    pub unchanged: usize,
+    pub download_bytes: u64,
+    pub upload_bytes: u64,
+    pub deleted_bytes: u64,
+}
+
+fn item(path: &str, local: Option<&FileMetadata>, remote: Option<&FileMetadata>) -> PreviewItem {
+    let live_size = |m: Option<&FileMetadata>| m.filter(|m| !m.is_deleted).map(|m| m.size);
+    PreviewItem { path: path.to_string(), local_size: live_size(local), remote_size: live_size(remote) }
+}
+
+/// Classify `remote` (the peer's manifest) against `journal`, checking `cancel` between paths
+pub fn plan_preview(
+    journal: &ChangeJournal,
+    policy: &SyncPolicy,
+    remote: Vec<FileMetadata>,
+    cancel: &Option<CancellationToken>,
+) -> Result<SyncPreview, Error> {
+    let known: HashSet<(String, String)> = journal.history().map(|m| (m.path, m.hash)).collect();
+    let mut preview = SyncPreview::default();
+    let remote: HashMap<String, FileMetadata> = remote.into_iter().map(|m| (m.path.clone(), m)).collect();
+
+    for (path, theirs) in &remote {
+        cancel::check(cancel)?;
+        if !policy.should_sync(path) {
+            preview.skipped.push(path.clone());
+            continue;
+        }
+        let ours = journal.get(path);
+        let entry = item(path, ours.as_ref(), Some(theirs));
+        match (ours.as_ref().filter(|m| !m.is_deleted), theirs.is_deleted) {
+            (None, true) => preview.unchanged += 1,
+            (None, false) => match &ours {
+                // We deleted it: the later of the delete and the remote edit wins
+                Some(tombstone) if tombstone.mtime >= theirs.mtime => preview.push.push(entry),
+                _ => {
+                    preview.download_bytes += theirs.size;
+                    preview.pull.push(entry);
+                }
+            },
+            (Some(local), true) => {
+                if local.last_modified_by == theirs.last_modified_by || local.mtime <= theirs.mtime {
+                    preview.deleted_bytes += local.size;
+                    preview.delete.push(entry);
+                } else {
+                    preview.conflict.push(entry);
+                }
+            }
+            (Some(local), false) if local.hash == theirs.hash => preview.unchanged += 1,
+            (Some(local), false) => {
+                if known.contains(&(path.clone(), theirs.hash.clone())) {
+                    preview.upload_bytes += local.size;
+                    preview.push.push(entry);
+                } else if local.last_modified_by == theirs.last_modified_by {
+                    preview.download_bytes += theirs.size;
+                    preview.overwrite.push(entry);
+                } else {
+                    preview.conflict.push(entry);
+                }
+            }
+        }
+    }
+
+    for local in journal.files().filter(|m| !m.is_deleted && !remote.contains_key(&m.path)) {
+        cancel::check(cancel)?;
+        if policy.should_sync(&local.path) {
+            preview.upload_bytes += local.size;
+            preview.push.push(item(&local.path, Some(&local), None));
+        }
+    }
+
+    for list in [&mut preview.pull, &mut preview.overwrite, &mut preview.delete, &mut preview.conflict, &mut preview.push] {
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    preview.skipped.sort();
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::hash_content;
+
+    fn meta(path: &str, content: Option<&[u8]>, mtime: u64, device: &str) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: content.map(hash_content).unwrap_or_default(),
+            mtime,
+            size: content.map_or(0, |c| c.len() as u64),
+            version: 1,
+            is_deleted: content.is_none(),
+            last_modified_by: device.to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_classifies_without_touching_the_journal() {
+        let mut journal = ChangeJournal::new();
+        journal.update_file("same.md".to_string(), b"same", 1, "laptop".to_string());
+        journal.update_file("ahead.md".to_string(), b"v1", 1, "laptop".to_string());
+        journal.update_file("ahead.md".to_string(), b"v2", 2, "laptop".to_string());
+        journal.record_update("theirs.md".to_string(), hash_content(b"old"), 3, 1, "phone".to_string());
+        journal.update_file("both.md".to_string(), b"mine", 5, "laptop".to_string());
+        journal.update_file("removed.md".to_string(), b"bye", 1, "laptop".to_string());
+        journal.update_file("local-only.md".to_string(), b"only", 1, "laptop".to_string());
+        journal.update_file(".obsidian/workspace.json".to_string(), b"{}", 1, "laptop".to_string());
+        let sequence = journal.sequence();
+
+        let remote = vec![
+            meta("same.md", Some(b"same"), 1, "laptop"),
+            meta("ahead.md", Some(b"v1"), 1, "laptop"),
+            meta("theirs.md", Some(b"newer"), 4, "phone"),
+            meta("both.md", Some(b"yours"), 6, "phone"),
+            meta("removed.md", None, 9, "phone"),
+            meta("new.md", Some(b"fresh!"), 2, "phone"),
+            meta(".obsidian/workspace.json", Some(b"[]"), 2, "phone"),
+        ];
+        let preview = plan_preview(&journal, &SyncPolicy::default(), remote.clone(), &None).unwrap();
+        let paths = |items: &[PreviewItem]| items.iter().map(|i| i.path.clone()).collect::<Vec<_>>();
+        assert_eq!(preview.unchanged, 1);
+        assert_eq!(paths(&preview.pull), vec!["new.md"]);
+        assert_eq!(paths(&preview.overwrite), vec!["theirs.md"]);
+        assert_eq!(paths(&preview.delete), vec!["removed.md"]);
+        assert_eq!(paths(&preview.conflict), vec!["both.md"]);
+        assert_eq!(paths(&preview.push), vec!["ahead.md", "local-only.md"]);
+        assert_eq!(preview.skipped, vec![".obsidian/workspace.json"]);
+        assert_eq!((preview.download_bytes, preview.upload_bytes, preview.deleted_bytes), (11, 6, 3));
+        assert_eq!(journal.sequence(), sequence);
+
+        // Cancelled partway through the manifest: no preview, nothing changed
+        let token = CancellationToken::new();
+        token.cancel_after_checks(3);
+        let cancelled = plan_preview(&journal, &SyncPolicy::default(), remote, &Some(token.clone()));
+        assert_eq!(cancelled.unwrap_err().message(), cancel::CANCELLED);
+        assert!(token.is_cancelled());
+        assert_eq!(journal.sequence(), sequence);
+    }
+}
@@ -0,0 +1,139 @@
+//! Named device sync profiles
+//!
+//! A profile bundles what a device wants to hold locally (folder filters,
+//! size cap), how it treats attachments, and how often it syncs and on what
+//! battery. Phones can
+//! pick `mobile-lite` and still participate in the mesh without mirroring
+//! the entire vault. The active profile name is advertised to peers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachments::AttachmentRules;
+
+pub const PROFILE_FULL: &str = "full";
+pub const PROFILE_MOBILE_LITE: &str = "mobile-lite";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SyncProfile {
+    pub name: String,
+    /// Files larger than this are not pulled to this device
+    #[serde(default)]
+    pub max_file_size_bytes: Option<u64>,
+    /// Folder prefixes to hold locally; empty means the whole vault
+    #[serde(default)]
+    pub include_folders: Vec<String>,
+    /// Folder prefixes never held locally (applied after includes)
+    #[serde(default)]
+    pub exclude_folders: Vec<String>,
+    #[serde(default)]
+    pub attachments: AttachmentRules,
+    /// Interval between background sync rounds
+    #[serde(default = "default_sync_interval_ms")]
+    pub sync_interval_ms: u64,
+    /// Pause background sync and attachments below this battery level unless charging
+    #[serde(default = "default_pause_bulk_below_battery_percent")]
+    pub pause_bulk_below_battery_percent: Option<u8>,
+    /// Stop pulling attachments once the files held locally reach this many bytes
+    #[serde(default)]
+    pub storage_budget_bytes: Option<u64>,
+    /// Record new files as placeholders and pull their content only when requested
+    #[serde(default)]
+    pub on_demand: bool,
+}
+
+fn default_sync_interval_ms() -> u64 {
+    60_000
+}
+
+fn default_pause_bulk_below_battery_percent() -> Option<u8> {
+    Some(20)
+}
+
+impl SyncProfile {
+    pub fn full() -> SyncProfile {
+        SyncProfile {
+            name: PROFILE_FULL.to_string(),
+            max_file_size_bytes: None,
+            include_folders: Vec::new(),
+            exclude_folders: Vec::new(),
+            attachments: AttachmentRules::default(),
+            sync_interval_ms: default_sync_interval_ms(),
+            pause_bulk_below_battery_percent: default_pause_bulk_below_battery_percent(),
+            storage_budget_bytes: None,
+            on_demand: false,
+        }
+    }
+
+    pub fn mobile_lite() -> SyncProfile {
+        SyncProfile {
+            name: PROFILE_MOBILE_LITE.to_string(),
+            max_file_size_bytes: Some(50 * 1024 * 1024),
+            include_folders: Vec::new(),
+            exclude_folders: Vec::new(),
+            attachments: AttachmentRules {
+                unmetered_only: true,
+                max_size_bytes: Some(25 * 1024 * 1024),
+                transfer_last: true,
+            },
+            sync_interval_ms: 5 * 60_000,
+            pause_bulk_below_battery_percent: default_pause_bulk_below_battery_percent(),
+            storage_budget_bytes: None,
+            on_demand: false,
+        }
+    }
+
+    /// Look up a built-in profile by name
+    pub fn builtin(name: &str) -> Option<SyncProfile> {
+        match name {
+            PROFILE_FULL => Some(SyncProfile::full()),
+            PROFILE_MOBILE_LITE => Some(SyncProfile::mobile_lite()),
+            _ => None,
+        }
+    }
+
+    pub fn builtins() -> Vec<SyncProfile> {
+        vec![SyncProfile::full(), SyncProfile::mobile_lite()]
+    }
+
+    /// Whether this device wants a local copy of the file
+    pub fn wants(&self, path: &str, size: u64) -> bool {
+        if self.max_file_size_bytes.is_some_and(|max| size > max) {
+            return false;
+        }
+        let in_folder = |folder: &String| {
+            let folder = folder.trim_matches('/');
+            folder.is_empty() || path.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'))
+        };
+        if !self.include_folders.is_empty() && !self.include_folders.iter().any(in_folder) {
+            return false;
+        }
+        !self.exclude_folders.iter().any(in_folder)
+    }
+}
+
+impl Default for SyncProfile {
+    fn default() -> Self {
+        SyncProfile::full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_filters() {
+        let profile = SyncProfile {
+            include_folders: vec!["Notes".to_string(), "Daily/".to_string()],
+            exclude_folders: vec!["Notes/Archive".to_string()],
+            max_file_size_bytes: Some(100),
+            ..SyncProfile::full()
+        };
+        assert!(profile.wants("Notes/a.md", 10));
+        assert!(profile.wants("Daily/2024.md", 10));
+        assert!(!profile.wants("NotesExtra/a.md", 10));
+        assert!(!profile.wants("Notes/Archive/old.md", 10));
+        assert!(!profile.wants("Notes/big.md", 1000));
+        assert!(SyncProfile::full().wants("anything/at/all.bin", u64::MAX));
+    }
+}
@@ -0,0 +1,213 @@
+//! Key-change quarantine
+//!
+//! A paired device showing up with a different identity key is either a
+//! reinstall or someone impersonating it, and only the user can tell which
+//! by comparing fingerprints on both screens. Until they have, the node
+//! quarantines the device: the old key stays pinned, handshakes, device
+//! profiles and sync rounds from the device are refused whichever key they
+//! carry, and the alarm is raised once in the security and issue logs
+//! instead of as a generic error on every attempt.
+//!
+//! `get_quarantined_peers_json` lists both fingerprints for the
+//! re-verification screen. `accept_key_change` takes the fingerprint the
+//! user confirmed, so a key that changed again meanwhile is not accepted by
+//! mistake, and pins the new key everywhere the old one was pinned.
+//! `reject_key_change` keeps the old pin, lifts the quarantine and refuses
+//! the rejected key from then on.
+//!
+//! The pinned key of a device is the one recorded for it as a vault member
+//! (see `introductions`), or else the first one its device profile came with.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::clock;
+use crate::crypto::generate_fingerprint;
+use crate::issues;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::security::SecurityEventKind;
+use crate::P2PNode;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    pub device_id: String,
+    /// Base64 identity key still pinned
+    pub pinned_key: String,
+    pub presented_key: String,
+    pub pinned_fingerprint: String,
+    pub presented_fingerprint: String,
+    pub detected_at: u64,
+    /// What carried the new key: `handshake` or `device_profile`
+    pub source: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Quarantine {
+    #[serde(default)]
+    changes: BTreeMap<String, KeyChange>,
+    /// Device ID → keys the user rejected
+    #[serde(default)]
+    rejected: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Quarantine {
+    pub fn is_quarantined(&self, device_id: &str) -> bool {
+        self.changes.contains_key(device_id)
+    }
+}
+
+impl P2PNode {
+    /// Devices awaiting re-verification as `[{device_id, pinned_key, presented_key,
+    /// pinned_fingerprint, presented_fingerprint, detected_at, source}]`
+    pub fn get_quarantined_peers_json(&self) -> String {
+        serde_json::to_string(&self.quarantine.changes.values().collect::<Vec<_>>()).unwrap_or_default()
+    }
+
+    pub fn is_peer_quarantined(&self, device_id: &str) -> bool {
+        self.quarantine.is_quarantined(device_id)
+    }
+
+    /// The user confirmed `fingerprint` on the device itself: pin its new key
+    /// and lift the quarantine
+    pub fn accept_key_change(&mut self, device_id: &str, fingerprint: &str) -> Result<(), String> {
+        let Some(change) = self.quarantine.changes.remove(device_id) else {
+            return Err(self.record_error(format!("{} is not quarantined", device_id)));
+        };
+        if change.presented_fingerprint != fingerprint.trim() {
+            self.quarantine.changes.insert(device_id.to_string(), change);
+            let e = format!("Fingerprint {} is not the one {} presented", fingerprint, device_id);
+            return Err(self.record_error(e));
+        }
+        self.vault_members.repin(device_id, &change.presented_key);
+        self.devices.forget(device_id);
+        self.checkpoints.forget(device_id);
+        let message = format!("New key of {} accepted", self.get_device_display_name(device_id));
+        self.issues.push(issues::Severity::Info, issues::KEY_ACCEPTED, message, &[("device", device_id)]);
+        Ok(())
+    }
+
+    /// Keep the old key: lift the quarantine and refuse the presented key from now on
+    pub fn reject_key_change(&mut self, device_id: &str) -> Result<(), String> {
+        let Some(change) = self.quarantine.changes.remove(device_id) else {
+            return Err(self.record_error(format!("{} is not quarantined", device_id)));
+        };
+        self.quarantine.rejected.entry(change.device_id).or_default().insert(change.presented_key);
+        Ok(())
+    }
+
+    /// Quarantined devices and rejected keys as JSON, for persisting with `load_quarantine_state`
+    pub fn get_quarantine_state(&self) -> String {
+        serde_json::to_string(&self.quarantine).unwrap_or_default()
+    }
+
+    pub fn load_quarantine_state(&mut self, json: &str) -> Result<(), String> {
+        let result = check_size("Quarantine state", json, MAX_JOURNAL_BYTES)
+            .and_then(|_| serde_json::from_str::<Quarantine>(json).map_err(|e| e.to_string().into()));
+        match result {
+            Ok(quarantine) => {
+                self.quarantine = quarantine;
+                Ok(())
+            }
+            Err(e) => Err(self.record_error(format!("Invalid quarantine state: {}", e))),
+        }
+    }
+}
+
+impl P2PNode {
+    fn pinned_key(&self, device_id: &str) -> Option<String> {
+        let member = self.vault_members.get(device_id).map(|m| m.identity_key.clone());
+        member.or_else(|| self.devices.get(device_id).map(|d| d.identity_key().to_string()))
+    }
+
+    /// Check the identity key `device_id` presented in a `source` message against its
+    /// pin; a mismatch quarantines the device
+    pub(crate) fn check_identity_key(&mut self, device_id: &str, key: &str, source: &str) -> Result<(), String> {
+        if self.quarantine.is_quarantined(device_id) {
+            return Err(format!("{} is quarantined until its new key is verified", device_id));
+        }
+        if self.quarantine.rejected.get(device_id).is_some_and(|keys| keys.contains(key)) {
+            return Err(format!("The key {} presented was rejected", device_id));
+        }
+        let Some(pinned) = self.pinned_key(device_id).filter(|pinned| pinned != key) else {
+            return Ok(());
+        };
+        let change = KeyChange {
+            device_id: device_id.to_string(),
+            pinned_fingerprint: generate_fingerprint(&pinned),
+            presented_fingerprint: generate_fingerprint(key),
+            pinned_key: pinned,
+            presented_key: key.to_string(),
+            detected_at: clock::now_ms(),
+            source: source.to_string(),
+        };
+        let message = format!("{} presented a different identity key", device_id);
+        let context = [("frame", source), ("fingerprint", change.presented_fingerprint.as_str())];
+        self.security_log.record(SecurityEventKind::KeyChanged, device_id, message, &context);
+        let message = format!(
+            "{} has a new identity key; compare fingerprints before syncing with it again",
+            self.get_device_display_name(device_id)
+        );
+        self.issues.push(issues::Severity::Error, issues::KEY_CHANGED, message, &[("device", device_id)]);
+        self.quarantine.changes.insert(device_id.to_string(), change);
+        Err(format!("{} is quarantined: its identity key changed", device_id))
+    }
+
+    /// Refuse to sync with a quarantined device
+    pub(crate) fn check_not_quarantined(&self, device_id: &str) -> Result<(), String> {
+        if self.quarantine.is_quarantined(device_id) {
+            return Err(format!("{} is quarantined until its new key is verified", device_id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{DeviceIdentity, KeyExchange};
+
+    #[test]
+    fn test_changed_key_quarantines_until_verified() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let original = DeviceIdentity::new("phone".to_string()).unwrap();
+        let reinstalled = DeviceIdentity::new("phone".to_string()).unwrap();
+        let session = KeyExchange::new();
+        laptop.add_vault_member("phone", &original.get_public_key()).unwrap();
+        laptop.apply_handshake_frame(&phone.get_handshake_frame(&original, &session)).unwrap();
+
+        let handshake = phone.get_handshake_frame(&reinstalled, &session);
+        let e = laptop.apply_handshake_frame(&handshake).err().unwrap();
+        assert!(e.contains("quarantined"));
+        assert!(laptop.is_peer_quarantined("phone"));
+        // The old key is refused too, and so is syncing, without a second alarm
+        assert!(laptop.apply_handshake_frame(&phone.get_handshake_frame(&original, &session)).is_err());
+        assert!(laptop.prepare_round("[]", "phone").is_err());
+        assert!(laptop.apply_remote_change("{}", "phone").is_err());
+        assert_eq!(laptop.security_log.len(), 1);
+        assert_eq!(laptop.issues.iter().filter(|issue| issue.code == issues::KEY_CHANGED).count(), 1);
+
+        let listed: serde_json::Value = serde_json::from_str(&laptop.get_quarantined_peers_json()).unwrap();
+        let fingerprint = listed[0]["presented_fingerprint"].as_str().unwrap().to_string();
+        assert_eq!(fingerprint, generate_fingerprint(&reinstalled.get_public_key()));
+        let pinned = listed[0]["pinned_fingerprint"].as_str().unwrap();
+        assert_eq!(pinned, generate_fingerprint(&original.get_public_key()));
+
+        // Rejecting keeps the old pin
+        let saved = laptop.get_quarantine_state();
+        laptop.reject_key_change("phone").unwrap();
+        laptop.apply_handshake_frame(&phone.get_handshake_frame(&original, &session)).unwrap();
+        assert!(laptop.apply_handshake_frame(&handshake).is_err());
+        assert_eq!(laptop.security_log.len(), 1);
+
+        // Accepting needs the fingerprint that was shown
+        laptop.load_quarantine_state(&saved).unwrap();
+        let other = generate_fingerprint(&DeviceIdentity::new("x".to_string()).unwrap().get_public_key());
+        assert!(laptop.accept_key_change("phone", &other).is_err());
+        laptop.accept_key_change("phone", &fingerprint).unwrap();
+        assert!(laptop.is_vault_member("phone", &reinstalled.get_public_key()));
+        laptop.apply_handshake_frame(&handshake).unwrap();
+        laptop.prepare_round("[]", "phone").unwrap();
+        assert!(laptop.reject_key_change("phone").is_err());
+    }
+}
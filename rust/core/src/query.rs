@@ -0,0 +1,176 @@
+//! Journal queries
+//!
+//! Views like "what did my phone change yesterday?" or "which notes were
+//! deleted this week?" filter the journal's current entries by who wrote
+//! them, when, where and how big they are. `query_files` takes the filter
+//! as JSON and returns one page of matching entries, ordered by path or
+//! newest first, with a cursor for the next page. Cursors are positions in
+//! that order rather than offsets, so a page stays correct while new
+//! changes arrive between requests.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use crate::pins::{covers, normalize};
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryOrder {
+    #[default]
+    Path,
+    /// Highest journal version first
+    Newest,
+}
+
+/// Every field that is set must match
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct FileQuery {
+    /// Device ID of the last writer
+    pub modified_by: Option<String>,
+    /// `mtime` at or after, in ms
+    pub modified_after: Option<u64>,
+    /// `mtime` before, in ms
+    pub modified_before: Option<u64>,
+    /// `true` for deletions only, `false` for live files only; both when unset
+    pub deleted: Option<bool>,
+    /// The folder itself and everything under it
+    pub folder: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub hash: Option<String>,
+    pub order: QueryOrder,
+    /// Entries per page; 100 when unset, at most 1000
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct QueryPage {
+    pub files: Vec<FileMetadata>,
+    /// Pass back as `cursor` for the next page; absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Where a page starts in the query's order
+enum Cursor {
+    AfterPath(String),
+    BelowVersion(u64),
+}
+
+impl FileQuery {
+    pub fn matches(&self, meta: &FileMetadata) -> bool {
+        self.modified_by.as_ref().is_none_or(|device| meta.last_modified_by == *device)
+            && self.modified_after.is_none_or(|after| meta.mtime >= after)
+            && self.modified_before.is_none_or(|before| meta.mtime < before)
+            && self.deleted.is_none_or(|deleted| meta.is_deleted == deleted)
+            && self.folder.as_deref().is_none_or(|folder| covers(normalize(folder), &meta.path))
+            && self.min_size.is_none_or(|min| meta.size >= min)
+            && self.max_size.is_none_or(|max| meta.size <= max)
+            && self.hash.as_ref().is_none_or(|hash| meta.hash == *hash)
+    }
+
+    fn cursor(&self) -> Result<Option<Cursor>, String> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        let parsed = match self.order {
+            QueryOrder::Path => cursor.strip_prefix("p:").map(|path| Cursor::AfterPath(path.to_string())),
+            QueryOrder::Newest => cursor.strip_prefix("v:").and_then(|v| v.parse().ok()).map(Cursor::BelowVersion),
+        };
+        parsed.map(Some).ok_or_else(|| format!("Invalid cursor {:?} for this order", cursor))
+    }
+
+    /// One page of the matching entries of `files`
+    pub fn run(&self, files: impl Iterator<Item = FileMetadata>) -> Result<QueryPage, String> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(format!("Page size must be 1 to {}", MAX_PAGE_SIZE));
+        }
+        let cursor = self.cursor()?;
+        let mut matched: Vec<FileMetadata> = files
+            .filter(|meta| match &cursor {
+                Some(Cursor::AfterPath(path)) => meta.path > *path,
+                Some(Cursor::BelowVersion(version)) => meta.version < *version,
+                None => true,
+            })
+            .filter(|meta| self.matches(meta))
+            .collect();
+        match self.order {
+            QueryOrder::Path => matched.sort_unstable_by(|a, b| a.path.cmp(&b.path)),
+            QueryOrder::Newest => matched.sort_unstable_by_key(|meta| Reverse(meta.version)),
+        }
+        let more = matched.len() > limit;
+        matched.truncate(limit);
+        let next_cursor = matched.last().filter(|_| more).map(|last| match self.order {
+            QueryOrder::Path => format!("p:{}", last.path),
+            QueryOrder::Newest => format!("v:{}", last.version),
+        });
+        Ok(QueryPage { files: matched, next_cursor })
+    }
+}
+
+impl P2PNode {
+    /// One page of the journal entries matching `{modified_by?, modified_after?,
+    /// modified_before?, deleted?, folder?, min_size?, max_size?, hash?,
+    /// order?: "path" | "newest", limit?, cursor?}`, as `{files, next_cursor?}`
+    pub fn query_files(&mut self, filter_json: &str) -> Result<String, String> {
+        serde_json::from_str::<FileQuery>(filter_json)
+            .map_err(|e| format!("Invalid query: {}", e))
+            .and_then(|query| query.run(self.change_journal.files()))
+            .and_then(|page| serde_json::to_string(&page).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(node: &mut P2PNode, filter: &str) -> (Vec<String>, Option<String>) {
+        let page: serde_json::Value = serde_json::from_str(&node.query_files(filter).unwrap()).unwrap();
+        let paths = page["files"].as_array().unwrap().iter().map(|f| f["path"].as_str().unwrap().to_string()).collect();
+        (paths, page["next_cursor"].as_str().map(str::to_string))
+    }
+
+    #[test]
+    fn test_query_filters_and_pages() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.update_file("Daily/mon.md".to_string(), b"monday", 100);
+        node.update_file("Daily/tue.md".to_string(), b"tuesday", 200);
+        node.update_file("Dailyish.md".to_string(), b"x", 200);
+        node.update_file("Projects/big.pdf".to_string(), &[0; 4096], 300);
+        node.mark_file_deleted("Daily/mon.md".to_string(), 400);
+        let remote = r#"{"path":"Daily/wed.md","hash":"h","mtime":250,"size":9,"version":1,"is_deleted":false,"last_modified_by":"phone"}"#;
+        node.apply_remote_change(remote, "phone").unwrap();
+
+        assert_eq!(page(&mut node, r#"{"modified_by":"phone"}"#).0, vec!["Daily/wed.md"]);
+        assert_eq!(page(&mut node, r#"{"folder":"Daily/","deleted":false}"#).0, vec!["Daily/tue.md", "Daily/wed.md"]);
+        assert_eq!(page(&mut node, r#"{"deleted":true}"#).0, vec!["Daily/mon.md"]);
+        assert_eq!(page(&mut node, r#"{"modified_after":200,"modified_before":300,"max_size":8}"#).0.len(), 2);
+        assert_eq!(page(&mut node, r#"{"min_size":1000}"#).0, vec!["Projects/big.pdf"]);
+        let hash = node.change_journal.get("Dailyish.md").unwrap().hash;
+        assert_eq!(page(&mut node, &format!(r#"{{"hash":"{}"}}"#, hash)).0, vec!["Dailyish.md"]);
+
+        // Newest first, two at a time
+        let (first, cursor) = page(&mut node, r#"{"order":"newest","limit":2}"#);
+        assert_eq!(first, vec!["Daily/wed.md", "Daily/mon.md"]);
+        let (second, cursor) = page(&mut node, &format!(r#"{{"order":"newest","limit":2,"cursor":"{}"}}"#, cursor.unwrap()));
+        assert_eq!(second, vec!["Projects/big.pdf", "Dailyish.md"]);
+        let (last, cursor) = page(&mut node, &format!(r#"{{"order":"newest","limit":2,"cursor":"{}"}}"#, cursor.unwrap()));
+        assert_eq!((last, cursor), (vec!["Daily/tue.md".to_string()], None));
+
+        let (_, cursor) = page(&mut node, r#"{"limit":3}"#);
+        assert_eq!(page(&mut node, &format!(r#"{{"cursor":"{}"}}"#, cursor.clone().unwrap())).0, vec!["Dailyish.md", "Projects/big.pdf"]);
+        assert!(node.query_files(&format!(r#"{{"order":"newest","cursor":"{}"}}"#, cursor.unwrap())).is_err());
+        assert!(node.query_files(r#"{"limit":0}"#).is_err());
+        assert!(node.query_files(r#"{"deleted":"yes"}"#).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Pairing through an untrusted relay
+//!
+//! On a LAN the pairing code travels straight to the other device. Devices
+//! on different networks go through a relay, which must learn neither the
+//! code nor the resulting key. Pairing over a relay is therefore a PAKE in
+//! the style of CPace: both sides derive a group generator from the code and
+//! a temporary channel ID, exchange Diffie-Hellman shares over it, and
+//! confirm the derived key before trusting anything. The relay only sees the
+//! channel ID it routes by and base64 blobs. A relay that tampers with the
+//! exchange gets one guess at the code, after which the session fails and
+//! the host has to show a new code.
+//!
+//! The host (the device showing the code) creates the session and shows the
+//! channel ID with the code; the joiner sends first:
+//! `hello` (joiner) → `reply` with the host's confirmation → `confirm` (joiner).
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroize;
+
+use crate::crypto::{DeviceIdentity, PairingCode};
+
+const GENERATOR_DOMAIN: &[u8] = b"obsidian-p2p-sync relay pairing v1";
+
+/// What the relay forwards: the channel to route by and an opaque payload
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RelayEnvelope {
+    pub channel: String,
+    pub blob: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Message {
+    Hello { share: String, device_id: String, public_key: String },
+    Reply { share: String, device_id: String, public_key: String, confirm: String },
+    Confirm { confirm: String },
+}
+
+/// The authenticated peer and the key shared with it
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PairingResult {
+    pub device_id: String,
+    pub public_key: String,
+    pub session_key: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Host,
+    Joiner,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Stage {
+    Waiting,
+    /// Host: reply sent, waiting for the joiner's confirmation
+    Confirming { expected: [u8; 32], result: PairingResult },
+    Complete(PairingResult),
+    Failed,
+}
+
+pub struct RelayPairing {
+    role: Role,
+    channel: String,
+    code: String,
+    device_id: String,
+    public_key: String,
+    secret: Scalar,
+    share: [u8; 32],
+    stage: Stage,
+}
+
+fn length_prefixed(out: &mut Vec<u8>, part: &[u8]) {
+    out.extend_from_slice(&(part.len() as u32).to_be_bytes());
+    out.extend_from_slice(part);
+}
+
+fn random_scalar() -> Scalar {
+    let mut wide = [0u8; 64];
+    OsRng.fill_bytes(&mut wide);
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+fn generator(channel: &str, code: &str) -> RistrettoPoint {
+    let mut input = Vec::new();
+    length_prefixed(&mut input, GENERATOR_DOMAIN);
+    length_prefixed(&mut input, channel.as_bytes());
+    length_prefixed(&mut input, code.as_bytes());
+    RistrettoPoint::from_uniform_bytes(&Sha512::digest(&input).into())
+}
+
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Session key and the two confirmation tags derived from the shared point and transcript
+struct Keys {
+    session: [u8; 32],
+    host_confirm: [u8; 32],
+    joiner_confirm: [u8; 32],
+}
+
+impl RelayPairing {
+    fn create(role: Role, identity: &DeviceIdentity, channel: String, code: String) -> RelayPairing {
+        let secret = random_scalar();
+        let share = (secret * generator(&channel, &code)).compress().to_bytes();
+        RelayPairing {
+            role,
+            channel,
+            code,
+            device_id: identity.get_device_id(),
+            public_key: identity.get_public_key(),
+            secret,
+            share,
+            stage: Stage::Waiting,
+        }
+    }
+
+    fn envelope(&self, message: &Message) -> Vec<u8> {
+        let blob = BASE64.encode(serde_json::to_vec(message).unwrap_or_default());
+        serde_json::to_vec(&RelayEnvelope { channel: self.channel.clone(), blob }).unwrap_or_default()
+    }
+
+    /// Derive keys from the peer's share; `peer` is `(share, device_id, public_key)`
+    fn derive(&self, peer: (&str, &str, &str)) -> Result<Keys, String> {
+        let (share_b64, peer_id, peer_key) = peer;
+        let bytes: [u8; 32] = BASE64
+            .decode(share_b64)
+            .map_err(|e| e.to_string())?
+            .try_into()
+            .map_err(|_| "Invalid pairing share length".to_string())?;
+        let point = CompressedRistretto(bytes).decompress().ok_or("Invalid pairing share")?;
+        let shared = self.secret * point;
+        if point.is_identity() || shared.is_identity() {
+            return Err("Invalid pairing share".to_string());
+        }
+
+        let ours = (BASE64.encode(self.share), self.device_id.as_str(), self.public_key.as_str());
+        let theirs = (share_b64.to_string(), peer_id, peer_key);
+        let (joiner, host) = match self.role {
+            Role::Joiner => (ours, theirs),
+            Role::Host => (theirs, ours),
+        };
+        let mut transcript = Vec::new();
+        length_prefixed(&mut transcript, self.channel.as_bytes());
+        for (share, id, key) in [&joiner, &host] {
+            length_prefixed(&mut transcript, share.as_bytes());
+            length_prefixed(&mut transcript, id.as_bytes());
+            length_prefixed(&mut transcript, key.as_bytes());
+        }
+        let mut ikm = shared.compress().to_bytes().to_vec();
+        ikm.extend_from_slice(&Sha256::digest(&transcript));
+        let hk = Hkdf::<Sha256>::new(Some(GENERATOR_DOMAIN), &ikm);
+        let mut keys = Keys { session: [0; 32], host_confirm: [0; 32], joiner_confirm: [0; 32] };
+        for (info, out) in [
+            (&b"session key"[..], &mut keys.session),
+            (&b"host confirm"[..], &mut keys.host_confirm),
+            (&b"joiner confirm"[..], &mut keys.joiner_confirm),
+        ] {
+            hk.expand(info, out).map_err(|e| e.to_string())?;
+        }
+        Ok(keys)
+    }
+
+    fn handle(&mut self, message: Message) -> Result<Option<Vec<u8>>, String> {
+        let stage = std::mem::replace(&mut self.stage, Stage::Failed);
+        match (self.role, stage, message) {
+            (Role::Host, Stage::Waiting, Message::Hello { share, device_id, public_key }) => {
+                let keys = self.derive((&share, &device_id, &public_key))?;
+                let result = PairingResult { device_id, public_key, session_key: BASE64.encode(keys.session) };
+                let reply = Message::Reply {
+                    share: BASE64.encode(self.share),
+                    device_id: self.device_id.clone(),
+                    public_key: self.public_key.clone(),
+                    confirm: BASE64.encode(keys.host_confirm),
+                };
+                self.stage = Stage::Confirming { expected: keys.joiner_confirm, result };
+                Ok(Some(self.envelope(&reply)))
+            }
+            (Role::Joiner, Stage::Waiting, Message::Reply { share, device_id, public_key, confirm }) => {
+                let keys = self.derive((&share, &device_id, &public_key))?;
+                if !equal(&BASE64.decode(confirm).unwrap_or_default(), &keys.host_confirm) {
+                    return Err("Pairing confirmation failed: wrong code or tampered exchange".to_string());
+                }
+                let reply = Message::Confirm { confirm: BASE64.encode(keys.joiner_confirm) };
+                self.stage =
+                    Stage::Complete(PairingResult { device_id, public_key, session_key: BASE64.encode(keys.session) });
+                Ok(Some(self.envelope(&reply)))
+            }
+            (Role::Host, Stage::Confirming { expected, result }, Message::Confirm { confirm }) => {
+                if !equal(&BASE64.decode(confirm).unwrap_or_default(), &expected) {
+                    return Err("Pairing confirmation failed: wrong code or tampered exchange".to_string());
+                }
+                self.stage = Stage::Complete(result);
+                Ok(None)
+            }
+            _ => Err("Unexpected pairing message".to_string()),
+        }
+    }
+}
+
+impl Drop for RelayPairing {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+        self.code.zeroize();
+        if let Stage::Confirming { expected, result } = &mut self.stage {
+            expected.zeroize();
+            result.session_key.zeroize();
+        } else if let Stage::Complete(result) = &mut self.stage {
+            result.session_key.zeroize();
+        }
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        self.session.zeroize();
+        self.host_confirm.zeroize();
+        self.joiner_confirm.zeroize();
+    }
+}
+
+impl RelayPairing {
+    /// Host a pairing session under a fresh channel ID and code, both shown to the user
+    pub fn host(identity: &DeviceIdentity) -> RelayPairing {
+        let mut channel = [0u8; 8];
+        OsRng.fill_bytes(&mut channel);
+        RelayPairing::create(Role::Host, identity, hex::encode(channel), PairingCode::generate().get_code())
+    }
+
+    /// Join the session with the channel ID and code the host shows
+    pub fn join(identity: &DeviceIdentity, channel_id: &str, code: &str) -> RelayPairing {
+        RelayPairing::create(Role::Joiner, identity, channel_id.trim().to_lowercase(), code.trim().to_string())
+    }
+
+    /// Relay channel to subscribe to and address envelopes to
+    pub fn get_channel_id(&self) -> String {
+        self.channel.clone()
+    }
+
+    /// The code to show, on the host
+    pub fn get_code(&self) -> Option<String> {
+        (self.role == Role::Host).then(|| self.code.clone())
+    }
+
+    /// The joiner's first envelope to send through the relay
+    pub fn start(&self) -> Result<Vec<u8>, String> {
+        if self.role != Role::Joiner || self.stage != Stage::Waiting {
+            return Err("Only a new joiner starts the exchange".to_string());
+        }
+        let hello = Message::Hello {
+            share: BASE64.encode(self.share),
+            device_id: self.device_id.clone(),
+            public_key: self.public_key.clone(),
+        };
+        Ok(self.envelope(&hello))
+    }
+
+    /// Handle an envelope from the relay; returns the envelope to send back, if any.
+    /// Any failure ends the session for good
+    pub fn receive(&mut self, envelope: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        if matches!(self.stage, Stage::Complete(_) | Stage::Failed) {
+            return Err("Pairing session is over".to_string());
+        }
+        let envelope: RelayEnvelope = serde_json::from_slice(envelope).map_err(|e| format!("Invalid relay envelope: {}", e))?;
+        if envelope.channel != self.channel {
+            return Err("Envelope is for another pairing channel".to_string());
+        }
+        let message = BASE64
+            .decode(&envelope.blob)
+            .map_err(|e| e.to_string())
+            .and_then(|blob| serde_json::from_slice(&blob).map_err(|e| format!("Invalid pairing message: {}", e)));
+        let outcome = message.and_then(|message| self.handle(message));
+        if outcome.is_err() {
+            self.stage = Stage::Failed;
+        }
+        outcome
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.stage, Stage::Complete(_))
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.stage == Stage::Failed
+    }
+
+    /// `{device_id, public_key, session_key}` of the paired device as JSON, once complete
+    pub fn get_result_json(&self) -> Option<String> {
+        match &self.stage {
+            Stage::Complete(result) => serde_json::to_string(result).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(id: &str) -> DeviceIdentity {
+        DeviceIdentity::new(id.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_pairing_through_relay() {
+        let mut host = RelayPairing::host(&identity("laptop"));
+        let mut joiner = RelayPairing::join(&identity("phone"), &host.get_channel_id(), &host.get_code().unwrap());
+
+        let hello = joiner.start().unwrap();
+        let relayed: RelayEnvelope = serde_json::from_slice(&hello).unwrap();
+        assert_eq!(relayed.channel, host.get_channel_id());
+        assert!(!relayed.blob.contains(&host.get_code().unwrap()));
+
+        let reply = host.receive(&hello).unwrap().unwrap();
+        let confirm = joiner.receive(&reply).unwrap().unwrap();
+        assert_eq!(host.receive(&confirm).unwrap(), None);
+
+        let on_host: serde_json::Value = serde_json::from_str(&host.get_result_json().unwrap()).unwrap();
+        let on_joiner: serde_json::Value = serde_json::from_str(&joiner.get_result_json().unwrap()).unwrap();
+        assert_eq!(on_host["device_id"], "phone");
+        assert_eq!(on_joiner["device_id"], "laptop");
+        assert_eq!(on_joiner["session_key"], on_host["session_key"]);
+    }
+
+    #[test]
+    fn test_wrong_code_fails_both_sides() {
+        let mut host = RelayPairing::host(&identity("laptop"));
+        let code = host.get_code().unwrap();
+        let wrong = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+        let mut joiner = RelayPairing::join(&identity("phone"), &host.get_channel_id(), &wrong);
+
+        let reply = host.receive(&joiner.start().unwrap()).unwrap().unwrap();
+        assert!(joiner.receive(&reply).is_err());
+        assert!(joiner.is_failed() && !host.is_complete());
+
+        // A forged confirmation ends the host's session too
+        let forged = joiner.envelope(&Message::Confirm { confirm: BASE64.encode([0u8; 32]) });
+        assert!(host.receive(&forged).is_err());
+        assert!(host.receive(&forged).is_err());
+        assert!(host.is_failed());
+    }
+}
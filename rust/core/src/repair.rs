@@ -0,0 +1,210 @@
+//! Repairing corrupted local files
+//!
+//! Bit rot or a write cut short can leave a file whose content no longer
+//! matches its journal entry. The journal is right about the version (it
+//! was verified when recorded), so the fix is to pull that version again
+//! rather than record the damaged content as a new one. When the host's
+//! verification pass or a read finds a mismatch (`verify_local_file`,
+//! `verify_local_hash`), the file is flagged and a repair pull is scheduled
+//! from the peer most likely to hold the version: the peer it was pulled
+//! from, then its author, then other peers, preferring those currently
+//! discovered and with the best sync record. The host carries out the pulls
+//! listed by `get_repairs_json` and hands the content to `complete_repair`;
+//! a peer that can't supply it is skipped with `fail_repair` and the next
+//! one is tried. Detection, repair and giving up are reported in the issue
+//! log.
+//!
+//! A repair is dropped when the journal moves on (the file was written or
+//! deleted locally, or a newer version arrived).
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::sync::ChangeJournal;
+
+use crate::audit::AuditOrigin;
+use crate::clock;
+use crate::hashing::HashAlgorithm;
+use crate::issues;
+use crate::P2PNode;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Repair {
+    pub path: String,
+    /// Hash of the journal's version, which the repair must deliver
+    pub expected_hash: String,
+    pub found_hash: String,
+    pub detected_at: u64,
+    /// Peer to pull from; `None` once every candidate has failed
+    pub peer: Option<String>,
+    /// Peers that could not supply the version
+    pub tried: BTreeSet<String>,
+}
+
+#[derive(Default)]
+pub struct RepairQueue {
+    repairs: BTreeMap<String, Repair>,
+}
+
+impl RepairQueue {
+    /// Drop repairs whose version is no longer the journal's
+    fn prune(&mut self, journal: &ChangeJournal) {
+        self.repairs
+            .retain(|path, repair| journal.get(path).is_some_and(|m| !m.is_deleted && m.hash == repair.expected_hash));
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Repair> {
+        self.repairs.get(path)
+    }
+}
+
+impl P2PNode {
+    /// Check a local file's content against its journal entry; a mismatch flags it and
+    /// schedules a repair. Returns whether the content matched
+    pub fn verify_local_file(&mut self, path: &str, content: &[u8]) -> Result<bool, String> {
+        let expected = self.change_journal.get(path).map(|m| m.hash).unwrap_or_default();
+        let hash = HashAlgorithm::of(&expected).hash(content);
+        self.check_local_hash(path, hash).map_err(|e| self.record_error(e))
+    }
+
+    /// `verify_local_file` for a hash the host already computed, e.g. on a read
+    pub fn verify_local_hash(&mut self, path: &str, hash: &str) -> Result<bool, String> {
+        self.check_local_hash(path, hash.to_string()).map_err(|e| self.record_error(e))
+    }
+
+    /// Scheduled repairs as `[{path, expected_hash, found_hash, detected_at, peer, tried}]`
+    pub fn get_repairs_json(&mut self) -> String {
+        self.repairs.prune(&self.change_journal);
+        serde_json::to_string(&self.repairs.repairs.values().collect::<Vec<_>>()).unwrap_or_default()
+    }
+
+    /// Hand over the content pulled for a repair; the host writes it over the damaged
+    /// file once this succeeds
+    pub fn complete_repair(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        self.repairs.prune(&self.change_journal);
+        let Some(repair) = self.repairs.get(path) else {
+            return Err(self.record_error(format!("No repair scheduled for {}", path)));
+        };
+        let hash = HashAlgorithm::of(&repair.expected_hash).hash(content);
+        if hash != repair.expected_hash {
+            let e = format!("Content for {} does not match hash {}", path, repair.expected_hash);
+            return Err(self.record_error(e));
+        }
+        let peer = repair.peer.clone().unwrap_or_default();
+        self.repairs.repairs.remove(path);
+        let message = format!("Restored {} from {}", path, self.get_device_display_name(&peer));
+        self.issues.push(issues::Severity::Info, issues::FILE_REPAIRED, message, &[("path", path), ("peer", &peer)]);
+        Ok(())
+    }
+
+    /// The scheduled peer couldn't supply the version; move on to the next candidate.
+    /// Returns the new peer, or `undefined` when none is left
+    pub fn fail_repair(&mut self, path: &str, reason: &str) -> Result<Option<String>, String> {
+        self.repairs.prune(&self.change_journal);
+        let Some(repair) = self.repairs.repairs.get_mut(path) else {
+            return Err(self.record_error(format!("No repair scheduled for {}", path)));
+        };
+        repair.tried.extend(repair.peer.take());
+        let tried = repair.tried.clone();
+        let next = self.repair_candidates(path).into_iter().find(|peer| !tried.contains(peer));
+        if next.is_none() {
+            let message = format!("No peer could restore {}: {}", path, reason);
+            self.issues.push(issues::Severity::Error, issues::REPAIR_FAILED, message, &[("path", path)]);
+        }
+        if let Some(repair) = self.repairs.repairs.get_mut(path) {
+            repair.peer = next.clone();
+        }
+        Ok(next)
+    }
+}
+
+impl P2PNode {
+    fn check_local_hash(&mut self, path: &str, hash: String) -> Result<bool, String> {
+        let meta = self.change_journal.get(path).filter(|m| !m.is_deleted);
+        let meta = meta.ok_or_else(|| format!("No live entry for {}", path))?;
+        if meta.hash == hash {
+            return Ok(true);
+        }
+        self.repairs.prune(&self.change_journal);
+        if !self.repairs.repairs.contains_key(path) {
+            let peer = self.repair_candidates(path).into_iter().next();
+            let message = format!("{} no longer matches its synced version", path);
+            self.issues.warn(issues::CORRUPT_FILE, message, &[("path", path), ("expected", &meta.hash), ("found", &hash)]);
+            let repair = Repair {
+                path: path.to_string(),
+                expected_hash: meta.hash,
+                found_hash: hash,
+                detected_at: clock::now_ms(),
+                peer,
+                tried: BTreeSet::new(),
+            };
+            self.repairs.repairs.insert(path.to_string(), repair);
+        }
+        Ok(false)
+    }
+
+    /// Peers to pull `path`'s current version from, most likely holder first
+    pub(crate) fn repair_candidates(&self, path: &str) -> Vec<String> {
+        let Some(meta) = self.change_journal.get(path) else {
+            return Vec::new();
+        };
+        let source = self.audit.entries_for(path).filter(|e| e.sequence == meta.version).find_map(|e| match &e.origin {
+            AuditOrigin::Peer { peer_id } => Some(peer_id.clone()),
+            _ => None,
+        });
+        let online: BTreeSet<&str> = self.peers.values().map(|p| p.device_id.as_str()).collect();
+        let mut candidates: Vec<String> = online
+            .iter()
+            .copied()
+            .chain(self.peer_history.device_ids())
+            .chain(source.as_deref())
+            .chain([meta.last_modified_by.as_str()])
+            .filter(|peer| *peer != self.device_id)
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        candidates.sort_by_key(|peer| {
+            let stats = self.peer_history.get(peer);
+            (
+                source.as_deref() != Some(peer.as_str()),
+                *peer != meta.last_modified_by,
+                !online.contains(peer.as_str()),
+                stats.map_or(0, |s| s.consecutive_failures),
+                std::cmp::Reverse(stats.and_then(|s| s.last_success_at)),
+            )
+        });
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrupt_file_is_pulled_again() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let remote = r#"{"path":"a.md","hash":"HASH","mtime":1,"size":4,"version":3,"is_deleted":false,"last_modified_by":"tablet"}"#;
+        let remote = remote.replace("HASH", &crate::sync::hash_content(b"good"));
+        node.apply_remote_change(&remote, "phone").unwrap();
+        node.record_sync_round("desktop", 0, 10, true, 0, 0);
+
+        assert!(node.verify_local_file("a.md", b"good").unwrap());
+        assert!(!node.verify_local_file("a.md", b"go\0d").unwrap());
+        assert!(node.verify_local_file("missing.md", b"").is_err());
+        assert_eq!(node.repairs.get("a.md").unwrap().peer.as_deref(), Some("phone"));
+        assert!(node.issues.iter().any(|issue| issue.code == issues::CORRUPT_FILE));
+
+        // The source, then the author, then everyone else
+        assert_eq!(node.fail_repair("a.md", "offline").unwrap().as_deref(), Some("tablet"));
+        assert_eq!(node.fail_repair("a.md", "offline").unwrap().as_deref(), Some("desktop"));
+        assert!(node.complete_repair("a.md", b"bad").is_err());
+        node.complete_repair("a.md", b"good").unwrap();
+        assert_eq!(node.get_repairs_json(), "[]");
+
+        node.verify_local_hash("a.md", "0000").unwrap();
+        node.update_file("a.md".to_string(), b"rewritten", 2);
+        assert_eq!(node.get_repairs_json(), "[]");
+    }
+}
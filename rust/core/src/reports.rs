@@ -0,0 +1,181 @@
+//! Sync round reports
+//!
+//! While the host works through the peers `tick` returned, the node tallies
+//! what happened: files pulled through committed rounds, files the host
+//! reports as pushed, conflicts queued, and the bytes and outcome the host
+//! records with `record_sync_round`. `finish_round_report` closes the tally
+//! into a report with a per-peer breakdown and a one-line summary for a
+//! toast, and keeps the last `MAX_ROUND_REPORTS` reports for a history view.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::clock;
+use crate::P2PNode;
+
+/// Finished reports kept; the oldest are dropped first
+pub const MAX_ROUND_REPORTS: usize = 50;
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerBreakdown {
+    pub device_id: String,
+    pub name: String,
+    pub pulled: usize,
+    pub deleted: usize,
+    pub moved: usize,
+    pub pushed: usize,
+    pub conflicts: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// `None` until the host records the round's outcome
+    pub succeeded: Option<bool>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoundReport {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    pub pulled: usize,
+    pub deleted: usize,
+    pub moved: usize,
+    pub pushed: usize,
+    pub conflicts: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub failed_peers: usize,
+    pub peers: Vec<PeerBreakdown>,
+    /// e.g. "Synced with Phone: 3 pulled, 1 pushed, 1 conflict (12.0 KB)"
+    pub summary: String,
+}
+
+#[derive(Debug, Default)]
+pub struct RoundReports {
+    /// Tally of the round in progress, opened by its first event
+    open: Option<(u64, BTreeMap<String, PeerBreakdown>)>,
+    history: VecDeque<RoundReport>,
+}
+
+impl RoundReports {
+    pub fn peer(&mut self, device_id: &str) -> &mut PeerBreakdown {
+        let (_, peers) = self.open.get_or_insert_with(|| (clock::now_ms(), BTreeMap::new()));
+        peers
+            .entry(device_id.to_string())
+            .or_insert_with(|| PeerBreakdown { device_id: device_id.to_string(), ..Default::default() })
+    }
+
+    /// Close the open tally, if any, naming peers with `name`
+    pub fn finish(&mut self, now: u64, name: impl Fn(&str) -> String) -> Option<&RoundReport> {
+        let (started_at, peers) = self.open.take()?;
+        let mut report = RoundReport { started_at, finished_at: now, duration_ms: now.saturating_sub(started_at), ..Default::default() };
+        for (device_id, mut peer) in peers {
+            peer.name = name(&device_id);
+            report.pulled += peer.pulled;
+            report.deleted += peer.deleted;
+            report.moved += peer.moved;
+            report.pushed += peer.pushed;
+            report.conflicts += peer.conflicts;
+            report.bytes_sent += peer.bytes_sent;
+            report.bytes_received += peer.bytes_received;
+            report.failed_peers += usize::from(peer.succeeded == Some(false));
+            report.peers.push(peer);
+        }
+        report.summary = summarize(&report);
+        if self.history.len() >= MAX_ROUND_REPORTS {
+            self.history.pop_front();
+        }
+        self.history.push_back(report);
+        self.history.back()
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn summarize(report: &RoundReport) -> String {
+    let names: Vec<&str> = report.peers.iter().map(|p| p.name.as_str()).collect();
+    let mut parts = Vec::new();
+    if report.pulled > 0 {
+        parts.push(format!("{} pulled", report.pulled));
+    }
+    if report.deleted > 0 {
+        parts.push(format!("{} deleted", report.deleted));
+    }
+    if report.moved > 0 {
+        parts.push(format!("{} moved", report.moved));
+    }
+    if report.pushed > 0 {
+        parts.push(format!("{} pushed", report.pushed));
+    }
+    if report.conflicts > 0 {
+        parts.push(count(report.conflicts, "conflict"));
+    }
+    if report.failed_peers > 0 {
+        parts.push(format!("{} failed", count(report.failed_peers, "peer")));
+    }
+    let changes = if parts.is_empty() { "up to date".to_string() } else { parts.join(", ") };
+    let bytes = report.bytes_sent + report.bytes_received;
+    let bytes = if bytes > 0 { format!(" ({})", format_bytes(bytes)) } else { String::new() };
+    format!("Synced with {}: {}{}", names.join(", "), changes, bytes)
+}
+
+impl P2PNode {
+    /// Tell the report how many files the host pushed to a peer this round
+    pub fn note_files_pushed(&mut self, device_id: &str, count: usize) {
+        self.round_reports.peer(device_id).pushed += count;
+    }
+
+    /// Close the current round's tally; returns the report as JSON, or `null`
+    /// when nothing happened since the last one
+    pub fn finish_round_report(&mut self, now: u64) -> String {
+        let names: BTreeMap<String, String> = match &self.round_reports.open {
+            Some((_, peers)) => peers.keys().map(|id| (id.clone(), self.get_device_display_name(id))).collect(),
+            None => BTreeMap::new(),
+        };
+        let report = self.round_reports.finish(now, |id| names.get(id).cloned().unwrap_or_default());
+        serde_json::to_string(&report).unwrap_or_default()
+    }
+
+    /// Recent round reports as a JSON array, oldest first
+    pub fn get_round_reports_json(&self) -> String {
+        serde_json::to_string(&self.round_reports.history).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_tallies_rounds_per_peer() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        assert_eq!(node.finish_round_report(0), "null");
+
+        let changes = r#"[{"path":"a.md","hash":"","mtime":5,"size":0,"version":1,"is_deleted":true,"last_modified_by":"phone"},
+                         {"path":"b.md","hash":"","mtime":5,"size":0,"version":1,"is_deleted":true,"last_modified_by":"phone"}]"#;
+        node.update_file("a.md".to_string(), b"a", 1);
+        node.begin_sync_round(changes, "phone").unwrap();
+        node.commit_sync_round().unwrap();
+        node.note_files_pushed("phone", 2);
+        node.record_sync_round("phone", 0, 10, true, 2048, 1024);
+        node.record_sync_round("tablet", 0, 10, false, 0, 0);
+
+        let report: serde_json::Value = serde_json::from_str(&node.finish_round_report(clock::now_ms())).unwrap();
+        assert_eq!(report["deleted"], 1);
+        assert_eq!(report["pushed"], 2);
+        assert_eq!(report["failed_peers"], 1);
+        assert_eq!(report["peers"].as_array().unwrap().len(), 2);
+        assert_eq!(report["summary"], "Synced with phone, tablet: 1 deleted, 2 pushed, 1 peer failed (3.0 KB)");
+        assert_eq!(node.finish_round_report(0), "null");
+        assert_eq!(serde_json::from_str::<Vec<serde_json::Value>>(&node.get_round_reports_json()).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,405 @@
+//! Transfer resumption across reconnects
+//!
+//! When a connection drops mid-transfer both sides still know which file was
+//! moving and how far it got, but a new connection starts with a new session
+//! key and nothing tying it to the old one. Each tracked transfer therefore
+//! keeps a resumption secret derived from the session key it started under.
+//! After reconnecting, the receiver sends a token per in-flight transfer:
+//! the transfer ID, the number of chunks it has verified, and a proof keyed
+//! by both the resumption secret and the new session key. The sender checks
+//! the proof, continues from the first missing chunk, and both sides rebind
+//! the secret to the new session so the transfer can survive another drop.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeSet, VecDeque};
+use zeroize::Zeroize;
+
+use crate::crypto::{unwrap_secret, wrap_secret};
+use crate::ids;
+use crate::P2PNode;
+
+/// Transfers tracked at once; the oldest is forgotten first
+pub const MAX_RESUMABLE_TRANSFERS: usize = 256;
+const SECRET_WRAP_LABEL: &[u8] = b"obsidian-p2p-sync wrapped resumption secret";
+
+/// Associated data for a saved secret, so it only unwraps for its own transfer
+fn wrap_label(transfer_id: &str) -> Vec<u8> {
+    [SECRET_WRAP_LABEL, transfer_id.as_bytes()].concat()
+}
+
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn decode_key(session_key_b64: &str) -> Result<Vec<u8>, String> {
+    match BASE64.decode(session_key_b64) {
+        Ok(key) if key.len() == 32 => Ok(key),
+        _ => Err("Invalid session key".to_string()),
+    }
+}
+
+/// Secret tying a transfer to the session that carried it
+fn resumption_secret(session_key: &[u8], transfer_id: &str) -> [u8; 32] {
+    let mut secret = [0; 32];
+    let info = [&b"resume "[..], transfer_id.as_bytes()].concat();
+    // 32 bytes is always a valid HKDF-SHA256 output length
+    let _ = Hkdf::<Sha256>::new(None, session_key).expand(&info, &mut secret);
+    secret
+}
+
+/// Proof that the token's sender knows both the transfer's secret and the new session key
+fn proof(secret: &[u8; 32], new_session_key: &[u8], transfer: &TrackedTransfer, verified_chunks: u32) -> [u8; 32] {
+    let mut out = [0; 32];
+    let mut info = b"resume proof".to_vec();
+    for field in [transfer.id.as_bytes(), transfer.file_path.as_bytes(), transfer.hash.as_bytes()] {
+        info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        info.extend_from_slice(field);
+    }
+    info.extend_from_slice(&verified_chunks.to_be_bytes());
+    let _ = Hkdf::<Sha256>::new(Some(new_session_key), secret).expand(&info, &mut out);
+    out
+}
+
+pub struct TrackedTransfer {
+    id: String,
+    peer_id: String,
+    file_path: String,
+    hash: String,
+    total_chunks: u32,
+    /// Chunks the receiving side has verified (receiver only)
+    verified: BTreeSet<u32>,
+    secret: [u8; 32],
+}
+
+impl TrackedTransfer {
+    /// Chunks verified without a gap from the first one
+    fn verified_prefix(&self) -> u32 {
+        (0..self.total_chunks).find(|i| !self.verified.contains(i)).unwrap_or(self.total_chunks)
+    }
+}
+
+impl Drop for TrackedTransfer {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ResumptionToken {
+    transfer_id: String,
+    verified_chunks: u32,
+    /// Base64 proof
+    proof: String,
+}
+
+/// What the sender continues with after accepting a token
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Resumption {
+    pub transfer_id: String,
+    pub file_path: String,
+    /// First chunk to send on the new connection
+    pub next_chunk: u32,
+    pub total_chunks: u32,
+}
+
+#[derive(Serialize)]
+struct TransferJson<'a> {
+    id: &'a str,
+    peer_id: &'a str,
+    file_path: &'a str,
+    total_chunks: u32,
+    verified_chunks: usize,
+}
+
+/// A tracked transfer as kept across a restart (see `shutdown`); holds the
+/// resumption secret wrapped under a key the host keeps elsewhere
+#[derive(Serialize, Deserialize)]
+pub struct SavedTransfer {
+    id: String,
+    peer_id: String,
+    file_path: String,
+    hash: String,
+    total_chunks: u32,
+    verified: BTreeSet<u32>,
+    /// Base64, wrapped with `crypto::wrap_secret`
+    secret: String,
+}
+
+/// In-flight transfers that can be resumed on a new connection
+#[derive(Default)]
+pub struct ResumableTransfers {
+    transfers: VecDeque<TrackedTransfer>,
+}
+
+impl ResumableTransfers {
+    fn get_mut(&mut self, transfer_id: &str) -> Result<&mut TrackedTransfer, String> {
+        self.transfers
+            .iter_mut()
+            .find(|t| t.id == transfer_id)
+            .ok_or_else(|| format!("Unknown transfer: {}", transfer_id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+
+    /// The tracked transfers with their secrets wrapped under `wrapping_key_b64`
+    pub fn save(&self, wrapping_key_b64: &str) -> Result<Vec<SavedTransfer>, String> {
+        self.transfers
+            .iter()
+            .map(|t| {
+                Ok(SavedTransfer {
+                    id: t.id.clone(),
+                    peer_id: t.peer_id.clone(),
+                    file_path: t.file_path.clone(),
+                    hash: t.hash.clone(),
+                    total_chunks: t.total_chunks,
+                    verified: t.verified.clone(),
+                    secret: wrap_secret(wrapping_key_b64, &wrap_label(&t.id), &t.secret)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Track saved transfers again, skipping ones already tracked; returns how many were restored
+    pub fn restore(&mut self, saved: Vec<SavedTransfer>, wrapping_key_b64: &str) -> Result<usize, String> {
+        let mut restored = 0;
+        for transfer in saved {
+            let mut secret = [0; 32];
+            match unwrap_secret(wrapping_key_b64, &wrap_label(&transfer.id), &transfer.secret) {
+                Ok(bytes) if bytes.len() == 32 => secret.copy_from_slice(&bytes),
+                _ => return Err(format!("Invalid resumption secret for transfer {}", transfer.id)),
+            }
+            if self.transfers.iter().any(|t| t.id == transfer.id) {
+                continue;
+            }
+            if self.transfers.len() == MAX_RESUMABLE_TRANSFERS {
+                self.transfers.pop_front();
+            }
+            self.transfers.push_back(TrackedTransfer {
+                id: transfer.id,
+                peer_id: transfer.peer_id,
+                file_path: transfer.file_path,
+                hash: transfer.hash,
+                total_chunks: transfer.total_chunks,
+                verified: transfer.verified,
+                secret,
+            });
+            restored += 1;
+        }
+        Ok(restored)
+    }
+}
+
+impl P2PNode {
+    /// Track a transfer so it can be resumed after a reconnect. The sender passes
+    /// no `transfer_id` and gets a new one to announce; the receiver passes that ID.
+    /// Returns the transfer ID
+    pub fn track_transfer(
+        &mut self,
+        peer_id: &str,
+        transfer_id: Option<String>,
+        file_path: String,
+        hash: String,
+        total_chunks: u32,
+        session_key_b64: &str,
+    ) -> Result<String, String> {
+        self.start_tracking(peer_id, transfer_id, file_path, hash, total_chunks, session_key_b64)
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Receiver: record a chunk that decrypted and verified
+    pub fn record_verified_chunk(&mut self, transfer_id: &str, chunk_index: u32) -> Result<(), String> {
+        self.mark_chunk_verified(transfer_id, chunk_index).map_err(|e| self.record_error(e))
+    }
+
+    /// Stop tracking a transfer that completed or was abandoned
+    pub fn finish_transfer(&mut self, transfer_id: &str) {
+        self.resumable.transfers.retain(|t| t.id != transfer_id);
+    }
+
+    /// Receiver, after reconnecting: a JSON array of tokens (one per transfer from
+    /// `peer_id`) to send to the peer; rebinds the transfers to `new_session_key_b64`
+    pub fn create_resumption_tokens(&mut self, peer_id: &str, new_session_key_b64: &str) -> Result<String, String> {
+        self.resumption_tokens(peer_id, new_session_key_b64)
+            .map(|tokens| serde_json::to_string(&tokens).unwrap_or_default())
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Sender: check a peer's token; returns `{transfer_id, file_path, next_chunk,
+    /// total_chunks}` as JSON and rebinds the transfer to `new_session_key_b64`
+    pub fn accept_resumption_token(&mut self, peer_id: &str, token: &str, new_session_key_b64: &str) -> Result<String, String> {
+        self.accept_resumption(peer_id, token, new_session_key_b64)
+            .map(|resumption| serde_json::to_string(&resumption).unwrap_or_default())
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Tracked transfers as `[{id, peer_id, file_path, total_chunks, verified_chunks}]` JSON
+    pub fn get_resumable_transfers_json(&self) -> String {
+        let transfers: Vec<_> = self
+            .resumable
+            .transfers
+            .iter()
+            .map(|t| TransferJson {
+                id: &t.id,
+                peer_id: &t.peer_id,
+                file_path: &t.file_path,
+                total_chunks: t.total_chunks,
+                verified_chunks: t.verified.len(),
+            })
+            .collect();
+        serde_json::to_string(&transfers).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn start_tracking(
+        &mut self,
+        peer_id: &str,
+        transfer_id: Option<String>,
+        file_path: String,
+        hash: String,
+        total_chunks: u32,
+        session_key_b64: &str,
+    ) -> Result<String, String> {
+        let key = decode_key(session_key_b64)?;
+        let id = transfer_id.unwrap_or_else(ids::new_id);
+        if self.resumable.transfers.iter().any(|t| t.id == id) {
+            return Err(format!("Transfer {} is already tracked", id));
+        }
+        if self.resumable.transfers.len() == MAX_RESUMABLE_TRANSFERS {
+            self.resumable.transfers.pop_front();
+        }
+        self.resumable.transfers.push_back(TrackedTransfer {
+            secret: resumption_secret(&key, &id),
+            id: id.clone(),
+            peer_id: peer_id.to_string(),
+            file_path,
+            hash,
+            total_chunks,
+            verified: BTreeSet::new(),
+        });
+        Ok(id)
+    }
+
+    pub(crate) fn mark_chunk_verified(&mut self, transfer_id: &str, chunk_index: u32) -> Result<(), String> {
+        let transfer = self.resumable.get_mut(transfer_id)?;
+        if chunk_index >= transfer.total_chunks {
+            return Err(format!("Chunk {} of {} is out of range", chunk_index, transfer.total_chunks));
+        }
+        transfer.verified.insert(chunk_index);
+        Ok(())
+    }
+
+    pub(crate) fn resumption_tokens(&mut self, peer_id: &str, new_session_key_b64: &str) -> Result<Vec<String>, String> {
+        let key = decode_key(new_session_key_b64)?;
+        Ok(self
+            .resumable
+            .transfers
+            .iter_mut()
+            .filter(|t| t.peer_id == peer_id)
+            .map(|transfer| {
+                let verified_chunks = transfer.verified_prefix();
+                let token = ResumptionToken {
+                    transfer_id: transfer.id.clone(),
+                    verified_chunks,
+                    proof: BASE64.encode(proof(&transfer.secret, &key, transfer, verified_chunks)),
+                };
+                transfer.secret = resumption_secret(&key, &transfer.id);
+                BASE64.encode(serde_json::to_vec(&token).unwrap_or_default())
+            })
+            .collect())
+    }
+
+    pub(crate) fn accept_resumption(&mut self, peer_id: &str, token: &str, new_session_key_b64: &str) -> Result<Resumption, String> {
+        let key = decode_key(new_session_key_b64)?;
+        let token: ResumptionToken = BASE64
+            .decode(token)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or("Malformed resumption token")?;
+        let transfer = self.resumable.get_mut(&token.transfer_id)?;
+        if transfer.peer_id != peer_id {
+            return Err(format!("Transfer {} is not with {}", transfer.id, peer_id));
+        }
+        let expected = proof(&transfer.secret, &key, transfer, token.verified_chunks);
+        let received = BASE64.decode(&token.proof).unwrap_or_default();
+        if !equal(&expected, &received) {
+            return Err(format!("Resumption proof for {} does not verify", transfer.id));
+        }
+        if token.verified_chunks > transfer.total_chunks {
+            return Err(format!("Transfer {} has only {} chunks", transfer.id, transfer.total_chunks));
+        }
+        transfer.secret = resumption_secret(&key, &transfer.id);
+        Ok(Resumption {
+            transfer_id: transfer.id.clone(),
+            file_path: transfer.file_path.clone(),
+            next_chunk: token.verified_chunks,
+            total_chunks: transfer.total_chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_resumes_on_new_session() {
+        let (old_key, new_key, next_key) = (BASE64.encode([1u8; 32]), BASE64.encode([2u8; 32]), BASE64.encode([3u8; 32]));
+        let mut sender = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut receiver = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let id = sender.start_tracking("phone", None, "big.pdf".to_string(), "ab".repeat(32), 10, &old_key).unwrap();
+        receiver.start_tracking("laptop", Some(id.clone()), "big.pdf".to_string(), "ab".repeat(32), 10, &old_key).unwrap();
+        for chunk in [0, 1, 2, 4] {
+            receiver.mark_chunk_verified(&id, chunk).unwrap();
+        }
+        assert!(receiver.mark_chunk_verified(&id, 10).is_err());
+
+        // Reconnect: the receiver sends its tokens under the new session
+        let tokens = receiver.resumption_tokens("laptop", &new_key).unwrap();
+        assert_eq!(tokens.len(), 1);
+        // Replaying the token from another device or under another session fails
+        assert!(sender.accept_resumption("desktop", &tokens[0], &new_key).is_err());
+        assert!(sender.accept_resumption("phone", &tokens[0], &next_key).is_err());
+        let resumption = sender.accept_resumption("phone", &tokens[0], &new_key).unwrap();
+        assert_eq!(resumption.next_chunk, 3);
+        assert_eq!(resumption.file_path, "big.pdf");
+
+        // Both sides were rebound, so a second drop resumes too
+        receiver.mark_chunk_verified(&id, 3).unwrap();
+        let tokens = receiver.resumption_tokens("laptop", &next_key).unwrap();
+        assert_eq!(sender.accept_resumption("phone", &tokens[0], &next_key).unwrap().next_chunk, 5);
+
+        sender.finish_transfer(&id);
+        assert!(sender.resumable.is_empty());
+        assert!(sender.accept_resumption("phone", &tokens[0], &next_key).is_err());
+    }
+
+    #[test]
+    fn test_saved_secrets_are_wrapped() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let id = node.start_tracking("phone", None, "big.pdf".to_string(), "ab".repeat(32), 10, &BASE64.encode([1u8; 32])).unwrap();
+        let (key, wrong) = (BASE64.encode([9u8; 32]), BASE64.encode([8u8; 32]));
+        let saved = node.resumable.save(&key).unwrap();
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(!json.contains(&BASE64.encode(node.resumable.transfers[0].secret)));
+
+        let mut restored = ResumableTransfers::default();
+        assert!(restored.restore(node.resumable.save(&key).unwrap(), &wrong).is_err());
+        // A wrapped secret moved onto another transfer does not unwrap
+        let mut moved = node.resumable.save(&key).unwrap();
+        moved[0].id = "other".to_string();
+        assert!(restored.restore(moved, &key).is_err());
+        assert_eq!(restored.restore(saved, &key).unwrap(), 1);
+        assert_eq!(restored.get_mut(&id).unwrap().secret, node.resumable.transfers[0].secret);
+        assert!(node.resumable.save("short").is_err());
+    }
+}
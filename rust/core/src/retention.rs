@@ -0,0 +1,150 @@
+//! Backup schedule and retention
+//!
+//! Bookkeeping for the plugin's backup feature: which snapshots exist, when
+//! the next one is due, and which ones a tiered retention policy ("hourly
+//! for a day, daily for a month") no longer needs. The schedule is stored
+//! with the journal so it survives restarts together with the sequence
+//! numbers snapshots refer to.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// Keep one snapshot per `every_ms` bucket for the last `keep_for_ms`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RetentionTier {
+    pub every_ms: u64,
+    pub keep_for_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Minimum time between snapshots
+    pub interval_ms: u64,
+    pub tiers: Vec<RetentionTier>,
+}
+
+impl Default for RetentionPolicy {
+    /// Hourly snapshots kept for a day, daily ones for a month
+    fn default() -> Self {
+        RetentionPolicy {
+            interval_ms: HOUR_MS,
+            tiers: vec![
+                RetentionTier { every_ms: HOUR_MS, keep_for_ms: DAY_MS },
+                RetentionTier { every_ms: DAY_MS, keep_for_ms: 30 * DAY_MS },
+            ],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    pub id: String,
+    pub created_at: u64,
+    /// Journal sequence the snapshot captured
+    pub sequence: u64,
+    #[serde(default)]
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BackupSchedule {
+    #[serde(default)]
+    pub policy: RetentionPolicy,
+    /// Oldest first
+    #[serde(default)]
+    snapshots: Vec<SnapshotRecord>,
+}
+
+impl BackupSchedule {
+    pub fn snapshots(&self) -> &[SnapshotRecord] {
+        &self.snapshots
+    }
+
+    pub fn record(&mut self, snapshot: SnapshotRecord) {
+        self.snapshots.retain(|s| s.id != snapshot.id);
+        let index = self.snapshots.partition_point(|s| s.created_at <= snapshot.created_at);
+        self.snapshots.insert(index, snapshot);
+    }
+
+    /// A snapshot is due once the interval has passed and the journal has moved on
+    pub fn is_due(&self, now: u64, sequence: u64) -> bool {
+        match self.snapshots.last() {
+            None => true,
+            Some(last) => now.saturating_sub(last.created_at) >= self.policy.interval_ms && sequence > last.sequence,
+        }
+    }
+
+    /// When the next snapshot may be taken (it also needs new changes)
+    pub fn next_due_at(&self) -> u64 {
+        self.snapshots.last().map_or(0, |last| last.created_at.saturating_add(self.policy.interval_ms))
+    }
+
+    /// Ids the policy keeps: the newest snapshot in each tier bucket, plus the latest overall
+    pub fn retained(&self, now: u64) -> HashSet<&str> {
+        let mut keep: HashSet<&str> = self.snapshots.last().map(|s| s.id.as_str()).into_iter().collect();
+        for tier in self.policy.tiers.iter().filter(|t| t.every_ms > 0) {
+            let since = now.saturating_sub(tier.keep_for_ms);
+            let mut last_bucket = None;
+            // Newest first, so the first snapshot seen in a bucket is the one kept
+            for snapshot in self.snapshots.iter().rev().filter(|s| s.created_at >= since) {
+                let bucket = snapshot.created_at / tier.every_ms;
+                if last_bucket != Some(bucket) {
+                    keep.insert(&snapshot.id);
+                    last_bucket = Some(bucket);
+                }
+            }
+        }
+        keep
+    }
+
+    /// Remove snapshots the policy no longer keeps; returns their ids for deletion
+    pub fn prune(&mut self, now: u64) -> Vec<String> {
+        let keep: HashSet<String> = self.retained(now).into_iter().map(str::to_string).collect();
+        let (kept, pruned): (Vec<_>, Vec<_>) = self.snapshots.drain(..).partition(|s| keep.contains(&s.id));
+        self.snapshots = kept;
+        pruned.into_iter().map(|s| s.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(id: &str, created_at: u64, sequence: u64) -> SnapshotRecord {
+        SnapshotRecord { id: id.to_string(), created_at, sequence, size_bytes: 0 }
+    }
+
+    #[test]
+    fn test_due_requires_interval_and_changes() {
+        let mut schedule = BackupSchedule::default();
+        assert!(schedule.is_due(0, 0));
+        schedule.record(snapshot("a", 10 * DAY_MS, 5));
+        assert!(!schedule.is_due(10 * DAY_MS + HOUR_MS / 2, 6));
+        assert!(!schedule.is_due(10 * DAY_MS + HOUR_MS, 5));
+        assert!(schedule.is_due(10 * DAY_MS + HOUR_MS, 6));
+        assert_eq!(schedule.next_due_at(), 10 * DAY_MS + HOUR_MS);
+    }
+
+    #[test]
+    fn test_prune_keeps_hourly_then_daily() {
+        let now = 100 * DAY_MS;
+        let mut schedule = BackupSchedule::default();
+        // Two snapshots per hour over the last three hours
+        for (i, minutes) in [170u64, 150, 110, 90, 50, 30].iter().enumerate() {
+            schedule.record(snapshot(&format!("recent-{}", i), now - minutes * 60_000, i as u64));
+        }
+        // Several per day a week ago, and one far beyond the daily window
+        schedule.record(snapshot("week-morning", now - 7 * DAY_MS + HOUR_MS, 0));
+        schedule.record(snapshot("week-evening", now - 7 * DAY_MS + 10 * HOUR_MS, 0));
+        schedule.record(snapshot("ancient", now - 90 * DAY_MS, 0));
+
+        let mut pruned = schedule.prune(now);
+        pruned.sort();
+        assert_eq!(pruned, vec!["ancient", "recent-0", "recent-2", "recent-4", "week-morning"]);
+        assert_eq!(schedule.snapshots().len(), 4);
+        assert_eq!(schedule.snapshots().last().unwrap().id, "recent-5");
+    }
+}
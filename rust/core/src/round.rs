@@ -0,0 +1,448 @@
+//! Two-phase application of sync rounds
+//!
+//! Applying a peer's changes one file at a time means a round that dies
+//! halfway leaves the vault half old, half new. A round is instead prepared
+//! first: the changes the journal doesn't have yet are staged, the host
+//! downloads each new body to a staging location and hands it (or its
+//! ingest) back for verification, and only when every body has arrived and
+//! matched its hash does `commit` record the whole set in the journal and
+//! return the writes and deletes for the host to move into place together.
+//! Aborting drops the round without having touched the journal.
+//!
+//! A rename arrives as a tombstone for the old path and an entry for the new
+//! one with `moved_from` set. When both are in the round and our copy at the
+//! old path has the moved content, the change needs no download: it commits
+//! as one `move` operation, so the host renames the file (and Obsidian
+//! updates links to it) instead of deleting it and writing a new copy.
+//!
+//! A version that only changes extended metadata (see `extmeta`) needs no
+//! download either and commits as a `metadata` operation. Every operation
+//! that leaves a file in place carries the file's resulting extended
+//! metadata for the host to apply.
+//!
+//! Under an on-demand profile, writes of files this device doesn't hold
+//! commit as `placeholder` operations without a download (see
+//! `SyncRound::stage_placeholders`).
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cancel::{self, CancellationToken};
+use crate::debugring::DebugCategory;
+use crate::error::Error;
+use crate::extmeta::MetadataRules;
+use crate::hashing::HashAlgorithm;
+use crate::ingest::FileIngest;
+use crate::sync::{ChangeJournal, ExtendedMetadata, FileMetadata};
+use crate::P2PNode;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundAction {
+    Write,
+    Delete,
+    /// Rename the file at `from` to `path`
+    Move,
+    /// Content is unchanged; apply `extended` to the file at `path`
+    Metadata,
+    /// Show `path` without content; it is fetched with `request_file`
+    Placeholder,
+}
+
+/// One operation of a committed round, for the host to carry out
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct CommitOp {
+    pub path: String,
+    pub action: RoundAction,
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "ExtendedMetadata::is_empty")]
+    pub extended: ExtendedMetadata,
+}
+
+struct StagedChange {
+    remote: FileMetadata,
+    /// Deletes, local moves, metadata changes and placeholders need no content and start verified
+    verified: bool,
+    /// A rename we carry out by moving our own copy
+    local_move: bool,
+    /// Our copy already has the content
+    metadata_only: bool,
+    /// Recorded without content
+    placeholder: bool,
+}
+
+impl StagedChange {
+    fn needs_content(&self) -> bool {
+        !self.remote.is_deleted && !self.local_move && !self.metadata_only && !self.placeholder
+    }
+
+    fn action(&self) -> (RoundAction, Option<String>) {
+        if self.remote.is_deleted {
+            (RoundAction::Delete, None)
+        } else if self.local_move {
+            (RoundAction::Move, self.remote.moved_from.clone())
+        } else if self.metadata_only {
+            (RoundAction::Metadata, None)
+        } else if self.placeholder {
+            (RoundAction::Placeholder, None)
+        } else {
+            (RoundAction::Write, None)
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoundStatus {
+    pub peer_id: String,
+    pub staged: usize,
+    /// Paths whose content has not arrived and verified yet
+    pub awaiting: Vec<String>,
+}
+
+pub struct SyncRound {
+    peer_id: String,
+    changes: BTreeMap<String, StagedChange>,
+    /// Journal sequence the peer advertised when the round was prepared
+    peer_sequence: Option<u64>,
+}
+
+impl SyncRound {
+    /// Stage `changes` (already filtered by policy) that `journal` doesn't have yet,
+    /// checking `cancel` between changes
+    pub fn prepare(
+        peer_id: &str,
+        changes: Vec<FileMetadata>,
+        journal: &ChangeJournal,
+        rules: &MetadataRules,
+        cancel: &Option<CancellationToken>,
+    ) -> Result<SyncRound, Error> {
+        let tombstones: BTreeSet<String> = changes.iter().filter(|c| c.is_deleted).map(|c| c.path.clone()).collect();
+        let mut staged = BTreeMap::new();
+        for remote in changes {
+            cancel::check(cancel)?;
+            let local = journal.get(&remote.path).filter(|m| !m.is_deleted);
+            let same_content = local.as_ref().is_some_and(|m| m.hash == remote.hash);
+            let current = if remote.is_deleted {
+                local.is_none()
+            } else {
+                local.is_some_and(|m| {
+                    m.hash == remote.hash && rules.merge_incoming(&m.extended, remote.extended.clone()) == m.extended
+                })
+            };
+            if !current {
+                let metadata_only = !remote.is_deleted && same_content;
+                let local_move = !metadata_only
+                    && remote.moved_from.as_ref().is_some_and(|from| {
+                        tombstones.contains(from) && journal.get(from).is_some_and(|m| !m.is_deleted && m.hash == remote.hash)
+                    });
+                let verified = remote.is_deleted || local_move || metadata_only;
+                let change = StagedChange { remote, verified, local_move, metadata_only, placeholder: false };
+                staged.insert(change.remote.path.clone(), change);
+            }
+        }
+        Ok(SyncRound { peer_id: peer_id.to_string(), changes: staged, peer_sequence: None })
+    }
+
+    /// Turn the writes of files whose content this device doesn't hold (`held` is
+    /// false for the path, or for the source of a move) into placeholders
+    pub fn stage_placeholders(&mut self, held: impl Fn(&str) -> bool) {
+        for change in self.changes.values_mut().filter(|c| !c.remote.is_deleted && !c.metadata_only) {
+            let source = if change.local_move { change.remote.moved_from.as_deref() } else { Some(change.remote.path.as_str()) };
+            if !source.is_some_and(&held) {
+                change.local_move = false;
+                change.placeholder = true;
+                change.verified = true;
+            }
+        }
+    }
+
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Remember the peer's advertised journal sequence, reconciled once the round commits
+    pub fn note_peer_sequence(&mut self, sequence: u64) {
+        self.peer_sequence = Some(sequence);
+    }
+
+    pub fn peer_sequence(&self) -> Option<u64> {
+        self.peer_sequence
+    }
+
+    fn staged_write(&mut self, path: &str) -> Result<&mut StagedChange, Error> {
+        match self.changes.get_mut(path) {
+            Some(change) if change.needs_content() => Ok(change),
+            _ => Err(format!("{} is not a staged write in this round", path).into()),
+        }
+    }
+
+    /// Check a downloaded body against the staged hash
+    pub fn verify_content(&mut self, path: &str, content: &[u8]) -> Result<(), Error> {
+        let change = self.staged_write(path)?;
+        let expected = &change.remote.hash;
+        if HashAlgorithm::of(expected).hash(content) != *expected {
+            return Err(format!("Content for {} does not match hash {}", path, expected).into());
+        }
+        change.verified = true;
+        Ok(())
+    }
+
+    /// Check a finished ingest of a downloaded body against the staged hash
+    pub fn verify_ingest(&mut self, ingest: &FileIngest) -> Result<(), Error> {
+        let path = ingest.file_path().to_string();
+        let change = self.staged_write(&path)?;
+        let expected = &change.remote.hash;
+        match ingest.hash_for(HashAlgorithm::of(expected)) {
+            Some(hash) if hash == expected => {
+                change.verified = true;
+                Ok(())
+            }
+            Some(_) => Err(format!("Content for {} does not match hash {}", path, expected).into()),
+            None => Err(format!("Ingest of {} was not hashed with {}", path, HashAlgorithm::of(expected).as_str()).into()),
+        }
+    }
+
+    pub fn status(&self) -> RoundStatus {
+        RoundStatus {
+            peer_id: self.peer_id.clone(),
+            staged: self.changes.len(),
+            awaiting: self.changes.values().filter(|c| !c.verified).map(|c| c.remote.path.clone()).collect(),
+        }
+    }
+
+    /// The staged changes and the operations they turn into, if every body has verified
+    pub fn into_commit(self) -> Result<(Vec<FileMetadata>, Vec<CommitOp>), (SyncRound, Error)> {
+        let awaiting = self.changes.values().filter(|c| !c.verified).count();
+        if awaiting > 0 {
+            let e = format!("Round with {} still has {} unverified files", self.peer_id, awaiting).into();
+            return Err((self, e));
+        }
+        // The host's move takes the old file away; no separate delete for it
+        let moved: BTreeSet<String> =
+            self.changes.values().filter(|c| c.local_move).filter_map(|c| c.remote.moved_from.clone()).collect();
+        let mut changes = Vec::new();
+        let mut ops = Vec::new();
+        for (path, change) in self.changes {
+            let (action, from) = change.action();
+            if action != RoundAction::Delete || !moved.contains(&path) {
+                let hash = change.remote.hash.clone();
+                ops.push(CommitOp { path, action, hash, from, extended: ExtendedMetadata::default() });
+            }
+            changes.push(change.remote);
+        }
+        Ok((changes, ops))
+    }
+
+    /// Paths staged for writing, whose staged bodies the host should discard on abort
+    pub fn staged_writes(&self) -> Vec<String> {
+        self.changes.values().filter(|c| c.needs_content()).map(|c| c.remote.path.clone()).collect()
+    }
+}
+
+impl P2PNode {
+    /// Stage a peer's changes (a JSON array of file metadata) as one round; returns its status
+    pub fn begin_sync_round(&mut self, changes_json: &str, from_peer_id: &str) -> Result<String, String> {
+        self.prepare_round(changes_json, from_peer_id)
+            .and_then(|status| serde_json::to_string(&status).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Verify a staged body the host has downloaded
+    pub fn verify_round_content(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        let result = match self.sync_round.as_mut() {
+            Some(round) => round.verify_content(path, content),
+            None => Err("No sync round in progress".into()),
+        };
+        result.map_err(|e| self.record_error(e))
+    }
+
+    /// Verify a staged body the host has streamed through a `FileIngest`
+    pub fn verify_round_ingest(&mut self, ingest: &FileIngest) -> Result<(), String> {
+        let result = match self.sync_round.as_mut() {
+            Some(round) => round.verify_ingest(ingest),
+            None => Err("No sync round in progress".into()),
+        };
+        result.map_err(|e| self.record_error(e))
+    }
+
+    /// Status of the current round as JSON, or `null`
+    pub fn get_sync_round_json(&self) -> String {
+        serde_json::to_string(&self.sync_round.as_ref().map(|r| r.status())).unwrap_or_default()
+    }
+
+    /// Record the round in the journal once every body has verified; returns the
+    /// `{path, action, hash}` operations for the host to apply as JSON
+    pub fn commit_sync_round(&mut self) -> Result<String, String> {
+        self.commit_round()
+            .and_then(|ops| serde_json::to_string(&ops).map_err(|e| e.to_string()))
+            .map_err(|e| self.record_error(e))
+    }
+
+    /// Drop the current round; returns the staged write paths to discard as JSON
+    pub fn abort_sync_round(&mut self) -> String {
+        let Some(round) = self.sync_round.take() else {
+            return "[]".to_string();
+        };
+        let paths = round.staged_writes();
+        self.debug_event(DebugCategory::State, "round_aborted", round.peer_id(), Some(paths.len() as u64));
+        serde_json::to_string(&paths).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn prepare_round(&mut self, changes_json: &str, from_peer_id: &str) -> Result<RoundStatus, String> {
+        if let Some(round) = &self.sync_round {
+            return Err(format!("A sync round with {} is already in progress", round.peer_id()));
+        }
+        self.check_not_quarantined(from_peer_id)?;
+        let changes: Vec<FileMetadata> =
+            serde_json::from_str(changes_json).map_err(|e| format!("Invalid remote metadata: {}", e))?;
+        let changes = changes.into_iter().filter(|c| self.policy.should_sync(&c.path)).collect();
+        let mut round = SyncRound::prepare(from_peer_id, changes, &self.change_journal, &self.metadata_rules, &self.cancel_token)?;
+        if self.profile.on_demand {
+            round.stage_placeholders(|path| self.holds_content(path) || self.is_pinned(path));
+        }
+        if let Some(sequence) = self.advertised_sequence(from_peer_id) {
+            round.note_peer_sequence(sequence);
+        }
+        let status = round.status();
+        self.debug_event(DebugCategory::Decision, "round_staged", from_peer_id, Some(status.staged as u64));
+        self.sync_round = Some(round);
+        Ok(status)
+    }
+
+    pub(crate) fn commit_round(&mut self) -> Result<Vec<CommitOp>, String> {
+        let round = self.sync_round.take().ok_or("No sync round in progress")?;
+        let peer_id = round.peer_id().to_string();
+        let peer_sequence = round.peer_sequence();
+        let (changes, mut ops) = round.into_commit().map_err(|(round, e)| {
+            let awaiting = round.status().awaiting.len() as u64;
+            self.debug_event(DebugCategory::State, "round_incomplete", &peer_id, Some(awaiting));
+            self.sync_round = Some(round);
+            e
+        })?;
+        for change in changes {
+            self.apply_remote(change, &peer_id);
+        }
+        self.placeholders.record_round(&ops);
+        if let Some(sequence) = peer_sequence {
+            self.first_sync.reconciled(&peer_id, sequence);
+        }
+        // The merged metadata, with this device's local attributes
+        for op in ops.iter_mut().filter(|op| op.action != RoundAction::Delete) {
+            op.extended = self.change_journal.get(&op.path).map(|m| m.extended).unwrap_or_default();
+        }
+        let report = self.round_reports.peer(&peer_id);
+        for op in &ops {
+            match op.action {
+                RoundAction::Write | RoundAction::Metadata | RoundAction::Placeholder => report.pulled += 1,
+                RoundAction::Delete => report.deleted += 1,
+                RoundAction::Move => report.moved += 1,
+            }
+        }
+        self.debug_event(DebugCategory::State, "round_committed", &peer_id, Some(ops.len() as u64));
+        Ok(ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::hash_content;
+
+    fn remote(path: &str, content: Option<&[u8]>) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: content.map(hash_content).unwrap_or_default(),
+            mtime: 5,
+            size: content.map_or(0, |c| c.len() as u64),
+            version: 9,
+            is_deleted: content.is_none(),
+            last_modified_by: "phone".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_commit_requires_every_body_verified() {
+        let mut journal = ChangeJournal::new();
+        journal.update_file("same.md".to_string(), b"same", 1, "laptop".to_string());
+        journal.update_file("gone.md".to_string(), b"old", 1, "laptop".to_string());
+        let changes = vec![
+            remote("same.md", Some(b"same")),
+            remote("new.md", Some(b"new")),
+            remote("gone.md", None),
+            remote("never-existed.md", None),
+        ];
+        let token = CancellationToken::new();
+        token.cancel_after_checks(2);
+        let cancelled = SyncRound::prepare("phone", changes.clone(), &journal, &MetadataRules::default(), &Some(token));
+        assert!(cancelled.is_err());
+        let mut round = SyncRound::prepare("phone", changes, &journal, &MetadataRules::default(), &None).unwrap();
+        assert_eq!(round.status().staged, 2);
+        assert_eq!(round.status().awaiting, vec!["new.md"]);
+
+        assert!(round.verify_content("new.md", b"tampered").is_err());
+        assert!(round.verify_content("gone.md", b"").is_err());
+        let (mut round, _) = round.into_commit().unwrap_err();
+        round.verify_content("new.md", b"new").unwrap();
+
+        let Ok((changes, ops)) = round.into_commit() else { panic!("round should commit") };
+        assert_eq!(changes.len(), 2);
+        let delete = CommitOp {
+            path: "gone.md".to_string(),
+            action: RoundAction::Delete,
+            hash: String::new(),
+            from: None,
+            extended: ExtendedMetadata::default(),
+        };
+        assert_eq!(ops[0], delete);
+        assert_eq!(ops[1].action, RoundAction::Write);
+    }
+
+    #[test]
+    fn test_node_round_touches_journal_only_on_commit() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let changes = serde_json::to_string(&vec![remote("a.md", Some(b"one")), remote("b.md", Some(b"two"))]).unwrap();
+        assert_eq!(node.prepare_round(&changes, "phone").unwrap().awaiting.len(), 2);
+        assert!(node.prepare_round(&changes, "tablet").is_err());
+        node.verify_round_content("a.md", b"one").unwrap();
+        assert!(node.commit_round().is_err());
+        assert_eq!(node.change_journal.sequence(), 0);
+
+        node.verify_round_content("b.md", b"two").unwrap();
+        assert_eq!(node.commit_round().unwrap().len(), 2);
+        assert_eq!(node.change_journal.get("b.md").unwrap().hash, hash_content(b"two"));
+        assert_eq!(node.get_sync_round_json(), "null");
+
+        node.prepare_round(&serde_json::to_string(&vec![remote("c.md", Some(b"3"))]).unwrap(), "phone").unwrap();
+        assert_eq!(node.abort_sync_round(), r#"["c.md"]"#);
+        assert!(node.change_journal.get("c.md").is_none());
+    }
+
+    #[test]
+    fn test_rename_commits_as_local_move() {
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        phone.update_file("old.md".to_string(), b"note", 1);
+        phone.update_file("other.md".to_string(), b"other", 1);
+        assert!(phone.rename_file("old.md".to_string(), "new.md".to_string(), 2));
+        phone.rename_file("other.md".to_string(), "elsewhere.md".to_string(), 2);
+        let changes: Vec<FileMetadata> = phone.change_journal.files().filter(|m| m.version > 2).collect();
+        assert_eq!(phone.change_journal.get("new.md").unwrap().moved_from.as_deref(), Some("old.md"));
+
+        // The laptop has old.md with the same content but never had other.md
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        laptop.update_file("old.md".to_string(), b"note", 1);
+        let status = laptop.prepare_round(&serde_json::to_string(&changes).unwrap(), "phone").unwrap();
+        assert_eq!(status.awaiting, vec!["elsewhere.md"]);
+        laptop.verify_round_content("elsewhere.md", b"other").unwrap();
+        let ops = laptop.commit_round().unwrap();
+        let summary: Vec<(&str, RoundAction, Option<&str>)> = ops.iter().map(|op| (op.path.as_str(), op.action, op.from.as_deref())).collect();
+        assert_eq!(summary, vec![("elsewhere.md", RoundAction::Write, None), ("new.md", RoundAction::Move, Some("old.md"))]);
+        assert!(laptop.change_journal.get("old.md").unwrap().is_deleted);
+        assert_eq!(laptop.change_journal.get("new.md").unwrap().moved_from.as_deref(), Some("old.md"));
+    }
+}
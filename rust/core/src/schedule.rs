@@ -0,0 +1,220 @@
+//! Per-peer sync scheduling
+//!
+//! The host used to keep one JS timer per peer. The node now owns the
+//! schedule: each peer syncs at its own interval (the profile's by default),
+//! nothing is due during quiet hours, and peers marked "only when idle" wait
+//! until the user has stopped editing for a while. The host calls `tick(now)`
+//! from a single timer and starts a round with every peer it returns. Nothing
+//! is due while the device is offline or background sync is paused for low
+//! battery (see `conditions`), and peers that keep failing are tried less
+//! often (see `peerstats`).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::clock;
+use crate::debugring::DebugCategory;
+use crate::P2PNode;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// A daily window in local time, in minutes after midnight; wraps past midnight when `end < start`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+impl QuietHours {
+    fn contains(&self, minute: u16) -> bool {
+        if self.start_minute <= self.end_minute {
+            (self.start_minute..self.end_minute).contains(&minute)
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerSchedule {
+    /// Overrides the profile's sync interval for this peer
+    #[serde(default)]
+    pub interval_ms: Option<u64>,
+    /// Only sync once the user has been idle for `idle_after_ms`
+    #[serde(default)]
+    pub only_when_idle: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleRules {
+    /// Keyed by peer device ID; peers without an entry use the defaults
+    #[serde(default)]
+    pub peers: BTreeMap<String, PeerSchedule>,
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietHours>,
+    /// Offset of the user's local time from UTC, for quiet hours
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+    #[serde(default = "default_idle_after_ms")]
+    pub idle_after_ms: u64,
+}
+
+fn default_idle_after_ms() -> u64 {
+    2 * 60_000
+}
+
+impl Default for ScheduleRules {
+    fn default() -> Self {
+        ScheduleRules {
+            peers: BTreeMap::new(),
+            quiet_hours: Vec::new(),
+            utc_offset_minutes: 0,
+            idle_after_ms: default_idle_after_ms(),
+        }
+    }
+}
+
+impl ScheduleRules {
+    pub fn validate(&self) -> Result<(), String> {
+        let bad = |m: u16| m as i64 >= MINUTES_PER_DAY;
+        if self.quiet_hours.iter().any(|q| bad(q.start_minute) || bad(q.end_minute)) {
+            return Err("Quiet hours must be minutes within a day (0-1439)".to_string());
+        }
+        if self.peers.values().any(|p| p.interval_ms == Some(0)) {
+            return Err("Peer sync intervals must be non-zero".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_quiet(&self, now: u64) -> bool {
+        let local = (now / 60_000) as i64 + self.utc_offset_minutes as i64;
+        let minute = local.rem_euclid(MINUTES_PER_DAY) as u16;
+        self.quiet_hours.iter().any(|q| q.contains(minute))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncScheduler {
+    pub rules: ScheduleRules,
+    /// When each peer was last handed out by `tick`
+    last_round: HashMap<String, u64>,
+    last_activity: Option<u64>,
+}
+
+impl SyncScheduler {
+    pub fn note_activity(&mut self, now: u64) {
+        self.last_activity = Some(self.last_activity.map_or(now, |last| last.max(now)));
+    }
+
+    fn is_idle(&self, now: u64) -> bool {
+        self.last_activity.is_none_or(|last| now.saturating_sub(last) >= self.rules.idle_after_ms)
+    }
+
+    /// Peers due at `now`, in ID order; each is not due again until its interval,
+    /// multiplied by `backoff(peer)` while it keeps failing, has passed
+    pub fn tick<'a>(
+        &mut self,
+        now: u64,
+        peers: impl IntoIterator<Item = &'a str>,
+        default_interval_ms: u64,
+        backoff: impl Fn(&str) -> u64,
+    ) -> Vec<String> {
+        if self.rules.is_quiet(now) {
+            return Vec::new();
+        }
+        let mut due: Vec<String> = Vec::new();
+        for peer in peers {
+            let schedule = self.rules.peers.get(peer).cloned().unwrap_or_default();
+            let interval = schedule.interval_ms.unwrap_or(default_interval_ms).saturating_mul(backoff(peer));
+            let elapsed = self.last_round.get(peer).is_none_or(|last| now.saturating_sub(*last) >= interval);
+            if elapsed && (!schedule.only_when_idle || self.is_idle(now)) {
+                due.push(peer.to_string());
+            }
+        }
+        due.sort();
+        due.dedup();
+        for peer in &due {
+            self.last_round.insert(peer.clone(), now);
+        }
+        due
+    }
+}
+
+impl P2PNode {
+    /// Set per-peer intervals, quiet hours and the idle window from JSON
+    /// (`{peers: {device_id: {interval_ms, only_when_idle}}, quiet_hours: [{start_minute, end_minute}], utc_offset_minutes, idle_after_ms}`)
+    pub fn set_sync_schedule(&mut self, rules_json: &str) -> Result<(), String> {
+        let rules: ScheduleRules = serde_json::from_str(rules_json)
+            .map_err(|e| format!("Invalid sync schedule: {}", e))
+            .and_then(|rules: ScheduleRules| rules.validate().map(|_| rules))
+            .map_err(|e| self.record_error(e))?;
+        self.scheduler.rules = rules;
+        Ok(())
+    }
+
+    pub fn get_sync_schedule_json(&self) -> String {
+        serde_json::to_string(&self.scheduler.rules).unwrap_or_default()
+    }
+
+    /// Tell the scheduler the user is editing, for "only when idle" peers
+    pub fn note_user_activity(&mut self, now: u64) {
+        self.scheduler.note_activity(now);
+    }
+
+    /// Advance the schedule and drop expired guest sessions; returns the device IDs
+    /// of peers due for a sync round as JSON
+    pub fn tick(&mut self, now: u64) -> String {
+        self.access.expire(clock::now_ms());
+        serde_json::to_string(&self.due_peers(now)).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn due_peers(&mut self, now: u64) -> Vec<String> {
+        if self.conditions.is_offline() || self.conditions.bulk_paused(&self.profile) {
+            self.debug_event(DebugCategory::Decision, "sync_held", "", None);
+            return Vec::new();
+        }
+        let peers = self.peers.values().map(|p| p.device_id.as_str());
+        let history = &self.peer_history;
+        let due = self.scheduler.tick(now, peers, self.profile.sync_interval_ms, |peer| history.backoff_factor(peer));
+        for peer in &due {
+            self.debug_event(DebugCategory::Decision, "peer_due", peer, None);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60_000;
+
+    #[test]
+    fn test_intervals_quiet_hours_and_idle() {
+        let rules: ScheduleRules = serde_json::from_str(
+            r#"{"peers":{"phone":{"interval_ms":600000},"nas":{"only_when_idle":true}},
+                "quiet_hours":[{"start_minute":1380,"end_minute":420}],"utc_offset_minutes":60}"#,
+        )
+        .unwrap();
+        rules.validate().unwrap();
+        let mut scheduler = SyncScheduler { rules, ..Default::default() };
+        let peers = ["phone", "nas", "tablet"];
+        let noon = 11 * HOUR;
+
+        scheduler.note_activity(noon - 60_000);
+        assert_eq!(scheduler.tick(noon, peers, 60_000, |_| 1), vec!["phone", "tablet"]);
+        assert!(scheduler.tick(noon + 30_000, peers, 60_000, |_| 1).is_empty());
+        assert_eq!(scheduler.tick(noon + 60_000, peers, 60_000, |_| 1), vec!["nas", "tablet"]);
+        assert_eq!(scheduler.tick(noon + 600_000, peers, 60_000, |_| 1), vec!["nas", "phone", "tablet"]);
+
+        // 23:30 local is 22:30 UTC; 07:00 local ends the window
+        assert!(scheduler.tick(22 * HOUR + HOUR / 2, peers, 60_000, |_| 1).is_empty());
+        assert!(scheduler.tick(29 * HOUR + HOUR / 2, peers, 60_000, |_| 1).is_empty());
+        assert_eq!(scheduler.tick(30 * HOUR, peers, 60_000, |_| 1).len(), 3);
+
+        let invalid: ScheduleRules = serde_json::from_str(r#"{"quiet_hours":[{"start_minute":1440,"end_minute":0}]}"#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+}
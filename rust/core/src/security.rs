@@ -0,0 +1,189 @@
+//! Security event log
+//!
+//! Failed signatures, lockouts and the like mean someone may be probing the
+//! mesh. They are kept apart from the issue log, which holds routine problems
+//! and is cleared whenever the user dismisses them, so an audit can still see
+//! every event. The log is bounded like the issue log; the oldest events are
+//! dropped first and the count of dropped events is exported with the rest.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::clock;
+use crate::P2PNode;
+use crate::memory::{deque_bytes, string_bytes, string_map_bytes, MemoryFootprint};
+
+/// Default number of security events retained
+pub const DEFAULT_SECURITY_CAPACITY: usize = 500;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    /// A signature on a frame or bundle did not verify
+    SignatureInvalid,
+    /// A known peer presented a different identity key
+    KeyChanged,
+    /// A message that was already processed arrived again
+    ReplayDetected,
+    /// A source was locked out after too many failed attempts
+    Lockout,
+    /// A peer offered weaker parameters than it previously advertised
+    DowngradeAttempt,
+    /// A peer's signed journal checkpoints contradict each other
+    HistoryRewritten,
+}
+
+impl SecurityEventKind {
+    pub fn parse(name: &str) -> Result<SecurityEventKind, String> {
+        match name {
+            "signature_invalid" => Ok(SecurityEventKind::SignatureInvalid),
+            "key_changed" => Ok(SecurityEventKind::KeyChanged),
+            "replay_detected" => Ok(SecurityEventKind::ReplayDetected),
+            "lockout" => Ok(SecurityEventKind::Lockout),
+            "downgrade_attempt" => Ok(SecurityEventKind::DowngradeAttempt),
+            "history_rewritten" => Ok(SecurityEventKind::HistoryRewritten),
+            other => Err(format!("Unknown security event kind: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SecurityEvent {
+    pub kind: SecurityEventKind,
+    /// Peer, device or address the event is about
+    pub source: String,
+    pub message: String,
+    pub context: BTreeMap<String, String>,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize)]
+struct SecurityExport<'a> {
+    events: &'a VecDeque<SecurityEvent>,
+    dropped: u64,
+}
+
+#[derive(Debug)]
+pub struct SecurityLog {
+    events: VecDeque<SecurityEvent>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl SecurityLog {
+    pub fn new(capacity: usize) -> SecurityLog {
+        SecurityLog { events: VecDeque::new(), capacity: capacity.max(1), dropped: 0 }
+    }
+
+    /// Record an event with `(key, value)` context pairs
+    pub fn record(&mut self, kind: SecurityEventKind, source: &str, message: String, context: &[(&str, &str)]) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(SecurityEvent {
+            kind,
+            source: source.to_string(),
+            message,
+            context: context.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            timestamp: clock::now_ms(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Number of events evicted because the log was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SecurityEvent> {
+        self.events.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.dropped = 0;
+    }
+
+    /// `{events: [...], dropped}` as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&SecurityExport { events: &self.events, dropped: self.dropped }).unwrap_or_default()
+    }
+}
+
+impl Default for SecurityLog {
+    fn default() -> Self {
+        SecurityLog::new(DEFAULT_SECURITY_CAPACITY)
+    }
+}
+
+impl P2PNode {
+    /// Security events as `{events: [{kind, source, message, context, timestamp}], dropped}` JSON
+    pub fn get_security_log_json(&self) -> String {
+        self.security_log.to_json()
+    }
+
+    pub fn get_security_event_count(&self) -> usize {
+        self.security_log.len()
+    }
+
+    /// Record an event the host detected itself (e.g. a replayed transport
+    /// message); `kind` is one of the snake_case event kinds
+    pub fn record_security_event(&mut self, kind: &str, source: &str, message: String) -> Result<(), String> {
+        let kind = SecurityEventKind::parse(kind).map_err(|e| self.record_error(e))?;
+        self.security_log.record(kind, source, message, &[]);
+        Ok(())
+    }
+
+    /// Forget all security events; call after the user has exported them
+    pub fn clear_security_log(&mut self) {
+        self.security_log.clear();
+    }
+}
+
+impl MemoryFootprint for SecurityLog {
+    fn heap_bytes(&self) -> usize {
+        deque_bytes(&self.events, |e| {
+            string_bytes(&e.source) + string_bytes(&e.message) + string_map_bytes(&e.context)
+        })
+    }
+
+    fn trim(&mut self) {
+        self.events.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_and_exported() {
+        let mut log = SecurityLog::new(2);
+        log.record(SecurityEventKind::Lockout, "10.0.0.9", "first".to_string(), &[]);
+        log.record(SecurityEventKind::SignatureInvalid, "desktop", "second".to_string(), &[("frame", "handshake")]);
+        log.record(SecurityEventKind::KeyChanged, "desktop", "third".to_string(), &[]);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.dropped(), 1);
+
+        let export: serde_json::Value = serde_json::from_str(&log.to_json()).unwrap();
+        assert_eq!(export["dropped"], 1);
+        assert_eq!(export["events"][0]["kind"], "signature_invalid");
+        assert_eq!(export["events"][0]["context"]["frame"], "handshake");
+        assert_eq!(export["events"][1]["kind"], "key_changed");
+
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.record_security_event("replay_detected", "desktop", "nonce reused".to_string()).unwrap();
+        assert!(SecurityEventKind::parse("bogus").is_err());
+        assert_eq!(node.get_security_event_count(), 1);
+        node.clear_security_log();
+        assert!(node.security_log.is_empty());
+    }
+}
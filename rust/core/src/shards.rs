@@ -0,0 +1,195 @@
+//! Journal shards
+//!
+//! Entries are grouped by top-level folder; files in the vault root form the
+//! `""` shard. The file table keeps each shard's highest version, entry
+//! counts and an order-independent digest up to date as entries change, so
+//! finding what changed never walks the entries of other shards:
+//!
+//! - Saving: the host keeps the digests from its last save and rewrites only
+//!   the shards whose digest moved (`export_journal_shard`), plus the small
+//!   journal tail. Loading is `load_journal_tail` then `load_journal_shard`
+//!   for each saved shard.
+//! - Syncing: peers exchange `get_journal_shards_json`, and only shards whose
+//!   digests differ need `get_shard_changes_since`, whose output feeds
+//!   `begin_sync_round` like any other changes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sync::ChangeJournal;
+
+use crate::filetable::{shard_of, ShardStats};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShardSummary {
+    pub shard: String,
+    pub sequence: u64,
+    pub file_count: usize,
+    pub deleted_count: usize,
+    /// Hex digest over the shard's entries; equal digests mean equal entries
+    pub digest: String,
+}
+
+impl ShardSummary {
+    fn new(shard: &str, stats: &ShardStats) -> ShardSummary {
+        ShardSummary {
+            shard: shard.to_string(),
+            sequence: stats.sequence,
+            file_count: stats.entries - stats.deleted,
+            deleted_count: stats.deleted,
+            digest: hex::encode(stats.digest),
+        }
+    }
+}
+
+/// One shard saved on its own
+#[derive(Serialize, Deserialize)]
+pub struct JournalShard {
+    pub shard: String,
+    pub digest: String,
+    pub files: Vec<FileMetadata>,
+}
+
+/// Summaries of every non-empty shard, by name
+pub fn summaries(journal: &ChangeJournal) -> Vec<ShardSummary> {
+    let mut out: Vec<ShardSummary> = journal.shards().map(|(shard, stats)| ShardSummary::new(shard, stats)).collect();
+    out.sort_by(|a, b| a.shard.cmp(&b.shard));
+    out
+}
+
+/// Shards whose digest differs between `local` and `remote`, including shards only one side has
+pub fn differing(local: &[ShardSummary], remote: &[ShardSummary]) -> Vec<String> {
+    let digest = |list: &[ShardSummary], shard: &str| list.iter().find(|s| s.shard == shard).map(|s| s.digest.clone());
+    let mut out: Vec<String> = local
+        .iter()
+        .chain(remote)
+        .map(|s| s.shard.clone())
+        .filter(|shard| digest(local, shard) != digest(remote, shard))
+        .collect();
+    out.sort();
+    out.dedup();
+    out
+}
+
+pub fn export(journal: &ChangeJournal, shard: &str) -> JournalShard {
+    let mut files: Vec<FileMetadata> = journal.shard_files(shard).collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let digest = journal.shard(shard).map(|stats| hex::encode(stats.digest)).unwrap_or_default();
+    JournalShard { shard: shard.to_string(), digest, files }
+}
+
+/// Check a saved shard against its digest and replace the journal's entries for it
+pub fn load(journal: &mut ChangeJournal, saved: JournalShard) -> Result<usize, String> {
+    let mut stats = ShardStats::default();
+    for meta in &saved.files {
+        if shard_of(&meta.path) != saved.shard {
+            return Err(format!("{} does not belong to shard {:?}", meta.path, saved.shard));
+        }
+        stats.add(meta);
+    }
+    let expected = if saved.files.is_empty() { String::new() } else { hex::encode(stats.digest) };
+    if expected != saved.digest {
+        return Err(format!("Shard {:?} failed its digest check", saved.shard));
+    }
+    let count = saved.files.len();
+    journal.replace_shard(&saved.shard, saved.files)?;
+    Ok(count)
+}
+
+impl P2PNode {
+    /// `[{shard, sequence, file_count, deleted_count, digest}]` for every non-empty shard
+    pub fn get_journal_shards_json(&self) -> String {
+        serde_json::to_string(&summaries(&self.change_journal)).unwrap_or_default()
+    }
+
+    /// Names of the shards whose digests differ from a peer's `get_journal_shards_json`
+    pub fn diff_journal_shards(&mut self, remote_json: &str) -> Result<String, String> {
+        let remote: Vec<ShardSummary> = match serde_json::from_str(remote_json) {
+            Ok(remote) => remote,
+            Err(e) => return Err(self.record_error(format!("Invalid shard list: {}", e))),
+        };
+        let names = differing(&summaries(&self.change_journal), &remote);
+        serde_json::to_string(&names).map_err(|e| e.to_string())
+    }
+
+    /// Entries of `shard` changed after `since_sequence`, as a JSON array of file metadata
+    pub fn get_shard_changes_since(&self, shard: &str, since_sequence: u64) -> String {
+        let mut changes: Vec<FileMetadata> =
+            self.change_journal.shard_files(shard).filter(|meta| meta.version > since_sequence).collect();
+        changes.sort_by_key(|meta| meta.version);
+        serde_json::to_string(&changes).unwrap_or_default()
+    }
+
+    /// One shard's entries as JSON, for saving it without the rest of the journal
+    pub fn export_journal_shard(&self, shard: &str) -> String {
+        serde_json::to_string(&export(&self.change_journal, shard)).unwrap_or_default()
+    }
+
+    /// Replace one shard's entries with a saved shard; returns the number of entries loaded
+    pub fn load_journal_shard(&mut self, json: &str) -> Result<usize, String> {
+        let result = check_size("Journal shard", json, MAX_JOURNAL_BYTES)
+            .map_err(String::from)
+            .and_then(|_| serde_json::from_str::<JournalShard>(json).map_err(|e| e.to_string()))
+            .and_then(|saved| load(&mut self.change_journal, saved));
+        match result {
+            Ok(count) => {
+                self.wal_sequence = self.change_journal.sequence();
+                Ok(count)
+            }
+            Err(e) => Err(self.record_error(format!("Failed to load journal shard: {}", e))),
+        }
+    }
+
+    /// The journal's sequence counter and backups, without entries
+    pub fn export_journal_tail(&self) -> String {
+        self.change_journal.tail_json()
+    }
+
+    /// Start loading a sharded journal: replaces the journal with the saved tail and no entries
+    pub fn load_journal_tail(&mut self, json: &str) -> Result<(), String> {
+        self.load_journal_state(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(device: &str) -> P2PNode {
+        P2PNode::new(device.to_string(), device.to_string(), 8080)
+    }
+
+    #[test]
+    fn test_shards_save_and_sync_independently() {
+        let mut laptop = node("laptop");
+        laptop.update_file("Daily/2024-01-01.md".to_string(), b"day one", 1);
+        laptop.update_file("Attachments/photo.png".to_string(), b"png", 2);
+        laptop.update_file("Inbox.md".to_string(), b"inbox", 3);
+        let before = summaries(&laptop.change_journal);
+        assert_eq!(before.iter().map(|s| s.shard.as_str()).collect::<Vec<_>>(), vec!["", "Attachments", "Daily"]);
+
+        laptop.update_file("Daily/2024-01-02.md".to_string(), b"day two", 4);
+        let after = summaries(&laptop.change_journal);
+        assert_eq!(differing(&before, &after), vec!["Daily"]);
+        let changes: Vec<FileMetadata> = serde_json::from_str(&laptop.get_shard_changes_since("Daily", 3)).unwrap();
+        assert_eq!(changes.iter().map(|m| m.path.as_str()).collect::<Vec<_>>(), vec!["Daily/2024-01-02.md"]);
+
+        // Saved shard by shard and loaded back into the same journal
+        let mut restored = node("laptop");
+        restored.load_journal_tail(&laptop.export_journal_tail()).unwrap();
+        for summary in &after {
+            restored.load_journal_shard(&laptop.export_journal_shard(&summary.shard)).unwrap();
+        }
+        assert_eq!(restored.get_journal_shards_json(), laptop.get_journal_shards_json());
+        assert_eq!(restored.change_journal.sequence(), 4);
+        assert_eq!(restored.diff_journal_shards(&laptop.get_journal_shards_json()).unwrap(), "[]");
+
+        let mut tampered: JournalShard = serde_json::from_str(&laptop.export_journal_shard("Daily")).unwrap();
+        tampered.files[0].version = 9;
+        assert!(restored.load_journal_shard(&serde_json::to_string(&tampered).unwrap()).is_err());
+        tampered.files[0].path = "Other/x.md".to_string();
+        assert!(restored.load_journal_shard(&serde_json::to_string(&tampered).unwrap()).is_err());
+    }
+}
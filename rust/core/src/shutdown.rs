@@ -0,0 +1,169 @@
+//! Graceful shutdown
+//!
+//! Obsidian gives a plugin only milliseconds on unload, not enough to save
+//! the full journal. `shutdown` does the minimum synchronously: an open
+//! transaction is rolled back (it never completed), queued vault events and
+//! debounced changes are committed, a staged sync round is dropped, and the
+//! state that would be expensive to lose goes into one compressed blob — the
+//! WAL records not yet taken, tracked transfers (so they resume instead of
+//! restarting) and the bootstrap watermarks. The host writes the blob wherever it can fastest.
+//!
+//! On the next start the host loads the last checkpoint as usual and passes
+//! the blob to `resume_after_shutdown`. The blob records that the previous
+//! session closed cleanly, which the host can use to skip crash recovery
+//! work such as an orphan scan. Transfer resumption secrets go into the blob
+//! only wrapped under a key the host supplies and keeps apart from it (e.g.
+//! in the OS keychain); without one, tracked transfers are left out and
+//! restart from the first chunk.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::compression;
+use crate::firstsync::BootstrapState;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::resume::SavedTransfer;
+use crate::P2PNode;
+
+const SHUTDOWN_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ShutdownState {
+    format_version: u32,
+    device_id: String,
+    closed_at: u64,
+    /// WAL lines written since the host last took them
+    wal: String,
+    /// Empty unless the host supplied a wrapping key
+    transfers: Vec<SavedTransfer>,
+    bootstrap: BootstrapState,
+}
+
+/// What `resume_after_shutdown` restored
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct ResumeReport {
+    /// The previous session ended through `shutdown` rather than a crash
+    pub clean_shutdown: bool,
+    pub closed_at: Option<u64>,
+    pub wal_records_applied: usize,
+    pub transfers_restored: usize,
+}
+
+impl P2PNode {
+    /// Finish pending journal work and return the state to persist before unloading,
+    /// as a base64 blob for `resume_after_shutdown`. Tracked transfers are kept only
+    /// with a 32-byte `wrapping_key_b64`, which their secrets are wrapped under
+    pub fn shutdown(&mut self, wrapping_key_b64: Option<String>) -> Result<String, String> {
+        self.shutdown_blob(wrapping_key_b64.as_deref()).map_err(|e| self.record_error(e))
+    }
+
+    /// Restore a blob from `shutdown` over the loaded journal checkpoint; an empty blob
+    /// (nothing was saved, e.g. after a crash) restores nothing. Pass the key given to
+    /// `shutdown` to restore tracked transfers; without it they are skipped.
+    /// Returns `{clean_shutdown, closed_at, wal_records_applied, transfers_restored}` as JSON
+    pub fn resume_after_shutdown(&mut self, blob: &str, wrapping_key_b64: Option<String>) -> Result<String, String> {
+        let report = self.resume_from(blob, wrapping_key_b64.as_deref()).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&report).map_err(|e| e.to_string())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn shutdown_blob(&mut self, wrapping_key_b64: Option<&str>) -> Result<String, String> {
+        if self.change_journal.in_transaction() {
+            self.close_transaction(false)?;
+        }
+        self.flush_vault_events_at(None);
+        self.flush_all_debounced();
+        self.sync_round = None;
+        let state = ShutdownState {
+            format_version: SHUTDOWN_FORMAT_VERSION,
+            device_id: self.device_id.clone(),
+            closed_at: clock::now_ms(),
+            wal: self.wal_records()?,
+            transfers: match wrapping_key_b64 {
+                Some(key) => self.resumable.save(key)?,
+                None => Vec::new(),
+            },
+            bootstrap: self.first_sync.clone(),
+        };
+        let json = serde_json::to_vec(&state).map_err(|e| e.to_string())?;
+        Ok(BASE64.encode(compression::compress(&json)))
+    }
+
+    pub(crate) fn resume_from(&mut self, blob: &str, wrapping_key_b64: Option<&str>) -> Result<ResumeReport, String> {
+        if blob.trim().is_empty() {
+            return Ok(ResumeReport::default());
+        }
+        check_size("Shutdown state", blob, MAX_JOURNAL_BYTES)?;
+        let compressed = BASE64.decode(blob.trim()).map_err(|e| format!("Invalid shutdown state: {}", e))?;
+        let json = compression::decompress(&compressed, MAX_JOURNAL_BYTES)?;
+        let state: ShutdownState = serde_json::from_slice(&json).map_err(|e| format!("Invalid shutdown state: {}", e))?;
+        if state.format_version != SHUTDOWN_FORMAT_VERSION {
+            return Err(format!("Unsupported shutdown state format {}", state.format_version));
+        }
+        if state.device_id != self.device_id {
+            return Err(format!("Shutdown state belongs to {}", state.device_id));
+        }
+        // Transfers first: a wrong key then fails before the journal is touched
+        let transfers_restored = match wrapping_key_b64 {
+            Some(key) => self.resumable.restore(state.transfers, key)?,
+            None => 0,
+        };
+        let wal_records_applied = self.apply_wal(&state.wal)?;
+        self.first_sync = state.bootstrap;
+        Ok(ResumeReport { clean_shutdown: true, closed_at: Some(state.closed_at), wal_records_applied, transfers_restored })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_blob_restores_pending_state() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"saved", 1);
+        let checkpoint = node.get_journal_state();
+        node.wal_records().unwrap();
+
+        node.update_file("b.md".to_string(), b"after checkpoint", 2);
+        node.set_debounce_rules(r#"[{"pattern":"*.md","interval_ms":60000}]"#).unwrap();
+        node.update_file("c.md".to_string(), b"still debounced", 3);
+        node.open_transaction().unwrap();
+        node.update_file("d.md".to_string(), b"half done", 4);
+        let key = BASE64.encode([7u8; 32]);
+        let transfer = node.track_transfer("phone", None, "big.pdf".to_string(), "h".repeat(64), 10, &key).unwrap();
+        node.record_verified_chunk(&transfer, 0).unwrap();
+
+        let wrapping_key = BASE64.encode([9u8; 32]);
+        let blob = node.shutdown(Some(wrapping_key.clone())).unwrap();
+        assert!(node.change_journal.get("d.md").is_none());
+
+        let mut restarted = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        restarted.load_journal_state(&checkpoint).unwrap();
+        assert!(restarted.resume_from(&blob, Some(&BASE64.encode([8u8; 32]))).is_err());
+        let report = restarted.resume_from(&blob, Some(&wrapping_key)).unwrap();
+        assert!(report.clean_shutdown);
+        assert_eq!((report.wal_records_applied, report.transfers_restored), (2, 1));
+        assert!(restarted.change_journal.get("c.md").is_some());
+        assert_eq!(restarted.get_resumable_transfers_json(), node.get_resumable_transfers_json());
+
+        assert_eq!(restarted.resume_from("", None).unwrap(), ResumeReport::default());
+        let mut other = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        assert!(other.resume_from(&blob, Some(&wrapping_key)).is_err());
+    }
+
+    #[test]
+    fn test_shutdown_without_key_leaves_transfers_out() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let key = BASE64.encode([7u8; 32]);
+        node.track_transfer("phone", None, "big.pdf".to_string(), "h".repeat(64), 10, &key).unwrap();
+        let blob = node.shutdown(None).unwrap();
+
+        let mut restarted = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let report = restarted.resume_from(&blob, Some(&BASE64.encode([9u8; 32]))).unwrap();
+        assert_eq!(report.transfers_restored, 0);
+        assert!(restarted.resumable.is_empty());
+    }
+}
@@ -0,0 +1,605 @@
+//! Deterministic multi-node simulation
+//!
+//! Wires several `P2PNode`s together through an in-memory network with
+//! configurable loss, latency and partitions, driven by a scripted clock.
+//! Everything random comes from a seeded generator, so a failing scenario
+//! reproduces exactly from its seed.
+//!
+//! Each node has an in-memory vault and the simulation plays the host's part
+//! of the sync exchange: every `request_sync` a node asks each peer for its
+//! journal entries past what it has committed, stages the ones it should
+//! apply as a round, fetches the bodies the round awaits and commits once
+//! they have all verified. Concurrent edits are settled the same way on both
+//! sides (the later edit keeps the path, the other moves to a conflict copy)
+//! and announced to the peer. A round or fetch that lost a message is
+//! dropped and asked for again on the next `request_sync`, so nodes converge
+//! once the network lets enough through.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::clock;
+use crate::conflicts::{conflict_copy_path, Resolution};
+use crate::preview;
+use crate::round::{CommitOp, RoundAction};
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+/// Time a round may wait for its bodies before `request_sync` drops it
+pub const ROUND_TIMEOUT_MS: u64 = 2_000;
+
+/// Network behavior for a simulation run
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub seed: u64,
+    /// Probability in [0, 1] that a message is dropped
+    pub loss_rate: f64,
+    pub min_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub start_time_ms: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 1,
+            loss_rate: 0.0,
+            min_latency_ms: 1,
+            max_latency_ms: 10,
+            start_time_ms: 1_000_000,
+        }
+    }
+}
+
+/// Messages the simulated network can carry
+#[derive(Clone, Debug)]
+pub enum SimMessage {
+    Announcement(String),
+    /// Ask for the journal entries recorded after `since`
+    ManifestRequest { since: u64 },
+    /// Entries past the requested sequence, and the sender's sequence
+    Manifest { sequence: u64, entries: Vec<FileMetadata> },
+    /// Ask for a body by hash, to be written to `path`
+    ContentRequest { path: String, hash: String },
+    Content { path: String, hash: String, body: Vec<u8> },
+    /// The sender found its version of a file in conflict with the receiver's
+    Conflict(FileMetadata),
+}
+
+/// Counters describing what the network did
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub blocked_by_partition: u64,
+}
+
+struct Envelope {
+    from: usize,
+    to: usize,
+    message: SimMessage,
+}
+
+/// A round waiting for its bodies
+struct OpenRound {
+    peer: usize,
+    /// Sequence the peer advertised in its manifest
+    sequence: u64,
+    started: u64,
+    /// Hash of every body the round awaits, by path
+    hashes: BTreeMap<String, String>,
+}
+
+/// What the host keeps for each node
+#[derive(Default)]
+struct Host {
+    vault: BTreeMap<String, Vec<u8>>,
+    /// Verified bodies of the open round, by path
+    staged: BTreeMap<String, Vec<u8>>,
+    round: Option<OpenRound>,
+    /// Journal sequence of each peer committed here
+    reconciled: BTreeMap<usize, u64>,
+    /// Bodies wanted outside a round (for settled conflicts), path to hash
+    fetches: BTreeMap<String, String>,
+}
+
+/// SplitMix64: tiny, fast and good enough for reproducible scheduling
+pub(crate) struct SimRng(u64);
+
+impl SimRng {
+    pub(crate) fn new(seed: u64) -> SimRng {
+        SimRng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        min + self.next_u64() % (max - min + 1)
+    }
+}
+
+pub struct Simulation {
+    config: SimConfig,
+    nodes: Vec<P2PNode>,
+    hosts: Vec<Host>,
+    now: u64,
+    rng: SimRng,
+    next_seq: u64,
+    in_flight: BTreeMap<(u64, u64), Envelope>,
+    partitions: HashSet<(usize, usize)>,
+    stats: SimStats,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Simulation {
+        clock::set_clock_time(config.start_time_ms);
+        Simulation {
+            now: config.start_time_ms,
+            rng: SimRng::new(config.seed),
+            config,
+            nodes: Vec::new(),
+            hosts: Vec::new(),
+            next_seq: 0,
+            in_flight: BTreeMap::new(),
+            partitions: HashSet::new(),
+            stats: SimStats::default(),
+        }
+    }
+
+    /// Add a node and return its index
+    pub fn add_node(&mut self, device_name: &str) -> usize {
+        let index = self.nodes.len();
+        let node = P2PNode::new(
+            device_name.to_string(),
+            format!("sim-device-{}", index),
+            8000 + index as u16,
+        );
+        self.nodes.push(node);
+        self.hosts.push(Host::default());
+        index
+    }
+
+    pub fn node(&self, index: usize) -> &P2PNode {
+        &self.nodes[index]
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut P2PNode {
+        &mut self.nodes[index]
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.stats
+    }
+
+    pub fn vault(&self, index: usize) -> &BTreeMap<String, Vec<u8>> {
+        &self.hosts[index].vault
+    }
+
+    /// Write a file into a node's vault and journal, as a local edit
+    pub fn write(&mut self, index: usize, path: &str, content: &[u8]) {
+        self.hosts[index].vault.insert(path.to_string(), content.to_vec());
+        self.nodes[index].update_file(path.to_string(), content, self.now);
+    }
+
+    pub fn delete(&mut self, index: usize, path: &str) {
+        self.hosts[index].vault.remove(path);
+        self.nodes[index].mark_file_deleted(path.to_string(), self.now);
+    }
+
+    /// Simulated address of a node, as seen by its peers
+    pub fn address_of(index: usize) -> String {
+        format!("10.0.0.{}", index + 1)
+    }
+
+    /// Queue a message, applying partitions, loss and latency
+    pub fn send(&mut self, from: usize, to: usize, message: SimMessage) {
+        self.stats.sent += 1;
+        if self.partitions.contains(&(from, to)) {
+            self.stats.blocked_by_partition += 1;
+            return;
+        }
+        if self.rng.next_f64() < self.config.loss_rate {
+            self.stats.dropped += 1;
+            return;
+        }
+        let latency = self.rng.range(self.config.min_latency_ms, self.config.max_latency_ms);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.in_flight.insert((self.now + latency, seq), Envelope { from, to, message });
+    }
+
+    /// Every node announces itself to every other node
+    pub fn broadcast_announcements(&mut self) {
+        for from in 0..self.nodes.len() {
+            let json = self.nodes[from].get_announcement_json();
+            for to in 0..self.nodes.len() {
+                if to != from {
+                    self.send(from, to, SimMessage::Announcement(json.clone()));
+                }
+            }
+        }
+    }
+
+    /// Every node asks every other node for its new entries; rounds that timed out
+    /// are dropped and outstanding fetches asked for again
+    pub fn request_sync(&mut self) {
+        for index in 0..self.nodes.len() {
+            if self.hosts[index].round.as_ref().is_some_and(|round| self.now >= round.started + ROUND_TIMEOUT_MS) {
+                self.nodes[index].abort_sync_round();
+                self.hosts[index].round = None;
+                self.hosts[index].staged.clear();
+            }
+            // Bodies whose request or reply was lost are asked for again
+            if let Some(round) = &self.hosts[index].round {
+                let peer = round.peer;
+                let awaiting: Vec<(String, String)> = round
+                    .hashes
+                    .iter()
+                    .filter(|(path, _)| !self.hosts[index].staged.contains_key(*path))
+                    .map(|(path, hash)| (path.clone(), hash.clone()))
+                    .collect();
+                for (path, hash) in awaiting {
+                    self.send(index, peer, SimMessage::ContentRequest { path, hash });
+                }
+            }
+            let fetches: Vec<(String, String)> = self.hosts[index].fetches.clone().into_iter().collect();
+            for peer in (0..self.nodes.len()).filter(|&peer| peer != index) {
+                let since = self.hosts[index].reconciled.get(&peer).copied().unwrap_or(0);
+                self.send(index, peer, SimMessage::ManifestRequest { since });
+                for (path, hash) in &fetches {
+                    self.send(index, peer, SimMessage::ContentRequest { path: path.clone(), hash: hash.clone() });
+                }
+            }
+        }
+    }
+
+    /// Sync every `interval_ms` until the nodes converge; false if they haven't after `rounds`
+    pub fn sync_until_converged(&mut self, rounds: usize, interval_ms: u64) -> bool {
+        for _ in 0..rounds {
+            self.request_sync();
+            self.advance(interval_ms);
+            if self.is_converged() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether no exchange is pending and every node holds the same files, matching its journal
+    pub fn is_converged(&self) -> bool {
+        let settled = self.hosts.iter().all(|host| host.round.is_none() && host.fetches.is_empty());
+        let matches_journal = self.nodes.iter().zip(&self.hosts).all(|(node, host)| {
+            let live: BTreeMap<String, String> =
+                node.change_journal.files().filter(|m| !m.is_deleted).map(|m| (m.path, m.hash)).collect();
+            live.len() == host.vault.len()
+                && host.vault.iter().all(|(path, body)| {
+                    live.get(path) == Some(&node.change_journal.content_hash(path, body))
+                })
+        });
+        settled && matches_journal && self.hosts.windows(2).all(|pair| pair[0].vault == pair[1].vault)
+    }
+
+    /// Cut all links between two groups of nodes (both directions)
+    pub fn partition(&mut self, group_a: &[usize], group_b: &[usize]) {
+        for &a in group_a {
+            for &b in group_b {
+                self.partitions.insert((a, b));
+                self.partitions.insert((b, a));
+            }
+        }
+    }
+
+    /// Remove all partitions
+    pub fn heal(&mut self) {
+        self.partitions.clear();
+    }
+
+    /// Advance the clock, delivering every message that falls due in order
+    pub fn advance(&mut self, delta_ms: u64) {
+        let target = self.now + delta_ms;
+        while let Some(entry) = self.in_flight.first_entry() {
+            let (deliver_at, _) = *entry.key();
+            if deliver_at > target {
+                break;
+            }
+            let envelope = entry.remove();
+            self.set_now(deliver_at);
+            self.deliver(envelope);
+        }
+        self.set_now(target);
+    }
+
+    /// Deliver everything currently in flight
+    pub fn run_until_idle(&mut self) {
+        while let Some((&(deliver_at, _), _)) = self.in_flight.iter().next() {
+            self.advance(deliver_at.saturating_sub(self.now));
+        }
+    }
+
+    fn set_now(&mut self, now: u64) {
+        self.now = now;
+        clock::set_clock_time(now);
+    }
+
+    fn deliver(&mut self, envelope: Envelope) {
+        self.stats.delivered += 1;
+        let (from, to) = (envelope.from, envelope.to);
+        let sender_ip = Self::address_of(from);
+        match envelope.message {
+            SimMessage::Announcement(json) => {
+                let _ = self.nodes[to].apply_announcement(&json, &sender_ip, self.now);
+            }
+            SimMessage::ManifestRequest { since } => {
+                let journal = &self.nodes[to].change_journal;
+                let mut entries: Vec<FileMetadata> = journal.files().filter(|m| m.version > since).collect();
+                entries.sort_by_key(|m| m.version);
+                let sequence = journal.sequence();
+                self.send(to, from, SimMessage::Manifest { sequence, entries });
+            }
+            SimMessage::Manifest { sequence, entries } => self.receive_manifest(to, from, sequence, entries),
+            SimMessage::ContentRequest { path, hash } => {
+                let node = &self.nodes[to];
+                let vault = &self.hosts[to].vault;
+                let body = vault
+                    .get(&path)
+                    .filter(|body| node.change_journal.content_hash(&path, body) == hash)
+                    .or_else(|| vault.iter().find(|(p, body)| node.change_journal.content_hash(p, body) == hash).map(|(_, b)| b));
+                if let Some(body) = body.cloned() {
+                    self.send(to, from, SimMessage::Content { path, hash, body });
+                }
+            }
+            SimMessage::Content { path, hash, body } => self.receive_content(to, path, hash, body),
+            SimMessage::Conflict(remote) => self.settle_conflicts(to, from, vec![remote]),
+        }
+    }
+
+    /// Stage the entries `index` should apply as a round and ask for the bodies it awaits
+    fn receive_manifest(&mut self, index: usize, peer: usize, sequence: u64, entries: Vec<FileMetadata>) {
+        // One round at a time; this peer is asked again on the next `request_sync`
+        if self.hosts[index].round.is_some() {
+            return;
+        }
+        let node = &self.nodes[index];
+        let Ok(plan) = preview::plan_preview(&node.change_journal, &node.policy, entries.clone(), &None) else {
+            return;
+        };
+        let conflicted: HashSet<String> = plan.conflict.into_iter().map(|item| item.path).collect();
+        let apply: HashSet<String> =
+            plan.pull.into_iter().chain(plan.overwrite).chain(plan.delete).map(|item| item.path).collect();
+        let (conflicts, entries): (Vec<FileMetadata>, Vec<FileMetadata>) =
+            entries.into_iter().partition(|m| conflicted.contains(&m.path));
+        self.settle_conflicts(index, peer, conflicts);
+
+        // Changes we are newer than stay out of the round; the peer pulls them from us
+        let changes: Vec<&FileMetadata> = entries.iter().filter(|m| apply.contains(&m.path)).collect();
+        let changes_json = serde_json::to_string(&changes).unwrap_or_default();
+        let peer_id = self.nodes[peer].device_id.clone();
+        let Ok(status) = self.nodes[index].prepare_round(&changes_json, &peer_id) else {
+            return;
+        };
+        let hashes: BTreeMap<String, String> = status
+            .awaiting
+            .into_iter()
+            .map(|path| {
+                let hash = entries.iter().find(|m| m.path == path).map(|m| m.hash.clone()).unwrap_or_default();
+                (path, hash)
+            })
+            .collect();
+        for (path, hash) in &hashes {
+            self.send(index, peer, SimMessage::ContentRequest { path: path.clone(), hash: hash.clone() });
+        }
+        self.hosts[index].round = Some(OpenRound { peer, sequence, started: self.now, hashes });
+        self.try_commit(index);
+    }
+
+    fn receive_content(&mut self, index: usize, path: String, hash: String, body: Vec<u8>) {
+        let host = &mut self.hosts[index];
+        let node = &mut self.nodes[index];
+        if let Some(round) = node.sync_round.as_mut() {
+            if round.status().awaiting.contains(&path) && round.verify_content(&path, &body).is_ok() {
+                host.staged.insert(path, body);
+                self.try_commit(index);
+                return;
+            }
+        }
+        if host.fetches.get(&path) == Some(&hash) && node.change_journal.content_hash(&path, &body) == hash {
+            host.fetches.remove(&path);
+            host.vault.insert(path, body);
+        }
+    }
+
+    fn try_commit(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        if !node.sync_round.as_ref().is_some_and(|round| round.status().awaiting.is_empty()) {
+            return;
+        }
+        let Ok(ops) = node.commit_round() else {
+            return;
+        };
+        let host = &mut self.hosts[index];
+        if let Some(round) = host.round.take() {
+            host.reconciled.insert(round.peer, round.sequence);
+        }
+        apply_ops(host, &ops);
+        host.staged.clear();
+    }
+
+    /// Settle each of `peer`'s versions found in conflict with ours: the later edit
+    /// (by mtime, then device id) keeps the path and the other moves to a conflict copy
+    /// named after its device, so both sides end up with the same two files
+    fn settle_conflicts(&mut self, index: usize, peer: usize, conflicts: Vec<FileMetadata>) {
+        for remote in conflicts {
+            let node = &mut self.nodes[index];
+            // A notice may arrive after the conflict was settled from a manifest
+            let Ok(plan) = preview::plan_preview(&node.change_journal, &node.policy, vec![remote.clone()], &None) else {
+                continue;
+            };
+            let Some(local) = node.change_journal.get(&remote.path).filter(|_| !plan.conflict.is_empty()) else {
+                continue;
+            };
+            let Ok(id) = node.queue_conflict(remote.clone(), None) else {
+                continue;
+            };
+            let host = &mut self.hosts[index];
+            let resolution = if (remote.mtime, &remote.last_modified_by) > (local.mtime, &local.last_modified_by) {
+                if let Some(body) = host.vault.get(&remote.path).cloned() {
+                    let copy = conflict_copy_path(&remote.path, &node.device_id);
+                    node.update_file(copy.clone(), &body, local.mtime);
+                    host.vault.insert(copy, body);
+                }
+                Resolution::KeepRemote
+            } else if remote.is_deleted {
+                Resolution::KeepLocal
+            } else {
+                Resolution::KeepBoth
+            };
+            let Ok(outcome) = node.apply_resolution(&id, resolution) else {
+                continue;
+            };
+            if let (Some(path), Some(hash)) = (outcome.fetch_remote_to, outcome.remote_hash) {
+                host.fetches.insert(path.clone(), hash.clone());
+                self.send(index, peer, SimMessage::ContentRequest { path, hash });
+            }
+            self.send(index, peer, SimMessage::Conflict(local));
+        }
+    }
+}
+
+/// Carry out a committed round's operations on the host's vault
+fn apply_ops(host: &mut Host, ops: &[CommitOp]) {
+    for op in ops {
+        match op.action {
+            RoundAction::Write => {
+                if let Some(body) = host.staged.remove(&op.path) {
+                    host.vault.insert(op.path.clone(), body);
+                }
+            }
+            RoundAction::Delete => {
+                host.vault.remove(&op.path);
+            }
+            RoundAction::Move => {
+                if let Some(body) = op.from.as_ref().and_then(|from| host.vault.remove(from)) {
+                    host.vault.insert(op.path.clone(), body);
+                }
+            }
+            RoundAction::Metadata | RoundAction::Placeholder => {}
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        clock::use_system_clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(config: SimConfig, size: usize) -> Simulation {
+        let mut sim = Simulation::new(config);
+        for i in 0..size {
+            sim.add_node(&format!("Device {}", i));
+        }
+        sim
+    }
+
+    #[test]
+    fn test_discovery_converges_over_lossy_network() {
+        let mut sim = mesh(SimConfig { loss_rate: 0.3, ..Default::default() }, 4);
+        for _ in 0..20 {
+            sim.broadcast_announcements();
+            sim.advance(1000);
+        }
+        for i in 0..sim.node_count() {
+            assert_eq!(sim.node(i).get_peer_count(), 3);
+        }
+        assert!(sim.stats().dropped > 0);
+    }
+
+    #[test]
+    fn test_same_seed_reproduces_run() {
+        let run = |seed| {
+            let mut sim = mesh(SimConfig { seed, loss_rate: 0.5, ..Default::default() }, 3);
+            sim.broadcast_announcements();
+            sim.run_until_idle();
+            sim.stats().clone()
+        };
+        assert_eq!(run(42), run(42));
+    }
+
+    #[test]
+    fn test_vaults_converge_after_partition_with_concurrent_edit() {
+        let run = |seed| {
+            let mut sim = mesh(SimConfig { seed, loss_rate: 0.2, ..Default::default() }, 2);
+            sim.write(0, "shared.md", b"base");
+            sim.write(1, "notes/b.md", b"from b");
+            assert!(sim.sync_until_converged(50, 100));
+            assert_eq!(sim.vault(1).get("shared.md").map(Vec::as_slice), Some(&b"base"[..]));
+
+            sim.partition(&[0], &[1]);
+            sim.write(0, "shared.md", b"edit on 0");
+            sim.write(0, "notes/a.md", b"from a");
+            sim.advance(500);
+            sim.write(1, "shared.md", b"edit on 1");
+            sim.delete(1, "notes/b.md");
+            assert!(!sim.sync_until_converged(5, 100));
+            assert!(sim.stats().blocked_by_partition > 0);
+
+            sim.heal();
+            assert!(sim.sync_until_converged(100, 100));
+            (sim.vault(0).clone(), sim.stats().clone(), sim.node(0).get_conflict_count())
+        };
+        let (vault, stats, unresolved) = run(11);
+        // The later edit keeps the path; the other is kept beside it
+        let expected: BTreeMap<String, Vec<u8>> = [
+            ("notes/a.md", &b"from a"[..]),
+            ("shared (conflict sim-device-0).md", b"edit on 0"),
+            ("shared.md", b"edit on 1"),
+        ]
+        .into_iter()
+        .map(|(path, body)| (path.to_string(), body.to_vec()))
+        .collect();
+        assert_eq!(vault, expected);
+        assert_eq!(unresolved, 0);
+        assert!(stats.dropped > 0);
+        assert_eq!(run(11).1, stats);
+    }
+
+    #[test]
+    fn test_partition_blocks_until_healed() {
+        let mut sim = mesh(SimConfig::default(), 3);
+        sim.partition(&[0], &[1, 2]);
+        sim.broadcast_announcements();
+        sim.run_until_idle();
+        assert_eq!(sim.node(0).get_peer_count(), 0);
+        assert_eq!(sim.node(1).get_peer_count(), 1);
+
+        sim.heal();
+        sim.broadcast_announcements();
+        sim.run_until_idle();
+        assert_eq!(sim.node(0).get_peer_count(), 2);
+    }
+}
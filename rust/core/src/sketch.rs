@@ -0,0 +1,122 @@
+//! Journal divergence sketches
+//!
+//! Before two peers exchange manifests they compare a 256-byte odd sketch of
+//! their journals: every current entry toggles one bit chosen by hashing its
+//! path and content hash. Identical journals give identical sketches, so a
+//! round where nothing changed costs one small message, and the number of
+//! differing bits estimates how many entries differ. Version numbers are
+//! local to each journal, so entries are keyed by content, not by version.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::sync::FileMetadata;
+
+/// Sketch size in bits
+pub const SKETCH_BITS: usize = 2048;
+const SKETCH_BYTES: usize = SKETCH_BITS / 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalSketch {
+    bits: [u8; SKETCH_BYTES],
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct SketchComparison {
+    pub identical: bool,
+    /// Estimated entries present on one side only (a change counts twice)
+    pub estimated_differences: u64,
+}
+
+impl JournalSketch {
+    pub fn new() -> JournalSketch {
+        JournalSketch { bits: [0; SKETCH_BYTES] }
+    }
+
+    pub fn from_entries(entries: impl IntoIterator<Item = FileMetadata>) -> JournalSketch {
+        let mut sketch = JournalSketch::new();
+        for meta in entries {
+            sketch.toggle(&meta);
+        }
+        sketch
+    }
+
+    pub fn toggle(&mut self, meta: &FileMetadata) {
+        let mut hasher = Sha256::new();
+        hasher.update(meta.path.as_bytes());
+        hasher.update([0, meta.is_deleted as u8]);
+        hasher.update(meta.hash.as_bytes());
+        let digest = hasher.finalize();
+        let bit = u64::from_le_bytes(digest[..8].try_into().unwrap_or_default()) as usize % SKETCH_BITS;
+        self.bits[bit / 8] ^= 1 << (bit % 8);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits.to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<JournalSketch, Error> {
+        let bits = bytes
+            .try_into()
+            .map_err(|_| format!("Journal sketch must be {} bytes, got {}", SKETCH_BYTES, bytes.len()))?;
+        Ok(JournalSketch { bits })
+    }
+
+    pub fn compare(&self, other: &JournalSketch) -> SketchComparison {
+        let differing: u32 = self.bits.iter().zip(other.bits.iter()).map(|(a, b)| (a ^ b).count_ones()).sum();
+        // Each differing entry flips a random bit, so the set bits of the XOR follow
+        // E[z] = m/2 * (1 - (1 - 2/m)^d); invert for d
+        let m = SKETCH_BITS as f64;
+        let fraction = (2.0 * differing as f64 / m).min(0.999);
+        let estimate = (-(m / 2.0) * (1.0 - fraction).ln()).round() as u64;
+        SketchComparison { identical: differing == 0, estimated_differences: estimate.max(differing as u64) }
+    }
+}
+
+impl Default for JournalSketch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(i: usize, hash: &str) -> FileMetadata {
+        FileMetadata {
+            path: format!("note-{}.md", i),
+            hash: hash.to_string(),
+            mtime: 0,
+            size: 0,
+            version: i as u64,
+            is_deleted: false,
+            last_modified_by: String::new(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_identical_and_diverged_journals() {
+        let ours: Vec<FileMetadata> = (0..1000).map(|i| meta(i, "a")).collect();
+        // Same entries recorded in another order, with other local version numbers
+        let mut theirs: Vec<FileMetadata> = ours.iter().rev().cloned().collect();
+        for m in &mut theirs {
+            m.version += 7;
+        }
+        let a = JournalSketch::from_entries(ours.clone());
+        assert!(a.compare(&JournalSketch::from_entries(theirs.clone())).identical);
+
+        for m in theirs.iter_mut().take(20) {
+            m.hash = "b".to_string();
+        }
+        let comparison = a.compare(&JournalSketch::from_entries(theirs.clone()));
+        assert!(!comparison.identical);
+        assert!((30..=50).contains(&comparison.estimated_differences), "{:?}", comparison);
+
+        assert_eq!(JournalSketch::from_bytes(&a.to_bytes()).unwrap(), a);
+        assert!(JournalSketch::from_bytes(&[0; 3]).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Structured node status for direct UI binding
+
+use serde::Serialize;
+
+use crate::clock;
+use crate::P2PNode;
+
+/// Peers not heard from within this window are reported as stale
+pub const PEER_STALE_AFTER_MS: u64 = 30_000;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NodeStatus {
+    is_discovering: bool,
+    peer_count: usize,
+    active_peer_count: usize,
+    stale_peer_count: usize,
+    journal_sequence: u64,
+    file_count: usize,
+    deleted_count: usize,
+    last_error: Option<String>,
+}
+
+impl NodeStatus {
+    pub fn get_is_discovering(&self) -> bool {
+        self.is_discovering
+    }
+
+    pub fn get_peer_count(&self) -> usize {
+        self.peer_count
+    }
+
+    pub fn get_active_peer_count(&self) -> usize {
+        self.active_peer_count
+    }
+
+    pub fn get_stale_peer_count(&self) -> usize {
+        self.stale_peer_count
+    }
+
+    pub fn get_journal_sequence(&self) -> u64 {
+        self.journal_sequence
+    }
+
+    pub fn get_file_count(&self) -> usize {
+        self.file_count
+    }
+
+    pub fn get_deleted_count(&self) -> usize {
+        self.deleted_count
+    }
+
+    pub fn get_last_error(&self) -> Option<String> {
+        self.last_error.clone()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn build_status(&self) -> NodeStatus {
+        let now = clock::now_ms();
+        let active_peer_count = self
+            .peers
+            .values()
+            .filter(|peer| now.saturating_sub(peer.last_seen_timestamp) < PEER_STALE_AFTER_MS)
+            .count();
+        let head = self.change_journal.head();
+
+        NodeStatus {
+            is_discovering: self.is_discovering,
+            peer_count: self.peers.len(),
+            active_peer_count,
+            stale_peer_count: self.peers.len() - active_peer_count,
+            journal_sequence: head.sequence,
+            file_count: head.file_count,
+            deleted_count: head.deleted_count,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Multi-stream transfers
+//!
+//! A large attachment sent over one ordered channel stalls behind every lost
+//! packet on it. A transfer can instead be spread over several channels: the
+//! sender keeps a `MultiStreamPlan`, each idle stream asks it for the next
+//! run of chunks, and every chunk frame carries the ID of the stream it went
+//! out on. A stream that closes hands its unsent chunks back to be sent on
+//! another. The receiving `IncomingTransfer` merges the streams with its
+//! reordering buffer; the plan never hands out chunks further ahead of the
+//! slowest stream than `reorder_window`, so a buffer of that size never
+//! overflows.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::limits::{check_size, MAX_CHUNK_JSON_BYTES};
+use crate::transfer::{FileChunk, IncomingTransfer};
+use crate::wire;
+
+/// Most streams one transfer can use
+pub const MAX_STREAMS: u32 = 16;
+/// Chunks a stream is given at a time by default
+pub const DEFAULT_RUN_CHUNKS: u32 = 4;
+
+/// Chunks `start..end` to send on `stream_id`, in order
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkRange {
+    pub stream_id: u32,
+    pub start: u32,
+    pub end: u32,
+}
+
+pub struct MultiStreamPlan {
+    total_chunks: u32,
+    streams: u32,
+    run_chunks: u32,
+    /// First chunk not handed out yet
+    next_chunk: u32,
+    /// Unsent ranges of closed streams, handed out before new ones
+    requeued: VecDeque<(u32, u32)>,
+    /// Range each stream is sending
+    active: BTreeMap<u32, (u32, u32)>,
+}
+
+impl MultiStreamPlan {
+    /// Plan `total_chunks` over `streams` streams, `run_chunks` at a time (0 for the default)
+    pub fn new(total_chunks: u32, streams: u32, run_chunks: u32) -> Result<MultiStreamPlan, String> {
+        if streams == 0 || streams > MAX_STREAMS {
+            return Err(format!("A transfer can use 1 to {} streams", MAX_STREAMS));
+        }
+        Ok(MultiStreamPlan {
+            total_chunks,
+            streams,
+            run_chunks: if run_chunks == 0 { DEFAULT_RUN_CHUNKS } else { run_chunks },
+            next_chunk: 0,
+            requeued: VecDeque::new(),
+            active: BTreeMap::new(),
+        })
+    }
+
+    /// The stream finished its range (or just opened): its next `{stream_id, start, end}`
+    /// as JSON, or `undefined` when there is nothing it may send yet
+    pub fn next_range(&mut self, stream_id: u32) -> Result<Option<String>, String> {
+        Ok(self.assign(stream_id)?.map(|range| serde_json::to_string(&range).unwrap_or_default()))
+    }
+
+    /// The stream closed after sending its range up to `sent_up_to`; the rest is requeued
+    pub fn close_stream(&mut self, stream_id: u32, sent_up_to: u32) {
+        if let Some((start, end)) = self.active.remove(&stream_id) {
+            let resume_at = sent_up_to.clamp(start, end);
+            if resume_at < end {
+                self.requeued.push_back((resume_at, end));
+            }
+        }
+    }
+
+    /// Reordering buffer, in chunks, the receiver needs for this plan
+    pub fn reorder_window(&self) -> usize {
+        (self.streams * self.run_chunks) as usize
+    }
+
+    /// Every chunk has been handed to a stream that is still open or finished
+    pub fn is_fully_assigned(&self) -> bool {
+        self.next_chunk == self.total_chunks && self.requeued.is_empty()
+    }
+}
+
+impl MultiStreamPlan {
+    pub fn assign(&mut self, stream_id: u32) -> Result<Option<ChunkRange>, String> {
+        if stream_id >= self.streams {
+            return Err(format!("Stream {} is out of range (plan has {})", stream_id, self.streams));
+        }
+        self.active.remove(&stream_id);
+        let range = match self.requeued.pop_front() {
+            Some(range) => range,
+            None if self.next_chunk < self.total_chunks => {
+                let end = self.next_chunk.saturating_add(self.run_chunks).min(self.total_chunks);
+                // Stay within the receiver's window of the oldest range still in flight
+                let oldest = self.active.values().map(|(start, _)| *start).min().unwrap_or(self.next_chunk);
+                if (end - oldest) as usize > self.reorder_window() {
+                    return Ok(None);
+                }
+                let range = (self.next_chunk, end);
+                self.next_chunk = end;
+                range
+            }
+            None => return Ok(None),
+        };
+        self.active.insert(stream_id, range);
+        Ok(Some(ChunkRange { stream_id, start: range.0, end: range.1 }))
+    }
+}
+
+/// Wrap a chunk (`FileChunk` JSON) in a protobuf frame tagged with `stream_id`
+pub fn encode_chunk_frame(chunk_json: &str, stream_id: u32) -> Result<Vec<u8>, String> {
+    check_size("Chunk", chunk_json, MAX_CHUNK_JSON_BYTES)?;
+    let chunk: FileChunk = serde_json::from_str(chunk_json).map_err(|e| format!("Invalid chunk JSON: {}", e))?;
+    let body = wire::Chunk { stream_id, ..chunk.into() };
+    Ok(wire::encode_frame(&wire::Envelope::new(wire::Body::Chunk(body))))
+}
+
+impl IncomingTransfer {
+    /// Accept a chunk frame from any of the transfer's streams; returns false for a duplicate
+    pub fn accept_chunk_frame(&mut self, frame: &[u8]) -> Result<bool, String> {
+        let Some((envelope, _)) = wire::decode_frame(frame)? else {
+            return Err("Incomplete chunk frame".to_string());
+        };
+        let Some(wire::Body::Chunk(chunk)) = envelope.body else {
+            return Err("Expected a chunk frame".to_string());
+        };
+        Ok(self.accept(chunk.into())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::{TransferManager, CHUNK_SIZE};
+
+    #[test]
+    fn test_streams_merge_within_the_window() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 20 + 5).map(|i| (i % 251) as u8).collect();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let chunks: Vec<FileChunk> = serde_json::from_str(&manager.prepare_transfer("a.pdf".to_string(), &content, key.clone()).unwrap()).unwrap();
+        assert_eq!(chunks.len(), 21);
+
+        let mut plan = MultiStreamPlan::new(21, 3, 4).unwrap();
+        let mut incoming = IncomingTransfer::new("t1".to_string(), "a.pdf".to_string(), "hash-a".to_string(), 21, key, plan.reorder_window()).unwrap();
+        assert!(plan.assign(3).is_err());
+        // Stream 0 is slow: one chunk per round while the others send two
+        let mut queues: Vec<VecDeque<u32>> = vec![VecDeque::new(); 3];
+        let mut received = Vec::new();
+        let mut closed = false;
+        while !incoming.is_complete() {
+            for stream in 0..3u32 {
+                let queue = &mut queues[stream as usize];
+                if queue.is_empty() {
+                    if let Some(range) = plan.assign(stream).unwrap() {
+                        queue.extend(range.start..range.end);
+                    }
+                }
+                for _ in 0..if stream == 0 { 1 } else { 2 } {
+                    if let Some(index) = queue.pop_front() {
+                        let frame = encode_chunk_frame(&serde_json::to_string(&chunks[index as usize]).unwrap(), stream).unwrap();
+                        incoming.accept_chunk_frame(&frame).unwrap();
+                    }
+                }
+            }
+            // Stream 2 drops after the first round, partway through its range
+            if !closed {
+                let next = *queues[2].front().unwrap();
+                plan.close_stream(2, next);
+                queues[2].clear();
+                closed = true;
+            }
+            received.extend(incoming.take_ready());
+        }
+        assert!(plan.is_fully_assigned());
+        assert_eq!(received, content);
+    }
+}
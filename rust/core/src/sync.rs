@@ -0,0 +1,643 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use sha2::{Sha256, Digest};
+
+use crate::compat::{self, JournalSource, JOURNAL_FORMAT_VERSION};
+use crate::error::Error;
+use crate::filetable::{FileTable, ShardStats};
+use crate::hashing::HashAlgorithm;
+use crate::journalhistory::JournalHistory;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::memory::{string_bytes, MemoryFootprint};
+use crate::retention::BackupSchedule;
+use crate::sketch::{JournalSketch, SketchComparison};
+
+fn current_format() -> u32 {
+    JOURNAL_FORMAT_VERSION
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub path: String,
+    pub hash: String, // Hex encoded SHA256
+    pub mtime: u64,
+    pub size: u64,
+    pub version: u64, // Sequence number
+    pub is_deleted: bool,
+    pub last_modified_by: String,
+    /// Path this version was moved from, for an entry recorded by a rename;
+    /// the old path's tombstone is the version just before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<String>,
+    /// Optional metadata beyond mtime and size (see `extmeta`)
+    #[serde(default, skip_serializing_if = "ExtendedMetadata::is_empty")]
+    pub extended: ExtendedMetadata,
+}
+
+/// Metadata some hosts want to round-trip across devices; empty for most entries
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtendedMetadata {
+    /// Creation time in ms, where the platform reports one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub executable: bool,
+    /// Small key-value pairs set by the host or its plugins
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl ExtendedMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.ctime.is_none() && !self.executable && self.attributes.is_empty()
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.attributes.iter().map(|(key, value)| string_bytes(key) + string_bytes(value)).sum()
+    }
+}
+
+/// Summary of the journal's current position
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JournalHead {
+    pub sequence: u64,
+    pub file_count: usize,
+    pub deleted_count: usize,
+}
+
+/// What an open transaction needs to put the journal back as it was
+struct Transaction {
+    sequence: u64,
+    /// Prior entry of every path written, `None` for paths that didn't exist
+    files: HashMap<String, Option<FileMetadata>>,
+    pending: HashMap<String, PendingChange>,
+    history_len: usize,
+    /// Entries that predate the transaction, pushed out of the capped history
+    history_dropped: Vec<FileMetadata>,
+    history_added: usize,
+    backups: BackupSchedule,
+    hash_algorithm: HashAlgorithm,
+}
+
+/// A change held back by a debounce interval
+#[derive(Clone, Debug)]
+struct PendingChange {
+    hash: String,
+    size: u64,
+    mtime: u64,
+    device_id: String,
+    due_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeJournal {
+    /// Format this journal was written in (see `compat`); always the current one once loaded
+    #[serde(default = "current_format")]
+    format_version: u32,
+    #[serde(default)]
+    files: FileTable,
+    global_sequence: u64,
+    #[serde(skip)]
+    pending: HashMap<String, PendingChange>,
+    /// Recorded versions, saved apart from the journal (see `journalhistory`)
+    #[serde(default, skip_serializing)]
+    history: JournalHistory,
+    /// Backup snapshots taken of this vault and their retention policy
+    #[serde(default)]
+    backups: BackupSchedule,
+    /// Algorithm for newly recorded content; existing entries keep theirs
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    #[serde(skip)]
+    transaction: Option<Transaction>,
+}
+
+impl ChangeJournal {
+    pub fn new() -> ChangeJournal {
+        ChangeJournal {
+            format_version: JOURNAL_FORMAT_VERSION,
+            files: FileTable::new(),
+            global_sequence: 0,
+            pending: HashMap::new(),
+            history: JournalHistory::new(),
+            backups: BackupSchedule::default(),
+            hash_algorithm: HashAlgorithm::default(),
+            transaction: None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Read journal JSON written by this or an earlier supported release
+    pub fn from_json(json: &str) -> Result<ChangeJournal, Error> {
+        check_size("Journal", json, MAX_JOURNAL_BYTES)?;
+        compat::decode_journal(JournalSource::Json(json))
+    }
+
+    /// Compact binary form of `to_json`
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        // Writing to a Vec cannot fail
+        let _ = ciborium::into_writer(self, &mut out);
+        out
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<ChangeJournal, Error> {
+        if data.len() > MAX_JOURNAL_BYTES {
+            return Err(format!("Journal too large: {} bytes (limit {})", data.len(), MAX_JOURNAL_BYTES).into());
+        }
+        compat::decode_journal(JournalSource::Cbor(data))
+    }
+
+    pub fn update_file(&mut self, path: String, content: &[u8], mtime: u64, device_id: String) -> bool {
+        let hash = self.content_hash(&path, content);
+        self.update_hashed(path, hash, content.len() as u64, mtime, device_id)
+    }
+
+    /// `update_file` for content hashed elsewhere (e.g. by a `FileIngest`)
+    pub fn update_hashed(&mut self, path: String, hash: String, size: u64, mtime: u64, device_id: String) -> bool {
+        self.pending.remove(&path);
+        self.record_update(path, hash, size, mtime, device_id)
+    }
+
+    /// Hold a change back until `interval_ms` passes without further changes
+    /// Returns true if the change was staged (nothing is recorded yet)
+    pub fn stage_update(
+        &mut self,
+        path: String,
+        content: &[u8],
+        mtime: u64,
+        device_id: String,
+        now: u64,
+        interval_ms: u64,
+    ) -> bool {
+        let hash = self.content_hash(&path, content);
+        self.stage_hashed(path, hash, content.len() as u64, mtime, device_id, now.saturating_add(interval_ms))
+    }
+
+    /// `stage_update` for content hashed elsewhere, held back until `due_at`
+    pub fn stage_hashed(&mut self, path: String, hash: String, size: u64, mtime: u64, device_id: String, due_at: u64) -> bool {
+        if self.files.is_live_with_hash(&path, &hash) {
+            // Back to the recorded content: nothing to commit
+            self.pending.remove(&path);
+            return false;
+        }
+        let change = PendingChange {
+            hash,
+            size,
+            mtime,
+            device_id,
+            due_at,
+        };
+        self.pending.insert(path, change);
+        true
+    }
+
+    /// Record staged changes whose interval has elapsed; returns how many were recorded
+    pub fn flush_pending(&mut self, now: u64) -> usize {
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, change)| change.due_at <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.commit_pending(due)
+    }
+
+    /// Record every staged change regardless of its interval
+    pub fn flush_all_pending(&mut self) -> usize {
+        let all: Vec<String> = self.pending.keys().cloned().collect();
+        self.commit_pending(all)
+    }
+
+    /// Number of changes waiting on a debounce interval
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn mark_deleted(&mut self, path: String, mtime: u64, device_id: String) -> bool {
+        self.pending.remove(&path);
+        if self.files.is_deleted(&path) == Some(true) {
+            return false;
+        }
+
+        self.global_sequence += 1;
+        let metadata = FileMetadata {
+            path: path.clone(),
+            hash: String::new(),
+            mtime,
+            size: 0,
+            version: self.global_sequence,
+            is_deleted: true,
+            last_modified_by: device_id,
+            moved_from: None,
+            extended: Default::default(),
+        };
+
+        self.push_history(&metadata);
+        self.put_file(metadata);
+        true
+    }
+}
+
+/// Hex-encoded SHA-256 of file content
+pub fn hash_content(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    hex::encode(hasher.finalize())
+}
+
+impl ChangeJournal {
+    /// Record new content metadata for a path; false if the content is unchanged.
+    /// Extended metadata carries over from the previous version
+    pub fn record_update(&mut self, path: String, hash: String, size: u64, mtime: u64, device_id: String) -> bool {
+        if self.files.is_live_with_hash(&path, &hash) {
+            return false; // No change
+        }
+
+        self.global_sequence += 1;
+        let metadata = FileMetadata {
+            extended: self.files.extended(&path),
+            path,
+            hash,
+            mtime,
+            size,
+            version: self.global_sequence,
+            is_deleted: false,
+            last_modified_by: device_id,
+            moved_from: None,
+        };
+
+        self.push_history(&metadata);
+        self.put_file(metadata);
+        true
+    }
+
+    /// Record a rename as two versions: a tombstone for `from`, then `to` with
+    /// `from`'s content and `moved_from` set. False if `from` has no live entry
+    pub fn record_move(&mut self, from: &str, to: String, mtime: u64, device_id: String) -> bool {
+        let Some(source) = self.files.get(from).filter(|m| !m.is_deleted && m.path != to) else {
+            return false;
+        };
+        if let Some(change) = self.pending.remove(from) {
+            self.pending.insert(to.clone(), change);
+        }
+        self.mark_deleted(from.to_string(), mtime, device_id.clone());
+        let moved_from = Some(source.path.clone());
+        self.record_version(FileMetadata { path: to, mtime, last_modified_by: device_id, moved_from, ..source })
+    }
+
+    /// Record a complete version (a received one, or the destination of a move) under
+    /// the next sequence number; false if the path already has this content and metadata
+    pub fn record_version(&mut self, mut metadata: FileMetadata) -> bool {
+        if self.files.is_live_with_hash(&metadata.path, &metadata.hash)
+            && self.files.extended(&metadata.path) == metadata.extended
+        {
+            return false;
+        }
+        self.global_sequence += 1;
+        metadata.version = self.global_sequence;
+        metadata.is_deleted = false;
+        self.push_history(&metadata);
+        self.put_file(metadata);
+        true
+    }
+
+    /// Replace the extended metadata of a live entry, recording a version with the same content
+    pub fn set_extended(&mut self, path: &str, extended: ExtendedMetadata, device_id: String) -> Result<bool, Error> {
+        let current = self.files.get(path).filter(|m| !m.is_deleted).ok_or_else(|| format!("No live entry for {}", path))?;
+        Ok(self.record_version(FileMetadata { extended, last_modified_by: device_id, moved_from: None, ..current }))
+    }
+
+    /// Current metadata for a path
+    pub fn get(&self, path: &str) -> Option<FileMetadata> {
+        self.files.get(path)
+    }
+
+    /// Current metadata for every path, deleted entries included
+    pub fn files(&self) -> impl Iterator<Item = FileMetadata> + '_ {
+        self.files.iter()
+    }
+
+    /// Totals of every non-empty shard (see `shards`)
+    pub fn shards(&self) -> impl Iterator<Item = (&str, &ShardStats)> {
+        self.files.shards()
+    }
+
+    pub fn shard(&self, shard: &str) -> Option<&ShardStats> {
+        self.files.shard(shard)
+    }
+
+    /// Current metadata for the paths in one shard
+    pub fn shard_files<'a>(&'a self, shard: &'a str) -> impl Iterator<Item = FileMetadata> + 'a {
+        self.files.shard_iter(shard)
+    }
+
+    /// Compact summary of the current entries for a cheap divergence check
+    pub fn sketch(&self) -> JournalSketch {
+        JournalSketch::from_entries(self.files())
+    }
+
+    /// Compare our entries against a peer's serialized sketch
+    pub fn compare_sketch(&self, remote: &[u8]) -> Result<SketchComparison, Error> {
+        Ok(self.sketch().compare(&JournalSketch::from_bytes(remote)?))
+    }
+
+    /// Sequence number of the latest recorded change
+    pub fn sequence(&self) -> u64 {
+        self.global_sequence
+    }
+
+    pub fn backups(&self) -> &BackupSchedule {
+        &self.backups
+    }
+
+    pub fn backups_mut(&mut self) -> &mut BackupSchedule {
+        &mut self.backups
+    }
+
+    /// Recorded versions, oldest first
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = FileMetadata> + '_ {
+        self.history.iter()
+    }
+
+    pub fn history_log(&self) -> &JournalHistory {
+        &self.history
+    }
+
+    /// Replace the history with one saved on its own
+    pub fn set_history(&mut self, history: JournalHistory) -> Result<(), Error> {
+        if self.transaction.is_some() {
+            return Err("Can't load history during a journal transaction".into());
+        }
+        self.history = history;
+        Ok(())
+    }
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    /// Hash new content with `algorithm` from now on
+    pub fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.hash_algorithm = algorithm;
+    }
+
+    /// Hash for new content at `path`; content that still matches an entry
+    /// recorded with another algorithm keeps that entry's hash, so switching
+    /// algorithms doesn't turn every file into a change
+    pub fn content_hash(&self, path: &str, content: &[u8]) -> String {
+        self.hash_with(path, |algorithm| Some(algorithm.hash(content))).unwrap_or_default()
+    }
+
+    /// `content_hash` where `hash` supplies the content's hash per algorithm, if known
+    pub fn hash_with(&self, path: &str, hash: impl Fn(HashAlgorithm) -> Option<String>) -> Option<String> {
+        if let Some(existing) = self.files.get(path).filter(|m| !m.is_deleted) {
+            let recorded = HashAlgorithm::of(&existing.hash);
+            if recorded != self.hash_algorithm && hash(recorded).as_ref() == Some(&existing.hash) {
+                return Some(existing.hash);
+            }
+        }
+        hash(self.hash_algorithm)
+    }
+
+    /// Algorithms `hash_with` may ask for at `path`, the journal's own first
+    pub fn hash_algorithms_for(&self, path: &str) -> Vec<HashAlgorithm> {
+        let mut algorithms = vec![self.hash_algorithm];
+        if let Some(existing) = self.files.get(path).filter(|m| !m.is_deleted) {
+            let recorded = HashAlgorithm::of(&existing.hash);
+            if recorded != self.hash_algorithm {
+                algorithms.push(recorded);
+            }
+        }
+        algorithms
+    }
+
+    /// Apply a version recorded elsewhere (e.g. a WAL record) with its own sequence
+    /// number; false if the journal is already at or past it
+    pub fn replay(&mut self, metadata: FileMetadata) -> bool {
+        if metadata.version <= self.global_sequence {
+            return false;
+        }
+        self.pending.remove(&metadata.path);
+        self.global_sequence = metadata.version;
+        self.push_history(&metadata);
+        self.put_file(metadata);
+        true
+    }
+
+    /// Build a journal from the parts of an older format
+    pub fn upgraded(
+        files: impl IntoIterator<Item = FileMetadata>,
+        global_sequence: u64,
+        history: impl IntoIterator<Item = FileMetadata>,
+        backups: BackupSchedule,
+    ) -> ChangeJournal {
+        let mut journal = ChangeJournal::new();
+        for metadata in files {
+            journal.restore_file(metadata);
+        }
+        journal.global_sequence = global_sequence;
+        for metadata in history {
+            journal.history.push(&metadata);
+        }
+        journal.backups = backups;
+        journal
+    }
+
+    /// Insert an entry read back from persisted state
+    pub fn restore_file(&mut self, metadata: FileMetadata) {
+        self.files.insert(metadata);
+    }
+
+    /// Replace every entry of `shard` with `files`, as saved on their own
+    pub fn replace_shard(&mut self, shard: &str, files: Vec<FileMetadata>) -> Result<(), Error> {
+        if self.transaction.is_some() {
+            return Err("Can't load a shard during a journal transaction".into());
+        }
+        let stale: Vec<String> = self.files.shard_iter(shard).map(|meta| meta.path).collect();
+        for path in stale {
+            self.pending.remove(&path);
+            self.files.remove(&path);
+        }
+        for metadata in files {
+            self.global_sequence = self.global_sequence.max(metadata.version);
+            self.files.insert(metadata);
+        }
+        Ok(())
+    }
+
+    /// The journal without its entries, for saving alongside separately saved shards
+    pub fn tail_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Tail<'a> {
+            format_version: u32,
+            global_sequence: u64,
+            backups: &'a BackupSchedule,
+            hash_algorithm: HashAlgorithm,
+        }
+        let tail = Tail {
+            format_version: JOURNAL_FORMAT_VERSION,
+            global_sequence: self.global_sequence,
+            backups: &self.backups,
+            hash_algorithm: self.hash_algorithm,
+        };
+        serde_json::to_string(&tail).unwrap_or_default()
+    }
+
+    /// Take the sequence counter, backups and any inline history from a journal parsed without files
+    pub fn restore_tail(&mut self, tail: ChangeJournal) {
+        self.global_sequence = self.global_sequence.max(tail.global_sequence);
+        if !tail.history.is_empty() {
+            self.history = tail.history;
+        }
+        self.backups = tail.backups;
+        self.hash_algorithm = tail.hash_algorithm;
+    }
+
+    fn push_history(&mut self, metadata: &FileMetadata) {
+        let dropped = self.history.push(metadata);
+        if let Some(tx) = self.transaction.as_mut() {
+            for dropped in dropped {
+                // The oldest entries go first, so those from before the transaction run out first
+                if tx.history_dropped.len() < tx.history_len {
+                    tx.history_dropped.push(dropped);
+                } else {
+                    tx.history_added -= 1;
+                }
+            }
+            tx.history_added += 1;
+        }
+    }
+
+    fn put_file(&mut self, metadata: FileMetadata) {
+        if let Some(tx) = self.transaction.as_mut() {
+            if !tx.files.contains_key(&metadata.path) {
+                tx.files.insert(metadata.path.clone(), self.files.get(&metadata.path));
+            }
+        }
+        self.files.insert(metadata);
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Start recording what changes so that `abort_transaction` can undo it
+    pub fn begin_transaction(&mut self) -> Result<(), Error> {
+        if self.transaction.is_some() {
+            return Err("A journal transaction is already open".into());
+        }
+        self.transaction = Some(Transaction {
+            sequence: self.global_sequence,
+            files: HashMap::new(),
+            pending: self.pending.clone(),
+            history_len: self.history.len(),
+            history_dropped: Vec::new(),
+            history_added: 0,
+            backups: self.backups.clone(),
+            hash_algorithm: self.hash_algorithm,
+        });
+        Ok(())
+    }
+
+    /// Keep everything done since `begin_transaction`
+    pub fn commit_transaction(&mut self) -> Result<(), Error> {
+        self.transaction.take().map(|_| ()).ok_or_else(|| "No journal transaction is open".into())
+    }
+
+    /// Put the journal back exactly as it was at `begin_transaction`
+    pub fn abort_transaction(&mut self) -> Result<(), Error> {
+        let tx = self.transaction.take().ok_or("No journal transaction is open")?;
+        for (path, prior) in tx.files {
+            match prior {
+                Some(meta) => self.files.insert(meta),
+                None => self.files.remove(&path),
+            }
+        }
+        for _ in 0..tx.history_added {
+            self.history.pop_back();
+        }
+        for entry in tx.history_dropped.iter().rev() {
+            self.history.push_front(entry);
+        }
+        self.global_sequence = tx.sequence;
+        self.pending = tx.pending;
+        self.backups = tx.backups;
+        self.hash_algorithm = tx.hash_algorithm;
+        Ok(())
+    }
+
+    fn commit_pending(&mut self, mut paths: Vec<String>) -> usize {
+        // Sorted so sequence numbers don't depend on map iteration order
+        paths.sort();
+        let mut committed = 0;
+        for path in paths {
+            if let Some(change) = self.pending.remove(&path) {
+                if self.record_update(path, change.hash, change.size, change.mtime, change.device_id) {
+                    committed += 1;
+                }
+            }
+        }
+        committed
+    }
+}
+
+impl Default for ChangeJournal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeJournal {
+    /// Drop the entry for `path` without recording a version; for cleaning up
+    /// entries that carry no information peers need
+    pub fn forget(&mut self, path: &str) -> bool {
+        let Some(prior) = self.files.get(path) else {
+            return false;
+        };
+        self.pending.remove(path);
+        if let Some(tx) = self.transaction.as_mut() {
+            tx.files.entry(path.to_string()).or_insert(Some(prior));
+        }
+        self.files.remove(path);
+        true
+    }
+
+    /// Current sequence and entry counts
+    pub fn head(&self) -> JournalHead {
+        let deleted_count = self.files.deleted_count();
+        JournalHead {
+            sequence: self.global_sequence,
+            file_count: self.files.len() - deleted_count,
+            deleted_count,
+        }
+    }
+}
+
+impl FileMetadata {
+    pub fn heap_bytes(&self) -> usize {
+        string_bytes(&self.path) + string_bytes(&self.hash) + string_bytes(&self.last_modified_by)
+    }
+}
+
+impl ChangeJournal {
+    /// Heap bytes of the history, included in `heap_bytes`
+    pub fn history_bytes(&self) -> usize {
+        self.history.heap_bytes()
+    }
+}
+
+impl MemoryFootprint for ChangeJournal {
+    fn heap_bytes(&self) -> usize {
+        self.files.heap_bytes() + self.history_bytes()
+    }
+
+    fn trim(&mut self) {
+        self.files.shrink_to_fit();
+        self.pending.shrink_to_fit();
+        self.history.trim();
+    }
+}
@@ -0,0 +1,94 @@
+//! Internal timing spans
+//!
+//! The hot stages of a sync (scanning the index, hashing, planning,
+//! encrypting and serializing) open a span that adds its duration to a
+//! per-stage total when it ends. The totals are cheap to keep and are
+//! reported through `get_metrics_json`, so a slowdown between releases shows
+//! up in the plugin without attaching a profiler. Stages can nest (a scan
+//! includes the hashing it triggers), so totals are not meant to be summed.
+//! Each thread, and so each WASM instance including every hashing worker,
+//! keeps its own totals.
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::clock;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Scan,
+    Hash,
+    Plan,
+    Encrypt,
+    Serialize,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageTiming {
+    pub count: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+thread_local! {
+    static TIMINGS: RefCell<BTreeMap<Stage, StageTiming>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Records its stage's duration when dropped
+pub struct Span {
+    stage: Stage,
+    started_ms: f64,
+}
+
+/// Start timing `stage` until the returned span goes out of scope
+pub fn span(stage: Stage) -> Span {
+    Span { stage, started_ms: clock::monotonic_ms() }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        record(self.stage, clock::monotonic_ms() - self.started_ms);
+    }
+}
+
+pub fn record(stage: Stage, elapsed_ms: f64) {
+    TIMINGS.with(|t| {
+        let mut timings = t.borrow_mut();
+        let timing = timings.entry(stage).or_default();
+        timing.count += 1;
+        timing.total_ms += elapsed_ms;
+        timing.max_ms = timing.max_ms.max(elapsed_ms);
+    });
+}
+
+/// Totals per stage; stages that never ran are absent
+pub fn snapshot() -> BTreeMap<Stage, StageTiming> {
+    TIMINGS.with(|t| t.borrow().clone())
+}
+
+/// Clear all totals, e.g. at the start of a measured run
+pub fn reset_timings() {
+    TIMINGS.with(|t| t.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_aggregate_per_stage() {
+        reset_timings();
+        for _ in 0..3 {
+            let _span = span(Stage::Hash);
+        }
+        record(Stage::Encrypt, 4.0);
+        record(Stage::Encrypt, 1.5);
+        let timings = snapshot();
+        assert_eq!(timings[&Stage::Hash].count, 3);
+        assert_eq!(timings[&Stage::Encrypt], StageTiming { count: 2, total_ms: 5.5, max_ms: 4.0 });
+        assert!(!timings.contains_key(&Stage::Plan));
+        assert_eq!(serde_json::to_value(&timings).unwrap()["encrypt"]["count"], 2);
+    }
+}
@@ -0,0 +1,281 @@
+//! Protocol trace recording and replay
+//!
+//! "These two devices never converge" can't be debugged from a description.
+//! While recording, the node keeps every protocol message it receives or
+//! produces (announcements, handshakes, remote changes) and every local
+//! change it journals, each with the time it happened. The user exports the
+//! trace and attaches it to a bug report; a fresh node created with the
+//! same device ID replays the inbound messages and local changes at their
+//! recorded times and ends up in the same state.
+//!
+//! Traces never contain file contents (local changes carry only the hash),
+//! and chunk payloads and cover-traffic filler are stripped from frames.
+//! Handshakes carry only public keys and signatures.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::clock;
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::wire;
+use crate::P2PNode;
+use crate::memory::{deque_bytes, string_bytes, MemoryFootprint};
+
+/// Events kept by default; the oldest are dropped first
+pub const DEFAULT_MAX_TRACE_EVENTS: usize = 10_000;
+const TRACE_FORMAT_VERSION: u32 = 1;
+
+/// Inbound events (messages received and local changes) are replayed;
+/// outbound ones are only there to read
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceMessage {
+    AnnouncementJson { json: String, sender_ip: String },
+    /// `frame` is base64
+    AnnouncementFrame { frame: String, sender_ip: String },
+    HandshakeFrame { frame: String },
+    RemoteChange { metadata: String, from_peer_id: String },
+    LocalChange { path: String, hash: String, size: u64, mtime: u64 },
+    LocalDelete { path: String, mtime: u64 },
+    LocalRename { old_path: String, path: String, mtime: u64 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub at: u64,
+    pub direction: Direction,
+    #[serde(flatten)]
+    pub message: TraceMessage,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TraceFile {
+    format_version: u32,
+    device_id: String,
+    /// Events dropped from the start because the trace was full
+    dropped: u64,
+    events: Vec<TraceEvent>,
+}
+
+#[derive(Debug, Default)]
+pub struct TraceRecorder {
+    recording: bool,
+    max_events: usize,
+    events: VecDeque<TraceEvent>,
+    dropped: u64,
+}
+
+impl TraceRecorder {
+    pub fn record(&mut self, at: u64, direction: Direction, message: TraceMessage) {
+        if !self.recording {
+            return;
+        }
+        if self.events.len() >= self.max_events {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(TraceEvent { at, direction, message });
+    }
+}
+
+/// Base64 of `frame` with chunk payloads and cover filler removed
+pub fn redact_frame(frame: &[u8]) -> String {
+    let redacted = match wire::decode_frame(frame) {
+        Ok(Some((mut envelope, _))) => {
+            match &mut envelope.body {
+                Some(wire::Body::Chunk(chunk)) => chunk.data.clear(),
+                Some(wire::Body::Cover(cover)) => cover.filler.clear(),
+                _ => {}
+            }
+            wire::encode_frame(&envelope)
+        }
+        // Frames that don't decode are kept as they are: they are what needs debugging
+        _ => frame.to_vec(),
+    };
+    BASE64.encode(redacted)
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    /// Events whose message the node rejected (as it did when recording)
+    pub rejected: usize,
+}
+
+impl P2PNode {
+    /// Start recording a new trace, keeping at most `max_events` (0 for the default)
+    pub fn start_trace_recording(&mut self, max_events: usize) {
+        let max_events = if max_events == 0 { DEFAULT_MAX_TRACE_EVENTS } else { max_events };
+        *self.trace.borrow_mut() = TraceRecorder { recording: true, max_events, ..Default::default() };
+    }
+
+    /// Stop recording; the trace is kept until the next `start_trace_recording`
+    pub fn stop_trace_recording(&mut self) {
+        self.trace.borrow_mut().recording = false;
+    }
+
+    pub fn get_trace_event_count(&self) -> usize {
+        self.trace.borrow().events.len()
+    }
+
+    /// The recorded trace as JSON, for a bug report
+    pub fn export_trace(&self) -> String {
+        let trace = self.trace.borrow();
+        serde_json::to_string(&TraceFile {
+            format_version: TRACE_FORMAT_VERSION,
+            device_id: self.device_id.clone(),
+            dropped: trace.dropped,
+            events: trace.events.iter().cloned().collect(),
+        })
+        .unwrap_or_default()
+    }
+
+    /// Feed an exported trace into this (fresh) node; returns `{replayed, rejected}` as JSON.
+    /// The clock is pinned to each event's time while it is replayed
+    pub fn replay_trace(&mut self, trace_json: &str) -> Result<String, String> {
+        let report = self.replay(trace_json).map_err(|e| self.record_error(e))?;
+        serde_json::to_string(&report).map_err(|e| e.to_string())
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn trace_event(&self, at: u64, direction: Direction, message: TraceMessage) {
+        self.trace.borrow_mut().record(at, direction, message);
+    }
+
+    pub(crate) fn replay(&mut self, trace_json: &str) -> Result<ReplayReport, String> {
+        check_size("Trace", trace_json, MAX_JOURNAL_BYTES)?;
+        let trace: TraceFile = serde_json::from_str(trace_json).map_err(|e| format!("Invalid trace: {}", e))?;
+        if trace.format_version != TRACE_FORMAT_VERSION {
+            return Err(format!("Unsupported trace format {}", trace.format_version));
+        }
+        if trace.device_id != self.device_id {
+            return Err(format!("Trace was recorded on {}; replay it on a node with that device ID", trace.device_id));
+        }
+        let pinned = clock::is_clock_overridden().then(clock::now_ms);
+        let mut report = ReplayReport::default();
+        for event in trace.events.into_iter().filter(|e| e.direction == Direction::Inbound) {
+            clock::set_clock_time(event.at);
+            let accepted = match event.message {
+                TraceMessage::AnnouncementJson { json, sender_ip } => self.apply_announcement(&json, &sender_ip, event.at).is_ok(),
+                TraceMessage::AnnouncementFrame { frame, sender_ip } => BASE64
+                    .decode(frame)
+                    .map_err(|e| e.to_string())
+                    .and_then(|frame| self.apply_announcement_frame(&frame, &sender_ip, event.at))
+                    .is_ok(),
+                TraceMessage::HandshakeFrame { frame } => BASE64
+                    .decode(frame)
+                    .map_err(|e| e.to_string())
+                    .and_then(|frame| self.apply_handshake_frame(&frame))
+                    .is_ok(),
+                TraceMessage::RemoteChange { metadata, from_peer_id } => match serde_json::from_str(&metadata) {
+                    Ok(remote) => {
+                        self.apply_remote(remote, &from_peer_id);
+                        true
+                    }
+                    Err(_) => false,
+                },
+                TraceMessage::LocalChange { path, hash, size, mtime } => {
+                    self.record_local_change(path, hash, size, mtime);
+                    true
+                }
+                TraceMessage::LocalDelete { path, mtime } => {
+                    self.mark_file_deleted(path, mtime);
+                    true
+                }
+                TraceMessage::LocalRename { old_path, path, mtime } => {
+                    self.rename_file(old_path, path, mtime);
+                    true
+                }
+            };
+            report.replayed += 1;
+            if !accepted {
+                report.rejected += 1;
+            }
+        }
+        match pinned {
+            Some(time) => clock::set_clock_time(time),
+            None => clock::use_system_clock(),
+        }
+        Ok(report)
+    }
+}
+
+impl TraceMessage {
+    fn heap_bytes(&self) -> usize {
+        match self {
+            TraceMessage::AnnouncementJson { json: a, sender_ip: b }
+            | TraceMessage::AnnouncementFrame { frame: a, sender_ip: b }
+            | TraceMessage::RemoteChange { metadata: a, from_peer_id: b }
+            | TraceMessage::LocalChange { path: a, hash: b, .. }
+            | TraceMessage::LocalRename { old_path: a, path: b, .. } => string_bytes(a) + string_bytes(b),
+            TraceMessage::HandshakeFrame { frame } => string_bytes(frame),
+            TraceMessage::LocalDelete { path, .. } => string_bytes(path),
+        }
+    }
+}
+
+impl MemoryFootprint for TraceRecorder {
+    fn heap_bytes(&self) -> usize {
+        deque_bytes(&self.events, |e| e.message.heap_bytes())
+    }
+
+    fn trim(&mut self) {
+        self.events.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_reproduces_journal_and_peers() {
+        clock::set_clock_time(1_000_000);
+        let laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        phone.start_trace_recording(0);
+
+        phone.update_file("a.md".to_string(), b"private words", 10);
+        clock::advance_clock(1000);
+        phone.process_announcement_frame(&laptop.get_announcement_frame(), "10.0.0.2", clock::now_ms()).unwrap();
+        assert!(phone.process_announcement_frame(b"garbage", "10.0.0.9", clock::now_ms()).is_err());
+        let _ = phone.get_announcement_frame();
+        let mut remote = phone.change_journal.get("a.md").unwrap();
+        remote.hash = "f".repeat(64);
+        remote.last_modified_by = "laptop".to_string();
+        remote.version += 1;
+        clock::advance_clock(1000);
+        phone.apply_remote_change(&serde_json::to_string(&remote).unwrap(), "laptop").unwrap();
+        phone.update_file("b.md".to_string(), b"more words", 30);
+        phone.mark_file_deleted("b.md".to_string(), 40);
+        phone.stop_trace_recording();
+        let files = |node: &P2PNode| {
+            let mut files: Vec<_> = node.change_journal.files().collect();
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            serde_json::to_string(&files).unwrap()
+        };
+        let recorded = files(&phone);
+        phone.update_file("late.md".to_string(), b"x", 50);
+
+        let trace = phone.export_trace();
+        assert!(!trace.contains("private words"));
+        let mut replica = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        // The outbound announcement is not replayed; the garbage frame is rejected again
+        assert_eq!(replica.replay(&trace).unwrap(), ReplayReport { replayed: 6, rejected: 1 });
+        assert_eq!(replica.get_peer_count(), 1);
+        assert_eq!(files(&replica), recorded);
+
+        let other = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080).export_trace();
+        assert!(replica.replay(&other).unwrap_err().contains("device ID"));
+        clock::use_system_clock();
+    }
+}
@@ -0,0 +1,407 @@
+use aes_gcm::Aes256Gcm;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use crate::cancel::CancellationToken;
+use crate::error::Error;
+use crate::crypto::{decrypt_in_place, session_key_from, transfer_cipher, TAG_LEN};
+use crate::ingest::FileIngest;
+use crate::limits::{check_size, MAX_CHUNK_JSON_BYTES};
+use crate::padding::{self, PADDED_CHUNK_BYTES};
+use crate::timing::{self, Stage};
+
+pub const CHUNK_SIZE: usize = 64 * 1024; // 64KB
+const CHUNK_AAD_DOMAIN: &[u8] = b"obsidian-p2p-sync chunk v1";
+
+/// Associated data binding a chunk's ciphertext to its transfer, file and
+/// position, so a chunk spliced into another transfer, file or slot fails to decrypt
+pub fn chunk_aad(transfer_id: &str, file_path: &str, index: u32, total: u32) -> Vec<u8> {
+    let mut aad = CHUNK_AAD_DOMAIN.to_vec();
+    aad.extend_from_slice(&(transfer_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(transfer_id.as_bytes());
+    aad.extend_from_slice(&Sha256::digest(file_path.as_bytes()));
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.extend_from_slice(&total.to_be_bytes());
+    aad
+}
+
+/// Chunks an `IncomingTransfer` holds ahead of the next expected one by default
+pub const DEFAULT_REORDER_CHUNKS: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileChunk {
+    pub file_path: String,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub data: Vec<u8>, // Encrypted data
+    pub nonce: Vec<u8>,
+}
+
+/// Append `bytes` as a JSON number array, the way serde renders `Vec<u8>`;
+/// digit by digit this is several times faster than serializing each element
+fn write_byte_array(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(b'[');
+    for (i, &b) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        if b >= 100 {
+            out.push(b'0' + b / 100);
+        }
+        if b >= 10 {
+            out.push(b'0' + b / 10 % 10);
+        }
+        out.push(b'0' + b % 10);
+    }
+    out.push(b']');
+}
+
+/// Append one encrypted chunk, laid out exactly as a serialized `FileChunk`,
+/// to the JSON array being written to `out`
+pub fn write_chunk_json(out: &mut Vec<u8>, path_json: &str, index: u32, total: u32, data: &[u8], nonce: &[u8]) {
+    let _span = timing::span(Stage::Serialize);
+    if out.last() != Some(&b'[') {
+        out.push(b',');
+    }
+    out.extend_from_slice(b"{\"file_path\":");
+    out.extend_from_slice(path_json.as_bytes());
+    out.extend_from_slice(format!(",\"chunk_index\":{},\"total_chunks\":{},\"data\":", index, total).as_bytes());
+    write_byte_array(out, data);
+    out.extend_from_slice(b",\"nonce\":");
+    write_byte_array(out, nonce);
+    out.push(b'}');
+}
+
+/// Upper bound on the JSON size of `content` once chunked: bytes render as at
+/// most three digits and a comma, plus the fields and nonce of every chunk
+fn json_capacity(content_len: usize, total_chunks: usize, file_path: &str) -> usize {
+    2 + (content_len + total_chunks * TAG_LEN) * 4 + total_chunks * (128 + file_path.len())
+}
+
+pub struct TransferManager {
+    // We could store active transfers here if needed
+    cancel_token: Option<CancellationToken>,
+    /// Chunks are padded (both peers must agree)
+    padding: bool,
+    /// Bound into every chunk's associated data (see `chunk_aad`)
+    transfer_id: String,
+    /// Bound into the transfer key (see `crypto::transfer_cipher`)
+    file_hash: String,
+}
+
+/// Chunks are only encrypted or decrypted for a named transfer of a known file
+pub fn check_binding(transfer_id: &str, file_hash: &str) -> Result<(), Error> {
+    if transfer_id.is_empty() || file_hash.is_empty() {
+        return Err("A transfer needs its transfer ID and file hash".into());
+    }
+    Ok(())
+}
+
+impl TransferManager {
+    /// Chunks of transfer `transfer_id`, carrying the file the receiver expects
+    /// as `file_hash` from the manifest; both peers must use the same values
+    pub fn new(transfer_id: String, file_hash: String) -> Result<TransferManager, Error> {
+        check_binding(&transfer_id, &file_hash)?;
+        Ok(TransferManager { cancel_token: None, padding: false, transfer_id, file_hash })
+    }
+
+    /// Prepare a file for transfer: split into chunks and encrypt
+    pub fn prepare_transfer(&self, file_path: String, content: &[u8], session_key: String) -> Result<String, Error> {
+        let session_key = session_key_from(&session_key)?;
+        // A whole-file ingest session: one buffer encrypted in place for every chunk and
+        // one output allocation sized up front, instead of a Vec and a FileChunk per chunk
+        let mut ingest = FileIngest::create(file_path, content.len() as u64, &[], Some(session_key))?;
+        ingest.set_cancellation(self.cancel_token.clone());
+        ingest.set_padding(self.padding)?;
+        ingest.set_transfer_id(self.transfer_id.clone())?;
+        ingest.set_file_hash(self.file_hash.clone())?;
+        let total_chunks = ingest.get_total_chunks() as usize;
+        let plain_len = if self.padding { total_chunks * PADDED_CHUNK_BYTES } else { content.len() };
+        let mut out = Vec::with_capacity(json_capacity(plain_len, total_chunks, ingest.file_path()));
+
+        out.push(b'[');
+        ingest.push_into(content, &mut out)?;
+        ingest.finish_into(&mut out)?;
+        out.push(b']');
+
+        String::from_utf8(out).map_err(|e| e.to_string().into())
+    }
+
+    /// Attach a token checked between chunks of long operations
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.cancel_token = Some(token.clone());
+    }
+
+    /// Detach the current cancellation token
+    pub fn clear_cancellation_token(&mut self) {
+        self.cancel_token = None;
+    }
+
+    /// Pad outgoing chunks and strip padding from incoming ones
+    pub fn set_padding_enabled(&mut self, enabled: bool) {
+        self.padding = enabled;
+    }
+
+    /// Process a received chunk: decrypt and return data
+    /// Note: This is a simple helper; `IncomingTransfer` reassembles whole files,
+    /// whatever order the chunks arrive in.
+    pub fn decrypt_chunk(&self, chunk_json: String, session_key: String) -> Result<Vec<u8>, Error> {
+        check_size("Chunk", &chunk_json, MAX_CHUNK_JSON_BYTES)?;
+        let mut chunk: FileChunk = serde_json::from_str(&chunk_json)
+            .map_err(|e| Error::new(format!("Invalid chunk JSON: {}", e)))?;
+
+        let cipher = transfer_cipher(&*session_key_from(&session_key)?, &self.transfer_id, &self.file_hash);
+        let aad = chunk_aad(&self.transfer_id, &chunk.file_path, chunk.chunk_index, chunk.total_chunks);
+        decrypt_in_place(&cipher, &chunk.nonce, &aad, &mut chunk.data)?;
+        if self.padding {
+            padding::unpad(&mut chunk.data)?;
+        }
+        Ok(chunk.data)
+    }
+}
+
+/// Receiving side of one file transfer. Chunks may arrive in any order and more
+/// than once; each is decrypted when it arrives, held if it is ahead of the next
+/// expected chunk, and released to the host strictly in order. The reordering
+/// buffer is bounded: a chunk that would overflow it is rejected, and the host
+/// can check the occupancy to slow the sender down before that happens.
+pub struct IncomingTransfer {
+    transfer_id: String,
+    file_path: String,
+    total_chunks: u32,
+    cipher: Aes256Gcm,
+    padded: bool,
+    next_chunk: u32,
+    /// Decrypted chunks that arrived ahead of `next_chunk`
+    pending: BTreeMap<u32, Vec<u8>>,
+    pending_bytes: usize,
+    max_buffered: usize,
+    /// In-order plaintext not yet taken by the host
+    ready: Vec<u8>,
+    duplicates: u32,
+}
+
+impl IncomingTransfer {
+    pub fn new(
+        transfer_id: String,
+        file_path: String,
+        file_hash: String,
+        total_chunks: u32,
+        session_key: String,
+        max_buffered_chunks: usize,
+    ) -> Result<IncomingTransfer, Error> {
+        check_binding(&transfer_id, &file_hash)?;
+        Ok(IncomingTransfer {
+            cipher: transfer_cipher(&*session_key_from(&session_key)?, &transfer_id, &file_hash),
+            transfer_id,
+            file_path,
+            total_chunks,
+            padded: false,
+            next_chunk: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            max_buffered: max_buffered_chunks.max(1),
+            ready: Vec::new(),
+            duplicates: 0,
+        })
+    }
+
+    /// Strip padding from the chunks (both peers must agree)
+    pub fn set_padding_enabled(&mut self, enabled: bool) {
+        self.padded = enabled;
+    }
+
+    /// Accept a chunk in `FileChunk` JSON; returns false for a duplicate, which is ignored
+    pub fn accept_chunk(&mut self, chunk_json: String) -> Result<bool, Error> {
+        check_size("Chunk", &chunk_json, MAX_CHUNK_JSON_BYTES)?;
+        let chunk: FileChunk = serde_json::from_str(&chunk_json).map_err(|e| Error::new(format!("Invalid chunk JSON: {}", e)))?;
+        self.accept(chunk)
+    }
+
+    /// Plaintext released in order since the last call
+    pub fn take_ready(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// First chunk not received yet; everything before it has been released
+    pub fn get_next_chunk(&self) -> u32 {
+        self.next_chunk
+    }
+
+    pub fn get_buffered_chunks(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn get_buffered_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    pub fn get_buffer_capacity(&self) -> usize {
+        self.max_buffered
+    }
+
+    pub fn get_duplicate_count(&self) -> u32 {
+        self.duplicates
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_chunk == self.total_chunks
+    }
+
+    pub fn accept(&mut self, mut chunk: FileChunk) -> Result<bool, Error> {
+        if chunk.file_path != self.file_path || chunk.total_chunks != self.total_chunks {
+            return Err(format!("Chunk belongs to a different transfer than {}", self.file_path).into());
+        }
+        if chunk.chunk_index >= self.total_chunks {
+            return Err(format!("Chunk {} of {} is out of range", chunk.chunk_index, self.total_chunks).into());
+        }
+        if chunk.chunk_index < self.next_chunk || self.pending.contains_key(&chunk.chunk_index) {
+            self.duplicates += 1;
+            return Ok(false);
+        }
+        if chunk.chunk_index != self.next_chunk && self.pending.len() >= self.max_buffered {
+            return Err(format!("Reordering buffer for {} is full ({} chunks)", self.file_path, self.max_buffered).into());
+        }
+
+        let aad = chunk_aad(&self.transfer_id, &chunk.file_path, chunk.chunk_index, chunk.total_chunks);
+        decrypt_in_place(&self.cipher, &chunk.nonce, &aad, &mut chunk.data)?;
+        if self.padded {
+            padding::unpad(&mut chunk.data)?;
+        }
+        if chunk.chunk_index != self.next_chunk {
+            self.pending_bytes += chunk.data.len();
+            self.pending.insert(chunk.chunk_index, chunk.data);
+            return Ok(true);
+        }
+        self.ready.extend_from_slice(&chunk.data);
+        self.next_chunk += 1;
+        while let Some(data) = self.pending.remove(&self.next_chunk) {
+            self.pending_bytes -= data.len();
+            self.ready.extend_from_slice(&data);
+            self.next_chunk += 1;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_round_trip_and_fit_capacity() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let json = manager.prepare_transfer("a.md".to_string(), &content, key.clone()).unwrap();
+        assert!(json.len() <= json_capacity(content.len(), 3, "a.md"));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json, value.to_string());
+
+        let chunks: Vec<FileChunk> = serde_json::from_str(&json).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].total_chunks, 3);
+        let mut restored = Vec::new();
+        for chunk in &chunks {
+            restored.extend(manager.decrypt_chunk(serde_json::to_string(chunk).unwrap(), key.clone()).unwrap());
+        }
+        assert_eq!(restored, content);
+        assert_eq!(manager.prepare_transfer("empty.md".to_string(), b"", key.clone()).unwrap(), "[]");
+
+        // Without a transfer ID or file hash nothing is bound into the chunks
+        assert!(TransferManager::new(String::new(), "hash-a".to_string()).is_err());
+        assert!(TransferManager::new("t1".to_string(), String::new()).is_err());
+        assert!(IncomingTransfer::new(String::new(), "a.md".to_string(), String::new(), 3, key, 2).is_err());
+    }
+
+    #[test]
+    fn test_incoming_chunks_in_any_order() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 4 + 10).map(|i| (i % 241) as u8).collect();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let json = manager.prepare_transfer("a.pdf".to_string(), &content, key.clone()).unwrap();
+        let chunks: Vec<FileChunk> = serde_json::from_str(&json).unwrap();
+
+        let mut incoming = IncomingTransfer::new("t1".to_string(), "a.pdf".to_string(), "hash-a".to_string(), 5, key.clone(), 2).unwrap();
+        let mut received = Vec::new();
+        for index in [2, 1, 2] {
+            incoming.accept(chunks[index].clone()).unwrap();
+        }
+        assert_eq!((incoming.get_buffered_chunks(), incoming.get_buffered_bytes()), (2, CHUNK_SIZE * 2));
+        // A third chunk ahead of the gap does not fit; the missing one always does
+        assert!(incoming.accept(chunks[3].clone()).unwrap_err().message().contains("full"));
+        assert!(incoming.accept(chunks[0].clone()).unwrap());
+        received.extend(incoming.take_ready());
+        assert_eq!((incoming.get_next_chunk(), incoming.get_buffered_chunks()), (3, 0));
+        assert!(!incoming.accept(chunks[1].clone()).unwrap());
+        incoming.accept(chunks[4].clone()).unwrap();
+        incoming.accept_chunk(serde_json::to_string(&chunks[3]).unwrap()).unwrap();
+        received.extend(incoming.take_ready());
+        assert!(incoming.is_complete());
+        assert_eq!(incoming.get_duplicate_count(), 2);
+        assert_eq!(received, content);
+
+        let mut other = IncomingTransfer::new("t1".to_string(), "b.pdf".to_string(), "hash-a".to_string(), 5, key, 2).unwrap();
+        assert!(other.accept(chunks[0].clone()).is_err());
+    }
+
+    #[test]
+    fn test_spliced_chunks_fail_to_decrypt() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let content: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| (i % 13) as u8).collect();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let chunks: Vec<FileChunk> = serde_json::from_str(&manager.prepare_transfer("a.md".to_string(), &content, key.clone()).unwrap()).unwrap();
+        let decrypt = |manager: &TransferManager, chunk: &FileChunk| manager.decrypt_chunk(serde_json::to_string(chunk).unwrap(), key.clone());
+        assert!(decrypt(&manager, &chunks[1]).is_ok());
+
+        // Moved to another slot, another file or another transfer
+        let mut moved = chunks[1].clone();
+        moved.chunk_index = 0;
+        assert!(decrypt(&manager, &moved).is_err());
+        let mut renamed = chunks[1].clone();
+        renamed.file_path = "b.md".to_string();
+        assert!(decrypt(&manager, &renamed).is_err());
+        let other = TransferManager::new("t2".to_string(), "hash-a".to_string()).unwrap();
+        assert!(decrypt(&other, &chunks[1]).is_err());
+    }
+
+    #[test]
+    fn test_each_file_gets_its_own_key() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let content = b"same bytes".to_vec();
+        let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        let chunks: Vec<FileChunk> = serde_json::from_str(&manager.prepare_transfer("a.md".to_string(), &content, key.clone()).unwrap()).unwrap();
+
+        let mut incoming = IncomingTransfer::new("t1".to_string(), "a.md".to_string(), "hash-a".to_string(), 1, key.clone(), 1).unwrap();
+        incoming.accept(chunks[0].clone()).unwrap();
+        assert_eq!(incoming.take_ready(), content);
+
+        // Same session, transfer and slot, but the key was derived for another file
+        let mut wrong = IncomingTransfer::new("t1".to_string(), "a.md".to_string(), "hash-b".to_string(), 1, key.clone(), 1).unwrap();
+        assert!(wrong.accept(chunks[0].clone()).is_err());
+        let session = session_key_from(&key).unwrap();
+        let (a, b) = (transfer_cipher(&session, "t1", "hash-a"), transfer_cipher(&session, "t2", "hash-a"));
+        let mut sealed = b"x".to_vec();
+        let nonce = crate::crypto::encrypt_in_place(&a, b"", &mut sealed).unwrap();
+        assert!(decrypt_in_place(&b, &nonce, b"", &mut sealed).is_err());
+    }
+
+    #[test]
+    fn test_padded_chunks_hide_sizes() {
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+        let mut manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+        manager.set_padding_enabled(true);
+        for len in [0, 3, CHUNK_SIZE * 8 + 1] {
+            let content: Vec<u8> = (0..len).map(|i| (i % 13) as u8).collect();
+            let json = manager.prepare_transfer("a.md".to_string(), &content, key.clone()).unwrap();
+            let chunks: Vec<FileChunk> = serde_json::from_str(&json).unwrap();
+            assert_eq!(chunks.len() as u32, padding::padded_chunk_count(len.div_ceil(CHUNK_SIZE) as u32));
+            let mut restored = Vec::new();
+            for chunk in &chunks {
+                assert!(padding::BUCKETS.contains(&(chunk.data.len() - TAG_LEN)));
+                restored.extend(manager.decrypt_chunk(serde_json::to_string(chunk).unwrap(), key.clone()).unwrap());
+            }
+            assert_eq!(restored, content);
+        }
+    }
+}
@@ -0,0 +1,838 @@
+//! Pluggable transports
+//!
+//! The protocol engine never touches a socket: the host runs whatever
+//! transports it has (UDP broadcast, WebSocket, WebRTC, a relay) and
+//! registers each one by name with its shape (`register_transport`). It then
+//! reports the connection lifecycle (`transport_connected`,
+//! `transport_disconnected`), feeds every received message to
+//! `receive_transport_message` and writes out what `next_outgoing_message`
+//! hands it for that transport.
+//!
+//! A peer can be reachable over several transports at once. `send_frame`
+//! picks the best connected one (lowest `preference`, then direct before
+//! relay), and when a link drops the frames still queued on it move to the
+//! next link, or wait until one opens. Transports that cap the size of a
+//! message get every frame fragmented to fit and reassembled on arrival
+//! (`wire::Fragmenter`).
+//!
+//! Each transport has two lanes. Control frames (handshakes, acks, nudges,
+//! heartbeats) always leave before bulk frames (file chunks, cover filler),
+//! and on a fragmenting transport they also overtake the rest of a chunk
+//! that is halfway out, so a large transfer never holds up the protocol.
+//! `send_frame` sorts frames into lanes by what they carry; frames larger
+//! than `MAX_CONTROL_FRAME_BYTES` are bulk whatever they are.
+//!
+//! Broadcast transports carry discovery only:
+//! `broadcast_frame` queues a frame for all of them, and what they receive
+//! is processed as an announcement. Frames from other transports wait in
+//! `next_incoming_frame` for the session layer.
+//!
+//! Native hosts and tests can implement `Transport` instead and move
+//! everything with `pump_transport`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::wire::Fragmenter;
+
+use crate::clock;
+use crate::debugring::DebugCategory;
+use crate::limits::MAX_FRAME_BYTES;
+use crate::memory::{deque_bytes, string_bytes, MemoryFootprint};
+use crate::wire::{self, MIN_FRAGMENT_MESSAGE_BYTES};
+use crate::P2PNode;
+
+/// Frames held per peer while it has no open link, and received frames
+/// waiting for the session layer, before the oldest are dropped
+const MAX_QUEUED_FRAMES: usize = 1024;
+/// Events kept for the host before the oldest are dropped
+const MAX_EVENTS: usize = 256;
+/// Larger frames go in the bulk lane without being looked into
+pub const MAX_CONTROL_FRAME_BYTES: usize = 16 * 1024;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lane {
+    /// Protocol messages, always sent first
+    Control,
+    /// File data and filler
+    Bulk,
+}
+
+impl Lane {
+    pub fn of(frame: &[u8]) -> Lane {
+        if frame.len() > MAX_CONTROL_FRAME_BYTES {
+            return Lane::Bulk;
+        }
+        match wire::decode_frame(frame) {
+            Ok(Some((envelope, _))) if !envelope.is_control() => Lane::Bulk,
+            _ => Lane::Control,
+        }
+    }
+}
+
+/// Whole frames waiting to be written to one transport, with their recipient
+#[derive(Default)]
+struct Lanes {
+    control: VecDeque<(String, Vec<u8>)>,
+    bulk: VecDeque<(String, Vec<u8>)>,
+}
+
+impl Lanes {
+    fn push(&mut self, lane: Lane, peer_id: &str, frame: Vec<u8>) {
+        let queue = match lane {
+            Lane::Control => &mut self.control,
+            Lane::Bulk => &mut self.bulk,
+        };
+        queue.push_back((peer_id.to_string(), frame));
+    }
+
+    fn pop(&mut self) -> Option<(Lane, String, Vec<u8>)> {
+        if let Some((peer_id, frame)) = self.control.pop_front() {
+            return Some((Lane::Control, peer_id, frame));
+        }
+        self.bulk.pop_front().map(|(peer_id, frame)| (Lane::Bulk, peer_id, frame))
+    }
+
+    /// Remove the frames queued for `peer_id`, control first
+    fn take_peer(&mut self, peer_id: &str) -> Vec<(Lane, Vec<u8>)> {
+        let mut taken = Vec::new();
+        for (lane, queue) in [(Lane::Control, &mut self.control), (Lane::Bulk, &mut self.bulk)] {
+            let (theirs, rest): (VecDeque<_>, VecDeque<_>) = queue.drain(..).partition(|(peer, _)| peer == peer_id);
+            *queue = rest;
+            taken.extend(theirs.into_iter().map(|(_, frame)| (lane, frame)));
+        }
+        taken
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let owned = |(peer, frame): &(String, Vec<u8>)| string_bytes(peer) + frame.capacity();
+        deque_bytes(&self.control, owned) + deque_bytes(&self.bulk, owned)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.control.shrink_to_fit();
+        self.bulk.shrink_to_fit();
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// A point-to-point connection (WebSocket, WebRTC, TCP)
+    Direct,
+    /// Traffic forwarded through a third party
+    Relay,
+    /// Discovery announcements to whoever listens (UDP broadcast, mDNS)
+    Broadcast,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TransportInfo {
+    pub name: String,
+    pub kind: TransportKind,
+    /// Largest message the transport carries; `None` for streams
+    #[serde(default)]
+    pub max_message_bytes: Option<usize>,
+    /// Lower is tried first among a peer's open links
+    #[serde(default)]
+    pub preference: u32,
+}
+
+impl TransportInfo {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("Transport name must not be empty".to_string());
+        }
+        match self.max_message_bytes {
+            Some(max) if max < MIN_FRAGMENT_MESSAGE_BYTES => {
+                let min = MIN_FRAGMENT_MESSAGE_BYTES;
+                Err(format!("Transport {} messages must allow at least {} bytes", self.name, min))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn fragmenter(&self) -> Option<Fragmenter> {
+        self.max_message_bytes.and_then(|max| Fragmenter::new(max).ok())
+    }
+}
+
+/// One open connection to a peer over one transport
+struct Link {
+    connected_at: u64,
+    last_activity: u64,
+    frames_sent: u64,
+    frames_received: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// Splits and joins messages on transports with a size cap
+    fragmenter: Option<Fragmenter>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct LinkStatus {
+    pub peer_id: String,
+    pub transport: String,
+    pub connected_at: u64,
+    pub last_activity: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// The link `send_frame` uses for this peer
+    pub active: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransportEvent {
+    Connected { peer_id: String, transport: String },
+    Disconnected { peer_id: String, transport: String, reason: String },
+    /// The last link to a peer closed; `queued` frames wait for the next one
+    Unreachable { peer_id: String, queued: usize },
+}
+
+/// A message for the host to write to one transport
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    peer_id: String,
+    data: Vec<u8>,
+}
+
+impl OutgoingMessage {
+    /// Recipient, empty for a broadcast
+    pub fn peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+/// A whole frame received from a peer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncomingFrame {
+    transport: String,
+    peer_id: String,
+    frame: Vec<u8>,
+}
+
+impl IncomingFrame {
+    pub fn transport(&self) -> String {
+        self.transport.clone()
+    }
+
+    pub fn peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+
+    pub fn frame(&self) -> Vec<u8> {
+        self.frame.clone()
+    }
+}
+
+/// What a `Transport` observed since it was last polled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportInput {
+    Connected { peer_id: String },
+    Disconnected { peer_id: String, reason: String },
+    /// For broadcast transports `peer_id` is the sender's address
+    Message { peer_id: String, data: Vec<u8> },
+}
+
+/// A transport driven from Rust; see `P2PNode::pump_transport`
+pub trait Transport {
+    fn info(&self) -> TransportInfo;
+
+    /// Write one message to `peer_id` (empty for a broadcast)
+    fn send(&mut self, peer_id: &str, message: &[u8]) -> Result<(), String>;
+
+    /// Everything that happened since the last poll, in order
+    fn poll(&mut self) -> Vec<TransportInput>;
+}
+
+#[derive(Default)]
+pub struct TransportRegistry {
+    transports: BTreeMap<String, TransportInfo>,
+    /// Peer → transport → open link
+    links: BTreeMap<String, BTreeMap<String, Link>>,
+    outgoing: BTreeMap<String, Lanes>,
+    /// Peer → frames waiting for a link to open
+    unrouted: BTreeMap<String, VecDeque<(Lane, Vec<u8>)>>,
+    incoming: VecDeque<IncomingFrame>,
+    events: VecDeque<TransportEvent>,
+}
+
+impl TransportRegistry {
+    pub fn get(&self, name: &str) -> Option<&TransportInfo> {
+        self.transports.get(name)
+    }
+
+    fn known(&self, name: &str) -> Result<&TransportInfo, String> {
+        self.transports.get(name).ok_or_else(|| format!("Unknown transport {}", name))
+    }
+
+    fn push_event(&mut self, event: TransportEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Add or replace a transport; replacing one resets the fragment state of its links
+    pub fn register(&mut self, info: TransportInfo) -> Result<(), String> {
+        info.validate()?;
+        for links in self.links.values_mut() {
+            if let Some(link) = links.get_mut(&info.name) {
+                link.fragmenter = info.fragmenter();
+            }
+        }
+        self.outgoing.entry(info.name.clone()).or_default();
+        self.transports.insert(info.name.clone(), info);
+        Ok(())
+    }
+
+    /// Remove a transport, closing its links; false if it wasn't registered
+    pub fn unregister(&mut self, name: &str) -> bool {
+        if self.transports.remove(name).is_none() {
+            return false;
+        }
+        let peers: Vec<String> =
+            self.links.iter().filter(|(_, links)| links.contains_key(name)).map(|(peer, _)| peer.clone()).collect();
+        for peer in peers {
+            self.disconnect(name, &peer, "transport unregistered");
+        }
+        self.outgoing.remove(name);
+        true
+    }
+
+    /// Best open link to `peer_id`
+    pub fn route(&self, peer_id: &str) -> Option<&str> {
+        let links = self.links.get(peer_id)?;
+        links
+            .keys()
+            .filter_map(|name| self.transports.get(name))
+            .filter(|info| info.kind != TransportKind::Broadcast)
+            .min_by_key(|info| (info.preference, info.kind, info.name.as_str()))
+            .map(|info| info.name.as_str())
+    }
+
+    /// Queue `frame` on the peer's best link, or hold it until one opens
+    fn queue(&mut self, peer_id: &str, lane: Lane, frame: Vec<u8>) -> Option<String> {
+        match self.route(peer_id).map(str::to_string) {
+            Some(transport) => {
+                self.outgoing.entry(transport.clone()).or_default().push(lane, peer_id, frame);
+                Some(transport)
+            }
+            None => {
+                let held = self.unrouted.entry(peer_id.to_string()).or_default();
+                if held.len() == MAX_QUEUED_FRAMES {
+                    held.pop_front();
+                }
+                held.push_back((lane, frame));
+                None
+            }
+        }
+    }
+
+    /// Frames and bytes held for peers without an open link (not counted in `heap_bytes`)
+    pub fn held(&self) -> (usize, usize) {
+        let frames = self.unrouted.values().flatten();
+        frames.fold((0, 0), |(count, bytes), (_, frame)| (count + 1, bytes + frame.len()))
+    }
+
+    /// Drop one held frame of the peer holding the most bytes, its oldest bulk frame
+    /// if it has one; returns the bytes freed
+    pub fn drop_held(&mut self) -> Option<usize> {
+        let bytes = |held: &VecDeque<(Lane, Vec<u8>)>| held.iter().map(|(_, frame)| frame.len()).sum::<usize>();
+        let peer = self.unrouted.iter().max_by_key(|(_, held)| bytes(held)).map(|(peer, _)| peer.clone())?;
+        let held = self.unrouted.get_mut(&peer)?;
+        let at = held.iter().position(|(lane, _)| *lane == Lane::Bulk).unwrap_or(0);
+        let (_, frame) = held.remove(at)?;
+        if held.is_empty() {
+            self.unrouted.remove(&peer);
+        }
+        Some(frame.len())
+    }
+
+    /// Drop every held frame; returns how many
+    pub fn clear_held(&mut self) -> usize {
+        std::mem::take(&mut self.unrouted).values().map(VecDeque::len).sum()
+    }
+
+    /// Queue a frame for `peer_id`; returns the transport it will leave on, `None`
+    /// while the peer has no open link
+    pub fn send(&mut self, peer_id: &str, frame: &[u8]) -> Result<Option<String>, String> {
+        if peer_id.is_empty() {
+            return Err("Frames need a recipient; use broadcast_frame for announcements".to_string());
+        }
+        if frame.len() > MAX_FRAME_BYTES {
+            return Err(format!("Frame too large: {} bytes (limit {})", frame.len(), MAX_FRAME_BYTES));
+        }
+        Ok(self.queue(peer_id, Lane::of(frame), frame.to_vec()))
+    }
+
+    /// Queue a frame on every broadcast transport; returns how many
+    pub fn broadcast(&mut self, frame: &[u8]) -> usize {
+        let names: Vec<String> = self
+            .transports
+            .values()
+            .filter(|info| info.kind == TransportKind::Broadcast)
+            .map(|info| info.name.clone())
+            .collect();
+        for name in &names {
+            self.outgoing.entry(name.clone()).or_default().push(Lane::Control, "", frame.to_vec());
+        }
+        names.len()
+    }
+
+    /// A link opened; frames held for the peer go out on its best link
+    pub fn connect(&mut self, transport: &str, peer_id: &str, now: u64) -> Result<bool, String> {
+        let info = self.known(transport)?;
+        if info.kind == TransportKind::Broadcast {
+            return Err(format!("Broadcast transport {} has no links", transport));
+        }
+        let fragmenter = info.fragmenter();
+        let links = self.links.entry(peer_id.to_string()).or_default();
+        if links.contains_key(transport) {
+            return Ok(false);
+        }
+        let link = Link {
+            connected_at: now,
+            last_activity: now,
+            frames_sent: 0,
+            frames_received: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            fragmenter,
+        };
+        links.insert(transport.to_string(), link);
+        self.push_event(TransportEvent::Connected { peer_id: peer_id.to_string(), transport: transport.to_string() });
+        for (lane, frame) in self.unrouted.remove(peer_id).unwrap_or_default() {
+            self.queue(peer_id, lane, frame);
+        }
+        Ok(true)
+    }
+
+    /// A link closed; frames still queued on it move to the peer's next link.
+    /// A fragmented frame cut off halfway is lost with the link
+    pub fn disconnect(&mut self, transport: &str, peer_id: &str, reason: &str) -> bool {
+        let Some(links) = self.links.get_mut(peer_id) else {
+            return false;
+        };
+        if links.remove(transport).is_none() {
+            return false;
+        }
+        if links.is_empty() {
+            self.links.remove(peer_id);
+        }
+        let event = TransportEvent::Disconnected {
+            peer_id: peer_id.to_string(),
+            transport: transport.to_string(),
+            reason: reason.to_string(),
+        };
+        self.push_event(event);
+        let stranded = self.outgoing.get_mut(transport).map(|lanes| lanes.take_peer(peer_id)).unwrap_or_default();
+        for (lane, frame) in stranded {
+            self.queue(peer_id, lane, frame);
+        }
+        if self.route(peer_id).is_none() {
+            let queued = self.unrouted.get(peer_id).map_or(0, VecDeque::len);
+            self.push_event(TransportEvent::Unreachable { peer_id: peer_id.to_string(), queued });
+        }
+        true
+    }
+
+    /// Next message to write to `transport`: the rest of a fragmented control frame,
+    /// the next control frame, the rest of a fragmented bulk frame, the next bulk frame
+    pub fn next_outgoing(&mut self, transport: &str, now: u64) -> Option<OutgoingMessage> {
+        if let Some(message) = self.next_fragment(transport, true, now) {
+            return Some(message);
+        }
+        let lanes = self.outgoing.get_mut(transport)?;
+        if lanes.control.is_empty() {
+            if let Some(message) = self.next_fragment(transport, false, now) {
+                return Some(message);
+            }
+        }
+        let (lane, peer_id, frame) = self.outgoing.get_mut(transport)?.pop()?;
+        let Some(link) = self.links.get_mut(&peer_id).and_then(|links| links.get_mut(transport)) else {
+            // Broadcasts have no link
+            return Some(OutgoingMessage { peer_id, data: frame });
+        };
+        link.frames_sent += 1;
+        link.last_activity = now;
+        let data = match link.fragmenter.as_mut() {
+            Some(fragmenter) => {
+                match lane {
+                    Lane::Control => fragmenter.queue_frame_ahead(&frame).ok()?,
+                    Lane::Bulk => fragmenter.queue_frame(&frame).ok()?,
+                };
+                fragmenter.next_message()?
+            }
+            None => frame,
+        };
+        link.bytes_sent += data.len() as u64;
+        Some(OutgoingMessage { peer_id, data })
+    }
+
+    /// A waiting fragment on one of the transport's links; only those of control
+    /// frames when `urgent`
+    fn next_fragment(&mut self, transport: &str, urgent: bool, now: u64) -> Option<OutgoingMessage> {
+        for (peer, links) in self.links.iter_mut() {
+            let Some(link) = links.get_mut(transport) else {
+                continue;
+            };
+            let fragmenter = link.fragmenter.as_mut().filter(|fragmenter| !urgent || fragmenter.has_urgent());
+            if let Some(data) = fragmenter.and_then(Fragmenter::next_message) {
+                link.bytes_sent += data.len() as u64;
+                link.last_activity = now;
+                return Some(OutgoingMessage { peer_id: peer.clone(), data });
+            }
+        }
+        None
+    }
+
+    /// A message arrived; returns the whole frame once it is complete. A message
+    /// on a link the host hasn't reported opens it
+    pub fn receive(
+        &mut self,
+        transport: &str,
+        peer_id: &str,
+        message: &[u8],
+        now: u64,
+    ) -> Result<Option<Vec<u8>>, String> {
+        if self.known(transport)?.kind == TransportKind::Broadcast {
+            return Ok(Some(message.to_vec()));
+        }
+        self.connect(transport, peer_id, now)?;
+        let link = self.links.get_mut(peer_id).and_then(|links| links.get_mut(transport)).ok_or("Link state missing")?;
+        link.bytes_received += message.len() as u64;
+        link.last_activity = now;
+        let frame = match link.fragmenter.as_mut() {
+            Some(fragmenter) => fragmenter.receive_message(message)?,
+            None => Some(message.to_vec()),
+        };
+        if frame.is_some() {
+            link.frames_received += 1;
+        }
+        Ok(frame)
+    }
+
+    fn push_incoming(&mut self, frame: IncomingFrame) {
+        if self.incoming.len() == MAX_QUEUED_FRAMES {
+            self.incoming.pop_front();
+        }
+        self.incoming.push_back(frame);
+    }
+
+    pub fn links(&self) -> Vec<LinkStatus> {
+        let mut statuses = Vec::new();
+        for (peer, links) in &self.links {
+            let active = self.route(peer);
+            for (transport, link) in links {
+                statuses.push(LinkStatus {
+                    peer_id: peer.clone(),
+                    transport: transport.clone(),
+                    connected_at: link.connected_at,
+                    last_activity: link.last_activity,
+                    frames_sent: link.frames_sent,
+                    frames_received: link.frames_received,
+                    bytes_sent: link.bytes_sent,
+                    bytes_received: link.bytes_received,
+                    active: active == Some(transport.as_str()),
+                });
+            }
+        }
+        statuses
+    }
+}
+
+impl P2PNode {
+    /// Register a transport as `{name, kind: "direct" | "relay" | "broadcast",
+    /// max_message_bytes?, preference?}`; registering a name again replaces it
+    pub fn register_transport(&mut self, json: &str) -> Result<(), String> {
+        let result = serde_json::from_str::<TransportInfo>(json)
+            .map_err(|e| format!("Invalid transport: {}", e))
+            .and_then(|info| self.transports.register(info));
+        result.map_err(|e| self.record_error(e))
+    }
+
+    /// Remove a transport and close its links; their queued frames move to other links
+    pub fn unregister_transport(&mut self, name: &str) -> bool {
+        self.transports.unregister(name)
+    }
+
+    pub fn get_transports_json(&self) -> String {
+        serde_json::to_string(&self.transports.transports.values().collect::<Vec<_>>()).unwrap_or_default()
+    }
+
+    /// The host opened a connection to `peer_id`; false if it was already open
+    pub fn transport_connected(&mut self, transport: &str, peer_id: &str) -> Result<bool, String> {
+        self.link_up(transport, peer_id, clock::now_ms()).map_err(|e| self.record_error(e))
+    }
+
+    /// A connection closed or failed; false if it wasn't open
+    pub fn transport_disconnected(&mut self, transport: &str, peer_id: &str, reason: &str) -> bool {
+        self.link_down(transport, peer_id, reason)
+    }
+
+    /// Queue a frame for a peer on its best open link. Returns that transport's
+    /// name, or `undefined` if the frame waits for a link to open; waiting frames
+    /// count against the cache quota (`set_cache_quota`)
+    pub fn send_frame(&mut self, peer_id: &str, frame: &[u8]) -> Result<Option<String>, String> {
+        let transport = self.transports.send(peer_id, frame).map_err(|e| self.record_error(e))?;
+        if transport.is_none() {
+            self.enforce_cache_quota();
+        }
+        Ok(transport)
+    }
+
+    /// Queue a frame on every broadcast transport; returns how many
+    pub fn broadcast_frame(&mut self, frame: &[u8]) -> usize {
+        self.transports.broadcast(frame)
+    }
+
+    /// Queue this node's announcement on every broadcast transport
+    pub fn broadcast_announcement(&mut self) -> usize {
+        let frame = self.get_announcement_frame();
+        self.transports.broadcast(&frame)
+    }
+
+    /// Next message to write to `transport`, `undefined` when there is none
+    pub fn next_outgoing_message(&mut self, transport: &str) -> Option<OutgoingMessage> {
+        let message = self.transports.next_outgoing(transport, clock::now_ms())?;
+        self.debug_event(DebugCategory::Sent, "message", &message.peer_id, Some(message.data.len() as u64));
+        Some(message)
+    }
+
+    /// Hand over a message read from `transport` (for broadcast transports
+    /// `peer_id` is the sender's address). Announcements are processed here;
+    /// other frames wait in `next_incoming_frame`. Returns whether a whole frame
+    /// was completed
+    pub fn receive_transport_message(&mut self, transport: &str, peer_id: &str, data: &[u8]) -> Result<bool, String> {
+        self.receive_message(transport, peer_id, data, clock::now_ms()).map_err(|e| self.record_error(e))
+    }
+
+    /// Oldest received frame not yet taken by the session layer
+    pub fn next_incoming_frame(&mut self) -> Option<IncomingFrame> {
+        self.transports.incoming.pop_front()
+    }
+
+    /// Open links as `[{peer_id, transport, connected_at, last_activity, frames_sent,
+    /// frames_received, bytes_sent, bytes_received, active}]`
+    pub fn get_transport_links_json(&self) -> String {
+        serde_json::to_string(&self.transports.links()).unwrap_or_default()
+    }
+
+    /// Lifecycle events since the last call as `[{type: "connected" | "disconnected" | "unreachable", ...}]`
+    pub fn take_transport_events(&mut self) -> String {
+        let events: Vec<TransportEvent> = self.transports.events.drain(..).collect();
+        serde_json::to_string(&events).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    fn link_up(&mut self, transport: &str, peer_id: &str, now: u64) -> Result<bool, String> {
+        let opened = self.transports.connect(transport, peer_id, now)?;
+        if opened {
+            self.debug_event(DebugCategory::State, "link_up", peer_id, None);
+        }
+        Ok(opened)
+    }
+
+    fn link_down(&mut self, transport: &str, peer_id: &str, reason: &str) -> bool {
+        let closed = self.transports.disconnect(transport, peer_id, reason);
+        if closed {
+            self.debug_event(DebugCategory::State, "link_down", peer_id, None);
+            self.enforce_cache_quota();
+        }
+        closed
+    }
+
+    fn receive_message(&mut self, transport: &str, peer_id: &str, message: &[u8], now: u64) -> Result<bool, String> {
+        self.debug_event(DebugCategory::Received, "message", peer_id, Some(message.len() as u64));
+        let Some(frame) = self.transports.receive(transport, peer_id, message, now)? else {
+            return Ok(false);
+        };
+        if self.transports.get(transport).is_some_and(|info| info.kind == TransportKind::Broadcast) {
+            self.apply_announcement_frame(&frame, peer_id, now)?;
+        } else {
+            self.transports.push_incoming(IncomingFrame {
+                transport: transport.to_string(),
+                peer_id: peer_id.to_string(),
+                frame,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Register `transport` if needed, apply what it observed and write out what is
+    /// queued for it. A failed write closes that link. Returns the frames completed
+    pub fn pump_transport(&mut self, transport: &mut dyn Transport, now: u64) -> Result<usize, String> {
+        let info = transport.info();
+        if self.transports.get(&info.name) != Some(&info) {
+            self.transports.register(info.clone())?;
+        }
+        let mut received = 0;
+        for input in transport.poll() {
+            match input {
+                TransportInput::Connected { peer_id } => {
+                    self.link_up(&info.name, &peer_id, now)?;
+                }
+                TransportInput::Disconnected { peer_id, reason } => {
+                    self.link_down(&info.name, &peer_id, &reason);
+                }
+                TransportInput::Message { peer_id, data } => {
+                    if self.receive_message(&info.name, &peer_id, &data, now)? {
+                        received += 1;
+                    }
+                }
+            }
+        }
+        while let Some(message) = self.transports.next_outgoing(&info.name, now) {
+            self.debug_event(DebugCategory::Sent, "message", &message.peer_id, Some(message.data.len() as u64));
+            if let Err(e) = transport.send(&message.peer_id, &message.data) {
+                self.link_down(&info.name, &message.peer_id, &e);
+            }
+        }
+        Ok(received)
+    }
+}
+
+impl MemoryFootprint for TransportRegistry {
+    fn heap_bytes(&self) -> usize {
+        let lanes = self.outgoing.values().map(Lanes::heap_bytes).sum::<usize>();
+        let fragments = self.links.values().flat_map(BTreeMap::values).filter_map(|link| link.fragmenter.as_ref());
+        let incoming = deque_bytes(&self.incoming, |f| {
+            string_bytes(&f.transport) + string_bytes(&f.peer_id) + f.frame.capacity()
+        });
+        lanes + fragments.map(Fragmenter::heap_bytes).sum::<usize>() + incoming
+    }
+
+    fn trim(&mut self) {
+        self.outgoing.values_mut().for_each(Lanes::shrink_to_fit);
+        self.unrouted.values_mut().for_each(VecDeque::shrink_to_fit);
+        self.incoming.shrink_to_fit();
+        self.events.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One end of an in-memory transport: what it sends is collected for the test
+    /// to hand to the other end
+    struct Pipe {
+        info: TransportInfo,
+        inputs: Vec<TransportInput>,
+        sent: Vec<(String, Vec<u8>)>,
+    }
+
+    impl Transport for Pipe {
+        fn info(&self) -> TransportInfo {
+            self.info.clone()
+        }
+
+        fn send(&mut self, peer_id: &str, message: &[u8]) -> Result<(), String> {
+            self.sent.push((peer_id.to_string(), message.to_vec()));
+            Ok(())
+        }
+
+        fn poll(&mut self) -> Vec<TransportInput> {
+            std::mem::take(&mut self.inputs)
+        }
+    }
+
+    #[test]
+    fn test_frames_fail_over_between_transports() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        laptop.register_transport(r#"{"name":"wifi","kind":"direct"}"#).unwrap();
+        laptop.register_transport(r#"{"name":"udp","kind":"broadcast"}"#).unwrap();
+        assert!(laptop.register_transport(r#"{"name":"tiny","kind":"relay","max_message_bytes":64}"#).is_err());
+        assert!(laptop.transport_connected("bluetooth", "phone").is_err());
+
+        // Frames wait for a link, then take the preferred one
+        assert_eq!(laptop.send_frame("phone", b"hello").unwrap(), None);
+        let relay = TransportInfo {
+            name: "relay".to_string(),
+            kind: TransportKind::Relay,
+            max_message_bytes: Some(MIN_FRAGMENT_MESSAGE_BYTES),
+            preference: 0,
+        };
+        let mut pipe = Pipe { info: relay.clone(), inputs: vec![], sent: vec![] };
+        pipe.inputs.push(TransportInput::Connected { peer_id: "phone".to_string() });
+        laptop.pump_transport(&mut pipe, 1).unwrap();
+        assert!(laptop.transport_connected("wifi", "phone").unwrap());
+        assert_eq!(pipe.sent.len(), 1);
+        assert_eq!(laptop.send_frame("phone", &[7; 1500]).unwrap().as_deref(), Some("wifi"));
+
+        // A dropped link hands its queue to the next one, fragmented to fit
+        laptop.transport_disconnected("wifi", "phone", "left the network");
+        laptop.pump_transport(&mut pipe, 2).unwrap();
+        assert_eq!(pipe.sent.len(), 4);
+        assert!(pipe.sent.iter().all(|(peer, data)| peer == "phone" && data.len() <= MIN_FRAGMENT_MESSAGE_BYTES));
+
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let mut phone_pipe = Pipe { info: relay, inputs: vec![], sent: vec![] };
+        let laptop_id = "laptop".to_string();
+        let messages = pipe.sent.drain(..).map(|(_, data)| TransportInput::Message { peer_id: laptop_id.clone(), data });
+        phone_pipe.inputs.extend(messages);
+        assert_eq!(phone.pump_transport(&mut phone_pipe, 3).unwrap(), 2);
+        assert_eq!(phone.next_incoming_frame().unwrap().frame(), b"hello");
+        let frame = phone.next_incoming_frame().unwrap();
+        assert_eq!((frame.transport(), frame.frame().len()), ("relay".to_string(), 1500));
+
+        // Announcements go out on broadcast transports and are applied on arrival
+        assert_eq!(laptop.broadcast_announcement(), 1);
+        let announcement = laptop.next_outgoing_message("udp").unwrap();
+        assert_eq!(announcement.peer_id(), "");
+        phone.register_transport(r#"{"name":"udp","kind":"broadcast"}"#).unwrap();
+        assert!(phone.receive_transport_message("udp", "192.168.1.20", &announcement.data()).unwrap());
+        assert_eq!(phone.get_peer_count(), 1);
+        assert!(phone.next_incoming_frame().is_none());
+
+        laptop.transport_disconnected("relay", "phone", "closed");
+        assert!(laptop.take_transport_events().ends_with(r#"{"type":"unreachable","peer_id":"phone","queued":0}]"#));
+        assert_eq!(laptop.get_transport_links_json(), "[]");
+    }
+
+    #[test]
+    fn test_control_frames_overtake_bulk() {
+        let mut laptop = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        let mut phone = P2PNode::new("Phone".to_string(), "phone".to_string(), 8081);
+        let relay = r#"{"name":"relay","kind":"relay","max_message_bytes":512}"#;
+        laptop.register_transport(relay).unwrap();
+        phone.register_transport(relay).unwrap();
+        laptop.transport_connected("relay", "phone").unwrap();
+
+        let chunk = |index: u32| {
+            let data = vec![1; 2000];
+            let chunk = wire::Chunk { file_path: "a.pdf".to_string(), chunk_index: index, total_chunks: 2, data, ..Default::default() };
+            wire::encode_frame(&wire::Envelope::new(wire::Body::Chunk(chunk)))
+        };
+        let ack = wire::encode_frame(&wire::Envelope::new(wire::Body::Ack(wire::Ack {
+            file_path: "a.pdf".to_string(),
+            chunk_index: Some(0),
+            sequence: 1,
+        })));
+        assert_eq!((Lane::of(&chunk(0)), Lane::of(&ack)), (Lane::Bulk, Lane::Control));
+        assert_eq!(Lane::of(&[0; MAX_CONTROL_FRAME_BYTES + 1]), Lane::Bulk);
+
+        // The ack cuts into the chunk that is halfway out, and goes before the next one
+        laptop.send_frame("phone", &chunk(0)).unwrap();
+        let mut messages = vec![laptop.next_outgoing_message("relay").unwrap()];
+        laptop.send_frame("phone", &chunk(1)).unwrap();
+        laptop.send_frame("phone", &ack).unwrap();
+        messages.extend(std::iter::from_fn(|| laptop.next_outgoing_message("relay")));
+        assert_eq!(messages.len(), 11);
+        for message in &messages {
+            phone.receive_transport_message("relay", "laptop", &message.data()).unwrap();
+        }
+        let frames: Vec<Vec<u8>> = std::iter::from_fn(|| phone.next_incoming_frame()).map(|f| f.frame()).collect();
+        assert_eq!(frames, vec![ack, chunk(0), chunk(1)]);
+    }
+}
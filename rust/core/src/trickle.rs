@@ -0,0 +1,192 @@
+//! Background trickle sync
+//!
+//! Scheduled rounds keep peers in step, but in bursts: nothing moves for the
+//! whole interval, then a full manifest exchange and every queued attachment
+//! at once. In trickle mode the host also calls `trickle_tick(now)` from a
+//! slow timer. For each connected peer that is behind it returns a small
+//! manifest frame with the next journal entries the peer hasn't acknowledged,
+//! and it hands out a few bytes of the queued transfers (interactive first,
+//! as in `grant_bandwidth`). Each tick is bounded in entries and bytes, so
+//! devices stay converged without noticeable battery or network use. Nothing
+//! is sent while offline or during quiet hours, and attachments wait while
+//! bulk sync is paused for the battery.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sync::FileMetadata;
+use crate::wire;
+use crate::P2PNode;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TrickleConfig {
+    pub enabled: bool,
+    /// Time between deltas to the same peer
+    pub interval_ms: u64,
+    /// Attachment bytes handed out per tick, for all peers together
+    pub max_bytes_per_tick: u64,
+    /// Journal entries in one delta
+    pub max_entries: usize,
+}
+
+impl Default for TrickleConfig {
+    fn default() -> Self {
+        TrickleConfig { enabled: false, interval_ms: 15_000, max_bytes_per_tick: 64 * 1024, max_entries: 32 }
+    }
+}
+
+impl TrickleConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.interval_ms == 0 || self.max_entries == 0 {
+            return Err("Trickle interval and entry limit must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct TricklePeer {
+    /// Highest journal sequence the peer has acknowledged
+    acked_sequence: u64,
+    last_sent_at: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct TrickleState {
+    pub config: TrickleConfig,
+    peers: HashMap<String, TricklePeer>,
+}
+
+impl TrickleState {
+    pub fn ack(&mut self, peer_id: &str, sequence: u64) {
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        peer.acked_sequence = peer.acked_sequence.max(sequence);
+    }
+}
+
+/// What to send one peer this tick
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct TrickleSend {
+    pub peer_id: String,
+    /// Base64 manifest frame with the next journal entries, if the peer is behind
+    pub frame: Option<String>,
+    /// Bytes of queued transfers the peer may be sent
+    pub chunk_bytes: u64,
+}
+
+impl P2PNode {
+    /// Set trickle mode from JSON (`{enabled, interval_ms, max_bytes_per_tick, max_entries}`)
+    pub fn set_trickle_mode(&mut self, config_json: &str) -> Result<(), String> {
+        let config: TrickleConfig = serde_json::from_str(config_json)
+            .map_err(|e| format!("Invalid trickle config: {}", e))
+            .and_then(|config: TrickleConfig| config.validate().map(|_| config))
+            .map_err(|e| self.record_error(e))?;
+        self.trickle.config = config;
+        Ok(())
+    }
+
+    pub fn get_trickle_mode_json(&self) -> String {
+        serde_json::to_string(&self.trickle.config).unwrap_or_default()
+    }
+
+    /// The peer applied journal entries up to `sequence` (also call it after a full round)
+    pub fn ack_trickle_sequence(&mut self, peer_id: &str, sequence: u64) {
+        self.trickle.ack(peer_id, sequence);
+    }
+
+    /// Advance trickle mode; returns a JSON array of `{peer_id, frame, chunk_bytes}`
+    pub fn trickle_tick(&mut self, now: u64) -> String {
+        serde_json::to_string(&self.trickle_sends(now)).unwrap_or_default()
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn trickle_sends(&mut self, now: u64) -> Vec<TrickleSend> {
+        if !self.trickle.config.enabled || self.conditions.is_offline() || self.scheduler.rules.is_quiet(now) {
+            return Vec::new();
+        }
+        let config = self.trickle.config.clone();
+        let sequence = self.change_journal.sequence();
+        let mut sends: BTreeMap<String, TrickleSend> = BTreeMap::new();
+
+        let mut peers: Vec<String> = self.peers.values().map(|p| p.device_id.clone()).collect();
+        peers.sort();
+        peers.dedup();
+        for peer_id in peers {
+            let state = self.trickle.peers.entry(peer_id.clone()).or_default();
+            let due = state.last_sent_at.is_none_or(|last| now.saturating_sub(last) >= config.interval_ms);
+            if !due || state.acked_sequence >= sequence {
+                continue;
+            }
+            let since = state.acked_sequence;
+            let mut entries: Vec<FileMetadata> = self.change_journal.files().filter(|m| m.version > since).collect();
+            entries.sort_by_key(|m| m.version);
+            entries.truncate(config.max_entries);
+            let manifest = wire::Manifest {
+                since_sequence: since,
+                sequence: entries.last().map_or(since, |m| m.version),
+                entries: entries.iter().map(wire::FileEntry::from).collect(),
+            };
+            state.last_sent_at = Some(now);
+            let frame = wire::encode_frame(&wire::Envelope::new(wire::Body::Manifest(manifest)));
+            sends.insert(peer_id.clone(), TrickleSend { peer_id, frame: Some(BASE64.encode(frame)), chunk_bytes: 0 });
+        }
+
+        if !self.conditions.bulk_paused(&self.profile) {
+            for (peer_id, bytes) in self.bandwidth.distribute(config.max_bytes_per_tick) {
+                if bytes > 0 {
+                    sends.entry(peer_id.clone()).or_insert_with(|| TrickleSend { peer_id, ..Default::default() }).chunk_bytes = bytes;
+                }
+            }
+        }
+        sends.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiscoveredPeer;
+
+    #[test]
+    fn test_small_deltas_and_bounded_chunk_bytes() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.add_discovered_peer(&DiscoveredPeer::new("p1".into(), "Phone".into(), "phone".into(), 0, "10.0.0.2".into(), 8081))
+            .unwrap();
+        for i in 0..5 {
+            node.update_file(format!("note{}.md", i), b"text", 1);
+        }
+        assert!(node.trickle_sends(0).is_empty());
+        let invalid: TrickleConfig = serde_json::from_str(r#"{"enabled":true,"max_entries":0}"#).unwrap();
+        assert!(invalid.validate().is_err());
+        node.set_trickle_mode(r#"{"enabled":true,"interval_ms":1000,"max_bytes_per_tick":3000,"max_entries":2}"#).unwrap();
+        node.enqueue_peer_transfer("phone", "bulk", 13_000).unwrap();
+
+        let decode = |send: &TrickleSend| {
+            let frame = BASE64.decode(send.frame.as_ref().unwrap()).unwrap();
+            match wire::decode_frame(&frame).unwrap().unwrap().0.body {
+                Some(wire::Body::Manifest(manifest)) => manifest,
+                other => panic!("expected a manifest, got {:?}", other),
+            }
+        };
+        let sends = node.trickle_sends(0);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].chunk_bytes, 3000);
+        let manifest = decode(&sends[0]);
+        assert_eq!((manifest.since_sequence, manifest.entries.len()), (0, 2));
+
+        // Not due again before the interval; unacknowledged entries are resent after it
+        assert!(node.trickle_sends(500).iter().all(|s| s.frame.is_none()));
+        assert_eq!(decode(&node.trickle_sends(1000)[0]).since_sequence, 0);
+        node.ack_trickle_sequence("phone", manifest.sequence);
+        let next = decode(&node.trickle_sends(2000)[0]);
+        assert_eq!((next.since_sequence, next.entries.len()), (manifest.sequence, 2));
+
+        // Caught up: only the remaining attachment bytes go out
+        node.ack_trickle_sequence("phone", node.change_journal.sequence());
+        let sends = node.trickle_sends(3000);
+        assert_eq!(sends, vec![TrickleSend { peer_id: "phone".into(), frame: None, chunk_bytes: 1000 }]);
+    }
+}
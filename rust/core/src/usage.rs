@@ -0,0 +1,200 @@
+//! Vault size and storage budget
+//!
+//! The journal knows the size of every live file, so it can say where the
+//! bytes are: per folder (each folder counts everything beneath it) and per
+//! file type. A device can also set a local storage budget in its profile;
+//! once the files it holds reach the budget, transfer planning stops pulling
+//! attachments (notes keep syncing, they are what the budget is for).
+//!
+//! Content hashes also reveal duplicates: the same PDF saved five times is
+//! synced five times.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sync::ChangeJournal;
+
+use crate::attachments::{AttachmentPolicy, TransferOrder};
+use crate::profiles::SyncProfile;
+use crate::P2PNode;
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UsageTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    /// Keyed by folder path; `""` is the vault root
+    pub by_folder: BTreeMap<String, UsageTotals>,
+    /// Keyed by lowercase extension; `""` for files without one
+    pub by_type: BTreeMap<String, UsageTotals>,
+    /// Bytes of the files this device's profile holds locally
+    pub local_bytes: u64,
+    pub budget_bytes: Option<u64>,
+}
+
+fn extension(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_ascii_lowercase(),
+        _ => String::new(),
+    }
+}
+
+pub fn usage_report(journal: &ChangeJournal, profile: &SyncProfile) -> UsageReport {
+    let mut report = UsageReport { budget_bytes: profile.storage_budget_bytes, ..Default::default() };
+    for meta in journal.files().filter(|m| !m.is_deleted) {
+        report.total.add(meta.size);
+        report.by_type.entry(extension(&meta.path)).or_default().add(meta.size);
+        report.by_folder.entry(String::new()).or_default().add(meta.size);
+        for (i, _) in meta.path.match_indices('/') {
+            report.by_folder.entry(meta.path[..i].to_string()).or_default().add(meta.size);
+        }
+        if profile.wants(&meta.path, meta.size) {
+            report.local_bytes += meta.size;
+        }
+    }
+    report
+}
+
+/// Live files sharing one content hash
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+    /// Bytes beyond the first copy
+    pub wasted_bytes: u64,
+}
+
+/// Groups of non-empty live files with identical content, most wasted bytes first
+pub fn find_duplicates(journal: &ChangeJournal) -> Vec<DuplicateGroup> {
+    let mut by_hash: HashMap<String, DuplicateGroup> = HashMap::new();
+    for meta in journal.files().filter(|m| !m.is_deleted && m.size > 0) {
+        by_hash
+            .entry(meta.hash.clone())
+            .or_insert_with(|| DuplicateGroup { hash: meta.hash, size: meta.size, paths: Vec::new(), wasted_bytes: 0 })
+            .paths
+            .push(meta.path);
+    }
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_values()
+        .filter(|g| g.paths.len() > 1)
+        .map(|mut g| {
+            g.paths.sort();
+            g.wasted_bytes = g.size * (g.paths.len() as u64 - 1);
+            g
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted_bytes.cmp(&a.wasted_bytes).then_with(|| a.paths.cmp(&b.paths)));
+    groups
+}
+
+/// Bytes of live files held under `profile`
+pub fn local_bytes(journal: &ChangeJournal, profile: &SyncProfile) -> u64 {
+    journal.files().filter(|m| !m.is_deleted && profile.wants(&m.path, m.size)).map(|m| m.size).sum()
+}
+
+/// Defer the attachments in `order` that would take local storage past `budget`
+pub fn apply_budget(
+    order: &mut TransferOrder,
+    sizes: &BTreeMap<String, u64>,
+    attachments: &AttachmentPolicy,
+    mut used: u64,
+    budget: u64,
+) {
+    let mut kept = Vec::with_capacity(order.transfer.len());
+    for path in order.transfer.drain(..) {
+        let size = sizes.get(&path).copied().unwrap_or(0);
+        if !attachments.is_attachment(&path) {
+            kept.push(path);
+        } else if used.saturating_add(size) <= budget {
+            used += size;
+            kept.push(path);
+        } else {
+            order.deferred.push(path);
+        }
+    }
+    order.transfer = kept;
+}
+
+impl P2PNode {
+    /// Bytes per folder and per file type, with local usage against the budget, as JSON
+    pub fn get_usage_report(&self) -> String {
+        serde_json::to_string(&usage_report(&self.change_journal, &self.profile)).unwrap_or_default()
+    }
+
+    /// Groups of paths with identical content (`{hash, size, paths, wasted_bytes}`) as JSON
+    pub fn find_duplicates(&self) -> String {
+        serde_json::to_string(&find_duplicates(&self.change_journal)).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::AttachmentLayout;
+
+    #[test]
+    fn test_report_and_budget() {
+        let mut journal = ChangeJournal::new();
+        journal.update_file("Notes/a.md".to_string(), b"aaaa", 1, "laptop".to_string());
+        journal.update_file("Notes/Sub/b.MD".to_string(), b"bb", 1, "laptop".to_string());
+        journal.update_file("Files/doc.pdf".to_string(), &[0; 100], 1, "laptop".to_string());
+        journal.update_file(".hidden".to_string(), b"x", 1, "laptop".to_string());
+        journal.update_file("gone.md".to_string(), b"x", 1, "laptop".to_string());
+        journal.mark_deleted("gone.md".to_string(), 2, "laptop".to_string());
+
+        let profile = SyncProfile { exclude_folders: vec!["Files".to_string()], ..SyncProfile::full() };
+        let report = usage_report(&journal, &profile);
+        assert_eq!(report.total, UsageTotals { files: 4, bytes: 107 });
+        assert_eq!(report.by_folder["Notes"], UsageTotals { files: 2, bytes: 6 });
+        assert_eq!(report.by_folder["Notes/Sub"].bytes, 2);
+        assert_eq!(report.by_folder[""].files, 4);
+        assert_eq!(report.by_type["md"].files, 2);
+        assert_eq!(report.by_type[""].bytes, 1);
+        assert_eq!(report.local_bytes, 7);
+        assert_eq!(local_bytes(&journal, &profile), 7);
+
+        let policy = AttachmentPolicy { layout: AttachmentLayout::SameFolderAsNote, ..Default::default() };
+        let mut order = TransferOrder {
+            transfer: vec!["a.png".to_string(), "b.md".to_string(), "c.png".to_string(), "d.png".to_string()],
+            ..Default::default()
+        };
+        let sizes = BTreeMap::from([("a.png".to_string(), 40), ("b.md".to_string(), 500), ("c.png".to_string(), 80), ("d.png".to_string(), 50)]);
+        apply_budget(&mut order, &sizes, &policy, 7, 100);
+        assert_eq!(order.transfer, vec!["a.png", "b.md", "d.png"]);
+        assert_eq!(order.deferred, vec!["c.png"]);
+    }
+
+    #[test]
+    fn test_duplicate_groups() {
+        let mut journal = ChangeJournal::new();
+        let pdf = vec![7; 40];
+        for path in ["b/copy.pdf", "a/paper.pdf", "c/paper (1).pdf"] {
+            journal.update_file(path.to_string(), &pdf, 1, "laptop".to_string());
+        }
+        journal.update_file("x.md".to_string(), b"same", 1, "laptop".to_string());
+        journal.update_file("y.md".to_string(), b"same", 1, "laptop".to_string());
+        journal.update_file("z.md".to_string(), b"same", 1, "laptop".to_string());
+        journal.mark_deleted("z.md".to_string(), 2, "laptop".to_string());
+        journal.update_file("empty1.md".to_string(), b"", 1, "laptop".to_string());
+        journal.update_file("empty2.md".to_string(), b"", 1, "laptop".to_string());
+
+        let groups = find_duplicates(&journal);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].paths, vec!["a/paper.pdf", "b/copy.pdf", "c/paper (1).pdf"]);
+        assert_eq!(groups[0].wasted_bytes, 80);
+        assert_eq!(groups[1].paths, vec!["x.md", "y.md"]);
+    }
+}
@@ -0,0 +1,350 @@
+//! Adapter for Obsidian's vault events
+//!
+//! Obsidian reports `create`, `modify`, `delete` and `rename` events, and
+//! they don't map one-to-one onto journal operations: typing fires a storm
+//! of `modify` events, a rename is a single event carrying the old path (and
+//! a folder rename may or may not be followed by one event per file), and a
+//! file can be created, renamed and deleted before any of it matters. The
+//! host passes the raw events to `push_vault_events`, one at a time or in
+//! batches, and the adapter keeps one pending operation per path:
+//!
+//! - `modify`/`create` replace the path's pending content and restart its
+//!   quiet period, so a storm becomes one update once it stops;
+//! - `rename` moves the pending operation to the new path and remembers the
+//!   path the journal knows, so A → B → C becomes one move A → C, and a file
+//!   renamed back to where it was becomes no move at all;
+//! - `delete` of a renamed file deletes the path the journal knows, and a
+//!   file created and deleted between flushes leaves nothing behind.
+//!
+//! `flush_vault_events` applies the operations whose quiet period has passed
+//! and lists the journal operations it performed; moves are recorded with
+//! `rename_file`.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::limits::{check_size, MAX_COMMAND_BATCH_BYTES};
+use crate::P2PNode;
+
+/// Default quiet period after the last `modify` of a path
+pub const DEFAULT_QUIET_MS: u64 = 500;
+
+/// A raw vault event; new content comes as `content_b64` or as an already computed `hash` and `size`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VaultEvent {
+    Create {
+        path: String,
+        #[serde(flatten)]
+        content: EventContent,
+        mtime: u64,
+    },
+    Modify {
+        path: String,
+        #[serde(flatten)]
+        content: EventContent,
+        mtime: u64,
+    },
+    Delete {
+        path: String,
+        mtime: u64,
+    },
+    Rename {
+        path: String,
+        old_path: String,
+        mtime: u64,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct EventContent {
+    #[serde(default)]
+    content_b64: Option<String>,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EventBatch {
+    One(VaultEvent),
+    Many(Vec<VaultEvent>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Content {
+    hash: String,
+    size: u64,
+}
+
+/// What is pending for one current path
+#[derive(Clone, Debug, Default)]
+struct Pending {
+    /// Path the journal knows this file by, when it has been renamed since
+    origin: Option<String>,
+    /// New content, if it changed
+    content: Option<Content>,
+    deleted: bool,
+    mtime: u64,
+    due_at: u64,
+}
+
+/// A journal operation performed by a flush
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AppliedEvent {
+    Update { path: String, changed: bool },
+    Rename { old_path: String, path: String },
+    Delete { path: String, changed: bool },
+}
+
+#[derive(Debug)]
+pub struct VaultEventAdapter {
+    quiet_ms: u64,
+    pending: BTreeMap<String, Pending>,
+}
+
+impl Default for VaultEventAdapter {
+    fn default() -> Self {
+        VaultEventAdapter { quiet_ms: DEFAULT_QUIET_MS, pending: BTreeMap::new() }
+    }
+}
+
+impl VaultEventAdapter {
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Paths due at `now` (all of them for `None`), renames first so their old paths are free
+    fn take_due(&mut self, now: Option<u64>) -> Vec<(String, Pending)> {
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.is_none_or(|now| p.due_at <= now))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut out: Vec<(String, Pending)> =
+            due.into_iter().filter_map(|path| self.pending.remove(&path).map(|p| (path, p))).collect();
+        out.sort_by_key(|(_, p)| p.origin.is_none());
+        out
+    }
+}
+
+impl P2PNode {
+    fn event_content(&self, path: &str, content: EventContent) -> Result<Content, String> {
+        match content {
+            EventContent { content_b64: Some(b64), .. } => {
+                let bytes = BASE64.decode(b64).map_err(|e| format!("Invalid base64 content for {}: {}", path, e))?;
+                Ok(Content { hash: self.change_journal.content_hash(path, &bytes), size: bytes.len() as u64 })
+            }
+            EventContent { hash: Some(hash), size: Some(size), .. } => Ok(Content { hash, size }),
+            _ => Err(format!("Event for {} has neither content_b64 nor hash and size", path)),
+        }
+    }
+
+    /// Whether the journal has a live entry for `path`
+    fn journal_has(&self, path: &str) -> bool {
+        self.change_journal.get(path).is_some_and(|meta| !meta.is_deleted)
+    }
+
+    pub(crate) fn push_vault_event(&mut self, event: VaultEvent, now: u64) -> Result<(), String> {
+        let quiet = self.vault_events.quiet_ms;
+        match event {
+            VaultEvent::Create { path, content, mtime } | VaultEvent::Modify { path, content, mtime } => {
+                let content = self.event_content(&path, content)?;
+                let pending = self.vault_events.pending.entry(path).or_default();
+                pending.content = Some(content);
+                pending.deleted = false;
+                pending.mtime = mtime;
+                pending.due_at = now.saturating_add(quiet);
+            }
+            VaultEvent::Delete { path, mtime } => {
+                let pending = self.vault_events.pending.remove(&path).unwrap_or_default();
+                let known = pending.origin.unwrap_or(path);
+                if self.journal_has(&known) {
+                    let delete = Pending { deleted: true, mtime, due_at: now, ..Default::default() };
+                    self.vault_events.pending.insert(known, delete);
+                }
+            }
+            VaultEvent::Rename { path, old_path, mtime } => {
+                if path == old_path {
+                    return Ok(());
+                }
+                let already_moved = self.vault_events.pending.get(&path).is_some_and(|p| p.origin.as_ref() == Some(&old_path));
+                if already_moved {
+                    return Ok(());
+                }
+                let folder = format!("{}/", old_path.trim_end_matches('/'));
+                let moved = match self.vault_events.pending.remove(&old_path) {
+                    Some(pending) => Some(pending),
+                    None if self.journal_has(&old_path) => Some(Pending { origin: Some(old_path.clone()), ..Default::default() }),
+                    None => None,
+                };
+                match moved {
+                    Some(mut pending) => {
+                        if pending.origin.as_deref() == Some(path.as_str()) {
+                            pending.origin = None;
+                        }
+                        pending.mtime = mtime;
+                        pending.due_at = now.saturating_add(quiet);
+                        if pending.origin.is_some() || pending.content.is_some() || pending.deleted {
+                            self.vault_events.pending.insert(path, pending);
+                        }
+                    }
+                    // A folder: move every file under it, journal entries and pending ones alike
+                    None => {
+                        let mut children: Vec<String> =
+                            self.vault_events.pending.keys().filter(|p| p.starts_with(&folder)).cloned().collect();
+                        children.extend(
+                            self.change_journal.files().filter(|m| !m.is_deleted && m.path.starts_with(&folder)).map(|m| m.path),
+                        );
+                        children.sort();
+                        children.dedup();
+                        let target = format!("{}/", path.trim_end_matches('/'));
+                        for child in children {
+                            let renamed = format!("{}{}", target, &child[folder.len()..]);
+                            self.push_vault_event(VaultEvent::Rename { path: renamed, old_path: child, mtime }, now)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply due operations (all of them for `None`) to the journal
+    pub(crate) fn flush_vault_events_at(&mut self, now: Option<u64>) -> Vec<AppliedEvent> {
+        let mut applied = Vec::new();
+        for (path, pending) in self.vault_events.take_due(now) {
+            if pending.deleted {
+                let changed = self.mark_file_deleted(path.clone(), pending.mtime);
+                applied.push(AppliedEvent::Delete { path, changed });
+                continue;
+            }
+            if let Some(origin) = pending.origin {
+                self.rename_file(origin.clone(), path.clone(), pending.mtime);
+                applied.push(AppliedEvent::Rename { old_path: origin, path: path.clone() });
+            }
+            if let Some(content) = pending.content {
+                let changed = self.record_local_change(path.clone(), content.hash, content.size, pending.mtime);
+                applied.push(AppliedEvent::Update { path, changed });
+            }
+        }
+        applied
+    }
+}
+
+impl P2PNode {
+    /// Queue raw vault events: one `{type, path, ...}` object or an array of them;
+    /// `type` is `create`, `modify`, `delete` or `rename` (with `old_path`)
+    pub fn push_vault_events(&mut self, events_json: &str, now: u64) -> Result<usize, String> {
+        let events = match check_size("Vault events", events_json, MAX_COMMAND_BATCH_BYTES)
+            .and_then(|_| serde_json::from_str(events_json).map_err(|e| format!("Invalid vault events: {}", e).into()))
+        {
+            Ok(EventBatch::One(event)) => vec![event],
+            Ok(EventBatch::Many(events)) => events,
+            Err(e) => return Err(self.record_error(e)),
+        };
+        for event in events {
+            self.push_vault_event(event, now).map_err(|e| self.record_error(e))?;
+        }
+        Ok(self.vault_events.len())
+    }
+
+    /// Apply the operations whose quiet period has passed; returns the journal
+    /// operations performed as a JSON array of `{op, path, ...}`
+    pub fn flush_vault_events(&mut self, now: u64) -> String {
+        serde_json::to_string(&self.flush_vault_events_at(Some(now))).unwrap_or_default()
+    }
+
+    /// Apply every queued operation now (e.g. before saving state)
+    pub fn flush_all_vault_events(&mut self) -> String {
+        serde_json::to_string(&self.flush_vault_events_at(None)).unwrap_or_default()
+    }
+
+    pub fn get_pending_vault_event_count(&self) -> usize {
+        self.vault_events.len()
+    }
+
+    /// Quiet period after the last `modify` of a path before it is recorded
+    pub fn set_vault_event_quiet_ms(&mut self, quiet_ms: u64) {
+        self.vault_events.quiet_ms = quiet_ms;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modify(path: &str, content: &str, mtime: u64) -> String {
+        format!(r#"{{"type":"modify","path":"{}","content_b64":"{}","mtime":{}}}"#, path, BASE64.encode(content), mtime)
+    }
+
+    fn rename(old_path: &str, path: &str) -> String {
+        format!(r#"{{"type":"rename","old_path":"{}","path":"{}","mtime":9}}"#, old_path, path)
+    }
+
+    fn flush(node: &mut P2PNode, now: u64) -> Vec<serde_json::Value> {
+        serde_json::from_str(&node.flush_vault_events(now)).unwrap()
+    }
+
+    #[test]
+    fn test_modify_storm_becomes_one_update() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        for (i, now) in [0, 100, 200, 300].into_iter().enumerate() {
+            node.push_vault_events(&modify("a.md", &"x".repeat(i + 1), now), now).unwrap();
+        }
+        assert!(flush(&mut node, 700).is_empty());
+        let applied = flush(&mut node, 800);
+        assert_eq!(applied.len(), 1);
+        assert_eq!(node.change_journal.get("a.md").unwrap().size, 4);
+        assert_eq!(node.change_journal.sequence(), 1);
+    }
+
+    #[test]
+    fn test_renames_coalesce_into_journal_moves() {
+        let mut node = P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080);
+        node.update_file("a.md".to_string(), b"note", 1);
+        node.update_file("Old/x.md".to_string(), b"x", 1);
+        node.update_file("Old/y.md".to_string(), b"y", 1);
+        let batch = format!(
+            "[{},{},{},{},{}]",
+            rename("a.md", "b.md"),
+            rename("b.md", "c.md"),
+            rename("Old", "New"),
+            // Obsidian may follow a folder rename with one event per file
+            rename("Old/x.md", "New/x.md"),
+            r#"{"type":"create","path":"tmp.md","hash":"h","size":1,"mtime":9}"#
+        );
+        node.push_vault_events(&batch, 0).unwrap();
+        node.push_vault_events(r#"{"type":"delete","path":"tmp.md","mtime":9}"#, 0).unwrap();
+        let applied: Vec<serde_json::Value> = serde_json::from_str(&node.flush_all_vault_events()).unwrap();
+        let renames: Vec<(&str, &str)> = applied
+            .iter()
+            .filter(|v| v["op"] == "rename")
+            .map(|v| (v["old_path"].as_str().unwrap(), v["path"].as_str().unwrap()))
+            .collect();
+        assert_eq!(renames, vec![("Old/x.md", "New/x.md"), ("Old/y.md", "New/y.md"), ("a.md", "c.md")]);
+
+        let journal = &node.change_journal;
+        assert!(journal.get("a.md").unwrap().is_deleted);
+        assert!(journal.get("b.md").is_none() && journal.get("tmp.md").is_none());
+        assert_eq!(journal.get("c.md").unwrap().hash, crate::sync::hash_content(b"note"));
+        assert_eq!(journal.get("c.md").unwrap().moved_from.as_deref(), Some("a.md"));
+        assert!(!journal.get("New/y.md").unwrap().is_deleted);
+
+        // A renamed file deleted before the flush deletes the path the journal knows
+        node.push_vault_events(&format!("[{},{}]", rename("c.md", "d.md"), r#"{"type":"delete","path":"d.md","mtime":10}"#), 0).unwrap();
+        node.flush_all_vault_events();
+        assert!(node.change_journal.get("c.md").unwrap().is_deleted);
+        assert!(node.change_journal.get("d.md").is_none());
+        assert_eq!(node.get_pending_vault_event_count(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+//! Write-ahead log for crash-safe journal persistence
+//!
+//! Saving the whole journal after every file event is too slow, but if
+//! Obsidian is killed between an event and the next save the change is lost.
+//! After each mutation the host takes the new WAL records (one JSON line per
+//! journal version) and appends them to a file right away; when it saves a
+//! full checkpoint it can truncate that file. On startup it loads the last
+//! checkpoint and replays the WAL over it. Records carry their journal
+//! sequence number, so replay skips what the checkpoint already has and a
+//! record is never applied twice; a torn final line from a crash mid-append
+//! is ignored.
+
+
+use crate::sync::FileMetadata;
+use crate::P2PNode;
+
+/// Parse WAL text into records, ignoring a torn (incomplete) final line
+pub fn parse_records(wal: &str) -> Result<Vec<FileMetadata>, String> {
+    let complete = match wal.rfind('\n') {
+        Some(end) => &wal[..end],
+        None => "",
+    };
+    complete
+        .lines()
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Invalid WAL record {}: {}", i + 1, e)))
+        .collect()
+}
+
+impl P2PNode {
+    /// Records for the journal versions written since the last call, as
+    /// newline-terminated JSON lines to append to the WAL (empty if none)
+    pub fn take_wal_records(&mut self) -> Result<String, String> {
+        self.wal_records().map_err(|e| self.record_error(e))
+    }
+
+    /// Replay WAL text over the loaded checkpoint; returns the number of records applied
+    pub fn replay_wal(&mut self, wal: &str) -> Result<usize, String> {
+        self.apply_wal(wal).map_err(|e| self.record_error(e))
+    }
+}
+
+impl P2PNode {
+    pub(crate) fn wal_records(&mut self) -> Result<String, String> {
+        if self.change_journal.in_transaction() {
+            return Err("Cannot take WAL records while a transaction is open".to_string());
+        }
+        let mut records: Vec<FileMetadata> =
+            self.change_journal.history().rev().take_while(|m| m.version > self.wal_sequence).collect();
+        records.reverse();
+        // History doesn't keep renames or extended metadata; the current entry has them
+        for record in &mut records {
+            if let Some(current) = self.change_journal.get(&record.path).filter(|c| c.version == record.version) {
+                *record = current;
+            }
+        }
+        // Versions are consecutive, so a gap means history was capped before the records were taken
+        if let Some(first) = records.first() {
+            if first.version != self.wal_sequence + 1 {
+                return Err(format!(
+                    "WAL fell behind: versions {} to {} are no longer in history; save a checkpoint",
+                    self.wal_sequence + 1,
+                    first.version - 1
+                ));
+            }
+        }
+        let mut out = String::new();
+        for record in &records {
+            out.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+            out.push('\n');
+        }
+        self.wal_sequence = self.change_journal.sequence();
+        Ok(out)
+    }
+
+    pub(crate) fn apply_wal(&mut self, wal: &str) -> Result<usize, String> {
+        let records = parse_records(wal)?;
+        let mut applied = 0;
+        for record in records {
+            if self.change_journal.replay(record) {
+                applied += 1;
+            }
+        }
+        self.wal_sequence = self.change_journal.sequence();
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> P2PNode {
+        P2PNode::new("Laptop".to_string(), "laptop".to_string(), 8080)
+    }
+
+    #[test]
+    fn test_crash_recovery_replays_once() {
+        let mut live = node();
+        live.update_file("a.md".to_string(), b"one", 1);
+        let checkpoint = live.get_journal_state();
+        live.wal_records().unwrap();
+
+        let mut wal = String::new();
+        live.update_file("a.md".to_string(), b"two", 2);
+        wal.push_str(&live.wal_records().unwrap());
+        live.mark_file_deleted("b.md".to_string(), 3);
+        live.update_file("c.md".to_string(), b"three", 3);
+        wal.push_str(&live.wal_records().unwrap());
+        assert_eq!(live.wal_records().unwrap(), "");
+        assert_eq!(wal.lines().count(), 3);
+
+        // Killed mid-append: the last line is torn
+        let torn = format!("{}{{\"path\":\"d.md\",\"ha", wal);
+        let mut restarted = node();
+        restarted.load_journal_state(&checkpoint).unwrap();
+        assert_eq!(restarted.apply_wal(&torn).unwrap(), 3);
+        assert_eq!(restarted.apply_wal(&wal).unwrap(), 0);
+        assert_eq!(restarted.change_journal.sequence(), live.change_journal.sequence());
+        assert_eq!(restarted.change_journal.get("c.md"), live.change_journal.get("c.md"));
+        // The checkpoint carries no history, so only the replayed versions are in it
+        assert_eq!(restarted.change_journal.history().count(), 3);
+
+        // Only versions after the replay are emitted again
+        restarted.update_file("e.md".to_string(), b"e", 4);
+        assert_eq!(restarted.wal_records().unwrap().lines().count(), 1);
+        assert!(parse_records("{\"bad\":1}\n").is_err());
+    }
+}
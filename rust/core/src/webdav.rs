@@ -0,0 +1,415 @@
+//! WebDAV mirror backend
+//!
+//! Mirrors the journal to a WebDAV share (Nextcloud, ownCloud, Apache
+//! mod_dav…) so an always-on box can hold a copy of the vault. The mirror
+//! plans PROPFIND/MKCOL/PUT/DELETE requests and interprets the responses;
+//! writes carry `If-Match` with the last seen ETag (or `If-None-Match: *`
+//! for new files) so changes made on the share in the meantime surface as
+//! conflicts instead of being silently overwritten.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::backend::{decode_path, encode_path, plan_mirror, HttpRequest, HttpResponse, MirrorAction};
+use crate::limits::{check_size, MAX_JOURNAL_BYTES};
+use crate::sync::FileMetadata;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// What we last knew about a file on the share
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct RemoteFile {
+    etag: Option<String>,
+    /// Content hash, known only for versions we uploaded ourselves
+    hash: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Pending {
+    List { dir: String },
+    MakeCollection { dir: String },
+    Put { path: String, hash: String },
+    Delete { path: String },
+}
+
+/// Result of feeding one response back
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WebDavOutcome {
+    /// Requests to perform next (e.g. listing subfolders)
+    pub follow_up: Vec<HttpRequest>,
+    /// Paths changed on the share since we last looked
+    pub conflicts: Vec<String>,
+    /// `path: status` for requests that failed outright
+    pub failed: Vec<String>,
+    /// No requests left outstanding
+    pub idle: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebDavMirror {
+    base_url: String,
+    files: HashMap<String, RemoteFile>,
+    collections: BTreeSet<String>,
+    #[serde(skip)]
+    pending: HashMap<u64, Pending>,
+    #[serde(skip)]
+    next_id: u64,
+    /// Paths seen by the refresh in progress
+    #[serde(skip)]
+    seen: Option<HashSet<String>>,
+}
+
+impl WebDavMirror {
+    /// `base_url` is the vault folder on the share, e.g. `https://cloud/remote.php/dav/files/me/Vault`
+    pub fn new(base_url: String) -> WebDavMirror {
+        WebDavMirror {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            files: HashMap::new(),
+            collections: BTreeSet::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+            seen: None,
+        }
+    }
+
+    /// Start listing the share; follow-up listings arrive via `handle_response`
+    pub fn plan_refresh(&mut self) -> String {
+        self.seen = Some(HashSet::new());
+        let request = self.propfind(String::new());
+        serde_json::to_string(&[request]).unwrap_or_default()
+    }
+
+    /// Requests that bring the share in line with `files_json` (journal metadata array)
+    pub fn plan_sync(&mut self, files_json: &str) -> Result<String, String> {
+        check_size("Journal files", files_json, MAX_JOURNAL_BYTES)?;
+        let files: Vec<FileMetadata> = serde_json::from_str(files_json)
+            .map_err(|e| format!("Invalid file list: {}", e))?;
+        let requests = self.plan_requests(&files);
+        serde_json::to_string(&requests).map_err(|e| e.to_string())
+    }
+
+    /// Feed back `{id, status, headers, body}` for a planned request
+    /// Returns `{follow_up, conflicts, failed, idle}` as JSON
+    pub fn handle_response(&mut self, response_json: &str) -> Result<String, String> {
+        check_size("WebDAV response", response_json, MAX_JOURNAL_BYTES)?;
+        let response: HttpResponse = serde_json::from_str(response_json)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+        let outcome = self.apply_response(response)?;
+        serde_json::to_string(&outcome).map_err(|e| e.to_string())
+    }
+
+    /// Requests planned but not yet answered
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Export what is known about the share for persistence
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn from_json(json: &str) -> Result<WebDavMirror, String> {
+        check_size("WebDAV state", json, MAX_JOURNAL_BYTES)?;
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+}
+
+impl WebDavMirror {
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, encode_path(path))
+    }
+
+    fn request(&mut self, method: &str, path: &str, pending: Pending) -> HttpRequest {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, pending);
+        HttpRequest {
+            id: self.next_id,
+            method: method.to_string(),
+            url: self.url(path),
+            headers: BTreeMap::new(),
+            body: None,
+            body_from_path: None,
+        }
+    }
+
+    fn propfind(&mut self, dir: String) -> HttpRequest {
+        let path = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let mut request = self.request("PROPFIND", &path, Pending::List { dir });
+        request.headers.insert("Depth".to_string(), "1".to_string());
+        request.headers.insert("Content-Type".to_string(), "application/xml; charset=utf-8".to_string());
+        request.body = Some(PROPFIND_BODY.to_string());
+        request
+    }
+
+    /// Folders first (shallowest first), then uploads, then deletions
+    pub fn plan_requests(&mut self, files: &[FileMetadata]) -> Vec<HttpRequest> {
+        let remote: HashMap<String, String> = self
+            .files
+            .iter()
+            .map(|(path, file)| (path.clone(), file.hash.clone().unwrap_or_default()))
+            .collect();
+        let actions = plan_mirror(files.iter(), &remote);
+
+        let mut missing_dirs = BTreeSet::new();
+        for action in &actions {
+            if let MirrorAction::Put { path, .. } = action {
+                let mut dir = path.as_str();
+                while let Some((parent, _)) = dir.rsplit_once('/') {
+                    if !self.collections.contains(parent) {
+                        missing_dirs.insert(parent.to_string());
+                    }
+                    dir = parent;
+                }
+            }
+        }
+        let mut dirs: Vec<String> = missing_dirs.into_iter().collect();
+        dirs.sort_by_key(|d| d.matches('/').count());
+
+        let mut requests = Vec::new();
+        for dir in dirs {
+            let path = format!("{}/", dir);
+            requests.push(self.request("MKCOL", &path, Pending::MakeCollection { dir }));
+        }
+        for action in actions {
+            let request = match action {
+                MirrorAction::Put { path, hash, .. } => {
+                    let etag = self.files.get(&path).and_then(|f| f.etag.clone());
+                    let mut request = self.request("PUT", &path, Pending::Put { path: path.clone(), hash });
+                    match etag {
+                        Some(etag) => request.headers.insert("If-Match".to_string(), etag),
+                        None => request.headers.insert("If-None-Match".to_string(), "*".to_string()),
+                    };
+                    request.body_from_path = Some(path);
+                    request
+                }
+                MirrorAction::Delete { path } => {
+                    let etag = self.files.get(&path).and_then(|f| f.etag.clone());
+                    let mut request = self.request("DELETE", &path, Pending::Delete { path: path.clone() });
+                    if let Some(etag) = etag {
+                        request.headers.insert("If-Match".to_string(), etag);
+                    }
+                    request
+                }
+            };
+            requests.push(request);
+        }
+        requests
+    }
+
+    pub fn apply_response(&mut self, response: HttpResponse) -> Result<WebDavOutcome, String> {
+        let pending = self
+            .pending
+            .remove(&response.id)
+            .ok_or_else(|| format!("Unknown request id: {}", response.id))?;
+        let mut outcome = WebDavOutcome::default();
+        let status = response.status;
+        match pending {
+            Pending::List { dir } => {
+                if status == 207 {
+                    self.apply_listing(&dir, &response.body, &mut outcome);
+                } else {
+                    outcome.failed.push(format!("{}/: {}", dir, status));
+                }
+            }
+            Pending::MakeCollection { dir } => {
+                // 405: the collection already exists
+                if response.is_success() || status == 405 {
+                    self.collections.insert(dir);
+                } else {
+                    outcome.failed.push(format!("{}/: {}", dir, status));
+                }
+            }
+            Pending::Put { path, hash } => {
+                if response.is_success() {
+                    let etag = response.header("ETag").map(str::to_string);
+                    self.files.insert(path, RemoteFile { etag, hash: Some(hash) });
+                } else if status == 412 {
+                    self.forget_hash(&path);
+                    outcome.conflicts.push(path);
+                } else {
+                    outcome.failed.push(format!("{}: {}", path, status));
+                }
+            }
+            Pending::Delete { path } => {
+                if response.is_success() || status == 404 {
+                    self.files.remove(&path);
+                } else if status == 412 {
+                    self.forget_hash(&path);
+                    outcome.conflicts.push(path);
+                } else {
+                    outcome.failed.push(format!("{}: {}", path, status));
+                }
+            }
+        }
+        self.finish_refresh_if_done();
+        outcome.idle = self.pending.is_empty();
+        Ok(outcome)
+    }
+
+    fn forget_hash(&mut self, path: &str) {
+        if let Some(file) = self.files.get_mut(path) {
+            file.hash = None;
+        }
+    }
+
+    fn apply_listing(&mut self, dir: &str, body: &str, outcome: &mut WebDavOutcome) {
+        let base_path = url_path(&self.base_url);
+        for response in elements(body, "response") {
+            let Some(href) = elements(response, "href").into_iter().next() else {
+                continue;
+            };
+            let href = decode_path(&xml_unescape(href.trim()));
+            let path = url_path(&href);
+            let Some(relative) = path.strip_prefix(base_path.as_str()).filter(|r| r.is_empty() || r.starts_with('/')) else {
+                continue;
+            };
+            let relative = relative.trim_matches('/').to_string();
+            if relative == dir {
+                continue; // The listed folder itself
+            }
+            let is_collection = elements(response, "resourcetype")
+                .first()
+                .is_some_and(|t| !elements(t, "collection").is_empty());
+            if is_collection {
+                self.collections.insert(relative.clone());
+                outcome.follow_up.push(self.propfind(relative));
+                continue;
+            }
+            let etag = elements(response, "getetag").first().map(|e| xml_unescape(e.trim()));
+            if let Some(seen) = &mut self.seen {
+                seen.insert(relative.clone());
+            }
+            let known = self.files.get(&relative);
+            // Keep the hash while the ETag is unchanged (or was unknown after our upload)
+            let hash = known
+                .filter(|f| f.etag.is_none() || f.etag == etag)
+                .and_then(|f| f.hash.clone());
+            self.files.insert(relative, RemoteFile { etag, hash });
+        }
+    }
+
+    /// Once every listing has answered, forget files the share no longer has
+    fn finish_refresh_if_done(&mut self) {
+        let listing = self.pending.values().any(|p| matches!(p, Pending::List { .. }));
+        if listing {
+            return;
+        }
+        if let Some(seen) = self.seen.take() {
+            self.files.retain(|path, _| seen.contains(path));
+        }
+    }
+}
+
+/// Path component of a URL or absolute href
+fn url_path(url: &str) -> String {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = if url.contains("://") {
+        without_scheme.find('/').map_or("/", |i| &without_scheme[i..])
+    } else {
+        without_scheme
+    };
+    path.trim_end_matches('/').to_string()
+}
+
+/// Inner text of every element with this local name (namespace prefix ignored)
+fn elements<'a>(xml: &'a str, local_name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find(['>', ' ', '\t', '\r', '\n', '/']).unwrap_or(rest.len());
+        let name = &rest[..end];
+        if name.starts_with(['/', '?', '!']) || name.is_empty() {
+            continue;
+        }
+        let local = name.rsplit(':').next().unwrap_or(name);
+        let Some(close) = rest.find('>') else {
+            break;
+        };
+        if local != local_name {
+            continue;
+        }
+        if rest[..close].ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let inner = &rest[close + 1..];
+        let closing = format!("</{}>", name);
+        if let Some(end) = inner.find(&closing) {
+            found.push(&inner[..end]);
+            rest = &inner[end + closing.len()..];
+        }
+    }
+    found
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(path: &str, hash: &str) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            mtime: 0,
+            size: 1,
+            version: 1,
+            is_deleted: false,
+            last_modified_by: "dev".to_string(),
+            moved_from: None,
+            extended: Default::default(),
+        }
+    }
+
+    fn respond(mirror: &mut WebDavMirror, id: u64, status: u16, etag: Option<&str>, body: &str) -> WebDavOutcome {
+        let headers = etag.map(|e| ("etag".to_string(), e.to_string())).into_iter().collect();
+        mirror.apply_response(HttpResponse { id, status, headers, body: body.to_string() }).unwrap()
+    }
+
+    #[test]
+    fn test_refresh_then_conditional_sync() {
+        let mut mirror = WebDavMirror::new("https://cloud.example/dav/Vault/".to_string());
+        mirror.plan_refresh();
+        let listing = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+              <d:response><d:href>/dav/Vault/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+              <d:response><d:href>/dav/Vault/Daily%20notes/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+              <d:response><d:href>https://cloud.example/dav/Vault/a.md</d:href><d:propstat><d:prop><d:getetag>&quot;e1&quot;</d:getetag><d:resourcetype/></d:prop></d:propstat></d:response>
+            </d:multistatus>"#;
+        let outcome = respond(&mut mirror, 1, 207, None, listing);
+        assert_eq!(outcome.follow_up.len(), 1);
+        assert_eq!(outcome.follow_up[0].url, "https://cloud.example/dav/Vault/Daily%20notes/");
+        assert!(respond(&mut mirror, 2, 207, None, "<d:multistatus xmlns:d=\"DAV:\"/>").idle);
+
+        let requests = mirror.plan_requests(&[meta("a.md", "h1"), meta("Daily notes/x.md", "h2"), meta("New/b.md", "h3")]);
+        let summary: Vec<(&str, &str)> = requests.iter().map(|r| (r.method.as_str(), r.url.rsplit("Vault/").next().unwrap())).collect();
+        assert_eq!(
+            summary,
+            vec![("MKCOL", "New/"), ("PUT", "Daily%20notes/x.md"), ("PUT", "New/b.md"), ("PUT", "a.md")]
+        );
+        assert_eq!(requests[3].headers.get("If-Match").map(String::as_str), Some("\"e1\""));
+        assert_eq!(requests[1].headers.get("If-None-Match").map(String::as_str), Some("*"));
+
+        for request in &requests[..3] {
+            respond(&mut mirror, request.id, 201, Some("\"new\""), "");
+        }
+        let outcome = respond(&mut mirror, requests[3].id, 412, None, "");
+        assert_eq!(outcome.conflicts, vec!["a.md".to_string()]);
+        assert!(outcome.idle);
+
+        // Uploaded files are up to date; the conflicted one is planned again
+        let again = mirror.plan_requests(&[meta("a.md", "h1"), meta("Daily notes/x.md", "h2"), meta("New/b.md", "h3")]);
+        assert_eq!(again.len(), 1);
+
+        let restored = WebDavMirror::from_json(&mirror.to_json()).unwrap();
+        assert_eq!(restored.files.len(), 3);
+    }
+}
@@ -0,0 +1,966 @@
+//! Binary wire protocol
+//!
+//! Every message exchanged between peers (discovery announcements, the
+//! session handshake, journal manifests, file chunks, acknowledgements and
+//! control messages) is a protobuf message wrapped in an `Envelope`. Frames
+//! on a stream are length-delimited (a varint length, then the envelope), so
+//! unknown fields added by newer peers are skipped instead of breaking the
+//! decode. Hosts without protobuf support can speak CBOR instead: the same
+//! envelopes serialized with serde, selected through the capability flags
+//! exchanged in the handshake. JSON is kept only as a debug rendering for
+//! logs and tooling.
+//!
+//! Message-oriented transports such as WebRTC DataChannels limit the size of
+//! a single message, so frames can be split into fragments with a small
+//! header (message ID, index, count) and reassembled on the other side, in
+//! any arrival order.
+//!
+//! Once both sides advertise `CAP_COMPRESSION`, the length prefix of every
+//! frame in the session carries a flag in its lowest bit (the length is
+//! shifted left by one) marking control frames that were compressed; see
+//! `compression`. The handshake itself is always sent uncompressed.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use prost::Message;
+use serde::de::{self, IntoDeserializer, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use crate::compat;
+use crate::compression;
+use crate::error::Error;
+use crate::hashing::HashAlgorithm;
+use crate::limits::MAX_FRAME_BYTES;
+use crate::memory::{deque_bytes, map_overhead};
+use crate::sync::{ExtendedMetadata, FileMetadata};
+use crate::transfer::FileChunk;
+
+/// Wire protocol revision carried in every envelope (2: handshake signatures
+/// cover the whole transcript, see `negotiation`; 3: chunk ciphertexts are bound
+/// to their transfer, file and index, see `transfer::chunk_aad`)
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Handshake capability: envelopes may be sent as protobuf
+pub const CAP_PROTOBUF: u32 = 1 << 0;
+/// Handshake capability: envelopes may be sent as CBOR
+pub const CAP_CBOR: u32 = 1 << 1;
+/// Handshake capability: BLAKE3 content hashes can be verified
+pub const CAP_BLAKE3: u32 = 1 << 2;
+/// Handshake capability: `Cover` frames are understood (and discarded)
+pub const CAP_COVER_TRAFFIC: u32 = 1 << 3;
+/// Handshake capability: signed `Checkpoint` frames are understood
+pub const CAP_CHECKPOINTS: u32 = 1 << 4;
+/// Handshake capability: frame headers carry the compression flag
+pub const CAP_COMPRESSION: u32 = 1 << 5;
+/// Handshake capability: signed `DeviceProfile` frames are understood
+pub const CAP_DEVICE_PROFILES: u32 = 1 << 6;
+/// Encodings and hashes this build supports
+pub const LOCAL_CAPABILITIES: u32 = CAP_PROTOBUF
+    | CAP_CBOR
+    | CAP_BLAKE3
+    | CAP_COVER_TRAFFIC
+    | CAP_CHECKPOINTS
+    | CAP_COMPRESSION
+    | CAP_DEVICE_PROFILES;
+
+/// Control frames whose payload is at most this size are sent as is
+pub const COMPRESSION_THRESHOLD_BYTES: usize = 256;
+
+/// Content hash for a session: BLAKE3 only when both sides can verify it
+pub fn negotiate_hash(local: u32, remote: u32) -> HashAlgorithm {
+    if local & remote & CAP_BLAKE3 != 0 {
+        HashAlgorithm::Blake3
+    } else {
+        HashAlgorithm::Sha256
+    }
+}
+
+/// Whether frames in a session use the compression flag: only when both sides understand it
+pub fn negotiate_compression(local: u32, remote: u32) -> bool {
+    local & remote & CAP_COMPRESSION != 0
+}
+
+/// Frame payload encoding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Protobuf,
+    Cbor,
+}
+
+impl Encoding {
+    /// Pick the encoding for a session: protobuf when both sides have it, else CBOR
+    pub fn negotiate(local: u32, remote: u32) -> Option<Encoding> {
+        let shared = local & remote;
+        if shared & CAP_PROTOBUF != 0 {
+            Some(Encoding::Protobuf)
+        } else if shared & CAP_CBOR != 0 {
+            Some(Encoding::Cbor)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Encoding, Error> {
+        match name {
+            "protobuf" => Ok(Encoding::Protobuf),
+            "cbor" => Ok(Encoding::Cbor),
+            other => Err(format!("Unknown encoding: {}", other).into()),
+        }
+    }
+}
+
+/// Byte fields: base64 in human-readable formats, raw bytes in CBOR
+mod bytes_field {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.serialize_str(&BASE64.encode(bytes))
+        } else {
+            s.serialize_bytes(bytes)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            BASE64.decode(v).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(byte) = seq.next_element()? {
+                out.push(byte);
+            }
+            Ok(out)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        d.deserialize_any(BytesVisitor)
+    }
+}
+
+/// Enumeration fields: the variant name in human-readable formats, the number in CBOR
+mod control_kind {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(kind: &i32, s: S) -> Result<S::Ok, S::Error> {
+        match ControlKind::try_from(*kind) {
+            Ok(kind) if s.is_human_readable() => kind.serialize(s),
+            _ => s.serialize_i32(*kind),
+        }
+    }
+
+    struct KindVisitor;
+
+    impl Visitor<'_> for KindVisitor {
+        type Value = i32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a control kind")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<i32, E> {
+            ControlKind::deserialize(v.into_deserializer()).map(|kind| kind as i32)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<i32, E> {
+            i32::try_from(v).map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<i32, E> {
+            i32::try_from(v).map_err(E::custom)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<i32, D::Error> {
+        d.deserialize_any(KindVisitor)
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Announcement {
+    #[prost(string, tag = "1")]
+    pub peer_id: String,
+    #[prost(string, tag = "2")]
+    pub device_name: String,
+    #[prost(string, tag = "3")]
+    pub device_id: String,
+    #[prost(uint32, tag = "4")]
+    pub service_port: u32,
+    #[prost(string, tag = "5")]
+    pub profile: String,
+    /// Journal head (see `journalhead`); an empty digest advertises none
+    #[prost(uint64, tag = "6")]
+    #[serde(default)]
+    pub journal_sequence: u64,
+    #[prost(bytes = "vec", tag = "7")]
+    #[serde(default, with = "bytes_field")]
+    pub journal_digest: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Handshake {
+    #[prost(string, tag = "1")]
+    pub device_id: String,
+    /// Ed25519 identity key
+    #[prost(bytes = "vec", tag = "2")]
+    #[serde(with = "bytes_field")]
+    pub identity_key: Vec<u8>,
+    /// Ephemeral X25519 key for this session
+    #[prost(bytes = "vec", tag = "3")]
+    #[serde(with = "bytes_field")]
+    pub session_key: Vec<u8>,
+    /// Identity signature over the session key
+    #[prost(bytes = "vec", tag = "4")]
+    #[serde(with = "bytes_field")]
+    pub signature: Vec<u8>,
+    /// `CAP_*` flags for the encodings the sender accepts
+    #[prost(uint32, tag = "5")]
+    pub capabilities: u32,
+    /// `JournalSketch` of the sender's journal; equal sketches skip the manifest exchange
+    #[prost(bytes = "vec", tag = "6")]
+    #[serde(default, with = "bytes_field")]
+    pub journal_sketch: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct FileEntry {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub hash: String,
+    #[prost(uint64, tag = "3")]
+    pub mtime: u64,
+    #[prost(uint64, tag = "4")]
+    pub size: u64,
+    #[prost(uint64, tag = "5")]
+    pub version: u64,
+    #[prost(bool, tag = "6")]
+    pub is_deleted: bool,
+    #[prost(string, tag = "7")]
+    pub last_modified_by: String,
+    /// Set for a rename: the receiver can move its copy instead of downloading
+    #[prost(string, optional, tag = "8")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<String>,
+    #[prost(uint64, optional, tag = "9")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<u64>,
+    #[prost(bool, tag = "10")]
+    #[serde(default)]
+    pub executable: bool,
+    #[prost(btree_map = "string, string", tag = "11")]
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Journal entries changed after `since_sequence`
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Manifest {
+    #[prost(uint64, tag = "1")]
+    pub since_sequence: u64,
+    #[prost(uint64, tag = "2")]
+    pub sequence: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub entries: Vec<FileEntry>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Chunk {
+    #[prost(string, tag = "1")]
+    pub file_path: String,
+    #[prost(uint32, tag = "2")]
+    pub chunk_index: u32,
+    #[prost(uint32, tag = "3")]
+    pub total_chunks: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    #[serde(with = "bytes_field")]
+    pub data: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    #[serde(with = "bytes_field")]
+    pub nonce: Vec<u8>,
+    /// Logical stream the chunk was sent on when a transfer is split (see `streams`)
+    #[prost(uint32, tag = "6")]
+    #[serde(default)]
+    pub stream_id: u32,
+}
+
+/// Receipt for a manifest (`chunk_index` unset) or a single chunk
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Ack {
+    #[prost(string, tag = "1")]
+    pub file_path: String,
+    #[prost(uint32, optional, tag = "2")]
+    pub chunk_index: Option<u32>,
+    #[prost(uint64, tag = "3")]
+    pub sequence: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum ControlKind {
+    Unspecified = 0,
+    Ping = 1,
+    Pong = 2,
+    Cancel = 3,
+    Close = 4,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Control {
+    #[prost(enumeration = "ControlKind", tag = "1")]
+    #[serde(with = "control_kind")]
+    pub kind: i32,
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    /// Journal head carried by `Ping` and `Pong`
+    #[prost(uint64, tag = "3")]
+    #[serde(default)]
+    pub journal_sequence: u64,
+    #[prost(bytes = "vec", tag = "4")]
+    #[serde(default, with = "bytes_field")]
+    pub journal_digest: Vec<u8>,
+}
+
+/// Cover traffic: random filler the receiver discards, only sent to peers with `CAP_COVER_TRAFFIC`
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Cover {
+    #[prost(bytes = "vec", tag = "1")]
+    #[serde(with = "bytes_field")]
+    pub filler: Vec<u8>,
+}
+
+/// Signed journal root, only sent to peers with `CAP_CHECKPOINTS`
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[prost(string, tag = "1")]
+    pub device_id: String,
+    #[prost(uint64, tag = "2")]
+    pub sequence: u64,
+    /// Merkle root over the journal entries at `sequence`
+    #[prost(bytes = "vec", tag = "3")]
+    #[serde(with = "bytes_field")]
+    pub root: Vec<u8>,
+    /// Ed25519 identity key
+    #[prost(bytes = "vec", tag = "4")]
+    #[serde(with = "bytes_field")]
+    pub identity_key: Vec<u8>,
+    /// Identity signature over device, sequence and root
+    #[prost(bytes = "vec", tag = "5")]
+    #[serde(with = "bytes_field")]
+    pub signature: Vec<u8>,
+}
+
+/// A device's signed name and details, only sent to peers with `CAP_DEVICE_PROFILES`
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    #[prost(string, tag = "1")]
+    pub device_id: String,
+    /// Increases with every update; older revisions are ignored
+    #[prost(uint64, tag = "2")]
+    pub revision: u64,
+    #[prost(string, tag = "3")]
+    pub name: String,
+    #[prost(string, tag = "4")]
+    pub icon: String,
+    #[prost(string, tag = "5")]
+    pub platform: String,
+    /// Ed25519 identity key
+    #[prost(bytes = "vec", tag = "6")]
+    #[serde(with = "bytes_field")]
+    pub identity_key: Vec<u8>,
+    /// Identity signature over all the fields above
+    #[prost(bytes = "vec", tag = "7")]
+    #[serde(with = "bytes_field")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Body {
+    #[prost(message, tag = "2")]
+    Announcement(Announcement),
+    #[prost(message, tag = "3")]
+    Handshake(Handshake),
+    #[prost(message, tag = "4")]
+    Manifest(Manifest),
+    #[prost(message, tag = "5")]
+    Chunk(Chunk),
+    #[prost(message, tag = "6")]
+    Ack(Ack),
+    #[prost(message, tag = "7")]
+    Control(Control),
+    #[prost(message, tag = "8")]
+    Cover(Cover),
+    #[prost(message, tag = "9")]
+    Checkpoint(Checkpoint),
+    #[prost(message, tag = "10")]
+    DeviceProfile(DeviceProfile),
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Envelope {
+    #[prost(uint32, tag = "1")]
+    pub protocol_version: u32,
+    #[prost(oneof = "Body", tags = "2, 3, 4, 5, 6, 7, 8, 9, 10")]
+    pub body: Option<Body>,
+}
+
+impl Envelope {
+    pub fn new(body: Body) -> Envelope {
+        Envelope { protocol_version: PROTOCOL_VERSION, body: Some(body) }
+    }
+
+    /// Everything but file data and filler
+    pub fn is_control(&self) -> bool {
+        !matches!(self.body, Some(Body::Chunk(_)) | Some(Body::Cover(_)))
+    }
+
+    /// Pretty JSON for logs; not a wire format
+    pub fn to_debug_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Encode one length-delimited protobuf frame
+pub fn encode_frame(envelope: &Envelope) -> Vec<u8> {
+    envelope.encode_length_delimited_to_vec()
+}
+
+/// Encode one length-delimited frame in the negotiated encoding
+pub fn encode_frame_as(encoding: Encoding, envelope: &Envelope) -> Vec<u8> {
+    encode_session_frame(encoding, false, envelope)
+}
+
+fn encode_payload(encoding: Encoding, envelope: &Envelope) -> Vec<u8> {
+    match encoding {
+        Encoding::Protobuf => envelope.encode_to_vec(),
+        Encoding::Cbor => {
+            let mut payload = Vec::new();
+            // Writing to a Vec cannot fail
+            let _ = ciborium::into_writer(envelope, &mut payload);
+            payload
+        }
+    }
+}
+
+/// Encode one frame for a session; with `compression` negotiated, control frames
+/// above `COMPRESSION_THRESHOLD_BYTES` are compressed when that makes them smaller
+pub fn encode_session_frame(encoding: Encoding, compression: bool, envelope: &Envelope) -> Vec<u8> {
+    let mut payload = encode_payload(encoding, envelope);
+    let mut prefix = payload.len();
+    if compression {
+        let packed = (envelope.is_control() && payload.len() > COMPRESSION_THRESHOLD_BYTES).then(|| compression::compress(&payload));
+        prefix = match packed.filter(|packed| packed.len() < payload.len()) {
+            Some(packed) => {
+                payload = packed;
+                payload.len() << 1 | 1
+            }
+            None => payload.len() << 1,
+        };
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    let _ = prost::encode_length_delimiter(prefix, &mut frame);
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Decode the protobuf frame at the start of `data`; returns the envelope and the bytes
+/// consumed, or `None` when the frame is not complete yet
+pub fn decode_frame(data: &[u8]) -> Result<Option<(Envelope, usize)>, Error> {
+    decode_frame_as(Encoding::Protobuf, data)
+}
+
+pub fn decode_frame_as(encoding: Encoding, data: &[u8]) -> Result<Option<(Envelope, usize)>, Error> {
+    decode_session_frame(encoding, false, data)
+}
+
+/// Decode a frame written by `encode_session_frame` with the same `compression` setting
+pub fn decode_session_frame(encoding: Encoding, compression: bool, data: &[u8]) -> Result<Option<(Envelope, usize)>, Error> {
+    let mut cursor = data;
+    let prefix = match prost::decode_length_delimiter(&mut cursor) {
+        Ok(prefix) => prefix,
+        // A length prefix is at most 10 bytes; shorter garbage may still be incomplete
+        Err(_) if data.len() < 10 => return Ok(None),
+        Err(e) => return Err(format!("Invalid frame length: {}", e).into()),
+    };
+    let (len, compressed) = if compression { (prefix >> 1, prefix & 1 == 1) } else { (prefix, false) };
+    if len > MAX_FRAME_BYTES {
+        return Err(format!("Frame too large: {} bytes (limit {})", len, MAX_FRAME_BYTES).into());
+    }
+    let header = data.len() - cursor.len();
+    if cursor.len() < len {
+        return Ok(None);
+    }
+    let unpacked;
+    let payload = if compressed {
+        unpacked = compression::decompress(&cursor[..len], MAX_FRAME_BYTES).map_err(|e| format!("Invalid frame: {}", e))?;
+        &unpacked[..]
+    } else {
+        &cursor[..len]
+    };
+    let envelope = match encoding {
+        Encoding::Protobuf => Envelope::decode(payload).map_err(|e| format!("Invalid frame: {}", e))?,
+        Encoding::Cbor => ciborium::from_reader(payload).map_err(|e| format!("Invalid frame: {}", e))?,
+    };
+    compat::check_protocol_version(envelope.protocol_version)?;
+    if envelope.body.is_none() {
+        return Err("Frame has no body".into());
+    }
+    Ok(Some((envelope, header + len)))
+}
+
+/// Reassembles frames from a byte stream that may split or merge them
+#[derive(Default)]
+pub struct FrameBuffer {
+    encoding: Encoding,
+    compression: bool,
+    buf: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new(encoding: Encoding) -> FrameBuffer {
+        FrameBuffer { encoding, compression: false, buf: Vec::new() }
+    }
+
+    /// Read the compression flag from frame headers; switch on after a handshake
+    /// that negotiated `CAP_COMPRESSION`
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Next complete frame, if one has arrived
+    pub fn next_frame(&mut self) -> Result<Option<Envelope>, Error> {
+        match decode_session_frame(self.encoding, self.compression, &self.buf)? {
+            Some((envelope, used)) => {
+                self.buf.drain(..used);
+                Ok(Some(envelope))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+impl From<&FileMetadata> for FileEntry {
+    fn from(meta: &FileMetadata) -> Self {
+        FileEntry {
+            path: meta.path.clone(),
+            hash: meta.hash.clone(),
+            mtime: meta.mtime,
+            size: meta.size,
+            version: meta.version,
+            is_deleted: meta.is_deleted,
+            last_modified_by: meta.last_modified_by.clone(),
+            moved_from: meta.moved_from.clone(),
+            ctime: meta.extended.ctime,
+            executable: meta.extended.executable,
+            attributes: meta.extended.attributes.clone(),
+        }
+    }
+}
+
+impl From<FileEntry> for FileMetadata {
+    fn from(entry: FileEntry) -> Self {
+        FileMetadata {
+            path: entry.path,
+            hash: entry.hash,
+            mtime: entry.mtime,
+            size: entry.size,
+            version: entry.version,
+            is_deleted: entry.is_deleted,
+            last_modified_by: entry.last_modified_by,
+            moved_from: entry.moved_from,
+            extended: ExtendedMetadata { ctime: entry.ctime, executable: entry.executable, attributes: entry.attributes },
+        }
+    }
+}
+
+impl From<FileChunk> for Chunk {
+    fn from(chunk: FileChunk) -> Self {
+        Chunk {
+            file_path: chunk.file_path,
+            chunk_index: chunk.chunk_index,
+            total_chunks: chunk.total_chunks,
+            data: chunk.data,
+            nonce: chunk.nonce,
+            stream_id: 0,
+        }
+    }
+}
+
+impl From<Chunk> for FileChunk {
+    fn from(chunk: Chunk) -> Self {
+        FileChunk {
+            file_path: chunk.file_path,
+            chunk_index: chunk.chunk_index,
+            total_chunks: chunk.total_chunks,
+            data: chunk.data,
+            nonce: chunk.nonce,
+        }
+    }
+}
+
+// ============================================================================
+// Fragmentation
+// ============================================================================
+
+const FRAGMENT_MARKER: u8 = 0xF7;
+/// Marker, message ID (u32), index (u16) and count (u16)
+pub const FRAGMENT_HEADER_BYTES: usize = 9;
+/// Smallest transport message a frame can be fragmented into
+pub const MIN_FRAGMENT_MESSAGE_BYTES: usize = 512;
+/// Partially received frames kept before the oldest is dropped
+pub const MAX_PENDING_FRAGMENTED: usize = 16;
+
+/// Splits frames into transport messages of at most `max_message_bytes` and
+/// reassembles the messages received from the peer
+pub struct Fragmenter {
+    max_message_bytes: usize,
+    next_id: u32,
+    outgoing: VecDeque<Vec<u8>>,
+    /// Messages at the front of `outgoing` queued with `queue_frame_ahead`
+    urgent: usize,
+    /// Message ID → fragments received so far
+    partial: HashMap<u32, PartialFrame>,
+    /// Partial message IDs, oldest first
+    arrival: VecDeque<u32>,
+}
+
+struct PartialFrame {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+}
+
+impl Fragmenter {
+    pub fn new(max_message_bytes: usize) -> Result<Fragmenter, Error> {
+        if max_message_bytes < MIN_FRAGMENT_MESSAGE_BYTES {
+            return Err(format!("Message size must be at least {} bytes", MIN_FRAGMENT_MESSAGE_BYTES).into());
+        }
+        Ok(Fragmenter {
+            max_message_bytes,
+            next_id: 0,
+            outgoing: VecDeque::new(),
+            urgent: 0,
+            partial: HashMap::new(),
+            arrival: VecDeque::new(),
+        })
+    }
+
+    /// Split `frame` into messages, taken in order with `next_message`
+    pub fn queue_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        let messages = self.split(frame)?;
+        let count = messages.len();
+        self.outgoing.extend(messages);
+        Ok(count)
+    }
+
+    /// Like `queue_frame`, but the messages overtake those of frames queued with
+    /// `queue_frame` that are still waiting, e.g. an ack during a large chunk.
+    /// The receiver reassembles interleaved frames by message ID
+    pub fn queue_frame_ahead(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        let messages = self.split(frame)?;
+        let count = messages.len();
+        for (offset, message) in messages.into_iter().enumerate() {
+            self.outgoing.insert(self.urgent + offset, message);
+        }
+        self.urgent += count;
+        Ok(count)
+    }
+
+    pub fn next_message(&mut self) -> Option<Vec<u8>> {
+        self.urgent = self.urgent.saturating_sub(1);
+        self.outgoing.pop_front()
+    }
+
+    /// Whether messages queued with `queue_frame_ahead` are still waiting
+    pub fn has_urgent(&self) -> bool {
+        self.urgent > 0
+    }
+
+    /// Add a received message; returns the frame once all its fragments are in
+    pub fn receive_message(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if message.len() < FRAGMENT_HEADER_BYTES || message[0] != FRAGMENT_MARKER {
+            return Err("Not a frame fragment".into());
+        }
+        let id = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+        let index = u16::from_be_bytes([message[5], message[6]]) as usize;
+        let count = u16::from_be_bytes([message[7], message[8]]) as usize;
+        let piece = &message[FRAGMENT_HEADER_BYTES..];
+        if index >= count {
+            return Err(format!("Fragment {} of {} is out of range", index, count).into());
+        }
+        if count == 1 {
+            return Ok(Some(piece.to_vec()));
+        }
+
+        if !self.partial.contains_key(&id) {
+            if self.partial.len() == MAX_PENDING_FRAGMENTED {
+                if let Some(oldest) = self.arrival.pop_front() {
+                    self.partial.remove(&oldest);
+                }
+            }
+            self.partial.insert(id, PartialFrame { fragments: vec![None; count], received: 0, bytes: 0 });
+            self.arrival.push_back(id);
+        }
+        let partial = self.partial.get_mut(&id).ok_or("Fragment state missing")?;
+        if partial.fragments.len() != count {
+            return Err(format!("Fragment count changed for message {}", id).into());
+        }
+        if partial.fragments[index].is_none() {
+            partial.bytes += piece.len();
+            if partial.bytes > MAX_FRAME_BYTES {
+                self.drop_partial(id);
+                return Err(format!("Fragmented frame too large (limit {})", MAX_FRAME_BYTES).into());
+            }
+            partial.fragments[index] = Some(piece.to_vec());
+            partial.received += 1;
+        }
+        if partial.received < count {
+            return Ok(None);
+        }
+        let partial = self.drop_partial(id).ok_or("Fragment state missing")?;
+        Ok(Some(partial.fragments.into_iter().flatten().flatten().collect()))
+    }
+
+    /// Frames still missing fragments
+    pub fn pending_frames(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Heap bytes of the messages waiting to go out and the fragments awaiting the rest of their frame
+    pub fn heap_bytes(&self) -> usize {
+        let queued = deque_bytes(&self.outgoing, Vec::capacity);
+        let partial = self.partial.values().map(|p| p.fragments.capacity() * size_of::<Option<Vec<u8>>>() + p.bytes);
+        queued + map_overhead(&self.partial) + partial.sum::<usize>() + self.arrival.capacity() * size_of::<u32>()
+    }
+
+    fn split(&mut self, frame: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        if frame.len() > MAX_FRAME_BYTES {
+            return Err(format!("Frame too large: {} bytes (limit {})", frame.len(), MAX_FRAME_BYTES).into());
+        }
+        let payload = self.max_message_bytes - FRAGMENT_HEADER_BYTES;
+        let count = frame.len().div_ceil(payload).max(1);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut messages = Vec::with_capacity(count);
+        for index in 0..count {
+            let piece = &frame[(index * payload).min(frame.len())..((index + 1) * payload).min(frame.len())];
+            let mut message = Vec::with_capacity(FRAGMENT_HEADER_BYTES + piece.len());
+            message.push(FRAGMENT_MARKER);
+            message.extend_from_slice(&id.to_be_bytes());
+            message.extend_from_slice(&(index as u16).to_be_bytes());
+            message.extend_from_slice(&(count as u16).to_be_bytes());
+            message.extend_from_slice(piece);
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    fn drop_partial(&mut self, id: u32) -> Option<PartialFrame> {
+        self.arrival.retain(|queued| *queued != id);
+        self.partial.remove(&id)
+    }
+}
+
+/// Render a binary frame (`"protobuf"` or `"cbor"`) as JSON for debugging
+pub fn wire_frame_debug_json(frame: &[u8], encoding: &str) -> Result<String, Error> {
+    match decode_frame_as(Encoding::parse(encoding)?, frame)? {
+        Some((envelope, _)) => Ok(envelope.to_debug_json()),
+        None => Err("Incomplete frame".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(index: u32) -> Envelope {
+        Envelope::new(Body::Chunk(Chunk {
+            file_path: "a.md".to_string(),
+            chunk_index: index,
+            total_chunks: 2,
+            data: (0..100u32).map(|i| (i * 7 + index) as u8).collect(),
+            nonce: vec![0; 12],
+            stream_id: 0,
+        }))
+    }
+
+    #[test]
+    fn test_frames_survive_split_and_merged_reads() {
+        let mut stream = encode_frame(&chunk(0));
+        stream.extend(encode_frame(&Envelope::new(Body::Ack(Ack {
+            file_path: "a.md".to_string(),
+            chunk_index: Some(0),
+            sequence: 0,
+        }))));
+        stream.extend(encode_frame(&chunk(1)));
+
+        let mut buffer = FrameBuffer::default();
+        let mut frames = Vec::new();
+        for piece in stream.chunks(7) {
+            buffer.push(piece);
+            while let Some(envelope) = buffer.next_frame().unwrap() {
+                frames.push(envelope);
+            }
+        }
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2], chunk(1));
+        assert_eq!(buffer.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_control_frames_compressed_when_negotiated() {
+        assert!(negotiate_compression(LOCAL_CAPABILITIES, CAP_PROTOBUF | CAP_COMPRESSION));
+        assert!(!negotiate_compression(LOCAL_CAPABILITIES, CAP_PROTOBUF));
+        let manifest = Envelope::new(Body::Manifest(Manifest {
+            since_sequence: 0,
+            sequence: 40,
+            entries: (0..40)
+                .map(|i| FileEntry {
+                    path: format!("notes/projects/note-{}.md", i),
+                    hash: "ab".repeat(32),
+                    version: i,
+                    last_modified_by: "laptop".to_string(),
+                    moved_from: None,
+                    ..Default::default()
+                })
+                .collect(),
+        }));
+        for encoding in [Encoding::Protobuf, Encoding::Cbor] {
+            let plain = encode_frame_as(encoding, &manifest);
+            let packed = encode_session_frame(encoding, true, &manifest);
+            assert!(packed.len() < plain.len() / 2);
+            // Chunks keep their size; only the header flag bit differs
+            assert_eq!(encode_session_frame(encoding, true, &chunk(0)).len(), encode_frame_as(encoding, &chunk(0)).len());
+
+            let mut stream = packed;
+            stream.extend(encode_session_frame(encoding, true, &chunk(1)));
+            let mut buffer = FrameBuffer::new(encoding);
+            buffer.set_compression(true);
+            let mut frames = Vec::new();
+            for piece in stream.chunks(50) {
+                buffer.push(piece);
+                while let Some(envelope) = buffer.next_frame().unwrap() {
+                    frames.push(envelope);
+                }
+            }
+            assert_eq!(frames, vec![manifest.clone(), chunk(1)]);
+        }
+    }
+
+    #[test]
+    fn test_fragments_reassemble_in_any_order() {
+        let mut sender = Fragmenter::new(512).unwrap();
+        let mut receiver = Fragmenter::new(512).unwrap();
+        let big = Envelope::new(Body::Cover(Cover { filler: (0..3000u32).map(|i| i as u8).collect() }));
+        let frames = [encode_frame(&big), encode_frame(&chunk(1))];
+        assert_eq!(sender.queue_frame(&frames[0]).unwrap(), 6);
+        assert_eq!(sender.queue_frame(&frames[1]).unwrap(), 1);
+        let mut messages: Vec<Vec<u8>> = std::iter::from_fn(|| sender.next_message()).collect();
+        assert!(messages.iter().all(|m| m.len() <= 512));
+
+        // Reverse the big frame's fragments and deliver one of them twice
+        messages[..6].reverse();
+        messages.insert(3, messages[2].clone());
+        let mut received = Vec::new();
+        for message in &messages {
+            if let Some(frame) = receiver.receive_message(message).unwrap() {
+                received.push(frame);
+            }
+        }
+        assert_eq!(received, frames);
+        assert_eq!(receiver.pending_frames(), 0);
+
+        assert!(Fragmenter::new(100).is_err());
+        assert!(receiver.receive_message(&[1, 2, 3]).is_err());
+        // Partial frames beyond the limit evict the oldest
+        for id in 0..=MAX_PENDING_FRAGMENTED as u32 {
+            let mut message = vec![FRAGMENT_MARKER];
+            message.extend_from_slice(&id.to_be_bytes());
+            message.extend_from_slice(&[0, 0, 0, 2, 9]);
+            assert_eq!(receiver.receive_message(&message).unwrap(), None);
+        }
+        assert_eq!(receiver.pending_frames(), MAX_PENDING_FRAGMENTED);
+    }
+
+    #[test]
+    fn test_rejects_oversized_and_empty_frames() {
+        let mut huge = Vec::new();
+        prost::encode_length_delimiter(MAX_FRAME_BYTES + 1, &mut huge).unwrap();
+        assert!(decode_frame(&huge).unwrap_err().message().contains("too large"));
+        assert!(decode_frame(&Envelope::default().encode_length_delimited_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_debug_rendering() {
+        let frame = encode_frame(&Envelope::new(Body::Control(Control {
+            kind: ControlKind::Cancel as i32,
+            reason: "user".to_string(),
+            ..Default::default()
+        })));
+        let json: serde_json::Value = serde_json::from_str(&wire_frame_debug_json(&frame, "protobuf").unwrap()).unwrap();
+        assert_eq!(json["body"]["type"], "control");
+        assert_eq!(json["body"]["kind"], "cancel");
+        assert_eq!(json["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_cbor_frames_match_protobuf() {
+        let envelopes = [
+            chunk(3),
+            Envelope::new(Body::Handshake(Handshake {
+                device_id: "laptop".to_string(),
+                identity_key: vec![7; 32],
+                session_key: vec![9; 32],
+                signature: vec![1; 64],
+                capabilities: CAP_CBOR,
+                journal_sketch: vec![3; 256],
+            })),
+            Envelope::new(Body::Control(Control { kind: ControlKind::Close as i32, reason: "bye".to_string(), ..Default::default() })),
+        ];
+        let mut buffer = FrameBuffer::new(Encoding::Cbor);
+        for envelope in &envelopes {
+            buffer.push(&encode_frame_as(Encoding::Cbor, envelope));
+            assert_eq!(buffer.next_frame().unwrap().as_ref(), Some(envelope));
+        }
+        // Against the JSON chunk format it replaces
+        let Some(Body::Chunk(body)) = chunk(3).body else { unreachable!() };
+        let json = serde_json::to_string(&FileChunk::from(body)).unwrap();
+        assert!(encode_frame_as(Encoding::Cbor, &chunk(3)).len() < json.len() / 2);
+
+        assert_eq!(Encoding::negotiate(LOCAL_CAPABILITIES, CAP_CBOR), Some(Encoding::Cbor));
+        assert_eq!(Encoding::negotiate(LOCAL_CAPABILITIES, LOCAL_CAPABILITIES), Some(Encoding::Protobuf));
+        assert_eq!(Encoding::negotiate(CAP_PROTOBUF, CAP_CBOR), None);
+        assert_eq!(negotiate_hash(LOCAL_CAPABILITIES, CAP_PROTOBUF), HashAlgorithm::Sha256);
+        assert_eq!(negotiate_hash(LOCAL_CAPABILITIES, CAP_PROTOBUF | CAP_BLAKE3), HashAlgorithm::Blake3);
+    }
+}
@@ -0,0 +1,602 @@
+//! Headless sync hub
+//!
+//! Runs the sync core natively as an always-on peer (for example on a
+//! Raspberry Pi next to the router). It indexes a vault directory into the
+//! change journal, keeps the journal persisted next to the vault, announces
+//! itself and tracks peers over UDP discovery, and syncs with the vault's
+//! members when they are due.
+//!
+//! A rescan only hashes files whose size or mtime differ from their journal
+//! entry (as `plan_hash_jobs` plans it) and marks files that disappeared as
+//! deleted.
+//!
+//! Sessions run over TCP on the service port with the library's frames, the
+//! way `loopback` drives them: signed handshakes both ways, the session
+//! confirmation in a `Ping`, then each side's manifest, a second manifest
+//! listing the entries it wants, the wanted bodies as encrypted chunk frames
+//! and a `Close`. Each side stages what it pulls as a round and commits it
+//! once every body has verified. Conflicts are left in place for a device
+//! with a UI to settle. Of two peers, the one with the lower device ID dials.
+//! Only devices in `.p2p-sync/members.json` (vault members state, see
+//! `introductions`) are synced with; the hub prints its own device ID and
+//! identity key at startup so a member can introduce it.
+//!
+//! The identity key is stored wrapped (`crypto::wrap_secret`) under a key
+//! kept outside the vault, `~/.p2p-sync-hub.key` unless `--key-file` says
+//! otherwise, so a copy or backup of the vault doesn't carry it. The key
+//! file is created on first start.
+//!
+//! Usage: `p2p-sync-hub <vault-dir> [--name NAME] [--port PORT] [--key-file PATH]`
+
+// The browser build only uses the library; the hub is native-only
+#[cfg(target_arch = "wasm32")]
+fn main() {}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    native::main()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+    use rand_core::{OsRng, RngCore};
+    use serde_json::json;
+    use std::collections::{BTreeMap, HashSet};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use p2p_sync_core::access;
+    use p2p_sync_core::crypto::{DeviceIdentity, KeyExchange};
+    use p2p_sync_core::hashjobs::compute_hash_job;
+    use p2p_sync_core::streams::encode_chunk_frame;
+    use p2p_sync_core::sync::FileMetadata;
+    use p2p_sync_core::transfer::{IncomingTransfer, TransferManager, DEFAULT_REORDER_CHUNKS};
+    use p2p_sync_core::wire::{self, Body, Control, ControlKind, Envelope, Manifest};
+    use p2p_sync_core::P2PNode;
+
+    const STATE_DIR: &str = ".p2p-sync";
+    const KEY_FILE: &str = ".p2p-sync-hub.key";
+    const DEFAULT_PORT: u16 = 8080;
+    const RESCAN_INTERVAL_MS: u64 = 5_000;
+    const PEER_TTL_MS: u64 = 60_000;
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+    struct Options {
+        vault: PathBuf,
+        name: String,
+        port: u16,
+        key_file: PathBuf,
+    }
+
+    fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut vault = None;
+        let mut name = "Sync hub".to_string();
+        let mut port = DEFAULT_PORT;
+        let mut key_file = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--name" => name = args.next().ok_or("--name needs a value")?,
+                "--port" => {
+                    let value = args.next().ok_or("--port needs a value")?;
+                    port = value.parse().map_err(|_| format!("Invalid port: {}", value))?;
+                }
+                "--key-file" => key_file = Some(PathBuf::from(args.next().ok_or("--key-file needs a value")?)),
+                other if vault.is_none() && !other.starts_with("--") => vault = Some(PathBuf::from(other)),
+                other => return Err(format!("Unexpected argument: {}", other)),
+            }
+        }
+        let vault = vault.ok_or("Usage: p2p-sync-hub <vault-dir> [--name NAME] [--port PORT] [--key-file PATH]")?;
+        let key_file = match key_file {
+            Some(path) => path,
+            None => PathBuf::from(std::env::var_os("HOME").ok_or("No home directory; pass --key-file")?).join(KEY_FILE),
+        };
+        Ok(Options { vault, name, port, key_file })
+    }
+
+    fn now_ms() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+
+    /// Every vault file with its path relative to the vault, skipping hidden entries
+    fn vault_files(root: &Path, dir: &Path, out: &mut Vec<(String, PathBuf)>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                vault_files(root, &path, out)?;
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                out.push((relative.to_string_lossy().replace('\\', "/"), path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every file's `{path, mtime, size}`, by path, and where it is on disk
+    fn stat_files(vault: &Path) -> std::io::Result<BTreeMap<String, (PathBuf, serde_json::Value)>> {
+        let mut files = Vec::new();
+        vault_files(vault, vault, &mut files)?;
+        let mut stats = BTreeMap::new();
+        for (relative, path) in files {
+            let metadata = fs::metadata(&path)?;
+            let stat = json!({"path": relative, "mtime": mtime_ms(&metadata), "size": metadata.len()});
+            stats.insert(relative, (path, stat));
+        }
+        Ok(stats)
+    }
+
+    fn mtime_ms(metadata: &fs::Metadata) -> u64 {
+        metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+
+    /// Paths the journal holds as live
+    fn live_paths(node: &P2PNode) -> HashSet<String> {
+        let files: Vec<FileMetadata> = serde_json::from_str(&node.get_all_files()).unwrap_or_default();
+        files.into_iter().filter(|m| !m.is_deleted).map(|m| m.path).collect()
+    }
+
+    /// Bring the journal up to date with the vault: hash the files whose size or mtime
+    /// changed and mark the ones gone since the last scan deleted; returns how many changed
+    fn rescan(node: &mut P2PNode, vault: &Path, known: &mut HashSet<String>) -> Result<u64, String> {
+        let files = stat_files(vault).map_err(|e| e.to_string())?;
+        let stats: Vec<&serde_json::Value> = files.values().map(|(_, stat)| stat).collect();
+        node.reset_bootstrap_progress();
+        let jobs = node.plan_hash_jobs(&serde_json::to_string(&stats).map_err(|e| e.to_string())?, 1)?;
+        let jobs: Vec<Vec<serde_json::Value>> = serde_json::from_str(&jobs).map_err(|e| e.to_string())?;
+        let mut results = Vec::new();
+        for job in jobs.into_iter().flatten() {
+            let Some((path, _)) = job["path"].as_str().and_then(|p| files.get(p)) else {
+                continue;
+            };
+            // A file removed since it was listed is picked up as deleted next time
+            if let Ok(data) = fs::read(path) {
+                results.push(serde_json::from_str::<serde_json::Value>(&compute_hash_job(&job.to_string(), &data)?).map_err(|e| e.to_string())?);
+            }
+        }
+        let progress = node.submit_hash_results(&serde_json::to_string(&results).map_err(|e| e.to_string())?)?;
+        let progress: serde_json::Value = serde_json::from_str(&progress).map_err(|e| e.to_string())?;
+        let mut changed = progress["recorded"].as_u64().unwrap_or(0);
+        for gone in known.iter().filter(|path| !files.contains_key(*path)) {
+            if node.mark_file_deleted(gone.clone(), now_ms()) {
+                changed += 1;
+            }
+        }
+        *known = files.into_keys().filter(|path| node.get_path_policy(path) != "skip").collect();
+        Ok(changed)
+    }
+
+    /// Write a file only the hub's user can read
+    fn write_private(path: &Path, contents: &str) -> Result<(), String> {
+        fs::write(path, contents).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// The key the identity is wrapped under (base64), created on first start
+    fn load_wrapping_key(path: &Path) -> Result<String, String> {
+        if let Ok(key) = fs::read_to_string(path) {
+            return Ok(key.trim().to_string());
+        }
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let key = BASE64.encode(key);
+        write_private(path, &key)?;
+        Ok(key)
+    }
+
+    /// This device's signing identity, created on first start and stored
+    /// wrapped under `wrapping_key`
+    fn load_identity(state_dir: &Path, device_id: &str, wrapping_key: &str) -> Result<DeviceIdentity, String> {
+        let path = state_dir.join("identity");
+        if let Ok(wrapped) = fs::read_to_string(&path) {
+            let wrapped = wrapped.trim().to_string();
+            return DeviceIdentity::from_wrapped_secret_key(device_id.to_string(), wrapped, wrapping_key.to_string())
+                .map_err(|e| format!("Cannot unwrap {} with the key file: {}", path.display(), e));
+        }
+        let identity = DeviceIdentity::new(device_id.to_string())?;
+        write_private(&path, &identity.export_wrapped_secret_key(wrapping_key.to_string())?)?;
+        Ok(identity)
+    }
+
+    /// One connection to a peer; frames are read on a background thread so that
+    /// neither side blocks writing while the other writes too
+    struct Connection {
+        stream: TcpStream,
+        frames: mpsc::Receiver<Result<Vec<u8>, String>>,
+    }
+
+    impl Connection {
+        fn new(stream: TcpStream) -> Result<Connection, String> {
+            stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+            let mut reader = stream.try_clone().map_err(|e| e.to_string())?;
+            let (sender, frames) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let mut read = vec![0u8; 64 * 1024];
+                loop {
+                    match reader.read(&mut read) {
+                        Ok(0) => return,
+                        Ok(len) => buf.extend_from_slice(&read[..len]),
+                        Err(e) => {
+                            let _ = sender.send(Err(e.to_string()));
+                            return;
+                        }
+                    }
+                    loop {
+                        match wire::decode_frame(&buf) {
+                            Ok(Some((_, len))) => {
+                                if sender.send(Ok(buf.drain(..len).collect())).is_err() {
+                                    return;
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                let _ = sender.send(Err(e.to_string()));
+                                return;
+                            }
+                        }
+                    }
+                }
+            });
+            Ok(Connection { stream, frames })
+        }
+
+        fn send(&mut self, frame: &[u8]) -> Result<(), String> {
+            self.stream.write_all(frame).map_err(|e| format!("Send failed: {}", e))
+        }
+
+        fn send_body(&mut self, body: Body) -> Result<(), String> {
+            self.send(&wire::encode_frame(&Envelope::new(body)))
+        }
+
+        /// The next frame, raw and decoded
+        fn receive(&mut self) -> Result<(Vec<u8>, Body), String> {
+            let frame = self.frames.recv_timeout(SESSION_TIMEOUT).map_err(|_| "Peer stopped responding".to_string())??;
+            match wire::decode_frame(&frame)? {
+                Some((Envelope { body: Some(body), .. }, _)) => Ok((frame, body)),
+                _ => Err("Empty frame".to_string()),
+            }
+        }
+
+        fn receive_manifest(&mut self) -> Result<Vec<FileMetadata>, String> {
+            match self.receive()? {
+                (_, Body::Manifest(manifest)) => Ok(manifest.entries.into_iter().map(Into::into).collect()),
+                _ => Err("Expected a manifest frame".to_string()),
+            }
+        }
+    }
+
+    fn control(kind: ControlKind, reason: String, journal_sequence: u64) -> Body {
+        Body::Control(Control { kind: kind as i32, reason, journal_sequence, journal_digest: Vec::new() })
+    }
+
+    fn manifest(sequence: u64, entries: &[FileMetadata]) -> Body {
+        Body::Manifest(Manifest { since_sequence: 0, sequence, entries: entries.iter().map(Into::into).collect() })
+    }
+
+    /// Both sides derive the same transfer ID for a body
+    fn transfer_id(path: &str, hash: &str) -> String {
+        format!("{}:{}", hash, path)
+    }
+
+    /// Handshake, exchange manifests and bodies, and commit what we pulled; returns
+    /// the peer's device ID and the operations carried out in the vault
+    fn sync_session(node: &mut P2PNode, hub: &Hub, stream: TcpStream) -> Result<(String, usize), String> {
+        let mut conn = Connection::new(stream)?;
+        let session = KeyExchange::new();
+        let own = node.get_handshake_frame(&hub.identity, &session);
+        conn.send(&own)?;
+        let (peer_frame, Body::Handshake(handshake)) = conn.receive()? else {
+            return Err("Expected a handshake frame".to_string());
+        };
+        let peer_id = handshake.device_id.clone();
+        if !node.is_vault_member(&peer_id, &BASE64.encode(&handshake.identity_key)) {
+            return Err(format!("{} is not a member of this vault", peer_id));
+        }
+        node.process_handshake_frame(&peer_frame)?;
+        let keys: serde_json::Value = serde_json::from_str(&node.derive_session_keys(&session, &own, &peer_frame)?)
+            .map_err(|e| e.to_string())?;
+        let key = |name: &str| keys[name].as_str().unwrap_or_default().to_string();
+        let sequence = node.get_status().get_journal_sequence();
+        // The confirmation rides in a ping's reason
+        conn.send_body(control(ControlKind::Ping, key("confirmation"), sequence))?;
+        let (_, Body::Control(confirmation)) = conn.receive()? else {
+            return Err("Expected the session confirmation".to_string());
+        };
+        if !node.check_session_confirmation(&peer_id, &key("expected_confirmation"), &confirmation.reason) {
+            return Err("Session confirmation mismatch".to_string());
+        }
+        let session_key = key("session_key");
+
+        let ours: Vec<FileMetadata> = serde_json::from_str(&node.get_all_files()).map_err(|e| e.to_string())?;
+        conn.send_body(manifest(sequence, &ours))?;
+        let theirs = conn.receive_manifest()?;
+
+        // Stage what we should apply; what we are newer on, the peer pulls from us
+        let plan: serde_json::Value =
+            serde_json::from_str(&node.plan_sync_preview(&serde_json::to_string(&theirs).map_err(|e| e.to_string())?)?)
+                .map_err(|e| e.to_string())?;
+        let listed = |list: &str| -> HashSet<String> {
+            plan[list].as_array().into_iter().flatten().filter_map(|item| item["path"].as_str().map(str::to_string)).collect()
+        };
+        let apply: HashSet<String> = ["pull", "overwrite", "delete"].into_iter().flat_map(listed).collect();
+        let conflicts = listed("conflict").len();
+        if conflicts > 0 {
+            println!("{} conflict(s) with {} left for a device with a UI to settle", conflicts, peer_id);
+        }
+        let changes: Vec<&FileMetadata> =
+            theirs.iter().filter(|m| apply.contains(&m.path) && access::is_plain_path(&m.path)).collect();
+        let status = node.begin_sync_round(&serde_json::to_string(&changes).map_err(|e| e.to_string())?, &peer_id)?;
+        let status: serde_json::Value = serde_json::from_str(&status).map_err(|e| e.to_string())?;
+        let awaiting: HashSet<&str> = status["awaiting"].as_array().into_iter().flatten().filter_map(|p| p.as_str()).collect();
+        let wanted: BTreeMap<String, FileMetadata> =
+            changes.into_iter().filter(|m| awaiting.contains(m.path.as_str())).map(|m| (m.path.clone(), m.clone())).collect();
+        let result = exchange_bodies(node, hub, &mut conn, &session_key, &ours, wanted);
+        let ops = match result.and_then(|staged| node.commit_sync_round().map(|ops| (staged, ops))) {
+            Ok((staged, ops)) => apply_ops(&hub.vault, &ops, staged, &theirs)?,
+            Err(e) => {
+                node.abort_sync_round();
+                return Err(e);
+            }
+        };
+        Ok((peer_id, ops))
+    }
+
+    /// Send the peer the bodies it wants and receive the ones we want, each verified
+    /// against the open round and staged; returns the staged files by path
+    fn exchange_bodies(
+        node: &mut P2PNode,
+        hub: &Hub,
+        conn: &mut Connection,
+        session_key: &str,
+        ours: &[FileMetadata],
+        wanted: BTreeMap<String, FileMetadata>,
+    ) -> Result<BTreeMap<String, PathBuf>, String> {
+        let list: Vec<FileMetadata> = wanted.values().cloned().collect();
+        conn.send_body(manifest(0, &list))?;
+        let requested = conn.receive_manifest()?;
+        for entry in requested {
+            // Only bodies we still hold as the manifest described them
+            let current = ours.iter().any(|m| m.path == entry.path && m.hash == entry.hash && !m.is_deleted);
+            let content = if current && access::is_plain_path(&entry.path) { fs::read(hub.vault.join(&entry.path)).ok() } else { None };
+            let Some(content) = content.filter(|_| entry.size > 0) else {
+                continue;
+            };
+            let manager = TransferManager::new(transfer_id(&entry.path, &entry.hash), entry.hash.clone())?;
+            let chunks = manager.prepare_transfer(entry.path.clone(), &content, session_key.to_string())?;
+            let chunks: Vec<serde_json::Value> = serde_json::from_str(&chunks).map_err(|e| e.to_string())?;
+            for chunk in chunks {
+                conn.send(&encode_chunk_frame(&chunk.to_string(), 0)?)?;
+            }
+        }
+        conn.send_body(control(ControlKind::Close, String::new(), 0))?;
+
+        let staging = hub.state_dir.join("staging");
+        fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+        let mut transfers: BTreeMap<String, (IncomingTransfer, Vec<u8>)> = BTreeMap::new();
+        let mut received: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        loop {
+            match conn.receive()? {
+                (frame, Body::Chunk(chunk)) => {
+                    let Some(entry) = wanted.get(&chunk.file_path) else {
+                        return Err(format!("Unrequested body for {}", chunk.file_path));
+                    };
+                    if !transfers.contains_key(&entry.path) {
+                        let id = transfer_id(&entry.path, &entry.hash);
+                        let incoming = IncomingTransfer::new(
+                            id,
+                            entry.path.clone(),
+                            entry.hash.clone(),
+                            chunk.total_chunks,
+                            session_key.to_string(),
+                            DEFAULT_REORDER_CHUNKS,
+                        )?;
+                        transfers.insert(entry.path.clone(), (incoming, Vec::new()));
+                    }
+                    let Some((incoming, body)) = transfers.get_mut(&entry.path) else {
+                        continue;
+                    };
+                    incoming.accept_chunk_frame(&frame)?;
+                    body.extend(incoming.take_ready());
+                    if incoming.is_complete() {
+                        if let Some((_, body)) = transfers.remove(&entry.path) {
+                            received.insert(entry.path.clone(), body);
+                        }
+                    }
+                }
+                (_, Body::Control(control)) if control.kind == ControlKind::Close as i32 => break,
+                _ => return Err("Unexpected frame during transfer".to_string()),
+            }
+        }
+
+        let mut staged = BTreeMap::new();
+        for (index, entry) in wanted.values().enumerate() {
+            // Empty files carry no chunks
+            let body = received.remove(&entry.path).or_else(|| (entry.size == 0).then(Vec::new));
+            let Some(body) = body else {
+                return Err(format!("{} did not arrive", entry.path));
+            };
+            node.verify_round_content(&entry.path, &body)?;
+            let target = staging.join(index.to_string());
+            fs::write(&target, &body).map_err(|e| e.to_string())?;
+            staged.insert(entry.path.clone(), target);
+        }
+        Ok(staged)
+    }
+
+    /// Carry out a committed round's operations (JSON) in the vault; writes get the
+    /// peer's mtime so the next rescan finds them unchanged
+    fn apply_ops(vault: &Path, ops: &str, mut staged: BTreeMap<String, PathBuf>, theirs: &[FileMetadata]) -> Result<usize, String> {
+        let ops: Vec<serde_json::Value> = serde_json::from_str(ops).map_err(|e| e.to_string())?;
+        for op in &ops {
+            let path = op["path"].as_str().unwrap_or_default();
+            let target = vault.join(path);
+            let result = match op["action"].as_str().unwrap_or_default() {
+                "write" => match staged.remove(path) {
+                    Some(source) => place(&source, &target, theirs.iter().find(|m| m.path == path).map(|m| m.mtime)),
+                    None => Ok(()),
+                },
+                "delete" => match fs::remove_file(&target) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                    _ => Ok(()),
+                },
+                "move" => match op["from"].as_str().filter(|from| access::is_plain_path(from)) {
+                    Some(from) => place(&vault.join(from), &target, None),
+                    None => Ok(()),
+                },
+                _ => Ok(()),
+            };
+            result.map_err(|e| format!("Cannot update {}: {}", path, e))?;
+        }
+        Ok(ops.len())
+    }
+
+    fn place(source: &Path, target: &Path, mtime: Option<u64>) -> std::io::Result<()> {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(source, target)?;
+        if let Some(mtime) = mtime {
+            fs::File::options().write(true).open(target)?.set_modified(UNIX_EPOCH + Duration::from_millis(mtime))?;
+        }
+        Ok(())
+    }
+
+    struct Hub {
+        vault: PathBuf,
+        state_dir: PathBuf,
+        identity: DeviceIdentity,
+    }
+
+    impl Hub {
+        fn save(&self, node: &P2PNode) -> Result<(), String> {
+            fs::write(self.state_dir.join("journal.json"), node.get_journal_state()).map_err(|e| e.to_string())?;
+            fs::write(self.state_dir.join("history.json"), node.get_history_state()).map_err(|e| e.to_string())
+        }
+
+        /// Run a session and record its outcome
+        fn sync(&self, node: &mut P2PNode, stream: TcpStream, known: &mut HashSet<String>) {
+            let started = now_ms();
+            match sync_session(node, self, stream) {
+                Ok((peer_id, ops)) => {
+                    node.record_sync_round(&peer_id, started, now_ms(), true, 0, 0);
+                    if ops > 0 {
+                        println!("Synced with {}: {} change(s)", peer_id, ops);
+                        *known = live_paths(node);
+                        if let Err(e) = self.save(node) {
+                            eprintln!("Cannot save the journal: {}", e);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Sync failed: {}", e),
+            }
+        }
+    }
+
+    /// Address and service port of a discovered peer
+    fn peer_address(node: &P2PNode, device_id: &str) -> Option<(String, u16)> {
+        let peers: Vec<serde_json::Value> = serde_json::from_str(&node.get_discovered_peers_json()).unwrap_or_default();
+        let peer = peers.into_iter().find(|p| p["device_id"] == device_id)?;
+        Some((peer["address"].as_str()?.to_string(), u16::try_from(peer["service_port"].as_u64()?).ok()?))
+    }
+
+    fn run(options: Options) -> Result<(), String> {
+        let state_dir = options.vault.join(STATE_DIR);
+        fs::create_dir_all(&state_dir).map_err(|e| format!("Cannot create {}: {}", state_dir.display(), e))?;
+        let device_id = match fs::read_to_string(state_dir.join("device-id")) {
+            Ok(id) => id.trim().to_string(),
+            Err(_) => {
+                let id = p2p_sync_core::ids::new_id();
+                fs::write(state_dir.join("device-id"), &id).map_err(|e| e.to_string())?;
+                id
+            }
+        };
+        let wrapping_key = load_wrapping_key(&options.key_file)?;
+        let identity = load_identity(&state_dir, &device_id, &wrapping_key)?;
+        println!("Device {} with identity key {}", device_id, identity.get_public_key());
+        let mut node = P2PNode::new(options.name, device_id.clone(), options.port);
+        if let Ok(json) = fs::read_to_string(state_dir.join("journal.json")) {
+            node.load_journal_state(&json)?;
+        }
+        if let Ok(json) = fs::read_to_string(state_dir.join("history.json")) {
+            node.load_history_state(&json)?;
+        }
+        match fs::read_to_string(state_dir.join("members.json")) {
+            Ok(json) => node.load_vault_members_state(&json)?,
+            Err(_) => println!("No members.json yet; not syncing with anyone"),
+        }
+        let hub = Hub { vault: options.vault, state_dir, identity };
+        let mut known = live_paths(&node);
+
+        let socket = UdpSocket::bind(("0.0.0.0", options.port)).map_err(|e| format!("Cannot bind UDP port {}: {}", options.port, e))?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+        socket.set_read_timeout(Some(Duration::from_millis(500))).map_err(|e| e.to_string())?;
+        let listener = TcpListener::bind(("0.0.0.0", options.port)).map_err(|e| format!("Cannot bind TCP port {}: {}", options.port, e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut last_rescan = 0;
+        loop {
+            let now = now_ms();
+            if now.saturating_sub(last_rescan) >= RESCAN_INTERVAL_MS {
+                last_rescan = now;
+                match rescan(&mut node, &hub.vault, &mut known) {
+                    Ok(0) => {}
+                    Ok(changed) => {
+                        println!("{} file(s) changed", changed);
+                        hub.save(&node)?;
+                    }
+                    Err(e) => eprintln!("Vault scan failed: {}", e),
+                }
+                // Best effort: a network without broadcast still reaches us through unicast announcements
+                let _ = socket.send_to(&node.get_announcement_frame(), ("255.255.255.255", options.port));
+                node.prune_peers(now, PEER_TTL_MS)?;
+                let due: Vec<String> = serde_json::from_str(&node.tick(now)).unwrap_or_default();
+                // The peer with the lower device ID dials, so two hubs don't wait on each other
+                for peer in due.iter().filter(|peer| device_id.as_str() < peer.as_str()) {
+                    let Some((address, port)) = peer_address(&node, peer) else {
+                        continue;
+                    };
+                    let target = format!("{}:{}", address, port).parse().map_err(|e| format!("Bad address of {}: {}", peer, e));
+                    match target.and_then(|target| TcpStream::connect_timeout(&target, CONNECT_TIMEOUT).map_err(|e| e.to_string())) {
+                        Ok(stream) => hub.sync(&mut node, stream, &mut known),
+                        Err(e) => eprintln!("Cannot reach {}: {}", peer, e),
+                    }
+                }
+            }
+            match listener.accept() {
+                Ok((stream, _)) => hub.sync(&mut node, stream, &mut known),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => eprintln!("Cannot accept a connection: {}", e),
+            }
+            match socket.recv_from(&mut buf) {
+                Ok((len, sender)) => match node.process_announcement_frame(&buf[..len], &sender.ip().to_string(), now_ms()) {
+                    Ok(true) => println!("Peer seen at {}", sender.ip()),
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Ignoring datagram from {}: {}", sender, e),
+                },
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(e) => return Err(format!("Discovery socket failed: {}", e)),
+            }
+        }
+    }
+
+    pub fn main() {
+        if let Err(e) = parse_args(std::env::args().skip(1)).and_then(run) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
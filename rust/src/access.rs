@@ -0,0 +1,87 @@
+//! JS bindings for capability tokens (see `p2p_sync_core::access`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::DeviceIdentity;
+use crate::ingest::FileIngest;
+use crate::P2PNode;
+
+pub use p2p_sync_core::access::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Accept tokens signed by this identity key (base64), normally our own and the vault owner's
+    pub fn trust_token_issuer(&mut self, public_key: String) {
+        self.0.trust_token_issuer(public_key);
+    }
+
+    pub fn untrust_token_issuer(&mut self, public_key: &str) {
+        self.0.untrust_token_issuer(public_key);
+    }
+
+    /// Issue a token for `grantee_public_key` with `permissions_json`
+    /// (`{read_only, folders, deny_attachments}`), valid until `expires_at` (0: no expiry)
+    pub fn issue_capability_token(
+        &mut self,
+        identity: &DeviceIdentity,
+        grantee_public_key: String,
+        permissions_json: &str,
+        expires_at: u64,
+    ) -> Result<String, String> {
+        self.0.issue_capability_token(identity, grantee_public_key, permissions_json, expires_at)
+    }
+
+    /// Give `guest_public_key` read-only access to `folder` for `duration_ms`; returns
+    /// the session (`{id, fingerprint, folder, expires_at, token}`) as JSON
+    pub fn create_guest_session(
+        &mut self,
+        identity: &DeviceIdentity,
+        guest_public_key: String,
+        folder: String,
+        duration_ms: u64,
+    ) -> Result<String, String> {
+        self.0.create_guest_session(identity, guest_public_key, folder, duration_ms)
+    }
+
+    /// End a guest session before it expires; false if there is no such session
+    pub fn revoke_guest_session(&mut self, id: &str) -> bool {
+        self.0.revoke_guest_session(id)
+    }
+
+    pub fn get_guest_sessions_json(&self) -> String {
+        self.0.get_guest_sessions_json()
+    }
+
+    /// Trusted issuers, guest sessions and revocations as JSON, for persisting with `load_access_state`
+    pub fn get_access_state(&self) -> String {
+        self.0.get_access_state()
+    }
+
+    pub fn load_access_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_access_state(json)
+    }
+
+    /// Check a request from `device_id`, whose handshake was verified, before serving it;
+    /// `access` is `manifest`, `read` or `write`. Errors with the reason when the token doesn't allow it
+    pub fn authorize_request(&mut self, token: &str, device_id: &str, access: &str, path: &str) -> Result<(), String> {
+        self.0.authorize_request(token, device_id, access, path)
+    }
+
+    /// The journal entries a token lets `device_id` see, as a JSON array
+    pub fn get_manifest_for_token(&mut self, token: &str, device_id: &str) -> Result<String, String> {
+        self.0.get_manifest_for_token(token, device_id)
+    }
+
+    /// Start producing the transfer chunks of `path` for `device_id`, if its token allows
+    /// reading it; see `begin_file_ingest`
+    pub fn begin_serving_file(
+        &mut self,
+        token: &str,
+        device_id: &str,
+        path: String,
+        size: u64,
+        session_key: String,
+    ) -> Result<FileIngest, String> {
+        self.0.begin_serving_file(token, device_id, path, size, session_key).map(Into::into)
+    }
+}
@@ -0,0 +1,46 @@
+//! JS bindings for vault snapshot archives (see `p2p_sync_core::archive`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::archive as core_archive;
+
+pub use p2p_sync_core::archive::*;
+
+#[wasm_bindgen]
+pub struct ArchiveBuilder(core_archive::ArchiveBuilder);
+
+core_wrapper!(ArchiveBuilder, core_archive::ArchiveBuilder);
+
+#[wasm_bindgen]
+impl ArchiveBuilder {
+    /// `format` is `tar` or `zip`; with `key_b64` the output is encrypted
+    #[wasm_bindgen(constructor)]
+    pub fn new(format: &str, key_b64: Option<String>) -> Result<ArchiveBuilder, String> {
+        core_archive::ArchiveBuilder::new(format, key_b64).map(Into::into)
+    }
+
+    /// Add one file; returns the bytes to append to the output
+    pub fn add_file(&mut self, path: String, content: &[u8], mtime: u64) -> Result<Vec<u8>, String> {
+        self.0.add_file(path, content, mtime)
+    }
+
+    /// Write the manifest and format trailer; returns the final output bytes
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        self.0.finish()
+    }
+
+    /// Manifest of the files added so far as JSON
+    pub fn get_manifest_json(&self) -> String {
+        self.0.get_manifest_json()
+    }
+
+    pub fn get_file_count(&self) -> usize {
+        self.0.get_file_count()
+    }
+}
+
+/// Recover the plain archive from an encrypted stream
+#[wasm_bindgen]
+pub fn decrypt_archive(key_b64: String, data: &[u8]) -> Result<Vec<u8>, String> {
+    core_archive::decrypt_archive(key_b64, data)
+}
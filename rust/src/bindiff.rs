@@ -0,0 +1,18 @@
+//! JS bindings for binary deltas for changed attachments (see `p2p_sync_core::bindiff`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::bindiff as core_bindiff;
+
+pub use p2p_sync_core::bindiff::*;
+
+/// Patch from the peer's version `base` to `target`, or `undefined` to send `target` in full
+#[wasm_bindgen]
+pub fn create_binary_delta(base: &[u8], target: &[u8]) -> Option<Vec<u8>> {
+    core_bindiff::create_binary_delta(base, target)
+}
+
+#[wasm_bindgen]
+pub fn apply_binary_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    core_bindiff::apply_binary_delta(base, delta)
+}
@@ -0,0 +1,39 @@
+//! JS bindings for cache quota (see `p2p_sync_core::caches`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::caches::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Bytes the chunk cache and frames held for unreachable peers may take together
+    pub fn set_cache_quota(&mut self, bytes: usize) -> Result<(), String> {
+        self.0.set_cache_quota(bytes)
+    }
+
+    /// Cache a chunk by content; returns its hash. A chunk larger than the quota isn't kept
+    pub fn cache_chunk(&mut self, data: &[u8]) -> String {
+        self.0.cache_chunk(data)
+    }
+
+    pub fn get_cached_chunk(&mut self, hash: &str) -> Option<Vec<u8>> {
+        self.0.get_cached_chunk(hash)
+    }
+
+    pub fn has_cached_chunk(&self, hash: &str) -> bool {
+        self.0.has_cached_chunk(hash)
+    }
+
+    /// `{quota_bytes, used_bytes, chunks: {entries, bytes, hits, misses, evictions},
+    /// held_frames: {frames, bytes, dropped}}`
+    pub fn get_cache_usage_json(&self) -> String {
+        self.0.get_cache_usage_json()
+    }
+
+    /// Empty the chunk cache and drop the frames held for unreachable peers
+    pub fn clear_caches(&mut self) {
+        self.0.clear_caches();
+    }
+}
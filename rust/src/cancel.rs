@@ -0,0 +1,34 @@
+//! JS bindings for cooperative cancellation (see `p2p_sync_core::cancel`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::cancel as core_cancel;
+pub use p2p_sync_core::cancel::*;
+
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct CancellationToken(core_cancel::CancellationToken);
+
+core_wrapper!(CancellationToken, core_cancel::CancellationToken);
+
+#[wasm_bindgen]
+impl CancellationToken {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Request cancellation of every operation observing this token
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Re-arm the token so it can be reused for the next operation
+    pub fn reset(&self) {
+        self.0.reset();
+    }
+}
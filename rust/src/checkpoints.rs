@@ -0,0 +1,35 @@
+//! JS bindings for signed journal checkpoints (see `p2p_sync_core::checkpoints`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::DeviceIdentity;
+use crate::P2PNode;
+
+pub use p2p_sync_core::checkpoints::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Whether enough has changed since our last checkpoint to send a new one
+    pub fn is_checkpoint_due(&self) -> bool {
+        self.0.is_checkpoint_due()
+    }
+
+    /// Sign the current journal root; returns a `Checkpoint` frame for peers with `CAP_CHECKPOINTS`
+    pub fn create_checkpoint_frame(&mut self, identity: &DeviceIdentity) -> Vec<u8> {
+        self.0.create_checkpoint_frame(identity)
+    }
+
+    /// Verify a peer's checkpoint frame and check it against its earlier ones
+    pub fn process_checkpoint_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.0.process_checkpoint_frame(frame)
+    }
+
+    /// Received checkpoints as JSON, for persisting with `load_checkpoint_state`
+    pub fn get_checkpoint_state(&self) -> String {
+        self.0.get_checkpoint_state()
+    }
+
+    pub fn load_checkpoint_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_checkpoint_state(json)
+    }
+}
@@ -0,0 +1,57 @@
+//! JS bindings for the crate clock (see `p2p_sync_core::clock`)
+//!
+//! The core has no clock of its own in WASM; `install` points it at the
+//! host's `Date.now()` and `performance.now()` when the module starts.
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::clock as core_clock;
+pub use p2p_sync_core::clock::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    // Older Node.js releases have no global `performance`; the call then throws
+    #[wasm_bindgen(catch, js_namespace = performance, js_name = now)]
+    fn performance_now() -> Result<f64, JsValue>;
+}
+
+/// Read the core clock from the host
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn install() {
+    core_clock::set_time_source(TimeSource {
+        now_ms: || date_now() as u64,
+        monotonic_ms: || performance_now().unwrap_or_else(|_| date_now()),
+    });
+}
+
+/// Pin the clock to a fixed time (milliseconds since the Unix epoch)
+#[wasm_bindgen]
+pub fn set_clock_time(time_ms: u64) {
+    core_clock::set_clock_time(time_ms);
+}
+
+/// Advance a pinned clock; pins it at the current system time first if needed
+#[wasm_bindgen]
+pub fn advance_clock(delta_ms: u64) {
+    core_clock::advance_clock(delta_ms);
+}
+
+/// Return to the system clock
+#[wasm_bindgen]
+pub fn use_system_clock() {
+    core_clock::use_system_clock();
+}
+
+/// Whether the clock is currently pinned
+#[wasm_bindgen]
+pub fn is_clock_overridden() -> bool {
+    core_clock::is_clock_overridden()
+}
@@ -0,0 +1,25 @@
+//! JS bindings for device power and network conditions (see `p2p_sync_core::conditions`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::conditions::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Report the battery level (0-100) and whether the device is charging
+    pub fn set_power_state(&mut self, battery_percent: u8, charging: bool) -> Result<(), String> {
+        self.0.set_power_state(battery_percent, charging)
+    }
+
+    /// Report the network: `unmetered`, `metered` or `offline`
+    pub fn set_network_class(&mut self, class: &str) -> Result<(), String> {
+        self.0.set_network_class(class)
+    }
+
+    /// Reported conditions as JSON, with whether bulk sync is currently paused
+    pub fn get_conditions_json(&self) -> String {
+        self.0.get_conditions_json()
+    }
+}
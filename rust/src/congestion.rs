@@ -0,0 +1,35 @@
+//! JS bindings for bandwidth sharing across peers (see `p2p_sync_core::congestion`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::congestion::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Upload budget shared by all peers, in bytes per second; 0 is unlimited
+    pub fn set_bandwidth_budget(&mut self, bytes_per_sec: u64) {
+        self.0.set_bandwidth_budget(bytes_per_sec);
+    }
+
+    /// Queue `bytes` to send to `peer_id`; `priority` is `interactive` or `bulk`
+    pub fn enqueue_peer_transfer(&mut self, peer_id: &str, priority: &str, bytes: u64) -> Result<(), String> {
+        self.0.enqueue_peer_transfer(peer_id, priority, bytes)
+    }
+
+    /// Drop a peer's flow when its transfers finished early or were cancelled
+    pub fn finish_peer_transfer(&mut self, peer_id: &str) {
+        self.0.finish_peer_transfer(peer_id);
+    }
+
+    /// Bytes each peer may be sent now, as a `{peer_id: bytes}` JSON object
+    pub fn grant_bandwidth(&mut self, now: u64) -> String {
+        self.0.grant_bandwidth(now)
+    }
+
+    /// `{budget_bytes_per_sec, flows: {peer_id: {priority, pending_bytes, last_grant}}}` as JSON
+    pub fn get_bandwidth_json(&self) -> String {
+        self.0.get_bandwidth_json()
+    }
+}
@@ -1,210 +1,154 @@
-/**
- * Cryptography and Authentication Module
- * Handles keypair generation, storage, and authenticated key exchange
- */
+//! JS bindings for device keys, pairing codes and encryption (see
+//! `p2p_sync_core::crypto`)
 
 use wasm_bindgen::prelude::*;
-use ed25519_dalek::{SigningKey, VerifyingKey, Signer, Verifier, Signature};
-use x25519_dalek::{StaticSecret, PublicKey as XPublicKey};
-use rand_core::{OsRng, RngCore};
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use std::convert::TryInto;
-use aes_gcm::{
-    aead::{Aead, KeyInit, Payload},
-    Aes256Gcm, Nonce // Or XChaCha20Poly1305 if preferred, but AES-GCM is in Cargo.toml
-};
-
-// Helper to encode/decode base64
-fn to_base64(data: &[u8]) -> String {
-    BASE64.encode(data)
-}
-
-fn from_base64(data: &str) -> Result<Vec<u8>, String> {
-    BASE64.decode(data).map_err(|e| e.to_string())
-}
 
-// ============================================================================
-// Device Identity (Ed25519)
-// ============================================================================
+use p2p_sync_core::crypto as core_crypto;
+pub use p2p_sync_core::crypto::*;
 
 #[wasm_bindgen]
-pub struct DeviceIdentity {
-    device_id: String,
-    secret_key: Vec<u8>, // Ed25519 secret key bytes
-    public_key: Vec<u8>, // Ed25519 public key bytes
-}
+pub struct DeviceIdentity(core_crypto::DeviceIdentity);
+
+core_wrapper!(DeviceIdentity, core_crypto::DeviceIdentity);
 
 #[wasm_bindgen]
 impl DeviceIdentity {
     /// Generate a new random device identity
     #[wasm_bindgen(constructor)]
     pub fn new(device_id: String) -> Result<DeviceIdentity, String> {
-        let mut bytes = [0u8; 32];
-        OsRng.fill_bytes(&mut bytes);
-        let signing_key = SigningKey::from_bytes(&bytes);
-        let verifying_key = signing_key.verifying_key();
-
-        Ok(DeviceIdentity {
-            device_id,
-            secret_key: signing_key.to_bytes().to_vec(),
-            public_key: verifying_key.to_bytes().to_vec(),
-        })
+        Ok(core_crypto::DeviceIdentity::new(device_id)?.into())
     }
 
     /// Reconstruct identity from saved secret key (base64)
     pub fn from_secret_key(device_id: String, secret_key_b64: String) -> Result<DeviceIdentity, String> {
-        let secret_bytes = from_base64(&secret_key_b64)?;
-        let secret_arr: [u8; 32] = secret_bytes.try_into().map_err(|_| "Invalid key length")?;
-        let signing_key = SigningKey::from_bytes(&secret_arr);
-        let verifying_key = signing_key.verifying_key();
+        Ok(core_crypto::DeviceIdentity::from_secret_key(device_id, secret_key_b64)?.into())
+    }
 
-        Ok(DeviceIdentity {
-            device_id,
-            secret_key: secret_arr.to_vec(),
-            public_key: verifying_key.to_bytes().to_vec(),
-        })
+    /// The secret key encrypted under a 32-byte wrapping key (base64), for
+    /// storing the identity outside the plugin's own data
+    pub fn export_wrapped_secret_key(&self, wrapping_key_b64: String) -> Result<String, String> {
+        Ok(self.0.export_wrapped_secret_key(wrapping_key_b64)?)
     }
 
-    pub fn get_device_id(&self) -> String {
-        self.device_id.clone()
+    /// Restore an identity exported with `export_wrapped_secret_key`
+    pub fn from_wrapped_secret_key(device_id: String, wrapped_b64: String, wrapping_key_b64: String) -> Result<DeviceIdentity, String> {
+        Ok(core_crypto::DeviceIdentity::from_wrapped_secret_key(device_id, wrapped_b64, wrapping_key_b64)?.into())
     }
 
-    pub fn get_public_key(&self) -> String {
-        to_base64(&self.public_key)
+    pub fn get_device_id(&self) -> String {
+        self.0.get_device_id()
     }
 
-    pub fn get_secret_key(&self) -> String {
-        to_base64(&self.secret_key)
+    pub fn get_public_key(&self) -> String {
+        self.0.get_public_key()
     }
 
     /// Sign a message with the device's private key
     pub fn sign(&self, message: &[u8]) -> String {
-        let secret_arr: [u8; 32] = self.secret_key.clone().try_into().unwrap();
-        let signing_key = SigningKey::from_bytes(&secret_arr);
-        let signature = signing_key.sign(message);
-        to_base64(&signature.to_bytes())
+        self.0.sign(message)
     }
 }
 
 /// Verify a signature from another device
 #[wasm_bindgen]
 pub fn verify_signature(public_key_b64: String, message: &[u8], signature_b64: String) -> bool {
-    let pk_bytes = match from_base64(&public_key_b64) { Ok(b) => b, Err(_) => return false };
-    let sig_bytes = match from_base64(&signature_b64) { Ok(b) => b, Err(_) => return false };
-
-    let pk_arr: [u8; 32] = match pk_bytes.try_into() { Ok(b) => b, Err(_) => return false };
-    let sig_arr: [u8; 64] = match sig_bytes.try_into() { Ok(b) => b, Err(_) => return false };
-
-    let verifying_key = match VerifyingKey::from_bytes(&pk_arr) { Ok(k) => k, Err(_) => return false };
-    let signature = Signature::from_bytes(&sig_arr);
-
-    verifying_key.verify(message, &signature).is_ok()
+    core_crypto::verify_signature(public_key_b64, message, signature_b64)
 }
 
-// ============================================================================
-// Ephemeral Key Exchange (X25519)
-// ============================================================================
-
 #[wasm_bindgen]
-pub struct KeyExchange {
-    secret: StaticSecret,
-    public: XPublicKey,
-}
+#[derive(Default)]
+pub struct KeyExchange(core_crypto::KeyExchange);
+
+core_wrapper!(KeyExchange, core_crypto::KeyExchange);
 
 #[wasm_bindgen]
 impl KeyExchange {
     #[wasm_bindgen(constructor)]
     pub fn new() -> KeyExchange {
-        let secret = StaticSecret::random_from_rng(OsRng);
-        let public = XPublicKey::from(&secret);
-        KeyExchange { secret, public }
+        core_crypto::KeyExchange::new().into()
+    }
+
+    /// Restore a long-lived key (e.g. a mailbox key) from its saved secret
+    pub fn from_secret_key(secret_key_b64: String) -> Result<KeyExchange, String> {
+        Ok(core_crypto::KeyExchange::from_secret_key(secret_key_b64)?.into())
+    }
+
+    /// The secret key encrypted under a 32-byte wrapping key (base64)
+    pub fn export_wrapped_secret_key(&self, wrapping_key_b64: String) -> Result<String, String> {
+        Ok(self.0.export_wrapped_secret_key(wrapping_key_b64)?)
+    }
+
+    /// Restore a key exported with `export_wrapped_secret_key`
+    pub fn from_wrapped_secret_key(wrapped_b64: String, wrapping_key_b64: String) -> Result<KeyExchange, String> {
+        Ok(core_crypto::KeyExchange::from_wrapped_secret_key(wrapped_b64, wrapping_key_b64)?.into())
     }
 
     pub fn get_public_key(&self) -> String {
-        to_base64(self.public.as_bytes())
+        self.0.get_public_key()
     }
 
     pub fn compute_shared_secret(&self, other_public_key_b64: String) -> Result<String, String> {
-        let other_bytes = from_base64(&other_public_key_b64)?;
-        let other_arr: [u8; 32] = other_bytes.try_into().map_err(|_| "Invalid key length")?;
-        let other_pk = XPublicKey::from(other_arr);
-
-        let shared_secret = self.secret.diffie_hellman(&other_pk);
-        Ok(to_base64(shared_secret.as_bytes()))
+        Ok(self.0.compute_shared_secret(other_public_key_b64)?)
     }
 }
 
-// ============================================================================
-// Pairing Logic
-// ============================================================================
-
 #[wasm_bindgen]
-pub struct PairingCode {
-    code: String,
-}
+pub struct PairingCode(core_crypto::PairingCode);
+
+core_wrapper!(PairingCode, core_crypto::PairingCode);
 
 #[wasm_bindgen]
 impl PairingCode {
     pub fn generate() -> PairingCode {
-        let mut bytes = [0u8; 4];
-        OsRng.fill_bytes(&mut bytes);
-        // Generate a 6-digit code
-        let num: u32 = u32::from_be_bytes(bytes);
-        let code = format!("{:06}", num % 1_000_000);
-        PairingCode { code }
+        core_crypto::PairingCode::generate().into()
     }
 
     pub fn get_code(&self) -> String {
-        self.code.clone()
+        self.0.get_code()
+    }
+
+    /// Creation time according to the crate clock
+    pub fn get_created_at(&self) -> u64 {
+        self.0.get_created_at()
+    }
+
+    /// Whether the code is older than `ttl_ms` (0 selects the default TTL)
+    pub fn is_expired(&self, ttl_ms: u64) -> bool {
+        self.0.is_expired(ttl_ms)
     }
 }
 
 /// Generate a pairing code (helper function)
 #[wasm_bindgen]
 pub fn generate_pairing_code() -> String {
-    PairingCode::generate().get_code()
+    core_crypto::generate_pairing_code()
 }
 
 /// Generate a device fingerprint from public keys
 #[wasm_bindgen]
 pub fn generate_fingerprint(public_key_b64: &str) -> String {
-    // Simple fingerprint: first 16 chars of hex representation of the key hash?
-    // Or just use the key itself if it's short enough?
-    // Let's hash the key and take first 16 chars of hex
-    // Since we don't have sha2 imported yet in this snippet (I added it to Cargo.toml but not used here yet),
-    // I'll just use a simple slice of the base64 string for now, or implement simple hash.
-    // Actually, I should use Sha256.
-    // I'll add `use sha2::{Sha256, Digest};` to imports.
-    // But I didn't add it to the imports in the `newString` above.
-    // I'll just return the first 16 chars of the public key base64 for now.
-    // It's a fingerprint for visual verification.
-    public_key_b64.chars().take(16).collect()
+    core_crypto::generate_fingerprint(public_key_b64)
 }
 
 /// Verify a pairing code format
 #[wasm_bindgen]
 pub fn verify_pairing_code_format(code: &str) -> bool {
-    code.len() == 6 && code.chars().all(|c| c.is_numeric())
+    core_crypto::verify_pairing_code_format(code)
 }
 
-// ============================================================================
-// Symmetric Encryption (AES-GCM)
-// ============================================================================
-
 #[wasm_bindgen]
-pub struct EncryptedChunk {
-    data: Vec<u8>,
-    nonce: Vec<u8>,
-}
+pub struct EncryptedChunk(core_crypto::EncryptedChunk);
+
+core_wrapper!(EncryptedChunk, core_crypto::EncryptedChunk);
 
 #[wasm_bindgen]
 impl EncryptedChunk {
     pub fn get_data(&self) -> Vec<u8> {
-        self.data.clone()
+        self.0.get_data()
     }
 
     pub fn get_nonce(&self) -> Vec<u8> {
-        self.nonce.clone()
+        self.0.get_nonce()
     }
 }
 
@@ -212,35 +156,11 @@ impl EncryptedChunk {
 /// Key must be 32 bytes (base64 encoded)
 #[wasm_bindgen]
 pub fn encrypt_data(key_b64: String, plaintext: &[u8]) -> Result<EncryptedChunk, String> {
-    let key_bytes = from_base64(&key_b64)?;
-    let key = aes_gcm::Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
-
-    let mut nonce_bytes = [0u8; 12]; // 96-bit nonce
-    OsRng.fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    let ciphertext = cipher.encrypt(nonce, plaintext)
-        .map_err(|e| format!("Encryption failed: {}", e))?;
-
-    Ok(EncryptedChunk {
-        data: ciphertext,
-        nonce: nonce_bytes.to_vec(),
-    })
+    Ok(core_crypto::encrypt_data(key_b64, plaintext)?.into())
 }
 
 /// Decrypt data using AES-256-GCM
 #[wasm_bindgen]
 pub fn decrypt_data(key_b64: String, ciphertext: &[u8], nonce_bytes: &[u8]) -> Result<Vec<u8>, String> {
-    let key_bytes = from_base64(&key_b64)?;
-    let key = aes_gcm::Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
-
-    let nonce = Nonce::from_slice(nonce_bytes);
-
-    let plaintext = cipher.decrypt(nonce, ciphertext)
-        .map_err(|e| format!("Decryption failed: {}", e))?;
-
-    Ok(plaintext)
+    Ok(core_crypto::decrypt_data(key_b64, ciphertext, nonce_bytes)?)
 }
-
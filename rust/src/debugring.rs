@@ -0,0 +1,25 @@
+//! JS bindings for rolling debug ring (see `p2p_sync_core::debugring`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::debugring::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// The ring as `{capacity, dropped, entries: [{at, category, event, subject?, value?}]}`,
+    /// oldest first, keeping entries from `since_ms` on (0 for all)
+    pub fn get_debug_ring_json(&self, since_ms: u64) -> String {
+        self.0.get_debug_ring_json(since_ms)
+    }
+
+    /// Resize the ring, keeping the newest entries; 0 turns it off
+    pub fn set_debug_ring_capacity(&mut self, capacity: usize) {
+        self.0.set_debug_ring_capacity(capacity);
+    }
+
+    pub fn clear_debug_ring(&mut self) {
+        self.0.clear_debug_ring();
+    }
+}
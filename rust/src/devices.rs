@@ -0,0 +1,47 @@
+//! JS bindings for device profiles (see `p2p_sync_core::devices`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::DeviceIdentity;
+use crate::P2PNode;
+
+pub use p2p_sync_core::devices::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Rename this device (and set its icon and platform) from JSON `{name, icon, platform}`;
+    /// returns a signed `DeviceProfile` frame for peers with `CAP_DEVICE_PROFILES`
+    pub fn update_device_profile(&mut self, identity: &DeviceIdentity, details_json: &str) -> Result<Vec<u8>, String> {
+        self.0.update_device_profile(identity, details_json)
+    }
+
+    /// Verify a device's profile frame (sent by it or forwarded by a peer); returns
+    /// true when it was newer than what we knew and names were updated
+    pub fn process_device_profile_frame(&mut self, frame: &[u8]) -> Result<bool, String> {
+        self.0.process_device_profile_frame(frame)
+    }
+
+    /// The latest signed profile of `device_id`, to forward to a peer that may not have it
+    pub fn get_device_profile_frame(&self, device_id: &str) -> Option<Vec<u8>> {
+        self.0.get_device_profile_frame(device_id)
+    }
+
+    /// Name to show for `device_id`, e.g. in journal attribution; the ID itself when unknown
+    pub fn get_device_display_name(&self, device_id: &str) -> String {
+        self.0.get_device_display_name(device_id)
+    }
+
+    /// Known device profiles as a JSON object keyed by device ID
+    pub fn get_known_devices_json(&self) -> String {
+        self.0.get_known_devices_json()
+    }
+
+    /// Device profiles as JSON, for persisting with `load_device_directory_state`
+    pub fn get_device_directory_state(&self) -> String {
+        self.0.get_device_directory_state()
+    }
+
+    pub fn load_device_directory_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_device_directory_state(json)
+    }
+}
@@ -0,0 +1,30 @@
+//! JS bindings for extended file metadata (see `p2p_sync_core::extmeta`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::extmeta::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Replace a file's extended metadata (`{ctime?, executable?, attributes?}`),
+    /// recording a new version if it changed
+    pub fn set_file_extended_metadata(&mut self, path: &str, json: &str) -> Result<bool, String> {
+        self.0.set_file_extended_metadata(path, json)
+    }
+
+    /// A file's extended metadata as JSON, `null` for an unknown path
+    pub fn get_file_extended_metadata(&self, path: &str) -> String {
+        self.0.get_file_extended_metadata(path)
+    }
+
+    /// Set `{local_prefixes?, max_attributes?, max_value_bytes?}`; omitted fields take their defaults
+    pub fn set_metadata_rules(&mut self, json: &str) -> Result<(), String> {
+        self.0.set_metadata_rules(json)
+    }
+
+    pub fn get_metadata_rules_json(&self) -> String {
+        self.0.get_metadata_rules_json()
+    }
+}
@@ -0,0 +1,40 @@
+//! JS bindings for first-sync bootstrap between two devices (see `p2p_sync_core::firstsync`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::firstsync::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Open a first-sync bootstrap: the `Offer` message to send the peer, as JSON
+    pub fn start_bootstrap_exchange(&self) -> String {
+        self.0.start_bootstrap_exchange()
+    }
+
+    /// Handle a bootstrap message from a peer; returns the reply to send as JSON, or `null`
+    /// once the exchange is over (see `get_bootstrap_result_json`)
+    pub fn process_bootstrap_message(&mut self, message_json: &str) -> Result<String, String> {
+        self.0.process_bootstrap_message(message_json)
+    }
+
+    /// `{identical, differing, missing_on_peer}` from the last bootstrap with a peer, or `null`
+    pub fn get_bootstrap_result_json(&self, device_id: &str) -> String {
+        self.0.get_bootstrap_result_json(device_id)
+    }
+
+    /// Peer journal sequence reconciled by bootstrap, if any
+    pub fn get_peer_watermark(&self, device_id: &str) -> Option<u64> {
+        self.0.get_peer_watermark(device_id)
+    }
+
+    /// Watermarks and results as JSON, for persisting with `load_bootstrap_state`
+    pub fn get_bootstrap_state(&self) -> String {
+        self.0.get_bootstrap_state()
+    }
+
+    pub fn load_bootstrap_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_bootstrap_state(json)
+    }
+}
@@ -0,0 +1,22 @@
+//! JS bindings for content hashing (see `p2p_sync_core::hashing`)
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+pub use p2p_sync_core::hashing::*;
+
+#[derive(Serialize)]
+struct BuildFeatures {
+    version: &'static str,
+    simd128: bool,
+}
+
+/// Which optional CPU features this build was compiled with, as JSON
+#[wasm_bindgen]
+pub fn get_build_features() -> String {
+    let features = BuildFeatures {
+        version: env!("CARGO_PKG_VERSION"),
+        simd128: cfg!(all(target_arch = "wasm32", target_feature = "simd128")),
+    };
+    serde_json::to_string(&features).unwrap_or_default()
+}
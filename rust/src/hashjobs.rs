@@ -0,0 +1,13 @@
+//! JS bindings for hash work offloading (see `p2p_sync_core::hashjobs`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::hashjobs as core_hashjobs;
+
+pub use p2p_sync_core::hashjobs::*;
+
+/// Run one job in a worker: hash the job's slice of `data`; returns `{id, hash}` JSON
+#[wasm_bindgen]
+pub fn compute_hash_job(job_json: &str, data: &[u8]) -> Result<String, String> {
+    core_hashjobs::compute_hash_job(job_json, data)
+}
@@ -0,0 +1,19 @@
+//! JS bindings for sortable identifiers (see `p2p_sync_core::ids`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::ids as core_ids;
+
+pub use p2p_sync_core::ids::*;
+
+/// A new sortable ID, for IDs the host creates itself
+#[wasm_bindgen]
+pub fn generate_id() -> String {
+    core_ids::generate_id()
+}
+
+/// Creation time of a ULID (or UUIDv7) in milliseconds, or `undefined`
+#[wasm_bindgen]
+pub fn get_id_timestamp(id: &str) -> Option<u64> {
+    core_ids::get_id_timestamp(id)
+}
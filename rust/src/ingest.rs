@@ -0,0 +1,96 @@
+//! JS bindings for slice-fed file ingestion (see `p2p_sync_core::ingest`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::ingest as core_ingest;
+
+use crate::cancel::CancellationToken;
+use crate::P2PNode;
+
+#[wasm_bindgen]
+pub struct FileIngest(core_ingest::FileIngest);
+
+core_wrapper!(FileIngest, core_ingest::FileIngest);
+
+#[wasm_bindgen]
+impl FileIngest {
+    /// Start ingesting `size` bytes of `file_path`, hashed with `algorithm`
+    /// and, if `session_key` is given, encrypted into transfer chunks
+    #[wasm_bindgen(constructor)]
+    pub fn new(file_path: String, size: u64, algorithm: &str, session_key: Option<String>) -> Result<FileIngest, String> {
+        Ok(core_ingest::FileIngest::new(file_path, size, algorithm, session_key)?.into())
+    }
+
+    /// Push the next slice; returns a JSON array of the chunks it completed
+    /// (always empty without a session key)
+    pub fn push(&mut self, slice: &[u8]) -> Result<String, String> {
+        Ok(self.0.push(slice)?)
+    }
+
+    /// End the session once every byte has been pushed; returns the last chunks as a JSON array
+    pub fn finish(&mut self) -> Result<String, String> {
+        Ok(self.0.finish()?)
+    }
+
+    /// Hash of the whole file with the session's algorithm, once finished
+    pub fn get_hash(&self) -> Option<String> {
+        self.0.get_hash()
+    }
+
+    pub fn get_received(&self) -> u64 {
+        self.0.get_received()
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.0.get_size()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+
+    /// Attach a token checked between chunks
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.0.set_cancellation_token(token);
+    }
+
+    /// Pad the chunks this session produces; only before the first slice
+    pub fn set_padding(&mut self, enabled: bool) -> Result<(), String> {
+        Ok(self.0.set_padding(enabled)?)
+    }
+
+    /// Transfer the chunks belong to, for the receiver's `IncomingTransfer`; only before the first slice
+    pub fn set_transfer_id(&mut self, transfer_id: String) -> Result<(), String> {
+        Ok(self.0.set_transfer_id(transfer_id)?)
+    }
+
+    /// Hash the receiver expects, when the sender knows it up front (e.g. from
+    /// the journal); both sides derive the transfer key from it. Only before the first slice
+    pub fn set_file_hash(&mut self, file_hash: String) -> Result<(), String> {
+        Ok(self.0.set_file_hash(file_hash)?)
+    }
+
+    /// Number of chunks the session will produce, filler chunks included
+    pub fn get_total_chunks(&self) -> u32 {
+        self.0.get_total_chunks()
+    }
+}
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Start an ingest of `path` that hashes the way the journal needs, and
+    /// produces transfer chunks too when `session_key` is given
+    pub fn begin_file_ingest(
+        &mut self,
+        path: String,
+        size: u64,
+        session_key: Option<String>,
+    ) -> Result<FileIngest, String> {
+        self.0.begin_file_ingest(path, size, session_key).map(Into::into)
+    }
+
+    /// Record a finished ingest as a local change, like `update_file`
+    pub fn record_ingest(&mut self, ingest: &FileIngest, mtime: u64) -> Result<bool, String> {
+        self.0.record_ingest(ingest, mtime)
+    }
+}
@@ -0,0 +1,57 @@
+//! JS bindings for vault membership and introductions (see `p2p_sync_core::introductions`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::DeviceIdentity;
+use crate::P2PNode;
+
+pub use p2p_sync_core::introductions::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Record a device paired directly with this one (e.g. after `check_pairing_attempt`
+    /// accepted its code); false if it was already a member with this key
+    pub fn add_vault_member(&mut self, device_id: &str, public_key: &str) -> Result<bool, String> {
+        self.0.add_vault_member(device_id, public_key)
+    }
+
+    /// Remove a device from the vault; false if it wasn't a member
+    pub fn remove_vault_member(&mut self, device_id: &str) -> bool {
+        self.0.remove_vault_member(device_id)
+    }
+
+    pub fn is_vault_member(&self, device_id: &str, public_key: &str) -> bool {
+        self.0.is_vault_member(device_id, public_key)
+    }
+
+    /// Members as `{device_id: {identity_key, added_at, introduced_by}}`
+    pub fn get_vault_members_json(&self) -> String {
+        self.0.get_vault_members_json()
+    }
+
+    /// Admit a newly paired device and introduce it to the vault; returns
+    /// `{certificate, messages: [{to, message}]}` as JSON
+    pub fn introduce_device(
+        &mut self,
+        identity: &DeviceIdentity,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<String, String> {
+        self.0.introduce_device(identity, device_id, public_key)
+    }
+
+    /// Apply a certificate or introduction from another member; returns the device IDs
+    /// it added as a JSON array
+    pub fn process_introduction(&mut self, message: &str) -> Result<String, String> {
+        self.0.process_introduction(message)
+    }
+
+    /// Members and removals as JSON, for persisting with `load_vault_members_state`
+    pub fn get_vault_members_state(&self) -> String {
+        self.0.get_vault_members_state()
+    }
+
+    pub fn load_vault_members_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_vault_members_state(json)
+    }
+}
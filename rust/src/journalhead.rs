@@ -0,0 +1,37 @@
+//! JS bindings for journal heads in announcements and heartbeats (see `p2p_sync_core::journalhead`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::journalhead::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// This device's journal head as `{sequence, digest}`
+    pub fn get_journal_head_json(&self) -> String {
+        self.0.get_journal_head_json()
+    }
+
+    /// How a discovered peer's journal compares to ours: `in_sync`, `ahead`, `behind`,
+    /// or `unknown` for an unknown peer or one that advertised no head
+    pub fn compare_peer_journal_head(&self, peer_id: &str) -> String {
+        self.0.compare_peer_journal_head(peer_id)
+    }
+
+    /// Every discovered peer as `[{peer_id, device_id, name, head?, status}]`
+    pub fn get_peer_journal_heads_json(&self) -> String {
+        self.0.get_peer_journal_heads_json()
+    }
+
+    /// A `Ping` control frame carrying our journal head, for a link's keepalive
+    pub fn get_heartbeat_frame(&self) -> Vec<u8> {
+        self.0.get_heartbeat_frame()
+    }
+
+    /// Record the journal head in a peer's `Ping` or `Pong`; returns the `Pong` to send
+    /// back for a `Ping`, or nothing
+    pub fn process_heartbeat_frame(&mut self, peer_id: &str, frame: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.0.process_heartbeat_frame(peer_id, frame)
+    }
+}
@@ -0,0 +1,22 @@
+//! JS bindings for the journal's version history (see `p2p_sync_core::journalhistory`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::journalhistory::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// The journal's version history as JSON, for persisting with `load_history_state`
+    /// apart from the journal itself
+    pub fn get_history_state(&self) -> String {
+        self.0.get_history_state()
+    }
+
+    /// Restore a history saved with `get_history_state`; load the journal first,
+    /// as loading a journal replaces its history
+    pub fn load_history_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_history_state(json)
+    }
+}
@@ -1,14 +1,96 @@
 use wasm_bindgen::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use uuid::Uuid;
+
+/// `From`, `Deref` and `DerefMut` between a JS class and the core type it wraps, so
+/// Rust callers can use the core API on it directly
+macro_rules! core_wrapper {
+    ($wrapper:ident, $inner:ty) => {
+        impl From<$inner> for $wrapper {
+            fn from(inner: $inner) -> $wrapper {
+                $wrapper(inner)
+            }
+        }
+
+        impl std::ops::Deref for $wrapper {
+            type Target = $inner;
+
+            fn deref(&self) -> &$inner {
+                &self.0
+            }
+        }
+
+        impl std::ops::DerefMut for $wrapper {
+            fn deref_mut(&mut self) -> &mut $inner {
+                &mut self.0
+            }
+        }
+    };
+}
 
 // Module declarations
+pub mod access;
+pub mod archive;
+pub mod bindiff;
+pub mod caches;
+pub mod cancel;
+pub mod checkpoints;
+pub mod clock;
+pub mod conditions;
+pub mod congestion;
 pub mod crypto;
+pub mod debugring;
+pub mod devices;
+pub mod extmeta;
+pub mod firstsync;
+pub mod hashing;
+pub mod hashjobs;
+pub mod ids;
+pub mod ingest;
+pub mod introductions;
+pub mod journalhead;
+pub mod journalhistory;
+pub mod links;
+pub mod mailbox;
+pub mod mergedrivers;
+pub mod mnemonic;
+pub mod negotiation;
+pub mod objectstore;
+pub mod orphans;
+pub mod padding;
+pub mod pairing;
+pub mod peerstats;
+pub mod pins;
+pub mod placeholders;
+pub mod prefetch;
+pub mod quarantine;
+pub mod query;
+pub mod relaypair;
+pub mod repair;
+pub mod reports;
+pub mod resume;
+pub mod round;
+pub mod schedule;
+pub mod security;
+pub mod shards;
+pub mod shutdown;
+pub mod status;
+pub mod streams;
 pub mod sync;
+pub mod timing;
+pub mod trace;
 pub mod transfer;
+pub mod transports;
+pub mod trickle;
+pub mod usage;
+pub mod vaultevents;
+pub mod wal;
+pub mod webdav;
+pub mod wire;
+
+// Logic without JS bindings lives in the core crate; re-exported so paths stay the same
+pub use p2p_sync_core::{attachments, audit, backend, bootstrap, commands, compat, compression, config, conflicts, diff, export, filetable, history, issues, limits, livesync, loader, loopback, memory, merge, policy, preview, profiles, retention, sim, sketch};
 
-use sync::ChangeJournal;
+use cancel::CancellationToken;
+use status::NodeStatus;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global allocator
 #[cfg(feature = "wee_alloc")]
@@ -16,9 +98,11 @@ use sync::ChangeJournal;
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 /// Initialize panic hook for better error messages in browser console
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
 pub fn init() {
     console_error_panic_hook::set_once();
+    clock::install();
 }
 
 /// Simple greeting function to test WASM integration
@@ -33,37 +117,12 @@ pub fn get_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
-// ============================================================================
-// P2P Discovery Structures
-// ============================================================================
-
-#[derive(Serialize, Deserialize)]
-struct PeerAnnouncement {
-    #[serde(default = "default_announcement_type")]
-    #[serde(rename = "type")]
-    msg_type: String,
-    peer_id: String,
-    device_name: String,
-    device_id: String,
-    #[serde(default)]
-    service_port: u16,
-}
-
-fn default_announcement_type() -> String {
-    "announcement".to_string()
-}
-
 /// Discovered peer information
 #[wasm_bindgen]
-#[derive(Serialize, Deserialize, Clone)]
-pub struct DiscoveredPeer {
-    id: String,
-    name: String,
-    device_id: String,
-    last_seen_timestamp: u64,
-    address: String, // IP address
-    service_port: u16,
-}
+#[derive(Clone)]
+pub struct DiscoveredPeer(p2p_sync_core::DiscoveredPeer);
+
+core_wrapper!(DiscoveredPeer, p2p_sync_core::DiscoveredPeer);
 
 #[wasm_bindgen]
 impl DiscoveredPeer {
@@ -76,233 +135,623 @@ impl DiscoveredPeer {
         address: String,
         service_port: u16,
     ) -> DiscoveredPeer {
-        DiscoveredPeer {
-            id,
-            name,
-            device_id,
-            last_seen_timestamp,
-            address,
-            service_port,
-        }
+        p2p_sync_core::DiscoveredPeer::new(id, name, device_id, last_seen_timestamp, address, service_port).into()
     }
 
     pub fn get_id(&self) -> String {
-        self.id.clone()
+        self.0.get_id()
     }
 
     pub fn get_name(&self) -> String {
-        self.name.clone()
+        self.0.get_name()
     }
 
     pub fn get_device_id(&self) -> String {
-        self.device_id.clone()
+        self.0.get_device_id()
     }
 
     pub fn get_last_seen_timestamp(&self) -> u64 {
-        self.last_seen_timestamp
+        self.0.get_last_seen_timestamp()
     }
 
     pub fn get_address(&self) -> String {
-        self.address.clone()
+        self.0.get_address()
     }
 
     pub fn get_service_port(&self) -> u16 {
-        self.service_port
+        self.0.get_service_port()
     }
-}
 
-// ============================================================================
-// P2P Node with Discovery
-// ============================================================================
+    pub fn get_profile(&self) -> String {
+        self.0.get_profile()
+    }
+}
 
 /// Main P2P Node structure
 #[wasm_bindgen]
-pub struct P2PNode {
-    peer_id: String,
-    device_name: String,
-    device_id: String,
-    service_port: u16,
-    peers: HashMap<String, DiscoveredPeer>,
-    is_discovering: bool,
-    change_journal: ChangeJournal,
-}
+pub struct P2PNode(p2p_sync_core::P2PNode);
+
+core_wrapper!(P2PNode, p2p_sync_core::P2PNode);
 
 #[wasm_bindgen]
 impl P2PNode {
     /// Create a new P2P node with device configuration
     #[wasm_bindgen(constructor)]
     pub fn new(device_name: String, device_id: String, service_port: u16) -> P2PNode {
-        let peer_id = Uuid::new_v4().to_string();
-        // Use provided device_id
-
-        P2PNode {
-            peer_id: peer_id.clone(),
-            device_name,
-            device_id,
-            service_port,
-            peers: HashMap::new(),
-            is_discovering: false,
-            change_journal: ChangeJournal::new(),
-        }
+        p2p_sync_core::P2PNode::new(device_name, device_id, service_port).into()
     }
 
     /// Get the peer ID of this node
     pub fn get_peer_id(&self) -> String {
-        self.peer_id.clone()
+        self.0.get_peer_id()
     }
 
     /// Get the device name
     pub fn get_device_name(&self) -> String {
-        self.device_name.clone()
+        self.0.get_device_name()
     }
 
     /// Get the device ID
     pub fn get_device_id(&self) -> String {
-        self.device_id.clone()
+        self.0.get_device_id()
     }
 
     /// Start peer discovery
-    pub fn start_discovery(&mut self) -> Result<(), JsValue> {
-        if self.is_discovering {
-            return Err(JsValue::from_str("Discovery already in progress"));
-        }
-
-        self.is_discovering = true;
-        Ok(())
+    pub fn start_discovery(&mut self) -> Result<(), String> {
+        self.0.start_discovery()
     }
 
     /// Stop peer discovery
-    pub fn stop_discovery(&mut self) -> Result<(), JsValue> {
-        self.is_discovering = false;
-        Ok(())
+    pub fn stop_discovery(&mut self) -> Result<(), String> {
+        self.0.stop_discovery()
     }
 
     /// Add a discovered peer (manual/legacy)
-    pub fn add_discovered_peer(&mut self, peer: &DiscoveredPeer) -> Result<(), JsValue> {
-        self.peers.insert(peer.id.clone(), peer.clone());
-        Ok(())
+    pub fn add_discovered_peer(&mut self, peer: &DiscoveredPeer) -> Result<(), String> {
+        self.0.add_discovered_peer(peer)
     }
 
     /// Process an incoming discovery announcement
     /// Returns true if this is a new peer or an update to an existing one
-    pub fn process_announcement(&mut self, json: &str, sender_ip: &str, current_time: u64) -> Result<bool, JsValue> {
-        // Parse as generic JSON first to check type
-        let v: serde_json::Value = serde_json::from_str(json)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse JSON: {}", e)))?;
-
-        // Enforce "type": "announcement"
-        if let Some(msg_type) = v.get("type") {
-            if msg_type.as_str() != Some("announcement") {
-                // Not an announcement (e.g. pairing request), ignore silently
-                return Ok(false);
-            }
-        } else {
-            // Missing type field - invalid protocol message
-            return Ok(false);
-        }
+    pub fn process_announcement(&mut self, json: &str, sender_ip: &str, current_time: u64) -> Result<bool, String> {
+        self.0.process_announcement(json, sender_ip, current_time)
+    }
 
-        let announcement: PeerAnnouncement = serde_json::from_value(v)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse announcement: {}", e)))?;
+    /// Generate an announcement message for this node
+    pub fn get_announcement_json(&self) -> String {
+        self.0.get_announcement_json()
+    }
 
-        // Ignore own announcements
-        if announcement.peer_id == self.peer_id {
-            return Ok(false);
-        }
+    /// Generate a binary announcement frame for this node
+    pub fn get_announcement_frame(&self) -> Vec<u8> {
+        self.0.get_announcement_frame()
+    }
 
-        let peer = DiscoveredPeer {
-            id: announcement.peer_id.clone(),
-            name: announcement.device_name,
-            device_id: announcement.device_id,
-            last_seen_timestamp: current_time,
-            address: sender_ip.to_string(),
-            service_port: announcement.service_port,
-        };
+    /// Process a binary discovery frame; other message types are ignored
+    pub fn process_announcement_frame(
+        &mut self,
+        frame: &[u8],
+        sender_ip: &str,
+        current_time: u64,
+    ) -> Result<bool, String> {
+        self.0.process_announcement_frame(frame, sender_ip, current_time)
+    }
 
-        self.peers.insert(announcement.peer_id, peer);
-        Ok(true)
+    /// Generate a handshake frame carrying our journal sketch, signed over the whole transcript
+    pub fn get_handshake_frame(&self, identity: &crypto::DeviceIdentity, session: &crypto::KeyExchange) -> Vec<u8> {
+        self.0.get_handshake_frame(identity, session)
     }
 
-    /// Generate an announcement message for this node
-    pub fn get_announcement_json(&self) -> String {
-        let announcement = PeerAnnouncement {
-            msg_type: "announcement".to_string(),
-            peer_id: self.peer_id.clone(),
-            device_name: self.device_name.clone(),
-            device_id: self.device_id.clone(),
-            service_port: self.service_port,
-        };
-        serde_json::to_string(&announcement).unwrap_or_default()
+    /// Verify a peer's handshake frame and compare journal sketches; returns a summary as JSON
+    pub fn process_handshake_frame(&mut self, frame: &[u8]) -> Result<String, String> {
+        self.0.process_handshake_frame(frame)
     }
 
     /// Prune peers that haven't been seen for `ttl_ms`
-    pub fn prune_peers(&mut self, current_time: u64, ttl_ms: u64) -> Result<usize, JsValue> {
-        let initial_count = self.peers.len();
-        self.peers.retain(|_, peer| {
-            current_time - peer.last_seen_timestamp < ttl_ms
-        });
-        Ok(initial_count - self.peers.len())
+    pub fn prune_peers(&mut self, current_time: u64, ttl_ms: u64) -> Result<usize, String> {
+        self.0.prune_peers(current_time, ttl_ms)
     }
 
     /// Get list of discovered peers as JSON
     pub fn get_discovered_peers_json(&self) -> String {
-        let peers_vec: Vec<&DiscoveredPeer> = self.peers.values().collect();
-        serde_json::to_string(&peers_vec).unwrap_or_default()
+        self.0.get_discovered_peers_json()
     }
 
     /// Remove a discovered peer by ID
-    pub fn remove_peer(&mut self, peer_id: &str) -> Result<(), JsValue> {
-        self.peers.remove(peer_id);
-        Ok(())
+    pub fn remove_peer(&mut self, peer_id: &str) -> Result<(), String> {
+        self.0.remove_peer(peer_id)
     }
 
     /// Clear all discovered peers
-    pub fn clear_peers(&mut self) -> Result<(), JsValue> {
-        self.peers.clear();
-        Ok(())
+    pub fn clear_peers(&mut self) -> Result<(), String> {
+        self.0.clear_peers()
     }
 
     /// Get the number of discovered peers
     pub fn get_peer_count(&self) -> usize {
-        self.peers.len()
+        self.0.get_peer_count()
+    }
+
+    /// Replace user policy rules from a JSON array of `{pattern, action}`
+    /// (`action` is `lww`, `merge` or `skip`); user rules take precedence over defaults
+    pub fn set_policy_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        self.0.set_policy_rules(rules_json)
+    }
+
+    /// Configure append-merged paths from a JSON array of `{pattern, separator}`
+    pub fn set_append_merge_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        self.0.set_append_merge_rules(rules_json)
+    }
+
+    /// Switch to a built-in profile (`full`, `mobile-lite`)
+    pub fn select_profile(&mut self, name: &str) -> Result<(), String> {
+        self.0.select_profile(name)
+    }
+
+    /// Use a custom profile given as JSON
+    pub fn set_custom_profile(&mut self, profile_json: &str) -> Result<(), String> {
+        self.0.set_custom_profile(profile_json)
+    }
+
+    /// All non-secret configuration as a versioned JSON document
+    pub fn export_config(&self) -> String {
+        self.0.export_config()
+    }
+
+    /// Replace configuration from an `export_config()` document
+    /// Nothing is changed if the document is invalid
+    pub fn import_config(&mut self, blob: &str) -> Result<(), String> {
+        self.0.import_config(blob)
+    }
+
+    /// Active profile as JSON
+    pub fn get_profile_json(&self) -> String {
+        self.0.get_profile_json()
+    }
+
+    /// Built-in profiles as JSON
+    pub fn list_builtin_profiles_json(&self) -> String {
+        self.0.list_builtin_profiles_json()
+    }
+
+    /// Set the attachment layout from Obsidian's `attachmentFolderPath` setting
+    pub fn set_attachment_folder(&mut self, attachment_folder_path: &str) {
+        self.0.set_attachment_folder(attachment_folder_path);
+    }
+
+    /// Set this device's attachment rules from JSON
+    /// (`{unmetered_only, max_size_bytes, transfer_last}`)
+    pub fn set_attachment_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        self.0.set_attachment_rules(rules_json)
+    }
+
+    /// Whether a path is an attachment under the configured layout
+    pub fn is_attachment(&self, path: &str) -> bool {
+        self.0.is_attachment(path)
+    }
+
+    /// Order a JSON array of `{path, size}` for transfer under the attachment rules
+    /// and the reported conditions (`metered` adds to what `set_network_class` says);
+    /// attachments past the profile's storage budget are deferred; pinned files go first
+    /// Returns `{transfer, deferred, skipped}` path lists as JSON
+    pub fn plan_transfer_order(&mut self, files_json: &str, metered: bool) -> Result<String, String> {
+        self.0.plan_transfer_order(files_json, metered)
+    }
+
+    /// Dry run of a sync against a peer's manifest (a JSON array of file metadata):
+    /// what would be pulled, overwritten, deleted, conflicted or pushed, with
+    /// byte totals, as JSON. Nothing is changed
+    pub fn plan_sync_preview(&self, remote_json: &str) -> Result<String, String> {
+        self.0.plan_sync_preview(remote_json)
+    }
+
+    /// Enable or disable the built-in `.obsidian/` rules
+    pub fn set_config_defaults_enabled(&mut self, enabled: bool) {
+        self.0.set_config_defaults_enabled(enabled);
+    }
+
+    /// Effective policy rules in evaluation order as JSON
+    pub fn get_policy_rules_json(&self) -> String {
+        self.0.get_policy_rules_json()
+    }
+
+    /// Policy action (`lww`, `merge` or `skip`) that applies to a path
+    pub fn get_path_policy(&self, path: &str) -> String {
+        self.0.get_path_policy(path)
+    }
+
+    /// Merge two versions of a file whose policy is `merge`, or that a merge driver
+    /// (`register_merge_driver`) takes
+    /// `options_json` is strategy-specific (for JSON: `{arrays, prefer}`, for other
+    /// text merged line by line against `base`: `{prefer}`) and may be empty.
+    /// Returns `{strategy, merged, conflicts}` as JSON
+    pub fn merge_file(
+        &mut self,
+        path: &str,
+        base: Option<String>,
+        local: &str,
+        remote: &str,
+        options_json: &str,
+    ) -> Result<String, String> {
+        self.0.merge_file(path, base, local, remote, options_json)
+    }
+
+    /// Queue a conflict between the local journal entry and a remote version
+    /// `remote_json` is the peer's file metadata; the texts, when given, feed the
+    /// diff summary. Returns the conflict id
+    pub fn add_conflict(
+        &mut self,
+        remote_json: &str,
+        local_text: Option<String>,
+        remote_text: Option<String>,
+    ) -> Result<String, String> {
+        self.0.add_conflict(remote_json, local_text, remote_text)
+    }
+
+    /// Unresolved conflicts as a JSON array of
+    /// `{id, path, local, remote, diff, suggestion, created_at, deferred_until}`
+    pub fn list_conflicts_json(&self, include_deferred: bool) -> String {
+        self.0.list_conflicts_json(include_deferred)
+    }
+
+    /// Number of unresolved conflicts, deferred ones included
+    pub fn get_conflict_count(&self) -> usize {
+        self.0.get_conflict_count()
+    }
+
+    /// Settle a conflict with `{action: keep_local|keep_remote|keep_both|use_merged, content?}`
+    /// Returns `{path, fetch_remote_to, remote_hash, write_content}` describing the disk changes
+    pub fn resolve_conflict(&mut self, id: &str, resolution_json: &str) -> Result<String, String> {
+        self.0.resolve_conflict(id, resolution_json)
+    }
+
+    /// Diff of a queued conflict's two texts with `context` lines around each change,
+    /// as `{unified, hunks: [{old_start, old_lines, new_start, new_lines, lines, words}]}`
+    pub fn get_conflict_diff(
+        &mut self,
+        id: &str,
+        local_text: &str,
+        remote_text: &str,
+        context: usize,
+    ) -> Result<String, String> {
+        self.0.get_conflict_diff(id, local_text, remote_text, context)
+    }
+
+    /// Hide a conflict from the active list until `until_ms`
+    pub fn defer_conflict(&mut self, id: &str, until_ms: u64) -> Result<(), String> {
+        self.0.defer_conflict(id, until_ms)
+    }
+
+    /// Export the conflict queue for persistence
+    pub fn get_conflict_state(&self) -> String {
+        self.0.get_conflict_state()
+    }
+
+    /// Restore a persisted conflict queue
+    pub fn load_conflict_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_conflict_state(json)
+    }
+
+    /// Recorded warnings as a JSON array of `{severity, code, message, context, timestamp}`
+    pub fn get_issues_json(&self) -> String {
+        self.0.get_issues_json()
+    }
+
+    /// Number of recorded warnings
+    pub fn get_issue_count(&self) -> usize {
+        self.0.get_issue_count()
+    }
+
+    /// Forget all recorded warnings
+    pub fn clear_issues(&mut self) {
+        self.0.clear_issues();
+    }
+
+    /// Drop the secrets and trust state the node holds (the active pairing code,
+    /// attempt counters, verified handshake keys and negotiated sessions, peers'
+    /// advertised capabilities, transfer resumption secrets) when the user logs out
+    /// of sync. Keys live in the host's `DeviceIdentity` / `KeyExchange` objects,
+    /// which wipe their memory when freed
+    pub fn wipe_secrets(&mut self) {
+        self.0.wipe_secrets();
+    }
+
+    /// Structured status (peer counts, journal head, last error) for UI binding
+    pub fn get_status(&self) -> NodeStatus {
+        self.0.get_status().into()
     }
 
     /// Node status
     pub fn status(&self) -> String {
-        format!(
-            "P2P Node [{}] on {}: {} peers discovered, discovering={}",
-            self.device_name, self.device_id, self.peers.len(), self.is_discovering
-        )
+        self.0.status()
     }
 
     /// Update file in change journal
+    /// Paths whose policy is `skip` are never recorded
     pub fn update_file(&mut self, path: String, content: &[u8], mtime: u64) -> bool {
-        self.change_journal.update_file(path, content, mtime, self.device_id.clone())
+        self.0.update_file(path, content, mtime)
+    }
+
+    /// Record debounced changes whose quiet interval has elapsed
+    /// Returns the number of journal entries written
+    pub fn flush_debounced(&mut self, current_time: u64) -> usize {
+        self.0.flush_debounced(current_time)
+    }
+
+    /// Record all debounced changes immediately (e.g. before saving state)
+    pub fn flush_all_debounced(&mut self) -> usize {
+        self.0.flush_all_debounced()
+    }
+
+    /// Number of changes waiting on a debounce interval
+    pub fn get_debounced_count(&self) -> usize {
+        self.0.get_debounced_count()
+    }
+
+    /// Replace user debounce rules from a JSON array of `{pattern, interval_ms}`
+    /// (`interval_ms: 0` disables debouncing for matching paths)
+    pub fn set_debounce_rules(&mut self, rules_json: &str) -> Result<(), String> {
+        self.0.set_debounce_rules(rules_json)
+    }
+
+    /// Effective debounce rules in evaluation order as JSON
+    pub fn get_debounce_rules_json(&self) -> String {
+        self.0.get_debounce_rules_json()
     }
 
     /// Mark file as deleted in change journal
     pub fn mark_file_deleted(&mut self, path: String, mtime: u64) -> bool {
-        self.change_journal.mark_deleted(path, mtime, self.device_id.clone())
+        self.0.mark_file_deleted(path, mtime)
+    }
+
+    /// Record a rename of a synced file as one move, so peers move their copy instead of
+    /// downloading it again; a move out of the synced paths is recorded as a delete
+    pub fn rename_file(&mut self, old_path: String, new_path: String, mtime: u64) -> bool {
+        self.0.rename_file(old_path, new_path, mtime)
+    }
+
+    /// Apply a change pulled from a peer (`remote_json` is the peer's file metadata)
+    /// Returns false if the journal already had this version or the path is skipped
+    pub fn apply_remote_change(&mut self, remote_json: &str, from_peer_id: &str) -> Result<bool, String> {
+        self.0.apply_remote_change(remote_json, from_peer_id)
+    }
+
+    /// Audit log entries for `path` (all paths when empty) as a JSON array, oldest first
+    pub fn get_audit_log_json(&self, path: &str) -> String {
+        self.0.get_audit_log_json(path)
+    }
+
+    pub fn get_audit_count(&self) -> usize {
+        self.0.get_audit_count()
+    }
+
+    pub fn clear_audit_log(&mut self) {
+        self.0.clear_audit_log();
+    }
+
+    /// Export the audit log for persistence
+    pub fn get_audit_state(&self) -> String {
+        self.0.get_audit_state()
+    }
+
+    /// Restore a persisted audit log
+    pub fn load_audit_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_audit_state(json)
     }
 
     /// Get all files metadata
     pub fn get_all_files(&self) -> String {
-        self.change_journal.get_all_files()
+        self.0.get_all_files()
     }
 
     /// Export change journal state as JSON
     pub fn get_journal_state(&self) -> String {
-        self.change_journal.to_json()
+        self.0.get_journal_state()
     }
 
     /// Import change journal state from JSON
-    pub fn load_journal_state(&mut self, json: &str) -> Result<(), JsValue> {
-        match ChangeJournal::from_json(json) {
-            Ok(journal) => {
-                self.change_journal = journal;
-                Ok(())
-            }
-            Err(e) => Err(JsValue::from_str(&format!("Failed to load journal: {}", e))),
-        }
+    pub fn load_journal_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_journal_state(json)
+    }
+
+    /// Start loading persisted journal JSON in segments; clears the current journal
+    /// Entries are queryable as soon as their segment arrives; hold back local
+    /// changes until the load completes
+    pub fn begin_journal_load(&mut self) {
+        self.0.begin_journal_load();
+    }
+
+    /// Feed the next segment of the journal blob; returns true once it is fully loaded
+    pub fn push_journal_segment(&mut self, segment: &str) -> Result<bool, String> {
+        self.0.push_journal_segment(segment)
+    }
+
+    /// Progress of the current load as `{bytes_received, files_loaded, complete}`
+    pub fn get_journal_load_progress(&self) -> String {
+        self.0.get_journal_load_progress()
+    }
+
+    /// Start a segmented export of `"journal"` (as `get_journal_state`) or `"files"` (as `get_all_files`)
+    pub fn begin_export(&mut self, kind: &str) -> Result<(), String> {
+        self.0.begin_export(kind)
+    }
+
+    /// Next segment of the current export, or `undefined` once it is complete
+    pub fn next_segment(&mut self) -> Result<Option<String>, String> {
+        self.0.next_segment()
+    }
+
+    /// Hash new content with `"sha256"` or `"blake3"`; recorded hashes stay valid
+    pub fn set_hash_algorithm(&mut self, algorithm: &str) -> Result<(), String> {
+        self.0.set_hash_algorithm(algorithm)
+    }
+
+    pub fn get_hash_algorithm(&self) -> String {
+        self.0.get_hash_algorithm()
+    }
+
+    /// Export change journal state as CBOR
+    pub fn get_journal_state_cbor(&self) -> Vec<u8> {
+        self.0.get_journal_state_cbor()
+    }
+
+    /// Import change journal state from CBOR
+    pub fn load_journal_state_cbor(&mut self, data: &[u8]) -> Result<(), String> {
+        self.0.load_journal_state_cbor(data)
+    }
+
+    /// Apply a JSON array of commands in one call
+    /// Returns a JSON array of `{ok, value?, error?}` results in command order;
+    /// commands skipped after cancellation are marked `cancelled`
+    pub fn execute_commands(&mut self, batch_json: &str) -> Result<String, String> {
+        self.0.execute_commands(batch_json)
+    }
+
+    /// Like `execute_commands`, but all or nothing: if any command fails, every journal
+    /// change the batch made is rolled back. Returns `{committed, results}` as JSON
+    pub fn execute_commands_atomic(&mut self, batch_json: &str) -> Result<String, String> {
+        self.0.execute_commands_atomic(batch_json)
+    }
+
+    /// Group the following journal changes so they can be undone together
+    pub fn begin_transaction(&mut self) -> Result<(), String> {
+        self.0.begin_transaction()
+    }
+
+    /// Keep the changes made since `begin_transaction`
+    pub fn commit_transaction(&mut self) -> Result<(), String> {
+        self.0.commit_transaction()
+    }
+
+    /// Undo every journal change, and its audit entries, since `begin_transaction`
+    pub fn abort_transaction(&mut self) -> Result<(), String> {
+        self.0.abort_transaction()
+    }
+
+    /// Attach a token checked at safe points inside long operations
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.0.set_cancellation_token(token);
+    }
+
+    /// Detach the current cancellation token
+    pub fn clear_cancellation_token(&mut self) {
+        self.0.clear_cancellation_token();
+    }
+
+    /// Index a batch of existing files in one pass
+    /// `entries_json` is an array of `{path, mtime, hash?, size?, content_b64?}`;
+    /// returns cumulative progress JSON across batches since the last reset
+    pub fn bootstrap_index(&mut self, entries_json: &str) -> Result<String, String> {
+        self.0.bootstrap_index(entries_json)
+    }
+
+    /// Plan hashing of scanned files across `workers` Web Workers
+    /// `files_json` is an array of `{path, mtime, size}`; returns one array of
+    /// `{id, path, mtime, offset, len}` jobs per worker, skipping unchanged files
+    pub fn plan_hash_jobs(&mut self, files_json: &str, workers: usize) -> Result<String, String> {
+        self.0.plan_hash_jobs(files_json, workers)
+    }
+
+    /// Record `{id, hash}` results from `compute_hash_job`; returns cumulative progress JSON
+    pub fn submit_hash_results(&mut self, results_json: &str) -> Result<String, String> {
+        self.0.submit_hash_results(results_json)
+    }
+
+    /// Jobs handed out and not yet reported back
+    pub fn get_pending_hash_job_count(&self) -> usize {
+        self.0.get_pending_hash_job_count()
+    }
+
+    /// Cumulative indexing progress as JSON
+    pub fn get_bootstrap_progress(&self) -> String {
+        self.0.get_bootstrap_progress()
+    }
+
+    /// Start a new indexing run
+    pub fn reset_bootstrap_progress(&mut self) {
+        self.0.reset_bootstrap_progress();
+    }
+
+    /// Recorded versions as a commit-log JSON array (oldest first)
+    pub fn export_history_commit_log(&self) -> String {
+        self.0.export_history_commit_log()
+    }
+
+    /// Recorded versions as a `git fast-import` stream on `branch`
+    /// `contents_json` maps content hashes to base64 file bodies; missing
+    /// bodies are exported as pointer files
+    pub fn export_history_fast_import(&mut self, contents_json: &str, branch: &str) -> Result<Vec<u8>, String> {
+        self.0.export_history_fast_import(contents_json, branch)
+    }
+
+    /// Export the journal as obsidian-livesync CouchDB documents (JSON array)
+    /// `contents_json` maps content hashes to base64 bodies; files without a
+    /// body are exported as metadata-only entries
+    pub fn export_livesync_docs(&mut self, contents_json: &str) -> Result<String, String> {
+        self.0.export_livesync_docs(contents_json)
+    }
+
+    /// Seed the journal from obsidian-livesync CouchDB documents
+    /// Returns `{imported, deleted, incomplete, contents}` where `contents` maps
+    /// hashes to base64 bodies the host should write to disk
+    pub fn import_livesync_docs(&mut self, docs_json: &str) -> Result<String, String> {
+        self.0.import_livesync_docs(docs_json)
+    }
+
+    /// Seal journal changes after `since_sequence` into a mailbox bundle for an offline peer
+    /// `contents_json` maps content hashes to base64 bodies; only those the delta needs are included
+    pub fn create_mailbox_bundle(
+        &mut self,
+        identity: &crypto::DeviceIdentity,
+        recipient_public_key: &str,
+        since_sequence: u64,
+        contents_json: &str,
+    ) -> Result<Vec<u8>, String> {
+        self.0.create_mailbox_bundle(identity, recipient_public_key, since_sequence, contents_json)
+    }
+
+    /// Replace the backup retention policy from `{interval_ms, tiers: [{every_ms, keep_for_ms}]}`
+    pub fn set_backup_retention(&mut self, policy_json: &str) -> Result<(), String> {
+        self.0.set_backup_retention(policy_json)
+    }
+
+    /// Record a snapshot taken at `created_at` of the current journal state
+    pub fn record_backup_snapshot(&mut self, id: String, created_at: u64, size_bytes: u64) {
+        self.0.record_backup_snapshot(id, created_at, size_bytes);
+    }
+
+    /// Whether a new snapshot should be taken now
+    pub fn is_backup_due(&self, current_time: u64) -> bool {
+        self.0.is_backup_due(current_time)
+    }
+
+    /// Forget snapshots the retention policy no longer keeps
+    /// Returns the ids (JSON array) the host should delete
+    pub fn prune_backup_snapshots(&mut self, current_time: u64) -> String {
+        self.0.prune_backup_snapshots(current_time)
+    }
+
+    /// Known snapshots as JSON, oldest first
+    pub fn list_backup_snapshots_json(&self) -> String {
+        self.0.list_backup_snapshots_json()
+    }
+
+    /// Estimated memory usage as JSON (journal, history, peer table, conflicts, logs,
+    /// transport buffers, chunk cache, held frames, linear memory)
+    pub fn get_memory_stats(&self) -> String {
+        self.0.get_memory_stats()
+    }
+
+    /// Memory estimates and per-stage timing totals as JSON
+    pub fn get_metrics_json(&self) -> String {
+        self.0.get_metrics_json()
+    }
+
+    /// Release spare capacity held by internal maps and buffers, and empty the chunk
+    /// cache and the frames held for unreachable peers (see `clear_caches`).
+    /// Returns the number of tracked bytes freed
+    pub fn trim_memory(&mut self) -> usize {
+        self.0.trim_memory()
     }
 }
 
@@ -316,45 +765,4 @@ mod tests {
         assert!(result.contains("Test"));
         assert!(result.contains("Rust/WASM"));
     }
-
-    #[test]
-    fn test_p2p_node_creation() {
-        let node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
-        assert!(!node.get_peer_id().is_empty());
-        assert_eq!(node.get_device_name(), "Device A");
-        assert!(!node.get_device_id().is_empty());
-    }
-
-    #[test]
-    fn test_peer_discovery() {
-        let mut node = P2PNode::new("Device A".to_string(), "device-id".to_string(), 8080);
-
-        // Start discovery
-        assert!(node.start_discovery().is_ok());
-        assert_eq!(node.get_peer_count(), 0);
-
-        // Add a peer
-        let peer = DiscoveredPeer::new(
-            "peer-1".to_string(),
-            "Device B".to_string(),
-            "device-b-id".to_string(),
-            1000u64,
-            "192.168.1.2".to_string(),
-            8081,
-        );
-        assert!(node.add_discovered_peer(&peer).is_ok());
-        assert_eq!(node.get_peer_count(), 1);
-
-        // Remove the peer
-        assert!(node.remove_peer("peer-1").is_ok());
-        assert_eq!(node.get_peer_count(), 0);
-    }
-
-    #[test]
-    fn test_node_status() {
-        let node = P2PNode::new("Test Device".to_string());
-        let status = node.status();
-        assert!(status.contains("Test Device"));
-        assert!(status.contains("0 peers"));
-    }
 }
@@ -0,0 +1,14 @@
+//! JS bindings for link rewriting for renames received from peers (see `p2p_sync_core::links`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::links as core_links;
+
+pub use p2p_sync_core::links::*;
+
+/// Rewrite links to a renamed file across a set of notes
+/// Takes a JSON array of `{path, content}` and returns only the notes that changed
+#[wasm_bindgen]
+pub fn rewrite_links_for_rename(old_path: &str, new_path: &str, notes_json: &str) -> Result<String, String> {
+    core_links::rewrite_links_for_rename(old_path, new_path, notes_json)
+}
@@ -0,0 +1,27 @@
+//! JS bindings for relay mailbox bundles (see `p2p_sync_core::mailbox`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::mailbox as core_mailbox;
+
+use crate::crypto::KeyExchange;
+
+pub use p2p_sync_core::mailbox::*;
+
+/// Recipient fingerprint of a bundle, readable without decrypting (for routing)
+#[wasm_bindgen]
+pub fn mailbox_bundle_recipient(data: &[u8]) -> Option<String> {
+    core_mailbox::mailbox_bundle_recipient(data)
+}
+
+/// Fingerprint bundles for this mailbox key are addressed to
+#[wasm_bindgen]
+pub fn mailbox_key_fingerprint(key: &KeyExchange) -> String {
+    core_mailbox::mailbox_key_fingerprint(key)
+}
+
+/// Decrypt and verify a bundle; returns `{sender_device_id, sender_public_key, created_at, since_sequence, entries, contents}`
+#[wasm_bindgen]
+pub fn open_mailbox_bundle(recipient: &KeyExchange, data: &[u8]) -> Result<String, String> {
+    core_mailbox::open_mailbox_bundle(recipient, data)
+}
@@ -0,0 +1,69 @@
+//! JS bindings for custom merge drivers (see `p2p_sync_core::mergedrivers`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::mergedrivers::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    /// `{merge(path: string, base: string | undefined, local: string, remote: string)}`
+    pub type JsMergeDriver;
+
+    #[wasm_bindgen(method, catch)]
+    fn merge(
+        this: &JsMergeDriver,
+        path: &str,
+        base: Option<String>,
+        local: &str,
+        remote: &str,
+    ) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch, js_namespace = JSON, js_name = stringify)]
+    fn json_stringify(value: &JsValue) -> Result<JsValue, JsValue>;
+}
+
+#[cfg(target_arch = "wasm32")]
+impl MergeDriver for JsMergeDriver {
+    fn merge(&self, path: &str, base: Option<&str>, local: &str, remote: &str) -> Result<DriverOutcome, String> {
+        let describe = |e: JsValue| e.as_string().unwrap_or_else(|| format!("{:?}", e));
+        let reply = JsMergeDriver::merge(self, path, base.map(str::to_string), local, remote).map_err(describe)?;
+        let json = json_stringify(&reply).map_err(describe)?;
+        parse_reply(json.as_string().as_deref())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl P2PNode {
+    /// Merge files matching any of `patterns_json` (a JSON array of globs) with `driver`;
+    /// replaces a driver registered under the same name
+    pub fn register_merge_driver(
+        &mut self,
+        name: &str,
+        patterns_json: &str,
+        driver: JsMergeDriver,
+    ) -> Result<(), String> {
+        self.0.register_merge_driver(name, patterns_json, Box::new(driver))
+    }
+}
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Remove a merge driver; false if none had this name
+    pub fn unregister_merge_driver(&mut self, name: &str) -> bool {
+        self.0.unregister_merge_driver(name)
+    }
+
+    /// Registered drivers as `[{name, patterns}]`, in the order they are tried
+    pub fn get_merge_drivers_json(&self) -> String {
+        self.0.get_merge_drivers_json()
+    }
+
+    /// Name of the driver `merge_file` would ask for `path`
+    pub fn get_merge_driver_for(&self, path: &str) -> Option<String> {
+        self.0.get_merge_driver_for(path)
+    }
+}
@@ -0,0 +1,23 @@
+//! JS bindings for mnemonic backup of the device identity (see `p2p_sync_core::mnemonic`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::{DeviceIdentity, KeyExchange};
+
+#[wasm_bindgen]
+impl DeviceIdentity {
+    /// The identity seed as a 24-word BIP39 phrase
+    pub fn to_mnemonic(&self) -> String {
+        p2p_sync_core::crypto::DeviceIdentity::to_mnemonic(self)
+    }
+
+    /// Restore an identity from its phrase (case and extra whitespace are ignored)
+    pub fn from_mnemonic(device_id: String, phrase: &str) -> Result<DeviceIdentity, String> {
+        p2p_sync_core::crypto::DeviceIdentity::from_mnemonic(device_id, phrase).map(Into::into)
+    }
+
+    /// Long-lived X25519 key derived from the identity seed, recoverable with it
+    pub fn derive_exchange_key(&self) -> KeyExchange {
+        p2p_sync_core::crypto::DeviceIdentity::derive_exchange_key(self).into()
+    }
+}
@@ -0,0 +1,30 @@
+//! JS bindings for session negotiation (see `p2p_sync_core::negotiation`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::KeyExchange;
+use crate::P2PNode;
+
+pub use p2p_sync_core::negotiation::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Derive the session from the handshake frame we sent and the peer's; returns
+    /// `{session_key, confirmation, expected_confirmation}` (base64) as JSON.
+    /// Send `confirmation`, and check the peer's with `check_session_confirmation`
+    pub fn derive_session_keys(
+        &mut self,
+        session: &KeyExchange,
+        own_frame: &[u8],
+        peer_frame: &[u8],
+    ) -> Result<String, String> {
+        self.0.derive_session_keys(session, own_frame, peer_frame)
+    }
+
+    /// Compare the peer's confirmation with the expected one; a mismatch means the
+    /// handshake was tampered with and is recorded in the security log. Requests from
+    /// the peer are only served under its handshake's identity key once this succeeds
+    pub fn check_session_confirmation(&mut self, peer_device_id: &str, expected_b64: &str, received_b64: &str) -> bool {
+        self.0.check_session_confirmation(peer_device_id, expected_b64, received_b64)
+    }
+}
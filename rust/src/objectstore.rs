@@ -0,0 +1,69 @@
+//! JS bindings for encrypted object-store snapshots (see `p2p_sync_core::objectstore`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::objectstore as core_objectstore;
+
+pub use p2p_sync_core::objectstore::*;
+
+#[wasm_bindgen]
+pub struct ObjectStoreSnapshot(core_objectstore::ObjectStoreSnapshot);
+
+core_wrapper!(ObjectStoreSnapshot, core_objectstore::ObjectStoreSnapshot);
+
+#[wasm_bindgen]
+impl ObjectStoreSnapshot {
+    /// `master_key_b64` is a 32-byte secret; names and contents are keyed from it
+    #[wasm_bindgen(constructor)]
+    pub fn new(master_key_b64: &str) -> Result<ObjectStoreSnapshot, String> {
+        core_objectstore::ObjectStoreSnapshot::new(master_key_b64).map(Into::into)
+    }
+
+    /// Restore planner state saved with `get_state()`
+    pub fn load_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_state(json)
+    }
+
+    pub fn get_state(&self) -> String {
+        self.0.get_state()
+    }
+
+    /// Whether the store already holds this content (so `add_known_file` suffices)
+    pub fn has_content(&self, hash: &str) -> bool {
+        self.0.has_content(hash)
+    }
+
+    /// Add a file whose content the store already holds; false if unknown
+    pub fn add_known_file(&mut self, path: String, hash: String, size: u64, mtime: u64) -> bool {
+        self.0.add_known_file(path, hash, size, mtime)
+    }
+
+    /// Add a file to the snapshot; returns `[{key, data_b64}]` for chunks not yet on the store
+    pub fn add_file(&mut self, path: String, content: &[u8], mtime: u64) -> Result<String, String> {
+        self.0.add_file(path, content, mtime)
+    }
+
+    /// Seal the snapshot; returns the manifest object `{key, data_b64}` to upload
+    pub fn finish_snapshot(&mut self) -> Result<String, String> {
+        self.0.finish_snapshot()
+    }
+
+    /// Keep the newest `keep` snapshots; returns `{manifests, chunks}` to delete
+    pub fn plan_gc(&mut self, keep: usize) -> String {
+        self.0.plan_gc(keep)
+    }
+
+    /// Decrypt a manifest object for restore
+    pub fn open_manifest(&self, data: &[u8]) -> Result<String, String> {
+        self.0.open_manifest(data)
+    }
+
+    /// Decrypt a chunk object for restore
+    pub fn open_chunk(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.0.open_chunk(data)
+    }
+
+    pub fn get_snapshot_count(&self) -> usize {
+        self.0.get_snapshot_count()
+    }
+}
@@ -0,0 +1,21 @@
+//! JS bindings for orphaned journal state (see `p2p_sync_core::orphans`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::orphans::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Compare the journal with a full vault scan (a JSON array of `{path, ...}`);
+    /// returns the `{orphans: [{path, kind}]}` report as JSON and remembers it for `cleanup_orphans`
+    pub fn detect_orphans(&mut self, scan_json: &str) -> Result<String, String> {
+        self.0.detect_orphans(scan_json)
+    }
+
+    /// Fix what the last `detect_orphans` found; with `dry_run` only report it
+    pub fn cleanup_orphans(&mut self, dry_run: bool) -> Result<String, String> {
+        self.0.cleanup_orphans(dry_run)
+    }
+}
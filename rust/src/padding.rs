@@ -0,0 +1,24 @@
+//! JS bindings for traffic padding (see `p2p_sync_core::padding`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::padding::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Configure padding from JSON (`{enabled, cover_interval_ms}`)
+    pub fn set_traffic_padding(&mut self, config_json: &str) -> Result<(), String> {
+        self.0.set_traffic_padding(config_json)
+    }
+
+    pub fn get_traffic_padding_json(&self) -> String {
+        self.0.get_traffic_padding_json()
+    }
+
+    /// A protobuf `Cover` frame to send if one is due at `now`; call from the host's timer
+    pub fn take_cover_frame(&mut self, now: u64) -> Option<Vec<u8>> {
+        self.0.take_cover_frame(now)
+    }
+}
@@ -0,0 +1,26 @@
+//! JS bindings for pairing attempt limiting (see `p2p_sync_core::pairing`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::pairing::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Start accepting pairing requests with a fresh single-use code, valid for
+    /// `ttl_ms` (0 selects the default); returns the code to show the user
+    pub fn start_pairing(&mut self, ttl_ms: u64) -> String {
+        self.0.start_pairing(ttl_ms)
+    }
+
+    pub fn cancel_pairing(&mut self) {
+        self.0.cancel_pairing();
+    }
+
+    /// Check the code in a pairing request from `source` (e.g. its IP); returns
+    /// `{result: accepted | rejected | locked_out | no_code, ...}` as JSON
+    pub fn check_pairing_attempt(&mut self, source: &str, code: &str) -> String {
+        self.0.check_pairing_attempt(source, code)
+    }
+}
@@ -0,0 +1,42 @@
+//! JS bindings for per-peer sync history and reliability (see `p2p_sync_core::peerstats`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::peerstats::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Record a finished round with a peer; also counts towards the current round report
+    pub fn record_sync_round(
+        &mut self,
+        device_id: &str,
+        started_at: u64,
+        finished_at: u64,
+        succeeded: bool,
+        bytes_sent: u64,
+        bytes_received: u64,
+    ) {
+        self.0.record_sync_round(device_id, started_at, finished_at, succeeded, bytes_sent, bytes_received);
+    }
+
+    /// Sync history of every peer with at least one recorded round, as a JSON array
+    pub fn get_peer_stats_json(&self, now: u64) -> String {
+        self.0.get_peer_stats_json(now)
+    }
+
+    /// Forget a peer's history, e.g. after it was fixed or unpaired
+    pub fn reset_peer_stats(&mut self, device_id: &str) {
+        self.0.reset_peer_stats(device_id);
+    }
+
+    /// Peer history as JSON, for persisting with `load_peer_history_state`
+    pub fn get_peer_history_state(&self) -> String {
+        self.0.get_peer_history_state()
+    }
+
+    pub fn load_peer_history_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_peer_history_state(json)
+    }
+}
@@ -0,0 +1,32 @@
+//! JS bindings for pinned files and folders (see `p2p_sync_core::pins`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::pins::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Pin a file or folder under the active profile so its content is always held here.
+    /// Returns how many placeholders under it were queued for download
+    pub fn pin(&mut self, path_or_folder: &str) -> Result<usize, String> {
+        self.0.pin(path_or_folder)
+    }
+
+    /// Remove a pin of the active profile; false if there was none. Content already
+    /// downloaded stays
+    pub fn unpin(&mut self, path_or_folder: &str) -> bool {
+        self.0.unpin(path_or_folder)
+    }
+
+    /// Whether the active profile pins `path` or one of its folders
+    pub fn is_pinned(&self, path: &str) -> bool {
+        self.0.is_pinned(path)
+    }
+
+    /// Pins of the active profile as a JSON array
+    pub fn get_pins_json(&self) -> String {
+        self.0.get_pins_json()
+    }
+}
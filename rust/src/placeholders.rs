@@ -0,0 +1,62 @@
+//! JS bindings for placeholders and on-demand fetch (see `p2p_sync_core::placeholders`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::ingest::FileIngest;
+use crate::P2PNode;
+
+pub use p2p_sync_core::placeholders::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Whether `path` is shown without its content
+    pub fn is_placeholder(&self, path: &str) -> bool {
+        self.0.is_placeholder(path)
+    }
+
+    /// Paths of every current placeholder as a JSON array
+    pub fn get_placeholders_json(&mut self) -> String {
+        self.0.get_placeholders_json()
+    }
+
+    /// Queue an on-demand pull of a placeholder's content; false if already queued
+    pub fn request_file(&mut self, path: &str) -> Result<bool, String> {
+        self.0.request_file(path)
+    }
+
+    /// Queued pulls as `[{path, hash, size, requested_at}]`, oldest first, to fetch
+    /// before any other transfer
+    pub fn get_file_requests_json(&mut self) -> String {
+        self.0.get_file_requests_json()
+    }
+
+    /// Hand over the fetched content of a placeholder; the host writes it into
+    /// the vault once this succeeds
+    pub fn complete_file_request(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        self.0.complete_file_request(path, content)
+    }
+
+    /// `complete_file_request` for content streamed through a `FileIngest`
+    pub fn complete_file_request_ingest(&mut self, ingest: &FileIngest) -> Result<(), String> {
+        self.0.complete_file_request_ingest(ingest)
+    }
+
+    /// Give up on a queued pull (e.g. no peer has the content); false if it wasn't queued
+    pub fn fail_file_request(&mut self, path: &str, reason: &str) -> bool {
+        self.0.fail_file_request(path, reason)
+    }
+
+    /// Events since the last call as `[{type: "arrived", path, hash} | {type: "failed", path, reason}]`
+    pub fn take_placeholder_events(&mut self) -> String {
+        self.0.take_placeholder_events()
+    }
+
+    /// Placeholders and queued pulls, to persist next to the journal
+    pub fn export_placeholder_state(&mut self) -> String {
+        self.0.export_placeholder_state()
+    }
+
+    pub fn load_placeholder_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_placeholder_state(json)
+    }
+}
@@ -0,0 +1,31 @@
+//! JS bindings for predictive prefetching (see `p2p_sync_core::prefetch`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::prefetch::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Note that the user opened `path`
+    pub fn record_file_opened(&mut self, path: &str) {
+        self.0.record_file_opened(path);
+    }
+
+    /// The note in focus (`undefined` when none); it is also recorded as opened
+    pub fn set_active_note(&mut self, path: Option<String>) {
+        self.0.set_active_note(path);
+    }
+
+    /// Replace the links/embeds graph, a JSON object mapping each note to the paths it references
+    pub fn set_link_graph(&mut self, graph_json: &str) -> Result<(), String> {
+        self.0.set_link_graph(graph_json)
+    }
+
+    /// Files to pull first from a newly connected peer, given its manifest (a JSON
+    /// array of file metadata); returns a JSON array of paths, most needed first
+    pub fn plan_prefetch(&mut self, remote_json: &str) -> Result<String, String> {
+        self.0.plan_prefetch(remote_json)
+    }
+}
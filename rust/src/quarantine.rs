@@ -0,0 +1,40 @@
+//! JS bindings for key-change quarantine (see `p2p_sync_core::quarantine`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::quarantine::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Devices awaiting re-verification as `[{device_id, pinned_key, presented_key,
+    /// pinned_fingerprint, presented_fingerprint, detected_at, source}]`
+    pub fn get_quarantined_peers_json(&self) -> String {
+        self.0.get_quarantined_peers_json()
+    }
+
+    pub fn is_peer_quarantined(&self, device_id: &str) -> bool {
+        self.0.is_peer_quarantined(device_id)
+    }
+
+    /// The user confirmed `fingerprint` on the device itself: pin its new key
+    /// and lift the quarantine
+    pub fn accept_key_change(&mut self, device_id: &str, fingerprint: &str) -> Result<(), String> {
+        self.0.accept_key_change(device_id, fingerprint)
+    }
+
+    /// Keep the old key: lift the quarantine and refuse the presented key from now on
+    pub fn reject_key_change(&mut self, device_id: &str) -> Result<(), String> {
+        self.0.reject_key_change(device_id)
+    }
+
+    /// Quarantined devices and rejected keys as JSON, for persisting with `load_quarantine_state`
+    pub fn get_quarantine_state(&self) -> String {
+        self.0.get_quarantine_state()
+    }
+
+    pub fn load_quarantine_state(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_quarantine_state(json)
+    }
+}
@@ -0,0 +1,17 @@
+//! JS bindings for journal queries (see `p2p_sync_core::query`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::query::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// One page of the journal entries matching `{modified_by?, modified_after?,
+    /// modified_before?, deleted?, folder?, min_size?, max_size?, hash?,
+    /// order?: "path" | "newest", limit?, cursor?}`, as `{files, next_cursor?}`
+    pub fn query_files(&mut self, filter_json: &str) -> Result<String, String> {
+        self.0.query_files(filter_json)
+    }
+}
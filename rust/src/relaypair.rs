@@ -0,0 +1,61 @@
+//! JS bindings for pairing through an untrusted relay (see `p2p_sync_core::relaypair`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::relaypair as core_relaypair;
+
+use crate::crypto::DeviceIdentity;
+
+pub use p2p_sync_core::relaypair::*;
+
+#[wasm_bindgen]
+pub struct RelayPairing(core_relaypair::RelayPairing);
+
+core_wrapper!(RelayPairing, core_relaypair::RelayPairing);
+
+#[wasm_bindgen]
+impl RelayPairing {
+    /// Host a pairing session under a fresh channel ID and code, both shown to the user
+    pub fn host(identity: &DeviceIdentity) -> RelayPairing {
+        core_relaypair::RelayPairing::host(identity).into()
+    }
+
+    /// Join the session with the channel ID and code the host shows
+    pub fn join(identity: &DeviceIdentity, channel_id: &str, code: &str) -> RelayPairing {
+        core_relaypair::RelayPairing::join(identity, channel_id, code).into()
+    }
+
+    /// Relay channel to subscribe to and address envelopes to
+    pub fn get_channel_id(&self) -> String {
+        self.0.get_channel_id()
+    }
+
+    /// The code to show, on the host
+    pub fn get_code(&self) -> Option<String> {
+        self.0.get_code()
+    }
+
+    /// The joiner's first envelope to send through the relay
+    pub fn start(&self) -> Result<Vec<u8>, String> {
+        self.0.start()
+    }
+
+    /// Handle an envelope from the relay; returns the envelope to send back, if any.
+    /// Any failure ends the session for good
+    pub fn receive(&mut self, envelope: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.0.receive(envelope)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.0.is_complete()
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.0.is_failed()
+    }
+
+    /// `{device_id, public_key, session_key}` of the paired device as JSON, once complete
+    pub fn get_result_json(&self) -> Option<String> {
+        self.0.get_result_json()
+    }
+}
@@ -0,0 +1,38 @@
+//! JS bindings for repairing corrupted local files (see `p2p_sync_core::repair`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::repair::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Check a local file's content against its journal entry; a mismatch flags it and
+    /// schedules a repair. Returns whether the content matched
+    pub fn verify_local_file(&mut self, path: &str, content: &[u8]) -> Result<bool, String> {
+        self.0.verify_local_file(path, content)
+    }
+
+    /// `verify_local_file` for a hash the host already computed, e.g. on a read
+    pub fn verify_local_hash(&mut self, path: &str, hash: &str) -> Result<bool, String> {
+        self.0.verify_local_hash(path, hash)
+    }
+
+    /// Scheduled repairs as `[{path, expected_hash, found_hash, detected_at, peer, tried}]`
+    pub fn get_repairs_json(&mut self) -> String {
+        self.0.get_repairs_json()
+    }
+
+    /// Hand over the content pulled for a repair; the host writes it over the damaged
+    /// file once this succeeds
+    pub fn complete_repair(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        self.0.complete_repair(path, content)
+    }
+
+    /// The scheduled peer couldn't supply the version; move on to the next candidate.
+    /// Returns the new peer, or `undefined` when none is left
+    pub fn fail_repair(&mut self, path: &str, reason: &str) -> Result<Option<String>, String> {
+        self.0.fail_repair(path, reason)
+    }
+}
@@ -0,0 +1,26 @@
+//! JS bindings for sync round reports (see `p2p_sync_core::reports`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::reports::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Tell the report how many files the host pushed to a peer this round
+    pub fn note_files_pushed(&mut self, device_id: &str, count: usize) {
+        self.0.note_files_pushed(device_id, count);
+    }
+
+    /// Close the current round's tally; returns the report as JSON, or `null`
+    /// when nothing happened since the last one
+    pub fn finish_round_report(&mut self, now: u64) -> String {
+        self.0.finish_round_report(now)
+    }
+
+    /// Recent round reports as a JSON array, oldest first
+    pub fn get_round_reports_json(&self) -> String {
+        self.0.get_round_reports_json()
+    }
+}
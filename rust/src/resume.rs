@@ -0,0 +1,57 @@
+//! JS bindings for transfer resumption across reconnects (see `p2p_sync_core::resume`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::resume::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Track a transfer so it can be resumed after a reconnect. The sender passes
+    /// no `transfer_id` and gets a new one to announce; the receiver passes that ID.
+    /// Returns the transfer ID
+    pub fn track_transfer(
+        &mut self,
+        peer_id: &str,
+        transfer_id: Option<String>,
+        file_path: String,
+        hash: String,
+        total_chunks: u32,
+        session_key_b64: &str,
+    ) -> Result<String, String> {
+        self.0.track_transfer(peer_id, transfer_id, file_path, hash, total_chunks, session_key_b64)
+    }
+
+    /// Receiver: record a chunk that decrypted and verified
+    pub fn record_verified_chunk(&mut self, transfer_id: &str, chunk_index: u32) -> Result<(), String> {
+        self.0.record_verified_chunk(transfer_id, chunk_index)
+    }
+
+    /// Stop tracking a transfer that completed or was abandoned
+    pub fn finish_transfer(&mut self, transfer_id: &str) {
+        self.0.finish_transfer(transfer_id);
+    }
+
+    /// Receiver, after reconnecting: a JSON array of tokens (one per transfer from
+    /// `peer_id`) to send to the peer; rebinds the transfers to `new_session_key_b64`
+    pub fn create_resumption_tokens(&mut self, peer_id: &str, new_session_key_b64: &str) -> Result<String, String> {
+        self.0.create_resumption_tokens(peer_id, new_session_key_b64)
+    }
+
+    /// Sender: check a peer's token; returns `{transfer_id, file_path, next_chunk,
+    /// total_chunks}` as JSON and rebinds the transfer to `new_session_key_b64`
+    pub fn accept_resumption_token(
+        &mut self,
+        peer_id: &str,
+        token: &str,
+        new_session_key_b64: &str,
+    ) -> Result<String, String> {
+        self.0.accept_resumption_token(peer_id, token, new_session_key_b64)
+    }
+
+    /// Tracked transfers as `[{id, peer_id, file_path, total_chunks, verified_chunks}]` JSON
+    pub fn get_resumable_transfers_json(&self) -> String {
+        self.0.get_resumable_transfers_json()
+    }
+}
@@ -0,0 +1,42 @@
+//! JS bindings for two-phase sync rounds (see `p2p_sync_core::round`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::ingest::FileIngest;
+use crate::P2PNode;
+
+pub use p2p_sync_core::round::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Stage a peer's changes (a JSON array of file metadata) as one round; returns its status
+    pub fn begin_sync_round(&mut self, changes_json: &str, from_peer_id: &str) -> Result<String, String> {
+        self.0.begin_sync_round(changes_json, from_peer_id)
+    }
+
+    /// Verify a staged body the host has downloaded
+    pub fn verify_round_content(&mut self, path: &str, content: &[u8]) -> Result<(), String> {
+        self.0.verify_round_content(path, content)
+    }
+
+    /// Verify a staged body the host has streamed through a `FileIngest`
+    pub fn verify_round_ingest(&mut self, ingest: &FileIngest) -> Result<(), String> {
+        self.0.verify_round_ingest(ingest)
+    }
+
+    /// Status of the current round as JSON, or `null`
+    pub fn get_sync_round_json(&self) -> String {
+        self.0.get_sync_round_json()
+    }
+
+    /// Record the round in the journal once every body has verified; returns the
+    /// `{path, action, hash}` operations for the host to apply as JSON
+    pub fn commit_sync_round(&mut self) -> Result<String, String> {
+        self.0.commit_sync_round()
+    }
+
+    /// Drop the current round; returns the staged write paths to discard as JSON
+    pub fn abort_sync_round(&mut self) -> String {
+        self.0.abort_sync_round()
+    }
+}
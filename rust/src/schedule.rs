@@ -0,0 +1,31 @@
+//! JS bindings for per-peer sync scheduling (see `p2p_sync_core::schedule`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::schedule::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Set per-peer intervals, quiet hours and the idle window from JSON
+    /// (`{peers: {device_id: {interval_ms, only_when_idle}}, quiet_hours: [{start_minute, end_minute}], utc_offset_minutes, idle_after_ms}`)
+    pub fn set_sync_schedule(&mut self, rules_json: &str) -> Result<(), String> {
+        self.0.set_sync_schedule(rules_json)
+    }
+
+    pub fn get_sync_schedule_json(&self) -> String {
+        self.0.get_sync_schedule_json()
+    }
+
+    /// Tell the scheduler the user is editing, for "only when idle" peers
+    pub fn note_user_activity(&mut self, now: u64) {
+        self.0.note_user_activity(now);
+    }
+
+    /// Advance the schedule and drop expired guest sessions; returns the device IDs
+    /// of peers due for a sync round as JSON
+    pub fn tick(&mut self, now: u64) -> String {
+        self.0.tick(now)
+    }
+}
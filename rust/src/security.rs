@@ -0,0 +1,30 @@
+//! JS bindings for security event log (see `p2p_sync_core::security`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::security::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Security events as `{events: [{kind, source, message, context, timestamp}], dropped}` JSON
+    pub fn get_security_log_json(&self) -> String {
+        self.0.get_security_log_json()
+    }
+
+    pub fn get_security_event_count(&self) -> usize {
+        self.0.get_security_event_count()
+    }
+
+    /// Record an event the host detected itself (e.g. a replayed transport
+    /// message); `kind` is one of the snake_case event kinds
+    pub fn record_security_event(&mut self, kind: &str, source: &str, message: String) -> Result<(), String> {
+        self.0.record_security_event(kind, source, message)
+    }
+
+    /// Forget all security events; call after the user has exported them
+    pub fn clear_security_log(&mut self) {
+        self.0.clear_security_log();
+    }
+}
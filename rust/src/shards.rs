@@ -0,0 +1,45 @@
+//! JS bindings for journal shards (see `p2p_sync_core::shards`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::shards::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// `[{shard, sequence, file_count, deleted_count, digest}]` for every non-empty shard
+    pub fn get_journal_shards_json(&self) -> String {
+        self.0.get_journal_shards_json()
+    }
+
+    /// Names of the shards whose digests differ from a peer's `get_journal_shards_json`
+    pub fn diff_journal_shards(&mut self, remote_json: &str) -> Result<String, String> {
+        self.0.diff_journal_shards(remote_json)
+    }
+
+    /// Entries of `shard` changed after `since_sequence`, as a JSON array of file metadata
+    pub fn get_shard_changes_since(&self, shard: &str, since_sequence: u64) -> String {
+        self.0.get_shard_changes_since(shard, since_sequence)
+    }
+
+    /// One shard's entries as JSON, for saving it without the rest of the journal
+    pub fn export_journal_shard(&self, shard: &str) -> String {
+        self.0.export_journal_shard(shard)
+    }
+
+    /// Replace one shard's entries with a saved shard; returns the number of entries loaded
+    pub fn load_journal_shard(&mut self, json: &str) -> Result<usize, String> {
+        self.0.load_journal_shard(json)
+    }
+
+    /// The journal's sequence counter and backups, without entries
+    pub fn export_journal_tail(&self) -> String {
+        self.0.export_journal_tail()
+    }
+
+    /// Start loading a sharded journal: replaces the journal with the saved tail and no entries
+    pub fn load_journal_tail(&mut self, json: &str) -> Result<(), String> {
+        self.0.load_journal_tail(json)
+    }
+}
@@ -0,0 +1,25 @@
+//! JS bindings for graceful shutdown (see `p2p_sync_core::shutdown`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::shutdown::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Finish pending journal work and return the state to persist before unloading,
+    /// as a base64 blob for `resume_after_shutdown`. Tracked transfers are kept only
+    /// with a 32-byte `wrapping_key_b64`, which their secrets are wrapped under
+    pub fn shutdown(&mut self, wrapping_key_b64: Option<String>) -> Result<String, String> {
+        self.0.shutdown(wrapping_key_b64)
+    }
+
+    /// Restore a blob from `shutdown` over the loaded journal checkpoint; an empty blob
+    /// (nothing was saved, e.g. after a crash) restores nothing. Pass the key given to
+    /// `shutdown` to restore tracked transfers; without it they are skipped.
+    /// Returns `{clean_shutdown, closed_at, wal_records_applied, transfers_restored}` as JSON
+    pub fn resume_after_shutdown(&mut self, blob: &str, wrapping_key_b64: Option<String>) -> Result<String, String> {
+        self.0.resume_after_shutdown(blob, wrapping_key_b64)
+    }
+}
@@ -0,0 +1,52 @@
+//! JS bindings for structured node status for direct UI binding (see `p2p_sync_core::status`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::status as core_status;
+
+pub use p2p_sync_core::status::*;
+
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct NodeStatus(core_status::NodeStatus);
+
+core_wrapper!(NodeStatus, core_status::NodeStatus);
+
+#[wasm_bindgen]
+impl NodeStatus {
+    pub fn get_is_discovering(&self) -> bool {
+        self.0.get_is_discovering()
+    }
+
+    pub fn get_peer_count(&self) -> usize {
+        self.0.get_peer_count()
+    }
+
+    pub fn get_active_peer_count(&self) -> usize {
+        self.0.get_active_peer_count()
+    }
+
+    pub fn get_stale_peer_count(&self) -> usize {
+        self.0.get_stale_peer_count()
+    }
+
+    pub fn get_journal_sequence(&self) -> u64 {
+        self.0.get_journal_sequence()
+    }
+
+    pub fn get_file_count(&self) -> usize {
+        self.0.get_file_count()
+    }
+
+    pub fn get_deleted_count(&self) -> usize {
+        self.0.get_deleted_count()
+    }
+
+    pub fn get_last_error(&self) -> Option<String> {
+        self.0.get_last_error()
+    }
+
+    pub fn to_json(&self) -> String {
+        self.0.to_json()
+    }
+}
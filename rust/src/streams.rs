@@ -0,0 +1,58 @@
+//! JS bindings for multi-stream transfers (see `p2p_sync_core::streams`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::streams as core_streams;
+
+use crate::transfer::IncomingTransfer;
+
+pub use p2p_sync_core::streams::*;
+
+#[wasm_bindgen]
+pub struct MultiStreamPlan(core_streams::MultiStreamPlan);
+
+core_wrapper!(MultiStreamPlan, core_streams::MultiStreamPlan);
+
+#[wasm_bindgen]
+impl MultiStreamPlan {
+    /// Plan `total_chunks` over `streams` streams, `run_chunks` at a time (0 for the default)
+    #[wasm_bindgen(constructor)]
+    pub fn new(total_chunks: u32, streams: u32, run_chunks: u32) -> Result<MultiStreamPlan, String> {
+        core_streams::MultiStreamPlan::new(total_chunks, streams, run_chunks).map(Into::into)
+    }
+
+    /// The stream finished its range (or just opened): its next `{stream_id, start, end}`
+    /// as JSON, or `undefined` when there is nothing it may send yet
+    pub fn next_range(&mut self, stream_id: u32) -> Result<Option<String>, String> {
+        self.0.next_range(stream_id)
+    }
+
+    /// The stream closed after sending its range up to `sent_up_to`; the rest is requeued
+    pub fn close_stream(&mut self, stream_id: u32, sent_up_to: u32) {
+        self.0.close_stream(stream_id, sent_up_to);
+    }
+
+    /// Reordering buffer, in chunks, the receiver needs for this plan
+    pub fn reorder_window(&self) -> usize {
+        self.0.reorder_window()
+    }
+
+    /// Every chunk has been handed to a stream that is still open or finished
+    pub fn is_fully_assigned(&self) -> bool {
+        self.0.is_fully_assigned()
+    }
+}
+
+/// Wrap a chunk (`FileChunk` JSON) in a protobuf frame tagged with `stream_id`
+#[wasm_bindgen]
+pub fn encode_chunk_frame(chunk_json: &str, stream_id: u32) -> Result<Vec<u8>, String> {
+    core_streams::encode_chunk_frame(chunk_json, stream_id)
+}
+
+#[wasm_bindgen]
+impl IncomingTransfer {
+    /// Accept a chunk frame from any of the transfer's streams; returns false for a duplicate
+    pub fn accept_chunk_frame(&mut self, frame: &[u8]) -> Result<bool, String> {
+        p2p_sync_core::transfer::IncomingTransfer::accept_chunk_frame(self, frame)
+    }
+}
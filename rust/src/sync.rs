@@ -1,100 +1,94 @@
+//! JS bindings for the change journal (see `p2p_sync_core::sync`)
+
 use wasm_bindgen::prelude::*;
-use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use sha2::{Sha256, Digest};
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct FileMetadata {
-    pub path: String,
-    pub hash: String, // Hex encoded SHA256
-    pub mtime: u64,
-    pub size: u64,
-    pub version: u64, // Sequence number
-    pub is_deleted: bool,
-    pub last_modified_by: String,
-}
 
-#[derive(Serialize, Deserialize)]
+use p2p_sync_core::sync as core_sync;
+pub use p2p_sync_core::sync::*;
+
 #[wasm_bindgen]
-pub struct ChangeJournal {
-    files: HashMap<String, FileMetadata>,
-    global_sequence: u64,
-}
+#[derive(Default)]
+pub struct ChangeJournal(core_sync::ChangeJournal);
+
+core_wrapper!(ChangeJournal, core_sync::ChangeJournal);
 
 #[wasm_bindgen]
 impl ChangeJournal {
     #[wasm_bindgen(constructor)]
     pub fn new() -> ChangeJournal {
-        ChangeJournal {
-            files: HashMap::new(),
-            global_sequence: 0,
-        }
+        ChangeJournal::default()
     }
 
     pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap_or_default()
+        self.0.to_json()
     }
 
+    /// Read journal JSON written by this or an earlier supported release
     pub fn from_json(json: &str) -> Result<ChangeJournal, String> {
-        serde_json::from_str(json).map_err(|e| e.to_string())
+        Ok(core_sync::ChangeJournal::from_json(json)?.into())
+    }
+
+    /// Compact binary form of `to_json`
+    pub fn to_cbor(&self) -> Vec<u8> {
+        self.0.to_cbor()
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<ChangeJournal, String> {
+        Ok(core_sync::ChangeJournal::from_cbor(data)?.into())
     }
 
     pub fn update_file(&mut self, path: String, content: &[u8], mtime: u64, device_id: String) -> bool {
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        let result = hasher.finalize();
-        let hash = hex::encode(result);
-        let size = content.len() as u64;
-
-        if let Some(existing) = self.files.get(&path) {
-            if existing.hash == hash && !existing.is_deleted {
-                return false; // No change
-            }
-        }
-
-        self.global_sequence += 1;
-        let metadata = FileMetadata {
-            path: path.clone(),
-            hash,
-            mtime,
-            size,
-            version: self.global_sequence,
-            is_deleted: false,
-            last_modified_by: device_id,
-        };
-
-        self.files.insert(path, metadata);
-        true
+        self.0.update_file(path, content, mtime, device_id)
+    }
+
+    /// `update_file` for content hashed elsewhere (e.g. by a `FileIngest`)
+    pub fn update_hashed(&mut self, path: String, hash: String, size: u64, mtime: u64, device_id: String) -> bool {
+        self.0.update_hashed(path, hash, size, mtime, device_id)
+    }
+
+    /// Hold a change back until `interval_ms` passes without further changes
+    /// Returns true if the change was staged (nothing is recorded yet)
+    pub fn stage_update(
+        &mut self,
+        path: String,
+        content: &[u8],
+        mtime: u64,
+        device_id: String,
+        now: u64,
+        interval_ms: u64,
+    ) -> bool {
+        self.0.stage_update(path, content, mtime, device_id, now, interval_ms)
+    }
+
+    /// `stage_update` for content hashed elsewhere, held back until `due_at`
+    pub fn stage_hashed(&mut self, path: String, hash: String, size: u64, mtime: u64, device_id: String, due_at: u64) -> bool {
+        self.0.stage_hashed(path, hash, size, mtime, device_id, due_at)
+    }
+
+    /// Record staged changes whose interval has elapsed; returns how many were recorded
+    pub fn flush_pending(&mut self, now: u64) -> usize {
+        self.0.flush_pending(now)
+    }
+
+    /// Record every staged change regardless of its interval
+    pub fn flush_all_pending(&mut self) -> usize {
+        self.0.flush_all_pending()
+    }
+
+    /// Number of changes waiting on a debounce interval
+    pub fn pending_count(&self) -> usize {
+        self.0.pending_count()
     }
 
     pub fn mark_deleted(&mut self, path: String, mtime: u64, device_id: String) -> bool {
-        if let Some(existing) = self.files.get(&path) {
-            if existing.is_deleted {
-                return false;
-            }
-        }
-
-        self.global_sequence += 1;
-        let metadata = FileMetadata {
-            path: path.clone(),
-            hash: String::new(),
-            mtime,
-            size: 0,
-            version: self.global_sequence,
-            is_deleted: true,
-            last_modified_by: device_id,
-        };
-
-        self.files.insert(path, metadata);
-        true
+        self.0.mark_deleted(path, mtime, device_id)
     }
 
     pub fn get_file_metadata(&self, path: &str) -> Option<String> {
-        self.files.get(path).map(|m| serde_json::to_string(m).unwrap_or_default())
+        self.0.get(path).map(|m| serde_json::to_string(&m).unwrap_or_default())
     }
 
     pub fn get_all_files(&self) -> String {
-        let all: Vec<&FileMetadata> = self.files.values().collect();
+        let all: Vec<FileMetadata> = self.0.files().collect();
         serde_json::to_string(&all).unwrap_or_default()
     }
 }
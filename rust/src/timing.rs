@@ -0,0 +1,11 @@
+//! JS bindings for the timing spans (see `p2p_sync_core::timing`)
+
+use wasm_bindgen::prelude::*;
+
+pub use p2p_sync_core::timing::*;
+
+/// Clear all totals, e.g. at the start of a measured run
+#[wasm_bindgen]
+pub fn reset_timings() {
+    p2p_sync_core::timing::reset_timings();
+}
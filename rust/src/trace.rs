@@ -0,0 +1,35 @@
+//! JS bindings for protocol trace recording and replay (see `p2p_sync_core::trace`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::trace::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Start recording a new trace, keeping at most `max_events` (0 for the default)
+    pub fn start_trace_recording(&mut self, max_events: usize) {
+        self.0.start_trace_recording(max_events);
+    }
+
+    /// Stop recording; the trace is kept until the next `start_trace_recording`
+    pub fn stop_trace_recording(&mut self) {
+        self.0.stop_trace_recording();
+    }
+
+    pub fn get_trace_event_count(&self) -> usize {
+        self.0.get_trace_event_count()
+    }
+
+    /// The recorded trace as JSON, for a bug report
+    pub fn export_trace(&self) -> String {
+        self.0.export_trace()
+    }
+
+    /// Feed an exported trace into this (fresh) node; returns `{replayed, rejected}` as JSON.
+    /// The clock is pinned to each event's time while it is replayed
+    pub fn replay_trace(&mut self, trace_json: &str) -> Result<String, String> {
+        self.0.replay_trace(trace_json)
+    }
+}
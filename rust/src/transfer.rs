@@ -1,59 +1,109 @@
+//! JS bindings for chunked, encrypted file transfers (see `p2p_sync_core::transfer`)
+
 use wasm_bindgen::prelude::*;
-use serde::{Serialize, Deserialize};
-use crate::crypto::{encrypt_data, decrypt_data};
-
-const CHUNK_SIZE: usize = 64 * 1024; // 64KB
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct FileChunk {
-    pub file_path: String,
-    pub chunk_index: u32,
-    pub total_chunks: u32,
-    pub data: Vec<u8>, // Encrypted data
-    pub nonce: Vec<u8>,
-}
+
+use p2p_sync_core::transfer as core_transfer;
+pub use p2p_sync_core::transfer::*;
+
+use crate::cancel::CancellationToken;
 
 #[wasm_bindgen]
-pub struct TransferManager {
-    // We could store active transfers here if needed
-}
+pub struct TransferManager(core_transfer::TransferManager);
+
+core_wrapper!(TransferManager, core_transfer::TransferManager);
 
 #[wasm_bindgen]
 impl TransferManager {
+    /// Chunks of transfer `transfer_id`, carrying the file the receiver expects
+    /// as `file_hash` from the manifest; both peers must use the same values
     #[wasm_bindgen(constructor)]
-    pub fn new() -> TransferManager {
-        TransferManager {}
+    pub fn new(transfer_id: String, file_hash: String) -> Result<TransferManager, String> {
+        Ok(core_transfer::TransferManager::new(transfer_id, file_hash)?.into())
     }
 
     /// Prepare a file for transfer: split into chunks and encrypt
     pub fn prepare_transfer(&self, file_path: String, content: &[u8], session_key: String) -> Result<String, String> {
-        let total_size = content.len();
-        let total_chunks = (total_size + CHUNK_SIZE - 1) / CHUNK_SIZE;
-        let mut chunks = Vec::new();
+        Ok(self.0.prepare_transfer(file_path, content, session_key)?)
+    }
 
-        for (i, chunk_slice) in content.chunks(CHUNK_SIZE).enumerate() {
-            let encrypted = encrypt_data(session_key.clone(), chunk_slice)?;
+    /// Attach a token checked between chunks of long operations
+    pub fn set_cancellation_token(&mut self, token: &CancellationToken) {
+        self.0.set_cancellation_token(token);
+    }
 
-            let chunk = FileChunk {
-                file_path: file_path.clone(),
-                chunk_index: i as u32,
-                total_chunks: total_chunks as u32,
-                data: encrypted.get_data(),
-                nonce: encrypted.get_nonce(),
-            };
-            chunks.push(chunk);
-        }
+    /// Detach the current cancellation token
+    pub fn clear_cancellation_token(&mut self) {
+        self.0.clear_cancellation_token();
+    }
 
-        serde_json::to_string(&chunks).map_err(|e| e.to_string())
+    /// Pad outgoing chunks and strip padding from incoming ones
+    pub fn set_padding_enabled(&mut self, enabled: bool) {
+        self.0.set_padding_enabled(enabled);
     }
 
     /// Process a received chunk: decrypt and return data
-    /// Note: This is a simple helper. In a real scenario, we might want to buffer chunks
-    /// and reassemble the file in Rust, but for now JS handles reassembly.
     pub fn decrypt_chunk(&self, chunk_json: String, session_key: String) -> Result<Vec<u8>, String> {
-        let chunk: FileChunk = serde_json::from_str(&chunk_json)
-            .map_err(|e| format!("Invalid chunk JSON: {}", e))?;
+        Ok(self.0.decrypt_chunk(chunk_json, session_key)?)
+    }
+}
+
+#[wasm_bindgen]
+pub struct IncomingTransfer(core_transfer::IncomingTransfer);
+
+core_wrapper!(IncomingTransfer, core_transfer::IncomingTransfer);
+
+#[wasm_bindgen]
+impl IncomingTransfer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        transfer_id: String,
+        file_path: String,
+        file_hash: String,
+        total_chunks: u32,
+        session_key: String,
+        max_buffered_chunks: usize,
+    ) -> Result<IncomingTransfer, String> {
+        let incoming = core_transfer::IncomingTransfer::new(transfer_id, file_path, file_hash, total_chunks, session_key, max_buffered_chunks)?;
+        Ok(incoming.into())
+    }
+
+    /// Strip padding from the chunks (both peers must agree)
+    pub fn set_padding_enabled(&mut self, enabled: bool) {
+        self.0.set_padding_enabled(enabled);
+    }
+
+    /// Accept a chunk in `FileChunk` JSON; returns false for a duplicate, which is ignored
+    pub fn accept_chunk(&mut self, chunk_json: String) -> Result<bool, String> {
+        Ok(self.0.accept_chunk(chunk_json)?)
+    }
+
+    /// Plaintext released in order since the last call
+    pub fn take_ready(&mut self) -> Vec<u8> {
+        self.0.take_ready()
+    }
+
+    /// First chunk not received yet; everything before it has been released
+    pub fn get_next_chunk(&self) -> u32 {
+        self.0.get_next_chunk()
+    }
+
+    pub fn get_buffered_chunks(&self) -> usize {
+        self.0.get_buffered_chunks()
+    }
+
+    pub fn get_buffered_bytes(&self) -> usize {
+        self.0.get_buffered_bytes()
+    }
+
+    pub fn get_buffer_capacity(&self) -> usize {
+        self.0.get_buffer_capacity()
+    }
+
+    pub fn get_duplicate_count(&self) -> u32 {
+        self.0.get_duplicate_count()
+    }
 
-        decrypt_data(session_key, &chunk.data, &chunk.nonce)
+    pub fn is_complete(&self) -> bool {
+        self.0.is_complete()
     }
 }
@@ -0,0 +1,124 @@
+//! JS bindings for pluggable transports (see `p2p_sync_core::transports`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::transports as core_transports;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::transports::*;
+
+/// A message for the host to write to one transport
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct OutgoingMessage(core_transports::OutgoingMessage);
+
+core_wrapper!(OutgoingMessage, core_transports::OutgoingMessage);
+
+#[wasm_bindgen]
+impl OutgoingMessage {
+    /// Recipient, empty for a broadcast
+    pub fn peer_id(&self) -> String {
+        self.0.peer_id()
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        self.0.data()
+    }
+}
+
+/// A whole frame received from a peer
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct IncomingFrame(core_transports::IncomingFrame);
+
+core_wrapper!(IncomingFrame, core_transports::IncomingFrame);
+
+#[wasm_bindgen]
+impl IncomingFrame {
+    pub fn transport(&self) -> String {
+        self.0.transport()
+    }
+
+    pub fn peer_id(&self) -> String {
+        self.0.peer_id()
+    }
+
+    pub fn frame(&self) -> Vec<u8> {
+        self.0.frame()
+    }
+}
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Register a transport as `{name, kind: "direct" | "relay" | "broadcast",
+    /// max_message_bytes?, preference?}`; registering a name again replaces it
+    pub fn register_transport(&mut self, json: &str) -> Result<(), String> {
+        self.0.register_transport(json)
+    }
+
+    /// Remove a transport and close its links; their queued frames move to other links
+    pub fn unregister_transport(&mut self, name: &str) -> bool {
+        self.0.unregister_transport(name)
+    }
+
+    pub fn get_transports_json(&self) -> String {
+        self.0.get_transports_json()
+    }
+
+    /// The host opened a connection to `peer_id`; false if it was already open
+    pub fn transport_connected(&mut self, transport: &str, peer_id: &str) -> Result<bool, String> {
+        self.0.transport_connected(transport, peer_id)
+    }
+
+    /// A connection closed or failed; false if it wasn't open
+    pub fn transport_disconnected(&mut self, transport: &str, peer_id: &str, reason: &str) -> bool {
+        self.0.transport_disconnected(transport, peer_id, reason)
+    }
+
+    /// Queue a frame for a peer on its best open link. Returns that transport's
+    /// name, or `undefined` if the frame waits for a link to open; waiting frames
+    /// count against the cache quota (`set_cache_quota`)
+    pub fn send_frame(&mut self, peer_id: &str, frame: &[u8]) -> Result<Option<String>, String> {
+        self.0.send_frame(peer_id, frame)
+    }
+
+    /// Queue a frame on every broadcast transport; returns how many
+    pub fn broadcast_frame(&mut self, frame: &[u8]) -> usize {
+        self.0.broadcast_frame(frame)
+    }
+
+    /// Queue this node's announcement on every broadcast transport
+    pub fn broadcast_announcement(&mut self) -> usize {
+        self.0.broadcast_announcement()
+    }
+
+    /// Next message to write to `transport`, `undefined` when there is none
+    pub fn next_outgoing_message(&mut self, transport: &str) -> Option<OutgoingMessage> {
+        self.0.next_outgoing_message(transport).map(Into::into)
+    }
+
+    /// Hand over a message read from `transport` (for broadcast transports
+    /// `peer_id` is the sender's address). Announcements are processed here;
+    /// other frames wait in `next_incoming_frame`. Returns whether a whole frame
+    /// was completed
+    pub fn receive_transport_message(&mut self, transport: &str, peer_id: &str, data: &[u8]) -> Result<bool, String> {
+        self.0.receive_transport_message(transport, peer_id, data)
+    }
+
+    /// Oldest received frame not yet taken by the session layer
+    pub fn next_incoming_frame(&mut self) -> Option<IncomingFrame> {
+        self.0.next_incoming_frame().map(Into::into)
+    }
+
+    /// Open links as `[{peer_id, transport, connected_at, last_activity, frames_sent,
+    /// frames_received, bytes_sent, bytes_received, active}]`
+    pub fn get_transport_links_json(&self) -> String {
+        self.0.get_transport_links_json()
+    }
+
+    /// Lifecycle events since the last call as `[{type: "connected" | "disconnected" | "unreachable", ...}]`
+    pub fn take_transport_events(&mut self) -> String {
+        self.0.take_transport_events()
+    }
+}
@@ -0,0 +1,29 @@
+//! JS bindings for background trickle sync (see `p2p_sync_core::trickle`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::trickle::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Set trickle mode from JSON (`{enabled, interval_ms, max_bytes_per_tick, max_entries}`)
+    pub fn set_trickle_mode(&mut self, config_json: &str) -> Result<(), String> {
+        self.0.set_trickle_mode(config_json)
+    }
+
+    pub fn get_trickle_mode_json(&self) -> String {
+        self.0.get_trickle_mode_json()
+    }
+
+    /// The peer applied journal entries up to `sequence` (also call it after a full round)
+    pub fn ack_trickle_sequence(&mut self, peer_id: &str, sequence: u64) {
+        self.0.ack_trickle_sequence(peer_id, sequence);
+    }
+
+    /// Advance trickle mode; returns a JSON array of `{peer_id, frame, chunk_bytes}`
+    pub fn trickle_tick(&mut self, now: u64) -> String {
+        self.0.trickle_tick(now)
+    }
+}
@@ -0,0 +1,20 @@
+//! JS bindings for vault size and storage budget (see `p2p_sync_core::usage`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::usage::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Bytes per folder and per file type, with local usage against the budget, as JSON
+    pub fn get_usage_report(&self) -> String {
+        self.0.get_usage_report()
+    }
+
+    /// Groups of paths with identical content (`{hash, size, paths, wasted_bytes}`) as JSON
+    pub fn find_duplicates(&self) -> String {
+        self.0.find_duplicates()
+    }
+}
@@ -0,0 +1,36 @@
+//! JS bindings for adapter for Obsidian's vault events (see `p2p_sync_core::vaultevents`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::vaultevents::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Queue raw vault events: one `{type, path, ...}` object or an array of them;
+    /// `type` is `create`, `modify`, `delete` or `rename` (with `old_path`)
+    pub fn push_vault_events(&mut self, events_json: &str, now: u64) -> Result<usize, String> {
+        self.0.push_vault_events(events_json, now)
+    }
+
+    /// Apply the operations whose quiet period has passed; returns the journal
+    /// operations performed as a JSON array of `{op, path, ...}`
+    pub fn flush_vault_events(&mut self, now: u64) -> String {
+        self.0.flush_vault_events(now)
+    }
+
+    /// Apply every queued operation now (e.g. before saving state)
+    pub fn flush_all_vault_events(&mut self) -> String {
+        self.0.flush_all_vault_events()
+    }
+
+    pub fn get_pending_vault_event_count(&self) -> usize {
+        self.0.get_pending_vault_event_count()
+    }
+
+    /// Quiet period after the last `modify` of a path before it is recorded
+    pub fn set_vault_event_quiet_ms(&mut self, quiet_ms: u64) {
+        self.0.set_vault_event_quiet_ms(quiet_ms);
+    }
+}
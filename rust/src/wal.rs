@@ -0,0 +1,21 @@
+//! JS bindings for write-ahead log for crash-safe journal persistence (see `p2p_sync_core::wal`)
+
+use wasm_bindgen::prelude::*;
+
+use crate::P2PNode;
+
+pub use p2p_sync_core::wal::*;
+
+#[wasm_bindgen]
+impl P2PNode {
+    /// Records for the journal versions written since the last call, as
+    /// newline-terminated JSON lines to append to the WAL (empty if none)
+    pub fn take_wal_records(&mut self) -> Result<String, String> {
+        self.0.take_wal_records()
+    }
+
+    /// Replay WAL text over the loaded checkpoint; returns the number of records applied
+    pub fn replay_wal(&mut self, wal: &str) -> Result<usize, String> {
+        self.0.replay_wal(wal)
+    }
+}
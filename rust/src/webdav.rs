@@ -0,0 +1,51 @@
+//! JS bindings for webDAV mirror backend (see `p2p_sync_core::webdav`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::webdav as core_webdav;
+
+pub use p2p_sync_core::webdav::*;
+
+#[wasm_bindgen]
+pub struct WebDavMirror(core_webdav::WebDavMirror);
+
+core_wrapper!(WebDavMirror, core_webdav::WebDavMirror);
+
+#[wasm_bindgen]
+impl WebDavMirror {
+    /// `base_url` is the vault folder on the share, e.g. `https://cloud/remote.php/dav/files/me/Vault`
+    #[wasm_bindgen(constructor)]
+    pub fn new(base_url: String) -> WebDavMirror {
+        core_webdav::WebDavMirror::new(base_url).into()
+    }
+
+    /// Start listing the share; follow-up listings arrive via `handle_response`
+    pub fn plan_refresh(&mut self) -> String {
+        self.0.plan_refresh()
+    }
+
+    /// Requests that bring the share in line with `files_json` (journal metadata array)
+    pub fn plan_sync(&mut self, files_json: &str) -> Result<String, String> {
+        self.0.plan_sync(files_json)
+    }
+
+    /// Feed back `{id, status, headers, body}` for a planned request
+    /// Returns `{follow_up, conflicts, failed, idle}` as JSON
+    pub fn handle_response(&mut self, response_json: &str) -> Result<String, String> {
+        self.0.handle_response(response_json)
+    }
+
+    /// Requests planned but not yet answered
+    pub fn pending_count(&self) -> usize {
+        self.0.pending_count()
+    }
+
+    /// Export what is known about the share for persistence
+    pub fn to_json(&self) -> String {
+        self.0.to_json()
+    }
+
+    pub fn from_json(json: &str) -> Result<WebDavMirror, String> {
+        core_webdav::WebDavMirror::from_json(json).map(Into::into)
+    }
+}
@@ -0,0 +1,57 @@
+//! JS bindings for the binary wire protocol (see `p2p_sync_core::wire`)
+
+use wasm_bindgen::prelude::*;
+
+use p2p_sync_core::wire as core_wire;
+pub use p2p_sync_core::wire::*;
+
+/// Splits frames into transport messages of at most `max_message_bytes` and
+/// reassembles the messages received from the peer
+#[wasm_bindgen]
+pub struct Fragmenter(core_wire::Fragmenter);
+
+core_wrapper!(Fragmenter, core_wire::Fragmenter);
+
+#[wasm_bindgen]
+impl Fragmenter {
+    #[wasm_bindgen(constructor)]
+    pub fn new(max_message_bytes: usize) -> Result<Fragmenter, String> {
+        Ok(core_wire::Fragmenter::new(max_message_bytes)?.into())
+    }
+
+    /// Split `frame` into messages, taken in order with `next_message`
+    pub fn queue_frame(&mut self, frame: &[u8]) -> Result<usize, String> {
+        Ok(self.0.queue_frame(frame)?)
+    }
+
+    /// Like `queue_frame`, but the messages overtake those of frames queued with
+    /// `queue_frame` that are still waiting, e.g. an ack during a large chunk
+    pub fn queue_frame_ahead(&mut self, frame: &[u8]) -> Result<usize, String> {
+        Ok(self.0.queue_frame_ahead(frame)?)
+    }
+
+    pub fn next_message(&mut self) -> Option<Vec<u8>> {
+        self.0.next_message()
+    }
+
+    /// Whether messages queued with `queue_frame_ahead` are still waiting
+    pub fn has_urgent(&self) -> bool {
+        self.0.has_urgent()
+    }
+
+    /// Add a received message; returns the frame once all its fragments are in
+    pub fn receive_message(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.0.receive_message(message)?)
+    }
+
+    /// Frames still missing fragments
+    pub fn pending_frames(&self) -> usize {
+        self.0.pending_frames()
+    }
+}
+
+/// Render a binary frame (`"protobuf"` or `"cbor"`) as JSON for debugging
+#[wasm_bindgen]
+pub fn wire_frame_debug_json(frame: &[u8], encoding: &str) -> Result<String, String> {
+    Ok(core_wire::wire_frame_debug_json(frame, encoding)?)
+}
@@ -0,0 +1,22 @@
+//! In-browser timing of transfer preparation on a 100 MB input
+//!
+//! Run with `wasm-pack test --release --headless --chrome`; the duration is
+//! printed to the browser console.
+
+#![cfg(target_arch = "wasm32")]
+
+use obsidian_p2p_sync::clock::now_ms;
+use obsidian_p2p_sync::transfer::TransferManager;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn prepare_transfer_100mb() {
+    let content: Vec<u8> = (0..100 * 1024 * 1024).map(|i: usize| (i * 31 % 251) as u8).collect();
+    let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string();
+    let manager = TransferManager::new("t1".to_string(), "hash-a".to_string()).unwrap();
+    let started = now_ms();
+    let json = manager.prepare_transfer("big.bin".to_string(), &content, key).unwrap();
+    console_log!("prepare_transfer 100 MB: {} ms, {} bytes of JSON", now_ms() - started, json.len());
+}
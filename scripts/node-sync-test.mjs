@@ -0,0 +1,59 @@
+// End-to-end sync test against the Node.js build of the Rust core.
+// Build it first with `npm run build:wasm:node`, then run `npm run test:node`.
+import assert from 'assert';
+import { createRequire } from 'module';
+
+const require = createRequire(import.meta.url);
+const wasm = require('../pkg-node/obsidian_p2p_sync.js');
+
+const encoder = new TextEncoder();
+const content = encoder.encode('# Meeting notes\n\n- ship the Node build\n'.repeat(4000));
+
+console.log('🔄 Running Node.js sync test...');
+
+// Pinned clock, so announcement timestamps are deterministic
+wasm.set_clock_time(1_700_000_000_000);
+const now = 1_700_000_000_000;
+
+const laptop = new wasm.P2PNode('Laptop', 'laptop', 8080);
+const phone = new wasm.P2PNode('Phone', 'phone', 8081);
+
+// Discovery
+assert.strictEqual(phone.process_announcement_frame(laptop.get_announcement_frame(), '127.0.0.1', now), true);
+assert.strictEqual(phone.get_peer_count(), 1);
+
+// Key agreement (uses the platform's random source)
+const laptopKeys = new wasm.KeyExchange();
+const phoneKeys = new wasm.KeyExchange();
+const sessionKey = laptopKeys.compute_shared_secret(phoneKeys.get_public_key());
+assert.strictEqual(phoneKeys.compute_shared_secret(laptopKeys.get_public_key()), sessionKey);
+
+// The laptop records a change; the phone sees it in a preview of the laptop's manifest
+assert.strictEqual(laptop.update_file('notes/meeting.md', content, now), true);
+const manifest = laptop.get_all_files();
+const preview = JSON.parse(phone.plan_sync_preview(manifest));
+assert.deepStrictEqual(preview.pull.map((item) => item.path), ['notes/meeting.md']);
+
+// Encrypted transfer, delivered out of order; both sides bind the chunks to the manifest hash
+const [meta] = JSON.parse(manifest);
+const sender = new wasm.TransferManager();
+sender.set_file_hash(meta.hash);
+const chunks = JSON.parse(sender.prepare_transfer('notes/meeting.md', content, sessionKey));
+const incoming = new wasm.IncomingTransfer('', 'notes/meeting.md', meta.hash, chunks.length, sessionKey, chunks.length);
+for (const chunk of chunks.reverse()) {
+    incoming.accept_chunk(JSON.stringify(chunk));
+}
+assert.strictEqual(incoming.is_complete(), true);
+assert.deepStrictEqual(Buffer.from(incoming.take_ready()), Buffer.from(content));
+
+// The phone applies the change and both journals agree
+assert.strictEqual(phone.apply_remote_change(JSON.stringify(meta), laptop.get_peer_id()), true);
+assert.deepStrictEqual(
+    JSON.parse(phone.get_all_files()).map((file) => file.hash),
+    JSON.parse(laptop.get_all_files()).map((file) => file.hash),
+);
+
+// Errors surface as exceptions, not panics
+assert.throws(() => phone.apply_remote_change('not json', 'laptop'));
+
+console.log('✅ Node.js sync test passed');
@@ -58,16 +58,17 @@ export class SecurityService extends EventEmitter {
     /**
      * Initialize the security service.
      * Loads existing identity or generates a new one.
-     * @param savedSecretKey Optional base64 secret key from storage
-     * @returns The secret key (base64) to be saved if it was generated or loaded
+     * @param wrappingKey Base64 32-byte key the host keeps outside the plugin's data (e.g. the OS keychain)
+     * @param savedWrappedKey Optional wrapped secret key from storage
+     * @returns The wrapped secret key (base64) to be saved if it was generated or loaded
      */
-    async initialize(savedSecretKey?: string): Promise<string> {
+    async initialize(wrappingKey: string, savedWrappedKey?: string): Promise<string> {
         const wasm = this.wasmBridge.getModule();
         if (!wasm) throw new Error("WASM not initialized");
 
-        if (savedSecretKey) {
+        if (savedWrappedKey) {
             try {
-                this.identity = wasm.DeviceIdentity.from_secret_key(this.deviceId, savedSecretKey);
+                this.identity = wasm.DeviceIdentity.from_wrapped_secret_key(this.deviceId, savedWrappedKey, wrappingKey);
                 console.log("Loaded device identity");
             } catch (e) {
                 console.error("Failed to load identity, generating new one", e);
@@ -80,7 +81,7 @@ export class SecurityService extends EventEmitter {
             console.log("Generated new device identity");
         }
 
-        return this.identity.get_secret_key();
+        return this.identity.export_wrapped_secret_key(wrappingKey);
     }
 
     getPublicKey(): string {
@@ -2,18 +2,23 @@ import { App, TFile, TAbstractFile, Notice, Events } from 'obsidian';
 import { WasmBridge } from './wasm-bridge';
 import { TcpTransport } from './transport/tcp-transport';
 import { SecurityService } from './security-service';
-import { TransferStatus, DiscoveredPeerData } from './types';
+import { TransferStatus, DiscoveredPeerData, TransferManagerInstance } from './types';
 import { ConfirmationModal, SyncChange } from './ui/confirmation-modal';
 import * as path from 'path';
 
+/** SHA-256 of a file's content (hex), which both peers bind into the transfer key */
+async function contentHash(content: Uint8Array): Promise<string> {
+    const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', content));
+    return Array.from(digest, b => b.toString(16).padStart(2, '0')).join('');
+}
+
 export class SyncService extends Events {
     private app: App;
     private wasmBridge: WasmBridge;
     private transport: TcpTransport;
     private securityService: SecurityService;
     private isWatching: boolean = false;
-    private transferManager: any = null; // Will be initialized from WASM
-    private incomingChunks: Map<string, { chunks: Map<number, any>, total: number, path: string }> = new Map();
+    private incomingChunks: Map<string, { chunks: Map<number, any>, total: number, path: string, transferId: string, fileHash: string }> = new Map();
     private activeTransfers: Map<string, TransferStatus> = new Map();
     private pendingSessionOffers: Map<string, any> = new Map(); // peerId -> keyExchange
     private remoteUpdateInProgress: Set<string> = new Set();
@@ -28,12 +33,6 @@ export class SyncService extends Events {
         this.transport = transport;
         this.securityService = securityService;
 
-        // Initialize TransferManager
-        const module = this.wasmBridge.getModule();
-        if (module && module.TransferManager) {
-            this.transferManager = new module.TransferManager();
-        }
-
         // Listen for incoming messages
         this.transport.on('message', this.handleMessage.bind(this));
 
@@ -348,7 +347,9 @@ export class SyncService extends Events {
                 this.incomingChunks.set(fileKey, {
                     chunks: new Map(),
                     total: message.totalChunks,
-                    path: message.filePath
+                    path: message.filePath,
+                    transferId: message.transferId,
+                    fileHash: message.fileHash
                 });
                 this.trigger('transfer-start', status);
             }
@@ -375,8 +376,18 @@ export class SyncService extends Events {
         }
     }
 
-    private async reassembleAndWrite(transfer: { chunks: Map<number, any>, total: number, path: string }, sessionKey: string) {
+    private async reassembleAndWrite(
+        transfer: { chunks: Map<number, any>, total: number, path: string, transferId: string, fileHash: string },
+        sessionKey: string
+    ) {
+        const module = this.wasmBridge.getModule();
+        if (!module) {
+            console.error('WASM not initialized');
+            return;
+        }
+        let transferManager: TransferManagerInstance | null = null;
         try {
+            transferManager = new module.TransferManager(transfer.transferId, transfer.fileHash);
             // Sort chunks
             const sortedChunks = Array.from(transfer.chunks.entries())
                 .sort((a, b) => a[0] - b[0])
@@ -407,7 +418,7 @@ export class SyncService extends Events {
                 };
 
                 const chunkJson = JSON.stringify(chunkObj);
-                const decrypted = this.transferManager.decrypt_chunk(chunkJson, sessionKey);
+                const decrypted = transferManager.decrypt_chunk(chunkJson, sessionKey);
                 decryptedParts.push(decrypted);
                 totalLength += decrypted.length;
             }
@@ -455,11 +466,14 @@ export class SyncService extends Events {
         } catch (e) {
             console.error(`Failed to reassemble ${transfer.path}`, e);
             new Notice(`Failed to save received file: ${transfer.path}`);
+        } finally {
+            transferManager?.free?.();
         }
     }
 
     async transferFile(file: TFile, peerId: string, sessionKey: string) {
-        if (!this.transferManager) {
+        const module = this.wasmBridge.getModule();
+        if (!module || !module.TransferManager) {
             console.error('TransferManager not initialized');
             return;
         }
@@ -480,8 +494,16 @@ export class SyncService extends Events {
             const content = await this.app.vault.readBinary(file);
             const contentArray = new Uint8Array(content);
 
-            // Prepare chunks
-            const chunksJson = this.transferManager.prepare_transfer(file.path, contentArray, sessionKey);
+            // Prepare chunks, bound to this transfer and the content's hash
+            const transferId = `${Date.now()}-${file.path}`;
+            const fileHash = await contentHash(contentArray);
+            const transferManager = new module.TransferManager(transferId, fileHash);
+            let chunksJson: string;
+            try {
+                chunksJson = transferManager.prepare_transfer(file.path, contentArray, sessionKey);
+            } finally {
+                transferManager.free?.();
+            }
             const chunks = JSON.parse(chunksJson);
 
             status.totalSize = chunks.length;
@@ -538,6 +560,8 @@ export class SyncService extends Events {
                             filePath: chunk.file_path,
                             chunkIndex: chunk.chunk_index,
                             totalChunks: chunk.total_chunks,
+                            transferId,
+                            fileHash,
                             data: chunk.data,
                             nonce: chunk.nonce
                         });
@@ -552,6 +576,8 @@ export class SyncService extends Events {
                     filePath: chunk.file_path,
                     chunkIndex: chunk.chunk_index,
                     totalChunks: chunk.total_chunks,
+                    transferId,
+                    fileHash,
                     data: chunk.data, // Array of numbers (bytes)
                     nonce: chunk.nonce
                 });
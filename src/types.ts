@@ -13,6 +13,8 @@ export interface WasmModule {
   // Utilities
   greet_from_rust(name: string): string;
   get_version(): string;
+  generate_id(): string;
+  get_id_timestamp(id: string): bigint | undefined;
 
   // Crypto
   DeviceIdentity: DeviceIdentityConstructor;
@@ -25,7 +27,7 @@ export interface WasmModule {
   verify_signature(publicKeyB64: string, message: Uint8Array, signatureB64: string): boolean;
 
   // Transfer
-  TransferManager: new () => TransferManagerInstance;
+  TransferManager: new (transferId: string, fileHash: string) => TransferManagerInstance;
   encrypt_data(keyB64: string, plaintext: Uint8Array): EncryptedChunkInstance;
   decrypt_data(keyB64: string, ciphertext: Uint8Array, nonce: Uint8Array): Uint8Array;
 
@@ -48,12 +50,13 @@ export interface EncryptedChunkInstance {
 export interface DeviceIdentityConstructor {
   new (deviceId: string): DeviceIdentityInstance;
   from_secret_key(deviceId: string, secretKeyB64: string): DeviceIdentityInstance;
+  from_wrapped_secret_key(deviceId: string, wrappedB64: string, wrappingKeyB64: string): DeviceIdentityInstance;
 }
 
 export interface DeviceIdentityInstance {
   get_device_id(): string;
   get_public_key(): string;
-  get_secret_key(): string;
+  export_wrapped_secret_key(wrappingKeyB64: string): string;
   sign(message: Uint8Array): string;
   free?(): void;
 }
@@ -93,6 +96,8 @@ export interface P2PNodeInstance {
   get_all_files(): string;
   get_journal_state(): string;
   load_journal_state(json: string): void;
+  get_history_state(): string;
+  load_history_state(json: string): void;
 
   free?(): void;
 }
@@ -137,7 +142,7 @@ export interface DiscoveredPeerData {
 export interface P2PSyncSettings {
   deviceName: string;
   deviceId: string; // Unique ID for this device
-  deviceSecretKey?: string; // Base64 encoded secret key
+  deviceSecretKey?: string; // Base64 secret key, wrapped under a key from the OS keychain
   pairedDevices: string[]; // List of paired device IDs
   pairedDeviceKeys: Record<string, string>; // deviceId -> publicKey
   enableLanDiscovery: boolean;